@@ -1,3 +1,36 @@
+/// The messages, which use the proto3 JSON field names.
+const MESSAGES: &[&str] = &[".wrapper.AuthWrapper", ".wrapper.Revocation"];
+
+/// The fields, paired with the module in `crate::json` implementing their proto3 JSON mapping.
+const FIELDS: &[(&str, &str)] = &[
+    (".wrapper.AuthWrapper.public_key", "bytes"),
+    (".wrapper.AuthWrapper.signature", "bytes"),
+    (".wrapper.AuthWrapper.scheme", "signature_scheme"),
+    (".wrapper.AuthWrapper.payload", "bytes"),
+    (".wrapper.AuthWrapper.payload_digest", "bytes"),
+    (".wrapper.AuthWrapper.digest_algorithm", "digest_algorithm"),
+    (".wrapper.Revocation.payload_digest", "bytes"),
+    (".wrapper.Revocation.timestamp", "int64"),
+];
+
 fn main() {
-    prost_build::compile_protos(&["src/proto/wrapper.proto"], &["src/"]).unwrap();
+    let mut config = prost_build::Config::new();
+
+    // Derive serde traits on all models when the serde feature is enabled
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+        for message in MESSAGES {
+            config.type_attribute(message, "#[serde(default, rename_all = \"camelCase\")]");
+        }
+        for (field, module) in FIELDS {
+            config.field_attribute(
+                field,
+                format!("#[serde(with = \"crate::json::{}\")]", module),
+            );
+        }
+    }
+
+    config
+        .compile_protos(&["src/proto/wrapper.proto"], &["src/"])
+        .unwrap();
 }
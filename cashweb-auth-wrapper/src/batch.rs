@@ -0,0 +1,63 @@
+//! This module contains methods for verifying batches of [`ParsedAuthWrapper`]s.
+//!
+//! A single verification context is shared across batches, rather than constructed for each
+//! signature. When the `rayon` feature is enabled the batch is verified in parallel.
+
+use crate::{shared_context, ParsedAuthWrapper, VerifyError};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Verify the signatures on a batch of [`ParsedAuthWrapper`]s.
+///
+/// The results are returned in the same order as the wrappers.
+pub fn verify_batch(wrappers: &[ParsedAuthWrapper]) -> Vec<Result<(), VerifyError>> {
+    let secp = shared_context();
+
+    #[cfg(feature = "rayon")]
+    let iter = wrappers.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let iter = wrappers.iter();
+
+    iter.map(|wrapper| wrapper.verify_with_context(secp))
+        .collect()
+}
+
+/// Verify the signatures on a batch of [`ParsedAuthWrapper`]s, returning the first failure paired
+/// with its index.
+pub fn verify_all(wrappers: &[ParsedAuthWrapper]) -> Result<(), (usize, VerifyError)> {
+    verify_batch(wrappers)
+        .into_iter()
+        .enumerate()
+        .find_map(|(index, result)| result.err().map(|err| (index, err)))
+        .map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::key::SecretKey;
+
+    use super::*;
+    use crate::AuthWrapper;
+
+    #[test]
+    fn verify_batch_order() {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let wrapper = AuthWrapper::sign(&private_key, b"payload".to_vec())
+            .parse()
+            .unwrap();
+        let mut forged = wrapper.clone();
+        forged.payload_digest = [0; 32];
+        let wrappers = vec![wrapper.clone(), forged, wrapper];
+
+        let results = verify_batch(&wrappers);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], Ok(()));
+        assert!(matches!(results[1], Err(VerifyError::InvalidSignature(_))));
+        assert_eq!(results[2], Ok(()));
+        assert!(matches!(
+            verify_all(&wrappers),
+            Err((1, VerifyError::InvalidSignature(_)))
+        ));
+        assert_eq!(verify_all(&wrappers[..1]), Ok(()));
+    }
+}
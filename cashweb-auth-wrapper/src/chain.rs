@@ -0,0 +1,305 @@
+//! This module contains methods for delegating signing keys and verifying chains of nested
+//! [`AuthWrapper`]s.
+//!
+//! A parent key delegates to a key by signing a [`DelegationCertificate`] over the delegate public
+//! key, rather than over any content. The delegate then signs content itself, and the certificate
+//! [wraps](DelegationCertificate::wrap) the resulting [`AuthWrapper`] in a wrapper marked as
+//! `nested`, whose signature is that of the certificate. The outermost wrapper must be signed by a
+//! trusted root key, such as an account master key, and each nested wrapper is then trusted through
+//! its parent. This allows device keys to be authorized by a master key which remains offline once
+//! the certificates are issued.
+//!
+//! The certificate signature covers a digest domain separated from payload digests, hence a
+//! signature over content cannot be presented as a delegation, nor a delegation as content.
+
+use prost::{DecodeError, Message as _};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Message, Secp256k1, Signature, Signing,
+};
+use thiserror::Error;
+
+use crate::{
+    hash, shared_context, verify_digest, AuthWrapper, DigestAlgorithm, ParseError,
+    ParsedAuthWrapper, SignatureScheme, VerifyError, VERSION,
+};
+
+/// The maximum number of nested wrappers below the root.
+pub const MAX_CHAIN_DEPTH: usize = 8;
+
+/// The domain separation tag prefixed to the delegate public key in the [`delegation_digest`].
+pub const DELEGATION_TAG: &[u8] = b"cashweb-auth-wrapper/delegation";
+
+/// Calculate the digest signed by a parent key to delegate to a public key.
+///
+/// This is the SHA-256 digest of the [`DELEGATION_TAG`] followed by the compressed delegate public
+/// key.
+pub fn delegation_digest(delegate: &PublicKey) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(DELEGATION_TAG.len() + 33);
+    preimage.extend_from_slice(DELEGATION_TAG);
+    preimage.extend_from_slice(&delegate.serialize());
+    hash::sha256(&preimage)
+}
+
+/// A delegation from a parent key to a delegate key, signed by the parent key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelegationCertificate {
+    /// The public key of the parent.
+    pub public_key: PublicKey,
+    /// The public key delegated to.
+    pub delegate: PublicKey,
+    /// The signature by the parent over the [`delegation_digest`] of the delegate.
+    pub signature: Signature,
+}
+
+impl DelegationCertificate {
+    /// Sign a delegation to a public key using ECDSA.
+    #[inline]
+    pub fn sign(private_key: &SecretKey, delegate: PublicKey) -> Self {
+        Self::sign_with_context(&Secp256k1::signing_only(), private_key, delegate)
+    }
+
+    /// Sign a delegation to a public key using ECDSA and an existing context.
+    pub fn sign_with_context<C: Signing>(
+        secp: &Secp256k1<C>,
+        private_key: &SecretKey,
+        delegate: PublicKey,
+    ) -> Self {
+        let msg = Message::from_slice(&delegation_digest(&delegate)).unwrap(); // This is safe
+        Self {
+            public_key: PublicKey::from_secret_key(secp, private_key),
+            delegate,
+            signature: secp.sign(&msg, private_key),
+        }
+    }
+
+    /// Verify the signature of the parent over the delegation.
+    #[inline]
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        verify_digest(
+            shared_context(),
+            SignatureScheme::Ecdsa,
+            &delegation_digest(&self.delegate),
+            &self.signature,
+            &self.public_key,
+        )
+    }
+
+    /// Wrap an [`AuthWrapper`], signed by the delegate key, in a `nested` wrapper carrying the
+    /// certificate.
+    ///
+    /// The certificate may be reused to wrap any number of wrappers signed by the delegate.
+    pub fn wrap(&self, inner: &AuthWrapper) -> AuthWrapper {
+        let mut payload = Vec::with_capacity(inner.encoded_len());
+        inner.encode(&mut payload).unwrap(); // This is safe
+        AuthWrapper {
+            public_key: self.public_key.serialize().to_vec(),
+            signature: self.signature.serialize_compact().to_vec(),
+            scheme: SignatureScheme::Ecdsa.into(),
+            payload_digest: hash::sha256(&payload).to_vec(),
+            payload,
+            version: VERSION,
+            digest_algorithm: DigestAlgorithm::Sha256.into(),
+            nested: true,
+        }
+    }
+}
+
+/// Error associated with verifying a chain of [`AuthWrapper`]s.
+///
+/// The depth of the failing wrapper is given, with the outermost wrapper at depth zero.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChainError {
+    /// Failed to decode a nested wrapper.
+    #[error("failed to decode wrapper at depth {0}: {1}")]
+    Decode(usize, DecodeError),
+    /// Failed to parse a wrapper.
+    #[error("failed to parse wrapper at depth {0}: {1}")]
+    Parse(usize, ParseError),
+    /// A wrapper failed verification, either its delegation or its signature over the payload.
+    #[error("failed to verify wrapper at depth {0}: {1}")]
+    Verify(usize, VerifyError),
+    /// The outermost wrapper was not signed by a root key.
+    #[error("untrusted root")]
+    UntrustedRoot,
+    /// The chain exceeded [`MAX_CHAIN_DEPTH`].
+    #[error("chain too deep")]
+    TooDeep,
+}
+
+/// A verified chain of [`AuthWrapper`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedChain {
+    /// The public keys along the chain, starting from the root key.
+    pub public_keys: Vec<PublicKey>,
+    /// The innermost wrapper, whose payload is the delegated content.
+    pub leaf: ParsedAuthWrapper,
+}
+
+impl VerifiedChain {
+    /// The root key which authorized the chain.
+    pub fn root(&self) -> &PublicKey {
+        &self.public_keys[0] // This is safe
+    }
+
+    /// The delegate key which signed the innermost wrapper.
+    pub fn signer(&self) -> &PublicKey {
+        &self.leaf.public_key
+    }
+
+    /// The payload of the innermost wrapper.
+    pub fn payload(&self) -> &[u8] {
+        &self.leaf.payload
+    }
+}
+
+impl AuthWrapper {
+    /// Parse the [`AuthWrapper`] and verify the chain of nested wrappers against a set of root
+    /// keys.
+    #[inline]
+    pub fn verify_chain(self, roots: &[PublicKey]) -> Result<VerifiedChain, ChainError> {
+        self.parse()
+            .map_err(|err| ChainError::Parse(0, err))?
+            .verify_chain(roots)
+    }
+}
+
+impl ParsedAuthWrapper {
+    /// Verify the chain of nested wrappers against a set of root keys.
+    ///
+    /// The wrapper must be signed by one of the `roots`. Each `nested` wrapper is decoded and
+    /// parsed, and its signature verified as a delegation to the public key of the wrapper it
+    /// carries, until a wrapper which is not `nested` is reached. The signature of that wrapper is
+    /// verified over its payload.
+    pub fn verify_chain(self, roots: &[PublicKey]) -> Result<VerifiedChain, ChainError> {
+        // Check root
+        if !roots.contains(&self.public_key) {
+            return Err(ChainError::UntrustedRoot);
+        }
+
+        let secp = shared_context();
+        let mut public_keys = Vec::new();
+        let mut wrapper = self;
+        for depth in 0..=MAX_CHAIN_DEPTH {
+            public_keys.push(wrapper.public_key);
+
+            if !wrapper.nested {
+                // Verify signature over the payload
+                wrapper
+                    .verify_with_context(secp)
+                    .map_err(|err| ChainError::Verify(depth, err))?;
+                return Ok(VerifiedChain {
+                    public_keys,
+                    leaf: wrapper,
+                });
+            }
+
+            // Decode and parse the delegate wrapper
+            let delegate = AuthWrapper::decode(wrapper.payload.as_slice())
+                .map_err(|err| ChainError::Decode(depth + 1, err))?
+                .parse()
+                .map_err(|err| ChainError::Parse(depth + 1, err))?;
+
+            // Verify signature over the delegation
+            verify_digest(
+                secp,
+                wrapper.scheme,
+                &delegation_digest(&delegate.public_key),
+                &wrapper.signature,
+                &wrapper.public_key,
+            )
+            .map_err(|err| ChainError::Verify(depth, err))?;
+
+            wrapper = delegate;
+        }
+
+        Err(ChainError::TooDeep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::keys;
+
+    fn delegate(private_key: &SecretKey, inner: &AuthWrapper) -> AuthWrapper {
+        let delegate = PublicKey::from_slice(&inner.public_key).unwrap();
+        DelegationCertificate::sign(private_key, delegate).wrap(inner)
+    }
+
+    #[test]
+    fn device_key_chain() {
+        let (master_sk, master_pk) = keys(1);
+        let (device_sk, device_pk) = keys(2);
+        let certificate = DelegationCertificate::sign(&master_sk, device_pk);
+        certificate.verify().unwrap();
+
+        // The certificate is reused across payloads
+        for payload in &[b"metadata".to_vec(), b"profile".to_vec()] {
+            let inner = AuthWrapper::sign(&device_sk, payload.clone());
+            let chain = certificate.wrap(&inner).verify_chain(&[master_pk]).unwrap();
+            assert_eq!(chain.public_keys, vec![master_pk, device_pk]);
+            assert_eq!(chain.root(), &master_pk);
+            assert_eq!(chain.signer(), &device_pk);
+            assert_eq!(chain.payload(), payload.as_slice());
+        }
+    }
+
+    #[test]
+    fn untrusted_root() {
+        let (master_sk, _) = keys(1);
+        let (device_sk, device_pk) = keys(2);
+        let inner = AuthWrapper::sign(&device_sk, b"metadata".to_vec());
+        assert_eq!(
+            delegate(&master_sk, &inner).verify_chain(&[device_pk]),
+            Err(ChainError::UntrustedRoot)
+        );
+    }
+
+    #[test]
+    fn invalid_delegate_signature() {
+        let (master_sk, master_pk) = keys(1);
+        let (device_sk, _) = keys(2);
+        let mut inner = AuthWrapper::sign(&device_sk, b"metadata".to_vec());
+        inner.signature = AuthWrapper::sign(&device_sk, b"other".to_vec()).signature;
+        match delegate(&master_sk, &inner).verify_chain(&[master_pk]) {
+            Err(ChainError::Verify(1, _)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_flag_is_signed() {
+        let (master_sk, master_pk) = keys(1);
+        let (device_sk, _) = keys(2);
+
+        // A signature over content is not a delegation
+        let inner = AuthWrapper::sign(&device_sk, b"metadata".to_vec());
+        let mut raw_inner = Vec::new();
+        inner.encode(&mut raw_inner).unwrap();
+        let mut wrapper = AuthWrapper::sign(&master_sk, raw_inner);
+        wrapper.nested = true;
+        match wrapper.verify_chain(&[master_pk]) {
+            Err(ChainError::Verify(0, _)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // A delegation is not a signature over content
+        let mut wrapper = delegate(&master_sk, &inner);
+        wrapper.nested = false;
+        match wrapper.verify_chain(&[master_pk]) {
+            Err(ChainError::Verify(0, _)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn too_deep() {
+        let (root_sk, root_pk) = keys(1);
+        let mut wrapper = AuthWrapper::sign(&root_sk, b"metadata".to_vec());
+        for _ in 0..=MAX_CHAIN_DEPTH {
+            wrapper = delegate(&root_sk, &wrapper);
+        }
+        assert_eq!(wrapper.verify_chain(&[root_pk]), Err(ChainError::TooDeep));
+    }
+}
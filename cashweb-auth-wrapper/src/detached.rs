@@ -0,0 +1,299 @@
+//! This module contains the [`DetachedSignature`], a compact proof separated from its payload.
+//!
+//! Large payloads may be stored externally, such as in object storage, while the detached proof
+//! travels separately. The proof is later verified against, or re-attached to, the payload bytes.
+//!
+//! The compact encoding is [`DETACHED_SIGNATURE_LEN`] bytes long:
+//! * 1 byte signature scheme.
+//! * 1 byte digest algorithm.
+//! * 1 byte `nested` flag.
+//! * 4 byte little-endian protocol version.
+//! * 33 byte compressed public key.
+//! * 64 byte compact signature.
+//! * 32 byte payload digest.
+
+use std::convert::TryInto;
+
+use prost::{DecodeError, Message as _};
+use secp256k1::{key::PublicKey, Error as SecpError, Secp256k1, Signature, Verification};
+use thiserror::Error;
+
+use crate::{
+    chain::delegation_digest, effective_version, shared_context, verify_digest, AuthWrapper,
+    DigestAlgorithm, ParsedAuthWrapper, SignatureScheme, VerifyError, MIN_VERSION, VERSION,
+};
+
+/// The length of an encoded [`DetachedSignature`].
+pub const DETACHED_SIGNATURE_LEN: usize = 1 + 1 + 1 + 4 + 33 + 64 + 32;
+
+/// A signature, public key and payload digest, detached from the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachedSignature {
+    /// The public key associated with the signature.
+    pub public_key: PublicKey,
+    /// The signature by public key covering the payload digest.
+    pub signature: Signature,
+    /// The signature scheme used for signing.
+    pub scheme: SignatureScheme,
+    /// The digest algorithm used to calculate the payload digest.
+    pub digest_algorithm: DigestAlgorithm,
+    /// The digest of the payload.
+    pub payload_digest: [u8; 32],
+    /// The version of the authorization wrapper protocol.
+    pub version: u32,
+    /// Whether the payload is a nested [`AuthWrapper`], see [`chain`](crate::chain).
+    pub nested: bool,
+}
+
+/// Error associated with decoding, verifying and attaching a [`DetachedSignature`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DetachedError {
+    /// The encoded signature was an unexpected length.
+    #[error("unexpected length")]
+    UnexpectedLength,
+    /// The public key provided was invalid.
+    #[error(transparent)]
+    PublicKey(SecpError),
+    /// The signature provided was an invalid format.
+    #[error(transparent)]
+    Signature(SecpError),
+    /// The signature scheme provided is unsupported.
+    #[error("unsupported signature scheme")]
+    UnsupportedScheme,
+    /// The digest algorithm is unsupported, the feature enabling it may be disabled.
+    #[error("unsupported digest algorithm")]
+    UnsupportedDigestAlgorithm,
+    /// The payload did not match the payload digest.
+    #[error("fraudulent digest")]
+    FraudulentDigest,
+    /// The signature failed verification.
+    #[error(transparent)]
+    Verify(VerifyError),
+    /// The version of the authorization wrapper protocol is unsupported.
+    #[error("unsupported version: {0}")]
+    UnsupportedVersion(u32),
+    /// Failed to decode the nested [`AuthWrapper`] payload.
+    #[error("nested wrapper decoding failure: {0}")]
+    Decode(DecodeError),
+}
+
+impl DetachedSignature {
+    /// Encode the [`DetachedSignature`] in its compact form.
+    pub fn to_bytes(&self) -> [u8; DETACHED_SIGNATURE_LEN] {
+        let mut raw = [0; DETACHED_SIGNATURE_LEN];
+        raw[0] = self.scheme as u8;
+        raw[1] = self.digest_algorithm as u8;
+        raw[2] = self.nested as u8;
+        raw[3..7].copy_from_slice(&self.version.to_le_bytes());
+        raw[7..40].copy_from_slice(&self.public_key.serialize());
+        raw[40..104].copy_from_slice(&self.signature.serialize_compact());
+        raw[104..].copy_from_slice(&self.payload_digest);
+        raw
+    }
+
+    /// Decode a [`DetachedSignature`] from its compact form.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, DetachedError> {
+        if raw.len() != DETACHED_SIGNATURE_LEN {
+            return Err(DetachedError::UnexpectedLength);
+        }
+
+        // Parse scheme and digest algorithm
+        let scheme =
+            SignatureScheme::from_i32(raw[0] as i32).ok_or(DetachedError::UnsupportedScheme)?;
+        let digest_algorithm = DigestAlgorithm::from_i32(raw[1] as i32)
+            .ok_or(DetachedError::UnsupportedDigestAlgorithm)?;
+
+        // Parse nested flag and version
+        let nested = match raw[2] {
+            0 => false,
+            1 => true,
+            _ => return Err(DetachedError::UnexpectedLength),
+        };
+        let version = effective_version(u32::from_le_bytes(raw[3..7].try_into().unwrap())); // This is safe
+        if version < MIN_VERSION || version > VERSION {
+            return Err(DetachedError::UnsupportedVersion(version));
+        }
+
+        // Parse public key and signature
+        let public_key = PublicKey::from_slice(&raw[7..40]).map_err(DetachedError::PublicKey)?;
+        let signature = Signature::from_compact(&raw[40..104]).map_err(DetachedError::Signature)?;
+
+        Ok(Self {
+            public_key,
+            signature,
+            scheme,
+            digest_algorithm,
+            payload_digest: raw[104..].try_into().unwrap(), // This is safe
+            version,
+            nested,
+        })
+    }
+
+    /// Verify the signature over the payload digest, without the payload.
+    ///
+    /// The signature of a `nested` wrapper covers the delegation to the key of the nested wrapper
+    /// rather than the payload digest, use [`verify`](DetachedSignature::verify) instead.
+    #[inline]
+    pub fn verify_digest(&self) -> Result<(), VerifyError> {
+        self.verify_digest_with_context(shared_context())
+    }
+
+    /// Verify the signature over the payload digest, without the payload, using an existing
+    /// context.
+    #[inline]
+    pub fn verify_digest_with_context<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), VerifyError> {
+        verify_digest(
+            secp,
+            self.scheme,
+            &self.payload_digest,
+            &self.signature,
+            &self.public_key,
+        )
+    }
+
+    /// Check the payload matches the payload digest and verify the signature.
+    pub fn verify(&self, payload: &[u8]) -> Result<(), DetachedError> {
+        // Check payload digest
+        let payload_digest = self
+            .digest_algorithm
+            .digest(payload)
+            .map_err(|_| DetachedError::UnsupportedDigestAlgorithm)?;
+        if payload_digest != self.payload_digest {
+            return Err(DetachedError::FraudulentDigest);
+        }
+
+        if !self.nested {
+            return self.verify_digest().map_err(DetachedError::Verify);
+        }
+
+        // Verify the delegation to the nested wrapper
+        let nested = AuthWrapper::decode(payload).map_err(DetachedError::Decode)?;
+        let delegate =
+            PublicKey::from_slice(&nested.public_key).map_err(DetachedError::PublicKey)?;
+        verify_digest(
+            shared_context(),
+            self.scheme,
+            &delegation_digest(&delegate),
+            &self.signature,
+            &self.public_key,
+        )
+        .map_err(DetachedError::Verify)
+    }
+
+    /// Verify the [`DetachedSignature`] against the payload and re-attach it, constructing a
+    /// [`ParsedAuthWrapper`].
+    pub fn attach(self, payload: Vec<u8>) -> Result<ParsedAuthWrapper, DetachedError> {
+        self.verify(&payload)?;
+
+        Ok(ParsedAuthWrapper {
+            version: self.version,
+            public_key: self.public_key,
+            signature: self.signature,
+            scheme: self.scheme,
+            payload,
+            payload_digest: self.payload_digest,
+            digest_algorithm: self.digest_algorithm,
+            nested: self.nested,
+        })
+    }
+}
+
+impl ParsedAuthWrapper {
+    /// Export the [`DetachedSignature`] covering the payload.
+    pub fn detached_signature(&self) -> DetachedSignature {
+        DetachedSignature {
+            public_key: self.public_key,
+            signature: self.signature,
+            scheme: self.scheme,
+            digest_algorithm: self.digest_algorithm,
+            payload_digest: self.payload_digest,
+            version: self.version,
+            nested: self.nested,
+        }
+    }
+
+    /// Split the [`ParsedAuthWrapper`] into a [`DetachedSignature`] and the payload.
+    pub fn detach(self) -> (DetachedSignature, Vec<u8>) {
+        let detached_signature = self.detached_signature();
+        (detached_signature, self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::DelegationCertificate,
+        tests::{keys, sign},
+    };
+
+    #[test]
+    fn detach_and_attach() {
+        let (private_key, _) = keys(1);
+        let wrapper = sign(&private_key, b"large payload".to_vec());
+        let (detached_signature, payload) = wrapper.clone().detach();
+
+        let raw = detached_signature.to_bytes();
+        let decoded = DetachedSignature::from_bytes(&raw).unwrap();
+        assert_eq!(decoded, detached_signature);
+        assert_eq!(decoded.attach(payload).unwrap(), wrapper);
+    }
+
+    #[test]
+    fn detach_and_attach_nested() {
+        let (master_sk, master_pk) = keys(1);
+        let (device_sk, device_pk) = keys(2);
+        let inner = AuthWrapper::sign(&device_sk, b"large payload".to_vec());
+        let wrapper = DelegationCertificate::sign(&master_sk, device_pk)
+            .wrap(&inner)
+            .parse()
+            .unwrap();
+        let (detached_signature, payload) = wrapper.clone().detach();
+        assert!(detached_signature.nested);
+        assert_eq!(detached_signature.version, wrapper.version);
+
+        let raw = detached_signature.to_bytes();
+        let attached = DetachedSignature::from_bytes(&raw)
+            .unwrap()
+            .attach(payload)
+            .unwrap();
+        assert_eq!(attached, wrapper);
+        assert_eq!(
+            attached.verify_chain(&[master_pk]).unwrap().signer(),
+            &device_pk
+        );
+    }
+
+    #[test]
+    fn fraudulent_payload() {
+        let (private_key, _) = keys(1);
+        let (detached_signature, _) = sign(&private_key, b"large payload".to_vec()).detach();
+        assert_eq!(
+            detached_signature.verify(b"other payload"),
+            Err(DetachedError::FraudulentDigest)
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let (private_key, _) = keys(1);
+        let mut detached_signature =
+            sign(&private_key, b"large payload".to_vec()).detached_signature();
+        detached_signature.version = VERSION + 1;
+        assert_eq!(
+            DetachedSignature::from_bytes(&detached_signature.to_bytes()),
+            Err(DetachedError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn unexpected_length() {
+        assert_eq!(
+            DetachedSignature::from_bytes(&[0; 32]),
+            Err(DetachedError::UnexpectedLength)
+        );
+    }
+}
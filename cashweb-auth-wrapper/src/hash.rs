@@ -0,0 +1,171 @@
+//! This module contains the backend used for SHA-256 and HMAC-SHA256, shared with the other
+//! cash:web crates.
+//!
+//! By default [`ring`] is used. The `sha2-backend` feature switches to the RustCrypto `sha2` and
+//! `hmac` crates, and the `asm` feature additionally enables their assembly implementations,
+//! including SHA-NI where available.
+//!
+//! BLAKE3 is available, as a [`DigestAlgorithm`], when the `blake3` feature is enabled.
+
+#[cfg(not(feature = "sha2-backend"))]
+use std::convert::TryInto;
+
+#[cfg(feature = "sha2-backend")]
+use hmac::{Hmac, Mac, NewMac};
+#[cfg(not(feature = "sha2-backend"))]
+use ring::{
+    digest::{digest as ring_digest, SHA256},
+    hmac::{sign, Key as HmacKey, HMAC_SHA256},
+};
+#[cfg(feature = "sha2-backend")]
+use sha2::{Digest, Sha256};
+
+use crate::DigestAlgorithm;
+
+/// A SHA-256 and HMAC-SHA256 implementation.
+pub(crate) trait HashBackend {
+    /// Calculate the SHA-256 digest of the data.
+    fn sha256(data: &[u8]) -> [u8; 32];
+
+    /// Calculate the HMAC-SHA256 of the data.
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32];
+}
+
+/// The [`ring`] backend.
+#[cfg(not(feature = "sha2-backend"))]
+#[derive(Debug)]
+pub(crate) struct Ring;
+
+#[cfg(not(feature = "sha2-backend"))]
+impl HashBackend for Ring {
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        ring_digest(&SHA256, data).as_ref().try_into().unwrap() // This is safe
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let key = HmacKey::new(HMAC_SHA256, key);
+        sign(&key, data).as_ref().try_into().unwrap() // This is safe
+    }
+}
+
+/// The RustCrypto backend.
+#[cfg(feature = "sha2-backend")]
+#[derive(Debug)]
+pub(crate) struct RustCrypto;
+
+#[cfg(feature = "sha2-backend")]
+impl HashBackend for RustCrypto {
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap(); // This is safe
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// The backend selected by the enabled features.
+#[cfg(not(feature = "sha2-backend"))]
+pub(crate) type Backend = Ring;
+
+/// The backend selected by the enabled features.
+#[cfg(feature = "sha2-backend")]
+pub(crate) type Backend = RustCrypto;
+
+/// Calculate the SHA-256 digest of the data using the selected backend.
+#[inline]
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Backend::sha256(data)
+}
+
+/// Calculate the HMAC-SHA256 of the data using the selected backend.
+#[inline]
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    Backend::hmac_sha256(key, data)
+}
+
+/// Calculate the BLAKE3 digest of the data, if the `blake3` feature is enabled.
+#[cfg(feature = "blake3")]
+#[inline]
+pub fn blake3_digest(data: &[u8]) -> Option<[u8; 32]> {
+    Some(*blake3::hash(data).as_bytes())
+}
+
+/// Calculate the BLAKE3 digest of the data, if the `blake3` feature is enabled.
+#[cfg(not(feature = "blake3"))]
+#[inline]
+pub fn blake3_digest(_data: &[u8]) -> Option<[u8; 32]> {
+    None
+}
+
+/// Calculate the BLAKE3 keyed hash of the data, keyed by the BLAKE3 digest of the key, if the
+/// `blake3` feature is enabled.
+#[cfg(feature = "blake3")]
+#[inline]
+pub fn blake3_hmac(key: &[u8], data: &[u8]) -> Option<[u8; 32]> {
+    let key = blake3::hash(key);
+    Some(*blake3::keyed_hash(key.as_bytes(), data).as_bytes())
+}
+
+/// Calculate the BLAKE3 keyed hash of the data, keyed by the BLAKE3 digest of the key, if the
+/// `blake3` feature is enabled.
+#[cfg(not(feature = "blake3"))]
+#[inline]
+pub fn blake3_hmac(_key: &[u8], _data: &[u8]) -> Option<[u8; 32]> {
+    None
+}
+
+/// Calculate the digest of the data under a [`DigestAlgorithm`], if it is supported.
+pub(crate) fn digest(algorithm: DigestAlgorithm, data: &[u8]) -> Option<[u8; 32]> {
+    match algorithm {
+        DigestAlgorithm::Sha256 => Some(sha256(data)),
+        DigestAlgorithm::Blake3 => blake3_digest(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_abc() {
+        let expected = [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ];
+        assert_eq!(sha256(b"abc"), expected);
+    }
+
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        // Test case 2 of RFC 4231
+        let expected = [
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43,
+        ];
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            expected
+        );
+    }
+
+    #[test]
+    fn digest_algorithms() {
+        assert_eq!(
+            digest(DigestAlgorithm::Sha256, b"abc"),
+            Some(sha256(b"abc"))
+        );
+        assert_eq!(
+            digest(DigestAlgorithm::Blake3, b"abc").is_some(),
+            cfg!(feature = "blake3")
+        );
+        assert_eq!(
+            blake3_hmac(b"key", b"abc").is_some(),
+            cfg!(feature = "blake3")
+        );
+    }
+}
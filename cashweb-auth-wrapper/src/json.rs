@@ -0,0 +1,109 @@
+//! This module contains the canonical [`proto3 JSON mapping`] of the protobuf models.
+//!
+//! When the `serde` feature is enabled the models implement [`Serialize`](serde::Serialize) and
+//! [`Deserialize`](serde::Deserialize) following the mapping: field names are `lowerCamelCase`,
+//! `bytes` are base64 encoded, 64-bit integers are strings and enums are their variant names.
+//! Deserialization additionally accepts URL-safe base64, numeric 64-bit integers and numeric enums.
+//!
+//! The mapping is implemented by the helpers of [`proto_json`], the `json` feature re-exports its
+//! [`to_json`] and [`from_json`] helpers.
+//!
+//! [`proto3 JSON mapping`]: https://developers.google.com/protocol-buffers/docs/proto3#json
+
+pub(crate) use proto_json::{bytes, int64};
+#[cfg(feature = "json")]
+pub use proto_json::{from_json, to_json};
+
+use proto_json::{deserialize_enum, serialize_enum};
+use serde::{Deserializer, Serializer};
+
+pub(crate) mod signature_scheme {
+    use super::*;
+    use crate::models::auth_wrapper::SignatureScheme;
+
+    pub(crate) fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = SignatureScheme::from_i32(*value).map(|variant| match variant {
+            SignatureScheme::Schnorr => "SCHNORR",
+            SignatureScheme::Ecdsa => "ECDSA",
+        });
+        serialize_enum(*value, name, serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "SCHNORR" => Some(SignatureScheme::Schnorr as i32),
+            "ECDSA" => Some(SignatureScheme::Ecdsa as i32),
+            _ => None,
+        })
+    }
+}
+
+pub(crate) mod digest_algorithm {
+    use super::*;
+    use crate::models::auth_wrapper::DigestAlgorithm;
+
+    pub(crate) fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = DigestAlgorithm::from_i32(*value).map(|variant| match variant {
+            DigestAlgorithm::Sha256 => "SHA256",
+            DigestAlgorithm::Blake3 => "BLAKE3",
+        });
+        serialize_enum(*value, name, serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "SHA256" => Some(DigestAlgorithm::Sha256 as i32),
+            "BLAKE3" => Some(DigestAlgorithm::Blake3 as i32),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AuthWrapper, DigestAlgorithm};
+
+    #[test]
+    fn derive_serde() {
+        let wrapper = AuthWrapper {
+            public_key: vec![2; 33],
+            payload: b"payload".to_vec(),
+            nested: true,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&wrapper).unwrap();
+        assert!(value.get("publicKey").is_some());
+        assert_eq!(
+            serde_json::from_value::<AuthWrapper>(value).unwrap(),
+            wrapper
+        );
+    }
+
+    #[test]
+    fn digest_algorithm() {
+        let wrapper = AuthWrapper {
+            digest_algorithm: DigestAlgorithm::Blake3.into(),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&wrapper).unwrap();
+        assert_eq!(value["digestAlgorithm"], "BLAKE3");
+        assert_eq!(
+            serde_json::from_value::<AuthWrapper>(value).unwrap(),
+            wrapper
+        );
+
+        // Unknown values are preserved as numbers, unknown names are rejected
+        let wrapper = AuthWrapper {
+            digest_algorithm: 7,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&wrapper).unwrap();
+        assert_eq!(value["digestAlgorithm"], 7);
+        assert_eq!(
+            serde_json::from_value::<AuthWrapper>(value).unwrap(),
+            wrapper
+        );
+        let value = serde_json::json!({ "digestAlgorithm": "SHA3" });
+        assert!(serde_json::from_value::<AuthWrapper>(value).is_err());
+    }
+}
@@ -7,22 +7,61 @@
 
 //! `cashweb-auth-wrapper` is a library providing deserialization, parsing, and verification needed within the [`Authorization Wrapper Framework`].
 //!
+//! Unknown fields are skipped during decoding, so wrappers produced under later revisions of the
+//! protocol still decode. Parsing then rejects wrappers whose version is not supported with
+//! [`ParseError::UnsupportedVersion`].
+//!
+//! The payload digest is calculated using the [`DigestAlgorithm`] given by the wrapper, SHA256 by
+//! default. BLAKE3 is supported when the `blake3` feature is enabled.
+//!
 //! [`Authorization Wrapper Framework`]: https://github.com/cashweb/specifications/blob/master/authorization-wrapper/specification.mediawiki
 
+pub mod batch;
+pub mod chain;
+pub mod detached;
+pub mod hash;
+#[cfg(feature = "serde")]
+pub mod json;
 #[allow(unreachable_pub)]
 mod models;
+pub mod revocation;
 
 use std::convert::TryInto;
 
-use ring::digest::{digest, SHA256};
-use secp256k1::{key::PublicKey, Error as SecpError, Message, Secp256k1, Signature};
+use once_cell::sync::OnceCell;
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Message, Secp256k1, Signature, Signing, Verification, VerifyOnly,
+};
 use thiserror::Error;
 
-pub use models::{auth_wrapper::SignatureScheme, AuthWrapper};
+pub use error_code::ErrorCode;
+pub use models::{
+    auth_wrapper::{DigestAlgorithm, SignatureScheme},
+    AuthWrapper, Revocation,
+};
+
+/// The latest version of the authorization wrapper protocol supported.
+pub const VERSION: u32 = 1;
+
+/// The earliest version of the authorization wrapper protocol supported.
+pub const MIN_VERSION: u32 = 1;
+
+/// Interpret the version field, an unset version is interpreted as version 1.
+#[inline]
+pub fn effective_version(version: u32) -> u32 {
+    if version == 0 {
+        1
+    } else {
+        version
+    }
+}
 
 /// Represents an [`AuthWrapper`] post-parsing.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParsedAuthWrapper {
+    /// The version of the authorization wrapper protocol.
+    pub version: u32,
     /// The public key associated with the signature.
     pub public_key: PublicKey,
     /// The signature by public key covering the payload.
@@ -31,8 +70,12 @@ pub struct ParsedAuthWrapper {
     pub scheme: SignatureScheme,
     /// The payload covered by the signature.
     pub payload: Vec<u8>,
-    /// The SHA256 digest of the payload.
+    /// The digest of the payload.
     pub payload_digest: [u8; 32],
+    /// The digest algorithm used to calculate the payload digest.
+    pub digest_algorithm: DigestAlgorithm,
+    /// Whether the payload is a nested [`AuthWrapper`], see [`chain`].
+    pub nested: bool,
 }
 
 /// Error associated with validation and parsing of the [`AuthWrapper`].
@@ -56,15 +99,104 @@ pub enum ParseError {
     /// The `payload_digest` was not 32 bytes long.
     #[error("unexpected length digest")]
     UnexpectedLengthDigest,
+    /// The version of the authorization wrapper protocol is unsupported.
+    #[error("unsupported version: {0}")]
+    UnsupportedVersion(u32),
+    /// The digest algorithm is unsupported, the feature enabling it may be disabled.
+    #[error("unsupported digest algorithm")]
+    UnsupportedDigestAlgorithm,
+}
+
+impl ErrorCode for ParseError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::PublicKey(_) => 4001,
+            Self::Signature(_) => 4002,
+            Self::UnsupportedScheme => 4003,
+            Self::FraudulentDigest => 4004,
+            Self::DigestAndPayloadMissing => 4005,
+            Self::UnexpectedLengthDigest => 4006,
+            Self::UnsupportedVersion(_) => 4007,
+            Self::UnsupportedDigestAlgorithm => 4008,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::PublicKey(_) => "auth_wrapper.public_key",
+            Self::Signature(_) => "auth_wrapper.signature",
+            Self::UnsupportedScheme => "auth_wrapper.unsupported_scheme",
+            Self::FraudulentDigest => "auth_wrapper.fraudulent_digest",
+            Self::DigestAndPayloadMissing => "auth_wrapper.digest_and_payload_missing",
+            Self::UnexpectedLengthDigest => "auth_wrapper.unexpected_length_digest",
+            Self::UnsupportedVersion(_) => "auth_wrapper.unsupported_version",
+            Self::UnsupportedDigestAlgorithm => "auth_wrapper.unsupported_digest_algorithm",
+        }
+    }
+}
+
+/// The digest algorithm is unsupported, the feature enabling it may be disabled.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unsupported digest algorithm")]
+pub struct UnsupportedDigestAlgorithm;
+
+impl DigestAlgorithm {
+    /// Check whether the digest algorithm is supported by the enabled features.
+    #[inline]
+    pub fn is_supported(self) -> bool {
+        self.digest(&[]).is_ok()
+    }
+
+    /// Calculate the digest of the data.
+    #[inline]
+    pub fn digest(self, data: &[u8]) -> Result<[u8; 32], UnsupportedDigestAlgorithm> {
+        hash::digest(self, data).ok_or(UnsupportedDigestAlgorithm)
+    }
 }
 
 impl AuthWrapper {
+    /// Sign a payload using ECDSA, the payload digest being calculated using SHA256.
+    #[inline]
+    pub fn sign(private_key: &SecretKey, payload: Vec<u8>) -> Self {
+        Self::sign_with_context(&Secp256k1::signing_only(), private_key, payload)
+    }
+
+    /// Sign a payload using ECDSA and an existing context, the payload digest being calculated
+    /// using SHA256.
+    #[inline]
+    pub fn sign_with_context<C: Signing>(
+        secp: &Secp256k1<C>,
+        private_key: &SecretKey,
+        payload: Vec<u8>,
+    ) -> Self {
+        let payload_digest = hash::sha256(&payload);
+        let msg = Message::from_slice(&payload_digest).unwrap(); // This is safe
+        Self {
+            public_key: PublicKey::from_secret_key(secp, private_key)
+                .serialize()
+                .to_vec(),
+            signature: secp.sign(&msg, private_key).serialize_compact().to_vec(),
+            scheme: SignatureScheme::Ecdsa.into(),
+            payload,
+            payload_digest: payload_digest.to_vec(),
+            version: VERSION,
+            digest_algorithm: DigestAlgorithm::Sha256.into(),
+            nested: false,
+        }
+    }
+
     /// Parse the [`AuthWrapper`] to construct a [`ParsedAuthWrapper`].
     ///
     /// The involves deserialization of both public keys, calculation of the payload digest, and coercion of byte fields
     /// into fixed-length arrays.
     #[inline]
     pub fn parse(self) -> Result<ParsedAuthWrapper, ParseError> {
+        // Check version
+        let version = effective_version(self.version);
+        if version < MIN_VERSION || version > VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+
         // Parse public key
         let public_key = PublicKey::from_slice(&self.public_key).map_err(ParseError::PublicKey)?;
 
@@ -74,20 +206,23 @@ impl AuthWrapper {
         // Parse signature
         let signature = Signature::from_compact(&self.signature).map_err(ParseError::Signature)?;
 
+        // Parse digest algorithm
+        let digest_algorithm = DigestAlgorithm::from_i32(self.digest_algorithm)
+            .filter(|algorithm| algorithm.is_supported())
+            .ok_or(ParseError::UnsupportedDigestAlgorithm)?;
+
         // Construct and validate payload digest
         let payload_digest = match self.payload_digest.len() {
             0 => {
                 if self.payload.is_empty() {
                     return Err(ParseError::DigestAndPayloadMissing);
                 } else {
-                    let payload_digest = digest(&SHA256, &self.payload);
-                    let digest_arr: [u8; 32] = payload_digest.as_ref().try_into().unwrap();
-                    digest_arr
+                    digest_algorithm.digest(&self.payload).unwrap() // This is safe
                 }
             }
             32 => {
-                let payload_digest = digest(&SHA256, &self.payload);
-                if *payload_digest.as_ref() != self.payload_digest[..] {
+                let payload_digest = digest_algorithm.digest(&self.payload).unwrap(); // This is safe
+                if payload_digest[..] != self.payload_digest[..] {
                     return Err(ParseError::FraudulentDigest);
                 }
                 let digest_arr: [u8; 32] = self.payload_digest[..].try_into().unwrap();
@@ -97,10 +232,13 @@ impl AuthWrapper {
         };
 
         Ok(ParsedAuthWrapper {
+            version,
             public_key,
             scheme,
             signature,
             payload_digest,
+            digest_algorithm,
+            nested: self.nested,
             payload: self.payload,
         })
     }
@@ -117,19 +255,145 @@ pub enum VerifyError {
     UnsupportedScheme,
 }
 
+impl ErrorCode for VerifyError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::InvalidSignature(_) => 4101,
+            Self::UnsupportedScheme => 4102,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::InvalidSignature(_) => "auth_wrapper.invalid_signature",
+            Self::UnsupportedScheme => "auth_wrapper.verify_unsupported_scheme",
+        }
+    }
+}
+
+/// The context used by the verification methods not given one, constructing a context is expensive.
+pub(crate) fn shared_context() -> &'static Secp256k1<VerifyOnly> {
+    static CONTEXT: OnceCell<Secp256k1<VerifyOnly>> = OnceCell::new();
+    CONTEXT.get_or_init(Secp256k1::verification_only)
+}
+
+/// Verify a signature over a payload digest.
+#[inline]
+fn verify_digest<C: Verification>(
+    secp: &Secp256k1<C>,
+    scheme: SignatureScheme,
+    payload_digest: &[u8; 32],
+    signature: &Signature,
+    public_key: &PublicKey,
+) -> Result<(), VerifyError> {
+    if scheme == SignatureScheme::Schnorr {
+        // TODO: Support Schnorr
+        return Err(VerifyError::UnsupportedScheme);
+    }
+    // Verify signature on the message
+    let msg = Message::from_slice(payload_digest.as_ref()).unwrap(); // This is safe
+    secp.verify(&msg, signature, public_key)
+        .map_err(VerifyError::InvalidSignature)?;
+    Ok(())
+}
+
 impl ParsedAuthWrapper {
     /// Verify the signature on [`ParsedAuthWrapper`].
     #[inline]
     pub fn verify(&self) -> Result<(), VerifyError> {
-        if self.scheme == SignatureScheme::Schnorr {
-            // TODO: Support Schnorr
-            return Err(VerifyError::UnsupportedScheme);
-        }
-        // Verify signature on the message
-        let msg = Message::from_slice(self.payload_digest.as_ref()).unwrap(); // This is safe
-        let secp = Secp256k1::verification_only();
-        secp.verify(&msg, &self.signature, &self.public_key)
-            .map_err(VerifyError::InvalidSignature)?;
-        Ok(())
+        self.verify_with_context(shared_context())
+    }
+
+    /// Verify the signature on [`ParsedAuthWrapper`] using an existing context.
+    ///
+    /// Constructing a context is expensive, this allows it to be reused across many verifications.
+    #[inline]
+    pub fn verify_with_context<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), VerifyError> {
+        verify_digest(
+            secp,
+            self.scheme,
+            &self.payload_digest,
+            &self.signature,
+            &self.public_key,
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use prost::Message as _;
+
+    use super::*;
+
+    /// The key pair whose private key repeats a byte.
+    pub(crate) fn keys(byte: u8) -> (SecretKey, PublicKey) {
+        let private_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        (private_key, public_key)
+    }
+
+    /// Sign and parse a wrapper over a payload.
+    pub(crate) fn sign(private_key: &SecretKey, payload: Vec<u8>) -> ParsedAuthWrapper {
+        AuthWrapper::sign(private_key, payload).parse().unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let (private_key, _) = keys(1);
+        let parsed = sign(&private_key, b"payload".to_vec());
+        assert_eq!(parsed.scheme, SignatureScheme::Ecdsa);
+        assert_eq!(parsed.payload, b"payload");
+        parsed.verify().unwrap();
+    }
+
+    #[test]
+    fn versions() {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut wrapper = AuthWrapper::sign(&private_key, b"payload".to_vec());
+        assert_eq!(wrapper.clone().parse().unwrap().version, VERSION);
+
+        // An unset version is version 1
+        wrapper.version = 0;
+        assert_eq!(wrapper.clone().parse().unwrap().version, 1);
+
+        wrapper.version = VERSION + 1;
+        assert_eq!(
+            wrapper.parse(),
+            Err(ParseError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn skip_unknown_fields() {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let wrapper = AuthWrapper::sign(&private_key, b"payload".to_vec());
+        let mut raw_wrapper = Vec::with_capacity(wrapper.encoded_len());
+        wrapper.encode(&mut raw_wrapper).unwrap();
+
+        // Field 15, varint 1
+        raw_wrapper.extend_from_slice(&[0x78, 0x01]);
+        assert_eq!(AuthWrapper::decode(&raw_wrapper[..]).unwrap(), wrapper);
+    }
+
+    #[test]
+    fn digest_algorithms() {
+        assert_eq!(
+            DigestAlgorithm::Sha256.digest(b"abc"),
+            Ok(hash::sha256(b"abc"))
+        );
+        assert!(DigestAlgorithm::Sha256.is_supported());
+        assert_eq!(
+            DigestAlgorithm::Blake3.digest(b"abc").is_ok(),
+            cfg!(feature = "blake3")
+        );
+
+        // Unknown algorithms are rejected when parsing
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut wrapper = AuthWrapper::sign(&private_key, b"payload".to_vec());
+        wrapper.digest_algorithm = 7;
+        assert_eq!(wrapper.parse(), Err(ParseError::UnsupportedDigestAlgorithm));
     }
 }
@@ -0,0 +1,254 @@
+//! This module contains methods for verifying [`Revocation`]s and the [`RevocationSet`].
+//!
+//! A [`Revocation`] is carried as the payload of an [`AuthWrapper`] and invalidates a payload
+//! previously signed by the same public key, identified by its digest. Consumers collect verified
+//! revocations into a [`RevocationSet`] and refuse wrappers which have been revoked.
+//!
+//! The signature on a revocation covers the [`revocation_digest`] of the payload, domain separated
+//! from the payload digest, hence a signature over content which happens to decode as a
+//! [`Revocation`] does not revoke anything.
+
+use std::{collections::HashMap, convert::TryInto};
+
+use prost::{DecodeError, Message as _};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Message, Secp256k1, Signing,
+};
+use thiserror::Error;
+
+use crate::{
+    hash, shared_context, verify_digest, AuthWrapper, DigestAlgorithm, ParsedAuthWrapper,
+    Revocation, SignatureScheme, VerifyError, VERSION,
+};
+
+/// The domain separation tag prefixed to the payload in the [`revocation_digest`].
+pub const REVOCATION_TAG: &[u8] = b"cashweb-auth-wrapper/revocation";
+
+/// Calculate the digest signed to revoke, given the serialized [`Revocation`].
+///
+/// This is the SHA-256 digest of the [`REVOCATION_TAG`] followed by the payload.
+pub fn revocation_digest(payload: &[u8]) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(REVOCATION_TAG.len() + payload.len());
+    preimage.extend_from_slice(REVOCATION_TAG);
+    preimage.extend_from_slice(payload);
+    hash::sha256(&preimage)
+}
+
+impl Revocation {
+    /// Sign the [`Revocation`] using ECDSA, wrapping it in an [`AuthWrapper`].
+    #[inline]
+    pub fn sign(&self, private_key: &SecretKey) -> AuthWrapper {
+        self.sign_with_context(&Secp256k1::signing_only(), private_key)
+    }
+
+    /// Sign the [`Revocation`] using ECDSA and an existing context, wrapping it in an
+    /// [`AuthWrapper`].
+    pub fn sign_with_context<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        private_key: &SecretKey,
+    ) -> AuthWrapper {
+        let mut payload = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut payload).unwrap(); // This is safe
+        let msg = Message::from_slice(&revocation_digest(&payload)).unwrap(); // This is safe
+        AuthWrapper {
+            public_key: PublicKey::from_secret_key(secp, private_key)
+                .serialize()
+                .to_vec(),
+            signature: secp.sign(&msg, private_key).serialize_compact().to_vec(),
+            scheme: SignatureScheme::Ecdsa.into(),
+            payload_digest: hash::sha256(&payload).to_vec(),
+            payload,
+            version: VERSION,
+            digest_algorithm: DigestAlgorithm::Sha256.into(),
+            nested: false,
+        }
+    }
+}
+
+/// Represents a [`Revocation`] post-verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedRevocation {
+    /// The public key which signed the revocation.
+    pub public_key: PublicKey,
+    /// The digest of the revoked payload.
+    pub payload_digest: [u8; 32],
+    /// The time of revocation. Given in unix time milliseconds.
+    pub timestamp: i64,
+}
+
+/// Error associated with verifying a [`Revocation`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RevocationError {
+    /// The wrapper failed verification.
+    #[error(transparent)]
+    Verify(VerifyError),
+    /// Failed to decode the [`Revocation`] payload.
+    #[error("revocation decoding failure: {0}")]
+    Decode(DecodeError),
+    /// The revoked payload digest was not 32 bytes long.
+    #[error("unexpected length digest")]
+    UnexpectedLengthDigest,
+}
+
+impl ParsedAuthWrapper {
+    /// Verify the signature on the [`ParsedAuthWrapper`], over the [`revocation_digest`] of its
+    /// payload, and decode its payload as a [`Revocation`].
+    pub fn revocation(&self) -> Result<ParsedRevocation, RevocationError> {
+        // Verify signature
+        verify_digest(
+            shared_context(),
+            self.scheme,
+            &revocation_digest(&self.payload),
+            &self.signature,
+            &self.public_key,
+        )
+        .map_err(RevocationError::Verify)?;
+
+        // Decode revocation
+        let revocation =
+            Revocation::decode(self.payload.as_slice()).map_err(RevocationError::Decode)?;
+        let payload_digest: [u8; 32] = revocation.payload_digest[..]
+            .try_into()
+            .map_err(|_| RevocationError::UnexpectedLengthDigest)?;
+
+        Ok(ParsedRevocation {
+            public_key: self.public_key,
+            payload_digest,
+            timestamp: revocation.timestamp,
+        })
+    }
+}
+
+/// A collection of verified revocations, keyed by the revoked payload digest.
+#[derive(Debug, Clone, Default)]
+pub struct RevocationSet {
+    revoked: HashMap<[u8; 32], Vec<PublicKey>>,
+}
+
+impl RevocationSet {
+    /// Create a new, empty, [`RevocationSet`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Insert a verified revocation.
+    pub fn insert(&mut self, revocation: ParsedRevocation) {
+        let public_keys = self.revoked.entry(revocation.payload_digest).or_default();
+        if !public_keys.contains(&revocation.public_key) {
+            public_keys.push(revocation.public_key);
+        }
+    }
+
+    /// Verify a wrapped [`Revocation`] and insert it.
+    pub fn insert_wrapper(&mut self, wrapper: &ParsedAuthWrapper) -> Result<(), RevocationError> {
+        let revocation = wrapper.revocation()?;
+        self.insert(revocation);
+        Ok(())
+    }
+
+    /// Check whether the payload digest has been revoked by the public key.
+    pub fn contains(&self, public_key: &PublicKey, payload_digest: &[u8; 32]) -> bool {
+        self.revoked
+            .get(payload_digest)
+            .map(|public_keys| public_keys.contains(public_key))
+            .unwrap_or(false)
+    }
+
+    /// Check whether the [`ParsedAuthWrapper`] has been revoked by its signer.
+    pub fn is_revoked(&self, wrapper: &ParsedAuthWrapper) -> bool {
+        self.contains(&wrapper.public_key, &wrapper.payload_digest)
+    }
+
+    /// The number of revoked payload digests.
+    pub fn len(&self) -> usize {
+        self.revoked.len()
+    }
+
+    /// Check whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.revoked.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{keys, sign};
+
+    fn revoke(private_key: &SecretKey, payload_digest: &[u8; 32]) -> ParsedAuthWrapper {
+        Revocation {
+            payload_digest: payload_digest.to_vec(),
+            timestamp: 1_600_000_000_000,
+        }
+        .sign(private_key)
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn revoked_by_signer() {
+        let (private_key, _) = keys(1);
+        let metadata = sign(&private_key, b"metadata".to_vec());
+
+        let mut revocations = RevocationSet::new();
+        assert!(!revocations.is_revoked(&metadata));
+        revocations
+            .insert_wrapper(&revoke(&private_key, &metadata.payload_digest))
+            .unwrap();
+        assert!(revocations.is_revoked(&metadata));
+        assert_eq!(revocations.len(), 1);
+    }
+
+    #[test]
+    fn revoked_by_other_key() {
+        let (private_key, _) = keys(1);
+        let (other_key, _) = keys(2);
+        let metadata = sign(&private_key, b"metadata".to_vec());
+
+        let mut revocations = RevocationSet::new();
+        revocations
+            .insert_wrapper(&revoke(&other_key, &metadata.payload_digest))
+            .unwrap();
+        assert!(!revocations.is_revoked(&metadata));
+    }
+
+    #[test]
+    fn domain_separation() {
+        let (private_key, _) = keys(1);
+        let revocation = Revocation {
+            payload_digest: vec![0; 32],
+            timestamp: 0,
+        };
+        let mut payload = Vec::with_capacity(revocation.encoded_len());
+        revocation.encode(&mut payload).unwrap();
+
+        // A signature over the payload digest is not a revocation
+        match sign(&private_key, payload).revocation() {
+            Err(RevocationError::Verify(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // A revocation is not a signature over the payload digest
+        let wrapper = revocation.sign(&private_key).parse().unwrap();
+        wrapper.revocation().unwrap();
+        assert!(wrapper.verify().is_err());
+    }
+
+    #[test]
+    fn unexpected_length_digest() {
+        let (private_key, _) = keys(1);
+        let wrapper = Revocation {
+            payload_digest: vec![0; 16],
+            timestamp: 0,
+        }
+        .sign(&private_key)
+        .parse()
+        .unwrap();
+        assert_eq!(
+            wrapper.revocation(),
+            Err(RevocationError::UnexpectedLengthDigest)
+        );
+    }
+}
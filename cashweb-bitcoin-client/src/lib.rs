@@ -7,7 +7,27 @@
 
 //! `cashweb-bitcoin-client` is a library providing a [`BitcoinClient`] with
 //! basic asynchronous methods for interacting with bitcoind.
+//!
+//! When the `metrics` feature is enabled the [`BitcoinClient`] emits the following via the
+//! [`metrics`] facade, labelled by JSON-RPC `method`:
+//! * `cashweb_bitcoin_client_calls`: counter of every call, labelled by `outcome`, this is `ok` or
+//!   the [`ErrorCode::label`] of the failure.
+//! * `cashweb_bitcoin_client_errors`: counter of failed calls, labelled by error `code`.
+//! * `cashweb_bitcoin_client_call_ns`: histogram of call latency in nanoseconds.
+//!
+//! The timer used by [`BitcoinClient::watch_mempool`] is tokio by default, disabling the `tokio`
+//! feature selects an executor-agnostic timer.
+//!
+//! [`metrics`]: https://docs.rs/metrics
 
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use clock::delay_for;
+use futures_core::Stream;
+use futures_util::stream;
 use hex::FromHexError;
 use hyper::{
     client::HttpConnector, Body, Client as HyperClient, Error as HyperError,
@@ -19,12 +39,15 @@ use json_rpc::{
         http::{Client as JsonClient, ConnectionError},
         Error as RpcCallError,
     },
-    prelude::{JsonError, RequestFactory, RpcError},
+    prelude::{JsonError, RequestFactory, Response, RpcError},
 };
+use serde::Deserialize;
 use serde_json::Value;
 use thiserror::Error;
 use tower_service::Service;
 
+pub use error_code::ErrorCode;
+
 /// Standard HTTP client.
 pub type HttpClient = HyperClient<HttpConnector>;
 
@@ -76,6 +99,91 @@ impl<C> std::ops::Deref for BitcoinClient<C> {
     }
 }
 
+/// The verbose result of the `getrawtransaction` method.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VerboseTransaction {
+    /// The hex-encoded raw transaction.
+    pub hex: String,
+    /// The transaction ID.
+    pub txid: String,
+    /// The number of confirmations, this is zero when the transaction is in the mempool.
+    #[serde(default)]
+    pub confirmations: u64,
+    /// The hash of the block containing the transaction.
+    pub blockhash: Option<String>,
+    /// The time of the block containing the transaction.
+    pub blocktime: Option<u64>,
+}
+
+impl VerboseTransaction {
+    /// Decode the hex-encoded raw transaction.
+    pub fn raw_transaction(&self) -> Result<Vec<u8>, FromHexError> {
+        hex::decode(&self.hex)
+    }
+}
+
+/// The verbose result of the `getblockheader` method.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VerboseBlockHeader {
+    /// The block hash.
+    pub hash: String,
+    /// The number of confirmations, this is -1 when the block is not in the main chain.
+    pub confirmations: i64,
+    /// The height of the block.
+    pub height: u32,
+    /// The time of the block.
+    pub time: u64,
+    /// The median time past of the block.
+    pub mediantime: u64,
+    /// The hash of the previous block.
+    pub previousblockhash: Option<String>,
+    /// The hash of the next block in the main chain.
+    pub nextblockhash: Option<String>,
+}
+
+/// The result of the `fundrawtransaction` method.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FundedTransaction {
+    /// The hex-encoded funded raw transaction.
+    pub hex: String,
+    /// The fee paid by the transaction, in BCH.
+    pub fee: f64,
+    /// The position of the change output, this is -1 when no change was added.
+    pub changepos: i64,
+}
+
+impl FundedTransaction {
+    /// Decode the hex-encoded raw transaction.
+    pub fn raw_transaction(&self) -> Result<Vec<u8>, FromHexError> {
+        hex::decode(&self.hex)
+    }
+}
+
+/// The result of the `signrawtransactionwithwallet` method.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SignedTransaction {
+    /// The hex-encoded signed raw transaction.
+    pub hex: String,
+    /// Whether the transaction has a complete set of signatures.
+    pub complete: bool,
+}
+
+impl SignedTransaction {
+    /// Decode the hex-encoded raw transaction.
+    pub fn raw_transaction(&self) -> Result<Vec<u8>, FromHexError> {
+        hex::decode(&self.hex)
+    }
+}
+
+/// The result of the `rescanblockchain` method.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RescanResult {
+    /// The height at which the rescan started.
+    pub start_height: u32,
+    /// The height at which the rescan stopped.
+    pub stop_height: u32,
+}
+
 /// Error associated with the Bitcoin RPC.
 #[derive(Debug, Error)]
 pub enum NodeError<E: std::fmt::Debug + std::fmt::Display + 'static> {
@@ -96,23 +204,90 @@ pub enum NodeError<E: std::fmt::Debug + std::fmt::Display + 'static> {
     HexDecode(#[from] FromHexError),
 }
 
+impl<E: std::fmt::Debug + std::fmt::Display + 'static> ErrorCode for NodeError<E> {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Http(_) => 3001,
+            Self::Rpc(_) => 3002,
+            Self::Json(_) => 3003,
+            Self::EmptyResponse => 3004,
+            Self::HexDecode(_) => 3005,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Http(_) => "node.http",
+            Self::Rpc(_) => "node.rpc",
+            Self::Json(_) => "node.json",
+            Self::EmptyResponse => "node.empty_response",
+            Self::HexDecode(_) => "node.hex_decode",
+        }
+    }
+}
+
 impl<S> BitcoinClient<S>
 where
     S: Service<HttpRequest<Body>, Response = HttpResponse<Body>> + Clone,
     S::Error: std::fmt::Debug + std::fmt::Display + 'static,
     S::Future: Send + 'static,
 {
+    /// Call a method, recording metrics when the `metrics` feature is enabled.
+    async fn call(
+        &self,
+        method: &'static str,
+        params: Vec<Value>,
+    ) -> Result<Response, NodeError<S::Error>> {
+        // Methods without parameters are sent without a `params` field
+        let request = if params.is_empty() {
+            self.build_request().method(method).finish()
+        } else {
+            self.build_request().method(method).params(params).finish()
+        }
+        .unwrap();
+
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+
+        let result = match self.send(request).await {
+            Ok(response) if response.is_error() => Err(NodeError::Rpc(response.error().unwrap())),
+            Ok(response) => Ok(response),
+            Err(err) => Err(NodeError::Http(err)),
+        };
+
+        #[cfg(feature = "metrics")]
+        {
+            let elapsed = start.elapsed().as_nanos() as u64;
+            metrics::histogram!("cashweb_bitcoin_client_call_ns", elapsed, "method" => method);
+            metrics::counter!(
+                "cashweb_bitcoin_client_calls",
+                1,
+                "method" => method,
+                "outcome" => call_outcome(&result)
+            );
+            match &result {
+                Ok(_) => {}
+                Err(NodeError::Rpc(err)) => metrics::counter!(
+                    "cashweb_bitcoin_client_errors",
+                    1,
+                    "method" => method,
+                    "code" => err.code.to_string()
+                ),
+                Err(_) => metrics::counter!(
+                    "cashweb_bitcoin_client_errors",
+                    1,
+                    "method" => method,
+                    "code" => "http"
+                ),
+            }
+        }
+
+        result
+    }
+
     /// Calls the `getnewaddress` method.
     pub async fn get_new_addr(&self) -> Result<String, NodeError<S::Error>> {
-        let request = self
-            .build_request()
-            .method("getnewaddress")
-            .finish()
-            .unwrap();
-        let response = self.send(request).await.map_err(NodeError::Http)?;
-        if response.is_error() {
-            return Err(NodeError::Rpc(response.error().unwrap()));
-        }
+        let response = self.call("getnewaddress", vec![]).await?;
         response
             .into_result()
             .ok_or(NodeError::EmptyResponse)?
@@ -121,17 +296,12 @@ where
 
     /// Calls the `sendrawtransaction` method.
     pub async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError<S::Error>> {
-        let request = self
-            .build_request()
-            .method("sendrawtransaction")
-            .params(vec![Value::String(hex::encode(raw_tx))])
-            .finish()
-            .unwrap();
-        let response = self.send(request).await.map_err(NodeError::Http)?;
-        if response.is_error() {
-            let err = response.error().unwrap();
-            return Err(NodeError::Rpc(err));
-        }
+        let response = self
+            .call(
+                "sendrawtransaction",
+                vec![Value::String(hex::encode(raw_tx))],
+            )
+            .await?;
         response
             .into_result()
             .ok_or(NodeError::EmptyResponse)?
@@ -140,20 +310,513 @@ where
 
     /// Calls the `getrawtransaction` method.
     pub async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError<S::Error>> {
-        let request = self
-            .build_request()
-            .method("getrawtransaction")
-            .params(vec![Value::String(hex::encode(tx_id))])
-            .finish()
-            .unwrap();
-        let response = self.send(request).await.map_err(NodeError::Http)?;
-        if response.is_error() {
-            return Err(NodeError::Rpc(response.error().unwrap()));
-        }
+        let response = self
+            .call("getrawtransaction", vec![Value::String(hex::encode(tx_id))])
+            .await?;
         let tx_hex: String = response
             .into_result()
             .ok_or(NodeError::EmptyResponse)?
             .map_err(NodeError::Json)?;
         hex::decode(tx_hex).map_err(Into::into)
     }
+
+    /// Calls the `getrawtransaction` method with the verbose flag set.
+    pub async fn get_raw_transaction_verbose(
+        &self,
+        tx_id: &[u8],
+    ) -> Result<VerboseTransaction, NodeError<S::Error>> {
+        let response = self
+            .call(
+                "getrawtransaction",
+                vec![Value::String(hex::encode(tx_id)), Value::Bool(true)],
+            )
+            .await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Calls the `fundrawtransaction` method.
+    pub async fn fund_raw_transaction(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<FundedTransaction, NodeError<S::Error>> {
+        let response = self
+            .call(
+                "fundrawtransaction",
+                vec![Value::String(hex::encode(raw_tx))],
+            )
+            .await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Calls the `signrawtransactionwithwallet` method.
+    pub async fn sign_raw_transaction_with_wallet(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<SignedTransaction, NodeError<S::Error>> {
+        let response = self
+            .call(
+                "signrawtransactionwithwallet",
+                vec![Value::String(hex::encode(raw_tx))],
+            )
+            .await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Calls the `importaddress` method, adding a watch-only address to the wallet.
+    ///
+    /// If `rescan` is set, the call blocks until the wallet has rescanned the chain for
+    /// transactions involving the address.
+    pub async fn import_address(
+        &self,
+        addr: &str,
+        rescan: bool,
+    ) -> Result<(), NodeError<S::Error>> {
+        self.call(
+            "importaddress",
+            vec![
+                Value::String(addr.to_string()),
+                Value::String(String::new()),
+                Value::Bool(rescan),
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Calls the `rescanblockchain` method, rescanning the chain from `start_height` to the tip.
+    pub async fn rescan_blockchain(
+        &self,
+        start_height: u32,
+    ) -> Result<RescanResult, NodeError<S::Error>> {
+        let response = self
+            .call("rescanblockchain", vec![Value::from(start_height)])
+            .await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Calls the `getblockheader` method with the verbose flag set.
+    pub async fn get_block_header_verbose(
+        &self,
+        block_hash: &[u8],
+    ) -> Result<VerboseBlockHeader, NodeError<S::Error>> {
+        let response = self
+            .call(
+                "getblockheader",
+                vec![Value::String(hex::encode(block_hash)), Value::Bool(true)],
+            )
+            .await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Get the number of confirmations of a transaction.
+    ///
+    /// This is zero when the transaction is in the mempool or its block has left the main chain.
+    pub async fn confirmations(&self, tx_id: &[u8]) -> Result<u64, NodeError<S::Error>> {
+        let transaction = self.get_raw_transaction_verbose(tx_id).await?;
+        let block_hash = match transaction.blockhash {
+            Some(some) => hex::decode(some)?,
+            None => return Ok(0),
+        };
+        let header = self.get_block_header_verbose(&block_hash).await?;
+        Ok(header.confirmations.max(0) as u64)
+    }
+
+    /// Calls the `getrawmempool` method.
+    pub async fn get_raw_mempool(&self) -> Result<Vec<String>, NodeError<S::Error>> {
+        let response = self.call("getrawmempool", vec![]).await?;
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    /// Poll the `getrawmempool` method every `interval`, yielding the transaction IDs not seen
+    /// before.
+    ///
+    /// The first poll establishes the initial mempool, only transactions observed afterwards are
+    /// yielded. The mempool is only polled as the stream is consumed and errors are yielded
+    /// without ending the stream.
+    ///
+    /// The most recent [`DEFAULT_SEEN_CAPACITY`] transaction IDs are remembered, see
+    /// [`watch_mempool_with_capacity`](BitcoinClient::watch_mempool_with_capacity).
+    #[inline]
+    pub fn watch_mempool(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<String, NodeError<S::Error>>> {
+        self.watch_mempool_with_capacity(interval, DEFAULT_SEEN_CAPACITY)
+    }
+
+    /// Poll the `getrawmempool` method every `interval`, yielding the transaction IDs not seen
+    /// before and remembering the most recent `capacity` transaction IDs.
+    ///
+    /// A transaction forgotten while still in the mempool is yielded again, the `capacity` should
+    /// exceed the expected size of the mempool.
+    pub fn watch_mempool_with_capacity(
+        &self,
+        interval: Duration,
+        capacity: usize,
+    ) -> impl Stream<Item = Result<String, NodeError<S::Error>>> {
+        let state = MempoolWatch {
+            client: self.clone(),
+            seen: SeenSet::new(capacity),
+            pending: VecDeque::new(),
+            started: false,
+            initialized: false,
+        };
+        stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(tx_id) = state.pending.pop_front() {
+                    return Some((Ok(tx_id), state));
+                }
+
+                // Wait between polls
+                if state.started {
+                    delay_for(interval).await;
+                }
+                state.started = true;
+
+                let mempool = match state.client.get_raw_mempool().await {
+                    Ok(ok) => ok,
+                    Err(err) => return Some((Err(err), state)),
+                };
+                for tx_id in mempool {
+                    if state.seen.insert(tx_id.clone()) && state.initialized {
+                        state.pending.push_back(tx_id);
+                    }
+                }
+                state.initialized = true;
+            }
+        })
+    }
+}
+
+/// The `outcome` label of a call, recorded by the `cashweb_bitcoin_client_calls` counter.
+#[cfg(any(feature = "metrics", test))]
+fn call_outcome<T, E: std::fmt::Debug + std::fmt::Display + 'static>(
+    result: &Result<T, NodeError<E>>,
+) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(err) => err.label(),
+    }
+}
+
+/// The default number of transaction IDs remembered by [`BitcoinClient::watch_mempool`].
+pub const DEFAULT_SEEN_CAPACITY: usize = 100_000;
+
+/// The state of [`BitcoinClient::watch_mempool`].
+struct MempoolWatch<S> {
+    client: BitcoinClient<S>,
+    seen: SeenSet,
+    pending: VecDeque<String>,
+    started: bool,
+    initialized: bool,
+}
+
+/// A set of transaction IDs which forgets the oldest once full.
+struct SeenSet {
+    set: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl SeenSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            set: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Insert a transaction ID, returning whether it was absent.
+    fn insert(&mut self, tx_id: String) -> bool {
+        if self.set.contains(&tx_id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+        if self.capacity != 0 {
+            self.set.insert(tx_id.clone());
+            self.order.push_back(tx_id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        convert::Infallible,
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Run a future to completion on a single threaded runtime.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// A successful JSON-RPC response.
+    fn ok(result: Value) -> Value {
+        json!({ "result": result, "error": null })
+    }
+
+    /// Responds to JSON-RPC requests in turn, repeating the last response, and records the
+    /// requests.
+    #[derive(Clone, Debug)]
+    struct MockNode {
+        responses: Arc<Mutex<VecDeque<Value>>>,
+        requests: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl MockNode {
+        fn new(responses: Vec<Value>) -> Self {
+            Self {
+                responses: Arc::new(Mutex::new(responses.into())),
+                requests: Default::default(),
+            }
+        }
+
+        fn client(&self) -> BitcoinClient<Self> {
+            BitcoinClient::from_service(
+                self.clone(),
+                "http://127.0.0.1:8332".to_string(),
+                "user".to_string(),
+                "password".to_string(),
+            )
+        }
+
+        /// The method and parameters of the recorded requests.
+        fn calls(&self) -> Vec<(String, Value)> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|request| {
+                    let method = request["method"].as_str().unwrap().to_string();
+                    (method, request["params"].clone())
+                })
+                .collect()
+        }
+    }
+
+    impl Service<HttpRequest<Body>> for MockNode {
+        type Response = HttpResponse<Body>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<HttpResponse<Body>, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: HttpRequest<Body>) -> Self::Future {
+            let mut responses = self.responses.lock().unwrap();
+            let mut response = if responses.len() > 1 {
+                responses.pop_front().unwrap()
+            } else {
+                responses[0].clone()
+            };
+            let requests = self.requests.clone();
+            Box::pin(async move {
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                response["jsonrpc"] = json!("2.0");
+                response["id"] = request["id"].clone();
+                requests.lock().unwrap().push(request);
+                Ok(HttpResponse::new(Body::from(response.to_string())))
+            })
+        }
+    }
+
+    #[test]
+    fn import_address() {
+        let node = MockNode::new(vec![ok(Value::Null)]);
+        block_on(node.client().import_address("bchreg:qq", false)).unwrap();
+        assert_eq!(
+            node.calls(),
+            vec![("importaddress".to_string(), json!(["bchreg:qq", "", false]))]
+        );
+    }
+
+    #[test]
+    fn rescan_blockchain() {
+        let node = MockNode::new(vec![ok(json!({ "start_height": 10, "stop_height": 20 }))]);
+        let result = block_on(node.client().rescan_blockchain(10)).unwrap();
+        assert_eq!((result.start_height, result.stop_height), (10, 20));
+        assert_eq!(
+            node.calls(),
+            vec![("rescanblockchain".to_string(), json!([10]))]
+        );
+    }
+
+    #[test]
+    fn rpc_error() {
+        let node = MockNode::new(vec![json!({
+            "result": null,
+            "error": { "code": -5, "message": "Invalid address" }
+        })]);
+        let err = block_on(node.client().import_address("invalid", true)).unwrap_err();
+        assert!(matches!(&err, NodeError::Rpc(err) if err.code == -5));
+        assert_eq!(err.code(), 3002);
+    }
+
+    /// A verbose `getblockheader` result with the given number of confirmations.
+    fn block_header(confirmations: i64) -> Value {
+        json!({
+            "hash": "0b",
+            "confirmations": confirmations,
+            "height": 100,
+            "version": 536870912,
+            "merkleroot": "0c",
+            "time": 1600000000,
+            "mediantime": 1599999000,
+            "nonce": 1,
+            "bits": "1d00ffff",
+            "difficulty": 1,
+            "chainwork": "0d",
+            "previousblockhash": "0a"
+        })
+    }
+
+    #[test]
+    fn block_header_verbose() {
+        let node = MockNode::new(vec![ok(block_header(3))]);
+        let header = block_on(node.client().get_block_header_verbose(&[0x0b])).unwrap();
+        assert_eq!(header.hash, "0b");
+        assert_eq!(header.confirmations, 3);
+        assert_eq!(header.height, 100);
+        assert_eq!(header.time, 1600000000);
+        assert_eq!(header.mediantime, 1599999000);
+        assert_eq!(header.previousblockhash.as_deref(), Some("0a"));
+        assert_eq!(header.nextblockhash, None);
+        assert_eq!(
+            node.calls(),
+            vec![("getblockheader".to_string(), json!(["0b", true]))]
+        );
+    }
+
+    #[test]
+    fn confirmations() {
+        // Transactions in the mempool are not contained in a block
+        let node = MockNode::new(vec![ok(json!({ "hex": "00", "txid": "0f" }))]);
+        assert_eq!(block_on(node.client().confirmations(&[0x0f])).unwrap(), 0);
+        assert_eq!(
+            node.calls(),
+            vec![("getrawtransaction".to_string(), json!(["0f", true]))]
+        );
+
+        // Blocks which have left the main chain have -1 confirmations
+        let transaction = json!({
+            "hex": "00",
+            "txid": "0f",
+            "confirmations": 0,
+            "blockhash": "0b",
+            "blocktime": 1600000000
+        });
+        let node = MockNode::new(vec![ok(transaction.clone()), ok(block_header(-1))]);
+        assert_eq!(block_on(node.client().confirmations(&[0x0f])).unwrap(), 0);
+
+        let node = MockNode::new(vec![ok(transaction), ok(block_header(6))]);
+        assert_eq!(block_on(node.client().confirmations(&[0x0f])).unwrap(), 6);
+        assert_eq!(
+            node.calls(),
+            vec![
+                ("getrawtransaction".to_string(), json!(["0f", true])),
+                ("getblockheader".to_string(), json!(["0b", true])),
+            ]
+        );
+    }
+
+    #[test]
+    fn watch_mempool() {
+        let node = MockNode::new(vec![
+            ok(json!(["a"])),
+            ok(json!(["a", "b"])),
+            ok(json!([])),
+            ok(json!(["b", "c"])),
+        ]);
+        let stream = node.client().watch_mempool(Duration::from_millis(1));
+        let tx_ids: Vec<String> = block_on(stream.take(2).map(Result::unwrap).collect());
+        assert_eq!(tx_ids, vec!["b".to_string(), "c".to_string()]);
+    }
+
+    #[test]
+    fn watch_mempool_capacity() {
+        let node = MockNode::new(vec![
+            ok(json!([])),
+            ok(json!(["a"])),
+            ok(json!(["b"])),
+            ok(json!(["a"])),
+        ]);
+        let stream = node
+            .client()
+            .watch_mempool_with_capacity(Duration::from_millis(1), 1);
+        let tx_ids: Vec<String> = block_on(stream.take(3).map(Result::unwrap).collect());
+        assert_eq!(
+            tx_ids,
+            vec!["a".to_string(), "b".to_string(), "a".to_string()]
+        );
+    }
+
+    #[test]
+    fn call_outcomes() {
+        type Result = std::result::Result<(), NodeError<Infallible>>;
+        assert_eq!(call_outcome(&Result::Ok(())), "ok");
+        assert_eq!(
+            call_outcome(&Result::Err(NodeError::EmptyResponse)),
+            "node.empty_response"
+        );
+
+        let node = MockNode::new(vec![json!({
+            "result": null,
+            "error": { "code": -5, "message": "Invalid address" }
+        })]);
+        let result = block_on(node.client().call("importaddress", vec![]));
+        assert_eq!(call_outcome(&result), "node.rpc");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_without_recorder() {
+        let node = MockNode::new(vec![
+            ok(json!("bchreg:qq")),
+            json!({ "result": null, "error": { "code": -1, "message": "failure" } }),
+        ]);
+        let client = node.client();
+        block_on(client.get_new_addr()).unwrap();
+        block_on(client.get_new_addr()).unwrap_err();
+    }
 }
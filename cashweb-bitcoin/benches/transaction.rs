@@ -1,6 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-use cashweb_bitcoin::{transaction::Transaction, Decodable, Encodable};
+use cashweb_bitcoin::{
+    transaction::{compute_txids, Transaction},
+    Decodable, Encodable,
+};
 
 fn decode(mut raw_tx: &[u8]) -> Transaction {
     Transaction::decode(&mut raw_tx).unwrap()
@@ -20,6 +23,10 @@ fn transaction_encoding_benchmark(c: &mut Criterion) {
     });
     let tx = decode(&raw_tx);
     c.bench_function("transaction encode", |b| b.iter(|| encode(black_box(&tx))));
+    let transactions = vec![tx; 1_000];
+    c.bench_function("transaction ids", |b| {
+        b.iter(|| compute_txids(black_box(&transactions)))
+    });
 }
 
 criterion_group!(benches, transaction_encoding_benchmark);
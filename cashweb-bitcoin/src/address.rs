@@ -0,0 +1,479 @@
+//! This module contains the [`Address`] struct which represents a pay-to-public-key-hash or
+//! pay-to-script-hash address, encoded as either a [`CashAddr`] or a legacy Base58Check address.
+//!
+//! CashAddr addresses are prefixed by their [`Network`], `bitcoincash`, `bchtest` or `bchreg`. The
+//! prefix may be omitted when decoding, in which case the network is that whose prefix the checksum
+//! verifies against. Legacy addresses share their version bytes between the test and regression
+//! test networks, hence they decode as [`Network::Testnet`].
+//!
+//! [`CashAddr`]: https://github.com/bitcoincashorg/bitcoincash.org/blob/master/spec/cashaddr.md
+
+use std::{convert::TryInto, fmt, str::FromStr};
+
+use secp256k1::PublicKey;
+use thiserror::Error;
+
+use crate::{double_sha256, signing::public_key_hash, transaction::script::Script, Network};
+
+/// The CashAddr character set, indexed by 5-bit value.
+const CASHADDR_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The number of 5-bit groups in a CashAddr checksum.
+const CASHADDR_CHECKSUM_LEN: usize = 8;
+
+/// The Base58 alphabet, indexed by digit.
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// The length of a Base58Check payload, the version byte and hash.
+const BASE58_PAYLOAD_LEN: usize = 21;
+
+/// Error associated with decoding an [`Address`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum AddressError {
+    /// The CashAddr contains a character outside of its character set.
+    #[error("invalid cashaddr character: {0:?}")]
+    CashAddrChar(char),
+    /// The CashAddr contains both upper and lower case characters.
+    #[error("mixed case cashaddr")]
+    MixedCase,
+    /// The CashAddr prefix does not belong to a known network.
+    #[error("unknown cashaddr prefix: {0}")]
+    UnknownPrefix(String),
+    /// The CashAddr checksum is invalid.
+    #[error("invalid cashaddr checksum")]
+    CashAddrChecksum,
+    /// The CashAddr payload has non-zero padding or an unexpected length.
+    #[error("invalid cashaddr payload")]
+    CashAddrPayload,
+    /// The CashAddr version byte is unsupported.
+    #[error("unsupported cashaddr version: {0}")]
+    CashAddrVersion(u8),
+    /// The Base58 string contains a character outside of its alphabet.
+    #[error("invalid base58 character: {0:?}")]
+    Base58Char(char),
+    /// The Base58Check checksum is invalid.
+    #[error("invalid base58 checksum")]
+    Base58Checksum,
+    /// The Base58Check payload is an unexpected length.
+    #[error("unexpected base58 payload length: {0}")]
+    Base58Length(usize),
+    /// The Base58Check version byte is unsupported.
+    #[error("unsupported base58 version: {0}")]
+    Base58Version(u8),
+}
+
+/// The type of an [`Address`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressType {
+    /// Pay-to-public-key-hash.
+    PubkeyHash,
+    /// Pay-to-script-hash.
+    ScriptHash,
+}
+
+/// Represents an address.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Address {
+    /// The network the address belongs to.
+    pub network: Network,
+    /// The type of the address.
+    pub address_type: AddressType,
+    /// The HASH160 of the public key or script.
+    pub hash: [u8; 20],
+}
+
+impl Address {
+    /// Create a new [`Address`].
+    pub fn new(network: Network, address_type: AddressType, hash: [u8; 20]) -> Self {
+        Self {
+            network,
+            address_type,
+            hash,
+        }
+    }
+
+    /// Create the pay-to-public-key-hash [`Address`] of a public key.
+    pub fn from_public_key(network: Network, public_key: &PublicKey) -> Self {
+        Self::new(
+            network,
+            AddressType::PubkeyHash,
+            public_key_hash(public_key),
+        )
+    }
+
+    /// The output script paying to the address.
+    pub fn script(&self) -> Script {
+        match self.address_type {
+            AddressType::PubkeyHash => Script::p2pkh(&self.hash),
+            AddressType::ScriptHash => Script::p2sh(&self.hash),
+        }
+    }
+
+    /// Encode the address as a CashAddr, including the prefix.
+    pub fn to_cashaddr(&self) -> String {
+        let prefix = cashaddr_prefix(self.network);
+        let type_bits = match self.address_type {
+            AddressType::PubkeyHash => 0,
+            AddressType::ScriptHash => 1,
+        };
+
+        // The version byte encodes the type and a 160-bit hash size
+        let mut raw_payload = Vec::with_capacity(21);
+        raw_payload.push(type_bits << 3);
+        raw_payload.extend_from_slice(&self.hash);
+        let payload = convert_bits(&raw_payload, 8, 5, true).unwrap(); // This is safe
+        let checksum = cashaddr_checksum(prefix, &payload);
+
+        let mut address = String::with_capacity(prefix.len() + 1 + payload.len() + 8);
+        address.push_str(prefix);
+        address.push(':');
+        address.extend(
+            payload
+                .iter()
+                .chain(checksum.iter())
+                .map(|value| CASHADDR_CHARSET[*value as usize] as char),
+        );
+        address
+    }
+
+    /// Decode a CashAddr, with or without its prefix.
+    pub fn from_cashaddr(address: &str) -> Result<Self, AddressError> {
+        // Check case
+        let has_lower = address.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = address.chars().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return Err(AddressError::MixedCase);
+        }
+        let address = address.to_ascii_lowercase();
+
+        // Split prefix
+        let (prefix, encoded) = match address.rfind(':') {
+            Some(index) => (Some(&address[..index]), &address[index + 1..]),
+            None => (None, &address[..]),
+        };
+        let values = encoded
+            .chars()
+            .map(|c| {
+                CASHADDR_CHARSET
+                    .iter()
+                    .position(|charset_c| *charset_c as char == c)
+                    .map(|value| value as u8)
+                    .ok_or(AddressError::CashAddrChar(c))
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if values.len() <= CASHADDR_CHECKSUM_LEN {
+            return Err(AddressError::CashAddrPayload);
+        }
+
+        // Verify checksum, inferring the network when the prefix is omitted
+        let network = match prefix {
+            Some(prefix) => {
+                let network = cashaddr_network(prefix)
+                    .ok_or_else(|| AddressError::UnknownPrefix(prefix.to_string()))?;
+                if cashaddr_polymod(prefix, &values) != 0 {
+                    return Err(AddressError::CashAddrChecksum);
+                }
+                network
+            }
+            None => [Network::Mainnet, Network::Testnet, Network::Regtest]
+                .iter()
+                .copied()
+                .find(|network| cashaddr_polymod(cashaddr_prefix(*network), &values) == 0)
+                .ok_or(AddressError::CashAddrChecksum)?,
+        };
+
+        // Decode payload
+        let payload = &values[..values.len() - CASHADDR_CHECKSUM_LEN];
+        let raw_payload =
+            convert_bits(payload, 5, 8, false).ok_or(AddressError::CashAddrPayload)?;
+        let (version, hash) = raw_payload
+            .split_first()
+            .ok_or(AddressError::CashAddrPayload)?;
+        let address_type = match version {
+            0x00 => AddressType::PubkeyHash,
+            0x08 => AddressType::ScriptHash,
+            _ => return Err(AddressError::CashAddrVersion(*version)),
+        };
+        let hash = hash.try_into().map_err(|_| AddressError::CashAddrPayload)?;
+        Ok(Self::new(network, address_type, hash))
+    }
+
+    /// Encode the address as a legacy Base58Check address.
+    pub fn to_base58(&self) -> String {
+        let mut raw = Vec::with_capacity(BASE58_PAYLOAD_LEN + 4);
+        raw.push(base58_version(self.network, self.address_type));
+        raw.extend_from_slice(&self.hash);
+        let checksum = double_sha256(&raw);
+        raw.extend_from_slice(&checksum[..4]);
+        base58_encode(&raw)
+    }
+
+    /// Decode a legacy Base58Check address.
+    pub fn from_base58(address: &str) -> Result<Self, AddressError> {
+        let raw = base58_decode(address)?;
+        if raw.len() != BASE58_PAYLOAD_LEN + 4 {
+            return Err(AddressError::Base58Length(raw.len().saturating_sub(4)));
+        }
+
+        // Verify checksum
+        let (payload, checksum) = raw.split_at(BASE58_PAYLOAD_LEN);
+        if double_sha256(payload)[..4] != checksum[..] {
+            return Err(AddressError::Base58Checksum);
+        }
+
+        let (network, address_type) = match payload[0] {
+            0x00 => (Network::Mainnet, AddressType::PubkeyHash),
+            0x05 => (Network::Mainnet, AddressType::ScriptHash),
+            0x6f => (Network::Testnet, AddressType::PubkeyHash),
+            0xc4 => (Network::Testnet, AddressType::ScriptHash),
+            version => return Err(AddressError::Base58Version(version)),
+        };
+        let hash = payload[1..].try_into().unwrap(); // This is safe
+        Ok(Self::new(network, address_type, hash))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_cashaddr())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    /// Decode a CashAddr or, failing that and absent a prefix, a legacy Base58Check address.
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        match Self::from_cashaddr(address) {
+            Ok(address) => Ok(address),
+            Err(err) if address.contains(':') => Err(err),
+            Err(_) => Self::from_base58(address),
+        }
+    }
+}
+
+/// The CashAddr prefix of a network.
+fn cashaddr_prefix(network: Network) -> &'static str {
+    match network {
+        Network::Mainnet => "bitcoincash",
+        Network::Testnet => "bchtest",
+        Network::Regtest => "bchreg",
+    }
+}
+
+/// The network of a CashAddr prefix.
+fn cashaddr_network(prefix: &str) -> Option<Network> {
+    match prefix {
+        "bitcoincash" => Some(Network::Mainnet),
+        "bchtest" => Some(Network::Testnet),
+        "bchreg" => Some(Network::Regtest),
+        _ => None,
+    }
+}
+
+/// Calculate the CashAddr checksum polynomial over the prefix and 5-bit values.
+fn cashaddr_polymod(prefix: &str, values: &[u8]) -> u64 {
+    let prefix_values = prefix.bytes().map(|byte| byte & 0x1f);
+    let mut c: u64 = 1;
+    for value in prefix_values
+        .chain(std::iter::once(0))
+        .chain(values.iter().copied())
+    {
+        let c0 = (c >> 35) as u8;
+        c = ((c & 0x07_ffff_ffff) << 5) ^ u64::from(value);
+        if c0 & 0x01 != 0 {
+            c ^= 0x98_f2bc_8e61;
+        }
+        if c0 & 0x02 != 0 {
+            c ^= 0x79_b76d_99e2;
+        }
+        if c0 & 0x04 != 0 {
+            c ^= 0xf3_3e5f_b3c4;
+        }
+        if c0 & 0x08 != 0 {
+            c ^= 0xae_2eab_e2a8;
+        }
+        if c0 & 0x10 != 0 {
+            c ^= 0x1e_4f43_e470;
+        }
+    }
+    c ^ 1
+}
+
+/// Calculate the CashAddr checksum of the 5-bit payload.
+fn cashaddr_checksum(prefix: &str, payload: &[u8]) -> [u8; CASHADDR_CHECKSUM_LEN] {
+    let mut values = Vec::with_capacity(payload.len() + CASHADDR_CHECKSUM_LEN);
+    values.extend_from_slice(payload);
+    values.extend_from_slice(&[0; CASHADDR_CHECKSUM_LEN]);
+    let polymod = cashaddr_polymod(prefix, &values);
+
+    let mut checksum = [0; CASHADDR_CHECKSUM_LEN];
+    for (index, value) in checksum.iter_mut().enumerate() {
+        *value = ((polymod >> (5 * (CASHADDR_CHECKSUM_LEN - 1 - index))) & 0x1f) as u8;
+    }
+    checksum
+}
+
+/// Regroup the data from `from` bit groups to `to` bit groups.
+///
+/// When not padding, incomplete or non-zero trailing bits are rejected.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max_value = (1 << to) - 1;
+    let mut output = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            output.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            output.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max_value) != 0 {
+        return None;
+    }
+    Some(output)
+}
+
+/// The Base58Check version byte of a network and address type.
+fn base58_version(network: Network, address_type: AddressType) -> u8 {
+    match (network, address_type) {
+        (Network::Mainnet, AddressType::PubkeyHash) => 0x00,
+        (Network::Mainnet, AddressType::ScriptHash) => 0x05,
+        (_, AddressType::PubkeyHash) => 0x6f,
+        (_, AddressType::ScriptHash) => 0xc4,
+    }
+}
+
+/// Encode the data in Base58, preserving leading zero bytes as leading `1`s.
+fn base58_encode(data: &[u8]) -> String {
+    let n_zeros = data.iter().take_while(|byte| **byte == 0).count();
+
+    // Repeatedly divide the big-endian number by 58, accumulating little-endian digits
+    let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+    for byte in &data[n_zeros..] {
+        let mut carry = u32::from(*byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    std::iter::repeat(BASE58_ALPHABET[0] as char)
+        .take(n_zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|digit| BASE58_ALPHABET[*digit as usize] as char),
+        )
+        .collect()
+}
+
+/// Decode Base58, preserving leading `1`s as leading zero bytes.
+fn base58_decode(encoded: &str) -> Result<Vec<u8>, AddressError> {
+    let n_zeros = encoded
+        .bytes()
+        .take_while(|c| *c == BASE58_ALPHABET[0])
+        .count();
+
+    // Repeatedly multiply the number by 58, accumulating little-endian bytes
+    let mut bytes: Vec<u8> = Vec::with_capacity(encoded.len() * 733 / 1000 + 1);
+    for c in encoded.chars().skip(n_zeros) {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|alphabet_c| *alphabet_c as char == c)
+            .ok_or(AddressError::Base58Char(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0; n_zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(hex_str: &str) -> [u8; 20] {
+        hex::decode(hex_str).unwrap().as_slice().try_into().unwrap()
+    }
+
+    #[test]
+    fn spec_vectors() {
+        let vectors = [
+            (
+                "1BpEi6DfDAUFd7GtittLSdBeYJvcoaVggu",
+                "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a",
+                AddressType::PubkeyHash,
+                "76a04053bda0a88bda5177b86a15c3b29f559873",
+            ),
+            (
+                "1KXrWXciRDZUpQwQmuM1DbwsKDLYAYsVLR",
+                "bitcoincash:qr95sy3j9xwd2ap32xkykttr4cvcu7as4y0qverfuy",
+                AddressType::PubkeyHash,
+                "cb481232299cd5743151ac4b2d63ae198e7bb0a9",
+            ),
+            (
+                "3CWFddi6m4ndiGyKqzYvsFYagqDLPVMTzC",
+                "bitcoincash:ppm2qsznhks23z7629mms6s4cwef74vcwvn0h829pq",
+                AddressType::ScriptHash,
+                "76a04053bda0a88bda5177b86a15c3b29f559873",
+            ),
+        ];
+        for (legacy, cashaddr, address_type, hex_hash) in vectors.iter() {
+            let address = Address::new(Network::Mainnet, *address_type, hash(hex_hash));
+            assert_eq!(address.to_base58(), *legacy);
+            assert_eq!(address.to_cashaddr(), *cashaddr);
+            assert_eq!(Address::from_base58(legacy).unwrap(), address);
+            assert_eq!(Address::from_cashaddr(cashaddr).unwrap(), address);
+            assert_eq!(legacy.parse::<Address>().unwrap(), address);
+            assert_eq!(cashaddr.to_uppercase().parse::<Address>().unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn networks() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Regtest].iter() {
+            let address = Address::new(*network, AddressType::PubkeyHash, [7; 20]);
+            let cashaddr = address.to_cashaddr();
+            assert!(cashaddr.starts_with(cashaddr_prefix(*network)));
+
+            // The network is inferred from the checksum
+            let (_, encoded) = cashaddr.split_at(cashaddr.find(':').unwrap() + 1);
+            assert_eq!(Address::from_cashaddr(encoded).unwrap(), address);
+        }
+
+        let address = Address::new(Network::Mainnet, AddressType::PubkeyHash, [7; 20]);
+        let mut cashaddr = address.to_cashaddr();
+        let last = cashaddr.pop().unwrap();
+        cashaddr.push(if last == 'q' { 'p' } else { 'q' });
+        assert_eq!(
+            Address::from_cashaddr(&cashaddr),
+            Err(AddressError::CashAddrChecksum)
+        );
+        assert_eq!(
+            Address::from_cashaddr("bitcoincash:Qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a"),
+            Err(AddressError::MixedCase)
+        );
+    }
+}
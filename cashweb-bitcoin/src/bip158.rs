@@ -0,0 +1,375 @@
+//! This module contains the [`BlockFilter`] struct which allows construction and matching of
+//! [`Compact Block Filters`].
+//!
+//! Filters are Golomb-coded sets keyed by the block hash. Matching a set of scripts against a
+//! filter allows blocks to be scanned for relevant payments without maintaining a full index.
+//!
+//! Block hashes are given in little-endian format, the order in which they're serialized.
+//!
+//! [`Compact Block Filters`]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+
+use std::{collections::HashSet, convert::TryInto};
+
+use thiserror::Error;
+
+use crate::{
+    double_sha256,
+    transaction::{Script, Transaction},
+    var_int::{DecodeError as VarIntDecodeError, VarInt},
+    Decodable, Encodable,
+};
+
+/// The Golomb-Rice coding parameter of the basic filter.
+pub const BASIC_FILTER_P: u8 = 19;
+
+/// The inverse false positive rate of the basic filter.
+pub const BASIC_FILTER_M: u64 = 784_931;
+
+/// Error associated with decoding a [`BlockFilter`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum FilterError {
+    /// Failed to decode the element count [`VarInt`].
+    #[error("element count: {0}")]
+    ElementCount(VarIntDecodeError),
+    /// The filter ended before all elements were decoded.
+    #[error("filter too short")]
+    TooShort,
+}
+
+/// Calculate the SipHash-2-4 of the data.
+fn siphash(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    #[inline]
+    fn sip_round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    // Compress message blocks
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap()); // This is safe
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    // Compress final block, including the length
+    let mut last = (data.len() as u64 & 0xff) << 56;
+    for (index, byte) in tail.iter().enumerate() {
+        last |= (*byte as u64) << (8 * index);
+    }
+    v[3] ^= last;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= last;
+
+    // Finalize
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Hash the elements into the range `[0, n * m)` and sort them.
+fn hashed_set<'a, I>(block_hash: &[u8; 32], n: u64, elements: I) -> Vec<u64>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let k0 = u64::from_le_bytes(block_hash[..8].try_into().unwrap()); // This is safe
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().unwrap()); // This is safe
+    let range = n as u128 * BASIC_FILTER_M as u128;
+    let mut hashes: Vec<u64> = elements
+        .into_iter()
+        .map(|element| ((siphash(k0, k1, element) as u128 * range) >> 64) as u64)
+        .collect();
+    hashes.sort_unstable();
+    hashes
+}
+
+/// Writes bits, most significant first.
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    offset: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.offset == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.offset; // This is safe
+        }
+        self.offset = (self.offset + 1) % 8;
+    }
+
+    fn write_bits(&mut self, value: u64, n_bits: u8) {
+        for index in (0..n_bits).rev() {
+            self.write_bit((value >> index) & 1 == 1);
+        }
+    }
+
+    fn write_golomb(&mut self, value: u64) {
+        let quotient = value >> BASIC_FILTER_P;
+        for _ in 0..quotient {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        self.write_bits(value, BASIC_FILTER_P);
+    }
+}
+
+/// Reads bits, most significant first.
+#[derive(Debug)]
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, n_bits: u8) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..n_bits {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    fn read_golomb(&mut self) -> Option<u64> {
+        let mut quotient = 0;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let remainder = self.read_bits(BASIC_FILTER_P)?;
+        Some((quotient << BASIC_FILTER_P) + remainder)
+    }
+}
+
+/// Represents a serialized basic block filter, the element count followed by the Golomb-coded set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// The serialized filter.
+    pub content: Vec<u8>,
+}
+
+impl From<Vec<u8>> for BlockFilter {
+    fn from(content: Vec<u8>) -> Self {
+        BlockFilter { content }
+    }
+}
+
+impl BlockFilter {
+    /// Construct a filter from a set of elements. Duplicate elements are included once.
+    pub fn build<'a, I>(block_hash: &[u8; 32], elements: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let elements: HashSet<&[u8]> = elements.into_iter().collect();
+        let n = elements.len() as u64;
+
+        let mut content = Vec::new();
+        VarInt(n).encode_raw(&mut content);
+
+        let mut writer = BitWriter::default();
+        let mut last_value = 0;
+        for value in hashed_set(block_hash, n, elements) {
+            writer.write_golomb(value - last_value);
+            last_value = value;
+        }
+        content.extend_from_slice(&writer.bytes);
+
+        BlockFilter { content }
+    }
+
+    /// Construct the basic filter of a block from its transactions and the scripts of the
+    /// outputs they spend.
+    ///
+    /// Empty and OP_RETURN output scripts are excluded.
+    pub fn from_transactions<'a, S>(
+        block_hash: &[u8; 32],
+        transactions: &'a [Transaction],
+        spent_scripts: S,
+    ) -> Self
+    where
+        S: IntoIterator<Item = &'a Script>,
+    {
+        let output_scripts = transactions
+            .iter()
+            .flat_map(|transaction| transaction.outputs.iter())
+            .map(|output| &output.script)
+            .filter(|script| !script.is_empty() && !script.is_op_return());
+        let spent_scripts = spent_scripts
+            .into_iter()
+            .filter(|script| !script.is_empty());
+        let elements = output_scripts
+            .chain(spent_scripts)
+            .map(|script| script.as_bytes());
+        Self::build(block_hash, elements)
+    }
+
+    /// Decode the number of elements in the filter.
+    pub fn element_count(&self) -> Result<u64, FilterError> {
+        let n = VarInt::decode(&mut self.content.as_slice()).map_err(FilterError::ElementCount)?;
+        Ok(n.into())
+    }
+
+    /// Check whether any of the queries match the filter.
+    ///
+    /// False positives occur at a rate of `1 / BASIC_FILTER_M`.
+    pub fn match_any<'a, I>(&self, block_hash: &[u8; 32], queries: I) -> Result<bool, FilterError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        // Decode element count
+        let mut buf = self.content.as_slice();
+        let n: u64 = VarInt::decode(&mut buf)
+            .map_err(FilterError::ElementCount)?
+            .into();
+        if n == 0 {
+            return Ok(false);
+        }
+
+        let queries = hashed_set(block_hash, n, queries);
+        let mut queries = queries.iter().peekable();
+        let mut reader = BitReader {
+            bytes: buf,
+            position: 0,
+        };
+        let mut value = 0;
+        for _ in 0..n {
+            value += reader.read_golomb().ok_or(FilterError::TooShort)?;
+
+            // Skip queries below the current value
+            while let Some(query) = queries.peek() {
+                if **query < value {
+                    queries.next();
+                } else if **query == value {
+                    return Ok(true);
+                } else {
+                    break;
+                }
+            }
+            if queries.peek().is_none() {
+                return Ok(false);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Check whether any of the scripts match the filter.
+    pub fn match_scripts(
+        &self,
+        block_hash: &[u8; 32],
+        scripts: &[Script],
+    ) -> Result<bool, FilterError> {
+        self.match_any(block_hash, scripts.iter().map(|script| script.as_bytes()))
+    }
+
+    /// Calculate the filter hash in little-endian format. This is the double SHA256 digest of the
+    /// serialized filter.
+    pub fn filter_hash(&self) -> [u8; 32] {
+        double_sha256(&self.content)
+    }
+
+    /// Calculate the filter header in little-endian format, from the header of the previous
+    /// block's filter.
+    pub fn filter_header(&self, previous_header: &[u8; 32]) -> [u8; 32] {
+        let mut preimage = [0; 64];
+        preimage[..32].copy_from_slice(&self.filter_hash());
+        preimage[32..].copy_from_slice(previous_header);
+        double_sha256(&preimage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_hash_le(block_hash: &str) -> [u8; 32] {
+        let mut block_hash_le = [0; 32];
+        block_hash_le.copy_from_slice(&hex::decode(block_hash).unwrap());
+        block_hash_le.reverse();
+        block_hash_le
+    }
+
+    #[test]
+    fn siphash_reference() {
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash(k0, k1, &data), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn testnet_genesis() {
+        // Test vector from BIP158
+        let block_hash =
+            block_hash_le("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943");
+        let script = Script::from_hex_str("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac").unwrap();
+        let filter = BlockFilter::build(&block_hash, vec![script.as_bytes()]);
+        assert_eq!(filter.content, hex::decode("019dfca8").unwrap());
+
+        let mut header = filter.filter_header(&[0; 32]);
+        header.reverse();
+        assert_eq!(
+            hex::encode(header),
+            "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750"
+        );
+
+        assert!(filter
+            .match_scripts(&block_hash, &[Script(vec![1, 2, 3]), script])
+            .unwrap());
+    }
+
+    #[test]
+    fn match_elements() {
+        let block_hash = [7; 32];
+        let elements: Vec<Vec<u8>> = (0..100u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let filter = BlockFilter::build(&block_hash, elements.iter().map(Vec::as_slice));
+        assert_eq!(filter.element_count().unwrap(), 100);
+
+        for element in &elements {
+            assert!(filter
+                .match_any(&block_hash, vec![element.as_slice()])
+                .unwrap());
+        }
+        let absent = b"absent".to_vec();
+        assert!(!filter
+            .match_any(&block_hash, vec![absent.as_slice()])
+            .unwrap());
+    }
+
+    #[test]
+    fn empty_filter() {
+        let filter = BlockFilter::build(&[0; 32], vec![]);
+        assert_eq!(filter.content, vec![0]);
+        assert!(!filter.match_any(&[0; 32], vec![&b"query"[..]]).unwrap());
+    }
+}
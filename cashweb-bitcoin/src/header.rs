@@ -0,0 +1,248 @@
+//! This module contains the [`BlockHeader`] struct which represents a Bitcoin block header.
+//! It enjoys [`Encodable`] and [`Decodable`].
+//!
+//! Targets and block hashes are given in little-endian format, the order in which they're
+//! serialized.
+
+use bytes::{Buf, BufMut};
+use thiserror::Error;
+
+use crate::{double_sha256, Decodable, Encodable};
+
+/// The length of an encoded [`BlockHeader`].
+pub const HEADER_LEN: usize = 80;
+
+/// Represents a block header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub previous_block_hash: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl Encodable for BlockHeader {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        HEADER_LEN
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u32_le(self.version);
+        buf.put(&self.previous_block_hash[..]);
+        buf.put(&self.merkle_root[..]);
+        buf.put_u32_le(self.time);
+        buf.put_u32_le(self.bits);
+        buf.put_u32_le(self.nonce);
+    }
+}
+
+/// Error associated with [`BlockHeader`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("block header too short")]
+pub struct DecodeError;
+
+impl Decodable for BlockHeader {
+    type Error = DecodeError;
+
+    #[inline]
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        if buf.remaining() < HEADER_LEN {
+            return Err(DecodeError);
+        }
+        let version = buf.get_u32_le();
+        let mut previous_block_hash = [0; 32];
+        buf.copy_to_slice(&mut previous_block_hash);
+        let mut merkle_root = [0; 32];
+        buf.copy_to_slice(&mut merkle_root);
+        let time = buf.get_u32_le();
+        let bits = buf.get_u32_le();
+        let nonce = buf.get_u32_le();
+
+        Ok(BlockHeader {
+            version,
+            previous_block_hash,
+            merkle_root,
+            time,
+            bits,
+            nonce,
+        })
+    }
+}
+
+/// Error associated with expanding a compact target.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum TargetError {
+    /// The compact target had its sign bit set.
+    #[error("negative target")]
+    Negative,
+    /// The compact target exceeded 256 bits.
+    #[error("target overflow")]
+    Overflow,
+}
+
+/// Expand a compact target, as found in the `bits` field, to its 256-bit little-endian form.
+pub fn compact_to_target(bits: u32) -> Result<[u8; 32], TargetError> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+
+    if mantissa != 0 && bits & 0x0080_0000 != 0 {
+        return Err(TargetError::Negative);
+    }
+    if mantissa != 0
+        && (exponent > 34
+            || (mantissa > 0xff && exponent > 33)
+            || (mantissa > 0xffff && exponent > 32))
+    {
+        return Err(TargetError::Overflow);
+    }
+
+    let mut target = [0; 32];
+    if exponent <= 3 {
+        let value = mantissa >> (8 * (3 - exponent));
+        target[..4].copy_from_slice(&value.to_le_bytes());
+    } else {
+        let offset = exponent - 3;
+        for (i, byte) in mantissa.to_le_bytes()[..3].iter().enumerate() {
+            if let Some(target_byte) = target.get_mut(offset + i) {
+                *target_byte = *byte;
+            }
+        }
+    }
+    Ok(target)
+}
+
+/// Calculate the expected number of hashes required to meet the little-endian target, that is
+/// `2^256 / (target + 1)`.
+///
+/// The division uses the leading 64 bits of the target and saturates at [`u128::MAX`], which is
+/// sufficient for comparing chains.
+pub fn target_work(target: &[u8; 32]) -> u128 {
+    let top = match target.iter().rposition(|byte| *byte != 0) {
+        Some(some) => some,
+        None => return u128::MAX,
+    };
+    let low = top.saturating_sub(7);
+    let mantissa = target[low..=top]
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | u128::from(*byte))
+        + 1;
+
+    // Work is approximately 2^exponent / mantissa
+    let exponent = 256 - 8 * low as u32;
+    if exponent < 128 {
+        return (1 << exponent) / mantissa;
+    }
+    let work = (1u128 << 127) / mantissa;
+    let shift = exponent - 127;
+    if work.leading_zeros() >= shift {
+        work << shift
+    } else {
+        u128::MAX
+    }
+}
+
+/// Check whether a little-endian hash is less than or equal to a little-endian target.
+pub(crate) fn meets_target(hash: &[u8; 32], target: &[u8; 32]) -> bool {
+    hash.iter().rev().le(target.iter().rev())
+}
+
+impl BlockHeader {
+    /// Calculate the block hash in little-endian format. This is the double SHA256 digest of the
+    /// raw header.
+    ///
+    /// Note that typically the block hash is big-endian encoded.
+    #[inline]
+    pub fn block_hash_le(&self) -> [u8; 32] {
+        let mut raw_header = Vec::with_capacity(HEADER_LEN);
+        self.encode_raw(&mut raw_header);
+        double_sha256(&raw_header)
+    }
+
+    /// Calculate the block hash. This is the double SHA256 digest of the raw header in big-endian
+    /// encoding.
+    #[inline]
+    pub fn block_hash(&self) -> [u8; 32] {
+        let mut block_hash = self.block_hash_le();
+        block_hash.reverse();
+        block_hash
+    }
+
+    /// Expand the compact target of the header.
+    #[inline]
+    pub fn target(&self) -> Result<[u8; 32], TargetError> {
+        compact_to_target(self.bits)
+    }
+
+    /// Calculate the work represented by the header.
+    #[inline]
+    pub fn work(&self) -> Result<u128, TargetError> {
+        self.target().map(|target| target_work(&target))
+    }
+
+    /// Check whether the block hash meets the target of the header.
+    ///
+    /// Note that this does not check that the target itself follows the difficulty adjustment
+    /// rules.
+    #[inline]
+    pub fn check_pow(&self) -> Result<bool, TargetError> {
+        let target = self.target()?;
+        Ok(meets_target(&self.block_hash_le(), &target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    #[test]
+    fn genesis() {
+        let raw_header = hex::decode(GENESIS_HEADER).unwrap();
+        let header = BlockHeader::decode(&mut raw_header.as_slice()).unwrap();
+        assert_eq!(header.bits, 0x1d00_ffff);
+        assert_eq!(hex::encode(header.block_hash()), GENESIS_HASH);
+        assert_eq!(header.check_pow(), Ok(true));
+        assert_eq!(header.work(), Ok(0x1_0001_0001));
+
+        let mut raw_header_new = Vec::with_capacity(header.encoded_len());
+        header.encode(&mut raw_header_new).unwrap();
+        assert_eq!(raw_header, raw_header_new);
+    }
+
+    #[test]
+    fn insufficient_pow() {
+        let raw_header = hex::decode(GENESIS_HEADER).unwrap();
+        let mut header = BlockHeader::decode(&mut raw_header.as_slice()).unwrap();
+        header.nonce += 1;
+        assert_eq!(header.check_pow(), Ok(false));
+    }
+
+    #[test]
+    fn compact_targets() {
+        let mut expected = [0; 32];
+        expected[29] = 0xff;
+        expected[30] = 0xff;
+        expected[31] = 0x7f;
+        assert_eq!(compact_to_target(0x207f_ffff), Ok(expected));
+        assert_eq!(target_work(&expected), 2);
+
+        assert_eq!(compact_to_target(0x0180_0012), Err(TargetError::Negative));
+        assert_eq!(compact_to_target(0x2301_0000), Err(TargetError::Overflow));
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            BlockHeader::decode(&mut &[0; HEADER_LEN - 1][..]),
+            Err(DecodeError)
+        );
+    }
+}
@@ -8,16 +8,31 @@
 //! `cashweb-bitcoin` is a library providing serialization/deserialization of Bitcoin structures,
 //!  utility methods for signing, and methods for [`Hierarchical Deterministic Wallets`] use.
 //!
+//! Transaction inputs spending pay-to-public-key-hash outputs may be signed using the [`signing`]
+//! module.
+//!
+//! Addresses are converted between pubkey and script hashes and their CashAddr or legacy
+//! Base58Check encodings using the [`address`] module.
+//!
 //! [`Hierarchical Deterministic Wallets`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
 
+pub mod address;
+pub mod bip158;
 pub mod bip32;
+pub mod header;
 pub mod prelude;
+pub mod serde_hex;
+pub mod signing;
+pub mod slp;
+pub mod spv;
 pub mod transaction;
 pub mod var_int;
 
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -26,6 +41,25 @@ use thiserror::Error;
 #[error("buffer has insufficient capacity")]
 pub struct InsufficientCapacity;
 
+/// Calculate the double SHA256 digest of the data.
+#[inline]
+pub(crate) fn double_sha256(data: &[u8]) -> [u8; 32] {
+    digest(&SHA256, digest(&SHA256, data).as_ref())
+        .as_ref()
+        .try_into()
+        .unwrap() // This is safe
+}
+
+/// Calculate the HASH160 of the data. This is the RIPEMD-160 digest of the SHA256 digest of the
+/// data.
+#[inline]
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(digest(&SHA256, data).as_ref())
+        .as_slice()
+        .try_into()
+        .unwrap() // This is safe
+}
+
 /// Provides a common interface for the serialization of bitcoin structures.
 pub trait Encodable: Sized {
     /// Returns the encoded length of the message.
@@ -43,6 +77,31 @@ pub trait Encodable: Sized {
 
     /// Encodes structure to a buffer. This panics if buffer contains insufficient capacity.
     fn encode_raw<B: BufMut>(&self, buf: &mut B);
+
+    /// Encodes structure to the end of a [`BytesMut`], reserving the capacity required.
+    ///
+    /// This allows a single buffer to be reused across many encodings.
+    #[inline]
+    fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(self.encoded_len());
+        self.encode_raw(buf);
+    }
+
+    /// Encodes structure to [`Bytes`].
+    #[inline]
+    fn encode_to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.encoded_len());
+        self.encode_raw(&mut buf);
+        buf.freeze()
+    }
+
+    /// Encodes structure to a [`Vec`], for fields requiring owned bytes.
+    #[inline]
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_raw(&mut buf);
+        buf
+    }
 }
 
 /// Provides a common interface for the deserialization of bitcoin structures.
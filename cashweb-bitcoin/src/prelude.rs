@@ -13,12 +13,15 @@
 
 #[doc(inline)]
 pub use crate::{
+    address::{Address, AddressError, AddressType},
+    header::{BlockHeader, DecodeError as BlockHeaderDecodeError},
     transaction::{
         input::{DecodeError as InputDecodeError, Input},
         outpoint::{DecodeError as OutpointDecodeError, Outpoint},
         output::{DecodeError as OutputDecodeError, Output},
         script::Script,
-        DecodeError as TransactionDecodeError, Transaction,
+        witness::{DecodeError as WitnessDecodeError, WitnessTransaction},
+        DecodeError as TransactionDecodeError, Transaction, Txid,
     },
     var_int::{DecodeError as VarIntDecodeError, VarInt},
 };
@@ -0,0 +1,85 @@
+//! This module contains [`serde`] helpers which encode byte-heavy fields as hex strings.
+//!
+//! Each submodule is intended for use with `#[serde(with = "...")]`, for example
+//! `#[serde(with = "cashweb_bitcoin::serde_hex::transaction")]`, allowing configuration files
+//! and JSON RPC results to deserialize directly into `cashweb-bitcoin` types.
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+use crate::transaction::{Script, Transaction};
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    hex::decode(value).map_err(D::Error::custom)
+}
+
+pub mod bytes {
+    //! Encodes a `Vec<u8>` as a hex string.
+
+    use super::*;
+
+    /// Serialize the bytes as a hex string.
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(value))
+    }
+
+    /// Deserialize the bytes from a hex string.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserialize_hex(deserializer)
+    }
+}
+
+pub mod script {
+    //! Encodes a [`Script`] as a hex string.
+
+    use super::*;
+
+    /// Serialize the [`Script`] as a hex string.
+    pub fn serialize<S: Serializer>(value: &Script, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_hex_string())
+    }
+
+    /// Deserialize the [`Script`] from a hex string.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Script, D::Error> {
+        deserialize_hex(deserializer).map(Script)
+    }
+}
+
+pub mod transaction {
+    //! Encodes a [`Transaction`] as a hex string.
+
+    use super::*;
+
+    /// Serialize the [`Transaction`] as a hex string.
+    pub fn serialize<S: Serializer>(value: &Transaction, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_hex_string())
+    }
+
+    /// Deserialize the [`Transaction`] from a hex string.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Transaction, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Transaction::from_hex_str(&value).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::{value::Error as ValueError, IntoDeserializer};
+
+    use super::*;
+
+    #[test]
+    fn deserialize_script() {
+        let deserializer = IntoDeserializer::<'_, ValueError>::into_deserializer("6a0100");
+        let script = script::deserialize(deserializer).unwrap();
+        assert_eq!(script, Script(vec![0x6a, 0x01, 0x00]));
+    }
+
+    #[test]
+    fn deserialize_invalid_hex() {
+        let deserializer = IntoDeserializer::<'_, ValueError>::into_deserializer("6a0");
+        assert!(bytes::deserialize(deserializer).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! This module contains methods for signing the inputs of a [`Transaction`].
+//!
+//! Signatures are made over the Bitcoin Cash replay protected signature hash, a BIP143-style digest
+//! committing to the value of the output being spent, see
+//! [`Transaction::signature_hash_forkid`]. The signature hash type is bitwise or'd with
+//! [`SIGHASH_FORKID`] when appended to the signature.
+//!
+//! Only pay-to-public-key-hash inputs are supported, their script signature takes the form
+//! `<signature> <public_key>`.
+
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey as PrivateKey, Signing};
+use thiserror::Error;
+
+use crate::{
+    hash160,
+    transaction::{script::Script, SignatureHashType, Transaction, SIGHASH_FORKID},
+};
+
+/// Error associated with signing a [`Transaction`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum SigningError {
+    /// The input index is out of range.
+    #[error("input index {0} out of range")]
+    InputIndex(usize),
+    /// The number of signing inputs does not match the number of transaction inputs.
+    #[error("expected {expected} signing inputs, found {found}")]
+    InputCount {
+        /// The number of transaction inputs.
+        expected: usize,
+        /// The number of signing inputs.
+        found: usize,
+    },
+}
+
+/// The private key and value of the output spent by an input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SigningInput {
+    /// The private key controlling the output.
+    pub private_key: PrivateKey,
+    /// The value of the output in satoshis.
+    pub value: u64,
+}
+
+/// Calculate the HASH160 of a public key. This is the RIPEMD-160 digest of the SHA256 digest of the
+/// serialized public key.
+#[inline]
+pub fn public_key_hash(public_key: &PublicKey) -> [u8; 20] {
+    hash160(&public_key.serialize())
+}
+
+/// Construct the pay-to-public-key-hash script for a public key.
+#[inline]
+pub fn p2pkh_script(public_key: &PublicKey) -> Script {
+    Script::p2pkh(&public_key_hash(public_key))
+}
+
+/// Calculate the signature hash of an input spending an output with the given script and value.
+#[inline]
+pub fn signature_hash(
+    transaction: &Transaction,
+    input_index: usize,
+    script_code: &Script,
+    value: u64,
+    sig_hash_type: SignatureHashType,
+) -> Result<[u8; 32], SigningError> {
+    transaction
+        .signature_hash_forkid(input_index, script_code, value, sig_hash_type)
+        .ok_or(SigningError::InputIndex(input_index))
+}
+
+/// Sign a pay-to-public-key-hash input, replacing its script signature.
+///
+/// The `value` is that of the output being spent.
+pub fn sign_p2pkh_input<C: Signing>(
+    secp: &Secp256k1<C>,
+    transaction: &mut Transaction,
+    input_index: usize,
+    value: u64,
+    private_key: &PrivateKey,
+    sig_hash_type: SignatureHashType,
+) -> Result<(), SigningError> {
+    let public_key = PublicKey::from_secret_key(secp, private_key);
+    let hash_type = sig_hash_type.clone() as u8 | SIGHASH_FORKID as u8;
+
+    // Sign signature hash
+    let script_code = p2pkh_script(&public_key);
+    let sig_hash = signature_hash(transaction, input_index, &script_code, value, sig_hash_type)?;
+    let message = Message::from_slice(&sig_hash).unwrap(); // This is safe
+    let signature = secp.sign(&message, private_key).serialize_der();
+
+    // Construct script signature
+    let mut raw_signature = Vec::with_capacity(signature.len() + 1);
+    raw_signature.extend_from_slice(&signature);
+    raw_signature.push(hash_type);
+    let mut script = Script::default();
+    script.push_slice(&raw_signature);
+    script.push_slice(&public_key.serialize());
+
+    transaction.inputs[input_index].script = script;
+    Ok(())
+}
+
+/// Sign every input of a transaction as pay-to-public-key-hash under `SIGHASH_ALL`.
+///
+/// The signing inputs must correspond, in order, to the transaction inputs.
+pub fn sign_transaction<C: Signing>(
+    secp: &Secp256k1<C>,
+    transaction: &mut Transaction,
+    signing_inputs: &[SigningInput],
+) -> Result<(), SigningError> {
+    if signing_inputs.len() != transaction.inputs.len() {
+        return Err(SigningError::InputCount {
+            expected: transaction.inputs.len(),
+            found: signing_inputs.len(),
+        });
+    }
+    for (input_index, signing_input) in signing_inputs.iter().enumerate() {
+        sign_p2pkh_input(
+            secp,
+            transaction,
+            input_index,
+            signing_input.value,
+            &signing_input.private_key,
+            SignatureHashType::All,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::Signature;
+
+    use super::*;
+    use crate::transaction::{script::split_push, Input, Output};
+
+    #[test]
+    fn p2pkh() {
+        let private_key = PrivateKey::from_slice(&[
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 1,
+        ])
+        .unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let pubkey_hash = public_key_hash(&public_key);
+        assert_eq!(
+            hex::encode(pubkey_hash),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+
+        let script = p2pkh_script(&public_key);
+        assert!(script.is_p2pkh());
+        assert_eq!(&script.as_bytes()[3..23], &pubkey_hash[..]);
+        assert!(Script::p2sh(&pubkey_hash).is_p2sh());
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let secp = Secp256k1::new();
+        let signing_inputs: Vec<_> = (1..=2)
+            .map(|i| SigningInput {
+                private_key: PrivateKey::from_slice(&[i; 32]).unwrap(),
+                value: 1000 * i as u64,
+            })
+            .collect();
+        let mut transaction = Transaction {
+            version: 2,
+            inputs: vec![Input::default(), Input::default()],
+            outputs: vec![Output {
+                value: 2500,
+                script: p2pkh_script(&PublicKey::from_secret_key(
+                    &secp,
+                    &signing_inputs[0].private_key,
+                )),
+            }],
+            lock_time: 0,
+        };
+
+        assert_eq!(
+            sign_transaction(&secp, &mut transaction, &signing_inputs[..1]),
+            Err(SigningError::InputCount {
+                expected: 2,
+                found: 1
+            })
+        );
+        sign_transaction(&secp, &mut transaction, &signing_inputs).unwrap();
+
+        for (input_index, signing_input) in signing_inputs.iter().enumerate() {
+            let public_key = PublicKey::from_secret_key(&secp, &signing_input.private_key);
+            let raw_script = transaction.inputs[input_index].script.as_bytes();
+            let (raw_signature, rest) = split_push(raw_script).unwrap();
+            let (raw_public_key, rest) = split_push(rest).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(raw_public_key, &public_key.serialize()[..]);
+
+            let (hash_type, raw_signature) = raw_signature.split_last().unwrap();
+            assert_eq!(*hash_type, 0x41);
+            let sig_hash = signature_hash(
+                &transaction,
+                input_index,
+                &p2pkh_script(&public_key),
+                signing_input.value,
+                SignatureHashType::All,
+            )
+            .unwrap();
+            let message = Message::from_slice(&sig_hash).unwrap();
+            let signature = Signature::from_der(raw_signature).unwrap();
+            secp.verify(&message, &signature, &public_key).unwrap();
+        }
+
+        assert_eq!(
+            sign_p2pkh_input(
+                &secp,
+                &mut transaction,
+                2,
+                0,
+                &signing_inputs[0].private_key,
+                SignatureHashType::All
+            ),
+            Err(SigningError::InputIndex(2))
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! This module contains the [`SlpMessage`] struct which represents a parsed [`Simple Ledger Protocol`]
+//! `OP_RETURN` payload.
+//!
+//! SLP transactions carry their token metadata in the first output. Recognizing these allows
+//! token-carrying outputs to be distinguished from plain outputs.
+//!
+//! Token IDs are given in big-endian format, the order in which they're pushed.
+//!
+//! [`Simple Ledger Protocol`]: https://github.com/simpleledger/slp-specifications/blob/master/slp-token-type-1.md
+
+use std::convert::TryInto;
+
+use thiserror::Error;
+
+use crate::transaction::{
+    script::{opcodes, split_push},
+    Script, Transaction,
+};
+
+/// The Lokad ID prefixing SLP payloads.
+pub const LOKAD_ID: &[u8] = b"SLP\0";
+
+/// The maximum number of outputs a send may assign amounts to.
+pub const MAX_SEND_OUTPUTS: usize = 19;
+
+/// Enumerates the supported token types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenType {
+    /// The fungible token type.
+    Fungible = 0x01,
+    /// The non-fungible child token type.
+    Nft1Child = 0x41,
+    /// The non-fungible group token type.
+    Nft1Group = 0x81,
+}
+
+/// Error associated with parsing an [`SlpMessage`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum SlpError {
+    /// The transaction had no outputs.
+    #[error("missing outputs")]
+    MissingOutputs,
+    /// The script was not an `OP_RETURN`.
+    #[error("not an op_return")]
+    NotOpReturn,
+    /// The script did not begin with the SLP Lokad ID.
+    #[error("not an slp payload")]
+    NotSlp,
+    /// The script contained an opcode other than a data push.
+    #[error("invalid push")]
+    InvalidPush,
+    /// The token type was unsupported.
+    #[error("unsupported token type")]
+    UnsupportedTokenType,
+    /// The transaction type was unknown.
+    #[error("unknown transaction type")]
+    UnknownTransactionType,
+    /// The payload contained an unexpected number of fields.
+    #[error("unexpected field count")]
+    UnexpectedFieldCount,
+    /// The token ID was not 32 bytes long.
+    #[error("invalid token id")]
+    InvalidTokenId,
+    /// The document hash was neither empty nor 32 bytes long.
+    #[error("invalid document hash")]
+    InvalidDocumentHash,
+    /// The decimals were not a single byte between 0 and 9.
+    #[error("invalid decimals")]
+    InvalidDecimals,
+    /// The mint baton output was not a single byte of at least 2.
+    #[error("invalid mint baton")]
+    InvalidMintBaton,
+    /// A quantity was not 8 bytes long.
+    #[error("invalid quantity")]
+    InvalidQuantity,
+}
+
+/// Represents the creation of a token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Genesis {
+    /// The token ticker.
+    pub ticker: Vec<u8>,
+    /// The token name.
+    pub name: Vec<u8>,
+    /// The URL of the token document.
+    pub document_url: Vec<u8>,
+    /// The SHA256 digest of the token document.
+    pub document_hash: Option<[u8; 32]>,
+    /// The number of decimal places.
+    pub decimals: u8,
+    /// The output receiving the mint baton, if any.
+    pub mint_baton_vout: Option<u8>,
+    /// The quantity issued to the first output.
+    pub initial_quantity: u64,
+}
+
+/// Represents the issuance of additional tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mint {
+    /// The token ID.
+    pub token_id: [u8; 32],
+    /// The output receiving the mint baton, if any.
+    pub mint_baton_vout: Option<u8>,
+    /// The quantity issued to the first output.
+    pub additional_quantity: u64,
+}
+
+/// Represents a transfer of tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    /// The token ID.
+    pub token_id: [u8; 32],
+    /// The quantities assigned to the outputs, starting from the first output.
+    pub amounts: Vec<u64>,
+}
+
+/// Enumerates the SLP transaction types.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlpPayload {
+    /// A `GENESIS` transaction.
+    Genesis(Genesis),
+    /// A `MINT` transaction.
+    Mint(Mint),
+    /// A `SEND` transaction.
+    Send(Transfer),
+}
+
+/// Represents a parsed SLP `OP_RETURN` payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlpMessage {
+    /// The token type.
+    pub token_type: TokenType,
+    /// The transaction type and its fields.
+    pub payload: SlpPayload,
+}
+
+fn parse_token_id(raw: &[u8]) -> Result<[u8; 32], SlpError> {
+    raw.try_into().map_err(|_| SlpError::InvalidTokenId)
+}
+
+fn parse_quantity(raw: &[u8]) -> Result<u64, SlpError> {
+    raw.try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| SlpError::InvalidQuantity)
+}
+
+fn parse_mint_baton(raw: &[u8]) -> Result<Option<u8>, SlpError> {
+    match raw {
+        [] => Ok(None),
+        [vout] if *vout >= 2 => Ok(Some(*vout)),
+        _ => Err(SlpError::InvalidMintBaton),
+    }
+}
+
+impl SlpMessage {
+    /// Parse an SLP payload from an `OP_RETURN` script.
+    pub fn from_script(script: &Script) -> Result<Self, SlpError> {
+        if !script.is_op_return() {
+            return Err(SlpError::NotOpReturn);
+        }
+
+        // Collect pushes
+        let mut fields = Vec::new();
+        let mut rest = &script.as_bytes()[1..];
+        while !rest.is_empty() {
+            // Empty fields must use OP_PUSHDATA1
+            if rest[0] == opcodes::OP_0 {
+                return Err(SlpError::InvalidPush);
+            }
+            let (field, remainder) = split_push(rest).ok_or(SlpError::InvalidPush)?;
+            fields.push(field);
+            rest = remainder;
+        }
+        if fields.first() != Some(&LOKAD_ID) {
+            return Err(SlpError::NotSlp);
+        }
+
+        // Parse token type
+        let token_type = match fields.get(1) {
+            Some([0x01]) => TokenType::Fungible,
+            Some([0x41]) => TokenType::Nft1Child,
+            Some([0x81]) => TokenType::Nft1Group,
+            Some(_) => return Err(SlpError::UnsupportedTokenType),
+            None => return Err(SlpError::UnexpectedFieldCount),
+        };
+
+        let fields = &fields[2..];
+        let payload = match fields.split_first() {
+            Some((&b"GENESIS", fields)) => {
+                if fields.len() != 7 {
+                    return Err(SlpError::UnexpectedFieldCount);
+                }
+                let document_hash = match fields[3] {
+                    [] => None,
+                    raw => Some(raw.try_into().map_err(|_| SlpError::InvalidDocumentHash)?),
+                };
+                let decimals = match fields[4] {
+                    [decimals] if *decimals <= 9 => *decimals,
+                    _ => return Err(SlpError::InvalidDecimals),
+                };
+                SlpPayload::Genesis(Genesis {
+                    ticker: fields[0].to_vec(),
+                    name: fields[1].to_vec(),
+                    document_url: fields[2].to_vec(),
+                    document_hash,
+                    decimals,
+                    mint_baton_vout: parse_mint_baton(fields[5])?,
+                    initial_quantity: parse_quantity(fields[6])?,
+                })
+            }
+            Some((&b"MINT", fields)) => {
+                if fields.len() != 3 {
+                    return Err(SlpError::UnexpectedFieldCount);
+                }
+                SlpPayload::Mint(Mint {
+                    token_id: parse_token_id(fields[0])?,
+                    mint_baton_vout: parse_mint_baton(fields[1])?,
+                    additional_quantity: parse_quantity(fields[2])?,
+                })
+            }
+            Some((&b"SEND", fields)) => {
+                if fields.len() < 2 || fields.len() > MAX_SEND_OUTPUTS + 1 {
+                    return Err(SlpError::UnexpectedFieldCount);
+                }
+                let amounts = fields[1..]
+                    .iter()
+                    .map(|raw| parse_quantity(raw))
+                    .collect::<Result<_, _>>()?;
+                SlpPayload::Send(Transfer {
+                    token_id: parse_token_id(fields[0])?,
+                    amounts,
+                })
+            }
+            Some(_) => return Err(SlpError::UnknownTransactionType),
+            None => return Err(SlpError::UnexpectedFieldCount),
+        };
+
+        Ok(SlpMessage {
+            token_type,
+            payload,
+        })
+    }
+
+    /// The token ID, this is unavailable for `GENESIS` transactions where the token ID is the
+    /// transaction ID.
+    #[inline]
+    pub fn token_id(&self) -> Option<&[u8; 32]> {
+        match &self.payload {
+            SlpPayload::Genesis(_) => None,
+            SlpPayload::Mint(mint) => Some(&mint.token_id),
+            SlpPayload::Send(transfer) => Some(&transfer.token_id),
+        }
+    }
+}
+
+impl Transaction {
+    /// Parse the SLP payload from the first output of the transaction.
+    #[inline]
+    pub fn slp_message(&self) -> Result<SlpMessage, SlpError> {
+        let output = self.outputs.first().ok_or(SlpError::MissingOutputs)?;
+        SlpMessage::from_script(&output.script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op_return(fields: &[&[u8]]) -> Script {
+        let mut script = Script(vec![opcodes::OP_RETURN]);
+        for field in fields {
+            if field.is_empty() {
+                script.push_opcode(opcodes::OP_PUSHDATA1);
+                script.push_opcode(0);
+            } else {
+                script.push_slice(field);
+            }
+        }
+        script
+    }
+
+    #[test]
+    fn genesis() {
+        let script = op_return(&[
+            LOKAD_ID,
+            &[0x01],
+            b"GENESIS",
+            b"CASH",
+            b"Cash Token",
+            b"",
+            b"",
+            &[8],
+            &[2],
+            &1_000u64.to_be_bytes(),
+        ]);
+        let message = SlpMessage::from_script(&script).unwrap();
+        assert_eq!(message.token_type, TokenType::Fungible);
+        assert_eq!(message.token_id(), None);
+        assert_eq!(
+            message.payload,
+            SlpPayload::Genesis(Genesis {
+                ticker: b"CASH".to_vec(),
+                name: b"Cash Token".to_vec(),
+                document_url: vec![],
+                document_hash: None,
+                decimals: 8,
+                mint_baton_vout: Some(2),
+                initial_quantity: 1_000,
+            })
+        );
+    }
+
+    #[test]
+    fn send() {
+        let script = op_return(&[
+            LOKAD_ID,
+            &[0x01],
+            b"SEND",
+            &[3; 32],
+            &5u64.to_be_bytes(),
+            &7u64.to_be_bytes(),
+        ]);
+        let message = SlpMessage::from_script(&script).unwrap();
+        assert_eq!(message.token_id(), Some(&[3; 32]));
+        assert_eq!(
+            message.payload,
+            SlpPayload::Send(Transfer {
+                token_id: [3; 32],
+                amounts: vec![5, 7],
+            })
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let script = op_return(&[b"BCHX", &[0x01], b"SEND"]);
+        assert_eq!(SlpMessage::from_script(&script), Err(SlpError::NotSlp));
+
+        let script = op_return(&[LOKAD_ID, &[0x01], b"BURN"]);
+        assert_eq!(
+            SlpMessage::from_script(&script),
+            Err(SlpError::UnknownTransactionType)
+        );
+
+        let script = op_return(&[LOKAD_ID, &[0x01], b"MINT", &[3; 32], &[1], &[0; 8]]);
+        assert_eq!(
+            SlpMessage::from_script(&script),
+            Err(SlpError::InvalidMintBaton)
+        );
+
+        let script = op_return(&[LOKAD_ID, &[0x01], b"SEND", &[3; 32], &[0; 4]]);
+        assert_eq!(
+            SlpMessage::from_script(&script),
+            Err(SlpError::InvalidQuantity)
+        );
+
+        let mut script = op_return(&[LOKAD_ID, &[0x01], b"SEND", &[3; 32]]);
+        script.push_opcode(opcodes::OP_0);
+        assert_eq!(SlpMessage::from_script(&script), Err(SlpError::InvalidPush));
+
+        assert_eq!(
+            SlpMessage::from_script(&Script(vec![opcodes::OP_DUP])),
+            Err(SlpError::NotOpReturn)
+        );
+    }
+}
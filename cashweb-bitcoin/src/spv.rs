@@ -0,0 +1,621 @@
+//! This module contains the [`HeaderChain`] and [`MerkleProof`] structs which provide the
+//! primitives for simplified payment verification.
+//!
+//! Headers, sourced from a node RPC or an Electrum server, are validated for proof-of-work and
+//! continuity before being added to the [`HeaderChain`]. The chain with the most cumulative work is
+//! tracked as the best chain, and reorganizations are reported as headers arrive. Combined with a
+//! [`MerkleProof`], the chain answers whether a transaction is confirmed at a given depth.
+//!
+//! The target of each header must be within the proof-of-work limit of the [`ChainParams`] and,
+//! outside of difficulty adjustment boundaries, equal to the target of its parent. At adjustment
+//! boundaries the difficulty may change by at most a factor of [`MAX_ADJUSTMENT`]. The exact
+//! difficulty adjustment algorithm is not evaluated, the cumulative work of the best chain is
+//! relied upon instead.
+//!
+//! Block hashes and transaction IDs are given in little-endian format, the order in which they're
+//! serialized.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{
+    double_sha256,
+    header::{compact_to_target, meets_target, BlockHeader, TargetError},
+};
+
+/// The number of previous headers used to calculate the median time past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The greatest factor by which the difficulty may change at an adjustment boundary.
+pub const MAX_ADJUSTMENT: u128 = 4;
+
+/// The consensus parameters used to validate the difficulty of headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainParams {
+    /// The compact form of the easiest target permitted.
+    pub pow_limit: u32,
+    /// The number of blocks between difficulty adjustments, `None` if the difficulty never
+    /// adjusts.
+    pub adjustment_interval: Option<u32>,
+}
+
+impl ChainParams {
+    /// The parameters of mainnet, where the difficulty adjusts every block.
+    pub const MAINNET: Self = Self {
+        pow_limit: 0x1d00_ffff,
+        adjustment_interval: Some(1),
+    };
+
+    /// The parameters of regtest, where the difficulty never adjusts.
+    pub const REGTEST: Self = Self {
+        pow_limit: 0x207f_ffff,
+        adjustment_interval: None,
+    };
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::MAINNET
+    }
+}
+
+/// A merkle branch proving the inclusion of a transaction within a block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// The position of the transaction within the block.
+    pub index: u32,
+    /// The sibling hashes, from the leaves towards the root.
+    pub branch: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Calculate the merkle root committed to by the proof for the transaction ID.
+    pub fn merkle_root(&self, tx_id_le: &[u8; 32]) -> [u8; 32] {
+        let mut hash = *tx_id_le;
+        let mut index = self.index;
+        let mut preimage = [0; 64];
+        for sibling in &self.branch {
+            if index & 1 == 1 {
+                preimage[..32].copy_from_slice(sibling);
+                preimage[32..].copy_from_slice(&hash);
+            } else {
+                preimage[..32].copy_from_slice(&hash);
+                preimage[32..].copy_from_slice(sibling);
+            }
+            hash = double_sha256(&preimage);
+            index >>= 1;
+        }
+        hash
+    }
+
+    /// Check whether the proof commits the transaction ID to the merkle root.
+    #[inline]
+    pub fn verify(&self, tx_id_le: &[u8; 32], merkle_root: &[u8; 32]) -> bool {
+        &self.merkle_root(tx_id_le) == merkle_root
+    }
+}
+
+/// Error associated with adding a header to the [`HeaderChain`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum HeaderError {
+    /// The previous header is unknown.
+    #[error("orphan header")]
+    Orphan,
+    /// The compact target was invalid.
+    #[error(transparent)]
+    Target(TargetError),
+    /// The block hash did not meet the target.
+    #[error("insufficient proof-of-work")]
+    InsufficientWork,
+    /// The target was easier than the proof-of-work limit.
+    #[error("target above proof-of-work limit")]
+    AbovePowLimit,
+    /// The target did not match the difficulty expected from the previous header.
+    #[error("unexpected difficulty")]
+    UnexpectedDifficulty,
+    /// The header time was not greater than the median time past.
+    #[error("header time too old")]
+    TimeTooOld,
+}
+
+/// Error associated with checking a transaction confirmation.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ConfirmationError {
+    /// The block is not within the best chain.
+    #[error("block not in best chain")]
+    NotInBestChain,
+    /// The merkle proof did not commit to the block merkle root.
+    #[error("invalid merkle proof")]
+    InvalidProof,
+}
+
+/// The effect of adding a header to the [`HeaderChain`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainUpdate {
+    /// The header was already known.
+    Duplicate,
+    /// The header extended the best chain.
+    Extended {
+        /// The height of the new tip.
+        height: u32,
+    },
+    /// The header was added to a side chain with less work than the best chain.
+    SideChain {
+        /// The height of the header.
+        height: u32,
+    },
+    /// The header caused a side chain to become the best chain.
+    Reorg {
+        /// The height of the last block common to both chains.
+        fork_height: u32,
+        /// The block hashes removed from the best chain, in ascending height.
+        disconnected: Vec<[u8; 32]>,
+        /// The block hashes added to the best chain, in ascending height.
+        connected: Vec<[u8; 32]>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct ChainEntry {
+    header: BlockHeader,
+    height: u32,
+    chain_work: u128,
+}
+
+/// A tree of validated headers, tracking the chain with the most cumulative work.
+///
+/// The chain is rooted at a trusted checkpoint, typically the genesis block or a recent block
+/// hard-coded by the application.
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    entries: HashMap<[u8; 32], ChainEntry>,
+    base_height: u32,
+    best_chain: Vec<[u8; 32]>,
+    params: ChainParams,
+}
+
+impl HeaderChain {
+    /// Create a new [`HeaderChain`] rooted at a trusted checkpoint header and its height, using the
+    /// [`ChainParams::MAINNET`] parameters.
+    pub fn new(checkpoint: BlockHeader, height: u32) -> Self {
+        let block_hash = checkpoint.block_hash_le();
+        let mut entries = HashMap::new();
+        entries.insert(
+            block_hash,
+            ChainEntry {
+                header: checkpoint,
+                height,
+                chain_work: 0,
+            },
+        );
+        Self {
+            entries,
+            base_height: height,
+            best_chain: vec![block_hash],
+            params: ChainParams::default(),
+        }
+    }
+
+    /// Set the [`ChainParams`] used to validate the difficulty of headers.
+    #[inline]
+    pub fn with_params(mut self, params: ChainParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// The [`ChainParams`] used to validate the difficulty of headers.
+    #[inline]
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// The height of the best chain tip.
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.base_height + self.best_chain.len() as u32 - 1
+    }
+
+    /// The block hash of the best chain tip.
+    #[inline]
+    pub fn tip_hash(&self) -> &[u8; 32] {
+        self.best_chain.last().unwrap() // This is safe
+    }
+
+    /// The header of the best chain tip.
+    #[inline]
+    pub fn tip(&self) -> &BlockHeader {
+        &self.entries[self.tip_hash()].header // This is safe
+    }
+
+    /// The cumulative work of the best chain, since the checkpoint.
+    #[inline]
+    pub fn chain_work(&self) -> u128 {
+        self.entries[self.tip_hash()].chain_work // This is safe
+    }
+
+    /// Get a known header, within the best chain or a side chain.
+    #[inline]
+    pub fn header(&self, block_hash: &[u8; 32]) -> Option<&BlockHeader> {
+        self.entries.get(block_hash).map(|entry| &entry.header)
+    }
+
+    /// Get the block hash at a height within the best chain.
+    #[inline]
+    pub fn block_hash_at(&self, height: u32) -> Option<&[u8; 32]> {
+        let index = height.checked_sub(self.base_height)?;
+        self.best_chain.get(index as usize)
+    }
+
+    /// Get the header at a height within the best chain.
+    #[inline]
+    pub fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.block_hash_at(height)
+            .and_then(|block_hash| self.header(block_hash))
+    }
+
+    /// Get the height of a block within the best chain.
+    pub fn best_height_of(&self, block_hash: &[u8; 32]) -> Option<u32> {
+        let height = self.entries.get(block_hash)?.height;
+        if self.block_hash_at(height) == Some(block_hash) {
+            Some(height)
+        } else {
+            None
+        }
+    }
+
+    /// Get the number of confirmations of a block within the best chain. The tip has a single
+    /// confirmation.
+    #[inline]
+    pub fn confirmations(&self, block_hash: &[u8; 32]) -> Option<u32> {
+        self.best_height_of(block_hash)
+            .map(|height| self.height() - height + 1)
+    }
+
+    /// Calculate the median time past of the header and its ancestors.
+    fn median_time_past(&self, block_hash: &[u8; 32]) -> u32 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut entry = self.entries.get(block_hash);
+        while let Some(some) = entry {
+            times.push(some.header.time);
+            if times.len() == MEDIAN_TIME_SPAN {
+                break;
+            }
+            entry = self.entries.get(&some.header.previous_block_hash);
+        }
+        times.sort_unstable();
+        times[times.len() / 2] // This is safe
+    }
+
+    /// Validate and add a header.
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<ChainUpdate, HeaderError> {
+        let block_hash = header.block_hash_le();
+        if self.entries.contains_key(&block_hash) {
+            return Ok(ChainUpdate::Duplicate);
+        }
+
+        // Check continuity
+        let previous = self
+            .entries
+            .get(&header.previous_block_hash)
+            .ok_or(HeaderError::Orphan)?;
+
+        let height = previous.height + 1;
+
+        // Check difficulty
+        let target = header.target().map_err(HeaderError::Target)?;
+        let pow_limit = compact_to_target(self.params.pow_limit).map_err(HeaderError::Target)?;
+        if !meets_target(&target, &pow_limit) {
+            return Err(HeaderError::AbovePowLimit);
+        }
+        let work = header.work().map_err(HeaderError::Target)?;
+        match self.params.adjustment_interval {
+            Some(interval) if interval != 0 && height % interval == 0 => {
+                let previous_work = previous.header.work().map_err(HeaderError::Target)?;
+                if work < previous_work / MAX_ADJUSTMENT
+                    || work > previous_work.saturating_mul(MAX_ADJUSTMENT)
+                {
+                    return Err(HeaderError::UnexpectedDifficulty);
+                }
+            }
+            _ => {
+                if header.bits != previous.header.bits {
+                    return Err(HeaderError::UnexpectedDifficulty);
+                }
+            }
+        }
+
+        // Check proof-of-work
+        if !meets_target(&header.block_hash_le(), &target) {
+            return Err(HeaderError::InsufficientWork);
+        }
+
+        // Check time
+        if header.time <= self.median_time_past(&header.previous_block_hash) {
+            return Err(HeaderError::TimeTooOld);
+        }
+
+        let chain_work = previous.chain_work.saturating_add(work);
+        let extends_tip = &header.previous_block_hash == self.tip_hash();
+        self.entries.insert(
+            block_hash,
+            ChainEntry {
+                header,
+                height,
+                chain_work,
+            },
+        );
+
+        if chain_work <= self.chain_work() {
+            return Ok(ChainUpdate::SideChain { height });
+        }
+        if extends_tip {
+            self.best_chain.push(block_hash);
+            return Ok(ChainUpdate::Extended { height });
+        }
+
+        // Walk back to the best chain
+        let mut connected = vec![block_hash];
+        let mut cursor = self.entries[&block_hash].header.previous_block_hash; // This is safe
+        while self.best_height_of(&cursor).is_none() {
+            connected.push(cursor);
+            cursor = self.entries[&cursor].header.previous_block_hash; // This is safe
+        }
+        connected.reverse();
+        let fork_height = self.entries[&cursor].height; // This is safe
+
+        let fork_index = (fork_height - self.base_height) as usize + 1;
+        let disconnected = self.best_chain.split_off(fork_index);
+        self.best_chain.extend_from_slice(&connected);
+
+        Ok(ChainUpdate::Reorg {
+            fork_height,
+            disconnected,
+            connected,
+        })
+    }
+
+    /// Validate and add a sequence of headers, in ascending height.
+    pub fn add_headers<I>(&mut self, headers: I) -> Result<Vec<ChainUpdate>, HeaderError>
+    where
+        I: IntoIterator<Item = BlockHeader>,
+    {
+        headers
+            .into_iter()
+            .map(|header| self.add_header(header))
+            .collect()
+    }
+
+    /// Check whether a transaction is included in a block within the best chain and return the
+    /// number of confirmations.
+    pub fn tx_confirmations(
+        &self,
+        tx_id_le: &[u8; 32],
+        block_hash: &[u8; 32],
+        proof: &MerkleProof,
+    ) -> Result<u32, ConfirmationError> {
+        let confirmations = self
+            .confirmations(block_hash)
+            .ok_or(ConfirmationError::NotInBestChain)?;
+        let header = self.header(block_hash).unwrap(); // This is safe
+        if !proof.verify(tx_id_le, &header.merkle_root) {
+            return Err(ConfirmationError::InvalidProof);
+        }
+        Ok(confirmations)
+    }
+
+    /// Check whether a transaction is confirmed at a depth of at least `depth` blocks.
+    #[inline]
+    pub fn is_confirmed(
+        &self,
+        tx_id_le: &[u8; 32],
+        block_hash: &[u8; 32],
+        proof: &MerkleProof,
+        depth: u32,
+    ) -> bool {
+        self.tx_confirmations(tx_id_le, block_hash, proof)
+            .map(|confirmations| confirmations >= depth)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The easiest regtest target, roughly every other nonce meets it.
+    const REGTEST_BITS: u32 = 0x207f_ffff;
+
+    /// A target 256 times harder than the regtest target.
+    const HARDER_BITS: u32 = 0x1f7f_ffff;
+
+    fn mine(previous: &BlockHeader, merkle_root: [u8; 32], salt: u32) -> BlockHeader {
+        mine_with_bits(previous, merkle_root, salt, previous.bits)
+    }
+
+    fn mine_with_bits(
+        previous: &BlockHeader,
+        merkle_root: [u8; 32],
+        salt: u32,
+        bits: u32,
+    ) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            previous_block_hash: previous.block_hash_le(),
+            merkle_root,
+            time: previous.time + 600 + salt,
+            bits,
+            nonce: 0,
+        };
+        while !header.check_pow().unwrap() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn genesis() -> BlockHeader {
+        BlockHeader {
+            time: 1_296_688_602,
+            bits: REGTEST_BITS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merkle_proof() {
+        let leaves: Vec<[u8; 32]> = (0..3u8).map(|i| double_sha256(&[i])).collect();
+        let concat = |a: &[u8; 32], b: &[u8; 32]| {
+            let mut preimage = [0; 64];
+            preimage[..32].copy_from_slice(a);
+            preimage[32..].copy_from_slice(b);
+            double_sha256(&preimage)
+        };
+        // The odd leaf is paired with itself
+        let left = concat(&leaves[0], &leaves[1]);
+        let right = concat(&leaves[2], &leaves[2]);
+        let root = concat(&left, &right);
+
+        let proof = MerkleProof {
+            index: 2,
+            branch: vec![leaves[2], left],
+        };
+        assert!(proof.verify(&leaves[2], &root));
+        assert!(!proof.verify(&leaves[1], &root));
+
+        let proof = MerkleProof {
+            index: 1,
+            branch: vec![leaves[0], right],
+        };
+        assert!(proof.verify(&leaves[1], &root));
+    }
+
+    #[test]
+    fn extend_and_confirm() {
+        let genesis = genesis();
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(ChainParams::REGTEST);
+
+        let tx_id = double_sha256(b"transaction");
+        let proof = MerkleProof::default();
+        let block = mine(&genesis, tx_id, 0);
+        let block_hash = block.block_hash_le();
+        assert_eq!(
+            chain.add_header(block.clone()),
+            Ok(ChainUpdate::Extended { height: 1 })
+        );
+        assert_eq!(chain.add_header(block.clone()), Ok(ChainUpdate::Duplicate));
+        assert_eq!(chain.tx_confirmations(&tx_id, &block_hash, &proof), Ok(1));
+        assert!(!chain.is_confirmed(&tx_id, &block_hash, &proof, 2));
+
+        let next = mine(&block, [0; 32], 0);
+        chain.add_header(next).unwrap();
+        assert_eq!(chain.height(), 2);
+        assert!(chain.is_confirmed(&tx_id, &block_hash, &proof, 2));
+        assert_eq!(
+            chain.tx_confirmations(&[0; 32], &block_hash, &proof),
+            Err(ConfirmationError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn reorg() {
+        let genesis = genesis();
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(ChainParams::REGTEST);
+
+        let a1 = mine(&genesis, [1; 32], 0);
+        chain.add_header(a1.clone()).unwrap();
+
+        let b1 = mine(&genesis, [2; 32], 1);
+        let b2 = mine(&b1, [2; 32], 1);
+        assert_eq!(
+            chain.add_header(b1.clone()),
+            Ok(ChainUpdate::SideChain { height: 1 })
+        );
+        assert_eq!(
+            chain.add_header(b2.clone()),
+            Ok(ChainUpdate::Reorg {
+                fork_height: 0,
+                disconnected: vec![a1.block_hash_le()],
+                connected: vec![b1.block_hash_le(), b2.block_hash_le()],
+            })
+        );
+        assert_eq!(chain.tip_hash(), &b2.block_hash_le());
+        assert_eq!(chain.confirmations(&a1.block_hash_le()), None);
+        assert_eq!(chain.confirmations(&b1.block_hash_le()), Some(2));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let genesis = genesis();
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(ChainParams::REGTEST);
+
+        let orphan = mine(&mine(&genesis, [0; 32], 0), [0; 32], 0);
+        assert_eq!(chain.add_header(orphan), Err(HeaderError::Orphan));
+
+        let mut stale = mine(&genesis, [0; 32], 0);
+        stale.time = genesis.time;
+        while !stale.check_pow().unwrap() {
+            stale.nonce += 1;
+        }
+        assert_eq!(chain.add_header(stale), Err(HeaderError::TimeTooOld));
+
+        let mut weak = mine(&genesis, [0; 32], 0);
+        while weak.check_pow().unwrap() {
+            weak.nonce += 1;
+        }
+        assert_eq!(chain.add_header(weak), Err(HeaderError::InsufficientWork));
+    }
+
+    #[test]
+    fn low_difficulty_fork() {
+        let genesis = BlockHeader {
+            bits: HARDER_BITS,
+            ..genesis()
+        };
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(ChainParams::REGTEST);
+        let a1 = mine(&genesis, [1; 32], 0);
+        chain.add_header(a1.clone()).unwrap();
+
+        let b1 = mine_with_bits(&genesis, [2; 32], 0, REGTEST_BITS);
+        assert_eq!(chain.add_header(b1), Err(HeaderError::UnexpectedDifficulty));
+        assert_eq!(chain.tip_hash(), &a1.block_hash_le());
+    }
+
+    #[test]
+    fn pow_limit() {
+        let genesis = genesis();
+        let params = ChainParams {
+            pow_limit: HARDER_BITS,
+            adjustment_interval: None,
+        };
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(params);
+        assert_eq!(
+            chain.add_header(mine(&genesis, [0; 32], 0)),
+            Err(HeaderError::AbovePowLimit)
+        );
+    }
+
+    #[test]
+    fn difficulty_adjustment() {
+        let genesis = genesis();
+        let params = ChainParams {
+            pow_limit: REGTEST_BITS,
+            adjustment_interval: Some(2),
+        };
+        let mut chain = HeaderChain::new(genesis.clone(), 0).with_params(params);
+
+        // Outside of adjustment boundaries the difficulty is unchanged
+        let a1 = mine_with_bits(&genesis, [0; 32], 0, 0x203f_ffff);
+        assert_eq!(chain.add_header(a1), Err(HeaderError::UnexpectedDifficulty));
+        let a1 = mine(&genesis, [0; 32], 0);
+        chain.add_header(a1.clone()).unwrap();
+
+        // At adjustment boundaries the difficulty changes by at most a factor of four
+        let a2 = mine_with_bits(&a1, [0; 32], 0, HARDER_BITS);
+        assert_eq!(chain.add_header(a2), Err(HeaderError::UnexpectedDifficulty));
+        let a2 = mine_with_bits(&a1, [0; 32], 0, 0x201f_ffff);
+        assert_eq!(
+            chain.add_header(a2),
+            Ok(ChainUpdate::Extended { height: 2 })
+        );
+    }
+}
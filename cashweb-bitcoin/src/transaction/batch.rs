@@ -0,0 +1,81 @@
+//! This module contains methods for computing the transaction IDs of batches of transactions.
+//!
+//! A single encoding buffer is reused across the batch, rather than allocated for each
+//! transaction. When the `rayon` feature is enabled the batch is hashed in parallel, with a buffer
+//! reused per worker.
+
+use bytes::BytesMut;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use super::{transaction_id_le, Transaction, Txid};
+use crate::Encodable;
+
+/// Calculate the transaction IDs, in little-endian format, of a batch of raw transactions.
+///
+/// The raw transactions are hashed as given, so must be serialized without witness data, see
+/// [`WitnessTransaction`](super::WitnessTransaction). The results are returned in the same order
+/// as the raw transactions.
+pub fn compute_raw_txids<T>(raw_transactions: &[T]) -> Vec<Txid>
+where
+    T: AsRef<[u8]> + Sync,
+{
+    #[cfg(feature = "rayon")]
+    let iter = raw_transactions.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let iter = raw_transactions.iter();
+
+    iter.map(|raw_transaction| transaction_id_le(raw_transaction.as_ref()))
+        .collect()
+}
+
+/// Calculate the transaction IDs, in little-endian format, of a batch of transactions.
+///
+/// The results are returned in the same order as the transactions.
+pub fn compute_txids(transactions: &[Transaction]) -> Vec<Txid> {
+    let hash = |raw_tx: &mut BytesMut, transaction: &Transaction| {
+        raw_tx.clear();
+        transaction.encode_into(raw_tx);
+        transaction_id_le(&raw_tx[..])
+    };
+
+    #[cfg(feature = "rayon")]
+    let txids = transactions
+        .par_iter()
+        .map_init(BytesMut::new, hash)
+        .collect();
+    #[cfg(not(feature = "rayon"))]
+    let txids = {
+        let mut raw_tx = BytesMut::new();
+        transactions
+            .iter()
+            .map(|transaction| hash(&mut raw_tx, transaction))
+            .collect()
+    };
+
+    txids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decodable;
+
+    #[test]
+    fn batch_matches_single() {
+        let tx_hex = "c47d5ad60485cb2f7a825587b95ea665a593769191382852f3514a486d7a7a11d220b62c54000000000663655253acab8c3cf32b0285b040e50dcf6987ddf7c385b3665048ad2f9317b9e0c5ba0405d8fde4129b00000000095251ab00ac65635300ffffffff549fe963ee410d6435bb2ed3042a7c294d0c7382a83edefba8582a2064af3265000000000152fffffffff7737a85e0e94c2d19cd1cde47328ece04b3e33cd60f24a8a345da7f2a96a6d0000000000865ab6a0051656aab28ff30d5049613ea020000000005ac51000063f06df1050000000008ac63516aabac5153afef5901000000000700656500655253688bc00000000000086aab5352526a53521ff1d5ff";
+        let raw_tx = hex::decode(tx_hex).unwrap();
+        let tx = Transaction::decode(&mut raw_tx.as_slice()).unwrap();
+        let transactions = vec![tx.clone(), Transaction::default(), tx.clone()];
+
+        let expected: Vec<Txid> = transactions
+            .iter()
+            .map(Transaction::transaction_id_le)
+            .collect();
+        assert_eq!(compute_txids(&transactions), expected);
+        assert_eq!(
+            compute_raw_txids(&[raw_tx.clone(), raw_tx]),
+            vec![tx.transaction_id_le(); 2]
+        );
+    }
+}
@@ -1,10 +1,15 @@
 //! This module contains the primary structs related to Bitcoin transactions.
 //! All of them enjoy [`Encodable`] and [`Decodable`].
+//!
+//! Transactions carrying segregated witness data, from other chains, are represented by the
+//! [`WitnessTransaction`].
 
+pub mod batch;
 pub mod input;
 pub mod outpoint;
 pub mod output;
 pub mod script;
+pub mod witness;
 
 use std::convert::TryInto;
 
@@ -17,11 +22,15 @@ use crate::{
     Decodable, Encodable,
 };
 #[doc(inline)]
+pub use batch::{compute_raw_txids, compute_txids};
+#[doc(inline)]
 pub use input::{DecodeError as InputDecodeError, Input};
 #[doc(inline)]
 pub use output::{DecodeError as OutputDecodeError, Output};
 #[doc(inline)]
 pub use script::Script;
+#[doc(inline)]
+pub use witness::{DecodeError as WitnessDecodeError, Witness, WitnessTransaction};
 
 /// Represents a transaction.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -33,6 +42,13 @@ pub struct Transaction {
     pub lock_time: u32,
 }
 
+/// A transaction ID in little-endian format.
+pub type Txid = [u8; 32];
+
+/// The flag bitwise or'd with the signature hash type to indicate the replay protected signature
+/// hash algorithm, see [`Transaction::signature_hash_forkid`].
+pub const SIGHASH_FORKID: u32 = 0x40;
+
 /// Enumerates the different signature hash types.
 #[derive(Clone, Debug, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -89,6 +105,19 @@ impl Transaction {
         transaction_id(&raw_tx)
     }
 
+    /// Construct a transaction from a hex string of the raw transaction.
+    #[inline]
+    pub fn from_hex_str(hex_str: &str) -> Result<Self, FromHexError> {
+        let raw_tx = hex::decode(hex_str).map_err(FromHexError::Hex)?;
+        Self::decode(&mut raw_tx.as_slice()).map_err(FromHexError::Decode)
+    }
+
+    /// Encode the raw transaction as a hex string.
+    #[inline]
+    pub fn to_hex_string(&self) -> String {
+        hex::encode(self.encode_to_bytes())
+    }
+
     /// Calculate input count VarInt.
     #[inline]
     fn input_count_varint(&self) -> VarInt {
@@ -194,6 +223,93 @@ impl Transaction {
 
         Some(pre_sig_hash)
     }
+
+    /// Calculate the signature hash of a specific input under the Bitcoin Cash replay protected
+    /// algorithm, `SIGHASH_FORKID`, which commits to the `value` of the output being spent.
+    ///
+    /// The returned digest is to be signed with the signature hash type bitwise or'd with
+    /// [`SIGHASH_FORKID`].
+    pub fn signature_hash_forkid(
+        &self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sig_hash_type: SignatureHashType,
+    ) -> Option<[u8; 32]> {
+        let input = self.inputs.get(input_index)?;
+        let anyone_can_pay = matches!(
+            sig_hash_type,
+            SignatureHashType::AnyoneCanPayAll
+                | SignatureHashType::AnyoneCanPayNone
+                | SignatureHashType::AnyoneCanPaySingle
+        );
+        let base_type = match sig_hash_type {
+            SignatureHashType::All | SignatureHashType::AnyoneCanPayAll => SignatureHashType::All,
+            SignatureHashType::None | SignatureHashType::AnyoneCanPayNone => {
+                SignatureHashType::None
+            }
+            SignatureHashType::Single | SignatureHashType::AnyoneCanPaySingle => {
+                SignatureHashType::Single
+            }
+        };
+        let double_sha256 = |raw: &[u8]| -> [u8; 32] {
+            digest(&SHA256, digest(&SHA256, raw).as_ref())
+                .as_ref()
+                .try_into()
+                .unwrap() // This is safe
+        };
+
+        // Hash outpoints and sequences
+        let hash_prevouts = if anyone_can_pay {
+            [0; 32]
+        } else {
+            let mut raw_prevouts = Vec::with_capacity(36 * self.inputs.len());
+            for input in &self.inputs {
+                input.outpoint.encode_raw(&mut raw_prevouts);
+            }
+            double_sha256(&raw_prevouts)
+        };
+        let hash_sequence = if anyone_can_pay || base_type != SignatureHashType::All {
+            [0; 32]
+        } else {
+            let mut raw_sequences = Vec::with_capacity(4 * self.inputs.len());
+            for input in &self.inputs {
+                raw_sequences.put_u32_le(input.sequence);
+            }
+            double_sha256(&raw_sequences)
+        };
+
+        // Hash outputs
+        let hash_outputs = match base_type {
+            SignatureHashType::All => {
+                let mut raw_outputs = Vec::new();
+                for output in &self.outputs {
+                    output.encode_raw(&mut raw_outputs);
+                }
+                double_sha256(&raw_outputs)
+            }
+            SignatureHashType::Single if input_index < self.outputs.len() => {
+                double_sha256(&self.outputs[input_index].encode_to_bytes())
+            }
+            _ => [0; 32],
+        };
+
+        // Serialize preimage
+        let mut preimage = Vec::with_capacity(156 + script_code.len());
+        preimage.put_u32_le(self.version);
+        preimage.put(&hash_prevouts[..]);
+        preimage.put(&hash_sequence[..]);
+        input.outpoint.encode_raw(&mut preimage);
+        script_code.len_varint().encode_raw(&mut preimage);
+        script_code.encode_raw(&mut preimage);
+        preimage.put_u64_le(value);
+        preimage.put_u32_le(input.sequence);
+        preimage.put(&hash_outputs[..]);
+        preimage.put_u32_le(self.lock_time);
+        preimage.put_u32_le(sig_hash_type as u32 | SIGHASH_FORKID);
+
+        Some(double_sha256(&preimage))
+    }
 }
 
 impl Encodable for Transaction {
@@ -226,6 +342,17 @@ impl Encodable for Transaction {
     }
 }
 
+/// Error associated with constructing a [`Transaction`] from a hex string.
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum FromHexError {
+    /// The string was not valid hex.
+    #[error("invalid hex: {0}")]
+    Hex(hex::FromHexError),
+    /// Failed to decode the raw transaction.
+    #[error("transaction decoding failure: {0}")]
+    Decode(DecodeError),
+}
+
 /// Error associated with [`Transaction`] deserialization.
 #[derive(Clone, Debug, PartialEq, Eq, Error)]
 pub enum DecodeError {
@@ -249,33 +376,44 @@ pub enum DecodeError {
     LockTimeTooShort,
 }
 
-impl Decodable for Transaction {
-    type Error = DecodeError;
-
-    fn decode<B: Buf>(mut buf: &mut B) -> Result<Self, Self::Error> {
-        // Parse version
-        if buf.remaining() < 4 {
-            return Err(Self::Error::VersionTooShort);
-        }
-        let version = buf.get_u32_le();
-
+impl Transaction {
+    /// Decode the inputs and outputs, each preceded by their count.
+    fn decode_inputs_outputs<B: Buf>(
+        mut buf: &mut B,
+    ) -> Result<(Vec<Input>, Vec<Output>), DecodeError> {
         // Parse inputs
         let n_inputs: u64 = VarInt::decode(&mut buf)
-            .map_err(Self::Error::InputCount)?
+            .map_err(DecodeError::InputCount)?
             .into();
         let inputs: Vec<Input> = (0..n_inputs)
             .map(|_| Input::decode(buf))
             .collect::<Result<Vec<Input>, _>>()
-            .map_err(Self::Error::Input)?;
+            .map_err(DecodeError::Input)?;
 
         // Parse outputs
         let n_outputs: u64 = VarInt::decode(&mut buf)
-            .map_err(Self::Error::OutputCount)?
+            .map_err(DecodeError::OutputCount)?
             .into();
         let outputs: Vec<Output> = (0..n_outputs)
             .map(|_| Output::decode(buf))
             .collect::<Result<Vec<Output>, _>>()
-            .map_err(Self::Error::Output)?;
+            .map_err(DecodeError::Output)?;
+        Ok((inputs, outputs))
+    }
+}
+
+impl Decodable for Transaction {
+    type Error = DecodeError;
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        // Parse version
+        if buf.remaining() < 4 {
+            return Err(Self::Error::VersionTooShort);
+        }
+        let version = buf.get_u32_le();
+
+        // Parse inputs and outputs
+        let (inputs, outputs) = Self::decode_inputs_outputs(buf)?;
 
         // Parse lock time
         if buf.remaining() < 4 {
@@ -328,6 +466,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn hex_str() {
+        for hex_tx in test_txs() {
+            let tx = Transaction::from_hex_str(hex_tx).unwrap();
+            assert_eq!(tx.to_hex_string(), hex_tx);
+        }
+        assert!(matches!(
+            Transaction::from_hex_str("zz"),
+            Err(FromHexError::Hex(_))
+        ));
+    }
+
+    #[test]
+    fn zero_inputs() {
+        // The input count is not mistaken for the segregated witness marker
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: vec![Output {
+                value: 3000,
+                script: Script::p2pkh(&[1; 20]),
+            }],
+            lock_time: 0,
+        };
+        let raw_tx = tx.encode_to_vec();
+        assert_eq!(&raw_tx[4..6], &[0, 1]);
+        assert_eq!(Transaction::decode(&mut raw_tx.as_slice()).unwrap(), tx);
+    }
+
     #[test]
     fn encode_insufficent_capacity() {
         for hex_tx in test_txs() {
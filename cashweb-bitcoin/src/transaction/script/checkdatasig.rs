@@ -0,0 +1,162 @@
+//! This module contains helpers for constructing and recognizing scripts using
+//! `OP_CHECKDATASIG`.
+//!
+//! `OP_CHECKDATASIG` verifies a signature over the SHA256 digest of an arbitrary message, rather
+//! than over the spending transaction. This allows an output to be conditioned on an oracle
+//! attesting to a message, such as the outcome of an event.
+
+use std::convert::TryInto;
+
+use ring::digest::{digest, SHA256};
+use secp256k1::{
+    Error as SecpError, Message, PublicKey, Secp256k1, SecretKey as PrivateKey, Signature, Signing,
+    Verification,
+};
+
+use super::{opcodes::*, split_push, Script};
+
+/// Calculate the digest of a message signed by an oracle. This is the SHA256 digest of the
+/// message.
+#[inline]
+pub fn message_digest(message: &[u8]) -> [u8; 32] {
+    digest(&SHA256, message).as_ref().try_into().unwrap() // This is safe
+}
+
+/// Sign a message as an oracle, for verification by `OP_CHECKDATASIG`.
+#[inline]
+pub fn sign_message<C: Signing>(
+    secp: &Secp256k1<C>,
+    message: &[u8],
+    private_key: &PrivateKey,
+) -> Signature {
+    let msg = Message::from_slice(&message_digest(message)).unwrap(); // This is safe
+    secp.sign(&msg, private_key)
+}
+
+/// Verify an oracle signature over a message, as performed by `OP_CHECKDATASIG`.
+#[inline]
+pub fn verify_message<C: Verification>(
+    secp: &Secp256k1<C>,
+    message: &[u8],
+    signature: &Signature,
+    public_key: &PublicKey,
+) -> Result<(), SecpError> {
+    let msg = Message::from_slice(&message_digest(message)).unwrap(); // This is safe
+    secp.verify(&msg, signature, public_key)
+}
+
+/// Represents an output script paying to a recipient, conditional on an oracle signing a message.
+///
+/// The script takes the form
+/// `<message> <oracle_public_key> OP_CHECKDATASIGVERIFY <recipient_public_key> OP_CHECKSIG`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleScript {
+    /// The message the oracle must sign.
+    pub message: Vec<u8>,
+    /// The public key of the oracle.
+    pub oracle_public_key: PublicKey,
+    /// The public key of the recipient.
+    pub recipient_public_key: PublicKey,
+}
+
+impl OracleScript {
+    /// Construct the output script.
+    pub fn to_script(&self) -> Script {
+        let mut script = Script(Vec::with_capacity(self.message.len() + 75));
+        script.push_slice(&self.message);
+        script.push_slice(&self.oracle_public_key.serialize());
+        script.push_opcode(OP_CHECKDATASIGVERIFY);
+        script.push_slice(&self.recipient_public_key.serialize());
+        script.push_opcode(OP_CHECKSIG);
+        script
+    }
+
+    /// Recognize an output script fitting the oracle pattern.
+    pub fn from_script(script: &Script) -> Option<Self> {
+        let (message, rest) = split_push(script.as_bytes())?;
+        if rest.len() != 70
+            || rest[0] != OP_PUSHBYTES_33
+            || rest[34] != OP_CHECKDATASIGVERIFY
+            || rest[35] != OP_PUSHBYTES_33
+            || rest[69] != OP_CHECKSIG
+        {
+            return None;
+        }
+        let oracle_public_key = PublicKey::from_slice(&rest[1..34]).ok()?;
+        let recipient_public_key = PublicKey::from_slice(&rest[36..69]).ok()?;
+
+        Some(OracleScript {
+            message: message.to_vec(),
+            oracle_public_key,
+            recipient_public_key,
+        })
+    }
+
+    /// Construct the input script spending the output.
+    ///
+    /// The recipient signature must be DER encoded with the signature hash type appended.
+    pub fn script_sig(recipient_signature: &[u8], oracle_signature: &Signature) -> Script {
+        let mut script = Script::default();
+        script.push_slice(recipient_signature);
+        script.push_slice(&oracle_signature.serialize_der());
+        script
+    }
+}
+
+impl Script {
+    /// Checks whether the script fits the oracle `OP_CHECKDATASIG` pattern.
+    #[inline]
+    pub fn is_oracle_script(&self) -> bool {
+        OracleScript::from_script(self).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(byte: u8) -> (PrivateKey, PublicKey) {
+        let private_key = PrivateKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        (private_key, public_key)
+    }
+
+    #[test]
+    fn oracle_script() {
+        let (_, oracle_public_key) = keys(1);
+        let (_, recipient_public_key) = keys(2);
+        for len in &[0, 32, 100, 300] {
+            let oracle_script = OracleScript {
+                message: vec![7; *len],
+                oracle_public_key,
+                recipient_public_key,
+            };
+            let script = oracle_script.to_script();
+            assert!(script.is_oracle_script());
+            assert_eq!(OracleScript::from_script(&script), Some(oracle_script));
+        }
+
+        let mut raw_script = [0; 25];
+        raw_script[0] = OP_DUP;
+        assert!(!Script(raw_script.to_vec()).is_oracle_script());
+    }
+
+    #[test]
+    fn oracle_signature() {
+        let secp = Secp256k1::new();
+        let (oracle_private_key, oracle_public_key) = keys(1);
+        let signature = sign_message(&secp, b"outcome", &oracle_private_key);
+        assert_eq!(
+            verify_message(&secp, b"outcome", &signature, &oracle_public_key),
+            Ok(())
+        );
+        assert!(verify_message(&secp, b"other", &signature, &oracle_public_key).is_err());
+
+        let script_sig = OracleScript::script_sig(&[0x30, 0x01], &signature);
+        let (recipient_signature, rest) = split_push(script_sig.as_bytes()).unwrap();
+        assert_eq!(recipient_signature, &[0x30, 0x01]);
+        let (oracle_signature, rest) = split_push(rest).unwrap();
+        assert_eq!(Signature::from_der(oracle_signature), Ok(signature));
+        assert!(rest.is_empty());
+    }
+}
@@ -1,12 +1,44 @@
 //! This module contains the [`Script`] struct which represents a Bitcoin transaction script.
 //! It enjoys [`Encodable`], and provides some utility methods.
 
+pub mod checkdatasig;
+pub mod num;
 pub mod opcodes;
 
+use std::convert::TryInto;
+
 use crate::{var_int::VarInt, Encodable};
 
 use bytes::BufMut;
 
+#[doc(inline)]
+pub use num::{ScriptNum, ScriptNumError};
+
+/// Split the leading data push from the raw script, returning the data and the remainder.
+pub(crate) fn split_push(raw: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (opcode, rest) = raw.split_first()?;
+    let (len, rest) = match *opcode {
+        opcode if opcode < opcodes::OP_PUSHDATA1 => (opcode as usize, rest),
+        opcodes::OP_PUSHDATA1 => {
+            let (len, rest) = rest.split_first()?;
+            (*len as usize, rest)
+        }
+        opcodes::OP_PUSHDATA2 if rest.len() >= 2 => (
+            u16::from_le_bytes(rest[..2].try_into().unwrap()) as usize, // This is safe
+            &rest[2..],
+        ),
+        opcodes::OP_PUSHDATA4 if rest.len() >= 4 => (
+            u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize, // This is safe
+            &rest[4..],
+        ),
+        _ => return None,
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
 /// Represents a script.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Script(pub Vec<u8>);
@@ -54,6 +86,75 @@ impl Script {
         &self.0
     }
 
+    /// Construct a script from a hex string.
+    #[inline]
+    pub fn from_hex_str(hex_str: &str) -> Result<Self, hex::FromHexError> {
+        hex::decode(hex_str).map(Script)
+    }
+
+    /// Encode the script as a hex string.
+    #[inline]
+    pub fn to_hex_string(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    /// Construct the pay-to-public-key-hash script paying to a public key hash.
+    pub fn p2pkh(pubkey_hash: &[u8; 20]) -> Self {
+        let mut raw_script = Vec::with_capacity(25);
+        raw_script.extend_from_slice(&[
+            opcodes::OP_DUP,
+            opcodes::OP_HASH160,
+            opcodes::OP_PUSHBYTES_20,
+        ]);
+        raw_script.extend_from_slice(pubkey_hash);
+        raw_script.extend_from_slice(&[opcodes::OP_EQUALVERIFY, opcodes::OP_CHECKSIG]);
+        Script(raw_script)
+    }
+
+    /// Construct the pay-to-script-hash script paying to a script hash.
+    pub fn p2sh(script_hash: &[u8; 20]) -> Self {
+        let mut raw_script = Vec::with_capacity(23);
+        raw_script.extend_from_slice(&[opcodes::OP_HASH160, opcodes::OP_PUSHBYTES_20]);
+        raw_script.extend_from_slice(script_hash);
+        raw_script.push(opcodes::OP_EQUAL);
+        Script(raw_script)
+    }
+
+    /// Append an opcode to the script.
+    #[inline]
+    pub fn push_opcode(&mut self, opcode: u8) {
+        self.0.push(opcode);
+    }
+
+    /// Append a push of the data to the script, using the smallest push opcode.
+    pub fn push_slice(&mut self, data: &[u8]) {
+        let len = data.len();
+        if len < opcodes::OP_PUSHDATA1 as usize {
+            self.0.push(len as u8);
+        } else if len <= 0xff {
+            self.0.push(opcodes::OP_PUSHDATA1);
+            self.0.push(len as u8);
+        } else if len <= 0xffff {
+            self.0.push(opcodes::OP_PUSHDATA2);
+            self.0.extend_from_slice(&(len as u16).to_le_bytes());
+        } else {
+            self.0.push(opcodes::OP_PUSHDATA4);
+            self.0.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.0.extend_from_slice(data);
+    }
+
+    /// Append a push of the script number to the script, using the small integer opcodes where
+    /// possible.
+    pub fn push_num(&mut self, num: ScriptNum) {
+        match num.0 {
+            0 => self.0.push(opcodes::OP_0),
+            -1 => self.0.push(opcodes::OP_1NEGATE),
+            1..=16 => self.0.push(opcodes::OP_1 + num.0 as u8 - 1),
+            _ => self.push_slice(&num.to_bytes()),
+        }
+    }
+
     /// Checks whether the script fits the OP_RETURN pattern.
     #[inline]
     pub fn is_op_return(&self) -> bool {
@@ -70,6 +171,21 @@ impl Script {
             && self.0[23] == opcodes::OP_EQUALVERIFY
             && self.0[24] == opcodes::OP_CHECKSIG
     }
+
+    /// Checks whether the script fits the P2SH pattern.
+    #[inline]
+    pub fn is_p2sh(&self) -> bool {
+        self.0.len() == 23
+            && self.0[0] == opcodes::OP_HASH160
+            && self.0[1] == opcodes::OP_PUSHBYTES_20
+            && self.0[22] == opcodes::OP_EQUAL
+    }
+
+    /// Checks whether the script fits the P2WPKH pattern.
+    #[inline]
+    pub fn is_p2wpkh(&self) -> bool {
+        self.0.len() == 22 && self.0[0] == opcodes::OP_0 && self.0[1] == opcodes::OP_PUSHBYTES_20
+    }
 }
 
 impl Encodable for Script {
@@ -0,0 +1,169 @@
+//! This module contains the [`ScriptNum`] struct which represents an integer operand within a
+//! Bitcoin script.
+//!
+//! Script numbers are encoded little-endian in sign-magnitude form, with the most significant bit
+//! of the last byte as the sign. Zero is encoded as the empty byte string and a minimal encoding
+//! contains no unnecessary trailing bytes.
+
+use thiserror::Error;
+
+/// The default maximum length of a script number operand.
+pub const DEFAULT_MAX_NUM_LEN: usize = 4;
+
+/// The maximum length of a script number operand used by locktime opcodes.
+pub const LOCKTIME_MAX_NUM_LEN: usize = 5;
+
+/// Error associated with decoding a [`ScriptNum`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ScriptNumError {
+    /// The operand exceeded the maximum length.
+    #[error("script number overflow")]
+    Overflow,
+    /// The operand was not minimally encoded.
+    #[error("non-minimally encoded script number")]
+    NonMinimal,
+}
+
+/// Represents an integer operand within a script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScriptNum(pub i64);
+
+impl From<i64> for ScriptNum {
+    fn from(value: i64) -> Self {
+        ScriptNum(value)
+    }
+}
+
+impl Into<i64> for ScriptNum {
+    fn into(self) -> i64 {
+        self.0
+    }
+}
+
+impl ScriptNum {
+    /// Encode the script number minimally.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.0 == 0 {
+            return Vec::new();
+        }
+
+        let negative = self.0 < 0;
+        let mut magnitude = if negative {
+            (self.0 as u64).wrapping_neg()
+        } else {
+            self.0 as u64
+        };
+        let mut raw = Vec::with_capacity(9);
+        while magnitude > 0 {
+            raw.push(magnitude as u8);
+            magnitude >>= 8;
+        }
+
+        // Add the sign bit, using an extra byte if the most significant bit is occupied
+        let last = raw.last_mut().unwrap(); // This is safe
+        if *last & 0x80 != 0 {
+            raw.push(if negative { 0x80 } else { 0x00 });
+        } else if negative {
+            *last |= 0x80;
+        }
+        raw
+    }
+
+    /// Check whether the operand is minimally encoded.
+    pub fn is_minimally_encoded(raw: &[u8]) -> bool {
+        match raw {
+            [] => true,
+            [.., last] if *last & 0x7f != 0 => true,
+            [_] => false,
+            [.., second_last, _] => *second_last & 0x80 != 0,
+        }
+    }
+
+    /// Decode a script number operand, rejecting operands longer than `max_len` bytes and, if
+    /// `require_minimal` is set, operands which are not minimally encoded.
+    ///
+    /// Operands longer than 8 bytes are always rejected.
+    pub fn decode(
+        raw: &[u8],
+        require_minimal: bool,
+        max_len: usize,
+    ) -> Result<Self, ScriptNumError> {
+        if raw.len() > max_len || raw.len() > 8 {
+            return Err(ScriptNumError::Overflow);
+        }
+        if require_minimal && !Self::is_minimally_encoded(raw) {
+            return Err(ScriptNumError::NonMinimal);
+        }
+
+        let last = match raw.last() {
+            Some(some) => *some,
+            None => return Ok(ScriptNum(0)),
+        };
+        let value = raw
+            .iter()
+            .rev()
+            .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+
+        // Remove the sign bit
+        if last & 0x80 != 0 {
+            let magnitude = value & !(0x80 << (8 * (raw.len() - 1)));
+            Ok(ScriptNum((magnitude as i64).wrapping_neg()))
+        } else {
+            Ok(ScriptNum(value as i64))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cases: &[(i64, &[u8])] = &[
+            (0, &[]),
+            (1, &[0x01]),
+            (-1, &[0x81]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x00]),
+            (-128, &[0x80, 0x80]),
+            (255, &[0xff, 0x00]),
+            (256, &[0x00, 0x01]),
+            (-256, &[0x00, 0x81]),
+            (0x7fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+            (-0x7fff_ffff, &[0xff, 0xff, 0xff, 0xff]),
+            (0x8000_0000, &[0x00, 0x00, 0x00, 0x80, 0x00]),
+        ];
+        for (value, raw) in cases {
+            assert_eq!(ScriptNum(*value).to_bytes(), *raw);
+            assert_eq!(
+                ScriptNum::decode(raw, true, LOCKTIME_MAX_NUM_LEN),
+                Ok(ScriptNum(*value))
+            );
+        }
+    }
+
+    #[test]
+    fn non_minimal() {
+        assert_eq!(
+            ScriptNum::decode(&[0x00], true, DEFAULT_MAX_NUM_LEN),
+            Err(ScriptNumError::NonMinimal)
+        );
+        assert_eq!(
+            ScriptNum::decode(&[0x01, 0x00], true, DEFAULT_MAX_NUM_LEN),
+            Err(ScriptNumError::NonMinimal)
+        );
+        assert_eq!(
+            ScriptNum::decode(&[0x01, 0x80], false, DEFAULT_MAX_NUM_LEN),
+            Ok(ScriptNum(-1))
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            ScriptNum::decode(&[0x00, 0x00, 0x00, 0x80, 0x00], true, DEFAULT_MAX_NUM_LEN),
+            Err(ScriptNumError::Overflow)
+        );
+    }
+}
@@ -12,8 +12,44 @@ pub const OP_HASH160: u8 = 0xa9;
 /// OP_PUSHBYTES_20
 pub const OP_PUSHBYTES_20: u8 = 0x14;
 
+/// OP_PUSHBYTES_32
+pub const OP_PUSHBYTES_32: u8 = 0x20;
+
+/// OP_EQUAL
+pub const OP_EQUAL: u8 = 0x87;
+
 /// OP_EQUALVERIFY
 pub const OP_EQUALVERIFY: u8 = 0x88;
 
 /// OP_CHECKSIG
 pub const OP_CHECKSIG: u8 = 0xac;
+
+/// OP_0
+pub const OP_0: u8 = 0x00;
+
+/// OP_PUSHBYTES_33
+pub const OP_PUSHBYTES_33: u8 = 0x21;
+
+/// OP_PUSHDATA1
+pub const OP_PUSHDATA1: u8 = 0x4c;
+
+/// OP_PUSHDATA2
+pub const OP_PUSHDATA2: u8 = 0x4d;
+
+/// OP_PUSHDATA4
+pub const OP_PUSHDATA4: u8 = 0x4e;
+
+/// OP_1NEGATE
+pub const OP_1NEGATE: u8 = 0x4f;
+
+/// OP_1
+pub const OP_1: u8 = 0x51;
+
+/// OP_16
+pub const OP_16: u8 = 0x60;
+
+/// OP_CHECKDATASIG
+pub const OP_CHECKDATASIG: u8 = 0xba;
+
+/// OP_CHECKDATASIGVERIFY
+pub const OP_CHECKDATASIGVERIFY: u8 = 0xbb;
@@ -0,0 +1,343 @@
+//! This module contains the [`WitnessTransaction`] struct which represents a [`Transaction`] along
+//! with the segregated witnesses of its inputs. It enjoys [`Encodable`] and [`Decodable`].
+//!
+//! Bitcoin Cash has no segregated witness, hence [`Transaction`] is always serialized without
+//! witness data. Transactions from chains with segregated witness are decoded by explicitly opting
+//! into the [`WitnessTransaction`], which reads the marker, flag and the witnesses following the
+//! outputs.
+//!
+//! The marker is indistinguishable from the input count of a transaction without inputs, so such
+//! transactions should be decoded as a [`Transaction`].
+
+use bytes::{Buf, BufMut};
+use thiserror::Error;
+
+use super::{
+    transaction_id, transaction_id_le, DecodeError as TransactionDecodeError, Transaction,
+};
+use crate::{
+    var_int::{DecodeError as VarIntDecodeError, VarInt},
+    Decodable, Encodable,
+};
+
+/// The segregated witness marker, replacing the input count of a serialized transaction.
+const WITNESS_MARKER: u8 = 0x00;
+
+/// The segregated witness flag, following the marker, indicating the presence of witnesses.
+const WITNESS_FLAG: u8 = 0x01;
+
+/// The witness of an input, a list of stack items.
+pub type Witness = Vec<Vec<u8>>;
+
+/// Error associated with [`WitnessTransaction`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    /// Failed to decode the transaction.
+    #[error("transaction: {0}")]
+    Transaction(TransactionDecodeError),
+    /// The segregated witness flag is unsupported.
+    #[error("unsupported witness flag: {0}")]
+    UnsupportedFlag(u8),
+    /// Failed to decode witness item count [`VarInt`].
+    #[error("witness item count: {0}")]
+    ItemCount(VarIntDecodeError),
+    /// Failed to decode witness item length [`VarInt`].
+    #[error("witness item length: {0}")]
+    ItemLen(VarIntDecodeError),
+    /// Exhausted buffer when decoding a witness item.
+    #[error("witness item too short")]
+    ItemTooShort,
+    /// The segregated witness flag is set, but no input carries witness data.
+    #[error("superfluous witness")]
+    SuperfluousWitness,
+}
+
+/// Represents a transaction along with the witnesses of its inputs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WitnessTransaction {
+    /// The transaction, excluding witness data.
+    pub transaction: Transaction,
+    /// The witnesses, indexed by input. Missing witnesses are empty.
+    pub witnesses: Vec<Witness>,
+}
+
+/// Returns the encoded length of a witness.
+#[inline]
+fn witness_encoded_len(witness: &[Vec<u8>]) -> usize {
+    let items_len: usize = witness
+        .iter()
+        .map(|item| VarInt(item.len() as u64).encoded_len() + item.len())
+        .sum();
+    VarInt(witness.len() as u64).encoded_len() + items_len
+}
+
+/// Decodes a witness from a buffer.
+fn decode_witness<B: Buf>(mut buf: &mut B) -> Result<Witness, DecodeError> {
+    let n_items: u64 = VarInt::decode(&mut buf)
+        .map_err(DecodeError::ItemCount)?
+        .into();
+    let mut witness = Vec::with_capacity((n_items as usize).min(buf.remaining()));
+    for _ in 0..n_items {
+        let item_len: u64 = VarInt::decode(&mut buf)
+            .map_err(DecodeError::ItemLen)?
+            .into();
+        let item_len = item_len as usize;
+        if buf.remaining() < item_len {
+            return Err(DecodeError::ItemTooShort);
+        }
+        let mut item = vec![0; item_len];
+        buf.copy_to_slice(&mut item);
+        witness.push(item);
+    }
+    Ok(witness)
+}
+
+impl From<Transaction> for WitnessTransaction {
+    fn from(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            witnesses: Vec::new(),
+        }
+    }
+}
+
+impl WitnessTransaction {
+    /// Check whether any input carries witness data.
+    #[inline]
+    pub fn has_witness(&self) -> bool {
+        self.witnesses
+            .iter()
+            .take(self.transaction.inputs.len())
+            .any(|witness| !witness.is_empty())
+    }
+
+    /// Get the witness of an input, empty if the input has no witness.
+    #[inline]
+    pub fn witness(&self, input_index: usize) -> &[Vec<u8>] {
+        self.witnesses
+            .get(input_index)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// Calculate the transaction ID in little-endian format. This is the double SHA256 digest of
+    /// the raw transaction, excluding witness data.
+    #[inline]
+    pub fn transaction_id_le(&self) -> [u8; 32] {
+        self.transaction.transaction_id_le()
+    }
+
+    /// Calculate the transaction ID. This is the double SHA256 digest of the raw transaction,
+    /// excluding witness data, in big-endian encoding.
+    #[inline]
+    pub fn transaction_id(&self) -> [u8; 32] {
+        self.transaction.transaction_id()
+    }
+
+    /// Calculate the witness transaction ID, the `wtxid`, in little-endian format. This is the
+    /// double SHA256 digest of the raw transaction, including witness data.
+    ///
+    /// This equals the transaction ID when no input carries witness data.
+    #[inline]
+    pub fn witness_transaction_id_le(&self) -> [u8; 32] {
+        transaction_id_le(&self.encode_to_vec())
+    }
+
+    /// Calculate the witness transaction ID, the `wtxid`. This is the double SHA256 digest of the
+    /// raw transaction, including witness data, in big-endian encoding.
+    #[inline]
+    pub fn witness_transaction_id(&self) -> [u8; 32] {
+        transaction_id(&self.encode_to_vec())
+    }
+}
+
+impl Encodable for WitnessTransaction {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        let base_length = self.transaction.encoded_len();
+        if self.has_witness() {
+            let witness_total_length: usize = (0..self.transaction.inputs.len())
+                .map(|input_index| witness_encoded_len(self.witness(input_index)))
+                .sum();
+            base_length + 2 + witness_total_length
+        } else {
+            base_length
+        }
+    }
+
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        if !self.has_witness() {
+            self.transaction.encode_raw(buf);
+            return;
+        }
+
+        let transaction = &self.transaction;
+        buf.put_u32_le(transaction.version);
+        buf.put_u8(WITNESS_MARKER);
+        buf.put_u8(WITNESS_FLAG);
+        transaction.input_count_varint().encode_raw(buf);
+        for input in &transaction.inputs {
+            input.encode_raw(buf);
+        }
+        transaction.output_count_varint().encode_raw(buf);
+        for output in &transaction.outputs {
+            output.encode_raw(buf);
+        }
+        for input_index in 0..transaction.inputs.len() {
+            let witness = self.witness(input_index);
+            VarInt(witness.len() as u64).encode_raw(buf);
+            for item in witness {
+                VarInt(item.len() as u64).encode_raw(buf);
+                buf.put_slice(item);
+            }
+        }
+        buf.put_u32_le(transaction.lock_time);
+    }
+}
+
+impl Decodable for WitnessTransaction {
+    type Error = DecodeError;
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        // Parse version
+        if buf.remaining() < 4 {
+            return Err(TransactionDecodeError::VersionTooShort.into());
+        }
+        let version = buf.get_u32_le();
+
+        // Parse marker and flag, the marker is followed by a non-zero flag
+        let witness = matches!(buf.bytes(), [WITNESS_MARKER, flag, ..] if *flag != 0);
+        if witness {
+            buf.advance(1);
+            let flag = buf.get_u8();
+            if flag != WITNESS_FLAG {
+                return Err(Self::Error::UnsupportedFlag(flag));
+            }
+        }
+
+        // Parse inputs and outputs
+        let (inputs, outputs) = Transaction::decode_inputs_outputs(buf)?;
+
+        // Parse witnesses
+        let witnesses = if witness {
+            let witnesses = (0..inputs.len())
+                .map(|_| decode_witness(buf))
+                .collect::<Result<Vec<Witness>, _>>()?;
+            if witnesses.iter().all(Vec::is_empty) {
+                return Err(Self::Error::SuperfluousWitness);
+            }
+            witnesses
+        } else {
+            Vec::new()
+        };
+
+        // Parse lock time
+        if buf.remaining() < 4 {
+            return Err(TransactionDecodeError::LockTimeTooShort.into());
+        }
+        let lock_time = buf.get_u32_le();
+        Ok(WitnessTransaction {
+            transaction: Transaction {
+                version,
+                inputs,
+                outputs,
+                lock_time,
+            },
+            witnesses,
+        })
+    }
+}
+
+impl From<TransactionDecodeError> for DecodeError {
+    fn from(err: TransactionDecodeError) -> Self {
+        Self::Transaction(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Input, Output, Script};
+
+    fn transaction(n_inputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![Input::default(); n_inputs],
+            outputs: vec![Output {
+                value: 3000,
+                script: Script::p2pkh(&[1; 20]),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let witness_tx = WitnessTransaction {
+            transaction: transaction(2),
+            witnesses: vec![vec![], vec![vec![0x30; 71], vec![0x02; 33]]],
+        };
+        let raw_tx = witness_tx.encode_to_vec();
+        assert_eq!(raw_tx.len(), witness_tx.encoded_len());
+        assert_eq!(&raw_tx[4..6], &[WITNESS_MARKER, WITNESS_FLAG]);
+        assert_eq!(
+            WitnessTransaction::decode(&mut raw_tx.as_slice()).unwrap(),
+            witness_tx
+        );
+
+        // The transaction ID excludes the witnesses
+        assert_eq!(
+            witness_tx.transaction_id(),
+            witness_tx.transaction.transaction_id()
+        );
+        assert_ne!(
+            witness_tx.witness_transaction_id(),
+            witness_tx.transaction_id()
+        );
+    }
+
+    #[test]
+    fn without_witness() {
+        let witness_tx = WitnessTransaction::from(transaction(1));
+        let raw_tx = witness_tx.encode_to_vec();
+        assert_eq!(raw_tx, witness_tx.transaction.encode_to_vec());
+        assert_eq!(
+            witness_tx.witness_transaction_id(),
+            witness_tx.transaction_id()
+        );
+        assert_eq!(
+            WitnessTransaction::decode(&mut raw_tx.as_slice()).unwrap(),
+            witness_tx
+        );
+    }
+
+    #[test]
+    fn invalid_witness() {
+        let transaction = transaction(1);
+        let raw_legacy_tx = transaction.encode_to_vec();
+        let with_flag = |flag: u8, witness: &[u8]| {
+            let mut raw_tx = raw_legacy_tx[..4].to_vec();
+            raw_tx.extend_from_slice(&[WITNESS_MARKER, flag]);
+            raw_tx.extend_from_slice(&raw_legacy_tx[4..raw_legacy_tx.len() - 4]);
+            raw_tx.extend_from_slice(witness);
+            raw_tx.extend_from_slice(&transaction.lock_time.to_le_bytes());
+            raw_tx
+        };
+
+        let raw_tx = with_flag(WITNESS_FLAG, &[0]);
+        assert_eq!(
+            WitnessTransaction::decode(&mut raw_tx.as_slice()),
+            Err(DecodeError::SuperfluousWitness)
+        );
+        let raw_tx = with_flag(2, &[1, 0]);
+        assert_eq!(
+            WitnessTransaction::decode(&mut raw_tx.as_slice()),
+            Err(DecodeError::UnsupportedFlag(2))
+        );
+        let raw_tx = with_flag(WITNESS_FLAG, &[1, 0x10]);
+        assert_eq!(
+            WitnessTransaction::decode(&mut raw_tx.as_slice()),
+            Err(DecodeError::ItemTooShort)
+        );
+    }
+}
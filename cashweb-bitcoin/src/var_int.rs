@@ -111,6 +111,8 @@ impl Decodable for VarInt {
 
 #[cfg(test)]
 mod tests {
+    use bytes::BytesMut;
+
     use super::*;
 
     #[test]
@@ -146,4 +148,16 @@ mod tests {
         var_int.encode_raw(&mut raw);
         assert_eq!(raw, vec![0xffu8, 0xe0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0, 0]);
     }
+
+    #[test]
+    fn encode_bytes() {
+        let var_int = VarInt(0xfff);
+        assert_eq!(&var_int.encode_to_bytes()[..], &[0xfdu8, 0xff, 0xf][..]);
+        assert_eq!(var_int.encode_to_vec(), vec![0xfdu8, 0xff, 0xf]);
+
+        let mut buf = BytesMut::new();
+        VarInt(10).encode_into(&mut buf);
+        var_int.encode_into(&mut buf);
+        assert_eq!(&buf[..], &[10u8, 0xfd, 0xff, 0xf][..]);
+    }
 }
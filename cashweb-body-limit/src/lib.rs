@@ -0,0 +1,111 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-body-limit` is a library providing [`read_body_limited`], which aggregates an HTTP body
+//! while enforcing a maximum size.
+//!
+//! Clients use it in place of [`hyper::body::to_bytes`] so that a malicious, or faulty, server
+//! cannot cause unbounded memory growth by streaming an endless body.
+//!
+//! Responses with unexpected status codes are described by a [`StatusError`], which captures a
+//! bounded excerpt of the body.
+
+pub mod status;
+
+use std::fmt;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use thiserror::Error;
+
+pub use status::{StatusError, DEFAULT_EXCERPT_SIZE};
+
+/// Error associated with reading a size-bounded body.
+#[derive(Debug, Error)]
+pub enum BodyError<E: fmt::Debug + fmt::Display> {
+    /// Error while streaming the body.
+    #[error("body stream failure: {0}")]
+    Stream(E),
+    /// The body exceeds the maximum size.
+    #[error("body exceeds {0} bytes")]
+    TooLarge(usize),
+}
+
+/// Aggregate a body into [`Bytes`], failing once more than `max_bytes` have been received.
+///
+/// Bodies whose size hint, for example given by the `Content-Length`, exceeds `max_bytes` are
+/// rejected before any data is read.
+pub async fn read_body_limited<B>(
+    mut body: B,
+    max_bytes: usize,
+) -> Result<Bytes, BodyError<B::Error>>
+where
+    B: HttpBody + Unpin,
+    B::Error: fmt::Debug + fmt::Display,
+{
+    if body.size_hint().lower() > max_bytes as u64 {
+        return Err(BodyError::TooLarge(max_bytes));
+    }
+
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Stream)?;
+        if buf.len() + chunk.remaining() > max_bytes {
+            return Err(BodyError::TooLarge(max_bytes));
+        }
+        buf.put(chunk);
+    }
+    Ok(buf.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, future::Future};
+
+    use futures_util::stream::iter;
+    use hyper::Body;
+
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// A body streaming the chunks without a size hint.
+    fn streamed(chunks: &[&'static str]) -> Body {
+        let chunks: Vec<Result<_, Infallible>> = chunks.iter().map(|chunk| Ok(*chunk)).collect();
+        Body::wrap_stream(iter(chunks))
+    }
+
+    #[test]
+    fn within_limit() {
+        let body = block_on(read_body_limited(Body::from("hello"), 5)).unwrap();
+        assert_eq!(body, Bytes::from("hello"));
+
+        let body = block_on(read_body_limited(streamed(&["abc", "def"]), 6)).unwrap();
+        assert_eq!(body, Bytes::from("abcdef"));
+
+        let body = block_on(read_body_limited(Body::empty(), 0)).unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn size_hint_exceeded() {
+        let err = block_on(read_body_limited(Body::from("hello"), 4)).unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(4)));
+    }
+
+    #[test]
+    fn stream_exceeded() {
+        let err = block_on(read_body_limited(streamed(&["abc", "def"]), 5)).unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(5)));
+    }
+}
@@ -0,0 +1,215 @@
+//! This module contains the [`StatusError`] describing a response with an unexpected status code.
+//!
+//! The statuses which callers commonly act upon are given dedicated variants. For example, a
+//! [`StatusError::PaymentRequired`] carries the payment request within its body, allowing the
+//! caller to pay and retry, while a [`StatusError::TooManyRequests`] carries the `Retry-After`
+//! delay. An excerpt of the body, bounded in size, is captured in every case.
+
+use std::time::Duration;
+
+use bytes::{buf::BufExt, Buf, BufMut, Bytes, BytesMut};
+use hyper::{body::HttpBody, header::RETRY_AFTER, Response, StatusCode};
+use thiserror::Error;
+
+/// The default maximum size, in bytes, of the body excerpt captured.
+pub const DEFAULT_EXCERPT_SIZE: usize = 16 * 1024;
+
+/// Error associated with a response with an unexpected status code.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum StatusError {
+    /// The request lacked valid authorization.
+    #[error("unauthorized")]
+    Unauthorized {
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+    /// Payment is required, the body typically contains a payment request.
+    #[error("payment required")]
+    PaymentRequired {
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+    /// The resource was not found.
+    #[error("not found")]
+    NotFound {
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+    /// The request body was too large.
+    #[error("payload too large")]
+    PayloadTooLarge {
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+    /// The request was rate limited.
+    #[error("too many requests")]
+    TooManyRequests {
+        /// The delay given by the `Retry-After` header, if given in seconds.
+        retry_after: Option<Duration>,
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+    /// Any other unexpected status code.
+    #[error("unexpected status code: {status}")]
+    Unexpected {
+        /// The status code.
+        status: u16,
+        /// An excerpt of the body.
+        body: Bytes,
+    },
+}
+
+impl StatusError {
+    /// Construct from a status code, the `Retry-After` delay and a body excerpt.
+    pub fn new(status: StatusCode, retry_after: Option<Duration>, body: Bytes) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized { body },
+            StatusCode::PAYMENT_REQUIRED => Self::PaymentRequired { body },
+            StatusCode::NOT_FOUND => Self::NotFound { body },
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge { body },
+            StatusCode::TOO_MANY_REQUESTS => Self::TooManyRequests { retry_after, body },
+            status => Self::Unexpected {
+                status: status.as_u16(),
+                body,
+            },
+        }
+    }
+
+    /// Construct from a response, capturing at most `max_excerpt` bytes of its body.
+    ///
+    /// Errors while reading the body truncate the excerpt.
+    pub async fn from_response<B>(response: Response<B>, max_excerpt: usize) -> Self
+    where
+        B: HttpBody + Unpin,
+    {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = read_excerpt(response.into_body(), max_excerpt).await;
+        Self::new(status, retry_after, body)
+    }
+
+    /// The status code.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Unauthorized { .. } => 401,
+            Self::PaymentRequired { .. } => 402,
+            Self::NotFound { .. } => 404,
+            Self::PayloadTooLarge { .. } => 413,
+            Self::TooManyRequests { .. } => 429,
+            Self::Unexpected { status, .. } => *status,
+        }
+    }
+
+    /// The body excerpt.
+    pub fn body(&self) -> &Bytes {
+        match self {
+            Self::Unauthorized { body }
+            | Self::PaymentRequired { body }
+            | Self::NotFound { body }
+            | Self::PayloadTooLarge { body }
+            | Self::TooManyRequests { body, .. }
+            | Self::Unexpected { body, .. } => body,
+        }
+    }
+
+    /// Check whether the request may succeed if retried unchanged, that is, after being rate
+    /// limited or after a server error.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::TooManyRequests { .. } => true,
+            Self::Unexpected { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// Read at most `max_bytes` of a body, stopping at the first error.
+async fn read_excerpt<B>(mut body: B, max_bytes: usize) -> Bytes
+where
+    B: HttpBody + Unpin,
+{
+    let mut buf = BytesMut::new();
+    while buf.len() < max_bytes {
+        let chunk = match body.data().await {
+            Some(Ok(chunk)) => chunk,
+            _ => break,
+        };
+        let len = chunk.remaining().min(max_bytes - buf.len());
+        buf.put(chunk.take(len));
+    }
+    buf.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, future::Future};
+
+    use futures_util::stream::iter;
+    use hyper::Body;
+
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn variants() {
+        let error = StatusError::new(StatusCode::PAYMENT_REQUIRED, None, Bytes::from("invoice"));
+        assert_eq!(error.status(), 402);
+        assert_eq!(error.body(), &Bytes::from("invoice"));
+        assert!(!error.is_retryable());
+
+        let error = StatusError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(3)),
+            Bytes::new(),
+        );
+        assert_eq!(
+            error,
+            StatusError::TooManyRequests {
+                retry_after: Some(Duration::from_secs(3)),
+                body: Bytes::new(),
+            }
+        );
+        assert!(error.is_retryable());
+
+        let error = StatusError::new(StatusCode::BAD_GATEWAY, None, Bytes::new());
+        assert_eq!(error.status(), 502);
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn truncated_excerpt() {
+        let chunks: Vec<Result<_, Infallible>> = vec![Ok("abc"), Ok("def"), Ok("ghi")];
+        let response = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, " 7 ")
+            .body(Body::wrap_stream(iter(chunks)))
+            .unwrap();
+        let error = block_on(StatusError::from_response(response, 5));
+        assert_eq!(
+            error,
+            StatusError::TooManyRequests {
+                retry_after: Some(Duration::from_secs(7)),
+                body: Bytes::from("abcde"),
+            }
+        );
+
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("missing"))
+            .unwrap();
+        let error = block_on(StatusError::from_response(response, DEFAULT_EXCERPT_SIZE));
+        assert_eq!(error.body(), &Bytes::from("missing"));
+    }
+}
@@ -0,0 +1,220 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-clock` is a library providing the [`Clock`] trait, an injectable source of the current
+//! time consulted by the time-dependent validation of the cash:web components, such as invoice
+//! expiry and token time buckets.
+//!
+//! The [`SystemClock`] reads the system time and is used by default. The [`OffsetClock`] corrects
+//! a clock known to be skewed, while the [`ManualClock`] is set explicitly, allowing deterministic
+//! tests.
+//!
+//! Signed timestamps, such as those of keyserver metadata and relay profiles, are checked against a
+//! maximum age using [`check_age`].
+//!
+//! When the `timer` feature is enabled, [`delay_for`] provides the timer shared by the cash:web
+//! clients. This is the tokio timer if the `tokio` feature is enabled, otherwise an
+//! executor-agnostic timer.
+
+#[cfg(feature = "timer")]
+mod timer;
+
+use std::{
+    convert::TryFrom,
+    error, fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "timer")]
+pub use timer::delay_for;
+
+/// A source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+
+    /// The current unix time in seconds, saturating at zero for times before the unix epoch.
+    fn unix_now(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// A [`Clock`] shared between components.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The [`SystemClock`] as a [`SharedClock`].
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A [`Clock`] reading the system time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A [`Clock`] offsetting an inner clock, correcting a known skew.
+#[derive(Clone, Debug)]
+pub struct OffsetClock<C> {
+    inner: C,
+    offset: Duration,
+    ahead: bool,
+}
+
+impl<C> OffsetClock<C> {
+    /// Create a new [`OffsetClock`] running `offset` ahead of the inner clock.
+    pub fn ahead(inner: C, offset: Duration) -> Self {
+        Self {
+            inner,
+            offset,
+            ahead: true,
+        }
+    }
+
+    /// Create a new [`OffsetClock`] running `offset` behind the inner clock.
+    pub fn behind(inner: C, offset: Duration) -> Self {
+        Self {
+            inner,
+            offset,
+            ahead: false,
+        }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> SystemTime {
+        let now = self.inner.now();
+        if self.ahead {
+            now + self.offset
+        } else {
+            now.checked_sub(self.offset).unwrap_or(UNIX_EPOCH)
+        }
+    }
+}
+
+/// A [`Clock`] which only changes when set or advanced.
+///
+/// Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl ManualClock {
+    /// Create a new [`ManualClock`] set to a time.
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Create a new [`ManualClock`] set to a unix time, given in seconds.
+    pub fn from_unix(secs: u64) -> Self {
+        Self::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Set the time.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Advance the time.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Error associated with a timestamp older than permitted, see [`check_age`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaleTimestamp {
+    /// The timestamp, in unix milliseconds.
+    pub timestamp: i64,
+    /// The oldest permitted time, in unix milliseconds.
+    pub oldest: i64,
+}
+
+impl fmt::Display for StaleTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stale timestamp: {} < {}", self.timestamp, self.oldest)
+    }
+}
+
+impl error::Error for StaleTimestamp {}
+
+/// Check a timestamp, in unix milliseconds, is no older than `max_age` at the time given by the
+/// [`Clock`].
+pub fn check_age<C: Clock + ?Sized>(
+    clock: &C,
+    timestamp: i64,
+    max_age: Duration,
+) -> Result<(), StaleTimestamp> {
+    let now = i64::try_from(clock.unix_now())
+        .unwrap_or(i64::MAX)
+        .saturating_mul(1000);
+    let max_age = i64::try_from(max_age.as_millis()).unwrap_or(i64::MAX);
+    let oldest = now.saturating_sub(max_age);
+    if timestamp < oldest {
+        return Err(StaleTimestamp { timestamp, oldest });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_and_offset() {
+        let clock = ManualClock::from_unix(100);
+        let shared: SharedClock = Arc::new(clock.clone());
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(shared.unix_now(), 105);
+
+        let offset = OffsetClock::behind(clock.clone(), Duration::from_secs(10));
+        assert_eq!(offset.unix_now(), 95);
+        let offset = OffsetClock::behind(clock, Duration::from_secs(200));
+        assert_eq!(offset.unix_now(), 0);
+    }
+
+    #[test]
+    fn timestamp_age() {
+        let clock = ManualClock::from_unix(100);
+        let max_age = Duration::from_secs(10);
+        assert_eq!(check_age(&clock, 90_000, max_age), Ok(()));
+        assert_eq!(check_age(&clock, 200_000, max_age), Ok(()));
+        assert_eq!(
+            check_age(&clock, 89_999, max_age),
+            Err(StaleTimestamp {
+                timestamp: 89_999,
+                oldest: 90_000
+            })
+        );
+
+        // The maximum age saturates
+        assert_eq!(check_age(&clock, 0, Duration::from_secs(u64::MAX)), Ok(()));
+    }
+}
@@ -0,0 +1,40 @@
+//! This module contains the timer shared by the cash:web clients, selected by the enabled features.
+//!
+//! The tokio timer is used when the `tokio` feature is enabled. Otherwise the executor-agnostic
+//! [`futures-timer`] is used, allowing the libraries to be embedded in applications driven by
+//! other runtimes, such as `async-std` or `smol`.
+//!
+//! [`futures-timer`]: https://docs.rs/futures-timer
+
+use std::time::Duration;
+
+/// Wait until the `duration` has elapsed.
+#[cfg(feature = "tokio")]
+pub async fn delay_for(duration: Duration) {
+    tokio::time::delay_for(duration).await
+}
+
+/// Wait until the `duration` has elapsed.
+#[cfg(not(feature = "tokio"))]
+pub async fn delay_for(duration: Duration) {
+    futures_timer::Delay::new(duration).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let start = Instant::now();
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(delay_for(Duration::from_millis(10)));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+}
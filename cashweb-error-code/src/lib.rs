@@ -0,0 +1,118 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-error-code` is a library providing the [`ErrorCode`] trait, implemented by the major
+//! error enums of the cash:web components, allowing non-Rust consumers and HTTP APIs to map
+//! failures without matching on error messages.
+//!
+//! Each error enum is assigned a disjoint block of codes in [`CODE_RANGES`], so that a code
+//! identifies both the enum and the variant. Variants wrapping another error enum take the code and
+//! label of the wrapped error. Codes, once assigned, are never reused for another variant.
+
+/// A block of codes reserved for a single error enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeRange {
+    /// The first code of the block.
+    pub first: u16,
+    /// The last code of the block.
+    pub last: u16,
+    /// The path of the error enum.
+    pub error: &'static str,
+}
+
+impl CodeRange {
+    /// Whether the code lies within the block.
+    pub fn contains(&self, code: u16) -> bool {
+        self.first <= code && code <= self.last
+    }
+}
+
+const fn range(first: u16, last: u16, error: &'static str) -> CodeRange {
+    CodeRange { first, last, error }
+}
+
+/// The blocks of codes reserved for each error enum, in ascending order.
+pub const CODE_RANGES: &[CodeRange] = &[
+    range(1001, 1009, "cashweb_relay::DigestError"),
+    range(1010, 1099, "cashweb_relay::ParseError"),
+    range(1101, 1120, "cashweb_relay::stamp::StampError"),
+    range(1121, 1199, "cashweb_relay::stamp::StampBuildError"),
+    range(1201, 1299, "cashweb_relay::OpenError"),
+    range(1301, 1399, "cashweb_relay::group::GroupError"),
+    range(1401, 1499, "cashweb_relay::entry::EntryError"),
+    range(1501, 1599, "cashweb_relay::seal::SealError"),
+    range(1601, 1699, "cashweb_relay::bundle::BundleError"),
+    range(
+        1701,
+        1799,
+        "cashweb_relay::received_time::ReceivedTimeViolation",
+    ),
+    range(
+        1801,
+        1899,
+        "cashweb_relay::payload_store::PayloadStoreError",
+    ),
+    range(
+        2001,
+        2099,
+        "cashweb_token::schemes::hmac_bearer::ValidationError",
+    ),
+    range(
+        2101,
+        2199,
+        "cashweb_token::schemes::chain_commitment::ValidationError",
+    ),
+    range(
+        2201,
+        2299,
+        "cashweb_token::schemes::pubkey_bound::ValidationError",
+    ),
+    range(3001, 3099, "cashweb_bitcoin_client::NodeError"),
+    range(4001, 4099, "cashweb_auth_wrapper::ParseError"),
+    range(4101, 4199, "cashweb_auth_wrapper::VerifyError"),
+];
+
+/// Find the error enum to which the code is reserved.
+pub fn reserved_by(code: u16) -> Option<&'static str> {
+    CODE_RANGES
+        .iter()
+        .find(|range| range.contains(code))
+        .map(|range| range.error)
+}
+
+/// An error identified by a stable code and label.
+///
+/// The code lies within the block reserved for the error in [`CODE_RANGES`].
+pub trait ErrorCode {
+    /// A stable numeric code identifying the error, allowing non-Rust consumers to map failures.
+    fn code(&self) -> u16;
+
+    /// A short, static label identifying the error.
+    fn label(&self) -> &'static str;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_ranges() {
+        for range in CODE_RANGES {
+            assert!(range.first <= range.last, "{:?}", range);
+        }
+        for pair in CODE_RANGES.windows(2) {
+            assert!(pair[0].last < pair[1].first, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn reserved_codes() {
+        assert_eq!(reserved_by(1102), Some("cashweb_relay::stamp::StampError"));
+        assert_eq!(reserved_by(3002), Some("cashweb_bitcoin_client::NodeError"));
+        assert_eq!(reserved_by(1000), None);
+    }
+}
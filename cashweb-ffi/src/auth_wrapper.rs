@@ -0,0 +1,52 @@
+//! This module contains the C ABI for verifying an [`AuthWrapper`].
+
+use auth_wrapper::{AuthWrapper, ErrorCode};
+use prost::Message as _;
+
+use crate::{slice, status, write_bytes, CashwebBuffer, CASHWEB_DECODE};
+
+unsafe fn auth_wrapper_verify(
+    raw: *const u8,
+    raw_len: usize,
+    public_key_out: *mut u8,
+    payload_out: *mut CashwebBuffer,
+) -> Result<(), u32> {
+    let auth_wrapper = AuthWrapper::decode(slice(raw, raw_len)?).map_err(|_| CASHWEB_DECODE)?;
+    let parsed = auth_wrapper.parse().map_err(|err| u32::from(err.code()))?;
+    parsed.verify().map_err(|err| u32::from(err.code()))?;
+
+    if !public_key_out.is_null() {
+        write_bytes(public_key_out, &parsed.public_key.serialize())?;
+    }
+    if !payload_out.is_null() {
+        CashwebBuffer::write(payload_out, parsed.payload)?;
+    }
+    Ok(())
+}
+
+/// Decode, parse and verify the signature of a serialized [`AuthWrapper`].
+///
+/// On success the 33 byte signer public key is written to `public_key_out` and the payload is
+/// written to `payload_out`, either may be null if not required.
+///
+/// # Safety
+///
+/// `raw` must point to `raw_len` readable bytes, `public_key_out` must be null or point to 33
+/// writable bytes, and `payload_out` must be null or writable. The buffer must be released using
+/// [`cashweb_buffer_free`].
+///
+/// [`cashweb_buffer_free`]: crate::cashweb_buffer_free
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_auth_wrapper_verify(
+    raw: *const u8,
+    raw_len: usize,
+    public_key_out: *mut u8,
+    payload_out: *mut CashwebBuffer,
+) -> u32 {
+    status(auth_wrapper_verify(
+        raw,
+        raw_len,
+        public_key_out,
+        payload_out,
+    ))
+}
@@ -0,0 +1,66 @@
+//! This module contains the [`CashwebBuffer`] struct, used to return owned bytes across the C ABI.
+
+use crate::{CASHWEB_NULL_POINTER, CASHWEB_OK};
+
+/// Bytes owned by the library, these must be released using [`cashweb_buffer_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct CashwebBuffer {
+    /// Pointer to the start of the bytes.
+    pub data: *mut u8,
+    /// The number of bytes.
+    pub len: usize,
+}
+
+impl CashwebBuffer {
+    /// Take ownership of a vector of bytes.
+    pub(crate) fn from_vec(vec: Vec<u8>) -> Self {
+        let mut boxed = vec.into_boxed_slice();
+        let buffer = CashwebBuffer {
+            data: boxed.as_mut_ptr(),
+            len: boxed.len(),
+        };
+        std::mem::forget(boxed);
+        buffer
+    }
+
+    /// Write a vector of bytes to an output buffer.
+    pub(crate) unsafe fn write(out: *mut CashwebBuffer, vec: Vec<u8>) -> Result<(), u32> {
+        if out.is_null() {
+            return Err(CASHWEB_NULL_POINTER);
+        }
+        *out = Self::from_vec(vec);
+        Ok(())
+    }
+}
+
+/// Release a [`CashwebBuffer`] returned by the library.
+///
+/// # Safety
+///
+/// The buffer must have been returned by the library and not previously released.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_buffer_free(buffer: CashwebBuffer) -> u32 {
+    if buffer.data.is_null() {
+        return CASHWEB_NULL_POINTER;
+    }
+    let slice = std::slice::from_raw_parts_mut(buffer.data, buffer.len);
+    drop(Box::from_raw(slice));
+    CASHWEB_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let buffer = CashwebBuffer::from_vec(vec![1, 2, 3]);
+        assert_eq!(buffer.len, 3);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) },
+            &[1, 2, 3]
+        );
+        assert_eq!(unsafe { cashweb_buffer_free(buffer) }, CASHWEB_OK);
+    }
+}
@@ -0,0 +1,78 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-ffi` is a library exposing the core cash:web flows over a C ABI, allowing mobile and
+//! other non-Rust applications to embed the protocol logic.
+//!
+//! Structures are passed across the boundary in their protobuf serialization, while parsed and
+//! sealed messages are held behind opaque handles which must be released by their `_free`
+//! functions. Bytes returned to the caller are given as a [`CashwebBuffer`] and must be released
+//! using [`cashweb_buffer_free`].
+//!
+//! Every fallible function returns a status code, [`CASHWEB_OK`] on success. Otherwise the code
+//! is the stable [`ErrorCode::code`] of the underlying error, or one of the boundary codes defined
+//! in this crate.
+//!
+//! [`ErrorCode::code`]: relay::ErrorCode::code
+
+pub mod auth_wrapper;
+pub mod buffer;
+pub mod message;
+pub mod stamp;
+
+pub use buffer::*;
+
+/// The operation was successful.
+pub const CASHWEB_OK: u32 = 0;
+
+/// A required pointer was null.
+pub const CASHWEB_NULL_POINTER: u32 = 9001;
+
+/// The input failed to decode from its protobuf serialization.
+pub const CASHWEB_DECODE: u32 = 9002;
+
+/// The private key provided was invalid.
+pub const CASHWEB_PRIVATE_KEY: u32 = 9003;
+
+/// Failed to generate randomness.
+pub const CASHWEB_RANDOM: u32 = 9004;
+
+/// Convert the result of a fallible operation into a status code.
+fn status(result: Result<(), u32>) -> u32 {
+    match result {
+        Ok(()) => CASHWEB_OK,
+        Err(code) => code,
+    }
+}
+
+/// Borrow a slice from a pointer and length, a null pointer is only permitted when the length is
+/// zero.
+unsafe fn slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], u32> {
+    if data.is_null() {
+        if len == 0 {
+            return Ok(&[]);
+        }
+        return Err(CASHWEB_NULL_POINTER);
+    }
+    Ok(std::slice::from_raw_parts(data, len))
+}
+
+/// Copy bytes to an output pointer.
+unsafe fn write_bytes(out: *mut u8, bytes: &[u8]) -> Result<(), u32> {
+    if out.is_null() {
+        return Err(CASHWEB_NULL_POINTER);
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+    Ok(())
+}
+
+/// Release a handle, null handles are ignored.
+unsafe fn free_handle<T>(handle: *mut T) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
@@ -0,0 +1,467 @@
+//! This module contains the C ABI for parsing, opening and sealing relay [`Message`]s.
+//!
+//! Sealing a message is split into steps, the stamp must commit to the payload digest which is
+//! only known once the payload has been encrypted:
+//!
+//! 1. Seal the payload with [`cashweb_message_seal`].
+//! 2. Read the payload digest with [`cashweb_sealed_message_payload_digest`].
+//! 3. Fund and sign the stamp transactions, see [`cashweb_stamp_transaction`].
+//! 4. Attach them with [`cashweb_sealed_message_add_stamp_transaction`].
+//! 5. Serialize the message with [`cashweb_sealed_message_encode`].
+//!
+//! [`cashweb_stamp_transaction`]: crate::stamp::cashweb_stamp_transaction
+
+use prost::Message as _;
+use relay::{
+    create_merged_key,
+    key_schedule::PayloadKeys,
+    secp::{PrivateKey, PublicKey, Secp256k1},
+    stamp::{Stamp, StampOutpoints, StampType},
+    DigestAlgorithm, EncryptionScheme, ErrorCode, Message, OpenError, ParseError, ParsedMessage,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::{
+    free_handle, slice, status, write_bytes, CashwebBuffer, CASHWEB_DECODE, CASHWEB_NULL_POINTER,
+    CASHWEB_OK, CASHWEB_PRIVATE_KEY, CASHWEB_RANDOM,
+};
+
+/// The length of a serialized compressed public key.
+const PUBLIC_KEY_LEN: usize = 33;
+
+/// The length of a salt generated during sealing.
+const SALT_LEN: usize = 32;
+
+/// An opaque handle to a [`ParsedMessage`].
+#[derive(Debug)]
+pub struct CashwebParsedMessage {
+    inner: ParsedMessage,
+}
+
+/// An opaque handle to a sealed [`Message`] awaiting its stamp.
+#[derive(Debug)]
+pub struct CashwebSealedMessage {
+    inner: Message,
+}
+
+unsafe fn message_parse(
+    raw: *const u8,
+    raw_len: usize,
+    out: *mut *mut CashwebParsedMessage,
+) -> Result<(), u32> {
+    if out.is_null() {
+        return Err(CASHWEB_NULL_POINTER);
+    }
+    let message = Message::decode(slice(raw, raw_len)?).map_err(|_| CASHWEB_DECODE)?;
+    let parsed_message = message.parse().map_err(|err| u32::from(err.code()))?;
+    *out = Box::into_raw(Box::new(CashwebParsedMessage {
+        inner: parsed_message,
+    }));
+    Ok(())
+}
+
+/// Decode and parse a serialized [`Message`], writing a handle to `out`.
+///
+/// # Safety
+///
+/// `raw` must point to `raw_len` readable bytes and `out` must be writable. The handle must be
+/// released using [`cashweb_parsed_message_free`].
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_message_parse(
+    raw: *const u8,
+    raw_len: usize,
+    out: *mut *mut CashwebParsedMessage,
+) -> u32 {
+    status(message_parse(raw, raw_len, out))
+}
+
+/// Release a [`CashwebParsedMessage`].
+///
+/// # Safety
+///
+/// The handle must have been returned by [`cashweb_message_parse`] and not previously released.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_parsed_message_free(message: *mut CashwebParsedMessage) {
+    free_handle(message)
+}
+
+/// Write the 32 byte payload digest of a parsed message to `out`.
+///
+/// # Safety
+///
+/// `message` must be a valid handle and `out` must point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_parsed_message_payload_digest(
+    message: *const CashwebParsedMessage,
+    out: *mut u8,
+) -> u32 {
+    match message.as_ref() {
+        Some(message) => status(write_bytes(out, &message.inner.payload_digest)),
+        None => CASHWEB_NULL_POINTER,
+    }
+}
+
+/// Write the 33 byte source public key of a parsed message to `out`.
+///
+/// # Safety
+///
+/// `message` must be a valid handle and `out` must point to 33 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_parsed_message_source_public_key(
+    message: *const CashwebParsedMessage,
+    out: *mut u8,
+) -> u32 {
+    match message.as_ref() {
+        Some(message) => status(write_bytes(
+            out,
+            &message.inner.source_public_key.serialize(),
+        )),
+        None => CASHWEB_NULL_POINTER,
+    }
+}
+
+/// Verify the stamp attached to a parsed message.
+///
+/// # Safety
+///
+/// `message` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_parsed_message_verify_stamp(
+    message: *const CashwebParsedMessage,
+) -> u32 {
+    match message.as_ref() {
+        Some(message) => match message.inner.verify_stamp() {
+            Ok(_) => CASHWEB_OK,
+            Err(err) => u32::from(err.code()),
+        },
+        None => CASHWEB_NULL_POINTER,
+    }
+}
+
+unsafe fn parsed_message_open(
+    message: *const CashwebParsedMessage,
+    private_key: *const u8,
+    out: *mut CashwebBuffer,
+) -> Result<(), u32> {
+    let message = message.as_ref().ok_or(CASHWEB_NULL_POINTER)?;
+    let private_key = slice(private_key, 32)?;
+    let opened = message
+        .inner
+        .open(private_key)
+        .map_err(|err| u32::from(err.code()))?;
+
+    let mut raw_payload = Vec::with_capacity(opened.payload.encoded_len());
+    opened.payload.encode(&mut raw_payload).unwrap(); // This is safe
+    CashwebBuffer::write(out, raw_payload)
+}
+
+/// Verify the stamp, authenticate and decrypt a parsed message, writing the serialized
+/// [`Payload`] to `out`.
+///
+/// [`Payload`]: relay::Payload
+///
+/// # Safety
+///
+/// `message` must be a valid handle, `private_key` must point to 32 readable bytes and `out` must
+/// be writable. The buffer must be released using [`cashweb_buffer_free`].
+///
+/// [`cashweb_buffer_free`]: crate::cashweb_buffer_free
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_parsed_message_open(
+    message: *const CashwebParsedMessage,
+    private_key: *const u8,
+    out: *mut CashwebBuffer,
+) -> u32 {
+    status(parsed_message_open(message, private_key, out))
+}
+
+unsafe fn message_seal(
+    private_key: *const u8,
+    destination_public_key: *const u8,
+    scheme: i32,
+    payload: *const u8,
+    payload_len: usize,
+    out: *mut *mut CashwebSealedMessage,
+) -> Result<(), u32> {
+    if out.is_null() {
+        return Err(CASHWEB_NULL_POINTER);
+    }
+
+    // Parse keys
+    let private_key =
+        PrivateKey::from_slice(slice(private_key, 32)?).map_err(|_| CASHWEB_PRIVATE_KEY)?;
+    let source_public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+    let destination_public_key =
+        PublicKey::from_slice(slice(destination_public_key, PUBLIC_KEY_LEN)?)
+            .map_err(|err| u32::from(ParseError::DestinationPublicKey(err).code()))?;
+    let scheme = EncryptionScheme::from_i32(scheme)
+        .ok_or_else(|| u32::from(OpenError::UnsupportedScheme.code()))?;
+
+    // Generate salt
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| CASHWEB_RANDOM)?;
+
+    // Create payload keys
+    let merged_key = create_merged_key(destination_public_key, &private_key[..])
+        .map_err(|err| u32::from(OpenError::SharedKey(err).code()))?;
+    let keys = PayloadKeys::derive(scheme, &merged_key, &salt)
+        .map_err(|_| u32::from(OpenError::UnsupportedScheme.code()))?;
+
+    // Encrypt payload
+    let ciphertext = keys.encrypt(slice(payload, payload_len)?);
+
+    // Calculate digest and HMAC, SHA256 is always supported
+    let payload_digest = DigestAlgorithm::Sha256.digest(&ciphertext).unwrap(); // This is safe
+    let payload_hmac = keys.payload_hmac(&payload_digest).unwrap(); // This is safe
+
+    let message = Message {
+        source_public_key: source_public_key.serialize().to_vec(),
+        destination_public_key: destination_public_key.serialize().to_vec(),
+        received_time: 0,
+        payload_digest: payload_digest.to_vec(),
+        stamp: Some(Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![],
+        }),
+        scheme: scheme.into(),
+        salt: salt.to_vec(),
+        payload_hmac: payload_hmac.to_vec(),
+        payload_size: ciphertext.len() as u64,
+        version: relay::VERSION,
+        digest_algorithm: DigestAlgorithm::Sha256.into(),
+        payload: ciphertext,
+    };
+    *out = Box::into_raw(Box::new(CashwebSealedMessage { inner: message }));
+    Ok(())
+}
+
+/// Encrypt a serialized [`Payload`] to a destination public key, writing a handle to `out`.
+///
+/// The `scheme` is the [`EncryptionScheme`] as given in the protobuf specification.
+///
+/// [`Payload`]: relay::Payload
+///
+/// # Safety
+///
+/// `private_key` must point to 32 readable bytes, `destination_public_key` to 33 readable bytes,
+/// `payload` to `payload_len` readable bytes, and `out` must be writable. The handle must be
+/// released using [`cashweb_sealed_message_free`].
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_message_seal(
+    private_key: *const u8,
+    destination_public_key: *const u8,
+    scheme: i32,
+    payload: *const u8,
+    payload_len: usize,
+    out: *mut *mut CashwebSealedMessage,
+) -> u32 {
+    status(message_seal(
+        private_key,
+        destination_public_key,
+        scheme,
+        payload,
+        payload_len,
+        out,
+    ))
+}
+
+/// Release a [`CashwebSealedMessage`].
+///
+/// # Safety
+///
+/// The handle must have been returned by [`cashweb_message_seal`] and not previously released.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_sealed_message_free(message: *mut CashwebSealedMessage) {
+    free_handle(message)
+}
+
+/// Write the 32 byte payload digest of a sealed message to `out`.
+///
+/// # Safety
+///
+/// `message` must be a valid handle and `out` must point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_sealed_message_payload_digest(
+    message: *const CashwebSealedMessage,
+    out: *mut u8,
+) -> u32 {
+    match message.as_ref() {
+        Some(message) => status(write_bytes(out, &message.inner.payload_digest)),
+        None => CASHWEB_NULL_POINTER,
+    }
+}
+
+unsafe fn sealed_message_add_stamp_transaction(
+    message: *mut CashwebSealedMessage,
+    raw_transaction: *const u8,
+    raw_transaction_len: usize,
+    vouts: *const u32,
+    vouts_len: usize,
+) -> Result<(), u32> {
+    let message = message.as_mut().ok_or(CASHWEB_NULL_POINTER)?;
+    let stamp_tx = slice(raw_transaction, raw_transaction_len)?.to_vec();
+    let vouts = if vouts_len == 0 {
+        Vec::new()
+    } else if vouts.is_null() {
+        return Err(CASHWEB_NULL_POINTER);
+    } else {
+        std::slice::from_raw_parts(vouts, vouts_len).to_vec()
+    };
+
+    let stamp = message.inner.stamp.get_or_insert_with(Default::default);
+    stamp
+        .stamp_outpoints
+        .push(StampOutpoints { stamp_tx, vouts });
+    Ok(())
+}
+
+/// Attach a signed stamp transaction, and the indexes of its stamp outputs, to a sealed message.
+///
+/// # Safety
+///
+/// `message` must be a valid handle, `raw_transaction` must point to `raw_transaction_len`
+/// readable bytes and `vouts` to `vouts_len` readable integers.
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_sealed_message_add_stamp_transaction(
+    message: *mut CashwebSealedMessage,
+    raw_transaction: *const u8,
+    raw_transaction_len: usize,
+    vouts: *const u32,
+    vouts_len: usize,
+) -> u32 {
+    status(sealed_message_add_stamp_transaction(
+        message,
+        raw_transaction,
+        raw_transaction_len,
+        vouts,
+        vouts_len,
+    ))
+}
+
+unsafe fn sealed_message_encode(
+    message: *const CashwebSealedMessage,
+    out: *mut CashwebBuffer,
+) -> Result<(), u32> {
+    let message = message.as_ref().ok_or(CASHWEB_NULL_POINTER)?;
+    let mut raw_message = Vec::with_capacity(message.inner.encoded_len());
+    message.inner.encode(&mut raw_message).unwrap(); // This is safe
+    CashwebBuffer::write(out, raw_message)
+}
+
+/// Write the serialized [`Message`] to `out`.
+///
+/// # Safety
+///
+/// `message` must be a valid handle and `out` must be writable. The buffer must be released using
+/// [`cashweb_buffer_free`].
+///
+/// [`cashweb_buffer_free`]: crate::cashweb_buffer_free
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_sealed_message_encode(
+    message: *const CashwebSealedMessage,
+    out: *mut CashwebBuffer,
+) -> u32 {
+    status(sealed_message_encode(message, out))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use relay::Payload;
+
+    use crate::cashweb_buffer_free;
+
+    use super::*;
+
+    #[test]
+    fn seal_and_parse() {
+        let private_key = [1; 32];
+        let destination_private_key = PrivateKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &destination_private_key);
+        let payload = Payload {
+            timestamp: 1,
+            entries: vec![],
+            padding: vec![],
+        };
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+
+        unsafe {
+            let mut sealed = ptr::null_mut();
+            assert_eq!(
+                cashweb_message_seal(
+                    private_key.as_ptr(),
+                    destination_public_key.serialize().as_ptr(),
+                    EncryptionScheme::EphemeralDh.into(),
+                    raw_payload.as_ptr(),
+                    raw_payload.len(),
+                    &mut sealed,
+                ),
+                CASHWEB_OK
+            );
+            let mut buffer = CashwebBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(
+                cashweb_sealed_message_encode(sealed, &mut buffer),
+                CASHWEB_OK
+            );
+
+            let mut parsed = ptr::null_mut();
+            assert_eq!(
+                cashweb_message_parse(buffer.data, buffer.len, &mut parsed),
+                CASHWEB_OK
+            );
+            let mut sealed_digest = [0; 32];
+            let mut parsed_digest = [0; 32];
+            cashweb_sealed_message_payload_digest(sealed, sealed_digest.as_mut_ptr());
+            cashweb_parsed_message_payload_digest(parsed, parsed_digest.as_mut_ptr());
+            assert_eq!(sealed_digest, parsed_digest);
+
+            let mut opened = CashwebBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(
+                cashweb_parsed_message_open(
+                    parsed,
+                    destination_private_key[..].as_ptr(),
+                    &mut opened
+                ),
+                CASHWEB_OK
+            );
+            let raw_opened = std::slice::from_raw_parts(opened.data, opened.len);
+            assert_eq!(Payload::decode(raw_opened).unwrap(), payload);
+
+            // Wrong key
+            assert_eq!(
+                cashweb_parsed_message_open(parsed, private_key.as_ptr(), &mut opened),
+                u32::from(OpenError::Authentication.code())
+            );
+
+            cashweb_parsed_message_free(parsed);
+            cashweb_sealed_message_free(sealed);
+            cashweb_buffer_free(buffer);
+            cashweb_buffer_free(opened);
+        }
+    }
+
+    #[test]
+    fn parse_garbage() {
+        let mut parsed = ptr::null_mut();
+        let raw = [0xff; 4];
+        assert_eq!(
+            unsafe { cashweb_message_parse(raw.as_ptr(), raw.len(), &mut parsed) },
+            CASHWEB_DECODE
+        );
+        assert_eq!(
+            unsafe { cashweb_message_parse(ptr::null(), 1, &mut parsed) },
+            CASHWEB_NULL_POINTER
+        );
+    }
+}
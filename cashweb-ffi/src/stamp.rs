@@ -0,0 +1,67 @@
+//! This module contains the C ABI for constructing stamp transactions.
+
+use bitcoin::{transaction::Transaction, Encodable};
+use relay::{secp::PublicKey, stamp::create_stamp_outputs, ErrorCode, ParseError};
+
+use crate::{slice, status, CashwebBuffer, CASHWEB_NULL_POINTER};
+
+unsafe fn stamp_transaction(
+    destination_public_key: *const u8,
+    payload_digest: *const u8,
+    tx_num: u32,
+    amounts: *const u64,
+    amounts_len: usize,
+    out: *mut CashwebBuffer,
+) -> Result<(), u32> {
+    let destination_public_key = PublicKey::from_slice(slice(destination_public_key, 33)?)
+        .map_err(|err| u32::from(ParseError::DestinationPublicKey(err).code()))?;
+    let mut digest = [0; 32];
+    digest.copy_from_slice(slice(payload_digest, 32)?);
+    if amounts.is_null() {
+        return Err(CASHWEB_NULL_POINTER);
+    }
+    let amounts = std::slice::from_raw_parts(amounts, amounts_len);
+
+    let outputs = create_stamp_outputs(&destination_public_key, &digest, tx_num, amounts)
+        .map_err(|err| u32::from(err.code()))?;
+    let transaction = Transaction {
+        version: 2,
+        inputs: vec![],
+        outputs,
+        lock_time: 0,
+    };
+    CashwebBuffer::write(out, transaction.encode_to_vec())
+}
+
+/// Construct an unfunded stamp transaction, paying each of the `amounts` to the stamp outputs
+/// derived from the destination public key and payload digest.
+///
+/// The transaction should be funded and signed by a wallet, the stamp outputs remain the first
+/// outputs unless the wallet reorders them. The `tx_num` is the position of the transaction within
+/// the stamp.
+///
+/// # Safety
+///
+/// `destination_public_key` must point to 33 readable bytes, `payload_digest` to 32 readable
+/// bytes, `amounts` to `amounts_len` readable integers, and `out` must be writable. The buffer must
+/// be released using [`cashweb_buffer_free`].
+///
+/// [`cashweb_buffer_free`]: crate::cashweb_buffer_free
+#[no_mangle]
+pub unsafe extern "C" fn cashweb_stamp_transaction(
+    destination_public_key: *const u8,
+    payload_digest: *const u8,
+    tx_num: u32,
+    amounts: *const u64,
+    amounts_len: usize,
+    out: *mut CashwebBuffer,
+) -> u32 {
+    status(stamp_transaction(
+        destination_public_key,
+        payload_digest,
+        tx_num,
+        amounts,
+        amounts_len,
+        out,
+    ))
+}
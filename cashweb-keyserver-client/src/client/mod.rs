@@ -2,11 +2,13 @@
 
 pub mod services;
 
-use std::{error, fmt};
+use std::{error, fmt, sync::Arc, time::Duration};
 
 use bytes::Bytes;
+use clock::{system_clock, Clock, SharedClock};
 use hyper::{client::HttpConnector, http::uri::InvalidUri, Client as HyperClient};
 use hyper_tls::HttpsConnector;
+use prost::{DecodeError, Message as _};
 use secp256k1::key::PublicKey;
 use thiserror::Error;
 use tower_service::Service;
@@ -39,6 +41,8 @@ pub struct MetadataPackage {
     pub public_key: PublicKey,
     /// The address metadata.
     pub metadata: AddressMetadata,
+    /// The digest of the [`AuthWrapper`] payload.
+    pub payload_digest: [u8; 32],
     /// The raw [`AuthWrapper`]
     pub raw_auth_wrapper: Bytes,
 }
@@ -54,10 +58,62 @@ pub struct RawAuthWrapperPackage {
     pub raw_auth_wrapper: Bytes,
 }
 
+/// Error associated with verifying a [`RawAuthWrapperPackage`].
+#[derive(Debug, Error)]
+pub enum RawPackageError {
+    /// Error while decoding the [`AuthWrapper`].
+    #[error("authwrapper decoding failure: {0}")]
+    AuthWrapperDecode(DecodeError),
+    /// Error while parsing the [`AuthWrapper`].
+    #[error("authwrapper parsing failure: {0}")]
+    AuthWrapperParse(ParseError),
+    /// Error while verifying the [`AuthWrapper`].
+    #[error("authwrapper verification failure: {0}")]
+    AuthWrapperVerify(VerifyError),
+    /// Error while decoding the [`AddressMetadata`].
+    #[error("metadata decoding failure: {0}")]
+    MetadataDecode(DecodeError),
+}
+
+impl RawAuthWrapperPackage {
+    /// Decode, parse and verify the raw [`AuthWrapper`], producing the [`MetadataPackage`].
+    ///
+    /// This allows packages fetched using [`get_raw_metadata`] to be archived and verified
+    /// lazily.
+    ///
+    /// [`get_raw_metadata`]: KeyserverClient::get_raw_metadata
+    pub fn verify(self) -> Result<MetadataPackage, RawPackageError> {
+        let auth_wrapper = AuthWrapper::decode(self.raw_auth_wrapper.clone())
+            .map_err(RawPackageError::AuthWrapperDecode)?;
+        let parsed_auth_wrapper = auth_wrapper
+            .parse()
+            .map_err(RawPackageError::AuthWrapperParse)?;
+        parsed_auth_wrapper
+            .verify()
+            .map_err(RawPackageError::AuthWrapperVerify)?;
+        let metadata = AddressMetadata::decode(&mut parsed_auth_wrapper.payload.as_slice())
+            .map_err(RawPackageError::MetadataDecode)?;
+
+        Ok(MetadataPackage {
+            token: self.token,
+            public_key: parsed_auth_wrapper.public_key,
+            metadata,
+            payload_digest: parsed_auth_wrapper.payload_digest,
+            raw_auth_wrapper: self.raw_auth_wrapper,
+        })
+    }
+}
+
+/// The default maximum size, in bytes, of response bodies read.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
 /// `KeyserverClient` allows queries to specific keyservers.
 #[derive(Clone, Debug)]
 pub struct KeyserverClient<S> {
     inner_client: S,
+    max_body_size: usize,
+    max_metadata_age: Option<Duration>,
+    clock: SharedClock,
 }
 
 impl<S> KeyserverClient<S> {
@@ -67,14 +123,41 @@ impl<S> KeyserverClient<S> {
     pub fn from_service(service: S) -> Self {
         Self {
             inner_client: service,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_metadata_age: None,
+            clock: system_clock(),
         }
     }
+
+    /// Set the maximum size, in bytes, of response bodies read.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Refuse metadata whose timestamp is older than `max_age`, see [`StaleTimestamp`].
+    ///
+    /// [`StaleTimestamp`]: crate::StaleTimestamp
+    pub fn with_max_metadata_age(mut self, max_age: Duration) -> Self {
+        self.max_metadata_age = Some(max_age);
+        self
+    }
+
+    /// Set the [`Clock`] against which the age of metadata is measured, the system clock by
+    /// default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
 }
 
 impl Default for KeyserverClient<HyperClient<HttpConnector>> {
     fn default() -> Self {
         Self {
             inner_client: HyperClient::new(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_metadata_age: None,
+            clock: system_clock(),
         }
     }
 }
@@ -92,6 +175,9 @@ impl KeyserverClient<HyperClient<HttpsConnector<HttpConnector>>> {
         let https = HttpsConnector::new();
         Self {
             inner_client: HyperClient::builder().build(https),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_metadata_age: None,
+            clock: system_clock(),
         }
     }
 }
@@ -149,6 +235,39 @@ where
     }
 }
 
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, GetRawAuthWrapper), Response = RawAuthWrapperPackage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetRawAuthWrapper)>>::Error: fmt::Display + std::error::Error,
+    <Self as Service<(Uri, GetRawAuthWrapper)>>::Future: Send + Sync + 'static,
+{
+    /// Get the raw [`AuthWrapper`] from a server, without parsing or verifying it. The result is
+    /// wrapped in [`RawAuthWrapperPackage`].
+    ///
+    /// The package may be verified later using [`RawAuthWrapperPackage::verify`].
+    pub async fn get_raw_metadata(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+    ) -> Result<
+        RawAuthWrapperPackage,
+        KeyserverError<<Self as Service<(Uri, GetRawAuthWrapper)>>::Error>,
+    > {
+        // Construct URI
+        let full_path = format!("{}/keys/{}", keyserver_url, address);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetRawAuthWrapper);
+
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(KeyserverError::Error)
+    }
+}
+
 impl<S> KeyserverClient<S>
 where
     Self: Service<(Uri, PutMetadata), Response = ()>,
@@ -209,7 +328,7 @@ where
             uri,
             PutRawAuthWrapper {
                 token,
-                raw_auth_wrapper,
+                raw_auth_wrapper: raw_auth_wrapper.into(),
             },
         );
 
@@ -220,3 +339,73 @@ where
             .map_err(KeyserverError::Error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::keys;
+
+    fn raw_package(auth_wrapper: &AuthWrapper) -> RawAuthWrapperPackage {
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        RawAuthWrapperPackage {
+            token: "POP token".to_string(),
+            raw_auth_wrapper: raw_auth_wrapper.into(),
+        }
+    }
+
+    fn metadata(timestamp: i64) -> Vec<u8> {
+        let metadata = AddressMetadata {
+            timestamp,
+            ..Default::default()
+        };
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        raw_metadata
+    }
+
+    #[test]
+    fn verify_raw_package() {
+        let (private_key, public_key) = keys(1);
+        let auth_wrapper = AuthWrapper::sign(&private_key, metadata(1_600_000_000_000));
+        let raw_package = raw_package(&auth_wrapper);
+
+        let package = raw_package.clone().verify().unwrap();
+        assert_eq!(package.token, raw_package.token);
+        assert_eq!(package.public_key, public_key);
+        assert_eq!(package.metadata.timestamp, 1_600_000_000_000);
+        assert_eq!(
+            package.payload_digest,
+            auth_wrapper.parse().unwrap().payload_digest
+        );
+        assert_eq!(package.raw_auth_wrapper, raw_package.raw_auth_wrapper);
+    }
+
+    #[test]
+    fn invalid_raw_package() {
+        let (private_key, _) = keys(1);
+
+        let garbage = RawAuthWrapperPackage {
+            token: "POP token".to_string(),
+            raw_auth_wrapper: Bytes::from_static(&[0xff]),
+        };
+        assert!(matches!(
+            garbage.verify(),
+            Err(RawPackageError::AuthWrapperDecode(_))
+        ));
+
+        let mut tampered = AuthWrapper::sign(&private_key, metadata(1));
+        tampered.payload = metadata(2);
+        tampered.payload_digest = Vec::new();
+        assert!(matches!(
+            raw_package(&tampered).verify(),
+            Err(RawPackageError::AuthWrapperVerify(_))
+        ));
+
+        let not_metadata = AuthWrapper::sign(&private_key, vec![0xff]);
+        assert!(matches!(
+            raw_package(&not_metadata).verify(),
+            Err(RawPackageError::MetadataDecode(_))
+        ));
+    }
+}
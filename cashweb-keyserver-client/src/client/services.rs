@@ -2,27 +2,27 @@
 
 use std::{fmt, pin::Pin};
 
+use body_limit::{read_body_limited, BodyError, StatusError, DEFAULT_EXCERPT_SIZE};
+use bytes::Bytes;
 use futures_core::{
     task::{Context, Poll},
     Future,
 };
 use futures_util::future::{join, join_all};
-use hyper::{
-    body::{aggregate, to_bytes},
-    http::header::AUTHORIZATION,
-    http::Method,
-    Body, Error as HyperError, Request, Response, StatusCode,
-};
 pub use hyper::{
     client::{connect::Connect, HttpConnector},
     Uri,
 };
+use hyper::{
+    http::header::AUTHORIZATION, http::Method, Body, Error as HyperError, Request, Response,
+    StatusCode,
+};
 use prost::{DecodeError, Message as _};
 use thiserror::Error;
 use tower_service::Service;
 
 use super::{KeyserverClient, MetadataPackage, RawAuthWrapperPackage};
-use crate::models::*;
+use crate::{models::*, StaleTimestamp};
 
 type FutResponse<Response, Error> =
     Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
@@ -36,7 +36,7 @@ pub struct GetPeers;
 pub enum GetPeersError<E: fmt::Debug + fmt::Display> {
     /// Error while processing the body.
     #[error("processing body failed: {0}")]
-    Body(HyperError),
+    Body(BodyError<HyperError>),
     /// A connection error occured.
     #[error("connection failure: {0}")]
     Service(E),
@@ -44,8 +44,8 @@ pub enum GetPeersError<E: fmt::Debug + fmt::Display> {
     #[error("body decoding failure: {0}")]
     Decode(DecodeError),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
     /// Peering is disabled on the keyserver.
     #[error("peering disabled")]
     PeeringDisabled,
@@ -71,6 +71,7 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetPeers)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
         let http_request = Request::builder()
             .method(Method::GET)
             .uri(uri)
@@ -85,10 +86,15 @@ where
             match response.status() {
                 StatusCode::OK => (),
                 StatusCode::NOT_IMPLEMENTED => return Err(Self::Error::PeeringDisabled),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+                _ => {
+                    let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                    return Err(Self::Error::UnexpectedStatus(error));
+                }
             }
             let body = response.into_body();
-            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let buf = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
             let peers = Peers::decode(buf).map_err(Self::Error::Decode)?;
             Ok(peers)
         };
@@ -107,13 +113,13 @@ pub struct GetRawAuthWrapper;
 pub enum GetRawAuthWrapperError<E: fmt::Debug + fmt::Display> {
     /// Error while processing the body.
     #[error("processing body failed: {0}")]
-    Body(HyperError),
+    Body(BodyError<HyperError>),
     /// A connection error occured.
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
     /// POP token missing from headers.
     #[error("missing token")]
     MissingToken,
@@ -138,6 +144,7 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetRawAuthWrapper)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
         let http_request = Request::builder()
             .method(Method::GET)
             .uri(uri)
@@ -151,10 +158,9 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             #[allow(clippy::borrow_interior_mutable_const)]
@@ -162,15 +168,17 @@ where
                 .headers()
                 .into_iter()
                 .find(|(name, value)| {
-                    *name == AUTHORIZATION && value.as_bytes()[..4] == b"POP "[..]
+                    *name == AUTHORIZATION && value.as_bytes().starts_with(b"POP ")
                 })
+                .and_then(|(_, value)| value.to_str().ok())
                 .ok_or(Self::Error::MissingToken)?
-                .0
                 .to_string();
 
             // Aggregate body
             let body = response.into_body();
-            let raw_auth_wrapper = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_auth_wrapper = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
 
             Ok(RawAuthWrapperPackage {
                 token,
@@ -202,16 +210,19 @@ pub enum GetMetadataError<E: fmt::Debug + fmt::Display> {
     AuthWrapperVerify(VerifyError),
     /// Error while processing the body.
     #[error("processing body failed: {0}")]
-    Body(HyperError),
+    Body(BodyError<HyperError>),
     /// A connection error occured.
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
     /// POP token missing from headers.
     #[error("missing token")]
     MissingToken,
+    /// The metadata is older than the maximum age.
+    #[error(transparent)]
+    StaleMetadata(StaleTimestamp),
 }
 
 impl<S> Service<(Uri, GetMetadata)> for KeyserverClient<S>
@@ -233,6 +244,9 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetMetadata)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
+        let max_metadata_age = self.max_metadata_age;
+        let clock = self.clock.clone();
         let http_request = Request::builder()
             .method(Method::GET)
             .uri(uri)
@@ -246,10 +260,9 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             #[allow(clippy::borrow_interior_mutable_const)]
@@ -257,15 +270,17 @@ where
                 .headers()
                 .into_iter()
                 .find(|(name, value)| {
-                    *name == AUTHORIZATION && value.as_bytes()[..4] == b"POP "[..]
+                    *name == AUTHORIZATION && value.as_bytes().starts_with(b"POP ")
                 })
+                .and_then(|(_, value)| value.to_str().ok())
                 .ok_or(Self::Error::MissingToken)?
-                .0
                 .to_string();
 
             // Deserialize and decode body
             let body = response.into_body();
-            let raw_auth_wrapper = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_auth_wrapper = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
             let auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.clone())
                 .map_err(Self::Error::AuthWrapperDecode)?;
 
@@ -283,12 +298,21 @@ where
             let metadata = AddressMetadata::decode(&mut parsed_auth_wrapper.payload.as_slice())
                 .map_err(Self::Error::MetadataDecode)?;
 
-            Ok(MetadataPackage {
+            let package = MetadataPackage {
                 token,
                 public_key: parsed_auth_wrapper.public_key,
                 metadata,
+                payload_digest: parsed_auth_wrapper.payload_digest,
                 raw_auth_wrapper,
-            })
+            };
+
+            // Check freshness
+            if let Some(max_age) = max_metadata_age {
+                package
+                    .check_metadata_age(&*clock, max_age)
+                    .map_err(Self::Error::StaleMetadata)?;
+            }
+            Ok(package)
         };
         Box::pin(fut)
     }
@@ -310,8 +334,8 @@ pub enum PutMetadataError<E: fmt::Debug + fmt::Display> {
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
 }
 
 impl<S> Service<(Uri, PutMetadata)> for KeyserverClient<S>
@@ -353,10 +377,9 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             Ok(())
@@ -371,7 +394,9 @@ pub struct PutRawAuthWrapper {
     /// POP authorization token.
     pub token: String,
     /// The raw [`AuthWrapper`] to be put to the keyserver.
-    pub raw_auth_wrapper: Vec<u8>,
+    ///
+    /// This is cheaply cloned when the request is sent to many keyservers.
+    pub raw_auth_wrapper: Bytes,
 }
 
 impl<S> Service<(Uri, PutRawAuthWrapper)> for KeyserverClient<S>
@@ -412,10 +437,9 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             Ok(())
@@ -442,6 +466,19 @@ pub enum SampleError<E: fmt::Debug + fmt::Display> {
     /// Sample totally failed. Contains errors paired with the [`Uri`] of the keyserver they originated at.
     #[error("sampling failure: {0:?}")] // TODO: Make this prettier
     Sample(Vec<(Uri, E)>),
+    /// Fewer keyservers succeeded than required by the [`ReplicationThreshold`]. Contains errors
+    /// paired with the [`Uri`] of the keyserver they originated at.
+    ///
+    /// [`ReplicationThreshold`]: crate::ReplicationThreshold
+    #[error("insufficient replication: {successes} of {required} required")]
+    InsufficientReplication {
+        /// The number of keyservers which succeeded.
+        successes: usize,
+        /// The number of keyservers required to succeed.
+        required: usize,
+        /// The errors paired with the [`Uri`] of the keyserver they originated at.
+        errors: Vec<(Uri, E)>,
+    },
 }
 
 impl<S, T> Service<SampleRequest<T>> for KeyserverClient<S>
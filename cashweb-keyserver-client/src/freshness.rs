@@ -0,0 +1,110 @@
+//! This module contains methods for checking the freshness of a [`MetadataPackage`].
+//!
+//! A keyserver may repeatedly serve an outdated, yet validly signed, [`AddressMetadata`] for an
+//! address, for example after falling out of sync with its peers. Bounding the age of the metadata
+//! timestamp, or of the block confirming the POP token commitment, allows clients to detect this
+//! and fall back to other keyservers.
+//!
+//! [`AddressMetadata`]: crate::models::AddressMetadata
+
+use std::time::Duration;
+
+use clock::{check_age, Clock};
+
+use crate::{BlockchainBackend, MetadataPackage, TokenVerificationError};
+
+pub use clock::StaleTimestamp;
+
+impl MetadataPackage {
+    /// Check the timestamp of the metadata is no older than `max_age` at the time given by the
+    /// [`Clock`].
+    pub fn check_metadata_age<C: Clock + ?Sized>(
+        &self,
+        clock: &C,
+        max_age: Duration,
+    ) -> Result<(), StaleTimestamp> {
+        check_age(clock, self.metadata.timestamp, max_age)
+    }
+
+    /// Check the commitment transaction of the POP token was confirmed no more than `max_age`
+    /// before the time given by the [`Clock`].
+    ///
+    /// Unconfirmed commitment transactions are considered fresh. This does not verify the
+    /// commitment itself, see [`verify_token`](MetadataPackage::verify_token).
+    pub async fn verify_token_age<B: BlockchainBackend, C: Clock + ?Sized>(
+        &self,
+        backend: &B,
+        clock: &C,
+        max_age: Duration,
+    ) -> Result<(), TokenVerificationError<B::Error>> {
+        let (tx_id, _) = self.token_outpoint()?;
+        let block_time = match backend
+            .get_block_time(&tx_id)
+            .await
+            .map_err(TokenVerificationError::Backend)?
+        {
+            Some(some) => some,
+            None => return Ok(()),
+        };
+
+        let oldest = clock.unix_now().saturating_sub(max_age.as_secs());
+        if block_time < oldest {
+            return Err(TokenVerificationError::StaleToken { block_time, oldest });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::ManualClock;
+    use token::schemes::chain_commitment::construct_token;
+
+    use super::*;
+    use crate::{
+        tests::{block_on, package},
+        verification::tests::{commitment_transaction, MockBackend},
+    };
+
+    #[test]
+    fn metadata_age() {
+        let clock = ManualClock::from_unix(100);
+        let max_age = Duration::from_secs(10);
+
+        assert!(package(90_000, String::new())
+            .check_metadata_age(&clock, max_age)
+            .is_ok());
+        assert_eq!(
+            package(89_999, String::new()).check_metadata_age(&clock, max_age),
+            Err(StaleTimestamp {
+                timestamp: 89_999,
+                oldest: 90_000
+            })
+        );
+    }
+
+    #[test]
+    fn token_age() {
+        let clock = ManualClock::from_unix(100);
+        let max_age = Duration::from_secs(10);
+        let package = package(0, construct_token(&[3; 32], 1));
+        let transaction = commitment_transaction(&package);
+
+        let mut backend = MockBackend::default();
+        backend.insert([3; 32], &transaction, Some(90));
+        block_on(package.verify_token_age(&backend, &clock, max_age)).unwrap();
+
+        backend.insert([3; 32], &transaction, Some(89));
+        assert!(matches!(
+            block_on(package.verify_token_age(&backend, &clock, max_age)),
+            Err(TokenVerificationError::StaleToken {
+                block_time: 89,
+                oldest: 90
+            })
+        ));
+
+        // Unconfirmed commitments are fresh
+        backend.insert([3; 32], &transaction, None);
+        block_on(package.verify_token_age(&backend, &clock, max_age)).unwrap();
+    }
+}
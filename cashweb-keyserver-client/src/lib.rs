@@ -7,12 +7,67 @@
 
 //! `cashweb-bitcoin-client` is a library providing [`KeyserverClient`] which allows
 //! interaction with specific keyservers and [`KeyserverManager`]
-//! which allows sampling and aggregation over multiple keyservers.
+//! which allows sampling and aggregation over multiple keyservers, selected by a [`Sampler`].
+//!
+//! Metadata older than a maximum age may be refused, see
+//! [`with_max_metadata_age`](KeyserverClient::with_max_metadata_age), so that samples fall back to
+//! keyservers serving fresher metadata.
 
 mod client;
+mod freshness;
 mod manager;
 #[allow(missing_docs)]
 pub mod models;
+mod peer_list;
+mod sampler;
+mod verification;
 
 pub use client::*;
+pub use freshness::*;
 pub use manager::*;
+pub use peer_list::*;
+pub use sampler::*;
+pub use verification::*;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::future::Future;
+
+    use bytes::Bytes;
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use crate::{models::AddressMetadata, MetadataPackage};
+
+    /// Run a future to completion on a single threaded runtime.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// A key pair whose private key repeats a byte.
+    pub(crate) fn keys(byte: u8) -> (SecretKey, PublicKey) {
+        let private_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        (private_key, public_key)
+    }
+
+    /// A [`MetadataPackage`] with a metadata timestamp, in unix milliseconds, and a POP token.
+    pub(crate) fn package(timestamp: i64, token: String) -> MetadataPackage {
+        MetadataPackage {
+            token,
+            public_key: keys(1).1,
+            metadata: AddressMetadata {
+                timestamp,
+                ..Default::default()
+            },
+            payload_digest: [2; 32],
+            raw_auth_wrapper: Bytes::new(),
+        }
+    }
+}
@@ -1,5 +1,14 @@
-use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
+use auth_wrapper::revocation::RevocationSet;
+use bytes::BytesMut;
+use clock::Clock;
 use hyper::{
     client::HttpConnector,
     http::uri::{InvalidUri, PathAndQuery},
@@ -7,13 +16,14 @@ use hyper::{
 };
 use prost::Message as _;
 use rand::seq::SliceRandom;
-use tokio::sync::RwLock;
 use tower_service::Service;
 use tower_util::ServiceExt;
 
 use crate::{
     client::{services::*, KeyserverClient, MetadataPackage},
     models::{AuthWrapper, Peer, Peers},
+    peer_list::{PeerHealth, PeerList, PeerRecord},
+    sampler::{Sampler, UniformSampler},
 };
 
 /// KeyserverManager wraps a client and allows sampling and selecting of queries across a set of keyservers.
@@ -21,22 +31,138 @@ use crate::{
 pub struct KeyserverManager<S> {
     inner_client: KeyserverClient<S>,
     uris: Arc<RwLock<Vec<Uri>>>,
+    health: Arc<RwLock<HashMap<String, PeerHealth>>>,
+    sampler: Arc<dyn Sampler>,
+    replication_threshold: ReplicationThreshold,
 }
 
 impl<S> KeyserverManager<S> {
     /// Creates a new manager from URIs and a client.
+    ///
+    /// The URIs are normalized, see [`normalize_uri`], and duplicates removed.
     pub fn from_service(service: S, uris: Vec<Uri>) -> Self {
         Self {
             inner_client: KeyserverClient::from_service(service),
-            uris: Arc::new(RwLock::new(uris)),
+            uris: Arc::new(RwLock::new(normalize_uris(uris))),
+            health: Default::default(),
+            sampler: Arc::new(UniformSampler),
+            replication_threshold: ReplicationThreshold::default(),
         }
     }
 
+    /// Set the [`Sampler`] used to select the keyservers queried, defaults to [`UniformSampler`].
+    pub fn with_sampler<T: Sampler + 'static>(mut self, sampler: T) -> Self {
+        self.sampler = Arc::new(sampler);
+        self
+    }
+
+    /// Set the [`ReplicationThreshold`] required of broadcasts, defaults to a single keyserver.
+    pub fn with_replication_threshold(
+        mut self,
+        replication_threshold: ReplicationThreshold,
+    ) -> Self {
+        self.replication_threshold = replication_threshold;
+        self
+    }
+
+    /// Set the maximum size, in bytes, of response bodies read from each keyserver.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.inner_client = self.inner_client.with_max_body_size(max_body_size);
+        self
+    }
+
+    /// Refuse metadata whose timestamp is older than `max_age`.
+    ///
+    /// Stale metadata is returned among the errors of a sample, allowing fresher metadata from
+    /// other keyservers to be selected.
+    pub fn with_max_metadata_age(mut self, max_age: Duration) -> Self {
+        self.inner_client = self.inner_client.with_max_metadata_age(max_age);
+        self
+    }
+
+    /// Set the [`Clock`] against which the age of metadata is measured, the system clock by
+    /// default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.inner_client = self.inner_client.with_clock(clock);
+        self
+    }
+
     /// Get shared reference the [`Uri`]s.
     pub fn get_uris(&self) -> Arc<RwLock<Vec<Uri>>> {
         self.uris.clone()
     }
 
+    /// Export the [`Uri`]s along with their health statistics.
+    pub async fn export_peers(&self) -> PeerList {
+        let uris = self.uris.read().unwrap();
+        let health = self.health.read().unwrap();
+        let peers = uris
+            .iter()
+            .map(|uri| {
+                let url = uri.to_string();
+                let health = health.get(&url).cloned().unwrap_or_default();
+                PeerRecord { url, health }
+            })
+            .collect();
+        PeerList { peers }
+    }
+
+    /// Import a [`PeerList`], adding unknown keyservers and merging health statistics.
+    ///
+    /// The URLs are normalized, see [`normalize_uri`], so that equivalent URLs share a record. No
+    /// keyservers are added if any URL is invalid.
+    pub async fn import_peers(&self, peer_list: PeerList) -> Result<(), InvalidUri> {
+        let records = peer_list
+            .peers
+            .into_iter()
+            .map(|record| {
+                record
+                    .url
+                    .parse::<Uri>()
+                    .map(|uri| (normalize_uri(uri), record.health))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut uris = self.uris.write().unwrap();
+        let mut health = self.health.write().unwrap();
+        for (uri, record_health) in records {
+            health
+                .entry(uri.to_string())
+                .or_default()
+                .merge(&record_health);
+            if !uris.contains(&uri) {
+                uris.push(uri);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the outcomes of requests to keyservers.
+    fn record_health(&self, outcomes: Vec<(String, bool)>) {
+        let mut health = self.health.write().unwrap();
+        for (url, success) in outcomes {
+            let peer_health = health.entry(url).or_default();
+            if success {
+                peer_health.record_success();
+            } else {
+                peer_health.record_failure();
+            }
+        }
+    }
+
+    /// Sample keyservers using the [`Sampler`], returning all keyservers along with the sample.
+    fn sample_uris(&self, address: &str, sample_size: usize) -> (Vec<Uri>, Vec<Uri>) {
+        let base_uris = self.uris.read().unwrap().clone();
+        let health = self.health.read().unwrap();
+        let uris = self
+            .sampler
+            .sample(&base_uris, &health, address, sample_size)
+            .into_iter()
+            .map(|uri| append_path(uri, &format!("/keys/{}", address)))
+            .collect();
+        (base_uris, uris)
+    }
+
     /// Converts the manager into the underlying client.
     pub fn into_client(self) -> KeyserverClient<S> {
         self.inner_client
@@ -47,14 +173,57 @@ impl KeyserverManager<HyperClient<HttpConnector>> {
     /// Create a HTTP manager.
     pub fn new(uris: Vec<String>) -> Result<Self, InvalidUri> {
         let uris: Result<Vec<Uri>, _> = uris.into_iter().map(|uri| uri.parse()).collect();
-        let uris = uris?;
+        let uris = normalize_uris(uris?);
         Ok(Self {
             inner_client: KeyserverClient::new(),
             uris: Arc::new(RwLock::new(uris)),
+            health: Default::default(),
+            sampler: Arc::new(UniformSampler),
+            replication_threshold: ReplicationThreshold::default(),
         })
     }
 }
 
+/// Normalize a keyserver [`Uri`], allowing equivalent URIs to be deduplicated.
+///
+/// The scheme defaults to `http` and, along with the host, is lowercased. The default port of the
+/// scheme and trailing slashes are removed, leaving `/` if the path is empty. URIs without a host
+/// are returned unchanged.
+pub fn normalize_uri(uri: Uri) -> Uri {
+    let host = match uri.host() {
+        Some(host) => host.to_ascii_lowercase(),
+        None => return uri,
+    };
+    let scheme = uri.scheme_str().unwrap_or("http").to_ascii_lowercase();
+    let port = match (scheme.as_str(), uri.port_u16()) {
+        ("http", Some(80)) | ("https", Some(443)) | (_, None) => String::new(),
+        (_, Some(port)) => format!(":{}", port),
+    };
+    let path = match uri.path().trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    };
+    let query = uri
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+
+    format!("{}://{}{}{}{}", scheme, host, port, path, query)
+        .parse()
+        .unwrap_or(uri)
+}
+
+/// Normalize [`Uri`]s, removing duplicates while preserving order.
+fn normalize_uris(uris: Vec<Uri>) -> Vec<Uri> {
+    let mut normalized: Vec<Uri> = Vec::with_capacity(uris.len());
+    for uri in uris.into_iter().map(normalize_uri) {
+        if !normalized.contains(&uri) {
+            normalized.push(uri);
+        }
+    }
+    normalized
+}
+
 /// Takes a URI and appends a path to it.
 ///
 /// This panics if `new_path` is invalid.
@@ -88,6 +257,22 @@ fn append_path(uri: Uri, new_path: &str) -> Uri {
     Uri::from_parts(parts).unwrap()
 }
 
+/// Pair the URL of the keyserver each response originated at with whether it succeeded.
+fn health_outcomes<R, E>(
+    base_uris: &[Uri],
+    responses: &[(Uri, Result<R, E>)],
+) -> Vec<(String, bool)> {
+    responses
+        .iter()
+        .filter_map(|(uri, result)| {
+            base_uris
+                .iter()
+                .find(|base_uri| base_uri.authority() == uri.authority())
+                .map(|base_uri| (base_uri.to_string(), result.is_ok()))
+        })
+        .collect()
+}
+
 /// Choose from a random subset of URIs.
 pub fn uniform_random_sampler(uris: &[Uri], size: usize) -> Vec<Uri> {
     let mut rng = &mut rand::thread_rng();
@@ -105,6 +290,20 @@ pub fn select_auth_wrapper(
         .max_by_key(move |(_, package)| package.metadata.timestamp)
 }
 
+/// Select best [`AuthWrapper`] from a list, excluding those revoked in the [`RevocationSet`].
+///
+/// [`AuthWrapper`]: auth_wrapper::AuthWrapper
+pub fn select_unrevoked_auth_wrapper(
+    metadatas: Vec<(Uri, MetadataPackage)>,
+    revocations: &RevocationSet,
+) -> Option<(Uri, MetadataPackage)> {
+    let metadatas = metadatas
+        .into_iter()
+        .filter(|(_, package)| !revocations.contains(&package.public_key, &package.payload_digest))
+        .collect();
+    select_auth_wrapper(metadatas)
+}
+
 /// Aggregate a collection of [`Peers`] into a single structure.
 pub fn aggregate_peers(peers: Vec<(Uri, Peers)>) -> Peers {
     let peers = peers
@@ -151,6 +350,58 @@ where
     }
 }
 
+/// The number of keyservers which must accept a broadcast for it to succeed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplicationThreshold {
+    /// An absolute number of keyservers.
+    Count(usize),
+    /// A fraction, between 0 and 1, of the keyservers sampled, rounded up.
+    Fraction(f64),
+}
+
+impl Default for ReplicationThreshold {
+    fn default() -> Self {
+        Self::Count(1)
+    }
+}
+
+impl ReplicationThreshold {
+    /// The number of keyservers required to succeed, out of those sampled.
+    pub fn required(&self, sampled: usize) -> usize {
+        match self {
+            Self::Count(count) => *count,
+            Self::Fraction(fraction) => {
+                (fraction.max(0.0).min(1.0) * sampled as f64).ceil() as usize
+            }
+        }
+    }
+
+    /// Aggregate the responses of a broadcast, failing if too few keyservers succeeded.
+    fn aggregate<E>(
+        &self,
+        responses: Vec<(Uri, Result<(), E>)>,
+    ) -> Result<AggregateResponse<(), E>, SampleError<E>>
+    where
+        E: fmt::Debug + fmt::Display,
+    {
+        let required = self.required(responses.len());
+        let successes = responses
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .count();
+        let AggregateResponse { response, errors } =
+            AggregateResponse::aggregate(responses, |_| ());
+        if successes < required {
+            return Err(SampleError::InsufficientReplication {
+                successes,
+                required,
+                errors,
+            });
+        }
+        Ok(AggregateResponse { response, errors })
+    }
+}
+
 /// Response to an aggregation query.
 #[derive(Debug)]
 pub struct AggregateResponse<R, E> {
@@ -194,7 +445,9 @@ where
     S::Future: Send,
     S::Error: fmt::Debug + fmt::Display + Send,
 {
-    /// Perform a uniform sample of metadata over keyservers and select the latest.
+    /// Perform a sample of metadata over keyservers and select the latest.
+    ///
+    /// The keyservers are chosen by the [`Sampler`] of the manager.
     pub async fn uniform_sample_metadata(
         &self,
         address: &str,
@@ -203,23 +456,49 @@ where
         SampleResponse<MetadataPackage, <KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
     > {
-        let uris = self.uris.read().await.clone();
-        let uris = uris
-            .into_iter()
-            .map(|uri| append_path(uri, &format!("/keys/{}", address)))
-            .collect::<Vec<Uri>>();
-        let uris = uniform_random_sampler(&uris, sample_size);
+        let (base_uris, uris) = self.sample_uris(address, sample_size);
         let sample_request = SampleRequest {
             request: GetMetadata,
             uris,
         };
 
         let responses = self.inner_client.clone().oneshot(sample_request).await?;
+        let outcomes = health_outcomes(&base_uris, &responses);
+        self.record_health(outcomes);
         let sample_response = SampleResponse::select(responses, select_auth_wrapper);
 
         Ok(sample_response)
     }
 
+    /// Perform a sample of metadata over keyservers and select the latest, refusing metadata
+    /// revoked in the [`RevocationSet`].
+    ///
+    /// The keyservers are chosen by the [`Sampler`] of the manager.
+    pub async fn uniform_sample_unrevoked_metadata(
+        &self,
+        address: &str,
+        sample_size: usize,
+        revocations: &RevocationSet,
+    ) -> Result<
+        SampleResponse<MetadataPackage, <KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+        SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
+    > {
+        let (base_uris, uris) = self.sample_uris(address, sample_size);
+        let sample_request = SampleRequest {
+            request: GetMetadata,
+            uris,
+        };
+
+        let responses = self.inner_client.clone().oneshot(sample_request).await?;
+        let outcomes = health_outcomes(&base_uris, &responses);
+        self.record_health(outcomes);
+        let sample_response = SampleResponse::select(responses, |metadatas| {
+            select_unrevoked_auth_wrapper(metadatas, revocations)
+        });
+
+        Ok(sample_response)
+    }
+
     /// Collect all peers from keyservers.
     pub async fn collect_peers(
         &self,
@@ -227,9 +506,10 @@ where
         AggregateResponse<Peers, <KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
     > {
-        let uris = self.uris.read().await.clone();
-        let uris = uris
-            .into_iter()
+        let base_uris = self.uris.read().unwrap().clone();
+        let uris = base_uris
+            .iter()
+            .cloned()
             .map(|uri| append_path(uri, "/peers"))
             .collect::<Vec<Uri>>();
         let sample_request = SampleRequest {
@@ -237,6 +517,8 @@ where
             request: GetPeers,
         };
         let responses = self.inner_client.clone().oneshot(sample_request).await?;
+        let outcomes = health_outcomes(&base_uris, &responses);
+        self.record_health(outcomes);
 
         let aggregate_response = AggregateResponse::aggregate(responses, aggregate_peers);
 
@@ -244,6 +526,8 @@ where
     }
 
     /// Crawl peers.
+    ///
+    /// The URLs of the peers found are normalized, see [`normalize_uri`], before being compared.
     #[allow(clippy::mutable_key_type)]
     pub async fn crawl_peers(
         &self,
@@ -251,10 +535,10 @@ where
         AggregateResponse<Peers, <KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
-        let mut found_uris: HashSet<_> = read_uris.iter().cloned().collect();
+        let base_uris = self.uris.read().unwrap().clone();
+        let mut found_uris: HashSet<_> = base_uris.iter().cloned().map(normalize_uri).collect();
 
-        let mut total: HashSet<_> = read_uris.iter().cloned().collect();
+        let mut total = found_uris.clone();
 
         let mut total_errors = Vec::new();
         while !found_uris.is_empty() {
@@ -268,6 +552,8 @@ where
                 request: GetPeers,
             };
             let responses: Vec<_> = self.inner_client.clone().oneshot(sample_request).await?;
+            let outcomes = health_outcomes(&base_uris, &responses);
+            self.record_health(outcomes);
 
             let AggregateResponse { response, errors } =
                 AggregateResponse::aggregate(responses, aggregate_peers);
@@ -280,6 +566,7 @@ where
                 .peers
                 .iter()
                 .filter_map(|peer| peer.url.parse::<Uri>().ok())
+                .map(normalize_uri)
                 .collect();
 
             // Only keep new URIs
@@ -301,7 +588,11 @@ where
         })
     }
 
-    /// Perform a uniform broadcast of metadata over keyservers and select the latest.
+    /// Broadcast metadata to a sample of keyservers.
+    ///
+    /// The keyservers are chosen by the [`Sampler`] of the manager. Fails with
+    /// [`SampleError::InsufficientReplication`] if fewer keyservers accept the metadata than
+    /// required by the [`ReplicationThreshold`].
     pub async fn uniform_broadcast_metadata(
         &self,
         address: &str,
@@ -312,15 +603,12 @@ where
         AggregateResponse<(), <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
-        let uris = uniform_random_sampler(&read_uris, sample_size)
-            .into_iter()
-            .map(|uri| append_path(uri, &format!("/keys/{}", address)))
-            .collect::<Vec<Uri>>();
+        let (read_uris, uris) = self.sample_uris(address, sample_size);
 
         // Construct body
-        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
-        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        let mut raw_auth_wrapper = BytesMut::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap(); // This is safe
+        let raw_auth_wrapper = raw_auth_wrapper.freeze();
 
         let request = PutRawAuthWrapper {
             token,
@@ -328,11 +616,17 @@ where
         };
         let sample_request = SampleRequest { uris, request };
         let responses = self.inner_client.clone().call(sample_request).await?;
+        let outcomes = health_outcomes(&read_uris, &responses);
+        self.record_health(outcomes);
 
-        Ok(AggregateResponse::aggregate(responses, |_| ()))
+        self.replication_threshold.aggregate(responses)
     }
 
-    /// Perform a uniform broadcast of raw metadata over keyservers and select the latest.
+    /// Broadcast raw metadata to a sample of keyservers.
+    ///
+    /// The keyservers are chosen by the [`Sampler`] of the manager. Fails with
+    /// [`SampleError::InsufficientReplication`] if fewer keyservers accept the metadata than
+    /// required by the [`ReplicationThreshold`].
     pub async fn uniform_broadcast_raw_metadata(
         &self,
         address: &str,
@@ -343,19 +637,159 @@ where
         AggregateResponse<(), <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
-        let uris = uniform_random_sampler(&read_uris, sample_size)
-            .into_iter()
-            .map(|uri| append_path(uri, &format!("/keys/{}", address)))
-            .collect::<Vec<Uri>>();
+        let (read_uris, uris) = self.sample_uris(address, sample_size);
 
         let request = PutRawAuthWrapper {
             token,
-            raw_auth_wrapper,
+            raw_auth_wrapper: raw_auth_wrapper.into(),
         };
         let sample_request = SampleRequest { uris, request };
         let responses = self.inner_client.clone().call(sample_request).await?;
+        let outcomes = health_outcomes(&read_uris, &responses);
+        self.record_health(outcomes);
+
+        self.replication_threshold.aggregate(responses)
+    }
+}
 
-        Ok(AggregateResponse::aggregate(responses, |_| ()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::block_on;
+
+    fn manager(uris: &[&str]) -> KeyserverManager<HyperClient<HttpConnector>> {
+        KeyserverManager::new(uris.iter().map(|uri| uri.to_string()).collect()).unwrap()
+    }
+
+    fn record(url: &str, successes: u64, failures: u64) -> PeerRecord {
+        PeerRecord {
+            url: url.to_string(),
+            health: PeerHealth {
+                successes,
+                failures,
+                last_seen: None,
+            },
+        }
+    }
+
+    fn normalize(uri: &'static str) -> String {
+        normalize_uri(Uri::from_static(uri)).to_string()
+    }
+
+    #[test]
+    fn normalize_peer_uris() {
+        assert_eq!(normalize("http://example.com"), "http://example.com/");
+        assert_eq!(normalize("http://example.com/"), "http://example.com/");
+        assert_eq!(
+            normalize("HTTP://Example.COM:80/keys/"),
+            "http://example.com/keys"
+        );
+        assert_eq!(normalize("https://example.com:443"), "https://example.com/");
+        assert_eq!(
+            normalize("https://example.com:8443/?a=1"),
+            "https://example.com:8443/?a=1"
+        );
+        assert_eq!(normalize("example.com:8080"), "http://example.com:8080/");
+
+        // URIs without a host are unchanged
+        assert_eq!(normalize("/keys/"), "/keys/");
+    }
+
+    #[test]
+    fn deduplicate_uris() {
+        let seeded = manager(&[
+            "http://a.example",
+            "https://b.example",
+            "http://A.example:80/",
+        ]);
+        let uris = seeded.get_uris().read().unwrap().clone();
+        assert_eq!(uris, vec!["http://a.example/", "https://b.example/"]);
+    }
+
+    #[test]
+    fn replication_required() {
+        assert_eq!(ReplicationThreshold::default().required(5), 1);
+        assert_eq!(ReplicationThreshold::Count(3).required(5), 3);
+        assert_eq!(ReplicationThreshold::Fraction(0.5).required(5), 3);
+        assert_eq!(ReplicationThreshold::Fraction(0.5).required(4), 2);
+        assert_eq!(ReplicationThreshold::Fraction(2.0).required(4), 4);
+        assert_eq!(ReplicationThreshold::Fraction(-1.0).required(4), 0);
+    }
+
+    #[test]
+    fn replication_aggregate() {
+        let responses = || {
+            vec![
+                (Uri::from_static("http://a.example"), Ok(())),
+                (Uri::from_static("http://b.example"), Err("refused")),
+                (Uri::from_static("http://c.example"), Ok(())),
+            ]
+        };
+
+        let aggregate = ReplicationThreshold::Count(2)
+            .aggregate(responses())
+            .unwrap();
+        assert_eq!(
+            aggregate.errors,
+            vec![(Uri::from_static("http://b.example"), "refused")]
+        );
+
+        match ReplicationThreshold::Fraction(1.0).aggregate(responses()) {
+            Err(SampleError::InsufficientReplication {
+                successes,
+                required,
+                errors,
+            }) => {
+                assert_eq!((successes, required), (2, 3));
+                assert_eq!(
+                    errors,
+                    vec![(Uri::from_static("http://b.example"), "refused")]
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn export_and_import_peers() {
+        let seeded = manager(&["http://a.example"]);
+        let peer_list = PeerList {
+            peers: vec![
+                record("HTTP://A.EXAMPLE:80/", 1, 2),
+                record("https://b.example", 3, 4),
+            ],
+        };
+        block_on(seeded.import_peers(peer_list.clone())).unwrap();
+        block_on(seeded.import_peers(peer_list)).unwrap();
+
+        let exported = block_on(seeded.export_peers());
+        assert_eq!(
+            exported.peers,
+            vec![
+                record("http://a.example/", 2, 4),
+                record("https://b.example/", 6, 8),
+            ]
+        );
+
+        // The exported list seeds another manager
+        let other = manager(&[]);
+        block_on(other.import_peers(exported.clone())).unwrap();
+        assert_eq!(block_on(other.export_peers()), exported);
+    }
+
+    #[test]
+    fn import_invalid_peers() {
+        let seeded = manager(&["http://a.example"]);
+        let peer_list = PeerList {
+            peers: vec![
+                record("https://b.example", 1, 0),
+                record("http://a b", 1, 0),
+            ],
+        };
+        assert!(block_on(seeded.import_peers(peer_list)).is_err());
+        assert_eq!(
+            block_on(seeded.export_peers()).peers,
+            vec![record("http://a.example/", 0, 0)]
+        );
     }
 }
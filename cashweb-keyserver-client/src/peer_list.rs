@@ -0,0 +1,100 @@
+//! This module contains the [`PeerList`] struct which allows the peer set of a
+//! [`KeyserverManager`] to be exported and imported along with its health statistics.
+//!
+//! When the `serde` feature is enabled the [`PeerList`] implements
+//! [`Serialize`](serde::Serialize) and [`Deserialize`](serde::Deserialize), allowing operators to
+//! seed new deployments and share curated keyserver lists.
+//!
+//! [`KeyserverManager`]: crate::KeyserverManager
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The health statistics of a keyserver.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct PeerHealth {
+    /// The number of successful requests.
+    pub successes: u64,
+    /// The number of failed requests.
+    pub failures: u64,
+    /// The time of the last successful request. Given in unix time milliseconds.
+    pub last_seen: Option<u64>,
+}
+
+impl PeerHealth {
+    /// Record a successful request at the current time.
+    pub fn record_success(&mut self) {
+        self.successes += 1;
+        self.last_seen = Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap() // This is safe
+                .as_millis() as u64,
+        );
+    }
+
+    /// Record a failed request.
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /// Merge the statistics of another record, keeping the latest `last_seen`.
+    pub fn merge(&mut self, other: &PeerHealth) {
+        self.successes += other.successes;
+        self.failures += other.failures;
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+/// A keyserver paired with its health statistics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PeerRecord {
+    /// The URL of the keyserver.
+    pub url: String,
+    /// The health statistics of the keyserver.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub health: PeerHealth,
+}
+
+/// An exported set of keyservers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PeerList {
+    /// The keyservers and their health statistics.
+    pub peers: Vec<PeerRecord>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_merge() {
+        let mut health = PeerHealth::default();
+        health.record_failure();
+        assert_eq!(health.last_seen, None);
+        health.record_success();
+        assert_eq!((health.successes, health.failures), (1, 1));
+        assert!(health.last_seen.is_some());
+
+        let mut other = PeerHealth {
+            successes: 2,
+            failures: 3,
+            last_seen: Some(u64::MAX),
+        };
+        other.merge(&health);
+        assert_eq!(
+            other,
+            PeerHealth {
+                successes: 3,
+                failures: 4,
+                last_seen: Some(u64::MAX),
+            }
+        );
+    }
+}
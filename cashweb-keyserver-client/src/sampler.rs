@@ -0,0 +1,218 @@
+//! This module contains the [`Sampler`] trait which selects the keyservers queried by a
+//! [`KeyserverManager`], along with the [`UniformSampler`], [`WeightedSampler`] and
+//! [`StickySampler`] implementations.
+//!
+//! [`KeyserverManager`]: crate::KeyserverManager
+
+use std::{collections::HashMap, fmt};
+
+use hyper::Uri;
+use rand::Rng;
+use ring::digest::{Context, SHA256};
+
+use crate::{manager::uniform_random_sampler, peer_list::PeerHealth};
+
+/// Selects a subset of keyservers to query.
+pub trait Sampler: fmt::Debug + Send + Sync {
+    /// Choose at most `size` of the `uris`.
+    ///
+    /// The `health` is keyed by the string representation of each [`Uri`] and `address` is the
+    /// address being queried.
+    fn sample(
+        &self,
+        uris: &[Uri],
+        health: &HashMap<String, PeerHealth>,
+        address: &str,
+        size: usize,
+    ) -> Vec<Uri>;
+}
+
+/// Chooses keyservers uniformly at random.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformSampler;
+
+impl Sampler for UniformSampler {
+    fn sample(
+        &self,
+        uris: &[Uri],
+        _health: &HashMap<String, PeerHealth>,
+        _address: &str,
+        size: usize,
+    ) -> Vec<Uri> {
+        uniform_random_sampler(uris, size)
+    }
+}
+
+/// Chooses keyservers at random, weighted by the proportion of their requests which succeeded.
+///
+/// Keyservers without health statistics are weighted as though they had succeeded half of their
+/// requests, so that newly discovered keyservers are still queried.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WeightedSampler;
+
+impl WeightedSampler {
+    fn weight(health: Option<&PeerHealth>) -> f64 {
+        let (successes, failures) = health
+            .map(|health| (health.successes, health.failures))
+            .unwrap_or_default();
+        (successes as f64 + 1.) / ((successes + failures) as f64 + 2.)
+    }
+}
+
+impl Sampler for WeightedSampler {
+    fn sample(
+        &self,
+        uris: &[Uri],
+        health: &HashMap<String, PeerHealth>,
+        _address: &str,
+        size: usize,
+    ) -> Vec<Uri> {
+        // Weighted sampling without replacement, see Efraimidis and Spirakis
+        let mut rng = rand::thread_rng();
+        let mut keyed: Vec<(f64, &Uri)> = uris
+            .iter()
+            .map(|uri| {
+                let weight = Self::weight(health.get(&uri.to_string()));
+                let key = rng.gen::<f64>().powf(1. / weight);
+                (key, uri)
+            })
+            .collect();
+        keyed.sort_by(|(key_a, _), (key_b, _)| key_b.partial_cmp(key_a).unwrap()); // This is safe
+        keyed
+            .into_iter()
+            .take(size)
+            .map(|(_, uri)| uri.clone())
+            .collect()
+    }
+}
+
+/// Chooses the same keyservers for a given address, while the set of keyservers is unchanged.
+///
+/// Keyservers are ranked by a digest of the seed, the address and their [`Uri`]. Adding or removing
+/// a keyserver only affects the addresses for which it ranks within the sample. Distinct seeds
+/// should be used by distinct clients to avoid every client querying the same keyservers.
+#[derive(Clone, Debug, Default)]
+pub struct StickySampler {
+    seed: Vec<u8>,
+}
+
+impl StickySampler {
+    /// Create a new [`StickySampler`] from a seed.
+    pub fn new(seed: Vec<u8>) -> Self {
+        Self { seed }
+    }
+
+    fn rank(&self, address: &str, uri: &Uri) -> Vec<u8> {
+        let mut context = Context::new(&SHA256);
+        context.update(&self.seed);
+        context.update(address.as_bytes());
+        context.update(uri.to_string().as_bytes());
+        context.finish().as_ref().to_vec()
+    }
+}
+
+impl Sampler for StickySampler {
+    fn sample(
+        &self,
+        uris: &[Uri],
+        _health: &HashMap<String, PeerHealth>,
+        address: &str,
+        size: usize,
+    ) -> Vec<Uri> {
+        let mut ranked: Vec<(Vec<u8>, &Uri)> = uris
+            .iter()
+            .map(|uri| (self.rank(address, uri), uri))
+            .collect();
+        ranked.sort_by(|(rank_a, _), (rank_b, _)| rank_a.cmp(rank_b));
+        ranked
+            .into_iter()
+            .take(size)
+            .map(|(_, uri)| uri.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(count: usize) -> Vec<Uri> {
+        (0..count)
+            .map(|i| format!("http://{}.example/", i).parse().unwrap())
+            .collect()
+    }
+
+    fn assert_distinct_subset(sample: &[Uri], uris: &[Uri]) {
+        for (i, uri) in sample.iter().enumerate() {
+            assert!(uris.contains(uri));
+            assert!(!sample[..i].contains(uri));
+        }
+    }
+
+    #[test]
+    fn sample_size() {
+        let uris = uris(5);
+        let health = HashMap::new();
+        let samplers: [&dyn Sampler; 3] = [
+            &UniformSampler,
+            &WeightedSampler,
+            &StickySampler::new(b"seed".to_vec()),
+        ];
+        for sampler in samplers.iter() {
+            let sample = sampler.sample(&uris, &health, "address", 3);
+            assert_eq!(sample.len(), 3);
+            assert_distinct_subset(&sample, &uris);
+
+            let sample = sampler.sample(&uris, &health, "address", 10);
+            assert_eq!(sample.len(), 5);
+            assert_distinct_subset(&sample, &uris);
+        }
+    }
+
+    #[test]
+    fn weights() {
+        assert_eq!(WeightedSampler::weight(None), 0.5);
+        let healthy = PeerHealth {
+            successes: 8,
+            failures: 0,
+            last_seen: None,
+        };
+        assert_eq!(WeightedSampler::weight(Some(&healthy)), 0.9);
+        let unhealthy = PeerHealth {
+            successes: 0,
+            failures: 8,
+            last_seen: None,
+        };
+        assert_eq!(WeightedSampler::weight(Some(&unhealthy)), 0.1);
+    }
+
+    #[test]
+    fn sticky() {
+        let uris = uris(10);
+        let health = HashMap::new();
+        let sampler = StickySampler::new(b"seed".to_vec());
+        let sample = sampler.sample(&uris, &health, "address", 3);
+        assert_eq!(sampler.sample(&uris, &health, "address", 3), sample);
+
+        // Removing a keyserver outside of the sample leaves it unchanged
+        let remaining: Vec<Uri> = uris
+            .iter()
+            .filter(|uri| !sample.contains(uri))
+            .skip(1)
+            .chain(sample.iter())
+            .cloned()
+            .collect();
+        assert_eq!(sampler.sample(&remaining, &health, "address", 3), sample);
+
+        // Distinct seeds and addresses rank keyservers independently
+        let other_seed = StickySampler::new(b"other seed".to_vec());
+        assert_ne!(
+            other_seed.sample(&uris, &health, "address", 10),
+            sampler.sample(&uris, &health, "address", 10)
+        );
+        assert_ne!(
+            sampler.sample(&uris, &health, "other address", 10),
+            sampler.sample(&uris, &health, "address", 10)
+        );
+    }
+}
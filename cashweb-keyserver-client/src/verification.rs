@@ -0,0 +1,262 @@
+//! This module contains the [`BlockchainBackend`] trait and methods for locally verifying the
+//! chain commitment tokens attached to a [`MetadataPackage`].
+//!
+//! A keyserver attaches the POP token proving the metadata was committed to on-chain. Verifying
+//! the token locally allows clients to detect keyservers serving stale tokens, whose commitment
+//! does not match the returned metadata.
+//!
+//! A token may also be checked against a maximum age, see
+//! [`verify_token_age`](MetadataPackage::verify_token_age), detecting keyservers which repeatedly
+//! serve an old commitment.
+
+use std::{convert::TryInto, fmt, future::Future, pin::Pin};
+
+use bitcoin::{
+    prelude::{Transaction, TransactionDecodeError},
+    signing::public_key_hash,
+    Decodable,
+};
+use bitcoin_client::{BitcoinClient, NodeError};
+use hyper::{Body, Request, Response};
+use thiserror::Error;
+use token::{
+    schemes::chain_commitment::{construct_commitment, construct_commitment_script},
+    split_pop_token,
+};
+use tower_service::Service;
+
+use crate::MetadataPackage;
+
+type FutTransaction<'a, Error> = Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>>;
+type FutBlockTime<'a, Error> =
+    Pin<Box<dyn Future<Output = Result<Option<u64>, Error>> + Send + 'a>>;
+
+/// Provides the transactions required to verify chain commitment tokens.
+pub trait BlockchainBackend {
+    /// Error associated with fetching a transaction.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Fetch a raw transaction by its transaction ID.
+    fn get_raw_transaction<'a>(&'a self, tx_id: &'a [u8]) -> FutTransaction<'a, Self::Error>;
+
+    /// Fetch the time, in unix seconds, of the block containing a transaction.
+    ///
+    /// This is `None` when the transaction is unconfirmed. By default every transaction is treated
+    /// as unconfirmed, hence considered fresh by
+    /// [`verify_token_age`](MetadataPackage::verify_token_age).
+    fn get_block_time<'a>(&'a self, _tx_id: &'a [u8]) -> FutBlockTime<'a, Self::Error> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+impl<S> BlockchainBackend for BitcoinClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone,
+    S: Send + Sync,
+    S::Error: fmt::Debug + fmt::Display + 'static,
+    S::Future: Send + 'static,
+{
+    type Error = NodeError<S::Error>;
+
+    fn get_raw_transaction<'a>(&'a self, tx_id: &'a [u8]) -> FutTransaction<'a, Self::Error> {
+        Box::pin(BitcoinClient::get_raw_transaction(self, tx_id))
+    }
+
+    fn get_block_time<'a>(&'a self, tx_id: &'a [u8]) -> FutBlockTime<'a, Self::Error> {
+        Box::pin(async move {
+            let transaction = self.get_raw_transaction_verbose(tx_id).await?;
+            Ok(transaction.blocktime)
+        })
+    }
+}
+
+/// Error associated with locally verifying a chain commitment token.
+#[derive(Debug, Error)]
+pub enum TokenVerificationError<E: fmt::Debug + fmt::Display> {
+    /// Failed to decode token.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Token was unexpected length.
+    #[error("unexpected token length")]
+    TokenLength,
+    /// Error occured when fetching the commitment transaction.
+    #[error("backend failure: {0}")]
+    Backend(E),
+    /// Error decoding the commitment transaction.
+    #[error("failed to decode transaction: {0}")]
+    Transaction(TransactionDecodeError),
+    /// Specified output did not exist.
+    #[error("output missing")]
+    OutputNotFound,
+    /// The commitment did not match the metadata, the token may be stale.
+    #[error("commitment mismatch")]
+    CommitmentMismatch,
+    /// The commitment transaction was confirmed before the oldest permitted time.
+    #[error("stale token: block time {block_time} < {oldest}")]
+    StaleToken {
+        /// The time of the block containing the commitment transaction, in unix seconds.
+        block_time: u64,
+        /// The oldest permitted time, in unix seconds.
+        oldest: u64,
+    },
+}
+
+impl MetadataPackage {
+    /// Decode the commitment outpoint, the transaction ID and output index, from the POP token.
+    pub(crate) fn token_outpoint<E: fmt::Debug + fmt::Display>(
+        &self,
+    ) -> Result<([u8; 32], u32), TokenVerificationError<E>> {
+        let token = split_pop_token(&self.token).unwrap_or(&self.token);
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let outpoint_raw = base64::decode_config(token, url_safe_config)
+            .map_err(TokenVerificationError::Base64)?;
+        if outpoint_raw.len() != 32 + 4 {
+            return Err(TokenVerificationError::TokenLength);
+        }
+        let tx_id = outpoint_raw[..32].try_into().unwrap(); // This is safe
+        let vout = u32::from_le_bytes(outpoint_raw[32..].try_into().unwrap()); // This is safe
+        Ok((tx_id, vout))
+    }
+
+    /// Verify the chain commitment token commits to the public key and metadata of the package.
+    pub async fn verify_token<B: BlockchainBackend>(
+        &self,
+        backend: &B,
+    ) -> Result<(), TokenVerificationError<B::Error>> {
+        let (tx_id, vout) = self.token_outpoint()?;
+
+        // Get transaction
+        let raw_transaction = backend
+            .get_raw_transaction(&tx_id)
+            .await
+            .map_err(TokenVerificationError::Backend)?;
+        let transaction = Transaction::decode(&mut raw_transaction.as_slice())
+            .map_err(TokenVerificationError::Transaction)?;
+        let output = transaction
+            .outputs
+            .get(vout as usize)
+            .ok_or(TokenVerificationError::OutputNotFound)?;
+
+        // Check commitment
+        let commitment =
+            construct_commitment(&public_key_hash(&self.public_key), &self.payload_digest);
+        if output.script != construct_commitment_script(&commitment) {
+            return Err(TokenVerificationError::CommitmentMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use bitcoin::{
+        prelude::{Input, Output},
+        Encodable,
+    };
+    use token::schemes::chain_commitment::{construct_commitment_output, construct_token};
+
+    use super::*;
+    use crate::tests::{block_on, package};
+
+    /// Serves transactions along with the time of the block containing them.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockBackend {
+        transactions: HashMap<Vec<u8>, (Vec<u8>, Option<u64>)>,
+    }
+
+    impl MockBackend {
+        pub(crate) fn insert(
+            &mut self,
+            tx_id: [u8; 32],
+            transaction: &Transaction,
+            block_time: Option<u64>,
+        ) {
+            self.transactions
+                .insert(tx_id.to_vec(), (transaction.encode_to_vec(), block_time));
+        }
+    }
+
+    impl BlockchainBackend for MockBackend {
+        type Error = &'static str;
+
+        fn get_raw_transaction<'a>(&'a self, tx_id: &'a [u8]) -> FutTransaction<'a, Self::Error> {
+            let result = self
+                .transactions
+                .get(tx_id)
+                .map(|(raw_transaction, _)| raw_transaction.clone())
+                .ok_or("missing transaction");
+            Box::pin(async move { result })
+        }
+
+        fn get_block_time<'a>(&'a self, tx_id: &'a [u8]) -> FutBlockTime<'a, Self::Error> {
+            let result = self
+                .transactions
+                .get(tx_id)
+                .map(|(_, block_time)| *block_time)
+                .ok_or("missing transaction");
+            Box::pin(async move { result })
+        }
+    }
+
+    /// A transaction whose second output commits to the package.
+    pub(crate) fn commitment_transaction(package: &MetadataPackage) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![Input::default()],
+            outputs: vec![
+                Output::default(),
+                construct_commitment_output(
+                    &public_key_hash(&package.public_key),
+                    &package.payload_digest,
+                ),
+            ],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn verify_token() {
+        let package = package(0, format!("POP {}", construct_token(&[3; 32], 1)));
+        let mut backend = MockBackend::default();
+        backend.insert([3; 32], &commitment_transaction(&package), None);
+        block_on(package.verify_token(&backend)).unwrap();
+    }
+
+    #[test]
+    fn stale_commitment() {
+        let mut package = package(0, construct_token(&[3; 32], 1));
+        let mut backend = MockBackend::default();
+        backend.insert([3; 32], &commitment_transaction(&package), None);
+
+        package.payload_digest = [4; 32];
+        assert!(matches!(
+            block_on(package.verify_token(&backend)),
+            Err(TokenVerificationError::CommitmentMismatch)
+        ));
+    }
+
+    #[test]
+    fn invalid_outpoint() {
+        let mut backend = MockBackend::default();
+        let missing_output = package(0, construct_token(&[3; 32], 2));
+        backend.insert([3; 32], &commitment_transaction(&missing_output), None);
+        assert!(matches!(
+            block_on(missing_output.verify_token(&backend)),
+            Err(TokenVerificationError::OutputNotFound)
+        ));
+
+        let missing_transaction = package(0, construct_token(&[4; 32], 1));
+        assert!(matches!(
+            block_on(missing_transaction.verify_token(&backend)),
+            Err(TokenVerificationError::Backend("missing transaction"))
+        ));
+
+        let short_token = package(0, "POP AAAA".to_string());
+        assert!(matches!(
+            block_on(short_token.verify_token(&backend)),
+            Err(TokenVerificationError::TokenLength)
+        ));
+    }
+}
@@ -0,0 +1,26 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-keyserver-server` is a library providing components for implementing servers within
+//! the [`Keyserver Protocol`].
+//!
+//! The [`PutValidator`] is a [`Service`](tower_service::Service) accepting the bodies of incoming
+//! PUT requests. It decodes, parses and verifies the [`AuthWrapper`](auth_wrapper::AuthWrapper),
+//! checks the public key is bound to the address being written and decodes the
+//! [`AddressMetadata`](keyserver::AddressMetadata), producing either a [`MetadataRecord`] or a
+//! structured [`Rejection`].
+//!
+//! Records are persisted by a [`MetadataStore`], keyed by public key hash, which only accepts
+//! records more recent than the one it holds.
+//!
+//! [`Keyserver Protocol`]: https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki
+
+pub mod store;
+pub mod validation;
+
+pub use store::{check_monotonic, MemoryMetadataStore, MetadataRecord, MetadataStore};
+pub use validation::{MetadataPut, PutValidator, Rejection};
@@ -0,0 +1,146 @@
+//! This module contains the [`MetadataStore`] trait which persists the latest [`MetadataRecord`]
+//! for each public key hash.
+//!
+//! The `timestamp` of the [`AddressMetadata`] orders the records for a public key hash. A store
+//! must never replace a record with one which is not strictly more recent, preventing old, but
+//! validly signed, metadata from being replayed. Resubmitting the held record is permitted. A
+//! [`MemoryMetadataStore`] is provided.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+use keyserver::AddressMetadata;
+use secp256k1::key::PublicKey;
+
+/// The [`AddressMetadata`] paired with its [`PublicKey`] and the raw [`AuthWrapper`] it was
+/// extracted from.
+///
+/// [`AuthWrapper`]: auth_wrapper::AuthWrapper
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataRecord {
+    /// Public key of the metadata.
+    pub public_key: PublicKey,
+    /// The address metadata.
+    pub metadata: AddressMetadata,
+    /// The digest of the [`AuthWrapper`](auth_wrapper::AuthWrapper) payload.
+    pub payload_digest: [u8; 32],
+    /// The raw [`AuthWrapper`](auth_wrapper::AuthWrapper), served in response to GET requests.
+    pub raw_auth_wrapper: Bytes,
+}
+
+impl MetadataRecord {
+    /// The timestamp of the metadata, in milliseconds.
+    pub fn timestamp(&self) -> i64 {
+        self.metadata.timestamp
+    }
+}
+
+/// A store persisting the latest [`MetadataRecord`] for each public key hash.
+pub trait MetadataStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get the [`MetadataRecord`] held for a public key hash.
+    fn get(&self, pubkey_hash: &[u8; 20]) -> Result<Option<MetadataRecord>, Self::Error>;
+
+    /// Put a [`MetadataRecord`] for a public key hash.
+    ///
+    /// If the held record has a different payload digest and a timestamp at least as recent then
+    /// its timestamp is returned and nothing is stored. Otherwise the record is stored.
+    fn put(
+        &self,
+        pubkey_hash: &[u8; 20],
+        record: MetadataRecord,
+    ) -> Result<Option<i64>, Self::Error>;
+}
+
+/// Check whether the `proposed` record may replace the `current` record, returning the timestamp of
+/// the `current` record if not.
+pub fn check_monotonic(current: &MetadataRecord, proposed: &MetadataRecord) -> Option<i64> {
+    if current.payload_digest != proposed.payload_digest
+        && current.timestamp() >= proposed.timestamp()
+    {
+        Some(current.timestamp())
+    } else {
+        None
+    }
+}
+
+/// An in-memory [`MetadataStore`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMetadataStore {
+    records: Arc<RwLock<HashMap<[u8; 20], MetadataRecord>>>,
+}
+
+impl MemoryMetadataStore {
+    /// Create a new, empty, [`MemoryMetadataStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl MetadataStore for MemoryMetadataStore {
+    type Error = Infallible;
+
+    fn get(&self, pubkey_hash: &[u8; 20]) -> Result<Option<MetadataRecord>, Self::Error> {
+        Ok(self.records.read().unwrap().get(pubkey_hash).cloned())
+    }
+
+    fn put(
+        &self,
+        pubkey_hash: &[u8; 20],
+        record: MetadataRecord,
+    ) -> Result<Option<i64>, Self::Error> {
+        let mut records = self.records.write().unwrap();
+        if let Some(current) = records.get(pubkey_hash) {
+            if let Some(timestamp) = check_monotonic(current, &record) {
+                return Ok(Some(timestamp));
+            }
+        }
+        records.insert(*pubkey_hash, record);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{key::SecretKey as PrivateKey, Secp256k1};
+
+    use super::*;
+
+    fn record(timestamp: i64, digest: u8) -> MetadataRecord {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        MetadataRecord {
+            public_key: PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key),
+            metadata: AddressMetadata {
+                timestamp,
+                ..Default::default()
+            },
+            payload_digest: [digest; 32],
+            raw_auth_wrapper: Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn monotonic() {
+        let store = MemoryMetadataStore::new();
+        assert_eq!(store.put(&[0; 20], record(10, 1)).unwrap(), None);
+
+        // Resubmission is permitted
+        assert_eq!(store.put(&[0; 20], record(10, 1)).unwrap(), None);
+
+        // Stale and conflicting records are rejected
+        assert_eq!(store.put(&[0; 20], record(5, 2)).unwrap(), Some(10));
+        assert_eq!(store.put(&[0; 20], record(10, 2)).unwrap(), Some(10));
+        assert_eq!(store.get(&[0; 20]).unwrap(), Some(record(10, 1)));
+
+        assert_eq!(store.put(&[0; 20], record(20, 2)).unwrap(), None);
+        assert_eq!(store.get(&[0; 20]).unwrap().unwrap().timestamp(), 20);
+        assert_eq!(store.get(&[1; 20]).unwrap(), None);
+    }
+}
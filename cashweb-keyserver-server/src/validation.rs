@@ -0,0 +1,251 @@
+//! This module contains the [`PutValidator`], a [`Service`] accepting or rejecting the bodies of
+//! incoming PUT requests.
+//!
+//! The body is validated as the keyserver clients expect to receive it in response to GET requests:
+//! 1. The body is checked against the maximum size.
+//! 2. The [`AuthWrapper`] is decoded, parsed and its signature verified.
+//! 3. The public key is checked against the public key hash of the address being written.
+//! 4. The payload is decoded as [`AddressMetadata`].
+//!
+//! Whether the metadata is more recent than the held metadata is left to the
+//! [`MetadataStore`](crate::MetadataStore).
+
+use std::pin::Pin;
+
+use auth_wrapper::{AuthWrapper, ParseError, VerifyError};
+use bitcoin::signing::public_key_hash;
+use bytes::Bytes;
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::StatusCode;
+use keyserver::AddressMetadata;
+use prost::{DecodeError, Message as _};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::store::MetadataRecord;
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The default maximum length, in bytes, of a serialized [`AuthWrapper`].
+pub const DEFAULT_MAX_AUTH_WRAPPER_SIZE: usize = 512 * 1024;
+
+/// The body of a PUT request paired with the public key hash of the address being written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataPut {
+    /// The public key hash of the address.
+    pub pubkey_hash: [u8; 20],
+    /// The raw [`AuthWrapper`].
+    pub raw_auth_wrapper: Bytes,
+}
+
+/// The reason the body of a PUT request was rejected.
+#[derive(Debug, Error)]
+pub enum Rejection {
+    /// The serialized [`AuthWrapper`] exceeded the maximum size.
+    #[error("authwrapper too large: {size} > {limit}")]
+    TooLarge {
+        /// The length, in bytes, of the serialized [`AuthWrapper`].
+        size: usize,
+        /// The maximum length, in bytes.
+        limit: usize,
+    },
+    /// Error while decoding the [`AuthWrapper`].
+    #[error("authwrapper decoding failure: {0}")]
+    Decode(DecodeError),
+    /// Error while parsing the [`AuthWrapper`].
+    #[error("authwrapper parsing failure: {0}")]
+    Parse(ParseError),
+    /// Error while verifying the [`AuthWrapper`].
+    #[error("authwrapper verification failure: {0}")]
+    Verify(VerifyError),
+    /// The [`AuthWrapper`] was missing its payload.
+    #[error("payload missing")]
+    PayloadMissing,
+    /// The public key did not match the public key hash of the address.
+    #[error("public key does not match address")]
+    AddressMismatch,
+    /// Error while decoding the [`AddressMetadata`].
+    #[error("metadata decoding failure: {0}")]
+    MetadataDecode(DecodeError),
+}
+
+impl Rejection {
+    /// A short, static label identifying the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::TooLarge { .. } => "too_large",
+            Self::Decode(_) => "decode",
+            Self::Parse(_) => "parse",
+            Self::Verify(_) => "verify",
+            Self::PayloadMissing => "payload_missing",
+            Self::AddressMismatch => "address_mismatch",
+            Self::MetadataDecode(_) => "metadata_decode",
+        }
+    }
+
+    /// The HTTP status code appropriate for the rejection.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Verify(_) | Self::AddressMismatch => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// A [`Service`] validating the bodies of incoming PUT requests, producing a [`MetadataRecord`] or
+/// a [`Rejection`].
+#[derive(Clone, Debug)]
+pub struct PutValidator {
+    max_size: usize,
+}
+
+impl Default for PutValidator {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_AUTH_WRAPPER_SIZE,
+        }
+    }
+}
+
+impl PutValidator {
+    /// Create a new [`PutValidator`] using the [`DEFAULT_MAX_AUTH_WRAPPER_SIZE`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the maximum length, in bytes, of the serialized [`AuthWrapper`].
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Validate the body of an incoming PUT request.
+    pub fn validate(&self, put: MetadataPut) -> Result<MetadataRecord, Rejection> {
+        // Check limit
+        let size = put.raw_auth_wrapper.len();
+        if size > self.max_size {
+            return Err(Rejection::TooLarge {
+                size,
+                limit: self.max_size,
+            });
+        }
+
+        // Decode, parse and verify
+        let auth_wrapper =
+            AuthWrapper::decode(put.raw_auth_wrapper.clone()).map_err(Rejection::Decode)?;
+        let parsed_auth_wrapper = auth_wrapper.parse().map_err(Rejection::Parse)?;
+        parsed_auth_wrapper.verify().map_err(Rejection::Verify)?;
+        if parsed_auth_wrapper.payload.is_empty() {
+            return Err(Rejection::PayloadMissing);
+        }
+
+        // Check address binding
+        if public_key_hash(&parsed_auth_wrapper.public_key) != put.pubkey_hash {
+            return Err(Rejection::AddressMismatch);
+        }
+
+        let metadata = AddressMetadata::decode(&mut parsed_auth_wrapper.payload.as_slice())
+            .map_err(Rejection::MetadataDecode)?;
+
+        Ok(MetadataRecord {
+            public_key: parsed_auth_wrapper.public_key,
+            metadata,
+            payload_digest: parsed_auth_wrapper.payload_digest,
+            raw_auth_wrapper: put.raw_auth_wrapper,
+        })
+    }
+}
+
+impl Service<MetadataPut> for PutValidator {
+    type Response = MetadataRecord;
+    type Error = Rejection;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, put: MetadataPut) -> Self::Future {
+        let result = self.validate(put);
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use auth_wrapper::SignatureScheme;
+    use ring::digest::{digest, SHA256};
+    use secp256k1::{
+        key::{PublicKey, SecretKey as PrivateKey},
+        Message, Secp256k1,
+    };
+
+    use super::*;
+
+    fn put(timestamp: i64) -> (PublicKey, Bytes) {
+        let secp = Secp256k1::signing_only();
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &private_key);
+
+        let mut payload = Vec::new();
+        AddressMetadata {
+            timestamp,
+            ..Default::default()
+        }
+        .encode(&mut payload)
+        .unwrap();
+        let msg = Message::from_slice(digest(&SHA256, &payload).as_ref()).unwrap();
+
+        let mut raw_auth_wrapper = Vec::new();
+        AuthWrapper {
+            public_key: public_key.serialize().to_vec(),
+            signature: secp.sign(&msg, &private_key).serialize_compact().to_vec(),
+            scheme: SignatureScheme::Ecdsa as i32,
+            payload,
+            ..Default::default()
+        }
+        .encode(&mut raw_auth_wrapper)
+        .unwrap();
+        (public_key, raw_auth_wrapper.into())
+    }
+
+    #[test]
+    fn accept() {
+        let (public_key, raw_auth_wrapper) = put(10);
+        let record = PutValidator::new()
+            .validate(MetadataPut {
+                pubkey_hash: public_key_hash(&public_key),
+                raw_auth_wrapper,
+            })
+            .unwrap();
+        assert_eq!(record.public_key, public_key);
+        assert_eq!(record.timestamp(), 10);
+    }
+
+    #[test]
+    fn reject() {
+        let (public_key, raw_auth_wrapper) = put(10);
+        let rejection = PutValidator::new()
+            .validate(MetadataPut {
+                pubkey_hash: [0; 20],
+                raw_auth_wrapper: raw_auth_wrapper.clone(),
+            })
+            .unwrap_err();
+        assert!(matches!(rejection, Rejection::AddressMismatch));
+        assert_eq!(rejection.status_code(), StatusCode::UNAUTHORIZED);
+
+        let rejection = PutValidator::new()
+            .with_max_size(8)
+            .validate(MetadataPut {
+                pubkey_hash: public_key_hash(&public_key),
+                raw_auth_wrapper,
+            })
+            .unwrap_err();
+        assert_eq!(rejection.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
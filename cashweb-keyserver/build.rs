@@ -1,3 +1,37 @@
+/// The messages, which use the proto3 JSON field names.
+const MESSAGES: &[&str] = &[
+    ".keyserver.Header",
+    ".keyserver.Entry",
+    ".keyserver.AddressMetadata",
+    ".keyserver.Peer",
+    ".keyserver.Peers",
+];
+
+/// The fields, paired with the module in `crate::json` implementing their proto3 JSON mapping.
+const FIELDS: &[(&str, &str)] = &[
+    (".keyserver.Entry.body", "bytes"),
+    (".keyserver.AddressMetadata.timestamp", "int64"),
+    (".keyserver.AddressMetadata.ttl", "int64"),
+];
+
 fn main() {
-    prost_build::compile_protos(&["src/proto/keyserver.proto"], &["src/"]).unwrap();
+    let mut config = prost_build::Config::new();
+
+    // Derive serde traits on all models when the serde feature is enabled
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+        for message in MESSAGES {
+            config.type_attribute(message, "#[serde(default, rename_all = \"camelCase\")]");
+        }
+        for (field, module) in FIELDS {
+            config.field_attribute(
+                field,
+                format!("#[serde(with = \"crate::json::{}\")]", module),
+            );
+        }
+    }
+
+    config
+        .compile_protos(&["src/proto/keyserver.proto"], &["src/"])
+        .unwrap();
 }
@@ -0,0 +1,39 @@
+//! This module contains the canonical [`proto3 JSON mapping`] of the protobuf models.
+//!
+//! When the `serde` feature is enabled the models implement [`Serialize`](serde::Serialize) and
+//! [`Deserialize`](serde::Deserialize) following the mapping: field names are `lowerCamelCase`,
+//! `bytes` are base64 encoded, 64-bit integers are strings and enums are their variant names.
+//! Deserialization additionally accepts URL-safe base64, numeric 64-bit integers and numeric enums.
+//!
+//! The mapping is implemented by the helpers of [`proto_json`], the `json` feature re-exports its
+//! [`to_json`] and [`from_json`] helpers.
+//!
+//! [`proto3 JSON mapping`]: https://developers.google.com/protocol-buffers/docs/proto3#json
+
+pub(crate) use proto_json::{bytes, int64};
+#[cfg(feature = "json")]
+pub use proto_json::{from_json, to_json};
+
+#[cfg(test)]
+mod tests {
+    use crate::{AddressMetadata, Entry};
+
+    #[test]
+    fn derive_serde() {
+        let metadata = AddressMetadata {
+            timestamp: 1_600_000_000_000,
+            ttl: 60_000,
+            entries: vec![Entry {
+                kind: "vcard".to_string(),
+                headers: Vec::new(),
+                body: b"body".to_vec(),
+            }],
+        };
+        let value = serde_json::to_value(&metadata).unwrap();
+        assert!(value["entries"][0].get("kind").is_some());
+        assert_eq!(
+            serde_json::from_value::<AddressMetadata>(value).unwrap(),
+            metadata
+        );
+    }
+}
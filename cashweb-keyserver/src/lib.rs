@@ -1,3 +1,6 @@
 #![warn(missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
 
+#[cfg(feature = "serde")]
+pub mod json;
+
 include!(concat!(env!("OUT_DIR"), "/keyserver.rs"));
@@ -1,3 +1,46 @@
+/// The messages, which use the proto3 JSON field names.
+const MESSAGES: &[&str] = &[
+    ".bip70.Output",
+    ".bip70.PaymentDetails",
+    ".bip70.PaymentRequest",
+    ".bip70.X509Certificates",
+    ".bip70.Payment",
+    ".bip70.PaymentACK",
+];
+
+/// The fields, paired with the module in `crate::json` implementing their proto3 JSON mapping.
+const FIELDS: &[(&str, &str)] = &[
+    (".bip70.Output.amount", "optional_uint64"),
+    (".bip70.Output.script", "bytes"),
+    (".bip70.PaymentDetails.time", "uint64"),
+    (".bip70.PaymentDetails.expires", "optional_uint64"),
+    (".bip70.PaymentDetails.merchant_data", "optional_bytes"),
+    (".bip70.PaymentRequest.pki_data", "optional_bytes"),
+    (".bip70.PaymentRequest.serialized_payment_details", "bytes"),
+    (".bip70.PaymentRequest.signature", "optional_bytes"),
+    (".bip70.X509Certificates.certificate", "repeated_bytes"),
+    (".bip70.Payment.merchant_data", "optional_bytes"),
+    (".bip70.Payment.transactions", "repeated_bytes"),
+];
+
 fn main() {
-    prost_build::compile_protos(&["src/proto/paymentrequest.proto"], &["src/"]).unwrap();
+    let mut config = prost_build::Config::new();
+
+    // Derive serde traits on all models when the serde feature is enabled
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+        for message in MESSAGES {
+            config.type_attribute(message, "#[serde(default, rename_all = \"camelCase\")]");
+        }
+        for (field, module) in FIELDS {
+            config.field_attribute(
+                field,
+                format!("#[serde(with = \"crate::json::{}\")]", module),
+            );
+        }
+    }
+
+    config
+        .compile_protos(&["src/proto/paymentrequest.proto"], &["src/"])
+        .unwrap();
 }
@@ -0,0 +1,114 @@
+//! This module contains helpers for constructing and reading [`PaymentAck`] responses carrying a
+//! POP token.
+//!
+//! The issued token is placed in the `Authorization` header, following the `POP` scheme, which is
+//! where the keyserver and relay clients expect to find it. The serialized [`PaymentAck`] forms
+//! the body, its memo is intended for display to the payer and never contains the token.
+
+use bytes::Bytes;
+use http::header::{HeaderMap, HeaderValue, InvalidHeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use prost::{DecodeError, Message};
+use thiserror::Error;
+
+use crate::bip70::{Payment, PaymentAck};
+
+/// The `Content-Type` of a serialized [`PaymentAck`].
+pub const PAYMENT_ACK_CONTENT_TYPE: &str = "application/bitcoincash-paymentack";
+
+/// The authorization scheme preceding the POP token.
+pub const TOKEN_SCHEME: &str = "POP";
+
+/// Construct a [`PaymentAck`] acknowledging a [`Payment`] with an optional memo.
+pub fn construct_payment_ack(payment: Payment, memo: Option<String>) -> PaymentAck {
+    PaymentAck { payment, memo }
+}
+
+/// Construct the `Authorization` header value carrying a POP token.
+pub fn token_header_value(token: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+    HeaderValue::from_str(&format!("{} {}", TOKEN_SCHEME, token))
+}
+
+/// Embed a [`PaymentAck`] and a POP token into a response, returning the body.
+///
+/// This sets the `Content-Type` and `Authorization` headers, replacing any existing values.
+pub fn embed_payment_ack(
+    headers: &mut HeaderMap,
+    payment_ack: &PaymentAck,
+    token: &str,
+) -> Result<Bytes, InvalidHeaderValue> {
+    headers.insert(AUTHORIZATION, token_header_value(token)?);
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(PAYMENT_ACK_CONTENT_TYPE),
+    );
+
+    let mut body = Vec::with_capacity(payment_ack.encoded_len());
+    payment_ack.encode(&mut body).unwrap(); // This is safe
+    Ok(body.into())
+}
+
+/// Extract the POP token from the `Authorization` headers of a response.
+pub fn extract_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(AUTHORIZATION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|value| {
+            value
+                .strip_prefix(TOKEN_SCHEME)?
+                .strip_prefix(' ')
+                .filter(|token| !token.is_empty())
+        })
+}
+
+/// Error associated with reading a [`PaymentAck`] response.
+#[derive(Debug, Error)]
+pub enum PaymentAckError {
+    /// POP token missing from headers.
+    #[error("missing token")]
+    MissingToken,
+    /// Failed to decode the [`PaymentAck`].
+    #[error("payment ack decoding failure: {0}")]
+    Decode(DecodeError),
+}
+
+/// Read a [`PaymentAck`] response, produced by [`embed_payment_ack`], returning the [`PaymentAck`]
+/// and the POP token.
+pub fn parse_payment_ack(
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<(PaymentAck, String), PaymentAckError> {
+    let token = extract_token(headers)
+        .ok_or(PaymentAckError::MissingToken)?
+        .to_string();
+    let payment_ack = PaymentAck::decode(body).map_err(PaymentAckError::Decode)?;
+    Ok((payment_ack, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let payment = Payment {
+            merchant_data: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        let payment_ack = construct_payment_ack(payment, Some("Thanks!".to_string()));
+
+        let mut headers = HeaderMap::new();
+        let body = embed_payment_ack(&mut headers, &payment_ack, "abc").unwrap();
+        assert_eq!(headers.get(AUTHORIZATION).unwrap(), "POP abc");
+
+        let (decoded, token) = parse_payment_ack(&headers, body).unwrap();
+        assert_eq!(decoded, payment_ack);
+        assert_eq!(token, "abc");
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("POPabc"));
+        assert!(matches!(
+            parse_payment_ack(&headers, Bytes::new()),
+            Err(PaymentAckError::MissingToken)
+        ));
+    }
+}
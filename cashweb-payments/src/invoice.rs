@@ -0,0 +1,403 @@
+//! This module contains the [`InvoiceStore`] which records issued [`PaymentRequest`]s and tracks
+//! their settlement.
+//!
+//! Invoices are identified by the `merchant_data` of their [`PaymentDetails`], which the payer
+//! echoes in the [`Payment`](crate::bip70::Payment). Once settled, an invoice may also be looked up
+//! by the ID of any transaction which settled it.
+//!
+//! Invoices may request several outputs, for example splitting a fee between a relay operator and
+//! a referrer. Each output is settled individually, allowing an invoice to be paid across several
+//! transactions.
+//!
+//! Times are given in unix seconds, as in [`PaymentDetails`]. The current time is read from the
+//! [`Clock`] of the store, the system clock by default.
+
+use std::sync::Arc;
+
+use clock::{system_clock, Clock, SharedClock};
+use dashmap::{mapref::entry::Entry, DashMap};
+use thiserror::Error;
+
+use crate::{
+    bip70::{Output, PaymentDetails, PaymentRequest},
+    construct_payment_request,
+};
+
+/// The settlement state of an [`Invoice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvoiceState {
+    /// The invoice is awaiting payment.
+    Pending,
+    /// Some, but not all, of the outputs have been settled.
+    PartiallyPaid,
+    /// All outputs have been settled.
+    Paid,
+    /// The invoice expired before it was settled.
+    Expired,
+}
+
+/// An output requested by an [`Invoice`] and its settlement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvoiceOutput {
+    /// The output requested.
+    pub output: Output,
+    /// The ID of the transaction which settled the output, if any.
+    pub settled_by: Option<Vec<u8>>,
+}
+
+/// An issued invoice.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+    /// The ID of the invoice, this is the `merchant_data` of the [`PaymentDetails`].
+    pub id: Vec<u8>,
+    /// The outputs requested.
+    pub outputs: Vec<InvoiceOutput>,
+    /// The time the invoice was created.
+    pub time: u64,
+    /// The time after which the invoice can no longer be settled.
+    pub expires: Option<u64>,
+    /// The settlement state.
+    pub state: InvoiceState,
+}
+
+impl Invoice {
+    /// Check whether the invoice has passed its expiry at a given time.
+    #[inline]
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        self.expires.map(|expires| now > expires).unwrap_or(false)
+    }
+
+    /// The outputs yet to be settled.
+    pub fn outstanding(&self) -> impl Iterator<Item = &Output> {
+        self.outputs
+            .iter()
+            .filter(|output| output.settled_by.is_none())
+            .map(|output| &output.output)
+    }
+
+    /// The total amount, in satoshis, yet to be settled.
+    pub fn amount_outstanding(&self) -> u64 {
+        self.outstanding()
+            .map(|output| output.amount.unwrap_or_default())
+            .sum()
+    }
+
+    /// The distinct IDs of the transactions which settled outputs.
+    pub fn tx_ids(&self) -> Vec<&[u8]> {
+        let mut tx_ids: Vec<&[u8]> = Vec::new();
+        for tx_id in self
+            .outputs
+            .iter()
+            .filter_map(|output| output.settled_by.as_deref())
+        {
+            if !tx_ids.contains(&tx_id) {
+                tx_ids.push(tx_id);
+            }
+        }
+        tx_ids
+    }
+
+    /// Settle the outstanding outputs matched by the outputs of a transaction, each received
+    /// output settling at most one requested output. Returns the number of outputs settled.
+    fn settle_outputs(&mut self, tx_id: &[u8], outputs: &[Output]) -> usize {
+        let mut used = vec![false; outputs.len()];
+        let mut settled = 0;
+        for invoice_output in self
+            .outputs
+            .iter_mut()
+            .filter(|output| output.settled_by.is_none())
+        {
+            let matched = outputs
+                .iter()
+                .zip(used.iter_mut())
+                .find(|(output, used)| !**used && **output == invoice_output.output);
+            if let Some((_, used)) = matched {
+                *used = true;
+                invoice_output.settled_by = Some(tx_id.to_vec());
+                settled += 1;
+            }
+        }
+
+        if settled != 0 {
+            self.state = if self.outstanding().next().is_none() {
+                InvoiceState::Paid
+            } else {
+                InvoiceState::PartiallyPaid
+            };
+        }
+        settled
+    }
+}
+
+/// Error associated with issuing or settling an [`Invoice`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InvoiceError {
+    /// The [`PaymentDetails`] did not include `merchant_data`.
+    #[error("missing merchant data")]
+    MissingMerchantData,
+    /// An invoice with the same ID has already been issued.
+    #[error("duplicate invoice")]
+    Duplicate,
+    /// No invoice exists with the given ID.
+    #[error("invoice not found")]
+    NotFound,
+    /// The invoice expired.
+    #[error("invoice expired")]
+    Expired,
+    /// The invoice has already been settled.
+    #[error("invoice already settled")]
+    AlreadySettled,
+    /// The payment did not include any of the outstanding outputs.
+    #[error("received unexpected outputs")]
+    UnexpectedOutputs,
+}
+
+/// Records issued invoices, allowing them to be queried by ID or settling transaction ID.
+#[derive(Clone, Debug)]
+pub struct InvoiceStore {
+    invoices: Arc<DashMap<Vec<u8>, Invoice>>,
+    tx_ids: Arc<DashMap<Vec<u8>, Vec<u8>>>,
+    clock: SharedClock,
+}
+
+impl Default for InvoiceStore {
+    fn default() -> Self {
+        Self {
+            invoices: Default::default(),
+            tx_ids: Default::default(),
+            clock: system_clock(),
+        }
+    }
+}
+
+impl InvoiceStore {
+    /// Create a new, empty, [`InvoiceStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the [`Clock`] used to check expiry.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Record an invoice and construct its [`PaymentRequest`].
+    pub fn issue(&self, payment_details: &PaymentDetails) -> Result<PaymentRequest, InvoiceError> {
+        let id = payment_details
+            .merchant_data
+            .clone()
+            .ok_or(InvoiceError::MissingMerchantData)?;
+        let invoice = Invoice {
+            id: id.clone(),
+            outputs: payment_details
+                .outputs
+                .iter()
+                .map(|output| InvoiceOutput {
+                    output: output.clone(),
+                    settled_by: None,
+                })
+                .collect(),
+            time: payment_details.time,
+            expires: payment_details.expires,
+            state: InvoiceState::Pending,
+        };
+
+        // Insert if vacant
+        match self.invoices.entry(id) {
+            Entry::Occupied(_) => return Err(InvoiceError::Duplicate),
+            Entry::Vacant(entry) => {
+                entry.insert(invoice);
+            }
+        }
+
+        Ok(construct_payment_request(payment_details))
+    }
+
+    /// Get an invoice by its ID.
+    pub fn get(&self, id: &[u8]) -> Option<Invoice> {
+        self.invoices.get(id).map(|invoice| invoice.value().clone())
+    }
+
+    /// Get an invoice by the ID of a transaction which settled it.
+    pub fn get_by_tx_id(&self, tx_id: &[u8]) -> Option<Invoice> {
+        let id = self.tx_ids.get(tx_id)?.value().clone();
+        self.get(&id)
+    }
+
+    /// Settle the outputs of an invoice with the outputs of a transaction.
+    ///
+    /// Outputs may be settled across several transactions, the invoice becomes
+    /// [`InvoiceState::Paid`] once all outputs are settled.
+    pub fn settle(
+        &self,
+        id: &[u8],
+        tx_id: Vec<u8>,
+        outputs: &[Output],
+    ) -> Result<Invoice, InvoiceError> {
+        self.settle_at(id, tx_id, outputs, self.clock.unix_now())
+    }
+
+    /// Settle the outputs of an invoice with the outputs of a transaction at a given time.
+    pub fn settle_at(
+        &self,
+        id: &[u8],
+        tx_id: Vec<u8>,
+        outputs: &[Output],
+        now: u64,
+    ) -> Result<Invoice, InvoiceError> {
+        let mut invoice = self.invoices.get_mut(id).ok_or(InvoiceError::NotFound)?;
+        match invoice.state {
+            InvoiceState::Pending | InvoiceState::PartiallyPaid => (),
+            InvoiceState::Paid => return Err(InvoiceError::AlreadySettled),
+            InvoiceState::Expired => return Err(InvoiceError::Expired),
+        }
+        if invoice.is_expired_at(now) {
+            invoice.state = InvoiceState::Expired;
+            return Err(InvoiceError::Expired);
+        }
+        if invoice.settle_outputs(&tx_id, outputs) == 0 {
+            return Err(InvoiceError::UnexpectedOutputs);
+        }
+
+        self.tx_ids.insert(tx_id, id.to_vec());
+        Ok(invoice.value().clone())
+    }
+
+    /// Mark the pending invoices which have passed their expiry, according to the [`Clock`], as
+    /// expired, returning their IDs.
+    pub fn expire_now(&self) -> Vec<Vec<u8>> {
+        self.expire(self.clock.unix_now())
+    }
+
+    /// Mark the pending invoices which have passed their expiry as expired, returning their IDs.
+    pub fn expire(&self, now: u64) -> Vec<Vec<u8>> {
+        let mut expired = Vec::new();
+        for mut invoice in self.invoices.iter_mut() {
+            let unsettled = matches!(
+                invoice.state,
+                InvoiceState::Pending | InvoiceState::PartiallyPaid
+            );
+            if unsettled && invoice.is_expired_at(now) {
+                invoice.state = InvoiceState::Expired;
+                expired.push(invoice.id.clone());
+            }
+        }
+        expired
+    }
+
+    /// Remove an invoice, and its transaction ID index, from the store.
+    pub fn remove(&self, id: &[u8]) -> Option<Invoice> {
+        let (_, invoice) = self.invoices.remove(id)?;
+        for tx_id in invoice.tx_ids() {
+            self.tx_ids.remove(tx_id);
+        }
+        Some(invoice)
+    }
+
+    /// The number of invoices recorded.
+    pub fn len(&self) -> usize {
+        self.invoices.len()
+    }
+
+    /// Check whether the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.invoices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment_details(id: &[u8], expires: Option<u64>) -> PaymentDetails {
+        PaymentDetails {
+            network: None,
+            outputs: vec![Output {
+                amount: Some(1_000),
+                script: vec![0x6a],
+            }],
+            time: 100,
+            expires,
+            memo: None,
+            payment_url: None,
+            merchant_data: Some(id.to_vec()),
+        }
+    }
+
+    #[test]
+    fn settle() {
+        let store = InvoiceStore::new();
+        let details = payment_details(b"invoice", None);
+        store.issue(&details).unwrap();
+        assert_eq!(store.issue(&details), Err(InvoiceError::Duplicate));
+
+        assert_eq!(
+            store.settle_at(b"invoice", vec![1; 32], &[], 200),
+            Err(InvoiceError::UnexpectedOutputs)
+        );
+        let invoice = store
+            .settle_at(b"invoice", vec![1; 32], &details.outputs, 200)
+            .unwrap();
+        assert_eq!(invoice.state, InvoiceState::Paid);
+        assert_eq!(store.get_by_tx_id(&[1; 32]), Some(invoice));
+        assert_eq!(
+            store.settle_at(b"invoice", vec![2; 32], &details.outputs, 200),
+            Err(InvoiceError::AlreadySettled)
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let store = InvoiceStore::new();
+        let details = payment_details(b"invoice", Some(150));
+        store.issue(&details).unwrap();
+        assert_eq!(store.expire(120), Vec::<Vec<u8>>::new());
+        assert_eq!(store.expire(200), vec![b"invoice".to_vec()]);
+        assert_eq!(
+            store.settle_at(b"invoice", vec![1; 32], &details.outputs, 120),
+            Err(InvoiceError::Expired)
+        );
+    }
+
+    #[test]
+    fn split() {
+        let store = InvoiceStore::new();
+        let mut details = payment_details(b"invoice", None);
+        let referrer_output = Output {
+            amount: Some(500),
+            script: vec![0x51],
+        };
+        details.outputs.push(referrer_output.clone());
+        store.issue(&details).unwrap();
+
+        let invoice = store
+            .settle_at(b"invoice", vec![1; 32], &[referrer_output], 200)
+            .unwrap();
+        assert_eq!(invoice.state, InvoiceState::PartiallyPaid);
+        assert_eq!(invoice.amount_outstanding(), 1_000);
+
+        let invoice = store
+            .settle_at(b"invoice", vec![2; 32], &details.outputs, 200)
+            .unwrap();
+        assert_eq!(invoice.state, InvoiceState::Paid);
+        assert_eq!(invoice.tx_ids(), vec![&[2; 32][..], &[1; 32][..]]);
+        assert_eq!(store.get_by_tx_id(&[1; 32]), Some(invoice.clone()));
+        assert_eq!(store.get_by_tx_id(&[2; 32]), Some(invoice));
+    }
+
+    #[test]
+    fn clock_expiry() {
+        let clock = clock::ManualClock::from_unix(120);
+        let store = InvoiceStore::new().with_clock(clock.clone());
+        let details = payment_details(b"invoice", Some(150));
+        store.issue(&details).unwrap();
+        assert_eq!(store.expire_now(), Vec::<Vec<u8>>::new());
+
+        clock.advance(std::time::Duration::from_secs(60));
+        assert_eq!(
+            store.settle(b"invoice", vec![1; 32], &details.outputs),
+            Err(InvoiceError::Expired)
+        );
+    }
+}
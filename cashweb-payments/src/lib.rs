@@ -9,9 +9,28 @@
 //! the [`BIP70: Payment Protocol`] and a [`Wallet`] structure to allow receiving
 //! payments.
 //!
+//! Issued invoices can be recorded, and their settlement tracked, using the [`InvoiceStore`].
+//! Invoices may be denominated in fiat using the [`PaymentRequestBuilder`] and a [`PriceOracle`].
+//!
+//! Payments may be acknowledged with a POP token using [`embed_payment_ack`], which clients read
+//! back using [`parse_payment_ack`].
+//!
+//! The [`Wallet`] expires pending outputs using the tokio timer by default, disabling the `tokio`
+//! feature selects an executor-agnostic timer.
+//!
 //! [`Wallet`]: wallet::Wallet
+//! [`embed_payment_ack`]: ack::embed_payment_ack
+//! [`parse_payment_ack`]: ack::parse_payment_ack
+//! [`InvoiceStore`]: invoice::InvoiceStore
+//! [`PaymentRequestBuilder`]: pricing::PaymentRequestBuilder
+//! [`PriceOracle`]: pricing::PriceOracle
 //! [`BIP70: Payment Protocol`]: https://github.com/bitcoin/bips/blob/master/bip-0070.mediawiki
 
+pub mod ack;
+pub mod invoice;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod pricing;
 pub mod wallet;
 
 use bytes::Bytes;
@@ -28,7 +47,55 @@ pub mod bip70 {
     include!(concat!(env!("OUT_DIR"), "/bip70.rs"));
 }
 
-use bip70::Payment;
+use bip70::{Output, Payment, PaymentDetails, PaymentRequest};
+
+/// The `Content-Type` of a serialized [`PaymentRequest`].
+pub const PAYMENT_REQUEST_CONTENT_TYPE: &str = "application/bitcoincash-paymentrequest";
+
+/// Construct an unsigned [`PaymentRequest`] from [`PaymentDetails`].
+pub fn construct_payment_request(payment_details: &PaymentDetails) -> PaymentRequest {
+    let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
+    payment_details
+        .encode(&mut serialized_payment_details)
+        .unwrap(); // This is safe
+    PaymentRequest {
+        payment_details_version: Some(1),
+        pki_type: Some("none".to_string()),
+        pki_data: None,
+        serialized_payment_details,
+        signature: None,
+    }
+}
+
+/// Split an amount between scripts in proportion to their weights, for example splitting a fee
+/// between a relay operator and a referrer.
+///
+/// Any remainder from the division is assigned to the first output. Outputs with zero amounts
+/// are omitted.
+pub fn split_outputs(amount: u64, shares: &[(Vec<u8>, u64)]) -> Vec<Output> {
+    let total_weight: u128 = shares.iter().map(|(_, weight)| *weight as u128).sum();
+    if total_weight == 0 {
+        return Vec::new();
+    }
+
+    // Apportion amount
+    let mut amounts: Vec<u64> = shares
+        .iter()
+        .map(|(_, weight)| (amount as u128 * *weight as u128 / total_weight) as u64)
+        .collect();
+    let remainder = amount - amounts.iter().sum::<u64>();
+    amounts[0] += remainder;
+
+    shares
+        .iter()
+        .zip(amounts)
+        .filter(|(_, amount)| *amount != 0)
+        .map(|((script, _), amount)| Output {
+            amount: Some(amount),
+            script: script.clone(),
+        })
+        .collect()
+}
 
 /// Error associated with payment preprocessing.
 #[derive(Debug, Error)]
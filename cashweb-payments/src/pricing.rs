@@ -0,0 +1,311 @@
+//! This module contains the [`PriceOracle`] trait and the [`PaymentRequestBuilder`] which allows
+//! invoices to be denominated in fiat.
+//!
+//! Fiat amounts are converted into satoshis at issue time using the [`Quote`] provided by the
+//! [`PriceOracle`]. The quote is recorded in the `merchant_data`, ahead of the invoice ID, so
+//! that the price paid can be audited later.
+//!
+//! Fiat amounts and prices are given in the minor unit of the currency, for example cents.
+
+use std::{convert::TryInto, fmt, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use clock::{system_clock, Clock, SharedClock};
+use thiserror::Error;
+
+use crate::{
+    bip70::{Output, PaymentDetails, PaymentRequest},
+    construct_payment_request,
+};
+
+/// The number of satoshis in a coin.
+pub const SATOSHIS_PER_COIN: u64 = 100_000_000;
+
+type FutQuote<'a, Error> = Pin<Box<dyn Future<Output = Result<Quote, Error>> + Send + 'a>>;
+
+/// A price quoted by a [`PriceOracle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quote {
+    /// The currency code, for example `USD`.
+    pub currency: String,
+    /// The price of a coin, given in the minor unit of the currency.
+    pub price: u64,
+    /// The time the quote was made. Given in unix seconds.
+    pub time: u64,
+}
+
+impl Quote {
+    /// Convert an amount, given in the minor unit of the currency, into satoshis.
+    ///
+    /// The result is rounded up, returning `None` if the price is zero or the result overflows.
+    pub fn to_satoshis(&self, amount: u64) -> Option<u64> {
+        if self.price == 0 {
+            return None;
+        }
+        let numerator = amount as u128 * SATOSHIS_PER_COIN as u128;
+        let price = self.price as u128;
+        let satoshis = numerator / price + u128::from(numerator % price != 0);
+        satoshis.try_into().ok()
+    }
+
+    /// Encode the quote followed by an invoice ID, for use as `merchant_data`.
+    pub fn encode_merchant_data(&self, id: &[u8]) -> Vec<u8> {
+        let currency = &self.currency.as_bytes()[..self.currency.len().min(u8::MAX as usize)];
+        let mut raw = Vec::with_capacity(1 + currency.len() + 16 + id.len());
+        raw.push(currency.len() as u8);
+        raw.extend_from_slice(currency);
+        raw.extend_from_slice(&self.price.to_be_bytes());
+        raw.extend_from_slice(&self.time.to_be_bytes());
+        raw.extend_from_slice(id);
+        raw
+    }
+
+    /// Decode `merchant_data` produced by [`Quote::encode_merchant_data`], returning the quote and
+    /// the invoice ID.
+    pub fn decode_merchant_data(raw: &[u8]) -> Option<(Self, &[u8])> {
+        let (currency_len, raw) = raw.split_first()?;
+        let currency_len = *currency_len as usize;
+        if raw.len() < currency_len + 16 {
+            return None;
+        }
+        let (currency, raw) = raw.split_at(currency_len);
+        let (price, raw) = raw.split_at(8);
+        let (time, id) = raw.split_at(8);
+        let quote = Quote {
+            currency: String::from_utf8(currency.to_vec()).ok()?,
+            price: u64::from_be_bytes(price.try_into().unwrap()), // This is safe
+            time: u64::from_be_bytes(time.try_into().unwrap()),   // This is safe
+        };
+        Some((quote, id))
+    }
+}
+
+/// Provides the price of a coin in fiat currencies.
+pub trait PriceOracle {
+    /// Error associated with fetching a quote.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Fetch the current price of a coin in the given currency.
+    fn quote<'a>(&'a self, currency: &'a str) -> FutQuote<'a, Self::Error>;
+}
+
+/// Error associated with building a [`PaymentRequest`].
+#[derive(Debug, Error)]
+pub enum PricingError<E: fmt::Debug + fmt::Display> {
+    /// No outputs were added.
+    #[error("no outputs")]
+    NoOutputs,
+    /// Outputs were denominated in more than one fiat currency.
+    #[error("multiple currencies")]
+    MultipleCurrencies,
+    /// Error occured when fetching a quote.
+    #[error("oracle failure: {0}")]
+    Oracle(E),
+    /// The quote could not convert the fiat amount.
+    #[error("invalid quote")]
+    InvalidQuote,
+}
+
+#[derive(Clone, Debug)]
+enum Amount {
+    Satoshis(u64),
+    Fiat { currency: String, amount: u64 },
+}
+
+/// Builds [`PaymentRequest`]s whose outputs may be denominated in satoshis or fiat.
+#[derive(Clone, Debug)]
+pub struct PaymentRequestBuilder {
+    outputs: Vec<(Vec<u8>, Amount)>,
+    network: Option<String>,
+    expires_in: Option<Duration>,
+    memo: Option<String>,
+    payment_url: Option<String>,
+    id: Vec<u8>,
+    clock: SharedClock,
+}
+
+impl Default for PaymentRequestBuilder {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            network: None,
+            expires_in: None,
+            memo: None,
+            payment_url: None,
+            id: Vec::new(),
+            clock: system_clock(),
+        }
+    }
+}
+
+impl PaymentRequestBuilder {
+    /// Create a new [`PaymentRequestBuilder`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add an output paying a fixed amount of satoshis to a script.
+    pub fn output(mut self, script: Vec<u8>, satoshis: u64) -> Self {
+        self.outputs.push((script, Amount::Satoshis(satoshis)));
+        self
+    }
+
+    /// Add an output paying a fiat amount, given in the minor unit of the currency, to a script.
+    pub fn fiat_output(mut self, script: Vec<u8>, currency: String, amount: u64) -> Self {
+        self.outputs
+            .push((script, Amount::Fiat { currency, amount }));
+        self
+    }
+
+    /// Set the network, `main` or `test`.
+    pub fn network(mut self, network: String) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Set the duration after issue at which the invoice expires.
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = Some(expires_in);
+        self
+    }
+
+    /// Set the human-readable description of the invoice.
+    pub fn memo(mut self, memo: String) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    /// Set the URL the payment should be sent to.
+    pub fn payment_url(mut self, payment_url: String) -> Self {
+        self.payment_url = Some(payment_url);
+        self
+    }
+
+    /// Set the invoice ID, this is included in the `merchant_data`.
+    pub fn id(mut self, id: Vec<u8>) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Set the [`Clock`] giving the issue time, the system clock by default.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Build the [`PaymentDetails`], quoting fiat outputs using the [`PriceOracle`].
+    ///
+    /// If any output is denominated in fiat the `merchant_data` is the encoded [`Quote`] followed
+    /// by the ID, otherwise it is the ID alone.
+    pub async fn build_details<O: PriceOracle>(
+        self,
+        oracle: &O,
+    ) -> Result<PaymentDetails, PricingError<O::Error>> {
+        if self.outputs.is_empty() {
+            return Err(PricingError::NoOutputs);
+        }
+
+        // Quote currency
+        let mut currencies = self.outputs.iter().filter_map(|(_, amount)| match amount {
+            Amount::Fiat { currency, .. } => Some(currency),
+            Amount::Satoshis(_) => None,
+        });
+        let quote = match currencies.next() {
+            Some(currency) => {
+                if currencies.any(|other| other != currency) {
+                    return Err(PricingError::MultipleCurrencies);
+                }
+                Some(oracle.quote(currency).await.map_err(PricingError::Oracle)?)
+            }
+            None => None,
+        };
+
+        // Convert amounts
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(|(script, amount)| {
+                let satoshis = match amount {
+                    Amount::Satoshis(satoshis) => satoshis,
+                    Amount::Fiat { amount, .. } => quote
+                        .as_ref()
+                        .and_then(|quote| quote.to_satoshis(amount))
+                        .ok_or(PricingError::InvalidQuote)?,
+                };
+                Ok(Output {
+                    amount: Some(satoshis),
+                    script,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let time = self.clock.unix_now();
+        let merchant_data = match &quote {
+            Some(quote) => quote.encode_merchant_data(&self.id),
+            None => self.id,
+        };
+        Ok(PaymentDetails {
+            network: self.network,
+            outputs,
+            time,
+            expires: self
+                .expires_in
+                .map(|expires_in| time + expires_in.as_secs()),
+            memo: self.memo,
+            payment_url: self.payment_url,
+            merchant_data: Some(merchant_data),
+        })
+    }
+
+    /// Build the [`PaymentRequest`], quoting fiat outputs using the [`PriceOracle`].
+    pub async fn build<O: PriceOracle>(
+        self,
+        oracle: &O,
+    ) -> Result<PaymentRequest, PricingError<O::Error>> {
+        let payment_details = self.build_details(oracle).await?;
+        Ok(construct_payment_request(&payment_details))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion() {
+        let quote = Quote {
+            currency: "USD".to_string(),
+            price: 30_000,
+            time: 100,
+        };
+        assert_eq!(quote.to_satoshis(300), Some(1_000_000));
+        assert_eq!(quote.to_satoshis(1), Some(3_334));
+        assert_eq!(quote.to_satoshis(0), Some(0));
+
+        let quote = Quote { price: 1, ..quote };
+        assert_eq!(quote.to_satoshis(u64::MAX), None);
+
+        let quote = Quote {
+            price: u64::MAX,
+            ..quote
+        };
+        assert_eq!(quote.to_satoshis(u64::MAX), Some(SATOSHIS_PER_COIN));
+
+        let quote = Quote { price: 0, ..quote };
+        assert_eq!(quote.to_satoshis(1), None);
+    }
+
+    #[test]
+    fn merchant_data() {
+        let quote = Quote {
+            currency: "USD".to_string(),
+            price: 30_000,
+            time: 100,
+        };
+        let raw = quote.encode_merchant_data(b"invoice");
+        assert_eq!(
+            Quote::decode_merchant_data(&raw),
+            Some((quote, &b"invoice"[..]))
+        );
+        assert_eq!(Quote::decode_merchant_data(&raw[..10]), None);
+    }
+}
@@ -2,9 +2,9 @@
 
 use std::{fmt, sync::Arc, time::Duration};
 
+use clock::delay_for;
 use dashmap::DashMap;
 use thiserror::Error;
-use tokio::time::delay_for;
 
 /// Received unexpected outputs.
 #[derive(Debug, Clone, PartialEq, Eq, Error)]
@@ -47,6 +47,8 @@ where
     }
 
     /// Synchronously adds outputs to the wallet and returns a delayed Future removing the output.
+    ///
+    /// The Future may be spawned on any executor, see the `tokio` feature.
     pub fn add_outputs(
         &self,
         key: K,
@@ -80,4 +82,39 @@ where
             Err(UnexpectedOutputs)
         }
     }
+
+    /// Removes the outputs matched by the received outputs, each received output matching at
+    /// most one pending output, else raises an error if none matched.
+    ///
+    /// This allows invoices with multiple outputs to be paid across several transactions. Returns
+    /// the number of outputs still pending, the entry is removed once none remain.
+    pub fn recv_partial_outputs(&self, key: &K, outputs: &[O]) -> Result<usize, UnexpectedOutputs> {
+        let remaining = {
+            let mut expected_outputs = self.pending.get_mut(key).ok_or(UnexpectedOutputs)?;
+            let mut used = vec![false; outputs.len()];
+            let before = expected_outputs.len();
+            expected_outputs.retain(|expected_output| {
+                let matched = outputs
+                    .iter()
+                    .zip(used.iter_mut())
+                    .find(|(output, used)| !**used && *output == expected_output);
+                if let Some((_, used)) = matched {
+                    *used = true;
+                    false
+                } else {
+                    true
+                }
+            });
+            if expected_outputs.len() == before {
+                return Err(UnexpectedOutputs);
+            }
+            expected_outputs.len()
+        };
+
+        if remaining == 0 {
+            self.pending
+                .remove_if(key, |_, expected_outputs| expected_outputs.is_empty());
+        }
+        Ok(remaining)
+    }
 }
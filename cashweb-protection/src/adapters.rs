@@ -0,0 +1,172 @@
+//! This module contains adapters allowing the [`ProtectedService`] to be mounted directly on
+//! [`hyper`] servers.
+//!
+//! Hyper treats service errors as fatal to the connection, the [`IntoResponseService`] bridges
+//! this by converting each [`GuardError`] returned from a call into a [`Response`].
+//!
+//! This module is enabled by the `hyper` feature.
+//!
+//! [`ProtectedService`]: crate::ProtectedService
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::{ready, Ready},
+    pin::Pin,
+};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{Request, Response};
+use hyper::Body;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::GuardError;
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// Convert a [`GuardError`] into an empty [`Response`] with the appropriate status code.
+///
+/// See [`ResponseMapper`](crate::ResponseMapper) for a mapping including challenges and bodies.
+pub fn status_response<V, E>(err: GuardError<V, E>) -> Response<Body>
+where
+    V: fmt::Debug + fmt::Display,
+    E: fmt::Debug + fmt::Display,
+{
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = err.status_code();
+    response
+}
+
+/// A [`Layer`] producing [`IntoResponseService`]s.
+#[derive(Clone, Debug)]
+pub struct IntoResponseLayer<M> {
+    mapper: M,
+}
+
+impl<M> IntoResponseLayer<M> {
+    /// Create a new [`IntoResponseLayer`] using a function mapping [`GuardError`]s to [`Response`]s.
+    pub fn new(mapper: M) -> Self {
+        Self { mapper }
+    }
+}
+
+impl<S, M: Clone> Layer<S> for IntoResponseLayer<M> {
+    type Service = IntoResponseService<S, M>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IntoResponseService {
+            inner,
+            mapper: self.mapper.clone(),
+        }
+    }
+}
+
+/// A [`Service`] converting [`GuardError`]s returned from calls into [`Response`]s.
+#[derive(Clone, Debug)]
+pub struct IntoResponseService<S, M> {
+    inner: S,
+    mapper: M,
+}
+
+impl<S, M, V, E, B> Service<Request<B>> for IntoResponseService<S, M>
+where
+    S: Service<Request<B>, Response = Response<Body>, Error = GuardError<V, E>>,
+    S::Future: Send + 'static,
+    M: Fn(GuardError<V, E>) -> Response<Body> + Clone + Send + 'static,
+    V: fmt::Debug + fmt::Display,
+    E: fmt::Debug + fmt::Display,
+{
+    type Response = Response<Body>;
+    type Error = GuardError<V, E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let mapper = self.mapper.clone();
+        let fut = self.inner.call(request);
+        Box::pin(async move { Ok(fut.await.unwrap_or_else(mapper)) })
+    }
+}
+
+/// A make service, suitable for [`hyper::Server::serve`], which clones a service for each
+/// connection.
+#[derive(Clone, Debug)]
+pub struct MakeCloneService<S> {
+    service: S,
+}
+
+impl<S> MakeCloneService<S> {
+    /// Create a new [`MakeCloneService`].
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+impl<S: Clone, T> Service<T> for MakeCloneService<S> {
+    type Response = S;
+    type Error = Infallible;
+    type Future = Ready<Result<S, Infallible>>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _target: T) -> Self::Future {
+        ready(Ok(self.service.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::*;
+    use crate::{
+        path_data,
+        tests::{block_on, pop_request, EqualValidator},
+        ProtectionLayer,
+    };
+
+    #[derive(Clone, Debug)]
+    struct Empty;
+
+    impl<B> Service<Request<B>> for Empty {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<B>) -> Self::Future {
+            ready(Ok(Response::new(Body::empty())))
+        }
+    }
+
+    #[test]
+    fn into_response() {
+        let protected = ProtectionLayer::new(EqualValidator, path_data).layer(Empty);
+        let mut service =
+            IntoResponseLayer::new(status_response::<&str, Infallible>).layer(protected);
+
+        let response = block_on(service.call(pop_request("/a", None))).unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn make_clone() {
+        let mut make_service = MakeCloneService::new(7);
+        assert_eq!(block_on(make_service.call(())), Ok(7));
+    }
+}
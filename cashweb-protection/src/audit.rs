@@ -0,0 +1,237 @@
+//! This module contains the [`AuditLayer`] which records an [`AuditEvent`] for every request
+//! passing through a [`ProtectedService`].
+//!
+//! Events are passed to a sink function. When the `log` feature is enabled [`log_event`] provides a
+//! sink recording events via the [`log`] facade.
+//!
+//! Tokens are never recorded directly, only their SHA256 digest.
+//!
+//! [`ProtectedService`]: crate::ProtectedService
+//! [`log`]: https://docs.rs/log
+
+use std::{
+    fmt,
+    pin::Pin,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{Method, Request};
+use ring::digest::{digest, SHA256};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{GuardError, PopTokenExtractor, TokenExtractor};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The decision made regarding a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// The request was passed to the inner service and succeeded.
+    Allowed,
+    /// The request was rejected, or failed, labelled by the [`GuardError::label`].
+    Denied(&'static str),
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allowed => f.write_str("allowed"),
+            Self::Denied(reason) => write!(f, "denied ({})", reason),
+        }
+    }
+}
+
+/// A record of a request to a protected resource.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// The time at which the request was received.
+    pub timestamp: SystemTime,
+    /// The hex encoded SHA256 digest of the extracted POP token.
+    pub token_digest: Option<String>,
+    /// The decision made.
+    pub decision: Decision,
+    /// The request method.
+    pub method: Method,
+    /// The request path.
+    pub path: String,
+    /// The time taken to handle the request.
+    pub latency: Duration,
+}
+
+/// Record an [`AuditEvent`] via the [`log`](https://docs.rs/log) facade.
+///
+/// Allowed requests are logged at `info` level, denied requests at `warn` level.
+#[cfg(feature = "log")]
+pub fn log_event(event: &AuditEvent) {
+    let timestamp = event
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let level = match event.decision {
+        Decision::Allowed => log::Level::Info,
+        Decision::Denied(_) => log::Level::Warn,
+    };
+    log::log!(
+        target: "cashweb_protection::audit",
+        level,
+        "timestamp={} token_digest={} decision=\"{}\" method={} path={} latency_ms={}",
+        timestamp,
+        event.token_digest.as_deref().unwrap_or("none"),
+        event.decision,
+        event.method,
+        event.path,
+        event.latency.as_millis()
+    );
+}
+
+/// A [`Layer`] producing [`AuditService`]s.
+///
+/// The `sink` is called with an [`AuditEvent`] once each request completes.
+#[derive(Clone, Debug)]
+pub struct AuditLayer<F> {
+    extractor: PopTokenExtractor,
+    sink: F,
+}
+
+impl<F> AuditLayer<F> {
+    /// Create a new [`AuditLayer`] using the default [`PopTokenExtractor`].
+    pub fn new(sink: F) -> Self {
+        Self {
+            extractor: PopTokenExtractor::default(),
+            sink,
+        }
+    }
+
+    /// Replace the [`PopTokenExtractor`], this should match the one used by the guard.
+    pub fn with_extractor(mut self, extractor: PopTokenExtractor) -> Self {
+        self.extractor = extractor;
+        self
+    }
+}
+
+impl<S, F: Clone> Layer<S> for AuditLayer<F> {
+    type Service = AuditService<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuditService {
+            inner,
+            extractor: self.extractor.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+/// A [`Service`] recording an [`AuditEvent`] for each request.
+#[derive(Clone, Debug)]
+pub struct AuditService<S, F> {
+    inner: S,
+    extractor: PopTokenExtractor,
+    sink: F,
+}
+
+impl<S, F, V, E, B> Service<Request<B>> for AuditService<S, F>
+where
+    S: Service<Request<B>, Error = GuardError<V, E>>,
+    S::Response: Send + 'static,
+    S::Future: Send + 'static,
+    F: Fn(&AuditEvent) + Clone + Send + 'static,
+    V: fmt::Debug + fmt::Display + Send + 'static,
+    E: fmt::Debug + fmt::Display + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = GuardError<V, E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let timestamp = SystemTime::now();
+        let start = Instant::now();
+
+        // Digest token
+        let token_digest = self
+            .extractor
+            .extract(request.headers(), request.uri())
+            .map(|token| hex::encode(digest(&SHA256, token.as_bytes())));
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+
+        let sink = self.sink.clone();
+        let fut = self.inner.call(request);
+        Box::pin(async move {
+            let result = fut.await;
+            let decision = match &result {
+                Ok(_) => Decision::Allowed,
+                Err(err) => Decision::Denied(err.label()),
+            };
+            let event = AuditEvent {
+                timestamp,
+                token_digest,
+                decision,
+                method,
+                path,
+                latency: start.elapsed(),
+            };
+            sink(&event);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        path_data,
+        tests::{block_on, pop_request, EqualValidator, Identity},
+        ProtectionLayer,
+    };
+
+    #[test]
+    fn record_decisions() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_inner = events.clone();
+        let protected = ProtectionLayer::new(EqualValidator, path_data).layer(Identity);
+        let mut service = AuditLayer::new(move |event: &AuditEvent| {
+            events_inner.lock().unwrap().push(event.clone())
+        })
+        .layer(protected);
+
+        block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+        block_on(service.call(pop_request("/a", Some("POP /b")))).unwrap_err();
+        block_on(service.call(pop_request("/a", None))).unwrap_err();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].decision, Decision::Allowed);
+        assert_eq!(events[0].method, Method::GET);
+        assert_eq!(events[0].path, "/a");
+        assert_eq!(
+            events[0].token_digest.as_deref(),
+            Some(hex::encode(digest(&SHA256, b"/a")).as_str())
+        );
+        assert_eq!(events[1].decision, Decision::Denied("token_validate"));
+        assert_eq!(events[2].decision, Decision::Denied("no_auth_data"));
+        assert_eq!(events[2].token_digest, None);
+    }
+
+    #[test]
+    fn display_decision() {
+        assert_eq!(Decision::Allowed.to_string(), "allowed");
+        assert_eq!(
+            Decision::Denied("rate_limited").to_string(),
+            "denied (rate_limited)"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! This module contains the [`BypassRules`] which allow requests to skip token validation.
+//!
+//! This allows public endpoints, such as `/peers` or health checks, to share the same middleware
+//! stack as protected endpoints.
+//!
+//! Paths are compared segment by segment after normalization, see [`path_segments`], so that the
+//! prefix `/public` matches `/public/index.html` but neither `/publicadmin` nor
+//! `/public/../admin`.
+//!
+//! The client IP address is read from the request extensions, where it is expected to have been
+//! inserted as a [`SocketAddr`] or [`IpAddr`] when the connection was accepted.
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    num::ParseIntError,
+    str::FromStr,
+};
+
+use http::Request;
+use thiserror::Error;
+
+/// A range of IP addresses in CIDR notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// Error associated with constructing an [`IpRange`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IpRangeError {
+    /// Failed to parse the address.
+    #[error("invalid address: {0}")]
+    Address(std::net::AddrParseError),
+    /// Failed to parse the prefix length.
+    #[error("invalid prefix length: {0}")]
+    PrefixParse(ParseIntError),
+    /// The prefix length exceeds the length of the address.
+    #[error("prefix length too long: {0}")]
+    PrefixLength(u8),
+}
+
+impl IpRange {
+    /// Create a new [`IpRange`] from an address and prefix length.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, IpRangeError> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(IpRangeError::PrefixLength(prefix_len));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Whether the range contains the address.
+    ///
+    /// IPv4 ranges never contain IPv6 addresses and vice versa.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(*addr) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(*addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = IpRangeError;
+
+    /// Parse from CIDR notation, a bare address is treated as a single address range.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '/');
+        let addr: IpAddr = split
+            .next()
+            .unwrap() // This is safe
+            .parse()
+            .map_err(IpRangeError::Address)?;
+        let prefix_len = match split.next() {
+            Some(prefix_len) => prefix_len.parse().map_err(IpRangeError::PrefixParse)?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(addr, prefix_len)
+    }
+}
+
+/// Decode the percent-encoded bytes of a path segment.
+///
+/// Encoded separators are rejected, as servers disagree on whether they separate segments.
+fn percent_decode(segment: &str) -> Option<String> {
+    let raw_segment = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(raw_segment.len());
+    let mut i = 0;
+    while i < raw_segment.len() {
+        if raw_segment[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return None;
+            }
+            let byte = u8::from_str_radix(hex, 16).unwrap(); // This is safe
+            if byte == b'/' || byte == b'\\' {
+                return None;
+            }
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(raw_segment[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Normalize a path into its segments.
+///
+/// Segments are percent-decoded, empty and `.` segments are removed and `..` segments remove the
+/// preceding segment. Returns `None` if the path contains invalid or ambiguous percent-encoding.
+pub fn path_segments(path: &str) -> Option<Vec<String>> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        let segment = percent_decode(segment)?;
+        match segment.as_str() {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments)
+}
+
+/// Whether the path segments start with all of the prefix segments.
+pub(crate) fn has_segment_prefix(segments: &[String], prefix: &[String]) -> bool {
+    segments.len() >= prefix.len() && segments[..prefix.len()] == *prefix
+}
+
+/// Rules determining which requests bypass token validation.
+///
+/// A request bypasses validation if it matches any of the rules.
+#[derive(Clone, Debug, Default)]
+pub struct BypassRules {
+    paths: HashSet<Vec<String>>,
+    path_prefixes: Vec<Vec<String>>,
+    ip_ranges: Vec<IpRange>,
+}
+
+impl BypassRules {
+    /// Create a new, empty, [`BypassRules`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Bypass requests to the exact path.
+    ///
+    /// Paths which cannot be normalized are ignored.
+    pub fn path(mut self, path: String) -> Self {
+        if let Some(segments) = path_segments(&path) {
+            self.paths.insert(segments);
+        }
+        self
+    }
+
+    /// Bypass requests to paths starting with the segments of the prefix.
+    ///
+    /// Prefixes which cannot be normalized are ignored.
+    pub fn path_prefix(mut self, prefix: String) -> Self {
+        if let Some(segments) = path_segments(&prefix) {
+            self.path_prefixes.push(segments);
+        }
+        self
+    }
+
+    /// Bypass requests from clients within the IP range.
+    pub fn ip_range(mut self, range: IpRange) -> Self {
+        self.ip_ranges.push(range);
+        self
+    }
+
+    /// Whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.path_prefixes.is_empty() && self.ip_ranges.is_empty()
+    }
+
+    /// Whether the path and client IP match any rule.
+    pub fn matches_parts(&self, path: &str, client_ip: Option<IpAddr>) -> bool {
+        if let Some(segments) = path_segments(path) {
+            if self.paths.contains(&segments) {
+                return true;
+            }
+            if self
+                .path_prefixes
+                .iter()
+                .any(|prefix| has_segment_prefix(&segments, prefix))
+            {
+                return true;
+            }
+        }
+        match client_ip {
+            Some(client_ip) => self
+                .ip_ranges
+                .iter()
+                .any(|range| range.contains(&client_ip)),
+            None => false,
+        }
+    }
+
+    /// Whether the request matches any rule.
+    pub fn matches<B>(&self, request: &Request<B>) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        self.matches_parts(request.uri().path(), client_ip(request))
+    }
+}
+
+/// Get the client IP address from the request extensions.
+pub fn client_ip<B>(request: &Request<B>) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    extensions
+        .get::<SocketAddr>()
+        .map(SocketAddr::ip)
+        .or_else(|| extensions.get::<IpAddr>().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_ranges() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!range.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(!range.contains(&"::1".parse().unwrap()));
+
+        let range: IpRange = "::1".parse().unwrap();
+        assert!(range.contains(&"::1".parse().unwrap()));
+        assert!(!range.contains(&"::2".parse().unwrap()));
+
+        let range: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains(&"192.168.0.1".parse().unwrap()));
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<IpRange>(),
+            Err(IpRangeError::PrefixLength(33))
+        );
+    }
+
+    #[test]
+    fn rules() {
+        let rules = BypassRules::new()
+            .path("/peers".to_string())
+            .path_prefix("/health".to_string())
+            .ip_range("127.0.0.0/8".parse().unwrap());
+
+        let request = Request::get("/peers").body(()).unwrap();
+        assert!(rules.matches(&request));
+
+        let request = Request::get("/health/live").body(()).unwrap();
+        assert!(rules.matches(&request));
+
+        let request = Request::get("/healthz").body(()).unwrap();
+        assert!(!rules.matches(&request));
+
+        let mut request = Request::get("/messages").body(()).unwrap();
+        assert!(!rules.matches(&request));
+        request
+            .extensions_mut()
+            .insert::<SocketAddr>("127.0.0.1:8080".parse().unwrap());
+        assert!(rules.matches(&request));
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(
+            path_segments("//a/./b/../c/"),
+            Some(vec!["a".to_string(), "c".to_string()])
+        );
+        assert_eq!(path_segments("/%61%2e%2E/b"), Some(vec!["b".to_string()]));
+        assert_eq!(path_segments("/a%2Fb"), None);
+        assert_eq!(path_segments("/a%5cb"), None);
+        assert_eq!(path_segments("/a%2"), None);
+        assert_eq!(path_segments("/a%+1"), None);
+    }
+
+    #[test]
+    fn segment_boundaries() {
+        let rules = BypassRules::new()
+            .path("/peers".to_string())
+            .path_prefix("/public".to_string());
+
+        assert!(rules.matches_parts("/public", None));
+        assert!(rules.matches_parts("/public/", None));
+        assert!(rules.matches_parts("//public/./a", None));
+        assert!(rules.matches_parts("/%70ublic/a", None));
+        assert!(!rules.matches_parts("/publicadmin", None));
+        assert!(!rules.matches_parts("/public/../admin", None));
+        assert!(!rules.matches_parts("/public%2F..%2Fadmin", None));
+
+        assert!(rules.matches_parts("/peers/", None));
+        assert!(rules.matches_parts("/a/../peers", None));
+        assert!(!rules.matches_parts("/peers/a", None));
+        assert!(!rules.matches_parts("/peersa", None));
+    }
+}
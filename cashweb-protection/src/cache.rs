@@ -0,0 +1,253 @@
+//! This module contains the [`CachedValidator`] which caches successful token validations.
+//!
+//! Validation of some schemes, such as the chain commitment scheme, requires a round trip to a
+//! bitcoin node. Caching the results, keyed by the token and the digest of the data it covers,
+//! allows repeated requests with the same POP token and data to skip this.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt,
+    future::ready,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_core::Future;
+use ring::digest::{Context, SHA256};
+
+use crate::{AuthContext, TokenValidator};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// A [`Hasher`] feeding the bytes written into a SHA256 digest.
+struct DigestHasher(Context);
+
+impl Hasher for DigestHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finish();
+        u64::from_le_bytes(digest.as_ref()[..8].try_into().unwrap()) // This is safe
+    }
+}
+
+/// Calculate the SHA256 digest of the data covered by a token, as fed by its [`Hash`]
+/// implementation.
+pub fn data_digest<D: Hash + ?Sized>(data: &D) -> [u8; 32] {
+    let mut hasher = DigestHasher(Context::new(&SHA256));
+    data.hash(&mut hasher);
+    hasher.0.finish().as_ref().try_into().unwrap() // This is safe
+}
+
+/// Collection of validation outputs, keyed by token and the digest of the data.
+#[derive(Debug)]
+pub struct ValidationCache<O> {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(String, [u8; 32]), (Instant, O)>>,
+}
+
+impl<O: Clone> ValidationCache<O> {
+    /// Create a new [`ValidationCache`] holding at most `max_entries` entries, each living for `ttl`.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Default::default(),
+        }
+    }
+
+    /// Get the output associated with the token and data digest, if present and not expired.
+    pub fn get(&self, token: &str, data_digest: &[u8; 32]) -> Option<O> {
+        self.get_at(token, data_digest, Instant::now())
+    }
+
+    /// Get the output associated with the token and data digest at a given instant.
+    pub fn get_at(&self, token: &str, data_digest: &[u8; 32], now: Instant) -> Option<O> {
+        let key = (token.to_string(), *data_digest);
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some((expiry, output)) if now < *expiry => Some(output.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Insert the output associated with the token and data digest.
+    pub fn insert(&self, token: String, data_digest: [u8; 32], output: O) {
+        self.insert_at(token, data_digest, output, Instant::now())
+    }
+
+    /// Insert the output associated with the token and data digest at a given instant.
+    pub fn insert_at(&self, token: String, data_digest: [u8; 32], output: O, now: Instant) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let key = (token, data_digest);
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            // Evict expired entries
+            entries.retain(|_, (expiry, _)| now < *expiry);
+
+            // Evict the entry closest to expiry
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (expiry, _))| *expiry)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+
+        entries.insert(key, (now + self.ttl, output));
+    }
+}
+
+/// A [`TokenValidator`] which caches the output of successful validations, keyed by the token and
+/// the [`data_digest`].
+///
+/// Failed validations are not cached.
+pub struct CachedValidator<V: TokenValidator> {
+    inner: Arc<V>,
+    cache: Arc<ValidationCache<V::Output>>,
+}
+
+impl<V: TokenValidator> Clone for CachedValidator<V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<V> fmt::Debug for CachedValidator<V>
+where
+    V: TokenValidator + fmt::Debug,
+    V::Output: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedValidator")
+            .field("inner", &self.inner)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl<V> CachedValidator<V>
+where
+    V: TokenValidator,
+    V::Output: Clone,
+{
+    /// Wrap a validator with a cache.
+    pub fn new(inner: V, ttl: Duration, max_entries: usize) -> Self {
+        Self::from_arc(Arc::new(inner), ttl, max_entries)
+    }
+
+    pub(crate) fn from_arc(inner: Arc<V>, ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(ValidationCache::new(ttl, max_entries)),
+        }
+    }
+
+    /// Get a reference to the cache.
+    pub fn cache(&self) -> &ValidationCache<V::Output> {
+        &self.cache
+    }
+}
+
+impl<V> TokenValidator for CachedValidator<V>
+where
+    V: TokenValidator,
+    V::Data: Hash,
+    V::Output: Clone + Send + Sync + 'static,
+    V::Error: Send + 'static,
+    V::Future: Send + 'static,
+{
+    type Data = V::Data;
+    type Output = V::Output;
+    type Error = V::Error;
+    type Future = FutResponse<Self::Output, Self::Error>;
+
+    fn validate(&self, data: Self::Data, token: String) -> Self::Future {
+        let data_digest = data_digest(&data);
+        if let Some(output) = self.cache.get(&token, &data_digest) {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("cashweb_protection_cache_hits", 1);
+            return Box::pin(ready(Ok(output)));
+        }
+
+        let validation = self.inner.validate(data, token.clone());
+        let cache = self.cache.clone();
+        let fut = async move {
+            let output = validation.await?;
+            cache.insert(token, data_digest, output.clone());
+            Ok(output)
+        };
+        Box::pin(fut)
+    }
+
+    fn auth_context(&self, token: String, output: &Self::Output) -> AuthContext {
+        self.inner.auth_context(token, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{block_on, EqualValidator};
+
+    #[test]
+    fn expiry() {
+        let cache = ValidationCache::new(Duration::from_secs(1), 8);
+        let now = Instant::now();
+        cache.insert_at("a".to_string(), [0; 32], 1, now);
+        assert_eq!(cache.get_at("a", &[0; 32], now), Some(1));
+        assert_eq!(cache.get_at("b", &[0; 32], now), None);
+        assert_eq!(cache.get_at("a", &[1; 32], now), None);
+        assert_eq!(
+            cache.get_at("a", &[0; 32], now + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn max_entries() {
+        let cache = ValidationCache::new(Duration::from_secs(10), 2);
+        let now = Instant::now();
+        cache.insert_at("a".to_string(), [0; 32], 1, now);
+        cache.insert_at("b".to_string(), [0; 32], 2, now + Duration::from_secs(1));
+        cache.insert_at("c".to_string(), [0; 32], 3, now + Duration::from_secs(2));
+        assert_eq!(cache.get_at("a", &[0; 32], now), None);
+        assert_eq!(cache.get_at("b", &[0; 32], now), Some(2));
+        assert_eq!(cache.get_at("c", &[0; 32], now), Some(3));
+    }
+
+    #[test]
+    fn data_mismatch() {
+        let validator = CachedValidator::new(EqualValidator, Duration::from_secs(10), 8);
+        block_on(validator.validate(b"a".to_vec(), "a".to_string())).unwrap();
+        assert_eq!(
+            validator.cache().get("a", &data_digest(&b"a".to_vec())),
+            Some(())
+        );
+
+        // The same token covering different data is validated again
+        let err = block_on(validator.validate(b"b".to_vec(), "a".to_string())).unwrap_err();
+        assert_eq!(err, "mismatch");
+    }
+}
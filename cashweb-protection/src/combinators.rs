@@ -0,0 +1,218 @@
+//! This module contains combinators composing multiple [`TokenValidator`]s into one.
+//!
+//! [`AnyOf`] accepts a token if either validator accepts it, reporting which succeeded via
+//! [`Either`], while [`AllOf`] requires that both validators accept it. The output is inserted
+//! into the request extensions by the [`ProtectedService`], making it available to the inner service.
+//!
+//! [`ProtectedService`]: crate::ProtectedService
+
+use std::{fmt, pin::Pin};
+
+use futures_core::Future;
+use thiserror::Error;
+
+use crate::{AuthContext, TokenValidator};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// Represents a value from one of two validators.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// Value from the first validator.
+    First(A),
+    /// Value from the second validator.
+    Second(B),
+}
+
+/// Error associated with [`AnyOf`], both validators rejected the token.
+#[derive(Debug, Error)]
+#[error("all validators failed: {0}, {1}")]
+pub struct AnyOfError<A: fmt::Debug + fmt::Display, B: fmt::Debug + fmt::Display>(pub A, pub B);
+
+/// Error associated with [`AllOf`], one of the validators rejected the token.
+#[derive(Debug, Error)]
+pub enum AllOfError<A: fmt::Debug + fmt::Display, B: fmt::Debug + fmt::Display> {
+    /// The first validator rejected the token.
+    #[error("first validator failed: {0}")]
+    First(A),
+    /// The second validator rejected the token.
+    #[error("second validator failed: {0}")]
+    Second(B),
+}
+
+/// Accepts a token if either validator accepts it, the first validator is tried first.
+///
+/// The data is a pair consisting of the data for each validator.
+#[derive(Clone, Debug)]
+pub struct AnyOf<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> AnyOf<A, B> {
+    /// Create a new [`AnyOf`] combinator.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A, B> TokenValidator for AnyOf<A, B>
+where
+    A: TokenValidator,
+    A::Error: fmt::Debug + fmt::Display + Send + 'static,
+    A::Output: Send + 'static,
+    A::Future: Send + 'static,
+    B: TokenValidator,
+    B::Error: fmt::Debug + fmt::Display + Send + 'static,
+    B::Output: Send + 'static,
+    B::Future: Send + 'static,
+{
+    type Data = (A::Data, B::Data);
+    type Output = Either<A::Output, B::Output>;
+    type Error = AnyOfError<A::Error, B::Error>;
+    type Future = FutResponse<Self::Output, Self::Error>;
+
+    fn validate(&self, (first_data, second_data): Self::Data, token: String) -> Self::Future {
+        let first = self.first.validate(first_data, token.clone());
+        let second = self.second.validate(second_data, token);
+        Box::pin(async move {
+            let first_err = match first.await {
+                Ok(output) => return Ok(Either::First(output)),
+                Err(err) => err,
+            };
+            match second.await {
+                Ok(output) => Ok(Either::Second(output)),
+                Err(second_err) => Err(AnyOfError(first_err, second_err)),
+            }
+        })
+    }
+
+    fn auth_context(&self, token: String, output: &Self::Output) -> AuthContext {
+        match output {
+            Either::First(output) => self.first.auth_context(token, output),
+            Either::Second(output) => self.second.auth_context(token, output),
+        }
+    }
+}
+
+/// Accepts a token only if both validators accept it.
+///
+/// The data is a pair consisting of the data for each validator.
+#[derive(Clone, Debug)]
+pub struct AllOf<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> AllOf<A, B> {
+    /// Create a new [`AllOf`] combinator.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A, B> TokenValidator for AllOf<A, B>
+where
+    A: TokenValidator,
+    A::Error: fmt::Debug + fmt::Display + Send + 'static,
+    A::Output: Send + 'static,
+    A::Future: Send + 'static,
+    B: TokenValidator,
+    B::Error: fmt::Debug + fmt::Display + Send + 'static,
+    B::Output: Send + 'static,
+    B::Future: Send + 'static,
+{
+    type Data = (A::Data, B::Data);
+    type Output = (A::Output, B::Output);
+    type Error = AllOfError<A::Error, B::Error>;
+    type Future = FutResponse<Self::Output, Self::Error>;
+
+    fn validate(&self, (first_data, second_data): Self::Data, token: String) -> Self::Future {
+        let first = self.first.validate(first_data, token.clone());
+        let second = self.second.validate(second_data, token);
+        Box::pin(async move {
+            let first_output = first.await.map_err(AllOfError::First)?;
+            let second_output = second.await.map_err(AllOfError::Second)?;
+            Ok((first_output, second_output))
+        })
+    }
+
+    /// The context of the first validator, extended by the public key hash and scopes of the second.
+    fn auth_context(&self, token: String, (first, second): &Self::Output) -> AuthContext {
+        let second_context = self.second.auth_context(token.clone(), second);
+        let mut context = self.first.auth_context(token, first);
+        if context.pub_key_hash.is_none() {
+            context.pub_key_hash = second_context.pub_key_hash;
+        }
+        context.scopes.extend(second_context.scopes);
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{block_on, EqualValidator};
+
+    #[test]
+    fn any_of() {
+        let validator = AnyOf::new(EqualValidator, EqualValidator);
+        let data = || (b"a".to_vec(), b"b".to_vec());
+        assert_eq!(
+            block_on(validator.validate(data(), "a".to_string())).unwrap(),
+            Either::First(())
+        );
+        assert_eq!(
+            block_on(validator.validate(data(), "b".to_string())).unwrap(),
+            Either::Second(())
+        );
+        block_on(validator.validate(data(), "c".to_string())).unwrap_err();
+    }
+
+    #[test]
+    fn all_of() {
+        let validator = AllOf::new(EqualValidator, EqualValidator);
+        block_on(validator.validate((b"a".to_vec(), b"a".to_vec()), "a".to_string())).unwrap();
+        let err = block_on(validator.validate((b"a".to_vec(), b"b".to_vec()), "a".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, AllOfError::Second(_)));
+        let err = block_on(validator.validate((b"b".to_vec(), b"a".to_vec()), "a".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, AllOfError::First(_)));
+    }
+
+    /// Accepts any token, attaching a public key hash and scope.
+    #[derive(Debug)]
+    struct Bound;
+
+    impl TokenValidator for Bound {
+        type Data = ();
+        type Output = ();
+        type Error = &'static str;
+        type Future = std::future::Ready<Result<(), &'static str>>;
+
+        fn validate(&self, _: (), _: String) -> Self::Future {
+            std::future::ready(Ok(()))
+        }
+
+        fn auth_context(&self, token: String, _: &()) -> AuthContext {
+            AuthContext::new(token, "bound")
+                .with_pub_key_hash(vec![1; 20])
+                .with_scope(token::scope::Scope::new(http::Method::GET, "/".to_string()))
+        }
+    }
+
+    #[test]
+    fn auth_context() {
+        let validator = AnyOf::new(EqualValidator, Bound);
+        let context = validator.auth_context("a".to_string(), &Either::Second(()));
+        assert_eq!(context.scheme, "bound");
+
+        let validator = AllOf::new(EqualValidator, Bound);
+        let context = validator.auth_context("a".to_string(), &((), ()));
+        assert_eq!(context.scheme, "custom");
+        assert_eq!(context.pub_key_hash, Some(vec![1; 20]));
+        assert_eq!(context.scopes.len(), 1);
+    }
+}
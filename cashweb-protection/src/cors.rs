@@ -0,0 +1,297 @@
+//! This module contains the [`CorsLayer`] which adds [`CORS`] headers to responses for configured
+//! origins.
+//!
+//! Browser-based clients cannot read a response lacking the appropriate CORS headers, including
+//! the `401 Unauthorized` and `402 Payment Required` challenges. The [`CorsLayer`] should therefore
+//! wrap the whole stack, outside of the [`PaymentRequiredLayer`] or [`IntoResponseLayer`], so
+//! that rejections converted into responses also carry the headers.
+//!
+//! Preflight requests from allowed origins are answered directly, they carry no POP token and
+//! would otherwise be rejected.
+//!
+//! [`CORS`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS
+//! [`PaymentRequiredLayer`]: crate::PaymentRequiredLayer
+//! [`IntoResponseLayer`]: crate::adapters::IntoResponseLayer
+
+use std::{pin::Pin, sync::Arc, time::Duration};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{
+    header::{
+        HeaderName, HeaderValue, ACCEPT, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD,
+        AUTHORIZATION, CONTENT_TYPE, ORIGIN, VARY, WWW_AUTHENTICATE,
+    },
+    Method, Request, Response, StatusCode,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The origins allowed to make cross-origin requests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// Any origin is allowed.
+    Any,
+    /// Only the listed origins are allowed.
+    List(Vec<HeaderValue>),
+}
+
+/// Configuration of the CORS headers emitted.
+///
+/// By default no origins are allowed. The `Authorization`, `Content-Type` and `Accept` headers are
+/// allowed, and the `WWW-Authenticate` and `Content-Type` headers are exposed, allowing clients to
+/// read POP challenges and invoices.
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    origins: AllowedOrigins,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: AllowedOrigins::List(Vec::new()),
+            methods: vec![Method::GET, Method::PUT, Method::POST, Method::DELETE],
+            headers: vec![AUTHORIZATION, CONTENT_TYPE, ACCEPT],
+            expose_headers: vec![WWW_AUTHENTICATE, CONTENT_TYPE],
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+/// Join header names or methods into a comma separated header value.
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+    let joined = items
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+    HeaderValue::from_str(&joined).unwrap() // This is safe
+}
+
+impl CorsConfig {
+    /// Create the default [`CorsConfig`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allow an origin, for example `https://wallet.example.com`.
+    pub fn allow_origin(mut self, origin: HeaderValue) -> Self {
+        match &mut self.origins {
+            AllowedOrigins::Any => (),
+            AllowedOrigins::List(origins) => origins.push(origin),
+        }
+        self
+    }
+
+    /// Allow any origin.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = AllowedOrigins::Any;
+        self
+    }
+
+    /// Set the methods allowed.
+    pub fn allow_methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    /// Set the request headers allowed.
+    pub fn allow_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Set the response headers exposed to the client.
+    pub fn expose_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.expose_headers = headers;
+        self
+    }
+
+    /// Set the duration for which preflight responses may be cached.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Allow credentials, such as cookies, to be included in requests.
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    /// Whether the origin is allowed.
+    pub fn is_allowed(&self, origin: &HeaderValue) -> bool {
+        match &self.origins {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(origins) => origins.contains(origin),
+        }
+    }
+
+    /// Add the CORS headers to a response to a request from an allowed origin.
+    pub fn apply<B>(&self, origin: &HeaderValue, response: &mut Response<B>) {
+        let headers = response.headers_mut();
+
+        // Credentialed requests require the origin to be echoed
+        if self.origins == AllowedOrigins::Any && !self.credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.append(VARY, HeaderValue::from_static("origin"));
+        }
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.expose_headers.is_empty() {
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, join(&self.expose_headers));
+        }
+    }
+
+    /// Construct the response to a preflight request from an allowed origin.
+    pub fn preflight_response<B: Default>(&self, origin: &HeaderValue) -> Response<B> {
+        let mut response = Response::new(B::default());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        self.apply(origin, &mut response);
+
+        let headers = response.headers_mut();
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+        if !self.headers.is_empty() {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, join(&self.headers));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+        response
+    }
+}
+
+/// A [`Layer`] producing [`CorsService`]s.
+#[derive(Clone, Debug)]
+pub struct CorsLayer {
+    config: Arc<CorsConfig>,
+}
+
+impl CorsLayer {
+    /// Create a new [`CorsLayer`].
+    pub fn new(config: CorsConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// A [`Service`] adding CORS headers to the responses of the inner service.
+#[derive(Clone, Debug)]
+pub struct CorsService<S> {
+    inner: S,
+    config: Arc<CorsConfig>,
+}
+
+impl<S, ReqB, ResB> Service<Request<ReqB>> for CorsService<S>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>>,
+    S::Future: Send + 'static,
+    ResB: Default + Send + 'static,
+{
+    type Response = Response<ResB>;
+    type Error = S::Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<ReqB>) -> Self::Future {
+        let origin = request
+            .headers()
+            .get(ORIGIN)
+            .filter(|origin| self.config.is_allowed(origin))
+            .cloned();
+
+        // Answer preflight requests
+        if let Some(origin) = &origin {
+            if request.method() == Method::OPTIONS
+                && request
+                    .headers()
+                    .contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+            {
+                let response = self.config.preflight_response(origin);
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+
+        let config = self.config.clone();
+        let fut = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = fut.await?;
+            if let Some(origin) = origin {
+                config.apply(&origin, &mut response);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_origins() {
+        let origin = HeaderValue::from_static("https://wallet.example.com");
+        let config = CorsConfig::new().allow_origin(origin.clone());
+        assert!(config.is_allowed(&origin));
+        assert!(!config.is_allowed(&HeaderValue::from_static("https://evil.example.com")));
+
+        let mut response = Response::new(());
+        *response.status_mut() = StatusCode::PAYMENT_REQUIRED;
+        config.apply(&origin, &mut response);
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_EXPOSE_HEADERS],
+            "www-authenticate, content-type"
+        );
+    }
+
+    #[test]
+    fn preflight() {
+        let origin = HeaderValue::from_static("https://wallet.example.com");
+        let config = CorsConfig::new()
+            .allow_any_origin()
+            .max_age(Duration::from_secs(600));
+        let response: Response<Vec<u8>> = config.preflight_response(&origin);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, PUT, POST, DELETE"
+        );
+        assert_eq!(response.headers()[ACCESS_CONTROL_MAX_AGE], "600");
+    }
+}
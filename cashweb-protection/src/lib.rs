@@ -0,0 +1,568 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-protection` is a library providing [`tower`] middleware which protects resources using
+//! the [`POP Token Protocol`].
+//!
+//! The [`ProtectionLayer`] wraps a service, extracting a POP token from each request using a
+//! [`TokenExtractor`] and validating it using a [`TokenValidator`] before the request is passed to
+//! the inner service.
+//!
+//! On success the [`TokenValidator::Output`] and an [`AuthContext`] are inserted into the request
+//! extensions, allowing handlers to authorize requests without re-parsing the token.
+//!
+//! Servers exposing routes with differing requirements can configure them together using the
+//! [`ProtectionConfigBuilder`], which produces a single layer.
+//!
+//! State-changing endpoints can additionally be guarded against replayed requests using the
+//! [`ReplayGuardLayer`], which requires a unique nonce in each request and is applied inside the
+//! [`ProtectionLayer`] so that nonces are only recorded for validated tokens.
+//!
+//! Browser-based clients are supported by wrapping the stack in a [`CorsLayer`], which adds CORS
+//! headers to all responses, including challenges, for the configured origins.
+//!
+//! When the `metrics` feature is enabled the [`ProtectedService`] emits the following via the
+//! [`metrics`] facade:
+//! * `cashweb_protection_successes`: counter of successfully validated requests.
+//! * `cashweb_protection_failures`: counter of rejected requests, labelled by `reason`.
+//! * `cashweb_protection_validation_ns`: histogram of token validation latency in nanoseconds.
+//! * `cashweb_protection_bypassed`: counter of requests matching the [`BypassRules`].
+//! * `cashweb_protection_cache_hits`: counter of validations served from the [`ValidationCache`].
+//!
+//! [`tower`]: https://docs.rs/tower
+//! [`metrics`]: https://docs.rs/metrics
+//! [`POP Token Protocol`]: https://github.com/cashweb/specifications/blob/master/proof-of-payment-token/specification.mediawiki
+
+#[cfg(feature = "hyper")]
+pub mod adapters;
+pub mod audit;
+pub mod bypass;
+pub mod cache;
+pub mod combinators;
+pub mod cors;
+pub mod payment_required;
+pub mod rate_limit;
+pub mod replay;
+pub mod response;
+pub mod routes;
+
+use std::{fmt, pin::Pin, sync::Arc, time::Duration};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{Request, StatusCode};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use rate_limit::RateLimiter;
+
+pub use audit::{AuditEvent, AuditLayer};
+pub use bypass::{BypassRules, IpRange};
+pub use cache::{CachedValidator, ValidationCache};
+pub use combinators::{AllOf, AnyOf, Either};
+pub use cors::{CorsConfig, CorsLayer, CorsService};
+pub use payment_required::{PaymentRequiredLayer, PaymentRequiredService};
+pub use rate_limit::{InvalidRateLimit, RateLimit, RateLimitLayer, RateLimited};
+pub use replay::{MemoryNonceStore, NonceStore, RedisNonceStore, ReplayGuardLayer, ReplayRejected};
+pub use response::{guard_error_response, ResponseMapper};
+pub use routes::{ProtectionConfigBuilder, Route, RoutedProtectedService, RoutedProtectionLayer};
+pub use token::{
+    AuthContext, ExtractionConfig, PopTokenExtractor, TokenExtractor, TokenSource, TokenValidator,
+};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// Error associated with the [`ProtectedService`].
+#[derive(Debug, Error)]
+pub enum GuardError<V: fmt::Debug + fmt::Display, S: fmt::Debug + fmt::Display> {
+    /// No POP token was found in the request.
+    #[error("no authorization data")]
+    NoAuthData,
+    /// The POP token failed validation.
+    #[error("failed to validate token: {0}")]
+    TokenValidate(V),
+    /// The request exceeded the rate limit.
+    #[error("{0}")]
+    RateLimited(RateLimited),
+    /// The request was rejected by the [`ReplayGuard`](replay::ReplayGuard).
+    #[error("replay rejected: {0}")]
+    Replay(ReplayRejected),
+    /// Error executing the inner service.
+    #[error("failed to execute service method: {0}")]
+    Service(S),
+}
+
+impl<V, S> GuardError<V, S>
+where
+    V: fmt::Debug + fmt::Display,
+    S: fmt::Debug + fmt::Display,
+{
+    /// A short, static label identifying the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::NoAuthData => "no_auth_data",
+            Self::TokenValidate(_) => "token_validate",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Replay(_) => "replay",
+            Self::Service(_) => "service",
+        }
+    }
+
+    /// The HTTP status code appropriate for the error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NoAuthData | Self::TokenValidate(_) => StatusCode::UNAUTHORIZED,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Replay(rejection) => rejection.status_code(),
+            Self::Service(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl<V, S> From<RateLimited> for GuardError<V, S>
+where
+    V: fmt::Debug + fmt::Display,
+    S: fmt::Debug + fmt::Display,
+{
+    fn from(rate_limited: RateLimited) -> Self {
+        GuardError::RateLimited(rate_limited)
+    }
+}
+
+impl<V, S> From<ReplayRejected> for GuardError<V, S>
+where
+    V: fmt::Debug + fmt::Display,
+    S: fmt::Debug + fmt::Display,
+{
+    fn from(rejection: ReplayRejected) -> Self {
+        GuardError::Replay(rejection)
+    }
+}
+
+/// Uses the request path as the data covered by the token.
+pub fn path_data<B>(request: &Request<B>) -> Vec<u8> {
+    request.uri().path().as_bytes().to_vec()
+}
+
+/// Await the validation of a token then, on success, attach the [`AuthContext`] and validator
+/// output to the request and call the inner service.
+///
+/// If a [`RateLimiter`] is given, it is checked using the validated token.
+async fn validate_and_call<S, V, B>(
+    mut inner: S,
+    validator: Arc<V>,
+    validation: V::Future,
+    token: String,
+    limiter: Option<Arc<RateLimiter>>,
+    mut request: Request<B>,
+) -> Result<S::Response, GuardError<V::Error, S::Error>>
+where
+    S: Service<Request<B>>,
+    S::Error: fmt::Debug + fmt::Display,
+    V: TokenValidator,
+    V::Output: Send + Sync + 'static,
+    V::Error: fmt::Debug + fmt::Display,
+{
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+
+    let validation_result = validation.await;
+
+    #[cfg(feature = "metrics")]
+    {
+        let elapsed = start.elapsed().as_nanos() as u64;
+        metrics::histogram!("cashweb_protection_validation_ns", elapsed);
+        if validation_result.is_ok() {
+            metrics::counter!("cashweb_protection_successes", 1);
+        } else {
+            metrics::counter!("cashweb_protection_failures", 1, "reason" => "token_validate");
+        }
+    }
+
+    let output = validation_result.map_err(GuardError::TokenValidate)?;
+
+    // Apply rate limit
+    if let Some(limiter) = limiter {
+        if let Err(err) = limiter.check(&token) {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("cashweb_protection_failures", 1, "reason" => "rate_limited");
+            return Err(err.into());
+        }
+    }
+
+    // Attach identity
+    let context = validator.auth_context(token, &output);
+    request.extensions_mut().insert(context);
+    request.extensions_mut().insert(output);
+    let result = inner.call(request).await.map_err(GuardError::Service);
+
+    #[cfg(feature = "metrics")]
+    {
+        if result.is_err() {
+            metrics::counter!("cashweb_protection_failures", 1, "reason" => "service");
+        }
+    }
+
+    result
+}
+
+/// A [`Layer`] producing [`ProtectedService`]s.
+///
+/// The `data_fn` constructs the data the token is expected to cover from the incoming request.
+pub struct ProtectionLayer<V, E, F> {
+    validator: Arc<V>,
+    extractor: Arc<E>,
+    bypass: Arc<BypassRules>,
+    limiter: Option<Arc<RateLimiter>>,
+    data_fn: F,
+}
+
+impl<V, E, F> Clone for ProtectionLayer<V, E, F>
+where
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            validator: self.validator.clone(),
+            extractor: self.extractor.clone(),
+            bypass: self.bypass.clone(),
+            limiter: self.limiter.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+impl<V: fmt::Debug, E: fmt::Debug, F> fmt::Debug for ProtectionLayer<V, E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtectionLayer")
+            .field("validator", &self.validator)
+            .field("extractor", &self.extractor)
+            .field("bypass", &self.bypass)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl<V, F> ProtectionLayer<V, PopTokenExtractor, F> {
+    /// Create a new [`ProtectionLayer`] using the default [`PopTokenExtractor`].
+    pub fn new(validator: V, data_fn: F) -> Self {
+        Self {
+            validator: Arc::new(validator),
+            extractor: Arc::new(PopTokenExtractor::default()),
+            bypass: Default::default(),
+            limiter: None,
+            data_fn,
+        }
+    }
+}
+
+impl<V, E, F> ProtectionLayer<V, E, F> {
+    /// Replace the [`TokenExtractor`].
+    pub fn with_extractor<T>(self, extractor: T) -> ProtectionLayer<V, T, F> {
+        ProtectionLayer {
+            validator: self.validator,
+            extractor: Arc::new(extractor),
+            bypass: self.bypass,
+            limiter: self.limiter,
+            data_fn: self.data_fn,
+        }
+    }
+
+    /// Allow requests matching the [`BypassRules`] to skip token validation.
+    pub fn with_bypass(mut self, bypass: BypassRules) -> Self {
+        self.bypass = Arc::new(bypass);
+        self
+    }
+
+    /// Limit requests, keyed by validated token, tracking at most `max_keys` tokens.
+    ///
+    /// Tokens failing validation do not consume the allowance.
+    pub fn with_rate_limit(mut self, limit: RateLimit, max_keys: usize) -> Self {
+        self.limiter = Some(Arc::new(RateLimiter::new(limit, max_keys)));
+        self
+    }
+
+    /// Cache successful validations, keyed by token and data, for `ttl` and holding at most
+    /// `max_entries`.
+    pub fn with_cache(
+        self,
+        ttl: Duration,
+        max_entries: usize,
+    ) -> ProtectionLayer<CachedValidator<V>, E, F>
+    where
+        V: TokenValidator,
+        V::Output: Clone,
+    {
+        ProtectionLayer {
+            validator: Arc::new(CachedValidator::from_arc(self.validator, ttl, max_entries)),
+            extractor: self.extractor,
+            bypass: self.bypass,
+            limiter: self.limiter,
+            data_fn: self.data_fn,
+        }
+    }
+}
+
+impl<S, V, E, F> Layer<S> for ProtectionLayer<V, E, F>
+where
+    F: Clone,
+{
+    type Service = ProtectedService<S, V, E, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ProtectedService {
+            inner,
+            validator: self.validator.clone(),
+            extractor: self.extractor.clone(),
+            bypass: self.bypass.clone(),
+            limiter: self.limiter.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+/// A [`Service`] which validates the POP token attached to each request before calling the inner
+/// service.
+pub struct ProtectedService<S, V, E, F> {
+    inner: S,
+    validator: Arc<V>,
+    extractor: Arc<E>,
+    bypass: Arc<BypassRules>,
+    limiter: Option<Arc<RateLimiter>>,
+    data_fn: F,
+}
+
+impl<S, V, E, F> Clone for ProtectedService<S, V, E, F>
+where
+    S: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            validator: self.validator.clone(),
+            extractor: self.extractor.clone(),
+            bypass: self.bypass.clone(),
+            limiter: self.limiter.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, V: fmt::Debug, E: fmt::Debug, F> fmt::Debug for ProtectedService<S, V, E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtectedService")
+            .field("inner", &self.inner)
+            .field("validator", &self.validator)
+            .field("extractor", &self.extractor)
+            .field("bypass", &self.bypass)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl<S, V, E, F> ProtectedService<S, V, E, F> {
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Convert into the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, V, E, F, B> Service<Request<B>> for ProtectedService<S, V, E, F>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Response: Send + 'static,
+    S::Error: fmt::Debug + fmt::Display + Send + 'static,
+    S::Future: Send,
+    V: TokenValidator + Send + Sync + 'static,
+    V::Output: Send + Sync + 'static,
+    V::Error: fmt::Debug + fmt::Display + Send + 'static,
+    V::Future: Send + 'static,
+    E: TokenExtractor,
+    F: Fn(&Request<B>) -> V::Data,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = GuardError<V::Error, S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context).map_err(GuardError::Service)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // Skip validation for public resources
+        if self.bypass.matches(&request) {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("cashweb_protection_bypassed", 1);
+            let clone = self.inner.clone();
+            let mut inner = std::mem::replace(&mut self.inner, clone);
+            return Box::pin(async move { inner.call(request).await.map_err(GuardError::Service) });
+        }
+
+        // Extract token
+        let token = match self.extractor.extract(request.headers(), request.uri()) {
+            Some(token) => token.to_string(),
+            None => {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("cashweb_protection_failures", 1, "reason" => "no_auth_data");
+                return Box::pin(async { Err(GuardError::NoAuthData) });
+            }
+        };
+
+        // Validate token
+        let data = (self.data_fn)(&request);
+        let validation = self.validator.validate(data, token.clone());
+        let validator = self.validator.clone();
+
+        // Take the service which was polled ready
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+
+        let limiter = self.limiter.clone();
+        let fut = validate_and_call(inner, validator, validation, token, limiter, request);
+        Box::pin(fut)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+    };
+
+    use http::header::{HeaderValue, AUTHORIZATION, RETRY_AFTER};
+
+    use super::*;
+
+    /// Run a future to completion on a single threaded runtime.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Accepts tokens equal to the data.
+    #[derive(Debug)]
+    pub(crate) struct EqualValidator;
+
+    impl TokenValidator for EqualValidator {
+        type Data = Vec<u8>;
+        type Output = ();
+        type Error = &'static str;
+        type Future = Ready<Result<(), &'static str>>;
+
+        fn validate(&self, data: Vec<u8>, token: String) -> Self::Future {
+            if data == token.as_bytes() {
+                ready(Ok(()))
+            } else {
+                ready(Err("mismatch"))
+            }
+        }
+    }
+
+    /// Responds with the [`AuthContext`] attached to the request.
+    #[derive(Clone, Debug)]
+    pub(crate) struct Identity;
+
+    impl<B> Service<Request<B>> for Identity {
+        type Response = Option<AuthContext>;
+        type Error = Infallible;
+        type Future = Ready<Result<Option<AuthContext>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<B>) -> Self::Future {
+            ready(Ok(request.extensions().get::<AuthContext>().cloned()))
+        }
+    }
+
+    pub(crate) fn pop_request(path: &str, token: Option<&'static str>) -> Request<()> {
+        let mut request = Request::builder().uri(path).body(()).unwrap();
+        if let Some(token) = token {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, HeaderValue::from_static(token));
+        }
+        request
+    }
+
+    #[test]
+    fn attach_context() {
+        let mut service = ProtectionLayer::new(EqualValidator, path_data).layer(Identity);
+        let context = block_on(service.call(pop_request("/a", Some("POP /a"))))
+            .unwrap()
+            .unwrap();
+        assert_eq!(context, AuthContext::new("/a".to_string(), "custom"));
+    }
+
+    #[test]
+    fn reject_token() {
+        let mut service = ProtectionLayer::new(EqualValidator, path_data).layer(Identity);
+
+        let err = block_on(service.call(pop_request("/a", None))).unwrap_err();
+        assert!(matches!(err, GuardError::NoAuthData));
+
+        let err = block_on(service.call(pop_request("/a", Some("POP /b")))).unwrap_err();
+        assert!(matches!(err, GuardError::TokenValidate("mismatch")));
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn rate_limit_validated_tokens() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).unwrap();
+        let mut service = ProtectionLayer::new(EqualValidator, path_data)
+            .with_rate_limit(limit, 8)
+            .layer(Identity);
+
+        // Invalid tokens do not consume the allowance of the valid token
+        let err = block_on(service.call(pop_request("/a", Some("POP /b")))).unwrap_err();
+        assert!(matches!(err, GuardError::TokenValidate(_)));
+        block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+
+        let err = block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap_err();
+        assert!(matches!(err, GuardError::RateLimited(_)));
+        let response: http::Response<Vec<u8>> = guard_error_response(err);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+    }
+
+    #[test]
+    fn error_labels() {
+        type Error = GuardError<&'static str, Infallible>;
+        assert_eq!(Error::NoAuthData.label(), "no_auth_data");
+        assert_eq!(Error::TokenValidate("mismatch").label(), "token_validate");
+        let rate_limited = Error::RateLimited(RateLimited {
+            retry_after: Duration::from_secs(1),
+        });
+        assert_eq!(rate_limited.label(), "rate_limited");
+        assert_eq!(rate_limited.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_without_recorder() {
+        let mut service = ProtectionLayer::new(EqualValidator, path_data).layer(Identity);
+        block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+        block_on(service.call(pop_request("/a", Some("POP /b")))).unwrap_err();
+        block_on(service.call(pop_request("/a", None))).unwrap_err();
+    }
+}
@@ -0,0 +1,286 @@
+//! This module contains the [`PaymentRequiredLayer`] which responds to requests rejected by a
+//! [`ProtectedService`] with `402 Payment Required` and a [`BIP70`] invoice.
+//!
+//! The invoice outputs are registered in a [`Wallet`], keyed by the `merchant_data`, so that the
+//! subsequent payment can be matched against the invoice. Invoices without `merchant_data` cannot
+//! be matched, in this case the original rejection is returned.
+//!
+//! The [`Wallet`] returns a future removing the outputs after its timeout. The library does not
+//! assume an executor, the layer is given a function which spawns these futures.
+//!
+//! [`ProtectedService`]: crate::ProtectedService
+//! [`BIP70`]: https://github.com/bitcoin/bips/blob/master/bip-0070.mediawiki
+
+use std::{fmt, pin::Pin};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{
+    header::{HeaderValue, CONTENT_TYPE, WWW_AUTHENTICATE},
+    Method, Request, Response, StatusCode, Uri,
+};
+use payments::{
+    bip70::{Output, PaymentDetails},
+    construct_payment_request,
+    wallet::Wallet,
+    PAYMENT_REQUEST_CONTENT_TYPE,
+};
+use prost::Message as _;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{response::POP_CHALLENGE, GuardError};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// A future removing the invoice outputs from the [`Wallet`] after its timeout.
+pub type Expiry = Pin<Box<dyn Future<Output = ()> + 'static + Send>>;
+
+/// Construct the `402 Payment Required` response containing the serialized [`PaymentRequest`].
+///
+/// [`PaymentRequest`]: payments::bip70::PaymentRequest
+pub fn payment_required_response<B: From<Vec<u8>>>(
+    payment_details: &PaymentDetails,
+) -> Response<B> {
+    let payment_request = construct_payment_request(payment_details);
+    let mut raw_payment_request = Vec::with_capacity(payment_request.encoded_len());
+    payment_request.encode(&mut raw_payment_request).unwrap(); // This is safe
+
+    let mut response = Response::new(B::from(raw_payment_request));
+    *response.status_mut() = StatusCode::PAYMENT_REQUIRED;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(PAYMENT_REQUEST_CONTENT_TYPE),
+    );
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static(POP_CHALLENGE));
+    response
+}
+
+/// A [`Layer`] producing [`PaymentRequiredService`]s.
+///
+/// The `generator` constructs the [`PaymentDetails`] from the request method and [`Uri`], the
+/// `merchant_data` is used as the key within the [`Wallet`]. The `spawn` function runs each
+/// [`Expiry`] on the application's executor.
+pub struct PaymentRequiredLayer<G, X> {
+    wallet: Wallet<Vec<u8>, Output>,
+    generator: G,
+    spawn: X,
+}
+
+impl<G: Clone, X: Clone> Clone for PaymentRequiredLayer<G, X> {
+    fn clone(&self) -> Self {
+        Self {
+            wallet: self.wallet.clone(),
+            generator: self.generator.clone(),
+            spawn: self.spawn.clone(),
+        }
+    }
+}
+
+impl<G, X> fmt::Debug for PaymentRequiredLayer<G, X> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PaymentRequiredLayer")
+            .field("wallet", &self.wallet)
+            .finish()
+    }
+}
+
+impl<G, X> PaymentRequiredLayer<G, X> {
+    /// Create a new [`PaymentRequiredLayer`].
+    ///
+    /// When using `tokio`, the `spawn` function is `|expiry| { tokio::spawn(expiry); }`.
+    pub fn new(wallet: Wallet<Vec<u8>, Output>, generator: G, spawn: X) -> Self {
+        Self {
+            wallet,
+            generator,
+            spawn,
+        }
+    }
+}
+
+impl<S, G: Clone, X: Clone> Layer<S> for PaymentRequiredLayer<G, X> {
+    type Service = PaymentRequiredService<S, G, X>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PaymentRequiredService {
+            inner,
+            wallet: self.wallet.clone(),
+            generator: self.generator.clone(),
+            spawn: self.spawn.clone(),
+        }
+    }
+}
+
+/// A [`Service`] responding with `402 Payment Required` when the inner [`ProtectedService`] finds
+/// no token or an invalid token.
+///
+/// [`ProtectedService`]: crate::ProtectedService
+pub struct PaymentRequiredService<S, G, X> {
+    inner: S,
+    wallet: Wallet<Vec<u8>, Output>,
+    generator: G,
+    spawn: X,
+}
+
+impl<S: Clone, G: Clone, X: Clone> Clone for PaymentRequiredService<S, G, X> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            wallet: self.wallet.clone(),
+            generator: self.generator.clone(),
+            spawn: self.spawn.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, G, X> fmt::Debug for PaymentRequiredService<S, G, X> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PaymentRequiredService")
+            .field("inner", &self.inner)
+            .field("wallet", &self.wallet)
+            .finish()
+    }
+}
+
+impl<S, G, X, V, E, ReqB, ResB> Service<Request<ReqB>> for PaymentRequiredService<S, G, X>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>, Error = GuardError<V, E>>,
+    S::Future: Send + 'static,
+    V: fmt::Debug + fmt::Display + Send + 'static,
+    E: fmt::Debug + fmt::Display + Send + 'static,
+    G: Fn(&Method, &Uri) -> PaymentDetails + Clone + Send + 'static,
+    X: Fn(Expiry) + Clone + Send + 'static,
+    ResB: From<Vec<u8>> + Send + 'static,
+{
+    type Response = Response<ResB>;
+    type Error = GuardError<V, E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<ReqB>) -> Self::Future {
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        let wallet = self.wallet.clone();
+        let generator = self.generator.clone();
+        let spawn = self.spawn.clone();
+
+        let fut = self.inner.call(request);
+        Box::pin(async move {
+            match fut.await {
+                Err(err @ GuardError::NoAuthData) | Err(err @ GuardError::TokenValidate(_)) => {
+                    // Generate invoice
+                    let payment_details = generator(&method, &uri);
+
+                    // Payments cannot be matched against invoices without merchant data
+                    let key = match &payment_details.merchant_data {
+                        Some(some) => some.clone(),
+                        None => return Err(err),
+                    };
+
+                    // Register outputs, removing them after the timeout
+                    let expiry = wallet.add_outputs(key, payment_details.outputs.clone());
+                    spawn(Box::pin(expiry));
+
+                    Ok(payment_required_response(&payment_details))
+                }
+                other => other,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        path_data,
+        tests::{block_on, pop_request, EqualValidator},
+        ProtectionLayer,
+    };
+
+    #[derive(Clone, Debug)]
+    struct Empty;
+
+    impl<B> Service<Request<B>> for Empty {
+        type Response = Response<Vec<u8>>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Vec<u8>>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<B>) -> Self::Future {
+            ready(Ok(Response::new(Vec::new())))
+        }
+    }
+
+    fn invoice(merchant_data: Option<Vec<u8>>) -> PaymentDetails {
+        PaymentDetails {
+            outputs: vec![Output {
+                amount: Some(1000),
+                script: vec![0x6a],
+            }],
+            merchant_data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn issue_invoice() {
+        let wallet = Wallet::new(Duration::from_secs(60));
+        let expiries = Arc::new(Mutex::new(Vec::new()));
+        let expiries_inner = expiries.clone();
+        let layer = PaymentRequiredLayer::new(
+            wallet.clone(),
+            |_: &Method, uri: &Uri| invoice(Some(uri.path().as_bytes().to_vec())),
+            move |expiry: Expiry| expiries_inner.lock().unwrap().push(expiry),
+        );
+        let mut service = layer.layer(ProtectionLayer::new(EqualValidator, path_data).layer(Empty));
+
+        let response = block_on(service.call(pop_request("/a", None))).unwrap();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            PAYMENT_REQUEST_CONTENT_TYPE
+        );
+        assert_eq!(expiries.lock().unwrap().len(), 1);
+        wallet
+            .recv_outputs(&b"/a".to_vec(), &invoice(None).outputs)
+            .unwrap();
+
+        // Valid tokens pass through
+        let response = block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(expiries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reject_missing_merchant_data() {
+        let wallet = Wallet::new(Duration::from_secs(60));
+        let layer = PaymentRequiredLayer::new(
+            wallet,
+            |_: &Method, _: &Uri| invoice(None),
+            |_: Expiry| panic!("no outputs should be registered"),
+        );
+        let mut service = layer.layer(ProtectionLayer::new(EqualValidator, path_data).layer(Empty));
+
+        let err = block_on(service.call(pop_request("/a", None))).unwrap_err();
+        assert!(matches!(err, GuardError::NoAuthData));
+    }
+}
@@ -0,0 +1,354 @@
+//! This module contains the [`RateLimitLayer`] which buckets requests by client IP, or any other
+//! key, and rejects requests once a bucket has been exhausted.
+//!
+//! Keys taken from the request before the token is validated can be chosen freely by the client,
+//! so the token should only be used as a key once validated. Token keyed limits are therefore
+//! owned by the [`ProtectionLayer`], see [`ProtectionLayer::with_rate_limit`], which checks them
+//! after validation.
+//!
+//! Rejected requests are answered with the [`RateLimited`] error, carrying the delay after which
+//! the bucket is replenished. This is converted into [`GuardError::RateLimited`] and answered with
+//! `429 Too Many Requests` and a `Retry-After` header by the [`ResponseMapper`].
+//!
+//! Each [`RateLimiter`] tracks a bounded number of keys. Once full, new keys are rejected until
+//! existing buckets have been replenished, so that clients cannot reset their own limits by
+//! rotating keys.
+//!
+//! [`ProtectionLayer`]: crate::ProtectionLayer
+//! [`ProtectionLayer::with_rate_limit`]: crate::ProtectionLayer::with_rate_limit
+//! [`GuardError::RateLimited`]: crate::GuardError::RateLimited
+//! [`ResponseMapper`]: crate::ResponseMapper
+
+use std::{
+    collections::HashMap,
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::Request;
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::bypass::client_ip;
+
+/// The request was rejected as the rate limit was exceeded.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("rate limited, retry after {retry_after:?}")]
+pub struct RateLimited {
+    /// The delay after which a request may be accepted.
+    pub retry_after: Duration,
+}
+
+/// Error associated with constructing a [`RateLimit`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InvalidRateLimit {
+    /// The burst was zero.
+    #[error("burst must be non-zero")]
+    ZeroBurst,
+    /// The period was zero.
+    #[error("period must be non-zero")]
+    ZeroPeriod,
+}
+
+/// Uses the client IP address as the rate limiting key.
+///
+/// See [`client_ip`] for how the address is found.
+pub fn client_ip_key<B>(request: &Request<B>) -> Option<String> {
+    client_ip(request).map(|client_ip| client_ip.to_string())
+}
+
+/// The rate allowed for each key.
+///
+/// Each key may make `burst` requests in a `period`, the allowance is replenished continuously.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    burst: u32,
+    period: Duration,
+}
+
+impl RateLimit {
+    /// Create a new [`RateLimit`] allowing `burst` requests in a `period`.
+    pub fn new(burst: u32, period: Duration) -> Result<Self, InvalidRateLimit> {
+        if burst == 0 {
+            return Err(InvalidRateLimit::ZeroBurst);
+        }
+        if period == Duration::from_secs(0) {
+            return Err(InvalidRateLimit::ZeroPeriod);
+        }
+        Ok(Self { burst, period })
+    }
+
+    /// The maximum number of requests which can be made at once.
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// The period over which the `burst` is replenished.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Bucket {
+    allowance: f64,
+    last_checked: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Option<Instant>,
+}
+
+/// Collection of token buckets, keyed by string.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    max_keys: usize,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// Create a new [`RateLimiter`] tracking at most `max_keys` keys.
+    ///
+    /// At least one key is always tracked.
+    pub fn new(limit: RateLimit, max_keys: usize) -> Self {
+        Self {
+            limit,
+            max_keys: max_keys.max(1),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Attempt to take from the bucket associated with the key.
+    pub fn check(&self, key: &str) -> Result<(), RateLimited> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Attempt to take from the bucket associated with the key at a given instant.
+    ///
+    /// If the key is new and the maximum number of keys are tracked, the replenished buckets are
+    /// evicted, at most once per period. If none are, the key is rejected until the next eviction.
+    pub fn check_at(&self, key: &str, now: Instant) -> Result<(), RateLimited> {
+        let burst = self.limit.burst as f64;
+        let period = self.limit.period;
+        let rate = burst / period.as_secs_f64();
+
+        let mut guard = self.buckets.lock().unwrap();
+        let Buckets {
+            buckets,
+            last_sweep,
+        } = &mut *guard;
+
+        if buckets.len() >= self.max_keys && !buckets.contains_key(key) {
+            let next_sweep = last_sweep.map(|last_sweep| last_sweep + period);
+            if next_sweep.map_or(true, |next_sweep| next_sweep <= now) {
+                // Evict the replenished buckets, these are equivalent to missing buckets
+                buckets.retain(|_, bucket| {
+                    let elapsed = now.saturating_duration_since(bucket.last_checked);
+                    bucket.allowance + elapsed.as_secs_f64() * rate < burst
+                });
+                *last_sweep = Some(now);
+            }
+
+            if buckets.len() >= self.max_keys {
+                let next_sweep = last_sweep.map_or(now, |last_sweep| last_sweep + period);
+                return Err(RateLimited {
+                    retry_after: next_sweep.saturating_duration_since(now),
+                });
+            }
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            allowance: burst,
+            last_checked: now,
+        });
+
+        // Replenish
+        let elapsed = now.saturating_duration_since(bucket.last_checked);
+        bucket.allowance = (bucket.allowance + elapsed.as_secs_f64() * rate).min(burst);
+        bucket.last_checked = now;
+
+        // Take
+        if bucket.allowance < 1.0 {
+            let retry_after = period.mul_f64((1.0 - bucket.allowance) / burst);
+            return Err(RateLimited { retry_after });
+        }
+        bucket.allowance -= 1.0;
+        Ok(())
+    }
+}
+
+/// A [`Layer`] producing [`RateLimitService`]s.
+pub struct RateLimitLayer<K> {
+    limiter: Arc<RateLimiter>,
+    key_fn: K,
+}
+
+impl<K: Clone> Clone for RateLimitLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for RateLimitLayer<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl<K> RateLimitLayer<K> {
+    /// Create a new [`RateLimitLayer`] using a function which constructs the key from the request,
+    /// such as [`client_ip_key`].
+    ///
+    /// Requests for which no key is constructed are not rate limited.
+    pub fn new(limit: RateLimit, max_keys: usize, key_fn: K) -> Self {
+        Self {
+            limiter: Arc::new(RateLimiter::new(limit, max_keys)),
+            key_fn,
+        }
+    }
+}
+
+impl<S, K: Clone> Layer<S> for RateLimitLayer<K> {
+    type Service = RateLimitService<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+/// A [`Service`] which rejects requests exceeding a [`RateLimit`].
+pub struct RateLimitService<S, K> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+    key_fn: K,
+}
+
+impl<S: Clone, K: Clone> Clone for RateLimitService<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, K> fmt::Debug for RateLimitService<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitService")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl<S, K, B> Service<Request<B>> for RateLimitService<S, K>
+where
+    S: Service<Request<B>>,
+    S::Error: From<RateLimited> + Send + 'static,
+    S::Response: Send + 'static,
+    S::Future: Send + 'static,
+    K: Fn(&Request<B>) -> Option<String>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if let Some(key) = (self.key_fn)(&request) {
+            if let Err(err) = self.limiter.check(&key) {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("cashweb_protection_failures", 1, "reason" => "rate_limited");
+                return Box::pin(async move { Err(S::Error::from(err)) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhaust_and_replenish() {
+        let limit = RateLimit::new(2, Duration::from_secs(2)).unwrap();
+        let limiter = RateLimiter::new(limit, 8);
+        let now = Instant::now();
+        limiter.check_at("a", now).unwrap();
+        limiter.check_at("a", now).unwrap();
+        assert_eq!(
+            limiter.check_at("a", now),
+            Err(RateLimited {
+                retry_after: Duration::from_secs(1)
+            })
+        );
+        limiter.check_at("b", now).unwrap();
+        limiter.check_at("a", now + Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn max_keys() {
+        let limit = RateLimit::new(1, Duration::from_secs(10)).unwrap();
+        let limiter = RateLimiter::new(limit, 2);
+        let now = Instant::now();
+        limiter.check_at("a", now).unwrap();
+        limiter.check_at("b", now + Duration::from_secs(1)).unwrap();
+
+        // A full table rejects new keys rather than evicting exhausted buckets
+        assert_eq!(
+            limiter.check_at("c", now + Duration::from_secs(2)),
+            Err(RateLimited {
+                retry_after: Duration::from_secs(10)
+            })
+        );
+        assert!(limiter.check_at("a", now + Duration::from_secs(2)).is_err());
+
+        // Replenished buckets are evicted at the next sweep
+        let later = now + Duration::from_secs(12);
+        limiter.check_at("c", later).unwrap();
+        limiter.check_at("a", later).unwrap();
+        assert_eq!(
+            limiter.check_at("b", later),
+            Err(RateLimited {
+                retry_after: Duration::from_secs(10)
+            })
+        );
+    }
+
+    #[test]
+    fn zero_parameters() {
+        assert_eq!(
+            RateLimit::new(0, Duration::from_secs(1)),
+            Err(InvalidRateLimit::ZeroBurst)
+        );
+        assert_eq!(
+            RateLimit::new(1, Duration::from_secs(0)),
+            Err(InvalidRateLimit::ZeroPeriod)
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! This module contains the [`ReplayGuardLayer`] which rejects requests reusing a
+//! `(token, nonce)` pair within a window.
+//!
+//! Time-bucketed tokens bound the lifetime of a token but permit any request to be replayed while
+//! the token is valid. State-changing endpoints, such as message deletion, can be guarded by
+//! additionally requiring a unique nonce in each request, recorded by a [`NonceStore`].
+//!
+//! A [`MemoryNonceStore`] is provided for single instance deployments. Deployments sharing a
+//! [`RedisNonceStore`] implement [`RedisConnection`] for their Redis client.
+//!
+//! Nonces must only be recorded for validated tokens, otherwise unauthenticated clients could fill
+//! the store with arbitrary pairs. The [`token_nonce`] reads the token from the [`AuthContext`]
+//! attached by the [`ProtectedService`], so the [`ReplayGuardLayer`] must be applied inside the
+//! [`ProtectionLayer`] when using it. Limiting the rate of each validated token, see
+//! [`ProtectionLayer::with_rate_limit`], additionally bounds the nonces recorded for each token.
+//!
+//! The [`ReplayGuard`] returns the [`ReplayRejected`] error which is converted into the inner
+//! service's error type. When wrapped by a [`ProtectedService`] this becomes
+//! [`GuardError::Service`].
+//!
+//! [`ProtectedService`]: crate::ProtectedService
+//! [`ProtectionLayer`]: crate::ProtectionLayer
+//! [`ProtectionLayer::with_rate_limit`]: crate::ProtectionLayer::with_rate_limit
+//! [`GuardError::Service`]: crate::GuardError::Service
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_core::task::{Context, Poll};
+use http::{Request, StatusCode};
+use ring::digest::{Context as DigestContext, SHA256};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{AuthContext, FutResponse};
+
+/// The name of the header containing the request nonce.
+pub const NONCE_HEADER: &str = "x-pop-nonce";
+
+/// The default prefix of the keys written by the [`RedisNonceStore`].
+pub const DEFAULT_REDIS_PREFIX: &str = "cashweb:nonce:";
+
+/// The request was rejected by the [`ReplayGuard`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ReplayRejected {
+    /// The request was missing the validated token or nonce.
+    #[error("missing nonce")]
+    MissingNonce,
+    /// The `(token, nonce)` pair was used within the window.
+    #[error("nonce reused")]
+    NonceReused,
+    /// Failed to access the [`NonceStore`].
+    #[error("nonce store failure: {0}")]
+    Store(String),
+}
+
+impl ReplayRejected {
+    /// A short, static label identifying the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::MissingNonce => "missing_nonce",
+            Self::NonceReused => "nonce_reused",
+            Self::Store(_) => "store",
+        }
+    }
+
+    /// The HTTP status code appropriate for the rejection.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingNonce => StatusCode::BAD_REQUEST,
+            Self::NonceReused => StatusCode::CONFLICT,
+            Self::Store(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Uses the validated POP token, from the [`AuthContext`] attached to the request, and the
+/// [`NONCE_HEADER`] as the `(token, nonce)` pair.
+pub fn token_nonce<B>(request: &Request<B>) -> Option<(String, String)> {
+    let token = &request.extensions().get::<AuthContext>()?.token;
+    let nonce = request
+        .headers()
+        .get(NONCE_HEADER)?
+        .to_str()
+        .ok()
+        .filter(|nonce| !nonce.is_empty())?;
+    Some((token.clone(), nonce.to_string()))
+}
+
+/// Calculate the key identifying a `(token, nonce)` pair, this is `SHA256(token || 0 || nonce)`.
+///
+/// Stores record the key rather than the pair, bounding the memory used by long tokens and nonces.
+pub fn nonce_key(token: &str, nonce: &str) -> [u8; 32] {
+    let mut context = DigestContext::new(&SHA256);
+    context.update(token.as_bytes());
+    context.update(&[0]);
+    context.update(nonce.as_bytes());
+    context.finish().as_ref().try_into().unwrap() // This is safe
+}
+
+/// A store recording the `(token, nonce)` pairs used within a window.
+pub trait NonceStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Record a `(token, nonce)` pair for the window, returning `false` if it was already recorded
+    /// within the window.
+    fn insert(&self, token: &str, nonce: &str, window: Duration) -> FutResponse<bool, Self::Error>;
+}
+
+/// The [`MemoryNonceStore`] was full of unexpired nonces.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("nonce store full")]
+pub struct StoreFull;
+
+/// An in-memory [`NonceStore`] holding at most a fixed number of nonces.
+///
+/// Expired nonces are evicted once the store is full. If the store is full of unexpired nonces
+/// then insertions fail, rather than evicting a nonce which could then be replayed.
+#[derive(Debug)]
+pub struct MemoryNonceStore {
+    max_entries: usize,
+    entries: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl MemoryNonceStore {
+    /// Create a new [`MemoryNonceStore`] holding at most `max_entries` nonces.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Default::default(),
+        }
+    }
+
+    /// Record a `(token, nonce)` pair for the window at a given instant.
+    pub fn insert_at(
+        &self,
+        token: &str,
+        nonce: &str,
+        window: Duration,
+        now: Instant,
+    ) -> Result<bool, StoreFull> {
+        let key = nonce_key(token, nonce);
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(expiry) = entries.get(&key) {
+            if *expiry > now {
+                return Ok(false);
+            }
+        } else if entries.len() >= self.max_entries {
+            // Evict the expired nonces
+            entries.retain(|_, expiry| *expiry > now);
+            if entries.len() >= self.max_entries {
+                return Err(StoreFull);
+            }
+        }
+
+        entries.insert(key, now + window);
+        Ok(true)
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    type Error = StoreFull;
+
+    fn insert(&self, token: &str, nonce: &str, window: Duration) -> FutResponse<bool, Self::Error> {
+        let result = self.insert_at(token, nonce, window, Instant::now());
+        Box::pin(async move { result })
+    }
+}
+
+/// A connection to Redis, providing the single command required by the [`RedisNonceStore`].
+pub trait RedisConnection {
+    /// Error associated with executing a command.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Execute `SET key 1 NX PX ttl`, returning whether the key was set.
+    fn set_nx_px(&self, key: String, ttl_ms: u64) -> FutResponse<bool, Self::Error>;
+}
+
+/// A [`NonceStore`] backed by Redis, allowing nonces to be shared between instances.
+///
+/// Each pair is written to the key `prefix || hex(nonce_key)`, expiring after the window.
+#[derive(Clone, Debug)]
+pub struct RedisNonceStore<C> {
+    connection: C,
+    prefix: String,
+}
+
+impl<C> RedisNonceStore<C> {
+    /// Create a new [`RedisNonceStore`] using the [`DEFAULT_REDIS_PREFIX`].
+    pub fn new(connection: C) -> Self {
+        Self {
+            connection,
+            prefix: DEFAULT_REDIS_PREFIX.to_string(),
+        }
+    }
+
+    /// Set the prefix of the keys written.
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = prefix;
+        self
+    }
+}
+
+impl<C: RedisConnection> NonceStore for RedisNonceStore<C> {
+    type Error = C::Error;
+
+    fn insert(&self, token: &str, nonce: &str, window: Duration) -> FutResponse<bool, Self::Error> {
+        let key = format!("{}{}", self.prefix, hex::encode(nonce_key(token, nonce)));
+        // Redis rejects a zero expiry
+        let ttl_ms = (window.as_millis() as u64).max(1);
+        self.connection.set_nx_px(key, ttl_ms)
+    }
+}
+
+/// A [`Layer`] producing [`ReplayGuard`]s.
+pub struct ReplayGuardLayer<N, K> {
+    store: Arc<N>,
+    window: Duration,
+    key_fn: K,
+}
+
+impl<N, K: Clone> Clone for ReplayGuardLayer<N, K> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            window: self.window,
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+impl<N: fmt::Debug, K> fmt::Debug for ReplayGuardLayer<N, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayGuardLayer")
+            .field("store", &self.store)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+impl<N, K> ReplayGuardLayer<N, K> {
+    /// Create a new [`ReplayGuardLayer`] using a function which constructs the `(token, nonce)`
+    /// pair from the request.
+    ///
+    /// The window should cover the lifetime of the token, for example two bucket widths when
+    /// wrapping a [`TimeBucketScheme`]. Requests for which no pair is constructed are rejected.
+    ///
+    /// The token in the pair should have been validated, see [`token_nonce`].
+    ///
+    /// [`TimeBucketScheme`]: token::schemes::time_bucket::TimeBucketScheme
+    pub fn new(store: N, window: Duration, key_fn: K) -> Self {
+        Self {
+            store: Arc::new(store),
+            window,
+            key_fn,
+        }
+    }
+}
+
+impl<S, N, K: Clone> Layer<S> for ReplayGuardLayer<N, K> {
+    type Service = ReplayGuard<S, N, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReplayGuard {
+            inner,
+            store: self.store.clone(),
+            window: self.window,
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+/// A [`Service`] which rejects requests reusing a `(token, nonce)` pair within a window.
+pub struct ReplayGuard<S, N, K> {
+    inner: S,
+    store: Arc<N>,
+    window: Duration,
+    key_fn: K,
+}
+
+impl<S: Clone, N, K: Clone> Clone for ReplayGuard<S, N, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            window: self.window,
+            key_fn: self.key_fn.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, N: fmt::Debug, K> fmt::Debug for ReplayGuard<S, N, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayGuard")
+            .field("inner", &self.inner)
+            .field("store", &self.store)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+impl<S, N, K, B> Service<Request<B>> for ReplayGuard<S, N, K>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Error: From<ReplayRejected> + Send + 'static,
+    S::Response: Send + 'static,
+    S::Future: Send,
+    N: NonceStore,
+    N::Error: 'static,
+    K: Fn(&Request<B>) -> Option<(String, String)>,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let (token, nonce) = match (self.key_fn)(&request) {
+            Some(some) => some,
+            None => {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("cashweb_protection_failures", 1, "reason" => "missing_nonce");
+                return Box::pin(async { Err(ReplayRejected::MissingNonce.into()) });
+            }
+        };
+        let insertion = self.store.insert(&token, &nonce, self.window);
+
+        // Take the service which was polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            match insertion.await {
+                Ok(true) => inner.call(request).await,
+                Ok(false) => {
+                    #[cfg(feature = "metrics")]
+                    metrics::counter!("cashweb_protection_failures", 1, "reason" => "nonce_reused");
+                    Err(ReplayRejected::NonceReused.into())
+                }
+                Err(err) => Err(ReplayRejected::Store(err.to_string()).into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{ready, Ready};
+
+    use http::header::{HeaderValue, AUTHORIZATION};
+
+    use super::*;
+    use crate::{
+        path_data,
+        tests::{block_on, pop_request, EqualValidator},
+        GuardError, ProtectionLayer,
+    };
+
+    /// Accepts all requests.
+    #[derive(Clone, Debug)]
+    struct Accept;
+
+    impl<B> Service<Request<B>> for Accept {
+        type Response = ();
+        type Error = ReplayRejected;
+        type Future = Ready<Result<(), ReplayRejected>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<B>) -> Self::Future {
+            ready(Ok(()))
+        }
+    }
+
+    fn nonce_request(token: &'static str, nonce: &'static str) -> Request<()> {
+        let mut request = pop_request("/a", Some(token));
+        request
+            .headers_mut()
+            .insert(NONCE_HEADER, HeaderValue::from_static(nonce));
+        request
+    }
+
+    #[test]
+    fn reuse_and_expiry() {
+        let store = MemoryNonceStore::new(2);
+        let window = Duration::from_secs(10);
+        let now = Instant::now();
+        assert_eq!(store.insert_at("token", "a", window, now), Ok(true));
+        assert_eq!(store.insert_at("token", "a", window, now), Ok(false));
+        assert_eq!(store.insert_at("other", "a", window, now), Ok(true));
+
+        // Full of unexpired nonces
+        assert_eq!(store.insert_at("token", "b", window, now), Err(StoreFull));
+
+        let later = now + window;
+        assert_eq!(store.insert_at("token", "b", window, later), Ok(true));
+        assert_eq!(store.insert_at("token", "a", window, later), Ok(true));
+    }
+
+    #[test]
+    fn record_validated_only() {
+        let guard = ReplayGuardLayer::new(
+            MemoryNonceStore::new(1),
+            Duration::from_secs(10),
+            token_nonce,
+        );
+        let mut service =
+            ProtectionLayer::new(EqualValidator, path_data).layer(guard.layer(Accept));
+
+        // Invalid tokens do not consume the store
+        let err = block_on(service.call(nonce_request("POP /b", "1"))).unwrap_err();
+        assert!(matches!(err, GuardError::TokenValidate("mismatch")));
+        let err = block_on(service.call(nonce_request("POP /c", "2"))).unwrap_err();
+        assert!(matches!(err, GuardError::TokenValidate("mismatch")));
+
+        block_on(service.call(nonce_request("POP /a", "1"))).unwrap();
+        let err = block_on(service.call(nonce_request("POP /a", "1"))).unwrap_err();
+        assert!(matches!(
+            err,
+            GuardError::Service(ReplayRejected::NonceReused)
+        ));
+    }
+
+    #[test]
+    fn extract_pair() {
+        let mut request = Request::new(());
+        assert_eq!(token_nonce(&request), None);
+
+        // The unvalidated token is ignored
+        let headers = request.headers_mut();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("POP abc"));
+        headers.insert(NONCE_HEADER, HeaderValue::from_static("1"));
+        assert_eq!(token_nonce(&request), None);
+
+        request
+            .extensions_mut()
+            .insert(AuthContext::new("abc".to_string(), "custom"));
+        assert_eq!(
+            token_nonce(&request),
+            Some(("abc".to_string(), "1".to_string()))
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! This module contains the [`ResponseMapper`] which converts [`GuardError`]s into standard HTTP
+//! responses.
+//!
+//! * Missing or invalid tokens are answered with `401 Unauthorized` and a `WWW-Authenticate: POP`
+//! challenge or, if an invoice is configured, `402 Payment Required` containing the invoice.
+//! * Rate limited requests are answered with `429 Too Many Requests` and a `Retry-After` header.
+//! * Errors in the inner service are answered with `500 Internal Server Error`, the error itself is
+//! not exposed.
+
+use std::fmt;
+
+use http::{
+    header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE},
+    Response, StatusCode,
+};
+use payments::bip70::PaymentDetails;
+
+use crate::{payment_required::payment_required_response, GuardError};
+
+/// The challenge sent in the `WWW-Authenticate` header.
+pub const POP_CHALLENGE: &str = "POP";
+
+/// Converts [`GuardError`]s into [`Response`]s.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseMapper {
+    payment_details: Option<PaymentDetails>,
+}
+
+impl ResponseMapper {
+    /// Create a new [`ResponseMapper`] responding to missing or invalid tokens with
+    /// `401 Unauthorized`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Respond to missing or invalid tokens with `402 Payment Required` containing an invoice.
+    ///
+    /// The invoice is fixed, use the [`PaymentRequiredLayer`] to generate an invoice per request.
+    ///
+    /// [`PaymentRequiredLayer`]: crate::PaymentRequiredLayer
+    pub fn with_invoice(mut self, payment_details: PaymentDetails) -> Self {
+        self.payment_details = Some(payment_details);
+        self
+    }
+
+    /// Convert a [`GuardError`] into a [`Response`].
+    pub fn map<V, E, B>(&self, err: GuardError<V, E>) -> Response<B>
+    where
+        V: fmt::Debug + fmt::Display,
+        E: fmt::Debug + fmt::Display,
+        B: From<Vec<u8>>,
+    {
+        let mut response = match (&err, &self.payment_details) {
+            (GuardError::NoAuthData, Some(payment_details))
+            | (GuardError::TokenValidate(_), Some(payment_details)) => {
+                payment_required_response(payment_details)
+            }
+            (GuardError::Service(_), _) => text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal server error".to_string(),
+            ),
+            _ => text_response(err.status_code(), err.to_string()),
+        };
+
+        match &err {
+            GuardError::NoAuthData | GuardError::TokenValidate(_) => {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static(POP_CHALLENGE));
+            }
+            GuardError::RateLimited(rate_limited) => {
+                // Round up to whole seconds
+                let retry_after = rate_limited.retry_after;
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(secs));
+            }
+            _ => (),
+        }
+        response
+    }
+
+    /// Convert into a function, suitable for the [`IntoResponseLayer`].
+    ///
+    /// [`IntoResponseLayer`]: crate::adapters::IntoResponseLayer
+    pub fn into_fn<V, E, B>(self) -> impl Fn(GuardError<V, E>) -> Response<B> + Clone + Send
+    where
+        V: fmt::Debug + fmt::Display,
+        E: fmt::Debug + fmt::Display,
+        B: From<Vec<u8>>,
+    {
+        move |err| self.map(err)
+    }
+}
+
+/// Convert a [`GuardError`] into a [`Response`] using the default [`ResponseMapper`].
+pub fn guard_error_response<V, E, B>(err: GuardError<V, E>) -> Response<B>
+where
+    V: fmt::Debug + fmt::Display,
+    E: fmt::Debug + fmt::Display,
+    B: From<Vec<u8>>,
+{
+    ResponseMapper::default().map(err)
+}
+
+fn text_response<B: From<Vec<u8>>>(status: StatusCode, body: String) -> Response<B> {
+    let mut response = Response::new(B::from(body.into_bytes()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::RateLimited;
+
+    type Error = GuardError<String, String>;
+
+    #[test]
+    fn unauthorized() {
+        let response: Response<Vec<u8>> = guard_error_response(Error::NoAuthData);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], POP_CHALLENGE);
+    }
+
+    #[test]
+    fn payment_required() {
+        let mapper = ResponseMapper::new().with_invoice(PaymentDetails::default());
+        let response: Response<Vec<u8>> = mapper.map(Error::TokenValidate("invalid".to_string()));
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], POP_CHALLENGE);
+    }
+
+    #[test]
+    fn too_many_requests() {
+        let response: Response<Vec<u8>> = guard_error_response(Error::RateLimited(RateLimited {
+            retry_after: Duration::from_millis(1500),
+        }));
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+
+    #[test]
+    fn service_error_hidden() {
+        let response: Response<Vec<u8>> =
+            guard_error_response(Error::Service("secret".to_string()));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body(), b"internal server error");
+    }
+}
@@ -0,0 +1,424 @@
+//! This module contains the [`ProtectionConfigBuilder`] which maps routes, identified by a path
+//! prefix and methods, to their own validator, rate limit and bypass rules.
+//!
+//! Path prefixes match whole segments of the normalized path, as in the [`BypassRules`].
+//!
+//! This produces a single [`RoutedProtectionLayer`], avoiding a separate middleware stack per
+//! route. Routes are matched in the order they were added, the first match applies. Requests
+//! matching no route are rejected with [`GuardError::NoAuthData`], add a route with the `/` prefix
+//! to handle the remainder.
+//!
+//! Validators of different types can be combined within a single configuration using [`Either`].
+//!
+//! [`Either`]: crate::Either
+
+use std::{fmt, pin::Pin, sync::Arc};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{Method, Request};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    bypass::{client_ip, has_segment_prefix, path_segments},
+    rate_limit::{RateLimit, RateLimiter},
+    validate_and_call, BypassRules, GuardError, PopTokenExtractor, TokenExtractor, TokenValidator,
+};
+
+/// The rate limiting key shared by requests to public routes lacking a client IP.
+const SHARED_KEY: &str = "";
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The protection applied to requests matching a path prefix and methods.
+pub struct Route<V> {
+    path_prefix: String,
+    prefix_segments: Option<Vec<String>>,
+    methods: Vec<Method>,
+    validator: Option<Arc<V>>,
+    limiter: Option<Arc<RateLimiter>>,
+    bypass: BypassRules,
+}
+
+impl<V> Clone for Route<V> {
+    fn clone(&self) -> Self {
+        Self {
+            path_prefix: self.path_prefix.clone(),
+            prefix_segments: self.prefix_segments.clone(),
+            methods: self.methods.clone(),
+            validator: self.validator.clone(),
+            limiter: self.limiter.clone(),
+            bypass: self.bypass.clone(),
+        }
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for Route<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("path_prefix", &self.path_prefix)
+            .field("methods", &self.methods)
+            .field("validator", &self.validator)
+            .field("limiter", &self.limiter)
+            .field("bypass", &self.bypass)
+            .finish()
+    }
+}
+
+impl<V> Route<V> {
+    /// Create a new public [`Route`] matching all methods on paths starting with the segments of
+    /// the prefix.
+    ///
+    /// Public routes do not require a token, use [`Route::validator`] to protect the route. A
+    /// prefix which cannot be normalized matches no paths.
+    pub fn new(path_prefix: String) -> Self {
+        Self {
+            prefix_segments: path_segments(&path_prefix),
+            path_prefix,
+            methods: Vec::new(),
+            validator: None,
+            limiter: None,
+            bypass: Default::default(),
+        }
+    }
+
+    /// Only match the given methods.
+    pub fn methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    /// Require tokens to be validated by the [`TokenValidator`].
+    pub fn validator(mut self, validator: V) -> Self {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Limit requests, keyed by client IP or, failing that, validated token, tracking at most
+    /// `max_keys` keys.
+    ///
+    /// Requests keyed by client IP are limited before the token is validated. Requests to public
+    /// routes lacking a client IP share a single bucket.
+    pub fn rate_limit(mut self, limit: RateLimit, max_keys: usize) -> Self {
+        self.limiter = Some(Arc::new(RateLimiter::new(limit, max_keys)));
+        self
+    }
+
+    /// Allow requests matching the [`BypassRules`] to skip token validation and rate limiting.
+    pub fn bypass(mut self, bypass: BypassRules) -> Self {
+        self.bypass = bypass;
+        self
+    }
+
+    /// Whether the route matches the method and path.
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        let path_matches = match (&self.prefix_segments, path_segments(path)) {
+            (Some(prefix), Some(segments)) => has_segment_prefix(&segments, prefix),
+            _ => false,
+        };
+        path_matches && (self.methods.is_empty() || self.methods.contains(method))
+    }
+}
+
+/// Builds a [`RoutedProtectionLayer`].
+///
+/// The `data_fn` constructs the data the token is expected to cover from the incoming request.
+pub struct ProtectionConfigBuilder<V, E, F> {
+    routes: Vec<Route<V>>,
+    extractor: E,
+    data_fn: F,
+}
+
+impl<V: fmt::Debug, E: fmt::Debug, F> fmt::Debug for ProtectionConfigBuilder<V, E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtectionConfigBuilder")
+            .field("routes", &self.routes)
+            .field("extractor", &self.extractor)
+            .finish()
+    }
+}
+
+impl<V, F> ProtectionConfigBuilder<V, PopTokenExtractor, F> {
+    /// Create a new [`ProtectionConfigBuilder`] using the default [`PopTokenExtractor`].
+    pub fn new(data_fn: F) -> Self {
+        Self {
+            routes: Vec::new(),
+            extractor: PopTokenExtractor::default(),
+            data_fn,
+        }
+    }
+}
+
+impl<V, E, F> ProtectionConfigBuilder<V, E, F> {
+    /// Replace the [`TokenExtractor`].
+    pub fn extractor<T>(self, extractor: T) -> ProtectionConfigBuilder<V, T, F> {
+        ProtectionConfigBuilder {
+            routes: self.routes,
+            extractor,
+            data_fn: self.data_fn,
+        }
+    }
+
+    /// Add a [`Route`], routes added earlier take precedence.
+    pub fn route(mut self, route: Route<V>) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Build the [`RoutedProtectionLayer`].
+    pub fn build(self) -> RoutedProtectionLayer<V, E, F> {
+        RoutedProtectionLayer {
+            routes: Arc::new(self.routes),
+            extractor: Arc::new(self.extractor),
+            data_fn: self.data_fn,
+        }
+    }
+}
+
+/// A [`Layer`] producing [`RoutedProtectedService`]s.
+pub struct RoutedProtectionLayer<V, E, F> {
+    routes: Arc<Vec<Route<V>>>,
+    extractor: Arc<E>,
+    data_fn: F,
+}
+
+impl<V, E, F: Clone> Clone for RoutedProtectionLayer<V, E, F> {
+    fn clone(&self) -> Self {
+        Self {
+            routes: self.routes.clone(),
+            extractor: self.extractor.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+impl<V: fmt::Debug, E: fmt::Debug, F> fmt::Debug for RoutedProtectionLayer<V, E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoutedProtectionLayer")
+            .field("routes", &self.routes)
+            .field("extractor", &self.extractor)
+            .finish()
+    }
+}
+
+impl<S, V, E, F: Clone> Layer<S> for RoutedProtectionLayer<V, E, F> {
+    type Service = RoutedProtectedService<S, V, E, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RoutedProtectedService {
+            inner,
+            routes: self.routes.clone(),
+            extractor: self.extractor.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+/// A [`Service`] which applies the protection of the matching [`Route`] to each request before
+/// calling the inner service.
+pub struct RoutedProtectedService<S, V, E, F> {
+    inner: S,
+    routes: Arc<Vec<Route<V>>>,
+    extractor: Arc<E>,
+    data_fn: F,
+}
+
+impl<S: Clone, V, E, F: Clone> Clone for RoutedProtectedService<S, V, E, F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            routes: self.routes.clone(),
+            extractor: self.extractor.clone(),
+            data_fn: self.data_fn.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, V: fmt::Debug, E: fmt::Debug, F> fmt::Debug
+    for RoutedProtectedService<S, V, E, F>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoutedProtectedService")
+            .field("inner", &self.inner)
+            .field("routes", &self.routes)
+            .field("extractor", &self.extractor)
+            .finish()
+    }
+}
+
+impl<S, V, E, F, B> Service<Request<B>> for RoutedProtectedService<S, V, E, F>
+where
+    S: Service<Request<B>> + Clone + Send + 'static,
+    S::Response: Send + 'static,
+    S::Error: fmt::Debug + fmt::Display + Send + 'static,
+    S::Future: Send,
+    V: TokenValidator + Send + Sync + 'static,
+    V::Output: Send + Sync + 'static,
+    V::Error: fmt::Debug + fmt::Display + Send + 'static,
+    V::Future: Send + 'static,
+    E: TokenExtractor,
+    F: Fn(&Request<B>) -> V::Data,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = GuardError<V::Error, S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context).map_err(GuardError::Service)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let routes = self.routes.clone();
+        let route = match routes
+            .iter()
+            .find(|route| route.matches(request.method(), request.uri().path()))
+        {
+            Some(some) => some,
+            None => return Box::pin(async { Err(GuardError::NoAuthData) }),
+        };
+
+        // Take the service which was polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        // Skip validation for public resources
+        if route.bypass.matches(&request) {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("cashweb_protection_bypassed", 1);
+            return Box::pin(async move { inner.call(request).await.map_err(GuardError::Service) });
+        }
+
+        // Extract token
+        let token = self
+            .extractor
+            .extract(request.headers(), request.uri())
+            .map(ToString::to_string);
+
+        // Apply rate limit by client IP, otherwise defer until the token is validated
+        let mut limiter = route.limiter.clone();
+        if let Some(client_ip) = client_ip(&request) {
+            if let Some(limiter) = limiter.take() {
+                if let Err(err) = limiter.check(&client_ip.to_string()) {
+                    #[cfg(feature = "metrics")]
+                    metrics::counter!("cashweb_protection_failures", 1, "reason" => "rate_limited");
+                    return Box::pin(async { Err(err.into()) });
+                }
+            }
+        }
+
+        let validator = match &route.validator {
+            Some(some) => some.clone(),
+            None => {
+                // Apply rate limit to the shared bucket, lacking both client IP and token
+                if let Some(limiter) = limiter {
+                    if let Err(err) = limiter.check(SHARED_KEY) {
+                        #[cfg(feature = "metrics")]
+                        metrics::counter!("cashweb_protection_failures", 1, "reason" => "rate_limited");
+                        return Box::pin(async { Err(err.into()) });
+                    }
+                }
+                return Box::pin(
+                    async move { inner.call(request).await.map_err(GuardError::Service) },
+                );
+            }
+        };
+        let token = match token {
+            Some(some) => some,
+            None => {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("cashweb_protection_failures", 1, "reason" => "no_auth_data");
+                return Box::pin(async { Err(GuardError::NoAuthData) });
+            }
+        };
+
+        // Validate token
+        let data = (self.data_fn)(&request);
+        let validation = validator.validate(data, token.clone());
+        Box::pin(validate_and_call(
+            inner, validator, validation, token, limiter, request,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        path_data,
+        tests::{block_on, pop_request, EqualValidator, Identity},
+    };
+
+    #[test]
+    fn route_matching() {
+        let route: Route<()> = Route::new("/messages".to_string()).methods(vec![Method::GET]);
+        assert!(route.matches(&Method::GET, "/messages/abc"));
+        assert!(!route.matches(&Method::PUT, "/messages/abc"));
+        assert!(!route.matches(&Method::GET, "/profiles/abc"));
+        assert!(!route.matches(&Method::GET, "/messagesabc"));
+        assert!(!route.matches(&Method::GET, "/messages/../profiles"));
+
+        let route: Route<()> = Route::new("/".to_string());
+        assert!(route.matches(&Method::DELETE, "/profiles/abc"));
+    }
+
+    #[test]
+    fn rate_limit_validated_tokens() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).unwrap();
+        let route = Route::new("/".to_string())
+            .validator(EqualValidator)
+            .rate_limit(limit, 8);
+        let mut service = ProtectionConfigBuilder::new(path_data)
+            .route(route)
+            .build()
+            .layer(Identity);
+
+        // Invalid tokens do not consume the allowance of the valid token
+        let err = block_on(service.call(pop_request("/a", Some("POP /b")))).unwrap_err();
+        assert!(matches!(err, GuardError::TokenValidate(_)));
+        block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap();
+        let err = block_on(service.call(pop_request("/a", Some("POP /a")))).unwrap_err();
+        assert!(matches!(err, GuardError::RateLimited(_)));
+
+        // Requests with a client IP are limited before validation
+        let mut request = pop_request("/c", Some("POP /c"));
+        request
+            .extensions_mut()
+            .insert::<std::net::IpAddr>("127.0.0.1".parse().unwrap());
+        block_on(service.call(request)).unwrap();
+        let mut request = pop_request("/c", Some("POP /d"));
+        request
+            .extensions_mut()
+            .insert::<std::net::IpAddr>("127.0.0.1".parse().unwrap());
+        let err = block_on(service.call(request)).unwrap_err();
+        assert!(matches!(err, GuardError::RateLimited(_)));
+    }
+
+    #[test]
+    fn rate_limit_public_route() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).unwrap();
+        let route: Route<EqualValidator> = Route::new("/".to_string()).rate_limit(limit, 8);
+        let mut service = ProtectionConfigBuilder::new(path_data)
+            .route(route)
+            .build()
+            .layer(Identity);
+
+        // Requests lacking a client IP share a bucket
+        block_on(service.call(pop_request("/a", None))).unwrap();
+        let err = block_on(service.call(pop_request("/b", None))).unwrap_err();
+        assert!(matches!(err, GuardError::RateLimited(_)));
+
+        // Requests with a client IP are limited separately
+        let mut request = pop_request("/a", None);
+        request
+            .extensions_mut()
+            .insert::<std::net::IpAddr>("127.0.0.1".parse().unwrap());
+        block_on(service.call(request)).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-proto-json` is a library providing the [`serde`] helpers implementing the canonical
+//! [`proto3 JSON mapping`] of the cash:web protobuf models.
+//!
+//! Each module is intended to be used via the `#[serde(with = "...")]` field attribute: `bytes`
+//! are base64 encoded, 64-bit integers are strings and enums are their variant names.
+//! Deserialization additionally accepts URL-safe base64, numeric 64-bit integers and numeric enums.
+//!
+//! The `json` feature provides [`to_json`] and [`from_json`] helpers.
+//!
+//! [`proto3 JSON mapping`]: https://developers.google.com/protocol-buffers/docs/proto3#json
+
+#[cfg(feature = "json")]
+use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+/// Serialize a message as proto3 JSON.
+#[cfg(feature = "json")]
+pub fn to_json<M: Serialize>(message: &M) -> Result<String, serde_json::Error> {
+    serde_json::to_string(message)
+}
+
+/// Deserialize a message from proto3 JSON.
+#[cfg(feature = "json")]
+pub fn from_json<M: DeserializeOwned>(json: &str) -> Result<M, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Decode standard or URL-safe base64.
+fn decode_base64<E: serde::de::Error>(value: &str) -> Result<Vec<u8>, E> {
+    base64::decode(value)
+        .or_else(|_| base64::decode_config(value, base64::URL_SAFE))
+        .map_err(E::custom)
+}
+
+/// The mapping of `bytes` fields.
+pub mod bytes {
+    use super::*;
+
+    /// Serialize as standard base64.
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(value))
+    }
+
+    /// Deserialize from standard or URL-safe base64.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        decode_base64(&value)
+    }
+}
+
+/// The mapping of `optional bytes` fields.
+pub mod optional_bytes {
+    use super::*;
+
+    /// Serialize as standard base64, or null if missing.
+    pub fn serialize<S: Serializer>(
+        value: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&base64::encode(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Deserialize from standard or URL-safe base64, or null.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        let value = Option::<String>::deserialize(deserializer)?;
+        value.map(|value| decode_base64(&value)).transpose()
+    }
+}
+
+/// The mapping of `repeated bytes` fields.
+pub mod repeated_bytes {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    /// Serialize as an array of standard base64.
+    pub fn serialize<S: Serializer>(values: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&base64::encode(value))?;
+        }
+        seq.end()
+    }
+
+    /// Deserialize from an array of standard or URL-safe base64.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        let values = Vec::<String>::deserialize(deserializer)?;
+        values.iter().map(|value| decode_base64(value)).collect()
+    }
+}
+
+/// A 64-bit integer encoded as either a string or a number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Integer<T> {
+    Number(T),
+    String(String),
+}
+
+impl<T: std::str::FromStr> Integer<T>
+where
+    T::Err: std::fmt::Display,
+{
+    fn into_inner<E: serde::de::Error>(self) -> Result<T, E> {
+        match self {
+            Self::Number(value) => Ok(value),
+            Self::String(value) => value.parse().map_err(E::custom),
+        }
+    }
+}
+
+/// The mapping of `int64` fields.
+pub mod int64 {
+    use super::*;
+
+    /// Serialize as a decimal string.
+    pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    /// Deserialize from a decimal string or a number.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        Integer::<i64>::deserialize(deserializer)?.into_inner()
+    }
+}
+
+/// The mapping of `uint64` fields.
+pub mod uint64 {
+    use super::*;
+
+    /// Serialize as a decimal string.
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    /// Deserialize from a decimal string or a number.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        Integer::<u64>::deserialize(deserializer)?.into_inner()
+    }
+}
+
+/// The mapping of `optional uint64` fields.
+pub mod optional_uint64 {
+    use super::*;
+
+    /// Serialize as a decimal string, or null if missing.
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Deserialize from a decimal string, a number, or null.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Option::<Integer<u64>>::deserialize(deserializer)?
+            .map(Integer::into_inner)
+            .transpose()
+    }
+}
+
+/// An enum encoded as either its variant name or its number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnumValue {
+    Number(i32),
+    Name(String),
+}
+
+/// Serialize an enum field as its variant name, or its number if the variant is unknown.
+pub fn serialize_enum<S: Serializer>(
+    value: i32,
+    name: Option<&'static str>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match name {
+        Some(name) => serializer.serialize_str(name),
+        None => serializer.serialize_i32(value),
+    }
+}
+
+/// Deserialize an enum field from its number or its variant name, resolved using `from_name`.
+pub fn deserialize_enum<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    from_name: fn(&str) -> Option<i32>,
+) -> Result<i32, D::Error> {
+    match EnumValue::deserialize(deserializer)? {
+        EnumValue::Number(value) => Ok(value),
+        EnumValue::Name(name) => {
+            from_name(&name).ok_or_else(|| D::Error::custom(format!("unknown variant: {}", name)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Model {
+        #[serde(with = "super::bytes")]
+        bytes: Vec<u8>,
+        #[serde(with = "optional_bytes")]
+        optional_bytes: Option<Vec<u8>>,
+        #[serde(with = "repeated_bytes")]
+        repeated_bytes: Vec<Vec<u8>>,
+        #[serde(with = "int64")]
+        int64: i64,
+        #[serde(with = "uint64")]
+        uint64: u64,
+        #[serde(with = "optional_uint64")]
+        optional_uint64: Option<u64>,
+        #[serde(
+            serialize_with = "serialize_kind",
+            deserialize_with = "deserialize_kind"
+        )]
+        kind: i32,
+    }
+
+    fn serialize_kind<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match value {
+            0 => Some("ZERO"),
+            1 => Some("ONE"),
+            _ => None,
+        };
+        serialize_enum(*value, name, serializer)
+    }
+
+    fn deserialize_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "ZERO" => Some(0),
+            "ONE" => Some(1),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let model = Model {
+            bytes: vec![0xfb, 0xff],
+            optional_bytes: Some(vec![1]),
+            repeated_bytes: vec![vec![2], vec![3]],
+            int64: -1,
+            uint64: u64::MAX,
+            optional_uint64: Some(7),
+            kind: 1,
+        };
+        let value = serde_json::to_value(&model).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "bytes": "+/8=",
+                "optional_bytes": "AQ==",
+                "repeated_bytes": ["Ag==", "Aw=="],
+                "int64": "-1",
+                "uint64": "18446744073709551615",
+                "optional_uint64": "7",
+                "kind": "ONE",
+            })
+        );
+        assert_eq!(serde_json::from_value::<Model>(value).unwrap(), model);
+
+        // Unknown variants are serialized as numbers
+        let value = serde_json::to_value(&Model {
+            kind: 5,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(value["kind"], 5);
+        assert_eq!(value["optional_bytes"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn lenient_deserialize() {
+        let value = json!({
+            "bytes": "-_8=",
+            "int64": -1,
+            "uint64": 2,
+            "optional_uint64": "3",
+            "kind": 1,
+        });
+        let model: Model = serde_json::from_value(value).unwrap();
+        assert_eq!(model.bytes, vec![0xfb, 0xff]);
+        assert_eq!(model.int64, -1);
+        assert_eq!(model.uint64, 2);
+        assert_eq!(model.optional_uint64, Some(3));
+        assert_eq!(model.kind, 1);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(serde_json::from_value::<Model>(json!({ "bytes": "!" })).is_err());
+        assert!(serde_json::from_value::<Model>(json!({ "int64": "a" })).is_err());
+        assert!(serde_json::from_value::<Model>(json!({ "uint64": "-1" })).is_err());
+        assert!(serde_json::from_value::<Model>(json!({ "kind": "TWO" })).is_err());
+    }
+}
@@ -7,44 +7,177 @@
 
 //! `cashweb-relay-client` is a library providing [`RelayClient`] which allows
 //! interaction with specific relay server.
+//!
+//! Outgoing requests can be paced per relay server using a [`Throttle`](throttle::Throttle). Its
+//! timer is tokio by default, disabling the `tokio` feature selects an executor-agnostic timer.
+//!
+//! Response bodies are read up to a maximum size, see
+//! [`with_max_body_size`](RelayClient::with_max_body_size).
+//!
+//! Every request carries the default headers of the client, including a `User-Agent`, and targets
+//! paths beneath an optional base path. Middleware, such as authentication, logging or proxying,
+//! is injected by wrapping the inner service in a [`Layer`](tower_layer::Layer), see
+//! [`with_layer`](RelayClient::with_layer).
+//!
+//! Profiles older than a maximum age may be refused, see
+//! [`with_max_profile_age`](RelayClient::with_max_profile_age), allowing clients to fall back to
+//! other relay servers.
 
 pub mod services;
+pub mod throttle;
 
-use std::{error, fmt};
+use std::{error, fmt, sync::Arc, time::Duration};
 
+pub use clock::StaleTimestamp;
+use clock::{check_age, system_clock, Clock, SharedClock};
 pub use hyper::{
     client::{connect::Connect, HttpConnector},
     Uri,
 };
-use hyper::{http::uri::InvalidUri, Client as HyperClient};
+use hyper::{
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT},
+        request::Builder as RequestBuilder,
+        uri::InvalidUri,
+        Method, Request,
+    },
+    Client as HyperClient,
+};
 use secp256k1::key::PublicKey;
 use thiserror::Error;
+use tower_layer::Layer;
 use tower_service::Service;
 use tower_util::ServiceExt;
 
-use relay::Profile;
+use relay::{postage::PostagePolicy, MessagePage, MessageSet, PayloadPage, Profile};
 use services::*;
+use throttle::{RequestKind, Throttle};
+
+/// The default maximum size, in bytes, of response bodies read.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// The default `User-Agent` of requests.
+pub const DEFAULT_USER_AGENT: &str = concat!("cashweb-relay-client/", env!("CARGO_PKG_VERSION"));
 
 /// RelayClient allows queries to specific relay servers.
 #[derive(Clone, Debug)]
 pub struct RelayClient<S> {
     inner_client: S,
+    throttle: Option<Arc<Throttle>>,
+    max_body_size: usize,
+    default_headers: Arc<HeaderMap>,
+    base_path: String,
+    max_profile_age: Option<Duration>,
+    clock: SharedClock,
 }
 
 impl<S> RelayClient<S> {
     /// Create a new client from a service.
     pub fn from_service(service: S) -> Self {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
         Self {
             inner_client: service,
+            throttle: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            default_headers: Arc::new(default_headers),
+            base_path: String::new(),
+            max_profile_age: None,
+            clock: system_clock(),
+        }
+    }
+
+    /// Wrap the inner service in a [`Layer`], for example to inject authentication, logging or a
+    /// proxy.
+    ///
+    /// Layers wrap one another in the order they are added, the last being outermost.
+    pub fn with_layer<L: Layer<S>>(self, layer: L) -> RelayClient<L::Service> {
+        RelayClient {
+            inner_client: layer.layer(self.inner_client),
+            throttle: self.throttle,
+            max_body_size: self.max_body_size,
+            default_headers: self.default_headers,
+            base_path: self.base_path,
+            max_profile_age: self.max_profile_age,
+            clock: self.clock,
+        }
+    }
+
+    /// Set the `User-Agent` of requests, defaults to [`DEFAULT_USER_AGENT`].
+    pub fn with_user_agent(self, user_agent: HeaderValue) -> Self {
+        self.with_header(USER_AGENT, user_agent)
+    }
+
+    /// Set a header attached to every request, replacing any existing default value.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        Arc::make_mut(&mut self.default_headers).insert(name, value);
+        self
+    }
+
+    /// Set the path, beneath the relay server URL, at which the relay protocol is served.
+    ///
+    /// For example, with a base path of `/api/v1` messages are requested from
+    /// `{relay_url}/api/v1/messages/{address}`.
+    pub fn with_base_path(mut self, base_path: &str) -> Self {
+        self.base_path = base_path.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Get the default headers attached to every request.
+    pub fn default_headers(&self) -> &HeaderMap {
+        &self.default_headers
+    }
+
+    /// Construct the [`Uri`] of an endpoint on a relay server.
+    fn endpoint(&self, relay_url: &str, path: &str) -> Result<Uri, InvalidUri> {
+        format!("{}{}{}", relay_url, self.base_path, path).parse()
+    }
+
+    /// Start building a request carrying the default headers.
+    fn request_builder(&self, method: Method, uri: Uri) -> RequestBuilder {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in self.default_headers.iter() {
+            builder = builder.header(name, value);
+        }
+        builder
+    }
+
+    /// Pace pushes and polls to each relay server using a [`Throttle`].
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(Arc::new(throttle));
+        self
+    }
+
+    /// Set the maximum size, in bytes, of response bodies read.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Refuse profiles whose timestamp is older than `max_age`, see [`StaleTimestamp`].
+    pub fn with_max_profile_age(mut self, max_age: Duration) -> Self {
+        self.max_profile_age = Some(max_age);
+        self
+    }
+
+    /// Set the [`Clock`] against which the age of profiles is measured, the system clock by
+    /// default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Wait until the [`Throttle`], if any, permits the request.
+    async fn throttle(&self, uri: &Uri, kind: RequestKind) {
+        if let Some(throttle) = &self.throttle {
+            throttle.acquire(uri, kind).await;
         }
     }
 }
 
 impl Default for RelayClient<HyperClient<HttpConnector>> {
     fn default() -> Self {
-        Self {
-            inner_client: HyperClient::new(),
-        }
+        Self::from_service(HyperClient::new())
     }
 }
 
@@ -75,6 +208,18 @@ pub struct ProfilePackage {
     pub profile: Profile,
 }
 
+impl ProfilePackage {
+    /// Check the timestamp of the profile is no older than `max_age` at the time given by the
+    /// [`Clock`].
+    pub fn check_profile_age<C: Clock + ?Sized>(
+        &self,
+        clock: &C,
+        max_age: Duration,
+    ) -> Result<(), StaleTimestamp> {
+        check_age(clock, self.profile.timestamp, max_age)
+    }
+}
+
 impl<S> RelayClient<S>
 where
     Self: Service<(Uri, GetProfile), Response = ProfilePackage>,
@@ -89,8 +234,13 @@ where
         address: &str,
     ) -> Result<ProfilePackage, RelayError<<Self as Service<(Uri, GetProfile)>>::Error>> {
         // Construct URI
-        let full_path = format!("{}/profiles/{}", keyserver_url, address);
-        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+        let path = format!("/profiles/{}", address);
+        let uri = self
+            .endpoint(keyserver_url, &path)
+            .map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Poll).await;
 
         // Construct request
         let request = (uri, GetProfile);
@@ -118,8 +268,11 @@ where
         token: String,
     ) -> Result<(), RelayError<<Self as Service<(Uri, PutProfile)>>::Error>> {
         // Construct URI
-        let full_path = format!("{}/profiles/{}", relay_url, address);
-        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+        let path = format!("/profiles/{}", address);
+        let uri = self.endpoint(relay_url, &path).map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Push).await;
 
         // Construct request
         let request = (uri, PutProfile { token, profile });
@@ -131,3 +284,368 @@ where
             .map_err(RelayError::Error)
     }
 }
+
+/// A range of an inbox, selected using the query parameters of a [`GetMessages`] or
+/// [`GetPayloads`] request.
+///
+/// Both bounds are inclusive, an unset bound is unbounded. A digest takes precedence over a time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageRange {
+    /// The earliest `received_time` of a message, in unix milliseconds.
+    pub start_time: Option<i64>,
+    /// The latest `received_time` of a message, in unix milliseconds.
+    pub end_time: Option<i64>,
+    /// The payload digest of the first message.
+    pub start_digest: Option<[u8; 32]>,
+    /// The payload digest of the last message.
+    pub end_digest: Option<[u8; 32]>,
+}
+
+impl MessageRange {
+    /// Construct the query string, empty if the range is unbounded.
+    pub fn to_query(&self) -> String {
+        let hex = |digest: &[u8; 32]| -> String {
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+        let params: Vec<String> = self
+            .start_time
+            .map(|time| format!("start_time={}", time))
+            .into_iter()
+            .chain(self.end_time.map(|time| format!("end_time={}", time)))
+            .chain(
+                self.start_digest
+                    .map(|digest| format!("start_digest={}", hex(&digest))),
+            )
+            .chain(
+                self.end_digest
+                    .map(|digest| format!("end_digest={}", hex(&digest))),
+            )
+            .collect();
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetMessages), Response = MessagePage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Get a [`MessagePage`] from a relay server.
+    pub async fn get_messages(
+        &self,
+        relay_url: &str,
+        address: &str,
+        token: String,
+    ) -> Result<MessagePage, RelayError<<Self as Service<(Uri, GetMessages)>>::Error>> {
+        self.get_messages_range(relay_url, address, token, &MessageRange::default())
+            .await
+    }
+
+    /// Get a [`MessagePage`], covering a [`MessageRange`], from a relay server.
+    pub async fn get_messages_range(
+        &self,
+        relay_url: &str,
+        address: &str,
+        token: String,
+        range: &MessageRange,
+    ) -> Result<MessagePage, RelayError<<Self as Service<(Uri, GetMessages)>>::Error>> {
+        // Construct URI
+        let path = format!("/messages/{}{}", address, range.to_query());
+        let uri = self.endpoint(relay_url, &path).map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Poll).await;
+
+        // Construct request
+        let request = (uri, GetMessages { token });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetPayloads), Response = PayloadPage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetPayloads)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetPayloads)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Get a [`PayloadPage`], covering a [`MessageRange`], from a relay server.
+    ///
+    /// Only the payloads are returned, allowing payload bodies to be fetched lazily once their
+    /// messages have been retrieved.
+    pub async fn get_payloads_range(
+        &self,
+        relay_url: &str,
+        address: &str,
+        token: String,
+        range: &MessageRange,
+    ) -> Result<PayloadPage, RelayError<<Self as Service<(Uri, GetPayloads)>>::Error>> {
+        // Construct URI
+        let path = format!("/payloads/{}{}", address, range.to_query());
+        let uri = self.endpoint(relay_url, &path).map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Poll).await;
+
+        // Construct request
+        let request = (uri, GetPayloads { token });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+
+    /// Get the payload with a payload digest from a relay server.
+    ///
+    /// The payload is not verified, see [`PayloadStore`](relay::payload_store::PayloadStore).
+    pub async fn get_payload(
+        &self,
+        relay_url: &str,
+        address: &str,
+        token: String,
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<Vec<u8>>, RelayError<<Self as Service<(Uri, GetPayloads)>>::Error>> {
+        let range = MessageRange {
+            start_digest: Some(*payload_digest),
+            end_digest: Some(*payload_digest),
+            ..Default::default()
+        };
+        let page = self
+            .get_payloads_range(relay_url, address, token, &range)
+            .await?;
+        Ok(page.payloads.into_iter().next())
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, PutMessages), Response = ()>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PutMessages)>>::Future: Send + 'static,
+    <Self as Service<(Uri, PutMessages)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Put a [`MessageSet`] to the inbox of an address on a relay server.
+    pub async fn put_messages(
+        &self,
+        relay_url: &str,
+        address: &str,
+        message_set: MessageSet,
+    ) -> Result<(), RelayError<<Self as Service<(Uri, PutMessages)>>::Error>> {
+        // Construct URI
+        let path = format!("/messages/{}", address);
+        let uri = self.endpoint(relay_url, &path).map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Push).await;
+
+        // Construct request
+        let request = (uri, PutMessages { message_set });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetPostagePolicy), Response = PostagePolicy>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetPostagePolicy)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetPostagePolicy)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Get the [`PostagePolicy`] advertised by a relay server.
+    ///
+    /// The policy may be passed to [`estimate_stamp`](relay::postage::estimate_stamp) to price the
+    /// stamp of a message sent via the relay server.
+    pub async fn get_postage_policy(
+        &self,
+        relay_url: &str,
+    ) -> Result<PostagePolicy, RelayError<<Self as Service<(Uri, GetPostagePolicy)>>::Error>> {
+        // Construct URI
+        let uri = self
+            .endpoint(relay_url, "/postage")
+            .map_err(RelayError::Uri)?;
+
+        // Wait for throttle
+        self.throttle(&uri, RequestKind::Poll).await;
+
+        // Construct request
+        let request = (uri, GetPostagePolicy);
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Future, Ready},
+        sync::Mutex,
+        task::{Context, Poll},
+    };
+
+    use clock::ManualClock;
+    use hyper::{http::header::AUTHORIZATION, Body, Response};
+    use secp256k1::{key::SecretKey, Secp256k1};
+
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Records the URI and headers of requests, responding with an empty body.
+    #[derive(Clone, Debug, Default)]
+    struct Recorder {
+        requests: Arc<Mutex<Vec<(Uri, HeaderMap)>>>,
+    }
+
+    impl Service<Request<Body>> for Recorder {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((request.uri().clone(), request.headers().clone()));
+            ready(Ok(Response::new(Body::empty())))
+        }
+    }
+
+    /// Attaches an `Authorization` header to each request.
+    #[derive(Clone, Debug)]
+    struct Authorize<S>(S);
+
+    impl<S: Service<Request<Body>>> Service<Request<Body>> for Authorize<S> {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.0.poll_ready(context)
+        }
+
+        fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, HeaderValue::from_static("POP abc"));
+            self.0.call(request)
+        }
+    }
+
+    #[derive(Debug)]
+    struct AuthorizeLayer;
+
+    impl<S> Layer<S> for AuthorizeLayer {
+        type Service = Authorize<S>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            Authorize(inner)
+        }
+    }
+
+    #[test]
+    fn base_path() {
+        let client = RelayClient::from_service(());
+        assert_eq!(
+            client.endpoint("http://relay.example", "/postage").unwrap(),
+            "http://relay.example/postage"
+        );
+
+        let client = client.with_base_path("/api/v1/");
+        assert_eq!(
+            client.endpoint("http://relay.example", "/postage").unwrap(),
+            "http://relay.example/api/v1/postage"
+        );
+    }
+
+    #[test]
+    fn default_headers() {
+        let client = RelayClient::from_service(());
+        assert_eq!(client.default_headers()[USER_AGENT], DEFAULT_USER_AGENT);
+
+        let client = client
+            .with_user_agent(HeaderValue::from_static("custom/1.0"))
+            .with_header(
+                HeaderName::from_static("x-extra"),
+                HeaderValue::from_static("1"),
+            );
+        let request = client
+            .request_builder(Method::GET, Uri::from_static("http://relay.example"))
+            .body(())
+            .unwrap();
+        assert_eq!(request.headers()[USER_AGENT], "custom/1.0");
+        assert_eq!(request.headers()["x-extra"], "1");
+        assert_eq!(request.headers().len(), 2);
+    }
+
+    #[test]
+    fn inject_layer() {
+        let recorder = Recorder::default();
+        let client = RelayClient::from_service(recorder.clone())
+            .with_base_path("/api")
+            .with_user_agent(HeaderValue::from_static("custom/1.0"))
+            .with_layer(AuthorizeLayer);
+        block_on(client.get_postage_policy("http://relay.example")).unwrap();
+
+        let requests = recorder.requests.lock().unwrap();
+        let (uri, headers) = &requests[0];
+        assert_eq!(uri, "http://relay.example/api/postage");
+        assert_eq!(headers[USER_AGENT], "custom/1.0");
+        assert_eq!(headers[AUTHORIZATION], "POP abc");
+    }
+
+    #[test]
+    fn profile_age() {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let package = |timestamp| ProfilePackage {
+            public_key: PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key),
+            profile: Profile {
+                timestamp,
+                ..Default::default()
+            },
+        };
+        let clock = ManualClock::from_unix(100);
+        let max_age = Duration::from_secs(10);
+
+        assert!(package(90_000).check_profile_age(&clock, max_age).is_ok());
+        assert_eq!(
+            package(89_999).check_profile_age(&clock, max_age),
+            Err(StaleTimestamp {
+                timestamp: 89_999,
+                oldest: 90_000
+            })
+        );
+    }
+}
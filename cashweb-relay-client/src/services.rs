@@ -2,26 +2,26 @@
 
 use std::{fmt, pin::Pin};
 
+use body_limit::{read_body_limited, BodyError, StatusError, DEFAULT_EXCERPT_SIZE};
 use futures_core::{
     task::{Context, Poll},
     Future,
 };
 use http::Method;
-use hyper::{
-    body::aggregate, http::header::AUTHORIZATION, Body, Error as HyperError, Request, Response,
-    StatusCode,
-};
 pub use hyper::{
     client::{connect::Connect, HttpConnector},
     Uri,
 };
+use hyper::{
+    http::header::AUTHORIZATION, Body, Error as HyperError, Request, Response, StatusCode,
+};
 use prost::{DecodeError, Message as _};
 use thiserror::Error;
 use tower_service::Service;
 
-use super::RelayClient;
+use super::{ProfilePackage, RelayClient, StaleTimestamp};
 use ::auth_wrapper::*;
-use relay::{MessagePage, Profile};
+use relay::{postage::PostagePolicy, MessagePage, MessageSet, PayloadPage, PostageRates, Profile};
 
 type ResponseFuture<Response, Error> =
     Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
@@ -39,15 +39,24 @@ pub enum GetProfileError<E: fmt::Debug + fmt::Display> {
     /// Error while decoding the [`AuthWrapper`].
     #[error("authwrapper decoding failure: {0}")]
     AuthWrapperDecode(DecodeError),
+    /// Error while parsing the [`AuthWrapper`].
+    #[error("authwrapper parsing failure: {0}")]
+    AuthWrapperParse(ParseError),
+    /// Error while verifying the [`AuthWrapper`].
+    #[error("authwrapper verification failure: {0}")]
+    AuthWrapperVerify(VerifyError),
     /// Error while processing the body.
     #[error("processing body failed: {0}")]
-    Body(HyperError),
+    Body(BodyError<HyperError>),
     /// A connection error occured.
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
+    /// The profile is older than the maximum age.
+    #[error(transparent)]
+    StaleProfile(StaleTimestamp),
 }
 
 type FutResponse<Response, Error> =
@@ -60,7 +69,7 @@ where
     S::Future: Send,
     S::Error: fmt::Debug + fmt::Display,
 {
-    type Response = AuthWrapper;
+    type Response = ProfilePackage;
     type Error = GetProfileError<S::Error>;
     type Future = FutResponse<Self::Response, Self::Error>;
 
@@ -72,9 +81,11 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetProfile)) -> Self::Future {
         let mut client = self.inner_client.clone();
-        let http_request = Request::builder()
-            .method(Method::GET)
-            .uri(uri)
+        let max_body_size = self.max_body_size;
+        let max_profile_age = self.max_profile_age;
+        let clock = self.clock.clone();
+        let http_request = self
+            .request_builder(Method::GET, uri)
             .body(Body::empty())
             .unwrap(); // This is safe
         let fut = async move {
@@ -85,18 +96,43 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             // Deserialize and decode body
             let body = response.into_body();
-            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let buf = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
             let auth_wrapper = AuthWrapper::decode(buf).map_err(Self::Error::AuthWrapperDecode)?;
 
-            Ok(auth_wrapper)
+            // Parse auth wrapper
+            let parsed_auth_wrapper = auth_wrapper
+                .parse()
+                .map_err(Self::Error::AuthWrapperParse)?;
+
+            // Verify signature
+            parsed_auth_wrapper
+                .verify()
+                .map_err(Self::Error::AuthWrapperVerify)?;
+
+            // Decode profile
+            let profile = Profile::decode(&mut parsed_auth_wrapper.payload.as_slice())
+                .map_err(Self::Error::ProfileDecode)?;
+            let package = ProfilePackage {
+                public_key: parsed_auth_wrapper.public_key,
+                profile,
+            };
+
+            // Check freshness
+            if let Some(max_age) = max_profile_age {
+                package
+                    .check_profile_age(&*clock, max_age)
+                    .map_err(Self::Error::StaleProfile)?;
+            }
+            Ok(package)
         };
         Box::pin(fut)
     }
@@ -109,8 +145,8 @@ pub enum PutProfileError<E: fmt::Debug + fmt::Display> {
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
 }
 
 /// Request for putting [`Profile`] to the keyserver.
@@ -146,9 +182,8 @@ where
         let mut body = Vec::with_capacity(request.profile.encoded_len());
         request.profile.encode(&mut body).unwrap();
 
-        let http_request = Request::builder()
-            .method(Method::PUT)
-            .uri(uri)
+        let http_request = self
+            .request_builder(Method::PUT, uri)
             .header(AUTHORIZATION, request.token)
             .body(Body::from(body))
             .unwrap(); // This is safe
@@ -161,10 +196,9 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             Ok(())
@@ -180,11 +214,11 @@ pub enum GetMessageError<E: fmt::Debug + fmt::Display> {
     #[error("connection failure: {0}")]
     Service(E),
     /// Unexpected status code.
-    #[error("unexpected status code: {0}")]
-    UnexpectedStatusCode(u16),
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
     /// Error while processing the body.
     #[error("processing body failed: {0}")]
-    Body(HyperError),
+    Body(BodyError<HyperError>),
     /// Error while decoding the [`MessagePage`].
     #[error("messagepage decoding failure: {0}")]
     MessagePageDecode(DecodeError),
@@ -216,10 +250,10 @@ where
 
     fn call(&mut self, (uri, request): (Uri, GetMessages)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
 
-        let http_request = Request::builder()
-            .method(Method::GET)
-            .uri(uri)
+        let http_request = self
+            .request_builder(Method::GET, uri)
             .header(AUTHORIZATION, request.token)
             .body(Body::empty())
             .unwrap(); // This is safe
@@ -232,15 +266,16 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
-            match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
             }
 
             // Deserialize and decode body
             let body = response.into_body();
-            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let buf = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
             let message_page = MessagePage::decode(buf).map_err(Self::Error::MessagePageDecode)?;
 
             Ok(message_page)
@@ -248,3 +283,218 @@ where
         Box::pin(fut)
     }
 }
+
+/// Error associated with getting a [`PayloadPage`] from the relay server.
+#[derive(Debug, Error)]
+pub enum GetPayloadsError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(BodyError<HyperError>),
+    /// Error while decoding the [`PayloadPage`].
+    #[error("payloadpage decoding failure: {0}")]
+    PayloadPageDecode(DecodeError),
+}
+
+/// Represents a request for a [`PayloadPage`].
+#[derive(Clone, Debug)]
+pub struct GetPayloads {
+    /// POP token attached to the request.
+    pub token: String,
+}
+
+impl<S> Service<(Uri, GetPayloads)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = PayloadPage;
+    type Error = GetPayloadsError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetPayloadsError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, GetPayloads)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
+
+        let http_request = self
+            .request_builder(Method::GET, uri)
+            .header(AUTHORIZATION, request.token)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
+            }
+
+            // Deserialize and decode body
+            let body = response.into_body();
+            let buf = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
+            let payload_page = PayloadPage::decode(buf).map_err(Self::Error::PayloadPageDecode)?;
+
+            Ok(payload_page)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Error associated with putting a [`MessageSet`] to the relay server.
+#[derive(Clone, Debug, Error)]
+pub enum PutMessagesError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
+}
+
+/// Request for putting a [`MessageSet`] to the relay server.
+#[derive(Clone, Debug)]
+pub struct PutMessages {
+    /// The [`MessageSet`] to be put.
+    pub message_set: MessageSet,
+}
+
+impl<S> Service<(Uri, PutMessages)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = ();
+    type Error = PutMessagesError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(PutMessagesError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, PutMessages)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        // Construct body
+        let mut body = Vec::with_capacity(request.message_set.encoded_len());
+        request.message_set.encode(&mut body).unwrap(); // This is safe
+
+        let http_request = self
+            .request_builder(Method::PUT, uri)
+            .body(Body::from(body))
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
+            }
+
+            Ok(())
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for the [`PostagePolicy`] advertised by a relay server.
+#[derive(Clone, Debug)]
+pub struct GetPostagePolicy;
+
+/// Error associated with getting the [`PostagePolicy`] from a relay server.
+#[derive(Debug, Error)]
+pub enum GetPostagePolicyError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(BodyError<HyperError>),
+    /// Error while decoding the [`PostageRates`].
+    #[error("postage rates decoding failure: {0}")]
+    PostageRatesDecode(DecodeError),
+}
+
+impl<S> Service<(Uri, GetPostagePolicy)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = PostagePolicy;
+    type Error = GetPostagePolicyError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetPostagePolicyError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, GetPostagePolicy)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let max_body_size = self.max_body_size;
+        let http_request = self
+            .request_builder(Method::GET, uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            if response.status() != StatusCode::OK {
+                let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+                return Err(Self::Error::UnexpectedStatus(error));
+            }
+
+            // Deserialize and decode body
+            let body = response.into_body();
+            let buf = read_body_limited(body, max_body_size)
+                .await
+                .map_err(Self::Error::Body)?;
+            let rates = PostageRates::decode(buf).map_err(Self::Error::PostageRatesDecode)?;
+
+            Ok(rates.into())
+        };
+        Box::pin(fut)
+    }
+}
@@ -0,0 +1,216 @@
+//! This module contains the [`Throttle`] which paces outgoing requests to each relay server.
+//!
+//! Relay servers rate limit clients by POP token and may revoke the token of persistent offenders.
+//! Clients syncing a large backlog should pace their pushes and polls to stay beneath these
+//! limits. Each relay server, identified by its authority, is assigned a token bucket for pushes
+//! and another for polls. Requests exceeding the bucket are delayed rather than rejected.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use clock::delay_for;
+use hyper::Uri;
+
+/// The rate allowed for each relay server.
+///
+/// Each relay server may receive `burst` requests in a `period`, the allowance is replenished
+/// continuously.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The maximum number of requests which can be made at once.
+    pub burst: u32,
+    /// The period over which the `burst` is replenished.
+    pub period: Duration,
+}
+
+/// The kind of request being throttled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    /// Putting messages or profiles to a relay server.
+    Push,
+    /// Getting messages or profiles from a relay server.
+    Poll,
+}
+
+#[derive(Clone, Debug)]
+struct Bucket {
+    allowance: f64,
+    last_checked: Instant,
+}
+
+/// Collection of token buckets, keyed by relay server and [`RequestKind`].
+#[derive(Debug)]
+pub struct Throttle {
+    push: Option<RateLimit>,
+    poll: Option<RateLimit>,
+    buckets: Mutex<HashMap<(String, RequestKind), Bucket>>,
+}
+
+impl Throttle {
+    /// Create a new [`Throttle`]. A `None` limit leaves that kind of request unthrottled.
+    pub fn new(push: Option<RateLimit>, poll: Option<RateLimit>) -> Self {
+        Self {
+            push,
+            poll,
+            buckets: Default::default(),
+        }
+    }
+
+    /// The [`RateLimit`] applied to a kind of request.
+    pub fn limit(&self, kind: RequestKind) -> Option<RateLimit> {
+        match kind {
+            RequestKind::Push => self.push,
+            RequestKind::Poll => self.poll,
+        }
+    }
+
+    /// Take from the bucket associated with the relay server, returning the delay to wait before
+    /// sending the request.
+    pub fn reserve(&self, uri: &Uri, kind: RequestKind) -> Duration {
+        self.reserve_at(uri, kind, Instant::now())
+    }
+
+    /// Take from the bucket associated with the relay server at a given instant, returning the
+    /// delay to wait before sending the request.
+    ///
+    /// The allowance may become negative, in which case subsequent requests are delayed further.
+    pub fn reserve_at(&self, uri: &Uri, kind: RequestKind, now: Instant) -> Duration {
+        let limit = match self.limit(kind) {
+            Some(some) if some.burst != 0 => some,
+            _ => return Duration::default(),
+        };
+        let burst = limit.burst as f64;
+        let rate = burst / limit.period.as_secs_f64();
+
+        let key = uri
+            .authority()
+            .map(|authority| authority.as_str().to_string())
+            .unwrap_or_else(|| uri.to_string());
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry((key, kind)).or_insert(Bucket {
+            allowance: burst,
+            last_checked: now,
+        });
+
+        // Replenish
+        let elapsed = now.saturating_duration_since(bucket.last_checked);
+        bucket.allowance = (bucket.allowance + elapsed.as_secs_f64() * rate).min(burst);
+        bucket.last_checked = now;
+
+        // Take
+        bucket.allowance -= 1.0;
+        if bucket.allowance >= 0.0 {
+            Duration::default()
+        } else {
+            Duration::from_secs_f64(-bucket.allowance / rate)
+        }
+    }
+
+    /// Wait until the request may be sent.
+    pub async fn acquire(&self, uri: &Uri, kind: RequestKind) {
+        let delay = self.reserve(uri, kind);
+        if delay != Duration::default() {
+            delay_for(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        period: Duration::from_secs(1),
+    };
+
+    #[test]
+    fn burst_then_delay() {
+        let throttle = Throttle::new(Some(LIMIT), None);
+        let uri: Uri = "http://relay.example/messages".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, now),
+            Duration::default()
+        );
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, now),
+            Duration::default()
+        );
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, now),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, now),
+            Duration::from_secs(1)
+        );
+
+        // The allowance is replenished continuously, up to the burst
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, later),
+            Duration::default()
+        );
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Push, later),
+            Duration::default()
+        );
+        assert_ne!(
+            throttle.reserve_at(&uri, RequestKind::Push, later),
+            Duration::default()
+        );
+    }
+
+    #[test]
+    fn separate_buckets() {
+        let throttle = Throttle::new(Some(LIMIT), Some(LIMIT));
+        let uri: Uri = "http://relay.example/messages".parse().unwrap();
+        let same_relay: Uri = "http://relay.example/profiles".parse().unwrap();
+        let other_relay: Uri = "http://other.example/messages".parse().unwrap();
+        let now = Instant::now();
+
+        throttle.reserve_at(&uri, RequestKind::Push, now);
+        throttle.reserve_at(&same_relay, RequestKind::Push, now);
+        assert_ne!(
+            throttle.reserve_at(&uri, RequestKind::Push, now),
+            Duration::default()
+        );
+
+        assert_eq!(
+            throttle.reserve_at(&uri, RequestKind::Poll, now),
+            Duration::default()
+        );
+        assert_eq!(
+            throttle.reserve_at(&other_relay, RequestKind::Push, now),
+            Duration::default()
+        );
+    }
+
+    #[test]
+    fn unthrottled() {
+        let throttle = Throttle::new(
+            None,
+            Some(RateLimit {
+                burst: 0,
+                period: Duration::from_secs(1),
+            }),
+        );
+        let uri: Uri = "http://relay.example/messages".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(
+                throttle.reserve_at(&uri, RequestKind::Push, now),
+                Duration::default()
+            );
+            assert_eq!(
+                throttle.reserve_at(&uri, RequestKind::Poll, now),
+                Duration::default()
+            );
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! This module contains the [`StampLedger`] which records the stamp outpoints of accepted messages,
+//! preventing a single stamp from being attached to several messages.
+//!
+//! An outpoint may be claimed repeatedly by the same payload digest, allowing clients to resubmit a
+//! message, or mirror it across relay servers sharing a ledger. A [`MemoryStampLedger`] is
+//! provided.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use relay::stamp::VerifiedStamp;
+
+/// A reference to a transaction output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Outpoint {
+    /// The transaction ID, in big-endian format.
+    pub txid: [u8; 32],
+    /// The index of the output within the transaction.
+    pub vout: u32,
+}
+
+impl Outpoint {
+    /// The outpoints of each output in a [`VerifiedStamp`], in the order they were specified.
+    pub fn from_stamp(stamp: &VerifiedStamp) -> Vec<Self> {
+        let txids: Vec<_> = stamp
+            .transactions
+            .iter()
+            .map(|transaction| transaction.transaction_id())
+            .collect();
+        stamp
+            .outputs
+            .iter()
+            .map(|output| Self {
+                txid: txids[output.tx_num as usize],
+                vout: output.vout,
+            })
+            .collect()
+    }
+}
+
+/// A ledger of the stamp outpoints claimed by accepted messages.
+pub trait StampLedger {
+    /// Error associated with accessing the ledger.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Claim the outpoints for a payload digest.
+    ///
+    /// If any outpoint has been claimed by a different payload digest then it is returned and no
+    /// outpoints are claimed. Otherwise every outpoint is recorded against the payload digest.
+    fn claim(
+        &self,
+        payload_digest: &[u8; 32],
+        outpoints: &[Outpoint],
+    ) -> Result<Option<Outpoint>, Self::Error>;
+
+    /// Release the outpoints claimed by a payload digest, for example once the message has been
+    /// pruned.
+    fn release(&self, payload_digest: &[u8; 32], outpoints: &[Outpoint])
+        -> Result<(), Self::Error>;
+}
+
+/// An in-memory [`StampLedger`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStampLedger {
+    claims: Arc<Mutex<HashMap<Outpoint, [u8; 32]>>>,
+}
+
+impl MemoryStampLedger {
+    /// Create a new, empty, [`MemoryStampLedger`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StampLedger for MemoryStampLedger {
+    type Error = Infallible;
+
+    fn claim(
+        &self,
+        payload_digest: &[u8; 32],
+        outpoints: &[Outpoint],
+    ) -> Result<Option<Outpoint>, Self::Error> {
+        let mut claims = self.claims.lock().unwrap();
+
+        // Check for reuse before claiming any outpoint
+        let reused = outpoints.iter().find(|outpoint| {
+            claims
+                .get(outpoint)
+                .map(|claimant| claimant != payload_digest)
+                .unwrap_or(false)
+        });
+        if let Some(reused) = reused {
+            return Ok(Some(*reused));
+        }
+
+        for outpoint in outpoints {
+            claims.insert(*outpoint, *payload_digest);
+        }
+        Ok(None)
+    }
+
+    fn release(
+        &self,
+        payload_digest: &[u8; 32],
+        outpoints: &[Outpoint],
+    ) -> Result<(), Self::Error> {
+        let mut claims = self.claims.lock().unwrap();
+        for outpoint in outpoints {
+            if claims.get(outpoint) == Some(payload_digest) {
+                claims.remove(outpoint);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let ledger = MemoryStampLedger::new();
+        let outpoint = Outpoint {
+            txid: [1; 32],
+            vout: 0,
+        };
+        let other = Outpoint {
+            txid: [1; 32],
+            vout: 1,
+        };
+        assert_eq!(ledger.claim(&[2; 32], &[outpoint]).unwrap(), None);
+
+        // Resubmission is permitted
+        assert_eq!(ledger.claim(&[2; 32], &[outpoint]).unwrap(), None);
+
+        // Reuse by another message claims nothing
+        assert_eq!(
+            ledger.claim(&[3; 32], &[other, outpoint]).unwrap(),
+            Some(outpoint)
+        );
+        assert_eq!(ledger.claim(&[4; 32], &[other]).unwrap(), None);
+
+        ledger.release(&[2; 32], &[outpoint]).unwrap();
+        assert_eq!(ledger.claim(&[3; 32], &[outpoint]).unwrap(), None);
+    }
+}
@@ -0,0 +1,30 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-relay-server` is a library providing components for implementing servers within the
+//! [`Relay Protocol`].
+//!
+//! The [`MessageValidator`] is a [`Service`](tower_service::Service) accepting incoming
+//! [`Message`](relay::Message)s. It enforces size limits, parses the message, verifies the stamp
+//! against a [`StampPolicy`] and checks for stamp outpoint reuse via a [`StampLedger`], producing
+//! either the accepted [`ParsedMessage`](relay::ParsedMessage) or a structured [`Rejection`].
+//!
+//! Accepted messages are persisted by a [`MessageStore`], which serves
+//! [`MessagePage`](relay::MessagePage)s directly. Enabling the `rocksdb` feature provides a
+//! RocksDB-backed implementation.
+//!
+//! [`Relay Protocol`]: https://github.com/cashweb/specifications/blob/master/relay-server-protocol/specification.mediawiki
+
+pub mod ledger;
+pub mod policy;
+pub mod store;
+pub mod validation;
+
+pub use ledger::{MemoryStampLedger, Outpoint, StampLedger};
+pub use policy::{PolicyViolation, StampPolicy};
+pub use store::{Cursor, MemoryMessageStore, MessageStore, PageQuery};
+pub use validation::{MessageValidator, Rejection, ValidationLimits};
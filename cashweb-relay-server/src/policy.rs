@@ -0,0 +1,74 @@
+//! This module contains the [`StampPolicy`] trait which decides whether the verified stamp of a
+//! message carries sufficient value.
+//!
+//! The [`PostagePolicy`] advertised to clients implements [`StampPolicy`], requiring the summed
+//! value of the stamp outputs to cover the postage of the `payload_size` and each output to lie
+//! between the dust limit and the maximum output value.
+
+use relay::{postage::PostagePolicy, stamp::VerifiedStamp, ParsedMessage};
+use thiserror::Error;
+
+/// The stamp violated the [`StampPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum PolicyViolation {
+    /// The summed value of the stamp outputs was insufficient.
+    #[error("insufficient stamp value: {provided} < {required}")]
+    InsufficientValue {
+        /// The value, in satoshis, required.
+        required: u64,
+        /// The value, in satoshis, provided.
+        provided: u64,
+    },
+    /// A stamp output was below the dust limit.
+    #[error("stamp output {tx_num}:{vout} below dust limit")]
+    DustOutput {
+        /// The position of the transaction within the stamp.
+        tx_num: u32,
+        /// The index of the output within the transaction.
+        vout: u32,
+    },
+    /// A stamp output exceeded the maximum output value.
+    #[error("stamp output {tx_num}:{vout} exceeds maximum value")]
+    ExcessiveOutput {
+        /// The position of the transaction within the stamp.
+        tx_num: u32,
+        /// The index of the output within the transaction.
+        vout: u32,
+    },
+}
+
+/// Decides whether the verified stamp of a message carries sufficient value.
+pub trait StampPolicy {
+    /// Check the [`VerifiedStamp`] attached to the [`ParsedMessage`].
+    fn check(&self, message: &ParsedMessage, stamp: &VerifiedStamp) -> Result<(), PolicyViolation>;
+}
+
+impl StampPolicy for PostagePolicy {
+    fn check(&self, message: &ParsedMessage, stamp: &VerifiedStamp) -> Result<(), PolicyViolation> {
+        for output in &stamp.outputs {
+            if output.value < self.dust_limit {
+                return Err(PolicyViolation::DustOutput {
+                    tx_num: output.tx_num,
+                    vout: output.vout,
+                });
+            }
+            if let Some(max_output_value) = self.max_output_value {
+                if output.value > max_output_value {
+                    return Err(PolicyViolation::ExcessiveOutput {
+                        tx_num: output.tx_num,
+                        vout: output.vout,
+                    });
+                }
+            }
+        }
+
+        let required = self.required_postage(message.payload_size as usize);
+        if stamp.total_value < required {
+            return Err(PolicyViolation::InsufficientValue {
+                required,
+                provided: stamp.total_value,
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! This module contains the [`MessageStore`] trait which persists accepted messages, indexed by
+//! recipient and `received_time`, and produces [`MessagePage`]s directly.
+//!
+//! Within an inbox messages are ordered by `received_time`, ties are broken by payload digest. A
+//! page may start and end at either a time or the payload digest of a message, see [`PageQuery`].
+//!
+//! A [`MemoryMessageStore`] is provided. When the `rocksdb` feature is enabled the
+//! [`RocksMessageStore`] persists messages to a RocksDB database.
+
+#[cfg(feature = "rocksdb")]
+mod rocks;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use relay::{Message, MessagePage, ParsedMessage};
+
+#[cfg(feature = "rocksdb")]
+pub use rocks::{RocksMessageStore, RocksStoreError};
+
+/// A position within an inbox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cursor {
+    /// A `received_time`, in unix milliseconds.
+    Time(i64),
+    /// The payload digest of a message.
+    Digest([u8; 32]),
+}
+
+/// A query selecting a range of an inbox.
+///
+/// Both bounds are inclusive, an unset bound is unbounded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageQuery {
+    /// The position of the first message.
+    pub start: Option<Cursor>,
+    /// The position of the last message.
+    pub end: Option<Cursor>,
+    /// The maximum number of messages returned.
+    pub limit: Option<usize>,
+}
+
+/// A store persisting accepted messages.
+pub trait MessageStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Append a message to the inbox of a recipient, typically the hash of the destination public
+    /// key.
+    ///
+    /// Appending a message already present is a no-op.
+    fn append(&self, recipient: &[u8], message: &ParsedMessage) -> Result<(), Self::Error>;
+
+    /// Get a message from an inbox by payload digest.
+    fn get_message(
+        &self,
+        recipient: &[u8],
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<Message>, Self::Error>;
+
+    /// Get a [`MessagePage`] from an inbox.
+    ///
+    /// A [`Cursor::Digest`] missing from the inbox yields an empty page.
+    fn get_page(&self, recipient: &[u8], query: &PageQuery) -> Result<MessagePage, Self::Error>;
+
+    /// Remove a message from an inbox, returning whether it was present.
+    fn remove(&self, recipient: &[u8], payload_digest: &[u8; 32]) -> Result<bool, Self::Error>;
+
+    /// Remove the messages, across all inboxes, received before a time, returning the number
+    /// removed.
+    fn prune(&self, before: i64) -> Result<usize, Self::Error>;
+}
+
+/// Construct a [`MessagePage`] from messages in inbox order, starting at the start of the query.
+///
+/// The `resolve` closure finds the `received_time` of a message by payload digest.
+pub(crate) fn collect_page<I, F, E>(
+    messages: I,
+    query: &PageQuery,
+    resolve: F,
+) -> Result<MessagePage, E>
+where
+    I: IntoIterator<Item = Result<(i64, [u8; 32], Message), E>>,
+    F: Fn(&[u8; 32]) -> Result<Option<i64>, E>,
+{
+    let end = match query.end {
+        Some(Cursor::Time(time)) => Some((time, [0xff; 32])),
+        Some(Cursor::Digest(digest)) => match resolve(&digest)? {
+            Some(time) => Some((time, digest)),
+            None => return Ok(MessagePage::default()),
+        },
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(usize::MAX);
+
+    let mut page = MessagePage::default();
+    for item in messages {
+        if page.messages.len() >= limit {
+            break;
+        }
+        let (time, digest, message) = item?;
+        if let Some(end) = end {
+            if (time, digest) > end {
+                break;
+            }
+        }
+        if page.messages.is_empty() {
+            page.start_time = time;
+            page.start_digest = digest.to_vec();
+        }
+        page.end_time = time;
+        page.end_digest = digest.to_vec();
+        page.messages.push(message);
+    }
+    Ok(page)
+}
+
+/// The position, within an inbox, of the start of a query.
+///
+/// Returns `None` if the start is a digest missing from the inbox.
+pub(crate) fn start_position<F, E>(
+    query: &PageQuery,
+    resolve: F,
+) -> Result<Option<(i64, [u8; 32])>, E>
+where
+    F: Fn(&[u8; 32]) -> Result<Option<i64>, E>,
+{
+    Ok(match query.start {
+        Some(Cursor::Time(time)) => Some((time, [0; 32])),
+        Some(Cursor::Digest(digest)) => resolve(&digest)?.map(|time| (time, digest)),
+        None => Some((i64::MIN, [0; 32])),
+    })
+}
+
+type Inbox = BTreeMap<(i64, [u8; 32]), Message>;
+
+/// An in-memory [`MessageStore`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMessageStore {
+    inboxes: Arc<RwLock<HashMap<Vec<u8>, Inbox>>>,
+}
+
+impl MemoryMessageStore {
+    /// Create a new, empty, [`MemoryMessageStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Find the `received_time` of a message within an inbox.
+fn find_time(inbox: Option<&Inbox>, payload_digest: &[u8; 32]) -> Option<i64> {
+    inbox?
+        .keys()
+        .find(|(_, digest)| digest == payload_digest)
+        .map(|(time, _)| *time)
+}
+
+impl MessageStore for MemoryMessageStore {
+    type Error = Infallible;
+
+    fn append(&self, recipient: &[u8], message: &ParsedMessage) -> Result<(), Self::Error> {
+        let mut inboxes = self.inboxes.write().unwrap();
+        let inbox = inboxes.entry(recipient.to_vec()).or_default();
+        if find_time(Some(inbox), &message.payload_digest).is_none() {
+            inbox.insert(
+                (message.received_time, message.payload_digest),
+                message.clone().into_message(),
+            );
+        }
+        Ok(())
+    }
+
+    fn get_message(
+        &self,
+        recipient: &[u8],
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<Message>, Self::Error> {
+        let inboxes = self.inboxes.read().unwrap();
+        let inbox = inboxes.get(recipient);
+        Ok(find_time(inbox, payload_digest)
+            .and_then(|time| inbox?.get(&(time, *payload_digest)).cloned()))
+    }
+
+    fn get_page(&self, recipient: &[u8], query: &PageQuery) -> Result<MessagePage, Self::Error> {
+        let inboxes = self.inboxes.read().unwrap();
+        let inbox = match inboxes.get(recipient) {
+            Some(some) => some,
+            None => return Ok(MessagePage::default()),
+        };
+        let resolve = |digest: &[u8; 32]| Ok(find_time(Some(inbox), digest));
+
+        let start = match start_position(query, resolve)? {
+            Some(some) => some,
+            None => return Ok(MessagePage::default()),
+        };
+        let messages = inbox
+            .range(start..)
+            .map(|((time, digest), message)| Ok((*time, *digest, message.clone())));
+        collect_page(messages, query, resolve)
+    }
+
+    fn remove(&self, recipient: &[u8], payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        let mut inboxes = self.inboxes.write().unwrap();
+        let inbox = match inboxes.get_mut(recipient) {
+            Some(some) => some,
+            None => return Ok(false),
+        };
+        Ok(match find_time(Some(inbox), payload_digest) {
+            Some(time) => inbox.remove(&(time, *payload_digest)).is_some(),
+            None => false,
+        })
+    }
+
+    fn prune(&self, before: i64) -> Result<usize, Self::Error> {
+        let mut inboxes = self.inboxes.write().unwrap();
+        let mut removed = 0;
+        for inbox in inboxes.values_mut() {
+            let retained = inbox.split_off(&(before, [0; 32]));
+            removed += inbox.len();
+            *inbox = retained;
+        }
+        inboxes.retain(|_, inbox| !inbox.is_empty());
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relay::{
+        secp::{PrivateKey, PublicKey, Secp256k1},
+        stamp::{Stamp, StampType},
+        DigestAlgorithm, EncryptionScheme,
+    };
+
+    use super::*;
+
+    fn message(received_time: i64, digest: u8) -> ParsedMessage {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        ParsedMessage {
+            version: 1,
+            source_public_key: public_key,
+            destination_public_key: public_key,
+            received_time,
+            payload_digest: [digest; 32],
+            digest_algorithm: DigestAlgorithm::Sha256,
+            stamp: Stamp {
+                stamp_type: StampType::MessageCommitment.into(),
+                stamp_outpoints: vec![],
+            },
+            scheme: EncryptionScheme::EphemeralDh,
+            salt: vec![],
+            payload_hmac: [0; 32],
+            payload_size: 0,
+            payload: vec![],
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let store = MemoryMessageStore::new();
+        for (time, digest) in &[(30, 3), (10, 1), (20, 2), (20, 4), (10, 1)] {
+            store.append(b"alice", &message(*time, *digest)).unwrap();
+        }
+        store.append(b"bob", &message(5, 9)).unwrap();
+
+        let page = store.get_page(b"alice", &PageQuery::default()).unwrap();
+        assert_eq!(page.messages.len(), 4);
+        assert_eq!(page.start_time, 10);
+        assert_eq!(page.end_digest, vec![3; 32]);
+
+        let query = PageQuery {
+            start: Some(Cursor::Digest([2; 32])),
+            end: Some(Cursor::Time(20)),
+            limit: None,
+        };
+        let page = store.get_page(b"alice", &query).unwrap();
+        assert_eq!(page.start_digest, vec![2; 32]);
+        assert_eq!(page.end_digest, vec![4; 32]);
+        assert_eq!(page.messages.len(), 2);
+
+        let query = PageQuery {
+            start: Some(Cursor::Time(15)),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = store.get_page(b"alice", &query).unwrap();
+        assert_eq!(page.end_digest, vec![2; 32]);
+        assert_eq!(page.messages.len(), 1);
+    }
+
+    #[test]
+    fn remove_and_prune() {
+        let store = MemoryMessageStore::new();
+        store.append(b"alice", &message(10, 1)).unwrap();
+        store.append(b"alice", &message(20, 2)).unwrap();
+        store.append(b"bob", &message(5, 3)).unwrap();
+
+        assert!(store.get_message(b"alice", &[1; 32]).unwrap().is_some());
+        assert!(store.remove(b"alice", &[1; 32]).unwrap());
+        assert!(!store.remove(b"alice", &[1; 32]).unwrap());
+
+        assert_eq!(store.prune(15).unwrap(), 1);
+        assert!(store.get_message(b"bob", &[3; 32]).unwrap().is_none());
+        assert!(store.get_message(b"alice", &[2; 32]).unwrap().is_some());
+    }
+}
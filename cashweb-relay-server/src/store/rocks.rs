@@ -0,0 +1,205 @@
+use std::{convert::TryInto, path::Path, sync::Arc};
+
+use prost::Message as _;
+use relay::{Message, MessagePage, ParsedMessage};
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use thiserror::Error;
+
+use super::{collect_page, start_position, MessageStore, PageQuery};
+
+/// Namespace of the messages, keyed by recipient, `received_time` and payload digest.
+const MESSAGE_NAMESPACE: u8 = b'm';
+
+/// Namespace of the digest index, mapping recipient and payload digest to `received_time`.
+const DIGEST_NAMESPACE: u8 = b'd';
+
+/// Error associated with the [`RocksMessageStore`].
+#[derive(Debug, Error)]
+pub enum RocksStoreError {
+    /// Error within RocksDB.
+    #[error(transparent)]
+    Rocks(#[from] rocksdb::Error),
+    /// Failed to decode a stored message.
+    #[error("failed to decode message: {0}")]
+    Decode(#[from] prost::DecodeError),
+    /// The recipient exceeded 255 bytes.
+    #[error("recipient too long")]
+    RecipientTooLong,
+    /// A stored key or index entry was malformed.
+    #[error("corrupt key")]
+    Corrupt,
+}
+
+/// A [`MessageStore`] persisting messages to a RocksDB database.
+///
+/// Messages are keyed by recipient, `received_time` and payload digest so that pages are read by
+/// a single forward iteration. A secondary index maps each payload digest to its `received_time`.
+#[derive(Clone, Debug)]
+pub struct RocksMessageStore {
+    db: Arc<DB>,
+}
+
+/// Encode a time such that the lexicographic order of the bytes matches the numeric order.
+fn encode_time(time: i64) -> [u8; 8] {
+    ((time as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+fn decode_time(raw: &[u8]) -> Result<i64, RocksStoreError> {
+    let raw: [u8; 8] = raw.try_into().map_err(|_| RocksStoreError::Corrupt)?;
+    Ok((u64::from_be_bytes(raw) ^ (1 << 63)) as i64)
+}
+
+fn prefix(namespace: u8, recipient: &[u8]) -> Result<Vec<u8>, RocksStoreError> {
+    let len = recipient.len();
+    if len > u8::MAX as usize {
+        return Err(RocksStoreError::RecipientTooLong);
+    }
+    let mut key = Vec::with_capacity(2 + len + 8 + 32);
+    key.push(namespace);
+    key.push(len as u8);
+    key.extend_from_slice(recipient);
+    Ok(key)
+}
+
+fn message_key(recipient: &[u8], time: i64, digest: &[u8; 32]) -> Result<Vec<u8>, RocksStoreError> {
+    let mut key = prefix(MESSAGE_NAMESPACE, recipient)?;
+    key.extend_from_slice(&encode_time(time));
+    key.extend_from_slice(digest);
+    Ok(key)
+}
+
+fn digest_key(recipient: &[u8], digest: &[u8; 32]) -> Result<Vec<u8>, RocksStoreError> {
+    let mut key = prefix(DIGEST_NAMESPACE, recipient)?;
+    key.extend_from_slice(digest);
+    Ok(key)
+}
+
+/// Split the `received_time` and payload digest from the end of a message key.
+fn split_message_key(key: &[u8]) -> Result<(i64, [u8; 32]), RocksStoreError> {
+    if key.len() < 2 + 8 + 32 {
+        return Err(RocksStoreError::Corrupt);
+    }
+    let (time, digest) = key[key.len() - 40..].split_at(8);
+    // This is safe
+    Ok((decode_time(time)?, digest.try_into().unwrap()))
+}
+
+impl RocksMessageStore {
+    /// Open a [`RocksMessageStore`] at a path, creating the database if missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RocksStoreError> {
+        Ok(Self::from_db(DB::open_default(path)?))
+    }
+
+    /// Create a [`RocksMessageStore`] from an opened database.
+    pub fn from_db(db: DB) -> Self {
+        Self { db: Arc::new(db) }
+    }
+
+    fn find_time(
+        &self,
+        recipient: &[u8],
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<i64>, RocksStoreError> {
+        self.db
+            .get(digest_key(recipient, payload_digest)?)?
+            .map(|raw| decode_time(&raw))
+            .transpose()
+    }
+}
+
+impl MessageStore for RocksMessageStore {
+    type Error = RocksStoreError;
+
+    fn append(&self, recipient: &[u8], message: &ParsedMessage) -> Result<(), Self::Error> {
+        let digest_key = digest_key(recipient, &message.payload_digest)?;
+        if self.db.get(&digest_key)?.is_some() {
+            return Ok(());
+        }
+
+        let key = message_key(recipient, message.received_time, &message.payload_digest)?;
+        let mut raw_message = Vec::with_capacity(message.payload.len() + 256);
+        // This is safe
+        message
+            .clone()
+            .into_message()
+            .encode(&mut raw_message)
+            .unwrap();
+
+        let mut batch = WriteBatch::default();
+        batch.put(key, raw_message);
+        batch.put(digest_key, encode_time(message.received_time));
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn get_message(
+        &self,
+        recipient: &[u8],
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<Message>, Self::Error> {
+        let time = match self.find_time(recipient, payload_digest)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+        self.db
+            .get(message_key(recipient, time, payload_digest)?)?
+            .map(|raw| Message::decode(&raw[..]).map_err(RocksStoreError::Decode))
+            .transpose()
+    }
+
+    fn get_page(&self, recipient: &[u8], query: &PageQuery) -> Result<MessagePage, Self::Error> {
+        let resolve = |digest: &[u8; 32]| self.find_time(recipient, digest);
+        let (time, digest) = match start_position(query, resolve)? {
+            Some(some) => some,
+            None => return Ok(MessagePage::default()),
+        };
+
+        let inbox_prefix = prefix(MESSAGE_NAMESPACE, recipient)?;
+        let start_key = message_key(recipient, time, &digest)?;
+        let messages = self
+            .db
+            .iterator(IteratorMode::From(&start_key, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&inbox_prefix))
+            .map(|(key, value)| {
+                let (time, digest) = split_message_key(&key)?;
+                let message = Message::decode(&value[..])?;
+                Ok((time, digest, message))
+            });
+        collect_page(messages, query, resolve)
+    }
+
+    fn remove(&self, recipient: &[u8], payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        let time = match self.find_time(recipient, payload_digest)? {
+            Some(some) => some,
+            None => return Ok(false),
+        };
+        let mut batch = WriteBatch::default();
+        batch.delete(message_key(recipient, time, payload_digest)?);
+        batch.delete(digest_key(recipient, payload_digest)?);
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn prune(&self, before: i64) -> Result<usize, Self::Error> {
+        // Inboxes are not ordered by time so every message key is visited
+        let mut batch = WriteBatch::default();
+        let mut removed = 0;
+        for (key, _) in self
+            .db
+            .iterator(IteratorMode::From(&[MESSAGE_NAMESPACE], Direction::Forward))
+        {
+            if key.first() != Some(&MESSAGE_NAMESPACE) {
+                break;
+            }
+            let (time, digest) = split_message_key(&key)?;
+            if time < before {
+                let recipient = &key[2..key.len() - 40];
+                batch.delete(digest_key(recipient, &digest)?);
+                batch.delete(&key);
+                removed += 1;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(removed)
+    }
+}
@@ -0,0 +1,327 @@
+//! This module contains the [`MessageValidator`], a [`Service`] accepting or rejecting incoming
+//! [`Message`]s.
+//!
+//! Validation proceeds from the cheapest checks to the most expensive:
+//! 1. The serialized message and payload are checked against the [`ValidationLimits`].
+//! 2. The message is parsed, checking the payload digest.
+//! 3. The stamp is verified and checked against the [`StampPolicy`].
+//! 4. The stamp outpoints are claimed in the [`StampLedger`].
+//!
+//! The `received_time` of the message is left unchanged.
+
+use std::{fmt, pin::Pin, sync::Arc};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::StatusCode;
+use prost::Message as _;
+use relay::{stamp::StampError, Message, ParseError, ParsedMessage};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::{
+    ledger::{Outpoint, StampLedger},
+    policy::{PolicyViolation, StampPolicy},
+};
+
+type FutResponse<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The default maximum length, in bytes, of a serialized message.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// The default maximum length, in bytes, of a payload.
+pub const DEFAULT_MAX_PAYLOAD_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The size limits enforced on incoming messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidationLimits {
+    /// The maximum length, in bytes, of the serialized message.
+    pub max_message_size: usize,
+    /// The maximum length, in bytes, of the payload.
+    pub max_payload_size: u64,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+        }
+    }
+}
+
+/// The reason an incoming [`Message`] was rejected.
+#[derive(Debug, Error)]
+pub enum Rejection<L: fmt::Debug + fmt::Display> {
+    /// The serialized message exceeded the maximum size.
+    #[error("message too large: {size} > {limit}")]
+    MessageTooLarge {
+        /// The length, in bytes, of the serialized message.
+        size: usize,
+        /// The maximum length, in bytes.
+        limit: usize,
+    },
+    /// The payload exceeded the maximum size.
+    #[error("payload too large: {size} > {limit}")]
+    PayloadTooLarge {
+        /// The length, in bytes, of the payload.
+        size: u64,
+        /// The maximum length, in bytes.
+        limit: u64,
+    },
+    /// The `payload_size` did not match the length of the payload.
+    #[error("payload size mismatch")]
+    PayloadSizeMismatch,
+    /// Failed to parse the message.
+    #[error("failed to parse message: {0}")]
+    Parse(ParseError),
+    /// The stamp failed verification.
+    #[error("invalid stamp: {0}")]
+    Stamp(StampError),
+    /// The stamp violated the [`StampPolicy`].
+    #[error("stamp policy violation: {0}")]
+    Policy(PolicyViolation),
+    /// A stamp outpoint was claimed by another message.
+    #[error("stamp outpoint reused")]
+    OutpointReused(Outpoint),
+    /// Failed to access the [`StampLedger`].
+    #[error("stamp ledger failure: {0}")]
+    Ledger(L),
+}
+
+impl<L> Rejection<L>
+where
+    L: fmt::Debug + fmt::Display,
+{
+    /// A short, static label identifying the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::MessageTooLarge { .. } => "message_too_large",
+            Self::PayloadTooLarge { .. } => "payload_too_large",
+            Self::PayloadSizeMismatch => "payload_size_mismatch",
+            Self::Parse(_) => "parse",
+            Self::Stamp(_) => "stamp",
+            Self::Policy(_) => "policy",
+            Self::OutpointReused(_) => "outpoint_reused",
+            Self::Ledger(_) => "ledger",
+        }
+    }
+
+    /// The HTTP status code appropriate for the rejection.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MessageTooLarge { .. } | Self::PayloadTooLarge { .. } => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            Self::Policy(PolicyViolation::InsufficientValue { .. }) => StatusCode::PAYMENT_REQUIRED,
+            Self::OutpointReused(_) => StatusCode::CONFLICT,
+            Self::Ledger(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// A [`Service`] validating incoming [`Message`]s, producing the accepted [`ParsedMessage`] or a
+/// [`Rejection`].
+pub struct MessageValidator<P, L> {
+    policy: Arc<P>,
+    ledger: Arc<L>,
+    limits: ValidationLimits,
+}
+
+impl<P, L> Clone for MessageValidator<P, L> {
+    fn clone(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            ledger: self.ledger.clone(),
+            limits: self.limits,
+        }
+    }
+}
+
+impl<P: fmt::Debug, L: fmt::Debug> fmt::Debug for MessageValidator<P, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageValidator")
+            .field("policy", &self.policy)
+            .field("ledger", &self.ledger)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+impl<P, L> MessageValidator<P, L> {
+    /// Create a new [`MessageValidator`] from a [`StampPolicy`] and a [`StampLedger`], using the
+    /// default [`ValidationLimits`].
+    pub fn new(policy: P, ledger: L) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            ledger: Arc::new(ledger),
+            limits: ValidationLimits::default(),
+        }
+    }
+
+    /// Set the [`ValidationLimits`].
+    pub fn with_limits(mut self, limits: ValidationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Get a reference to the [`StampLedger`].
+    pub fn ledger(&self) -> &L {
+        &self.ledger
+    }
+}
+
+impl<P, L> MessageValidator<P, L>
+where
+    P: StampPolicy,
+    L: StampLedger,
+{
+    /// Validate an incoming [`Message`].
+    pub fn validate(&self, message: Message) -> Result<ParsedMessage, Rejection<L::Error>> {
+        // Check limits
+        let size = message.encoded_len();
+        if size > self.limits.max_message_size {
+            return Err(Rejection::MessageTooLarge {
+                size,
+                limit: self.limits.max_message_size,
+            });
+        }
+        if message.payload_size > self.limits.max_payload_size {
+            return Err(Rejection::PayloadTooLarge {
+                size: message.payload_size,
+                limit: self.limits.max_payload_size,
+            });
+        }
+        if !message.payload.is_empty() && message.payload.len() as u64 != message.payload_size {
+            return Err(Rejection::PayloadSizeMismatch);
+        }
+
+        // Parse
+        let parsed_message = message.parse().map_err(Rejection::Parse)?;
+
+        // Verify stamp
+        let stamp = parsed_message
+            .stamp
+            .verify_stamp_detailed(
+                &parsed_message.payload_digest,
+                &parsed_message.destination_public_key,
+            )
+            .map_err(Rejection::Stamp)?;
+        self.policy
+            .check(&parsed_message, &stamp)
+            .map_err(Rejection::Policy)?;
+
+        // Claim outpoints
+        let outpoints = Outpoint::from_stamp(&stamp);
+        if let Some(reused) = self
+            .ledger
+            .claim(&parsed_message.payload_digest, &outpoints)
+            .map_err(Rejection::Ledger)?
+        {
+            return Err(Rejection::OutpointReused(reused));
+        }
+
+        Ok(parsed_message)
+    }
+}
+
+impl<P, L> Service<Message> for MessageValidator<P, L>
+where
+    P: StampPolicy + Send + Sync + 'static,
+    L: StampLedger + Send + Sync + 'static,
+    L::Error: Send + 'static,
+{
+    type Response = ParsedMessage;
+    type Error = Rejection<L::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, message: Message) -> Self::Future {
+        let result = self.validate(message);
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relay::{
+        postage::PostagePolicy,
+        secp::{PrivateKey, PublicKey, Secp256k1},
+        stamp::{Stamp, StampType},
+        EncryptionScheme,
+    };
+
+    use super::*;
+    use crate::ledger::MemoryStampLedger;
+
+    fn public_key(byte: u8) -> Vec<u8> {
+        let private_key = PrivateKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key)
+            .serialize()
+            .to_vec()
+    }
+
+    fn message(payload: Vec<u8>) -> Message {
+        Message {
+            source_public_key: public_key(1),
+            destination_public_key: public_key(2),
+            stamp: Some(Stamp {
+                stamp_type: StampType::MessageCommitment.into(),
+                stamp_outpoints: vec![],
+            }),
+            scheme: EncryptionScheme::EphemeralDh.into(),
+            payload_hmac: vec![0; 32],
+            payload_size: payload.len() as u64,
+            payload,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accept() {
+        let validator = MessageValidator::new(PostagePolicy::default(), MemoryStampLedger::new());
+        let parsed = validator.validate(message(vec![1; 64])).unwrap();
+        assert_eq!(parsed.payload, vec![1; 64]);
+    }
+
+    #[test]
+    fn reject() {
+        let limits = ValidationLimits {
+            max_payload_size: 32,
+            ..Default::default()
+        };
+        let validator = MessageValidator::new(PostagePolicy::default(), MemoryStampLedger::new())
+            .with_limits(limits);
+        let rejection = validator.validate(message(vec![1; 64])).unwrap_err();
+        assert_eq!(rejection.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let mut mismatched = message(vec![1; 16]);
+        mismatched.payload_size = 8;
+        assert!(matches!(
+            validator.validate(mismatched),
+            Err(Rejection::PayloadSizeMismatch)
+        ));
+
+        let policy = PostagePolicy {
+            base: 1_000,
+            ..Default::default()
+        };
+        let validator = MessageValidator::new(policy, MemoryStampLedger::new());
+        let rejection = validator.validate(message(vec![1; 16])).unwrap_err();
+        assert!(matches!(
+            rejection,
+            Rejection::Policy(PolicyViolation::InsufficientValue {
+                required: 1_000,
+                provided: 0,
+            })
+        ));
+        assert_eq!(rejection.status_code(), StatusCode::PAYMENT_REQUIRED);
+    }
+}
@@ -1,3 +1,79 @@
+/// The messages, which use the proto3 JSON field names.
+const MESSAGES: &[&str] = &[
+    ".relay.Header",
+    ".relay.ProfileEntry",
+    ".relay.Profile",
+    ".relay.PayloadEntry",
+    ".relay.Payload",
+    ".relay.StampOutpoints",
+    ".relay.Stamp",
+    ".relay.Message",
+    ".relay.MessageSet",
+    ".relay.PushError",
+    ".relay.PushErrors",
+    ".relay.MessagePage",
+    ".relay.PayloadPage",
+    ".relay.PostageRates",
+    ".relay.StampProof",
+    ".relay.VerificationBundle",
+];
+
+/// The fields, paired with the module in `crate::json` implementing their proto3 JSON mapping.
+const FIELDS: &[(&str, &str)] = &[
+    (".relay.ProfileEntry.body", "bytes"),
+    (".relay.Profile.timestamp", "int64"),
+    (".relay.Profile.ttl", "int64"),
+    (".relay.PayloadEntry.body", "bytes"),
+    (".relay.Payload.timestamp", "int64"),
+    (".relay.Payload.padding", "bytes"),
+    (".relay.StampOutpoints.stamp_tx", "bytes"),
+    (".relay.Stamp.stamp_type", "stamp_type"),
+    (".relay.Message.source_public_key", "bytes"),
+    (".relay.Message.destination_public_key", "bytes"),
+    (".relay.Message.received_time", "int64"),
+    (".relay.Message.payload_digest", "bytes"),
+    (".relay.Message.scheme", "encryption_scheme"),
+    (".relay.Message.salt", "bytes"),
+    (".relay.Message.payload_hmac", "bytes"),
+    (".relay.Message.payload_size", "uint64"),
+    (".relay.Message.digest_algorithm", "digest_algorithm"),
+    (".relay.Message.payload", "bytes"),
+    (".relay.MessagePage.start_time", "int64"),
+    (".relay.MessagePage.end_time", "int64"),
+    (".relay.MessagePage.start_digest", "bytes"),
+    (".relay.MessagePage.end_digest", "bytes"),
+    (".relay.PayloadPage.payloads", "repeated_bytes"),
+    (".relay.PayloadPage.start_time", "int64"),
+    (".relay.PayloadPage.end_time", "int64"),
+    (".relay.PayloadPage.start_digest", "bytes"),
+    (".relay.PayloadPage.end_digest", "bytes"),
+    (".relay.PostageRates.base", "uint64"),
+    (".relay.PostageRates.per_byte", "uint64"),
+    (".relay.PostageRates.dust_limit", "uint64"),
+    (".relay.PostageRates.max_output_value", "uint64"),
+    (".relay.StampProof.block_header", "bytes"),
+    (".relay.StampProof.branch", "repeated_bytes"),
+    (".relay.VerificationBundle.sender_auth_wrapper", "bytes"),
+];
+
 fn main() {
-    prost_build::compile_protos(&["src/proto/messaging.proto"], &["src/"]).unwrap();
+    let mut config = prost_build::Config::new();
+
+    // Derive serde traits on all models when the serde feature is enabled
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+        for message in MESSAGES {
+            config.type_attribute(message, "#[serde(default, rename_all = \"camelCase\")]");
+        }
+        for (field, module) in FIELDS {
+            config.field_attribute(
+                field,
+                format!("#[serde(with = \"crate::json::{}\")]", module),
+            );
+        }
+    }
+
+    config
+        .compile_protos(&["src/proto/messaging.proto"], &["src/"])
+        .unwrap();
 }
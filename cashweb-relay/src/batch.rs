@@ -0,0 +1,94 @@
+//! This module contains methods for verifying the stamps on batches of [`ParsedMessage`]s.
+//!
+//! A single context is shared across batches, rather than constructed for each stamp. When the
+//! `rayon` feature is enabled the batch is verified in parallel.
+
+use crate::{
+    stamp::{shared_context, StampError},
+    ParsedMessage,
+};
+use bitcoin::transaction::Transaction;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Verify the stamps on a batch of [`ParsedMessage`]s, returning the decoded transactions.
+///
+/// The results are returned in the same order as the messages.
+pub fn verify_stamps(messages: &[ParsedMessage]) -> Vec<Result<Vec<Transaction>, StampError>> {
+    let context = shared_context();
+
+    #[cfg(feature = "rayon")]
+    let iter = messages.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let iter = messages.iter();
+
+    iter.map(|message| message.verify_stamp_with_context(context))
+        .collect()
+}
+
+/// Verify the stamps on a batch of [`ParsedMessage`]s, returning the first failure paired with its
+/// index.
+pub fn verify_all_stamps(messages: &[ParsedMessage]) -> Result<(), (usize, StampError)> {
+    verify_stamps(messages)
+        .into_iter()
+        .enumerate()
+        .find_map(|(index, result)| result.err().map(|err| (index, err)))
+        .map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use bitcoin::{transaction::Input, Encodable};
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use super::*;
+    use crate::{
+        seal::MessageBuilder,
+        stamp::{create_stamp_outputs, Stamp, StampOutpoints, StampType},
+        Payload,
+    };
+
+    fn stamped_message() -> ParsedMessage {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let mut message = MessageBuilder::new(Payload::default())
+            .seal(&private_key, &public_key, &[2; 32])
+            .unwrap();
+        let payload_digest: [u8; 32] = message.payload_digest[..].try_into().unwrap();
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![Input::default()],
+            outputs: create_stamp_outputs(&public_key, &payload_digest, 0, &[1000]).unwrap(),
+            lock_time: 0,
+        };
+        message.stamp = Some(Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![StampOutpoints {
+                stamp_tx: transaction.encode_to_vec(),
+                vouts: vec![0],
+            }],
+        });
+        message.parse().unwrap()
+    }
+
+    #[test]
+    fn verify_batch() {
+        let message = stamped_message();
+        let mut unstamped = message.clone();
+        unstamped.stamp.stamp_type = StampType::None.into();
+        let messages = vec![message.clone(), unstamped, message];
+
+        let results = verify_stamps(&messages);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().len(), 1);
+        assert_eq!(results[1], Err(StampError::NoneType));
+        assert!(results[2].is_ok());
+        assert_eq!(verify_all_stamps(&messages), Err((1, StampError::NoneType)));
+        assert_eq!(verify_all_stamps(&messages[..1]), Ok(()));
+    }
+}
@@ -0,0 +1,333 @@
+//! This module contains the [`VerificationBundle`] which packages a [`Message`] with the evidence
+//! required for a third party to verify its authenticity and postage without network access, for
+//! example when resolving a dispute over a paid message.
+//!
+//! A bundle contains:
+//! * The [`Message`], whose stamp includes the raw stamp transactions.
+//! * A [`StampProof`] for each stamp transaction, pairing the header of the block it was confirmed
+//!   in with a merkle branch.
+//! * The [`AuthWrapper`] signed by the sender, typically over their address metadata, binding the
+//!   source public key to their identity.
+//!
+//! [`verify`](VerificationBundle::verify) checks that the stamp commits to the payload digest, that
+//! each merkle branch commits its stamp transaction to the block header, that each block header
+//! meets its own proof-of-work target, and that the [`AuthWrapper`] is validly signed by the source
+//! public key. Whether the blocks belong to the best chain is left to the verifier, see
+//! [`VerifiedBundle::confirmations`].
+//!
+//! The payload remains encrypted, hence its contents are not attested by the bundle.
+
+use auth_wrapper::{
+    AuthWrapper, ParseError as AuthWrapperParseError, ParsedAuthWrapper, VerifyError,
+};
+use bitcoin::{
+    header::{BlockHeader, DecodeError as HeaderDecodeError},
+    spv::{HeaderChain, MerkleProof},
+    Decodable, Encodable,
+};
+use error_code::ErrorCode;
+use prost::{DecodeError as MessageDecodeError, Message as _};
+use thiserror::Error;
+
+pub use crate::models::{StampProof, VerificationBundle};
+use crate::{
+    stamp::{StampError, VerifiedStamp},
+    Message, ParseError, ParsedMessage,
+};
+
+/// Error associated with verifying a [`VerificationBundle`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BundleError {
+    /// The message is missing.
+    #[error("missing message")]
+    MissingMessage,
+    /// Failed to parse the message.
+    #[error("failed to parse message: {0}")]
+    Parse(ParseError),
+    /// The stamp failed verification.
+    #[error("stamp error: {0}")]
+    Stamp(StampError),
+    /// The number of stamp proofs differs from the number of stamp transactions.
+    #[error("unexpected number of stamp proofs: {found} != {expected}")]
+    UnexpectedProofCount {
+        /// The number of stamp transactions.
+        expected: usize,
+        /// The number of stamp proofs.
+        found: usize,
+    },
+    /// The block header of a stamp proof could not be decoded.
+    #[error("invalid block header in stamp proof {0}")]
+    BlockHeader(usize),
+    /// The block header of a stamp proof did not meet its proof-of-work target.
+    #[error("insufficient proof-of-work in stamp proof {0}")]
+    InsufficientWork(usize),
+    /// The merkle branch of a stamp proof did not commit the transaction to the block header.
+    #[error("invalid merkle proof in stamp proof {0}")]
+    InvalidProof(usize),
+    /// Failed to decode the sender's [`AuthWrapper`].
+    #[error("failed to decode auth wrapper: {0}")]
+    AuthWrapperDecode(MessageDecodeError),
+    /// Failed to parse the sender's [`AuthWrapper`].
+    #[error("failed to parse auth wrapper: {0}")]
+    AuthWrapperParse(AuthWrapperParseError),
+    /// The signature of the sender's [`AuthWrapper`] failed verification.
+    #[error("failed to verify auth wrapper: {0}")]
+    AuthWrapperVerify(VerifyError),
+    /// The sender's [`AuthWrapper`] was not signed by the source public key.
+    #[error("auth wrapper not signed by the source")]
+    SenderMismatch,
+}
+
+impl ErrorCode for BundleError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::MissingMessage => 1601,
+            Self::Parse(_) => 1602,
+            Self::Stamp(_) => 1603,
+            Self::UnexpectedProofCount { .. } => 1604,
+            Self::BlockHeader(_) => 1605,
+            Self::InsufficientWork(_) => 1606,
+            Self::InvalidProof(_) => 1607,
+            Self::AuthWrapperDecode(_) => 1608,
+            Self::AuthWrapperParse(_) => 1609,
+            Self::AuthWrapperVerify(_) => 1610,
+            Self::SenderMismatch => 1611,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::MissingMessage => "bundle.missing_message",
+            Self::Parse(_) => "bundle.parse",
+            Self::Stamp(_) => "bundle.stamp",
+            Self::UnexpectedProofCount { .. } => "bundle.unexpected_proof_count",
+            Self::BlockHeader(_) => "bundle.block_header",
+            Self::InsufficientWork(_) => "bundle.insufficient_work",
+            Self::InvalidProof(_) => "bundle.invalid_proof",
+            Self::AuthWrapperDecode(_) => "bundle.auth_wrapper_decode",
+            Self::AuthWrapperParse(_) => "bundle.auth_wrapper_parse",
+            Self::AuthWrapperVerify(_) => "bundle.auth_wrapper_verify",
+            Self::SenderMismatch => "bundle.sender_mismatch",
+        }
+    }
+}
+
+impl StampProof {
+    /// Construct a [`StampProof`] from a block header and the [`MerkleProof`] of a transaction
+    /// within it.
+    pub fn new(block_header: &BlockHeader, merkle_proof: &MerkleProof) -> Self {
+        Self {
+            block_header: block_header.encode_to_vec(),
+            index: merkle_proof.index,
+            branch: merkle_proof
+                .branch
+                .iter()
+                .map(|sibling| sibling.to_vec())
+                .collect(),
+        }
+    }
+
+    /// Decode the block header.
+    pub fn block_header(&self) -> Result<BlockHeader, HeaderDecodeError> {
+        BlockHeader::decode(&mut self.block_header.as_slice())
+    }
+
+    /// The [`MerkleProof`], `None` if a sibling hash is not 32 bytes long.
+    pub fn merkle_proof(&self) -> Option<MerkleProof> {
+        let branch = self
+            .branch
+            .iter()
+            .map(|sibling| {
+                let mut hash = [0; 32];
+                if sibling.len() != hash.len() {
+                    return None;
+                }
+                hash.copy_from_slice(sibling);
+                Some(hash)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(MerkleProof {
+            index: self.index,
+            branch,
+        })
+    }
+}
+
+/// A [`VerificationBundle`] post-verification.
+#[derive(Debug, Clone)]
+pub struct VerifiedBundle {
+    /// The parsed message, the `payload` remains encrypted.
+    pub message: ParsedMessage,
+    /// The verified stamp, including its value.
+    pub stamp: VerifiedStamp,
+    /// The header of the block containing each stamp transaction.
+    pub block_headers: Vec<BlockHeader>,
+    /// The sender's [`AuthWrapper`].
+    pub sender_auth_wrapper: ParsedAuthWrapper,
+}
+
+impl VerifiedBundle {
+    /// The least number of confirmations of the stamp transactions within a [`HeaderChain`], `None`
+    /// if a block is not within its best chain.
+    pub fn confirmations(&self, chain: &HeaderChain) -> Option<u32> {
+        self.block_headers
+            .iter()
+            .map(|header| chain.confirmations(&header.block_hash_le()))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .min()
+    }
+}
+
+impl VerificationBundle {
+    /// Construct a [`VerificationBundle`] from a stamped [`Message`], the [`StampProof`] of each
+    /// stamp transaction, and the sender's [`AuthWrapper`].
+    pub fn new(
+        message: Message,
+        stamp_proofs: Vec<StampProof>,
+        auth_wrapper: &AuthWrapper,
+    ) -> Self {
+        let mut sender_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut sender_auth_wrapper).unwrap(); // This is safe
+        Self {
+            message: Some(message),
+            stamp_proofs,
+            sender_auth_wrapper,
+        }
+    }
+
+    /// Verify the stamp, the confirmation of each stamp transaction, and the sender's
+    /// [`AuthWrapper`], without network access.
+    pub fn verify(&self) -> Result<VerifiedBundle, BundleError> {
+        // Parse message
+        let message = self
+            .message
+            .clone()
+            .ok_or(BundleError::MissingMessage)?
+            .parse()
+            .map_err(BundleError::Parse)?;
+
+        // Verify stamp
+        let stamp = message
+            .stamp
+            .verify_stamp_detailed(&message.payload_digest, &message.destination_public_key)
+            .map_err(BundleError::Stamp)?;
+
+        // Verify stamp proofs
+        if self.stamp_proofs.len() != stamp.transactions.len() {
+            return Err(BundleError::UnexpectedProofCount {
+                expected: stamp.transactions.len(),
+                found: self.stamp_proofs.len(),
+            });
+        }
+        let mut block_headers = Vec::with_capacity(self.stamp_proofs.len());
+        for (index, (stamp_proof, transaction)) in self
+            .stamp_proofs
+            .iter()
+            .zip(&stamp.transactions)
+            .enumerate()
+        {
+            let block_header = stamp_proof
+                .block_header()
+                .map_err(|_| BundleError::BlockHeader(index))?;
+            if !block_header.check_pow().unwrap_or(false) {
+                return Err(BundleError::InsufficientWork(index));
+            }
+            let merkle_proof = stamp_proof
+                .merkle_proof()
+                .ok_or(BundleError::InvalidProof(index))?;
+            if !merkle_proof.verify(&transaction.transaction_id_le(), &block_header.merkle_root) {
+                return Err(BundleError::InvalidProof(index));
+            }
+            block_headers.push(block_header);
+        }
+
+        // Verify sender
+        let sender_auth_wrapper = AuthWrapper::decode(self.sender_auth_wrapper.as_slice())
+            .map_err(BundleError::AuthWrapperDecode)?
+            .parse()
+            .map_err(BundleError::AuthWrapperParse)?;
+        sender_auth_wrapper
+            .verify()
+            .map_err(BundleError::AuthWrapperVerify)?;
+        if sender_auth_wrapper.public_key != message.source_public_key {
+            return Err(BundleError::SenderMismatch);
+        }
+
+        Ok(VerifiedBundle {
+            message,
+            stamp,
+            block_headers,
+            sender_auth_wrapper,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::transaction::Transaction;
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use super::*;
+    use crate::{
+        seal::MessageBuilder,
+        stamp::{create_stamp_outputs, StampOutpoints},
+        Payload,
+    };
+
+    #[test]
+    fn verify_bundle() {
+        let source_private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let destination_public_key = PublicKey::from_secret_key(
+            &Secp256k1::signing_only(),
+            &SecretKey::from_slice(&[2; 32]).unwrap(),
+        );
+
+        // Seal and stamp message
+        let mut message = MessageBuilder::new(Payload::default())
+            .seal(&source_private_key, &destination_public_key, &[3; 32])
+            .unwrap();
+        let mut payload_digest = [0; 32];
+        payload_digest.copy_from_slice(&message.payload_digest);
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: create_stamp_outputs(&destination_public_key, &payload_digest, 0, &[1000])
+                .unwrap(),
+            lock_time: 0,
+        };
+        message.stamp.as_mut().unwrap().stamp_outpoints = vec![StampOutpoints {
+            stamp_tx: transaction.encode_to_vec(),
+            vouts: vec![0],
+        }];
+
+        // Mine a block containing only the stamp transaction
+        let mut block_header = BlockHeader {
+            merkle_root: transaction.transaction_id_le(),
+            bits: 0x207f_ffff,
+            ..Default::default()
+        };
+        while !block_header.check_pow().unwrap() {
+            block_header.nonce += 1;
+        }
+        let stamp_proof = StampProof::new(&block_header, &MerkleProof::default());
+
+        let auth_wrapper = AuthWrapper::sign(&source_private_key, b"metadata".to_vec());
+        let bundle =
+            VerificationBundle::new(message.clone(), vec![stamp_proof.clone()], &auth_wrapper);
+        let verified = bundle.verify().unwrap();
+        assert_eq!(verified.stamp.total_value, 1000);
+        assert_eq!(
+            verified.confirmations(&HeaderChain::new(block_header, 0)),
+            Some(1)
+        );
+
+        // The auth wrapper must be signed by the source
+        let auth_wrapper = AuthWrapper::sign(&SecretKey::from_slice(&[4; 32]).unwrap(), vec![]);
+        let bundle = VerificationBundle::new(message, vec![stamp_proof], &auth_wrapper);
+        assert_eq!(bundle.verify().unwrap_err(), BundleError::SenderMismatch);
+    }
+}
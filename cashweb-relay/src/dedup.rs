@@ -0,0 +1,153 @@
+//! This module contains the [`MessageDeduplicator`] which filters messages already seen, keyed by
+//! their payload digest.
+//!
+//! When an inbox is synchronized from several relay servers the same message may be fetched more
+//! than once. The deduplicator remembers a bounded number of payload digests, evicting the oldest
+//! first, so that each message is surfaced to the application once.
+//!
+//! The deduplicator is held in memory. Messages may instead be filtered against a
+//! [`PayloadStore`], which persists across restarts, using
+//! [`filter_stored`](MessageDeduplicator::filter_stored).
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    payload_store::{PayloadBackend, PayloadStore, PayloadStoreError},
+    ParsedMessage,
+};
+
+/// The default number of payload digests remembered.
+pub const DEFAULT_DEDUP_CAPACITY: usize = 4096;
+
+/// A bounded set of previously seen payload digests.
+#[derive(Clone, Debug)]
+pub struct MessageDeduplicator {
+    capacity: usize,
+    seen: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Default for MessageDeduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
+
+impl MessageDeduplicator {
+    /// Create a new [`MessageDeduplicator`] remembering up to `capacity` payload digests.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Insert a payload digest, returning `true` if it had not been seen.
+    ///
+    /// A deduplicator with zero capacity remembers nothing and treats every digest as new.
+    pub fn insert(&mut self, payload_digest: [u8; 32]) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.seen.insert(payload_digest) {
+            return false;
+        }
+        self.order.push_back(payload_digest);
+
+        // Evict the oldest digest
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Check whether the payload digest has been seen.
+    pub fn contains(&self, payload_digest: &[u8; 32]) -> bool {
+        self.seen.contains(payload_digest)
+    }
+
+    /// Remove the messages which have been seen, remembering the remainder.
+    ///
+    /// Duplicates within `messages` are also removed.
+    pub fn filter(&mut self, messages: Vec<ParsedMessage>) -> Vec<ParsedMessage> {
+        messages
+            .into_iter()
+            .filter(|message| self.insert(message.payload_digest))
+            .collect()
+    }
+
+    /// Remove the messages which have been seen, or whose payload is present in the
+    /// [`PayloadStore`], remembering the remainder and storing their payloads.
+    ///
+    /// Messages without a payload, whose payload is to be fetched lazily, are not stored.
+    pub fn filter_stored<B: PayloadBackend>(
+        &mut self,
+        messages: Vec<ParsedMessage>,
+        store: &PayloadStore<B>,
+    ) -> Result<Vec<ParsedMessage>, PayloadStoreError<B::Error>> {
+        let mut unseen = Vec::with_capacity(messages.len());
+        for message in messages {
+            if self.contains(&message.payload_digest) || store.contains(&message.payload_digest)? {
+                continue;
+            }
+            if !message.payload.is_empty() {
+                store.put_parsed(&message)?;
+            }
+            self.insert(message.payload_digest);
+            unseen.push(message);
+        }
+        Ok(unseen)
+    }
+
+    /// The maximum number of payload digests remembered.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of payload digests remembered.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Check whether no payload digests are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Forget all payload digests.
+    pub fn clear(&mut self) {
+        self.seen.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates() {
+        let mut deduplicator = MessageDeduplicator::new(2);
+        assert!(deduplicator.insert([1; 32]));
+        assert!(!deduplicator.insert([1; 32]));
+        assert!(deduplicator.insert([2; 32]));
+        assert_eq!(deduplicator.len(), 2);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut deduplicator = MessageDeduplicator::new(2);
+        deduplicator.insert([1; 32]);
+        deduplicator.insert([2; 32]);
+        deduplicator.insert([3; 32]);
+        assert!(!deduplicator.contains(&[1; 32]));
+        assert!(deduplicator.contains(&[3; 32]));
+        assert_eq!(deduplicator.len(), 2);
+
+        // The evicted digest is treated as new
+        assert!(deduplicator.insert([1; 32]));
+    }
+}
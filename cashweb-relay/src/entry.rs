@@ -0,0 +1,358 @@
+//! This module contains typed accessors and constructors for the [`PayloadEntry`]s of a
+//! [`Payload`].
+//!
+//! The `kind` of an entry selects its interpretation:
+//! * [`TEXT_KIND`] is a UTF-8 text body.
+//! * [`VCARD_KIND`] is a UTF-8 [`vCard`] body, sharing a contact.
+//! * [`IMAGE_KIND`] is an image, whose MIME type, beginning with `image/`, is given by the
+//!   [`CONTENT_TYPE_HEADER`].
+//! * [`ATTACHMENT_KIND`] is a file of any MIME type, optionally named by the [`FILENAME_HEADER`].
+//!
+//! Entries of any other kind are surfaced as [`TypedEntry::Unknown`] so that applications may
+//! interpret them. The size of each entry body is limited by [`EntryLimits`].
+//!
+//! [`vCard`]: https://tools.ietf.org/html/rfc6350
+
+use error_code::ErrorCode;
+use thiserror::Error;
+
+use crate::{models::Header, Payload, PayloadEntry};
+
+/// The kind of a text entry.
+pub const TEXT_KIND: &str = "text-utf8";
+
+/// The kind of a vCard entry.
+pub const VCARD_KIND: &str = "vcard";
+
+/// The kind of an image entry.
+pub const IMAGE_KIND: &str = "image";
+
+/// The kind of a file attachment entry.
+pub const ATTACHMENT_KIND: &str = "attachment";
+
+/// The header giving the MIME type of an image or attachment.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// The header giving the file name of an attachment.
+pub const FILENAME_HEADER: &str = "filename";
+
+/// Error associated with constructing or interpreting a [`PayloadEntry`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EntryError {
+    /// The body is not valid UTF-8.
+    #[error("invalid utf-8 body")]
+    InvalidUtf8,
+    /// The MIME type is missing.
+    #[error("missing content type")]
+    MissingContentType,
+    /// The MIME type is not permitted for the kind.
+    #[error("unexpected content type: {0}")]
+    UnexpectedContentType(String),
+    /// The body exceeds the limit for the kind.
+    #[error("body too large: {size} > {limit}")]
+    TooLarge {
+        /// The size of the body.
+        size: usize,
+        /// The limit for the kind.
+        limit: usize,
+    },
+}
+
+impl ErrorCode for EntryError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::InvalidUtf8 => 1401,
+            Self::MissingContentType => 1402,
+            Self::UnexpectedContentType(_) => 1403,
+            Self::TooLarge { .. } => 1404,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::InvalidUtf8 => "entry.invalid_utf8",
+            Self::MissingContentType => "entry.missing_content_type",
+            Self::UnexpectedContentType(_) => "entry.unexpected_content_type",
+            Self::TooLarge { .. } => "entry.too_large",
+        }
+    }
+}
+
+/// The maximum size of the body of each kind of entry, in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryLimits {
+    /// The maximum size of a text or vCard entry.
+    pub max_text_size: usize,
+    /// The maximum size of an image entry.
+    pub max_image_size: usize,
+    /// The maximum size of an attachment entry.
+    pub max_attachment_size: usize,
+}
+
+impl Default for EntryLimits {
+    fn default() -> Self {
+        Self {
+            max_text_size: 64 * 1024,
+            max_image_size: 5 * 1024 * 1024,
+            max_attachment_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl EntryLimits {
+    /// Set the maximum size of a text or vCard entry.
+    pub fn with_max_text_size(mut self, max_text_size: usize) -> Self {
+        self.max_text_size = max_text_size;
+        self
+    }
+
+    /// Set the maximum size of an image entry.
+    pub fn with_max_image_size(mut self, max_image_size: usize) -> Self {
+        self.max_image_size = max_image_size;
+        self
+    }
+
+    /// Set the maximum size of an attachment entry.
+    pub fn with_max_attachment_size(mut self, max_attachment_size: usize) -> Self {
+        self.max_attachment_size = max_attachment_size;
+        self
+    }
+
+    fn check(size: usize, limit: usize) -> Result<(), EntryError> {
+        if size > limit {
+            return Err(EntryError::TooLarge { size, limit });
+        }
+        Ok(())
+    }
+}
+
+/// An image or file attachment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    /// The MIME type.
+    pub mime_type: String,
+    /// The file name.
+    pub filename: Option<String>,
+    /// The contents.
+    pub data: Vec<u8>,
+}
+
+/// The interpretation of a [`PayloadEntry`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypedEntry {
+    /// A text entry.
+    Text(String),
+    /// A vCard entry.
+    VCard(String),
+    /// An image entry.
+    Image(Attachment),
+    /// A file attachment entry.
+    Attachment(Attachment),
+    /// An entry of an unknown kind.
+    Unknown(PayloadEntry),
+}
+
+impl PayloadEntry {
+    /// Construct a text entry.
+    pub fn text(text: String) -> Self {
+        Self {
+            kind: TEXT_KIND.to_string(),
+            headers: vec![],
+            body: text.into_bytes(),
+        }
+    }
+
+    /// Construct a vCard entry.
+    pub fn vcard(vcard: String) -> Self {
+        Self {
+            kind: VCARD_KIND.to_string(),
+            headers: vec![],
+            body: vcard.into_bytes(),
+        }
+    }
+
+    /// Construct an image entry, the MIME type must begin with `image/`.
+    pub fn image(mime_type: &str, data: Vec<u8>) -> Result<Self, EntryError> {
+        if !is_image(mime_type) {
+            return Err(EntryError::UnexpectedContentType(mime_type.to_string()));
+        }
+        Ok(Self {
+            kind: IMAGE_KIND.to_string(),
+            headers: vec![header(CONTENT_TYPE_HEADER, mime_type)],
+            body: data,
+        })
+    }
+
+    /// Construct a file attachment entry.
+    pub fn attachment(attachment: Attachment) -> Self {
+        let mut headers = vec![header(CONTENT_TYPE_HEADER, &attachment.mime_type)];
+        if let Some(filename) = &attachment.filename {
+            headers.push(header(FILENAME_HEADER, filename));
+        }
+        Self {
+            kind: ATTACHMENT_KIND.to_string(),
+            headers,
+            body: attachment.data,
+        }
+    }
+
+    /// Get the value of the first header with a name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// Interpret the entry according to its kind, enforcing the [`EntryLimits`].
+    pub fn to_typed(&self, limits: &EntryLimits) -> Result<TypedEntry, EntryError> {
+        let size = self.body.len();
+        let typed = match self.kind.as_str() {
+            TEXT_KIND => {
+                EntryLimits::check(size, limits.max_text_size)?;
+                TypedEntry::Text(self.utf8_body()?)
+            }
+            VCARD_KIND => {
+                EntryLimits::check(size, limits.max_text_size)?;
+                TypedEntry::VCard(self.utf8_body()?)
+            }
+            IMAGE_KIND => {
+                EntryLimits::check(size, limits.max_image_size)?;
+                let attachment = self.to_attachment()?;
+                if !is_image(&attachment.mime_type) {
+                    return Err(EntryError::UnexpectedContentType(attachment.mime_type));
+                }
+                TypedEntry::Image(attachment)
+            }
+            ATTACHMENT_KIND => {
+                EntryLimits::check(size, limits.max_attachment_size)?;
+                TypedEntry::Attachment(self.to_attachment()?)
+            }
+            _ => TypedEntry::Unknown(self.clone()),
+        };
+        Ok(typed)
+    }
+
+    fn utf8_body(&self) -> Result<String, EntryError> {
+        String::from_utf8(self.body.clone()).map_err(|_| EntryError::InvalidUtf8)
+    }
+
+    fn to_attachment(&self) -> Result<Attachment, EntryError> {
+        let mime_type = self
+            .header(CONTENT_TYPE_HEADER)
+            .ok_or(EntryError::MissingContentType)?
+            .to_string();
+        Ok(Attachment {
+            mime_type,
+            filename: self.header(FILENAME_HEADER).map(str::to_string),
+            data: self.body.clone(),
+        })
+    }
+}
+
+impl From<TypedEntry> for PayloadEntry {
+    fn from(entry: TypedEntry) -> Self {
+        match entry {
+            TypedEntry::Text(text) => Self::text(text),
+            TypedEntry::VCard(vcard) => Self::vcard(vcard),
+            TypedEntry::Image(attachment) => Self {
+                kind: IMAGE_KIND.to_string(),
+                ..Self::attachment(attachment)
+            },
+            TypedEntry::Attachment(attachment) => Self::attachment(attachment),
+            TypedEntry::Unknown(entry) => entry,
+        }
+    }
+}
+
+impl Payload {
+    /// Interpret each entry according to its kind, enforcing the [`EntryLimits`].
+    pub fn typed_entries<'a>(
+        &'a self,
+        limits: &'a EntryLimits,
+    ) -> impl Iterator<Item = Result<TypedEntry, EntryError>> + 'a {
+        self.entries.iter().map(move |entry| entry.to_typed(limits))
+    }
+
+    /// The concatenation of the text entries, separated by newlines, if any.
+    pub fn text(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == TEXT_KIND)
+            .filter_map(|entry| std::str::from_utf8(&entry.body).ok())
+            .collect();
+        if texts.is_empty() {
+            None
+        } else {
+            Some(texts.join("\n"))
+        }
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn is_image(mime_type: &str) -> bool {
+    mime_type
+        .get(..6)
+        .map(|prefix| prefix.eq_ignore_ascii_case("image/"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let limits = EntryLimits::default();
+        let attachment = Attachment {
+            mime_type: "application/pdf".to_string(),
+            filename: Some("invoice.pdf".to_string()),
+            data: vec![1, 2, 3],
+        };
+        let typed = vec![
+            TypedEntry::Text("hello".to_string()),
+            TypedEntry::VCard("BEGIN:VCARD\r\nEND:VCARD".to_string()),
+            TypedEntry::Image(Attachment {
+                mime_type: "image/png".to_string(),
+                filename: None,
+                data: vec![4, 5, 6],
+            }),
+            TypedEntry::Attachment(attachment),
+        ];
+        let payload = Payload {
+            entries: typed.iter().cloned().map(PayloadEntry::from).collect(),
+            ..Default::default()
+        };
+        let decoded: Vec<_> = payload
+            .typed_entries(&limits)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, typed);
+        assert_eq!(payload.text().as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn rejections() {
+        assert_eq!(
+            PayloadEntry::image("text/plain", vec![]),
+            Err(EntryError::UnexpectedContentType("text/plain".to_string()))
+        );
+
+        let limits = EntryLimits::default().with_max_text_size(2);
+        assert_eq!(
+            PayloadEntry::text("abc".to_string()).to_typed(&limits),
+            Err(EntryError::TooLarge { size: 3, limit: 2 })
+        );
+
+        let mut entry = PayloadEntry::image("image/jpeg", vec![]).unwrap();
+        entry.headers.clear();
+        assert_eq!(entry.to_typed(&limits), Err(EntryError::MissingContentType));
+    }
+}
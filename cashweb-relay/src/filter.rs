@@ -0,0 +1,158 @@
+//! This module contains the [`MessageFilter`] which selects messages from a [`MessagePage`] or
+//! [`MessageSet`] prior to parsing.
+//!
+//! Verifying stamps and decrypting payloads dominates the cost of syncing an inbox. Filtering on
+//! the cheap, unauthenticated fields first allows clients syncing busy inboxes to prioritize
+//! specific correspondents. Note that these fields are set by the sender, or the relay server in
+//! the case of `received_time`, and are not authenticated until the message is opened.
+
+use std::{collections::HashSet, ops::RangeInclusive};
+
+use secp256k1::key::PublicKey;
+
+use crate::{Message, MessagePage, MessageSet};
+
+/// Selects messages by source public key, received time and payload size.
+///
+/// An unset criterion matches every message.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageFilter {
+    source_public_keys: Option<HashSet<[u8; 33]>>,
+    received_time: Option<RangeInclusive<i64>>,
+    max_payload_size: Option<u64>,
+}
+
+impl MessageFilter {
+    /// Create a new [`MessageFilter`] matching every message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match messages from the source public key, this may be called repeatedly to match
+    /// messages from any of several correspondents.
+    pub fn with_source(mut self, source_public_key: &PublicKey) -> Self {
+        self.source_public_keys
+            .get_or_insert_with(HashSet::new)
+            .insert(source_public_key.serialize());
+        self
+    }
+
+    /// Only match messages whose `received_time` lies within the range, inclusive.
+    pub fn with_received_time(mut self, start: i64, end: i64) -> Self {
+        self.received_time = Some(start..=end);
+        self
+    }
+
+    /// Only match messages whose `payload_size` is at most `max_payload_size` bytes.
+    pub fn with_max_payload_size(mut self, max_payload_size: u64) -> Self {
+        self.max_payload_size = Some(max_payload_size);
+        self
+    }
+
+    /// Check whether the [`Message`] matches the filter.
+    ///
+    /// Messages with an invalid source public key never match a source filter.
+    pub fn matches(&self, message: &Message) -> bool {
+        if let Some(range) = &self.received_time {
+            if !range.contains(&message.received_time) {
+                return false;
+            }
+        }
+        if let Some(max_payload_size) = self.max_payload_size {
+            if message.payload_size > max_payload_size {
+                return false;
+            }
+        }
+        if let Some(source_public_keys) = &self.source_public_keys {
+            // Normalize to the compressed serialization
+            let source_public_key = match PublicKey::from_slice(&message.source_public_key) {
+                Ok(ok) => ok.serialize(),
+                Err(_) => return false,
+            };
+            if !source_public_keys.contains(&source_public_key) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Remove the messages not matching the filter from a [`MessagePage`].
+    ///
+    /// The page bounds are left unchanged, they describe the range queried rather than the
+    /// messages retained.
+    pub fn apply_page(&self, page: &mut MessagePage) {
+        page.messages.retain(|message| self.matches(message));
+    }
+
+    /// Remove the messages not matching the filter from a [`MessageSet`].
+    pub fn apply_set(&self, set: &mut MessageSet) {
+        set.messages.retain(|message| self.matches(message));
+    }
+
+    /// Split the messages into those matching the filter and the remainder, preserving order.
+    ///
+    /// This allows matching messages to be processed first without discarding the remainder.
+    pub fn partition(&self, messages: Vec<Message>) -> (Vec<Message>, Vec<Message>) {
+        messages
+            .into_iter()
+            .partition(|message| self.matches(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{key::SecretKey, Secp256k1};
+
+    use super::*;
+
+    fn public_key(byte: u8) -> PublicKey {
+        let private_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key)
+    }
+
+    fn message(source: u8, received_time: i64, payload_size: u64) -> Message {
+        Message {
+            source_public_key: public_key(source).serialize().to_vec(),
+            received_time,
+            payload_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn filter_page() {
+        let mut page = MessagePage {
+            messages: vec![
+                message(1, 10, 100),
+                message(2, 20, 100),
+                message(1, 30, 10_000),
+                message(1, 40, 100),
+            ],
+            ..Default::default()
+        };
+        let filter = MessageFilter::new()
+            .with_source(&public_key(1))
+            .with_received_time(0, 35)
+            .with_max_payload_size(1_000);
+        filter.apply_page(&mut page);
+        assert_eq!(page.messages, vec![message(1, 10, 100)]);
+    }
+
+    #[test]
+    fn uncompressed_source() {
+        let mut uncompressed = message(1, 0, 0);
+        uncompressed.source_public_key = public_key(1).serialize_uncompressed().to_vec();
+        let filter = MessageFilter::new().with_source(&public_key(1));
+        assert!(filter.matches(&uncompressed));
+        assert!(!filter.matches(&message(2, 0, 0)));
+        assert!(MessageFilter::new().matches(&message(2, 0, 0)));
+    }
+
+    #[test]
+    fn partition() {
+        let filter = MessageFilter::new().with_source(&public_key(2));
+        let (matching, rest) = filter.partition(vec![message(1, 1, 0), message(2, 2, 0)]);
+        assert_eq!(matching, vec![message(2, 2, 0)]);
+        assert_eq!(rest, vec![message(1, 1, 0)]);
+    }
+}
@@ -0,0 +1,552 @@
+//! This module contains the [`GroupSession`] which allows a message to be sealed once and sent to
+//! every member of a group.
+//!
+//! A group is established by distributing a random symmetric group key to each member, within a
+//! [`PayloadEntry`] of kind [`GROUP_KEY_KIND`] sealed pairwise under
+//! [`EncryptionScheme::EphemeralDhHkdf`]. Group messages are then encrypted once under
+//! [`EncryptionScheme::GroupKey`], the keys being derived from the group key and salt using
+//! HKDF-SHA256. The resulting payload, digest and HMAC are shared by the [`Message`] sent to each
+//! member, only the destination and stamp differ.
+//!
+//! The salt of a group message is prefixed by the group identifier and epoch, allowing recipients
+//! to select the session. Changes to the membership start a new epoch under a fresh group key, so
+//! that removed members cannot read later messages and added members cannot read earlier ones.
+//!
+//! Every member holds the group key, hence the source of a group message is only authenticated as
+//! being some member of the group.
+
+use std::convert::TryInto;
+
+use error_code::ErrorCode;
+use prost::{DecodeError as MessageDecodeError, Message as _};
+use ring::rand::{SecureRandom, SystemRandom};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Secp256k1,
+};
+use thiserror::Error;
+
+use crate::{
+    create_merged_key,
+    key_schedule::{DecryptError, Hkdf, KeySchedule, PayloadCipher, PayloadKeys},
+    stamp::{Stamp, StampType},
+    DigestAlgorithm, EncryptionScheme, Message, ParsedMessage, Payload, PayloadEntry, VERSION,
+};
+
+/// The kind of the [`PayloadEntry`] distributing a group key.
+pub const GROUP_KEY_KIND: &str = "group-key";
+
+/// The HKDF info label for the AES key of a group message.
+pub const GROUP_ENCRYPTION_KEY_INFO: &[u8] = b"cashweb-relay group encryption key";
+
+/// The HKDF info label for the AES IV of a group message.
+pub const GROUP_IV_INFO: &[u8] = b"cashweb-relay group iv";
+
+/// The HKDF info label for the HMAC key of a group message.
+pub const GROUP_HMAC_KEY_INFO: &[u8] = b"cashweb-relay group hmac key";
+
+/// The length of a group identifier.
+pub const GROUP_ID_LEN: usize = 32;
+
+/// The length of a group key.
+const GROUP_KEY_LEN: usize = 32;
+
+/// The length of the random portion of the salt.
+const NONCE_LEN: usize = 16;
+
+/// The length of the salt of a pairwise key distribution message.
+const SALT_LEN: usize = 32;
+
+/// The length of a serialized member public key.
+const PUBLIC_KEY_LEN: usize = 33;
+
+/// The length of the fixed portion of a key distribution body, the group identifier, epoch and
+/// group key.
+const HEADER_LEN: usize = GROUP_ID_LEN + 4 + GROUP_KEY_LEN;
+
+/// The [`KeySchedule`] of [`EncryptionScheme::GroupKey`].
+const GROUP_SCHEDULE: Hkdf = Hkdf {
+    encryption_key_info: GROUP_ENCRYPTION_KEY_INFO,
+    iv_info: GROUP_IV_INFO,
+    hmac_key_info: GROUP_HMAC_KEY_INFO,
+    cipher: PayloadCipher::Aes128Cbc,
+};
+
+/// Error associated with establishing, sealing or opening group messages.
+#[derive(Debug, Clone, Error)]
+pub enum GroupError {
+    /// Failed to generate randomness.
+    #[error("failed to generate randomness")]
+    Random,
+    /// Failed to construct a pairwise shared key.
+    #[error("shared key: {0}")]
+    SharedKey(SecpError),
+    /// The [`PayloadEntry`] is not a key distribution.
+    #[error("unexpected entry kind")]
+    UnexpectedKind,
+    /// The key distribution body was an unexpected length.
+    #[error("unexpected length key distribution")]
+    UnexpectedLengthDistribution,
+    /// Unable to parse a member public key.
+    #[error("member public key: {0}")]
+    MemberPublicKey(SecpError),
+    /// The public key is not a member of the group.
+    #[error("not a member")]
+    NotMember,
+    /// The message is not encrypted under [`EncryptionScheme::GroupKey`].
+    #[error("unexpected encryption scheme")]
+    UnexpectedScheme,
+    /// The salt does not identify this group and epoch.
+    #[error("unknown group or epoch")]
+    UnknownGroup,
+    /// Failed authentication.
+    #[error("authentication failed")]
+    Authentication,
+    /// Failed to decrypt the ciphertext [`Payload`].
+    #[error("decryption failure: {0}")]
+    Decrypt(DecryptError),
+    /// Failed to decode the plaintext [`Payload`].
+    #[error("payload decoding failure: {0}")]
+    Payload(MessageDecodeError),
+}
+
+impl ErrorCode for GroupError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Random => 1301,
+            Self::SharedKey(_) => 1302,
+            Self::UnexpectedKind => 1303,
+            Self::UnexpectedLengthDistribution => 1304,
+            Self::MemberPublicKey(_) => 1305,
+            Self::NotMember => 1306,
+            Self::UnexpectedScheme => 1307,
+            Self::UnknownGroup => 1308,
+            Self::Authentication => 1309,
+            Self::Decrypt(_) => 1310,
+            Self::Payload(_) => 1311,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Random => "group.random",
+            Self::SharedKey(_) => "group.shared_key",
+            Self::UnexpectedKind => "group.unexpected_kind",
+            Self::UnexpectedLengthDistribution => "group.unexpected_length_distribution",
+            Self::MemberPublicKey(_) => "group.member_public_key",
+            Self::NotMember => "group.not_member",
+            Self::UnexpectedScheme => "group.unexpected_scheme",
+            Self::UnknownGroup => "group.unknown_group",
+            Self::Authentication => "group.authentication",
+            Self::Decrypt(_) => "group.decrypt",
+            Self::Payload(_) => "group.payload",
+        }
+    }
+}
+
+/// Generate random bytes.
+fn random<T: Default + AsMut<[u8]>>() -> Result<T, GroupError> {
+    let mut bytes = T::default();
+    SystemRandom::new()
+        .fill(bytes.as_mut())
+        .map_err(|_| GroupError::Random)?;
+    Ok(bytes)
+}
+
+/// An unstamped [`Message`], sharing the payload with the other messages of a group.
+fn unstamped_message(
+    source_public_key: &PublicKey,
+    destination_public_key: &PublicKey,
+    scheme: EncryptionScheme,
+    salt: Vec<u8>,
+    keys: &PayloadKeys,
+    ciphertext: Vec<u8>,
+) -> Message {
+    // Calculate digest and HMAC, SHA256 is always supported
+    let payload_digest = DigestAlgorithm::Sha256.digest(&ciphertext).unwrap(); // This is safe
+    let payload_hmac = keys.payload_hmac(&payload_digest).unwrap(); // This is safe
+
+    Message {
+        source_public_key: source_public_key.serialize().to_vec(),
+        destination_public_key: destination_public_key.serialize().to_vec(),
+        received_time: 0,
+        payload_digest: payload_digest.to_vec(),
+        stamp: Some(Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![],
+        }),
+        scheme: scheme.into(),
+        salt,
+        payload_hmac: payload_hmac.to_vec(),
+        payload_size: ciphertext.len() as u64,
+        version: VERSION,
+        digest_algorithm: DigestAlgorithm::Sha256.into(),
+        payload: ciphertext,
+    }
+}
+
+/// A group identifier, epoch and symmetric group key shared by the members of a group.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GroupSession {
+    group_id: [u8; GROUP_ID_LEN],
+    epoch: u32,
+    key: [u8; GROUP_KEY_LEN],
+    members: Vec<PublicKey>,
+}
+
+impl std::fmt::Debug for GroupSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GroupSession")
+            .field("group_id", &self.group_id)
+            .field("epoch", &self.epoch)
+            .field("members", &self.members)
+            .finish()
+    }
+}
+
+impl GroupSession {
+    /// Create a new group, with a random identifier and group key, at epoch zero.
+    ///
+    /// The members should include the creator.
+    pub fn create(members: Vec<PublicKey>) -> Result<Self, GroupError> {
+        Ok(Self {
+            group_id: random()?,
+            epoch: 0,
+            key: random()?,
+            members: Self::dedup(members),
+        })
+    }
+
+    fn dedup(mut members: Vec<PublicKey>) -> Vec<PublicKey> {
+        let mut seen = Vec::with_capacity(members.len());
+        members.retain(|member| {
+            let raw = member.serialize();
+            if seen.contains(&raw) {
+                false
+            } else {
+                seen.push(raw);
+                true
+            }
+        });
+        members
+    }
+
+    /// The group identifier.
+    pub fn group_id(&self) -> &[u8; GROUP_ID_LEN] {
+        &self.group_id
+    }
+
+    /// The epoch, incremented on each change of membership.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// The members of the group.
+    pub fn members(&self) -> &[PublicKey] {
+        &self.members
+    }
+
+    /// Check whether a public key is a member of the group.
+    pub fn is_member(&self, public_key: &PublicKey) -> bool {
+        self.members.contains(public_key)
+    }
+
+    /// Start a new epoch, under a fresh group key, with a new set of members.
+    ///
+    /// The new session must be distributed to the members, see
+    /// [`distribution_messages`](GroupSession::distribution_messages).
+    pub fn rekey(&self, members: Vec<PublicKey>) -> Result<Self, GroupError> {
+        Ok(Self {
+            group_id: self.group_id,
+            epoch: self.epoch.wrapping_add(1),
+            key: random()?,
+            members: Self::dedup(members),
+        })
+    }
+
+    /// Start a new epoch with an additional member.
+    pub fn add_member(&self, member: PublicKey) -> Result<Self, GroupError> {
+        let mut members = self.members.clone();
+        members.push(member);
+        self.rekey(members)
+    }
+
+    /// Start a new epoch without a member.
+    pub fn remove_member(&self, member: &PublicKey) -> Result<Self, GroupError> {
+        let members = self
+            .members
+            .iter()
+            .filter(|existing| *existing != member)
+            .cloned()
+            .collect();
+        self.rekey(members)
+    }
+
+    /// The [`PayloadEntry`] distributing the session.
+    ///
+    /// The body is the group identifier, the big-endian epoch, the group key, then the serialized
+    /// public key of each member.
+    pub fn to_entry(&self) -> PayloadEntry {
+        let mut body = Vec::with_capacity(HEADER_LEN + PUBLIC_KEY_LEN * self.members.len());
+        body.extend_from_slice(&self.group_id);
+        body.extend_from_slice(&self.epoch.to_be_bytes());
+        body.extend_from_slice(&self.key);
+        for member in &self.members {
+            body.extend_from_slice(&member.serialize());
+        }
+        PayloadEntry {
+            kind: GROUP_KEY_KIND.to_string(),
+            headers: vec![],
+            body,
+        }
+    }
+
+    /// Parse a session from a key distribution [`PayloadEntry`], received from the source public
+    /// key.
+    ///
+    /// Distributions from sources outside of the group are rejected.
+    pub fn from_entry(
+        source_public_key: &PublicKey,
+        entry: &PayloadEntry,
+    ) -> Result<Self, GroupError> {
+        if entry.kind != GROUP_KEY_KIND {
+            return Err(GroupError::UnexpectedKind);
+        }
+        let body = &entry.body;
+        if body.len() < HEADER_LEN || (body.len() - HEADER_LEN) % PUBLIC_KEY_LEN != 0 {
+            return Err(GroupError::UnexpectedLengthDistribution);
+        }
+
+        let (group_id, rest) = body.split_at(GROUP_ID_LEN);
+        let (epoch, rest) = rest.split_at(4);
+        let (key, raw_members) = rest.split_at(GROUP_KEY_LEN);
+        let members = raw_members
+            .chunks(PUBLIC_KEY_LEN)
+            .map(PublicKey::from_slice)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(GroupError::MemberPublicKey)?;
+
+        let session = Self {
+            group_id: group_id.try_into().unwrap(), // This is safe
+            epoch: u32::from_be_bytes(epoch.try_into().unwrap()), // This is safe
+            key: key.try_into().unwrap(),           // This is safe
+            members: Self::dedup(members),
+        };
+        if !session.is_member(source_public_key) {
+            return Err(GroupError::NotMember);
+        }
+        Ok(session)
+    }
+
+    /// Seal the session to each member, other than the source, under
+    /// [`EncryptionScheme::EphemeralDhHkdf`].
+    ///
+    /// The messages are returned unstamped and in the order of the members.
+    pub fn distribution_messages(
+        &self,
+        private_key: &SecretKey,
+        timestamp: i64,
+    ) -> Result<Vec<Message>, GroupError> {
+        let source_public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), private_key);
+        let payload = Payload {
+            timestamp,
+            entries: vec![self.to_entry()],
+            ..Default::default()
+        };
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap(); // This is safe
+
+        self.members
+            .iter()
+            .filter(|member| **member != source_public_key)
+            .map(|member| {
+                let salt: [u8; SALT_LEN] = random()?;
+                let merged_key =
+                    create_merged_key(*member, &private_key[..]).map_err(GroupError::SharedKey)?;
+                // This is safe, the scheme supports key derivation
+                let keys =
+                    PayloadKeys::derive(EncryptionScheme::EphemeralDhHkdf, &merged_key, &salt)
+                        .unwrap();
+                let ciphertext = keys.encrypt(&raw_payload);
+                Ok(unstamped_message(
+                    &source_public_key,
+                    member,
+                    EncryptionScheme::EphemeralDhHkdf,
+                    salt.to_vec(),
+                    &keys,
+                    ciphertext,
+                ))
+            })
+            .collect()
+    }
+
+    /// The group identifier and epoch prefixing the salt of a group message.
+    pub fn identify(salt: &[u8]) -> Option<([u8; GROUP_ID_LEN], u32)> {
+        if salt.len() < GROUP_ID_LEN + 4 {
+            return None;
+        }
+        let group_id = salt[..GROUP_ID_LEN].try_into().unwrap(); // This is safe
+        let epoch = u32::from_be_bytes(salt[GROUP_ID_LEN..GROUP_ID_LEN + 4].try_into().unwrap()); // This is safe
+        Some((group_id, epoch))
+    }
+
+    fn keys(&self, salt: &[u8]) -> PayloadKeys {
+        GROUP_SCHEDULE.derive(&self.key, salt)
+    }
+
+    /// Encrypt a [`Payload`] once under the group key, returning a [`Message`] to each member other
+    /// than the source.
+    ///
+    /// The messages share the payload, digest and HMAC, and are returned unstamped and in the order
+    /// of the members.
+    pub fn seal(
+        &self,
+        private_key: &SecretKey,
+        payload: &Payload,
+    ) -> Result<Vec<Message>, GroupError> {
+        let source_public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), private_key);
+        if !self.is_member(&source_public_key) {
+            return Err(GroupError::NotMember);
+        }
+
+        // Construct salt
+        let nonce: [u8; NONCE_LEN] = random()?;
+        let mut salt = Vec::with_capacity(GROUP_ID_LEN + 4 + NONCE_LEN);
+        salt.extend_from_slice(&self.group_id);
+        salt.extend_from_slice(&self.epoch.to_be_bytes());
+        salt.extend_from_slice(&nonce);
+
+        // Encrypt payload
+        let keys = self.keys(&salt);
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap(); // This is safe
+        let ciphertext = keys.encrypt(&raw_payload);
+
+        let messages = self
+            .members
+            .iter()
+            .filter(|member| **member != source_public_key)
+            .map(|member| {
+                unstamped_message(
+                    &source_public_key,
+                    member,
+                    EncryptionScheme::GroupKey,
+                    salt.clone(),
+                    &keys,
+                    ciphertext.clone(),
+                )
+            })
+            .collect();
+        Ok(messages)
+    }
+
+    /// Authenticate, decrypt and decode a group message.
+    ///
+    /// The stamp is not verified, see [`ParsedMessage::verify_stamp`].
+    pub fn open(&self, message: &ParsedMessage) -> Result<Payload, GroupError> {
+        if message.scheme != EncryptionScheme::GroupKey {
+            return Err(GroupError::UnexpectedScheme);
+        }
+        if Self::identify(&message.salt) != Some((self.group_id, self.epoch)) {
+            return Err(GroupError::UnknownGroup);
+        }
+        if !self.is_member(&message.source_public_key) {
+            return Err(GroupError::NotMember);
+        }
+
+        // Authenticate HMAC payload
+        let keys = self
+            .keys(&message.salt)
+            .with_digest_algorithm(message.digest_algorithm);
+        keys.authenticate(&message.payload_digest, &message.payload_hmac)
+            .map_err(|_| GroupError::Authentication)?;
+
+        // Decrypt and decode
+        let plaintext = keys
+            .decrypt(&message.payload)
+            .map_err(GroupError::Decrypt)?;
+        let mut payload =
+            Payload::decode(&mut plaintext.as_slice()).map_err(GroupError::Payload)?;
+        payload.strip_padding();
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(byte: u8) -> (SecretKey, PublicKey) {
+        let private_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        (private_key, public_key)
+    }
+
+    #[test]
+    fn distribute_and_open() {
+        let (alice_private_key, alice) = member(1);
+        let (bob_private_key, bob) = member(2);
+        let (_, carol) = member(3);
+        let session = GroupSession::create(vec![alice, bob, carol, bob]).unwrap();
+        assert_eq!(session.members(), &[alice, bob, carol]);
+
+        // Bob receives the session pairwise
+        let distributions = session
+            .distribution_messages(&alice_private_key, 1)
+            .unwrap();
+        assert_eq!(distributions.len(), 2);
+        let opened = distributions[0]
+            .clone()
+            .parse()
+            .unwrap()
+            .open(&bob_private_key[..])
+            .unwrap();
+        let bob_session = GroupSession::from_entry(&alice, &opened.payload.entries[0]).unwrap();
+        assert_eq!(bob_session, session);
+
+        // Alice seals once for the group
+        let payload = Payload {
+            timestamp: 2,
+            ..Default::default()
+        };
+        let messages = session.seal(&alice_private_key, &payload).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload, messages[1].payload);
+        let parsed = messages[0].clone().parse().unwrap();
+        assert_eq!(bob_session.open(&parsed).unwrap(), payload);
+        assert!(parsed.open(&bob_private_key[..]).is_err());
+    }
+
+    #[test]
+    fn rekey_excludes_removed_member() {
+        let (alice_private_key, alice) = member(1);
+        let (_, bob) = member(2);
+        let session = GroupSession::create(vec![alice, bob]).unwrap();
+        let rekeyed = session.remove_member(&bob).unwrap();
+        assert_eq!(rekeyed.epoch(), 1);
+        assert_eq!(rekeyed.group_id(), session.group_id());
+        assert_eq!(rekeyed.members(), &[alice]);
+
+        let rekeyed = rekeyed.add_member(bob).unwrap();
+        let messages = rekeyed
+            .seal(&alice_private_key, &Payload::default())
+            .unwrap();
+        let parsed = messages[0].clone().parse().unwrap();
+        assert!(matches!(
+            session.open(&parsed),
+            Err(GroupError::UnknownGroup)
+        ));
+        assert!(rekeyed.open(&parsed).is_ok());
+    }
+
+    #[test]
+    fn rejects_outsider_distribution() {
+        let (_, alice) = member(1);
+        let (_, mallory) = member(4);
+        let session = GroupSession::create(vec![alice]).unwrap();
+        assert!(matches!(
+            GroupSession::from_entry(&mallory, &session.to_entry()),
+            Err(GroupError::NotMember)
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! This module contains the [`Inspection`] report, describing the outcome of each step of opening
+//! a [`ParsedMessage`].
+//!
+//! Unlike [`open`](ParsedMessage::open), which fails at the first error, inspection attempts every
+//! step. This is intended for relay server diagnostics and client-side debugging.
+
+use prost::Message as _;
+
+use crate::{
+    stamp::{StampError, VerifiedStamp},
+    DigestAlgorithm, EncryptionScheme, OpenError, ParsedMessage, Payload,
+};
+
+/// The outcome of each step of opening a [`ParsedMessage`].
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    /// The encryption scheme used.
+    pub scheme: EncryptionScheme,
+    /// The digest algorithm used.
+    pub digest_algorithm: DigestAlgorithm,
+    /// Whether the payload digest matches the payload, `None` if the payload was absent.
+    pub digest: Option<bool>,
+    /// The verified stamp, including its value.
+    pub stamp: Result<VerifiedStamp, StampError>,
+    /// The result of deriving the payload keys and authenticating the `payload_hmac`.
+    pub authentication: Result<(), OpenError>,
+    /// The decrypted and deserialized payload.
+    ///
+    /// Decryption is attempted even if authentication failed.
+    pub payload: Result<Payload, OpenError>,
+}
+
+impl Inspection {
+    /// Whether every step succeeded, an absent payload is not considered a failure.
+    pub fn is_valid(&self) -> bool {
+        self.digest != Some(false)
+            && self.stamp.is_ok()
+            && self.authentication.is_ok()
+            && self.payload.is_ok()
+    }
+
+    /// The summed value of the stamp outputs, in satoshis, if the stamp was valid.
+    pub fn stamp_value(&self) -> Option<u64> {
+        self.stamp.as_ref().ok().map(|stamp| stamp.total_value)
+    }
+}
+
+impl ParsedMessage {
+    /// Attempt each step of opening the message, reporting the outcome of each rather than
+    /// failing at the first error.
+    pub fn inspect(&self, private_key: &[u8]) -> Inspection {
+        // Check digest
+        let digest = if self.payload.is_empty() {
+            None
+        } else {
+            Some(
+                self.digest_algorithm
+                    .digest(&self.payload)
+                    .map(|payload_digest| payload_digest == self.payload_digest)
+                    .unwrap_or(false),
+            )
+        };
+
+        // Verify stamp
+        let stamp = self
+            .stamp
+            .verify_stamp_detailed(&self.payload_digest, &self.destination_public_key);
+
+        let (authentication, payload) = match self.create_payload_keys(private_key) {
+            Ok(keys) => {
+                // Authenticate HMAC payload
+                let authentication = keys
+                    .authenticate(&self.payload_digest, &self.payload_hmac)
+                    .map_err(|_| OpenError::Authentication);
+
+                // Decrypt and decode
+                let payload = keys
+                    .decrypt(&self.payload)
+                    .map_err(OpenError::Decrypt)
+                    .and_then(|plaintext| {
+                        Payload::decode(&mut plaintext.as_slice()).map_err(OpenError::Payload)
+                    })
+                    .map(|mut payload| {
+                        payload.strip_padding();
+                        payload
+                    });
+                (authentication, payload)
+            }
+            Err(err) => (Err(err.clone()), Err(err)),
+        };
+
+        Inspection {
+            scheme: self.scheme,
+            digest_algorithm: self.digest_algorithm,
+            digest,
+            stamp,
+            authentication,
+            payload,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use super::*;
+    use crate::{
+        create_merged_key,
+        key_schedule::PayloadKeys,
+        stamp::{Stamp, StampType},
+    };
+
+    fn sealed(private_key: &SecretKey, destination_public_key: PublicKey) -> ParsedMessage {
+        let secp = Secp256k1::signing_only();
+        let salt = vec![3; 32];
+        let merged_key = create_merged_key(destination_public_key, &private_key[..]).unwrap();
+        let keys = PayloadKeys::derive(EncryptionScheme::EphemeralDh, &merged_key, &salt).unwrap();
+
+        let payload = Payload {
+            timestamp: 1,
+            ..Default::default()
+        };
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+        let ciphertext = keys.encrypt(&raw_payload);
+        let payload_digest = DigestAlgorithm::Sha256.digest(&ciphertext).unwrap();
+
+        ParsedMessage {
+            version: 1,
+            source_public_key: PublicKey::from_secret_key(&secp, private_key),
+            destination_public_key,
+            received_time: 0,
+            payload_digest,
+            digest_algorithm: DigestAlgorithm::Sha256,
+            stamp: Stamp {
+                stamp_type: StampType::MessageCommitment.into(),
+                stamp_outpoints: vec![],
+            },
+            scheme: EncryptionScheme::EphemeralDh,
+            salt,
+            payload_hmac: keys.payload_hmac(&payload_digest).unwrap(),
+            payload_size: ciphertext.len() as u64,
+            payload: ciphertext,
+        }
+    }
+
+    #[test]
+    fn inspect_reports_each_step() {
+        let secp = Secp256k1::signing_only();
+        let source_private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let destination_private_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key = PublicKey::from_secret_key(&secp, &destination_private_key);
+        let mut message = sealed(&source_private_key, destination_public_key);
+
+        let inspection = message.inspect(&destination_private_key[..]);
+        assert!(inspection.is_valid());
+        assert_eq!(inspection.digest, Some(true));
+        assert_eq!(inspection.stamp_value(), Some(0));
+        assert_eq!(inspection.payload.unwrap().timestamp, 1);
+
+        // Tampered HMAC still decrypts
+        message.payload_hmac = [0; 32];
+        let inspection = message.inspect(&destination_private_key[..]);
+        assert!(!inspection.is_valid());
+        assert!(matches!(
+            inspection.authentication,
+            Err(OpenError::Authentication)
+        ));
+        assert!(inspection.payload.is_ok());
+
+        // Missing payload
+        message.payload = vec![];
+        let inspection = message.inspect(&destination_private_key[..]);
+        assert_eq!(inspection.digest, None);
+        assert!(inspection.payload.is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! This module contains the canonical [`proto3 JSON mapping`] of the protobuf models.
+//!
+//! When the `serde` feature is enabled the models implement [`Serialize`](serde::Serialize) and
+//! [`Deserialize`](serde::Deserialize) following the mapping: field names are `lowerCamelCase`,
+//! `bytes` are base64 encoded, 64-bit integers are strings and enums are their variant names.
+//! Deserialization additionally accepts URL-safe base64, numeric 64-bit integers and numeric enums.
+//!
+//! The mapping is implemented by the helpers of [`proto_json`], the `json` feature re-exports its
+//! [`to_json`] and [`from_json`] helpers.
+//!
+//! [`proto3 JSON mapping`]: https://developers.google.com/protocol-buffers/docs/proto3#json
+
+pub(crate) use proto_json::{bytes, int64, repeated_bytes, uint64};
+#[cfg(feature = "json")]
+pub use proto_json::{from_json, to_json};
+
+use proto_json::{deserialize_enum, serialize_enum};
+use serde::{Deserializer, Serializer};
+
+pub(crate) mod stamp_type {
+    use super::*;
+    use crate::models::stamp::StampType;
+
+    pub(crate) fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = StampType::from_i32(*value).map(|variant| match variant {
+            StampType::None => "None",
+            StampType::MessageCommitment => "MessageCommitment",
+        });
+        serialize_enum(*value, name, serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "None" => Some(StampType::None as i32),
+            "MessageCommitment" => Some(StampType::MessageCommitment as i32),
+            _ => None,
+        })
+    }
+}
+
+pub(crate) mod encryption_scheme {
+    use super::*;
+    use crate::models::message::EncryptionScheme;
+
+    pub(crate) fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = EncryptionScheme::from_i32(*value).map(|variant| match variant {
+            EncryptionScheme::None => "None",
+            EncryptionScheme::EphemeralDh => "EphemeralDH",
+            EncryptionScheme::EphemeralDhHkdf => "EphemeralDH_HKDF",
+            EncryptionScheme::GroupKey => "GroupKey",
+            EncryptionScheme::EphemeralDhAes256Gcm => "EphemeralDH_AES256_GCM",
+            EncryptionScheme::EphemeralDhChacha20Poly1305 => "EphemeralDH_CHACHA20_POLY1305",
+        });
+        serialize_enum(*value, name, serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "None" => Some(EncryptionScheme::None as i32),
+            "EphemeralDH" => Some(EncryptionScheme::EphemeralDh as i32),
+            "EphemeralDH_HKDF" => Some(EncryptionScheme::EphemeralDhHkdf as i32),
+            "GroupKey" => Some(EncryptionScheme::GroupKey as i32),
+            "EphemeralDH_AES256_GCM" => Some(EncryptionScheme::EphemeralDhAes256Gcm as i32),
+            "EphemeralDH_CHACHA20_POLY1305" => {
+                Some(EncryptionScheme::EphemeralDhChacha20Poly1305 as i32)
+            }
+            _ => None,
+        })
+    }
+}
+
+pub(crate) mod digest_algorithm {
+    use super::*;
+    use crate::models::message::DigestAlgorithm;
+
+    pub(crate) fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = DigestAlgorithm::from_i32(*value).map(|variant| match variant {
+            DigestAlgorithm::Sha256 => "SHA256",
+            DigestAlgorithm::Blake3 => "BLAKE3",
+        });
+        serialize_enum(*value, name, serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        deserialize_enum(deserializer, |name| match name {
+            "SHA256" => Some(DigestAlgorithm::Sha256 as i32),
+            "BLAKE3" => Some(DigestAlgorithm::Blake3 as i32),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DigestAlgorithm, Message, Payload};
+
+    #[test]
+    fn derive_serde() {
+        let message = Message {
+            source_public_key: vec![2; 33],
+            received_time: 1_600_000_000_000,
+            payload: b"payload".to_vec(),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert!(value.get("sourcePublicKey").is_some());
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), message);
+
+        let payload = Payload {
+            timestamp: 1_600_000_000_000,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(serde_json::from_value::<Payload>(value).unwrap(), payload);
+    }
+
+    #[test]
+    fn digest_algorithm() {
+        let message = Message {
+            digest_algorithm: DigestAlgorithm::Blake3.into(),
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["digestAlgorithm"], "BLAKE3");
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), message);
+
+        // Unknown values are preserved as numbers, unknown names are rejected
+        let message = Message {
+            digest_algorithm: 7,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["digestAlgorithm"], 7);
+        assert_eq!(serde_json::from_value::<Message>(value).unwrap(), message);
+        let value = serde_json::json!({ "digestAlgorithm": "SHA3" });
+        assert!(serde_json::from_value::<Message>(value).is_err());
+    }
+}
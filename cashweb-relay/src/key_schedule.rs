@@ -0,0 +1,415 @@
+//! This module contains the [`PayloadKeys`] used to encrypt and authenticate a [`Payload`], and
+//! their derivation under each [`EncryptionScheme`].
+//!
+//! * [`EncryptionScheme::EphemeralDh`] splits the shared key `HMAC(sdG, salt)` into the AES key
+//!   and IV, the shared key itself is used as the HMAC key.
+//! * [`EncryptionScheme::EphemeralDhHkdf`] derives the AES key, IV and HMAC key independently
+//!   using HKDF-SHA256, with `sdG` as the input keying material, the `salt` as the salt, and
+//!   distinct info labels.
+//! * [`EncryptionScheme::EphemeralDhAes256Gcm`] and
+//!   [`EncryptionScheme::EphemeralDhChacha20Poly1305`] derive the keys as
+//!   [`EncryptionScheme::EphemeralDhHkdf`], but under a distinct encryption key label, and encrypt
+//!   using the corresponding AEAD [`PayloadCipher`]. The first 12 bytes of the IV form the nonce.
+//! * [`EncryptionScheme::GroupKey`] derives the keys from a symmetric group key rather than `sdG`,
+//!   hence has no schedule here, see [`group`](crate::group).
+//!
+//! The AEAD nonce is fixed by the keys, which are unique to each `salt`. Hence a salt must never be
+//! reused with the same `sdG`.
+//!
+//! Each derivation is a [`KeySchedule`], [`schedule`] returns the schedule of an
+//! [`EncryptionScheme`]. Alternative derivations, such as HKDF under different labels, may be
+//! supplied to [`PayloadKeys::derive_with`].
+//!
+//! The `payload_hmac` is calculated using the [`DigestAlgorithm`] of the message, SHA-256 unless
+//! set using [`PayloadKeys::with_digest_algorithm`]. Key derivation always uses HMAC-SHA256.
+//!
+//! [`Payload`]: crate::Payload
+
+use std::convert::TryInto;
+
+use aes::{
+    block_cipher::generic_array::{typenum::U16, GenericArray},
+    Aes128,
+};
+use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+use ring::aead::{
+    Aad, Algorithm, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, CHACHA20_POLY1305, NONCE_LEN,
+};
+use secp256k1::key::PublicKey;
+use thiserror::Error;
+
+use crate::{hash, DigestAlgorithm, EncryptionScheme, InvalidHmac, UnsupportedDigestAlgorithm};
+
+pub(crate) type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+
+/// The HKDF info label for the AES key.
+pub const ENCRYPTION_KEY_INFO: &[u8] = b"cashweb-relay encryption key";
+
+/// The HKDF info label for the AES IV.
+pub const IV_INFO: &[u8] = b"cashweb-relay iv";
+
+/// The HKDF info label for the HMAC key.
+pub const HMAC_KEY_INFO: &[u8] = b"cashweb-relay hmac key";
+
+/// The HKDF info label for the AES-256-GCM key.
+pub const AES_256_GCM_KEY_INFO: &[u8] = b"cashweb-relay aes-256-gcm key";
+
+/// The HKDF info label for the ChaCha20-Poly1305 key.
+pub const CHACHA20_POLY1305_KEY_INFO: &[u8] = b"cashweb-relay chacha20-poly1305 key";
+
+/// The encryption scheme does not support key derivation.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unsupported encryption scheme")]
+pub struct UnsupportedScheme;
+
+/// The ciphertext could not be decrypted, either the padding was invalid or, under an AEAD
+/// cipher, the authentication tag did not match.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid ciphertext")]
+pub struct DecryptError;
+
+/// The cipher used to encrypt a payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadCipher {
+    /// AES-128-CBC with PKCS7 padding, keyed by the first 16 bytes of the encryption key.
+    Aes128Cbc,
+    /// AES-256-GCM, with an empty associated data.
+    Aes256Gcm,
+    /// ChaCha20-Poly1305, with an empty associated data.
+    ChaCha20Poly1305,
+}
+
+impl Default for PayloadCipher {
+    fn default() -> Self {
+        Self::Aes128Cbc
+    }
+}
+
+impl PayloadCipher {
+    /// The AEAD algorithm, if the cipher is an AEAD.
+    fn aead_algorithm(self) -> Option<&'static Algorithm> {
+        match self {
+            Self::Aes128Cbc => None,
+            Self::Aes256Gcm => Some(&AES_256_GCM),
+            Self::ChaCha20Poly1305 => Some(&CHACHA20_POLY1305),
+        }
+    }
+}
+
+/// HKDF-SHA256 extract.
+fn hkdf_extract(salt: &[u8], input_key_material: &[u8]) -> [u8; 32] {
+    hash::hmac_sha256(salt, input_key_material)
+}
+
+/// HKDF-SHA256 expand, limited to a single block of output.
+fn hkdf_expand(pseudorandom_key: &[u8; 32], info: &[u8]) -> [u8; 32] {
+    let mut block_input = Vec::with_capacity(info.len() + 1);
+    block_input.extend_from_slice(info);
+    block_input.push(1);
+    hash::hmac_sha256(pseudorandom_key, &block_input)
+}
+
+/// Derives the [`PayloadKeys`] from the merged key, `sdG`, and salt.
+pub trait KeySchedule {
+    /// Derive the keys from the serialized merged key and salt.
+    fn derive(&self, raw_merged_key: &[u8], salt: &[u8]) -> PayloadKeys;
+}
+
+/// The schedule of [`EncryptionScheme::EphemeralDh`], splitting the shared key `HMAC(sdG, salt)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitSharedKey;
+
+impl KeySchedule for SplitSharedKey {
+    fn derive(&self, raw_merged_key: &[u8], salt: &[u8]) -> PayloadKeys {
+        let shared_key = hash::hmac_sha256(raw_merged_key, salt);
+        PayloadKeys::from_shared_key(&shared_key)
+    }
+}
+
+/// A schedule deriving each key using HKDF-SHA256 under distinct info labels.
+///
+/// The default labels and cipher are those of [`EncryptionScheme::EphemeralDhHkdf`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hkdf {
+    /// The info label for the encryption key.
+    pub encryption_key_info: &'static [u8],
+    /// The info label for the IV.
+    pub iv_info: &'static [u8],
+    /// The info label for the HMAC key.
+    pub hmac_key_info: &'static [u8],
+    /// The cipher the keys are used with.
+    pub cipher: PayloadCipher,
+}
+
+impl Default for Hkdf {
+    fn default() -> Self {
+        Self {
+            encryption_key_info: ENCRYPTION_KEY_INFO,
+            iv_info: IV_INFO,
+            hmac_key_info: HMAC_KEY_INFO,
+            cipher: PayloadCipher::Aes128Cbc,
+        }
+    }
+}
+
+impl KeySchedule for Hkdf {
+    fn derive(&self, raw_merged_key: &[u8], salt: &[u8]) -> PayloadKeys {
+        let pseudorandom_key = hkdf_extract(salt, raw_merged_key);
+        let iv = hkdf_expand(&pseudorandom_key, self.iv_info);
+        PayloadKeys {
+            cipher: self.cipher,
+            encryption_key: hkdf_expand(&pseudorandom_key, self.encryption_key_info),
+            iv: iv[..16].try_into().unwrap(), // This is safe
+            hmac_key: hkdf_expand(&pseudorandom_key, self.hmac_key_info),
+            digest_algorithm: DigestAlgorithm::Sha256,
+        }
+    }
+}
+
+/// The [`KeySchedule`] of an [`EncryptionScheme`], if it supports key derivation.
+pub fn schedule(scheme: EncryptionScheme) -> Result<Box<dyn KeySchedule>, UnsupportedScheme> {
+    match scheme {
+        EncryptionScheme::None | EncryptionScheme::GroupKey => Err(UnsupportedScheme),
+        EncryptionScheme::EphemeralDh => Ok(Box::new(SplitSharedKey)),
+        EncryptionScheme::EphemeralDhHkdf => Ok(Box::new(Hkdf::default())),
+        EncryptionScheme::EphemeralDhAes256Gcm => Ok(Box::new(Hkdf {
+            encryption_key_info: AES_256_GCM_KEY_INFO,
+            cipher: PayloadCipher::Aes256Gcm,
+            ..Default::default()
+        })),
+        EncryptionScheme::EphemeralDhChacha20Poly1305 => Ok(Box::new(Hkdf {
+            encryption_key_info: CHACHA20_POLY1305_KEY_INFO,
+            cipher: PayloadCipher::ChaCha20Poly1305,
+            ..Default::default()
+        })),
+    }
+}
+
+/// The keys used to encrypt and authenticate a payload.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PayloadKeys {
+    /// The cipher used to encrypt the payload.
+    pub cipher: PayloadCipher,
+    /// The encryption key, AES-128-CBC uses only the first 16 bytes.
+    pub encryption_key: [u8; 32],
+    /// The AES-128-CBC IV, the AEAD ciphers use the first 12 bytes as the nonce.
+    pub iv: [u8; 16],
+    /// The key used to calculate the `payload_hmac`.
+    pub hmac_key: [u8; 32],
+    /// The digest algorithm used to calculate the `payload_hmac`.
+    pub digest_algorithm: DigestAlgorithm,
+}
+
+impl std::fmt::Debug for PayloadKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PayloadKeys")
+            .field("cipher", &self.cipher)
+            .field("digest_algorithm", &self.digest_algorithm)
+            .finish()
+    }
+}
+
+impl PayloadKeys {
+    /// Derive the keys from the merged key, `sdG`, and salt under an [`EncryptionScheme`].
+    pub fn derive(
+        scheme: EncryptionScheme,
+        merged_key: &PublicKey,
+        salt: &[u8],
+    ) -> Result<Self, UnsupportedScheme> {
+        Ok(Self::derive_with(&*schedule(scheme)?, merged_key, salt))
+    }
+
+    /// Derive the keys from the merged key, `sdG`, and salt using a [`KeySchedule`].
+    pub fn derive_with<K: KeySchedule + ?Sized>(
+        schedule: &K,
+        merged_key: &PublicKey,
+        salt: &[u8],
+    ) -> Self {
+        schedule.derive(&merged_key.serialize(), salt)
+    }
+
+    /// Split a shared key, `HMAC(sdG, salt)`, as in [`EncryptionScheme::EphemeralDh`].
+    pub fn from_shared_key(shared_key: &[u8; 32]) -> Self {
+        Self {
+            cipher: PayloadCipher::Aes128Cbc,
+            encryption_key: *shared_key,
+            iv: shared_key[16..].try_into().unwrap(), // This is safe
+            hmac_key: *shared_key,
+            digest_algorithm: DigestAlgorithm::Sha256,
+        }
+    }
+
+    /// Derive the keys using HKDF-SHA256, as in [`EncryptionScheme::EphemeralDhHkdf`].
+    pub fn hkdf(input_key_material: &[u8], salt: &[u8]) -> Self {
+        Hkdf::default().derive(input_key_material, salt)
+    }
+
+    /// Set the [`DigestAlgorithm`] used to calculate the `payload_hmac`.
+    pub fn with_digest_algorithm(mut self, digest_algorithm: DigestAlgorithm) -> Self {
+        self.digest_algorithm = digest_algorithm;
+        self
+    }
+
+    /// Set the [`PayloadCipher`].
+    pub fn with_cipher(mut self, cipher: PayloadCipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// The AES-128-CBC cipher, regardless of the [`PayloadCipher`].
+    pub(crate) fn cipher(&self) -> Aes128Cbc {
+        let key = GenericArray::<u8, U16>::from_slice(&self.encryption_key[..16]);
+        let iv = GenericArray::<u8, U16>::from_slice(&self.iv);
+        Aes128Cbc::new_var(&key, &iv).unwrap() // This is safe
+    }
+
+    /// The AEAD key and nonce.
+    fn aead(&self, algorithm: &'static Algorithm) -> (LessSafeKey, Nonce) {
+        let key = UnboundKey::new(algorithm, &self.encryption_key).unwrap(); // This is safe
+        let nonce = Nonce::assume_unique_for_key(self.iv[..NONCE_LEN].try_into().unwrap()); // This is safe
+        (LessSafeKey::new(key), nonce)
+    }
+
+    /// Encrypt a serialized payload.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        match self.cipher.aead_algorithm() {
+            Some(algorithm) => {
+                let (key, nonce) = self.aead(algorithm);
+                let mut in_out = plaintext.to_vec();
+                key.seal_in_place_append_tag(nonce, Aad::empty(), &mut in_out)
+                    .unwrap(); // This is safe
+                in_out
+            }
+            None => self.cipher().encrypt_vec(plaintext),
+        }
+    }
+
+    /// Decrypt an encrypted payload.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        let mut in_out = ciphertext.to_vec();
+        let plaintext_len = self.decrypt_in_place(&mut in_out)?.len();
+        in_out.truncate(plaintext_len);
+        Ok(in_out)
+    }
+
+    /// Decrypt an encrypted payload in place, returning the plaintext.
+    pub fn decrypt_in_place<'a>(&self, ciphertext: &'a mut [u8]) -> Result<&'a [u8], DecryptError> {
+        match self.cipher.aead_algorithm() {
+            Some(algorithm) => {
+                let (key, nonce) = self.aead(algorithm);
+                let plaintext = key
+                    .open_in_place(nonce, Aad::empty(), ciphertext)
+                    .map_err(|_| DecryptError)?;
+                Ok(plaintext)
+            }
+            None => self.cipher().decrypt(ciphertext).map_err(|_| DecryptError),
+        }
+    }
+
+    /// Calculate the `payload_hmac` of a payload digest.
+    pub fn payload_hmac(
+        &self,
+        payload_digest: &[u8],
+    ) -> Result<[u8; 32], UnsupportedDigestAlgorithm> {
+        self.digest_algorithm.hmac(&self.hmac_key, payload_digest)
+    }
+
+    /// Authenticate the `payload_hmac` against the payload digest.
+    ///
+    /// Authentication fails if the digest algorithm is unsupported.
+    pub fn authenticate(
+        &self,
+        payload_digest: &[u8],
+        payload_hmac: &[u8],
+    ) -> Result<(), InvalidHmac> {
+        match self.payload_hmac(payload_digest) {
+            Ok(expected) if expected[..] == payload_hmac[..] => Ok(()),
+            _ => Err(InvalidHmac),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_key_split() {
+        let shared_key = [7; 32];
+        let keys = PayloadKeys::from_shared_key(&shared_key);
+        let ciphertext = keys.encrypt(b"hello");
+        assert_eq!(ciphertext, crate::encrypt_payload(&shared_key, b"hello"));
+        assert_eq!(keys.decrypt(&ciphertext).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn hkdf_separates_keys() {
+        let keys = PayloadKeys::hkdf(&[2; 33], &[3; 32]);
+        assert_ne!(keys.encryption_key[..16], keys.iv[..]);
+        assert_ne!(keys.encryption_key[..], keys.hmac_key[..]);
+        let ciphertext = keys.encrypt(b"hello");
+        assert_eq!(keys.decrypt(&ciphertext).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn schedules() {
+        let keys = schedule(EncryptionScheme::EphemeralDhHkdf)
+            .unwrap()
+            .derive(&[2; 33], &[3; 32]);
+        assert_eq!(keys, PayloadKeys::hkdf(&[2; 33], &[3; 32]));
+        assert!(schedule(EncryptionScheme::None).is_err());
+
+        let relabelled = Hkdf {
+            iv_info: b"alternative iv",
+            ..Default::default()
+        };
+        let relabelled_keys = relabelled.derive(&[2; 33], &[3; 32]);
+        assert_eq!(relabelled_keys.encryption_key, keys.encryption_key);
+        assert_ne!(relabelled_keys.iv, keys.iv);
+    }
+
+    #[test]
+    fn aead_ciphers() {
+        let cbc_keys = PayloadKeys::hkdf(&[2; 33], &[3; 32]);
+        for scheme in [
+            EncryptionScheme::EphemeralDhAes256Gcm,
+            EncryptionScheme::EphemeralDhChacha20Poly1305,
+        ]
+        .iter()
+        {
+            let keys = schedule(*scheme).unwrap().derive(&[2; 33], &[3; 32]);
+            assert_ne!(keys.encryption_key, cbc_keys.encryption_key);
+            assert_eq!(keys.hmac_key, cbc_keys.hmac_key);
+
+            let mut ciphertext = keys.encrypt(b"hello");
+            assert_eq!(ciphertext.len(), 5 + 16);
+            assert_eq!(keys.decrypt(&ciphertext).unwrap(), b"hello");
+            assert_eq!(
+                keys.decrypt_in_place(&mut ciphertext.clone()).unwrap(),
+                b"hello"
+            );
+
+            // Tampering fails authentication
+            ciphertext[0] ^= 1;
+            assert_eq!(keys.decrypt(&ciphertext), Err(DecryptError));
+        }
+    }
+
+    #[test]
+    fn hkdf_rfc5869() {
+        // Test case 1 of RFC 5869, truncated to a single block
+        let input_key_material = [0x0b; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let pseudorandom_key = hkdf_extract(&salt, &input_key_material);
+        let okm = hkdf_expand(&pseudorandom_key, &info);
+        assert_eq!(
+            okm,
+            [
+                0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+                0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+                0xec, 0xc4, 0xc5, 0xbf,
+            ]
+        );
+    }
+}
@@ -8,26 +8,62 @@
 //! `cashweb-relay` is a library providing serialization/deserialization, encryption/decryption/verification of
 //! structures in the [`Relay Protocol`].
 //!
+//! Unknown fields are skipped during decoding, so messages produced under later revisions of the
+//! protocol still decode. Parsing then rejects messages whose version is not supported with
+//! [`ParseError::UnsupportedVersion`].
+//!
+//! The payload digest and HMAC are calculated using the [`DigestAlgorithm`] given by the message,
+//! SHA-256 by default. BLAKE3 is supported when the `blake3` feature is enabled.
+//!
+//! Payloads are encrypted using AES-128-CBC, AES-256-GCM or ChaCha20-Poly1305 according to the
+//! [`EncryptionScheme`] of the message, see the [`key_schedule`] module.
+//!
+//! Once opened, the entries of a [`Payload`] may be interpreted as text, vCards, images or file
+//! attachments using the accessors in the [`entry`] module.
+//!
+//! Messages are constructed from a [`Payload`] using the [`MessageBuilder`](seal::MessageBuilder),
+//! and their stamp transactions funded from unspent outputs using the
+//! [`StampBuilder`](stamp::StampBuilder).
+//!
+//! A message may be exported, along with proofs of its stamp transactions and the sender's
+//! authorization wrapper, as a [`VerificationBundle`](bundle::VerificationBundle) which can be
+//! verified offline.
+//!
+//! The server-supplied `received_time` of a parsed message may be checked against the local time
+//! and the lock times of its stamp transactions using a
+//! [`ReceivedTimePolicy`](received_time::ReceivedTimePolicy).
+//!
+//! Payloads may be persisted apart from their messages, content-addressed by payload digest, in a
+//! [`PayloadStore`](payload_store::PayloadStore) which verifies their digest on read.
+//!
 //! [`Relay Protocol`]: https://github.com/cashweb/specifications/blob/master/authorization-wrapper/specification.mediawiki
 
+pub mod batch;
+pub mod bundle;
+pub mod dedup;
+pub mod entry;
+pub mod filter;
+pub mod group;
+pub mod inspect;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod key_schedule;
 #[allow(unreachable_pub, missing_docs)]
 mod models;
+pub mod padding;
+pub mod payload_store;
+pub mod postage;
+pub mod received_time;
+pub mod seal;
 pub mod stamp;
 
 use std::convert::TryInto;
 
-use aes::{
-    block_cipher::generic_array::{typenum::U16, GenericArray},
-    Aes128,
-};
+use auth_wrapper::hash;
 use bitcoin::transaction::Transaction;
-use block_modes::{block_padding::Pkcs7, BlockMode, BlockModeError, Cbc};
+use block_modes::BlockMode;
 use prost::{DecodeError as MessageDecodeError, Message as _};
-use ring::{
-    digest::{digest, SHA256},
-    hmac::{sign, Key as HmacKey, HMAC_SHA256},
-};
-use secp256k1::{key::PublicKey, Error as SecpError, Secp256k1};
+use secp256k1::{key::PublicKey, Error as SecpError, Secp256k1, Signing, Verification};
 use thiserror::Error;
 
 pub mod secp {
@@ -40,23 +76,45 @@ pub mod secp {
 }
 
 pub use crate::models::{
-    message::EncryptionScheme, Message, MessagePage, MessageSet, Payload, PayloadPage, Profile,
+    message::{DigestAlgorithm, EncryptionScheme},
+    Message, MessagePage, MessageSet, Payload, PayloadEntry, PayloadPage, PostageRates, Profile,
 };
+pub use auth_wrapper::UnsupportedDigestAlgorithm;
+pub use error_code::ErrorCode;
+use key_schedule::{DecryptError, PayloadKeys};
 use stamp::*;
 
-type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+/// The latest version of the relay protocol supported.
+pub const VERSION: u32 = 1;
+
+/// The earliest version of the relay protocol supported.
+pub const MIN_VERSION: u32 = 1;
+
+/// Interpret the version field, an unset version is interpreted as version 1.
+#[inline]
+pub fn effective_version(version: u32) -> u32 {
+    if version == 0 {
+        1
+    } else {
+        version
+    }
+}
 
 /// Represents a [Message](struct.Message.html) post-parsing.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParsedMessage {
+    /// The version of the relay protocol.
+    pub version: u32,
     /// The source public key.
     pub source_public_key: PublicKey,
     /// The destinations public key.
     pub destination_public_key: PublicKey,
     /// Maleable server time.
     pub received_time: i64,
-    /// The SHA-256 digest of the payload.
+    /// The digest of the payload.
     pub payload_digest: [u8; 32],
+    /// The digest algorithm used for the `payload_digest` and `payload_hmac`.
+    pub digest_algorithm: DigestAlgorithm,
     /// The stamp attached to the message.
     pub stamp: Stamp,
     /// The encryption scheme used on the serialized `Payload` to produce the `payload` field.
@@ -84,6 +142,8 @@ impl ParsedMessage {
             salt: self.salt,
             payload_hmac: self.payload_hmac.to_vec(),
             payload_size: self.payload_size,
+            version: self.version,
+            digest_algorithm: self.digest_algorithm.into(),
             payload: self.payload,
         }
     }
@@ -110,6 +170,35 @@ pub enum ParseError {
     /// Payload HMAC was an unexpected length.
     #[error("unexpected length payload hmac")]
     UnexpectedLengthPayloadHmac,
+    /// The version of the relay protocol is unsupported.
+    #[error("unsupported version: {0}")]
+    UnsupportedVersion(u32),
+}
+
+impl ErrorCode for ParseError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Digest(err) => err.code(),
+            Self::SourcePublicKey(_) => 1010,
+            Self::DestinationPublicKey(_) => 1011,
+            Self::MissingStamp => 1012,
+            Self::UnsupportedStampType => 1013,
+            Self::UnexpectedLengthPayloadHmac => 1014,
+            Self::UnsupportedVersion(_) => 1015,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Digest(err) => err.label(),
+            Self::SourcePublicKey(_) => "parse.source_public_key",
+            Self::DestinationPublicKey(_) => "parse.destination_public_key",
+            Self::MissingStamp => "parse.missing_stamp",
+            Self::UnsupportedStampType => "parse.unsupported_stamp_type",
+            Self::UnexpectedLengthPayloadHmac => "parse.unexpected_length_payload_hmac",
+            Self::UnsupportedVersion(_) => "parse.unsupported_version",
+        }
+    }
 }
 
 /// Error associated with getting the [`Message::payload_digest`].
@@ -124,12 +213,69 @@ pub enum DigestError {
     /// Digest was an unexpected length.
     #[error("unexpected length digest")]
     UnexpectedLengthDigest,
+    /// The digest algorithm is unsupported.
+    #[error(transparent)]
+    UnsupportedAlgorithm(UnsupportedDigestAlgorithm),
+}
+
+impl ErrorCode for DigestError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::DigestAndPayloadMissing => 1001,
+            Self::FraudulentDigest => 1002,
+            Self::UnexpectedLengthDigest => 1003,
+            Self::UnsupportedAlgorithm(_) => 1004,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::DigestAndPayloadMissing => "digest.digest_and_payload_missing",
+            Self::FraudulentDigest => "digest.fraudulent_digest",
+            Self::UnexpectedLengthDigest => "digest.unexpected_length_digest",
+            Self::UnsupportedAlgorithm(_) => "digest.unsupported_algorithm",
+        }
+    }
+}
+
+impl DigestAlgorithm {
+    /// Check whether the digest algorithm is supported by the enabled features.
+    #[inline]
+    pub fn is_supported(self) -> bool {
+        self.digest(&[]).is_ok()
+    }
+
+    /// Calculate the digest of the data.
+    #[inline]
+    pub fn digest(self, data: &[u8]) -> Result<[u8; 32], UnsupportedDigestAlgorithm> {
+        match self {
+            Self::Sha256 => Some(hash::sha256(data)),
+            Self::Blake3 => hash::blake3_digest(data),
+        }
+        .ok_or(UnsupportedDigestAlgorithm)
+    }
+
+    /// Calculate the HMAC of the data.
+    #[inline]
+    pub fn hmac(self, key: &[u8], data: &[u8]) -> Result<[u8; 32], UnsupportedDigestAlgorithm> {
+        match self {
+            Self::Sha256 => Some(hash::hmac_sha256(key, data)),
+            Self::Blake3 => hash::blake3_hmac(key, data),
+        }
+        .ok_or(UnsupportedDigestAlgorithm)
+    }
 }
 
 impl Message {
-    /// Get the SHA-256 digest of the `payload`, if `payload_digest` is missing then calculate it.
+    /// Get the digest of the `payload`, if `payload_digest` is missing then calculate it using the
+    /// `digest_algorithm`.
     #[inline]
     pub fn digest(&self) -> Result<[u8; 32], DigestError> {
+        // Parse digest algorithm
+        let algorithm = DigestAlgorithm::from_i32(self.digest_algorithm).ok_or(
+            DigestError::UnsupportedAlgorithm(UnsupportedDigestAlgorithm),
+        )?;
+
         // Calculate payload digest
         let payload_digest: [u8; 32] = match self.payload_digest.len() {
             0 => {
@@ -139,23 +285,29 @@ impl Message {
                 }
 
                 // Calculate digest
-                let payload_digest: [u8; 32] =
-                    digest(&SHA256, &self.payload).as_ref().try_into().unwrap(); // This is safe
-
-                payload_digest
+                algorithm
+                    .digest(&self.payload)
+                    .map_err(DigestError::UnsupportedAlgorithm)?
             }
             32 => {
                 // Check digest is correct when payload is not missing
                 if !self.payload.is_empty() {
                     // Calculate digest
-                    let payload_digest: [u8; 32] =
-                        digest(&SHA256, &self.payload).as_ref().try_into().unwrap(); // This is safe
+                    let payload_digest = algorithm
+                        .digest(&self.payload)
+                        .map_err(DigestError::UnsupportedAlgorithm)?;
 
                     if payload_digest[..] != self.payload_digest[..] {
                         return Err(DigestError::FraudulentDigest);
                     }
                     payload_digest
                 } else {
+                    // Check the algorithm is supported
+                    if !algorithm.is_supported() {
+                        return Err(DigestError::UnsupportedAlgorithm(
+                            UnsupportedDigestAlgorithm,
+                        ));
+                    }
                     let slice = &self.payload_digest[..];
                     slice.try_into().unwrap()
                 }
@@ -171,6 +323,12 @@ impl Message {
     /// The involves deserialization of both public keys, calculation of the payload digest, and coercion of byte fields into arrays.
     #[inline]
     pub fn parse(self) -> Result<ParsedMessage, ParseError> {
+        // Check version
+        let version = effective_version(self.version);
+        if version < MIN_VERSION || version > VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+
         // Decode public keys
         let source_public_key =
             PublicKey::from_slice(&self.source_public_key).map_err(ParseError::SourcePublicKey)?;
@@ -179,6 +337,7 @@ impl Message {
 
         // Calculate payload digest
         let payload_digest = self.digest().map_err(ParseError::Digest)?;
+        let digest_algorithm = DigestAlgorithm::from_i32(self.digest_algorithm).unwrap(); // This is safe
 
         // Parse stamp data
         let stamp = self.stamp.ok_or(ParseError::MissingStamp)?;
@@ -193,10 +352,12 @@ impl Message {
             .map_err(|_| ParseError::UnexpectedLengthPayloadHmac)?;
 
         Ok(ParsedMessage {
+            version,
             source_public_key,
             destination_public_key,
             received_time: self.received_time,
             payload_digest,
+            digest_algorithm,
             stamp,
             scheme,
             salt: self.salt,
@@ -230,9 +391,7 @@ pub fn create_shared_key(
     let merged_key = create_merged_key(source_public_key, private_key)?;
     let raw_merged_key = merged_key.serialize();
 
-    let key = HmacKey::new(HMAC_SHA256, &raw_merged_key);
-    let digest = sign(&key, salt);
-    let shared_key: [u8; 32] = digest.as_ref().try_into().unwrap(); // This is safe
+    let shared_key = hash::hmac_sha256(&raw_merged_key, salt);
     Ok(shared_key)
 }
 
@@ -249,11 +408,10 @@ pub fn authenticate(
     payload_hmac: &[u8],
 ) -> Result<(), InvalidHmac> {
     // HMAC shared_key with payload_digest
-    let shared_key = HmacKey::new(HMAC_SHA256, shared_key);
-    let payload_hmac_expected = sign(&shared_key, payload_digest);
+    let payload_hmac_expected = hash::hmac_sha256(shared_key, payload_digest);
 
     // Check equality
-    if payload_hmac_expected.as_ref() != payload_hmac {
+    if payload_hmac_expected[..] != payload_hmac[..] {
         return Err(InvalidHmac);
     }
     Ok(())
@@ -291,7 +449,34 @@ pub enum OpenError {
     Payload(MessageDecodeError),
     /// Failed to decrypt the ciphertext [`Payload`].
     #[error("decryption failure: {0}")]
-    Decrypt(BlockModeError),
+    Decrypt(DecryptError),
+    /// The encryption scheme does not support decryption.
+    #[error("unsupported encryption scheme")]
+    UnsupportedScheme,
+}
+
+impl ErrorCode for OpenError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Stamp(err) => err.code(),
+            Self::SharedKey(_) => 1201,
+            Self::Authentication => 1202,
+            Self::Payload(_) => 1203,
+            Self::Decrypt(_) => 1204,
+            Self::UnsupportedScheme => 1205,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Stamp(err) => err.label(),
+            Self::SharedKey(_) => "open.shared_key",
+            Self::Authentication => "open.authentication",
+            Self::Payload(_) => "open.payload",
+            Self::Decrypt(_) => "open.decrypt",
+            Self::UnsupportedScheme => "open.unsupported_scheme",
+        }
+    }
 }
 
 impl ParsedMessage {
@@ -311,10 +496,22 @@ impl ParsedMessage {
         create_shared_key(self.source_public_key, private_key, salt)
     }
 
+    /// Create the [`PayloadKeys`] from the destination private key, according to the encryption
+    /// scheme.
+    #[inline]
+    pub fn create_payload_keys(&self, private_key: &[u8]) -> Result<PayloadKeys, OpenError> {
+        let merged_key = self
+            .create_merged_key(private_key)
+            .map_err(OpenError::SharedKey)?;
+        let keys = PayloadKeys::derive(self.scheme, &merged_key, &self.salt)
+            .map_err(|_| OpenError::UnsupportedScheme)?;
+        Ok(keys.with_digest_algorithm(self.digest_algorithm))
+    }
+
     /// Authenticate the HMAC payload and return the merged key.
     #[inline]
     pub fn authenticate(&self, shared_key: &[u8; 32]) -> Result<(), InvalidHmac> {
-        authenticate(shared_key, &self.payload_digest, &self.salt)?;
+        authenticate(shared_key, &self.payload_digest, &self.payload_hmac)?;
 
         Ok(())
     }
@@ -326,6 +523,20 @@ impl ParsedMessage {
             .verify_stamp(&self.payload_digest, &self.destination_public_key)
     }
 
+    /// Verify the stamp on the message, using an existing context, and return the decoded
+    /// transactions.
+    #[inline]
+    pub fn verify_stamp_with_context<C: Signing + Verification>(
+        &self,
+        context: &Secp256k1<C>,
+    ) -> Result<Vec<Transaction>, StampError> {
+        self.stamp.verify_stamp_with_context(
+            context,
+            &self.payload_digest,
+            &self.destination_public_key,
+        )
+    }
+
     /// Verify the stamp, authenticate the HMAC payload, and then decrypt and decode the payload.
     ///
     /// This is done in-place, replacing the encrypted `payload` field with the plain text.
@@ -334,27 +545,21 @@ impl ParsedMessage {
         // Verify stamp
         let txs = self.verify_stamp().map_err(OpenError::Stamp)?;
 
-        // Create shared key
-        let shared_key = self
-            .create_shared_key(private_key, &self.salt)
-            .map_err(OpenError::SharedKey)?;
+        // Create payload keys
+        let keys = self.create_payload_keys(private_key)?;
 
         // Authenticate HMAC payload
-        self.authenticate(&shared_key)
+        keys.authenticate(&self.payload_digest, &self.payload_hmac)
             .map_err(|_| OpenError::Authentication)?;
 
         // Decrypt
-        let mut raw_payload = &mut self.payload;
-        let (key, iv) = shared_key.split_at(16);
-        let key = GenericArray::<u8, U16>::from_slice(&key);
-        let iv = GenericArray::<u8, U16>::from_slice(&iv);
-        let cipher = Aes128Cbc::new_var(&key, &iv).unwrap(); // This is safe
-        cipher
-            .decrypt(&mut raw_payload)
+        let mut plaintext = keys
+            .decrypt_in_place(&mut self.payload)
             .map_err(OpenError::Decrypt)?;
 
         // Decode
-        let payload = Payload::decode(&mut raw_payload.as_slice()).map_err(OpenError::Payload)?;
+        let mut payload = Payload::decode(&mut plaintext).map_err(OpenError::Payload)?;
+        payload.strip_padding();
 
         Ok(Opened { txs, payload })
     }
@@ -365,27 +570,19 @@ impl ParsedMessage {
         // Verify stamp
         let txs = self.verify_stamp().map_err(OpenError::Stamp)?;
 
-        // Create shared key
-        let shared_key = self
-            .create_shared_key(private_key, &self.salt)
-            .map_err(OpenError::SharedKey)?;
+        // Create payload keys
+        let keys = self.create_payload_keys(private_key)?;
 
         // Authenticate HMAC payload
-        self.authenticate(&shared_key)
+        keys.authenticate(&self.payload_digest, &self.payload_hmac)
             .map_err(|_| OpenError::Authentication)?;
 
         // Decrypt
-        let raw_payload = &self.payload;
-        let (key, iv) = shared_key.as_ref().split_at(16);
-        let key = GenericArray::<u8, U16>::from_slice(&key);
-        let iv = GenericArray::<u8, U16>::from_slice(&iv);
-        let cipher = Aes128Cbc::new_var(&key, &iv).unwrap(); // This is safe
-        cipher
-            .decrypt_vec(raw_payload)
-            .map_err(OpenError::Decrypt)?;
+        let plaintext = keys.decrypt(&self.payload).map_err(OpenError::Decrypt)?;
 
         // Decode
-        let payload = Payload::decode(&mut raw_payload.as_slice()).map_err(OpenError::Payload)?;
+        let mut payload = Payload::decode(&mut plaintext.as_slice()).map_err(OpenError::Payload)?;
+        payload.strip_padding();
 
         Ok(Opened { txs, payload })
     }
@@ -418,21 +615,141 @@ impl Into<PayloadPage> for MessagePage {
 /// Encrypt a payload using a shared key.
 ///
 /// Typically the shared key is `HMAC(sdG, salt)` created using the [`create_shared_key`] method.
+///
+/// # Panics
+///
+/// Panics if the shared key is not 32 bytes long.
 pub fn encrypt_payload(shared_key: &[u8], plaintext: &[u8]) -> Vec<u8> {
-    let (key, iv) = shared_key.as_ref().split_at(16);
-    let key = GenericArray::<u8, U16>::from_slice(&key);
-    let iv = GenericArray::<u8, U16>::from_slice(&iv);
-    let cipher = Aes128Cbc::new_var(&key, &iv).unwrap(); // This is safe
-    cipher.encrypt_vec(plaintext)
+    shared_payload_keys(shared_key).encrypt(plaintext)
 }
 
 /// Encrypt a payload, in place, using a shared key.
 ///
 /// Typically the shared key is `HMAC(sdG, salt)` created using the [`create_shared_key`] method.
+///
+/// # Panics
+///
+/// Panics if the shared key is not 32 bytes long.
 pub fn encrypt_payload_in_place(shared_key: &[u8], payload: &mut [u8]) {
-    let (key, iv) = shared_key.as_ref().split_at(16);
-    let key = GenericArray::<u8, U16>::from_slice(&key);
-    let iv = GenericArray::<u8, U16>::from_slice(&iv);
-    let cipher = Aes128Cbc::new_var(&key, &iv).unwrap(); // This is safe
+    let cipher = shared_payload_keys(shared_key).cipher();
     cipher.encrypt(payload, 0).unwrap(); // TODO: Double check this is safe
 }
+
+/// Split a shared key into the [`PayloadKeys`] of [`EncryptionScheme::EphemeralDh`].
+fn shared_payload_keys(shared_key: &[u8]) -> PayloadKeys {
+    let shared_key: &[u8; 32] = shared_key.try_into().expect("shared key must be 32 bytes");
+    PayloadKeys::from_shared_key(shared_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message as _;
+    use ring::hmac::{sign, Key as HmacKey, HMAC_SHA256};
+
+    use super::*;
+    use crate::secp::PrivateKey;
+
+    fn sealed_message(payload: &Payload) -> (PrivateKey, [u8; 32], ParsedMessage) {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let salt = vec![2; 32];
+        let shared_key = create_shared_key(public_key, &private_key[..], &salt).unwrap();
+
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+        let mut message = Message {
+            source_public_key: public_key.serialize().to_vec(),
+            destination_public_key: public_key.serialize().to_vec(),
+            stamp: Some(Stamp {
+                stamp_type: StampType::MessageCommitment as i32,
+                ..Default::default()
+            }),
+            scheme: EncryptionScheme::EphemeralDh as i32,
+            salt,
+            payload: encrypt_payload(&shared_key, &raw_payload),
+            ..Default::default()
+        };
+        let payload_digest = message.digest().unwrap();
+        let payload_hmac = sign(&HmacKey::new(HMAC_SHA256, &shared_key), &payload_digest);
+        message.payload_hmac = payload_hmac.as_ref().to_vec();
+        (private_key, shared_key, message.parse().unwrap())
+    }
+
+    #[test]
+    fn authenticate_payload_hmac() {
+        let (_, shared_key, mut message) = sealed_message(&Payload::default());
+        assert_eq!(message.authenticate(&shared_key), Ok(()));
+
+        // The salt is not the payload HMAC
+        message.payload_hmac = message.salt[..].try_into().unwrap();
+        assert_eq!(message.authenticate(&shared_key), Err(InvalidHmac));
+    }
+
+    #[test]
+    fn open_decodes_plaintext() {
+        let payload = Payload {
+            timestamp: 1_600_000_000_000,
+            ..Default::default()
+        };
+        let (private_key, _, mut message) = sealed_message(&payload);
+        assert_eq!(message.open(&private_key[..]).unwrap().payload, payload);
+        assert_eq!(
+            message.open_in_place(&private_key[..]).unwrap().payload,
+            payload
+        );
+    }
+
+    #[test]
+    fn versions() {
+        let (_, _, message) = sealed_message(&Payload::default());
+        assert_eq!(message.version, VERSION);
+
+        let mut message = message.into_message();
+        message.version = VERSION + 1;
+        assert_eq!(
+            message.parse(),
+            Err(ParseError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn skip_unknown_fields() {
+        let (_, _, message) = sealed_message(&Payload::default());
+        let message = message.into_message();
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap();
+
+        // Field 20, varint 1
+        raw_message.extend_from_slice(&[0xa0, 0x01, 0x01]);
+        assert_eq!(Message::decode(&raw_message[..]).unwrap(), message);
+    }
+
+    #[test]
+    fn digest_algorithms() {
+        assert_eq!(
+            DigestAlgorithm::Sha256.digest(b"abc"),
+            Ok(hash::sha256(b"abc"))
+        );
+        assert_eq!(
+            DigestAlgorithm::Sha256.hmac(b"key", b"abc"),
+            Ok(hash::hmac_sha256(b"key", b"abc"))
+        );
+        assert_eq!(
+            DigestAlgorithm::Blake3.digest(b"abc").is_ok(),
+            cfg!(feature = "blake3")
+        );
+
+        // Unknown algorithms are rejected
+        let message = Message {
+            digest_algorithm: 7,
+            payload: b"payload".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            message.digest(),
+            Err(DigestError::UnsupportedAlgorithm(
+                UnsupportedDigestAlgorithm
+            ))
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! This module contains the [`PaddingPolicy`] which pads serialized [`Payload`]s up to a bucket
+//! size prior to encryption.
+//!
+//! Without padding the length of the ciphertext reveals the length of the plaintext, allowing
+//! relay observers to infer the type of content sent. The padding is carried within the
+//! [`Payload::padding`] field and hence is ignored by recipients unaware of it.
+
+use prost::Message as _;
+
+use crate::Payload;
+
+/// The default bucket sizes, in bytes.
+pub const DEFAULT_BUCKETS: &[usize] = &[256, 1024, 4096, 16_384, 65_536];
+
+/// The tag of the [`Payload::padding`] field, this fits in a single byte.
+const PADDING_TAG_LEN: usize = 1;
+
+/// Length of a variable-length integer encoding `value`.
+fn varint_len(value: usize) -> usize {
+    let mut len = 1;
+    let mut value = value >> 7;
+    while value != 0 {
+        len += 1;
+        value >>= 7;
+    }
+    len
+}
+
+/// The length of the padding content such that the padding field occupies exactly `remaining`
+/// bytes, if possible.
+fn padding_len(remaining: usize) -> Option<usize> {
+    if remaining == 0 {
+        return Some(0);
+    }
+    (1..=10).find_map(|len_len| {
+        let content_len = remaining.checked_sub(PADDING_TAG_LEN + len_len)?;
+        if content_len != 0 && varint_len(content_len) == len_len {
+            Some(content_len)
+        } else {
+            None
+        }
+    })
+}
+
+/// Describes the bucket sizes serialized payloads are padded to.
+///
+/// Payloads larger than the largest bucket are padded to a multiple of the largest bucket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaddingPolicy {
+    buckets: Vec<usize>,
+}
+
+impl Default for PaddingPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKETS.to_vec())
+    }
+}
+
+impl PaddingPolicy {
+    /// Create a new [`PaddingPolicy`] from a collection of bucket sizes.
+    ///
+    /// This panics if no non-zero buckets are given.
+    pub fn new(mut buckets: Vec<usize>) -> Self {
+        buckets.retain(|bucket| *bucket != 0);
+        assert!(!buckets.is_empty(), "no buckets given");
+        buckets.sort_unstable();
+        buckets.dedup();
+        Self { buckets }
+    }
+
+    /// Get the bucket sizes.
+    pub fn buckets(&self) -> &[usize] {
+        &self.buckets
+    }
+
+    /// Candidate target sizes for a serialized length, in ascending order.
+    fn targets(&self, len: usize) -> impl Iterator<Item = usize> + '_ {
+        let largest = *self.buckets.last().unwrap(); // This is safe
+        let multiple = (len / largest).max(1);
+        self.buckets
+            .iter()
+            .copied()
+            .chain((multiple..).map(move |n| n * largest))
+            .filter(move |target| *target >= len)
+    }
+
+    /// Pad the [`Payload`] so that its serialized length is a bucket size.
+    ///
+    /// Any existing padding is replaced.
+    pub fn pad(&self, payload: &mut Payload) {
+        payload.padding.clear();
+        let len = payload.encoded_len();
+        let padding_len = self
+            .targets(len)
+            .find_map(|target| padding_len(target - len))
+            .unwrap(); // This is safe as a large enough target is always padded to
+        payload.padding = vec![0; padding_len];
+    }
+}
+
+impl Payload {
+    /// Remove the padding from the [`Payload`].
+    pub fn strip_padding(&mut self) {
+        self.padding = Vec::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PayloadEntry;
+
+    fn payload(body_len: usize) -> Payload {
+        Payload {
+            timestamp: 1_600_000_000,
+            entries: vec![PayloadEntry {
+                kind: "text-utf8".to_string(),
+                headers: vec![],
+                body: vec![1; body_len],
+            }],
+            padding: vec![],
+        }
+    }
+
+    #[test]
+    fn pad_to_buckets() {
+        let policy = PaddingPolicy::default();
+        for body_len in 0..2048 {
+            let mut payload = payload(body_len);
+            policy.pad(&mut payload);
+            let len = payload.encoded_len();
+            assert!(
+                DEFAULT_BUCKETS.contains(&len),
+                "body length {} padded to {}",
+                body_len,
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn pad_beyond_largest() {
+        let policy = PaddingPolicy::new(vec![64]);
+        let mut payload = payload(200);
+        policy.pad(&mut payload);
+        assert_eq!(payload.encoded_len() % 64, 0);
+    }
+
+    #[test]
+    fn strip() {
+        let mut payload = payload(10);
+        let expected = payload.clone();
+        PaddingPolicy::default().pad(&mut payload);
+
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+        let mut decoded = Payload::decode(raw_payload.as_slice()).unwrap();
+        decoded.strip_padding();
+        assert_eq!(decoded, expected);
+    }
+}
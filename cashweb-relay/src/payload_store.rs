@@ -0,0 +1,381 @@
+//! This module contains the [`PayloadStore`] which persists encrypted payloads, content-addressed by
+//! their payload digest.
+//!
+//! Message metadata is small, while payloads may be large. Storing payloads separately allows a
+//! client to retain the metadata of its inbox and fetch payload bodies lazily, from the payloads
+//! endpoint of a relay server, only when they are opened. Payloads are addressed by their digest,
+//! hence storing the same payload twice, for example when it is fetched from several relay servers,
+//! is idempotent.
+//!
+//! The digest of a payload is recalculated whenever it is read, so a corrupted or tampered
+//! [`PayloadBackend`] is detected rather than surfacing the wrong payload. A
+//! [`MemoryPayloadBackend`] and a [`FilePayloadBackend`], storing each payload in a file named by
+//! its hex encoded digest, are provided.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use error_code::ErrorCode;
+use thiserror::Error;
+
+use crate::{DigestAlgorithm, Message, ParsedMessage, UnsupportedDigestAlgorithm};
+
+/// Error associated with accessing a [`PayloadStore`].
+#[derive(Debug, Error)]
+pub enum PayloadStoreError<E: fmt::Debug + fmt::Display> {
+    /// Failed to access the [`PayloadBackend`].
+    #[error("payload backend failure: {0}")]
+    Backend(E),
+    /// The digest algorithm is unsupported.
+    #[error(transparent)]
+    UnsupportedAlgorithm(UnsupportedDigestAlgorithm),
+    /// The stored payload does not match its digest.
+    #[error("stored payload does not match digest")]
+    DigestMismatch,
+    /// The message carries no payload.
+    #[error("payload missing")]
+    PayloadMissing,
+}
+
+impl<E: fmt::Debug + fmt::Display> ErrorCode for PayloadStoreError<E> {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Backend(_) => 1801,
+            Self::UnsupportedAlgorithm(_) => 1802,
+            Self::DigestMismatch => 1803,
+            Self::PayloadMissing => 1804,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Backend(_) => "payload_store.backend",
+            Self::UnsupportedAlgorithm(_) => "payload_store.unsupported_algorithm",
+            Self::DigestMismatch => "payload_store.digest_mismatch",
+            Self::PayloadMissing => "payload_store.payload_missing",
+        }
+    }
+}
+
+/// A backend persisting payloads by their payload digest.
+///
+/// Backends are not expected to verify the payloads, this is performed by the [`PayloadStore`].
+pub trait PayloadBackend {
+    /// Error associated with accessing the backend.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get the payload with the payload digest.
+    fn get_payload(&self, payload_digest: &[u8; 32]) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Put the payload with the payload digest.
+    fn put_payload(&self, payload_digest: &[u8; 32], payload: &[u8]) -> Result<(), Self::Error>;
+
+    /// Remove the payload with the payload digest, returning whether it was present.
+    fn remove_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error>;
+
+    /// Check whether the payload with the payload digest is present.
+    fn contains_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        self.get_payload(payload_digest)
+            .map(|payload| payload.is_some())
+    }
+}
+
+/// An in-memory [`PayloadBackend`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryPayloadBackend {
+    payloads: Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+}
+
+impl MemoryPayloadBackend {
+    /// Create a new, empty, [`MemoryPayloadBackend`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl PayloadBackend for MemoryPayloadBackend {
+    type Error = Infallible;
+
+    fn get_payload(&self, payload_digest: &[u8; 32]) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.payloads.lock().unwrap().get(payload_digest).cloned())
+    }
+
+    fn put_payload(&self, payload_digest: &[u8; 32], payload: &[u8]) -> Result<(), Self::Error> {
+        self.payloads
+            .lock()
+            .unwrap()
+            .insert(*payload_digest, payload.to_vec());
+        Ok(())
+    }
+
+    fn remove_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        Ok(self
+            .payloads
+            .lock()
+            .unwrap()
+            .remove(payload_digest)
+            .is_some())
+    }
+
+    fn contains_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        Ok(self.payloads.lock().unwrap().contains_key(payload_digest))
+    }
+}
+
+/// A [`PayloadBackend`] storing each payload in a file, within a directory, named by the hex
+/// encoded payload digest.
+#[derive(Clone, Debug)]
+pub struct FilePayloadBackend {
+    directory: PathBuf,
+}
+
+impl FilePayloadBackend {
+    /// Create a new [`FilePayloadBackend`] within a directory, creating it if missing.
+    pub fn new<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    /// The directory containing the payloads.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn path(&self, payload_digest: &[u8; 32]) -> PathBuf {
+        let file_name: String = payload_digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.directory.join(file_name)
+    }
+}
+
+impl PayloadBackend for FilePayloadBackend {
+    type Error = io::Error;
+
+    fn get_payload(&self, payload_digest: &[u8; 32]) -> Result<Option<Vec<u8>>, Self::Error> {
+        match fs::read(self.path(payload_digest)) {
+            Ok(payload) => Ok(Some(payload)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn put_payload(&self, payload_digest: &[u8; 32], payload: &[u8]) -> Result<(), Self::Error> {
+        // Write to a temporary file then rename, so a partial write is never read
+        let path = self.path(payload_digest);
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, payload)?;
+        fs::rename(temporary_path, path)
+    }
+
+    fn remove_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        match fs::remove_file(self.path(payload_digest)) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn contains_payload(&self, payload_digest: &[u8; 32]) -> Result<bool, Self::Error> {
+        Ok(self.path(payload_digest).is_file())
+    }
+}
+
+/// A content-addressed store of payloads, verifying their digest on read.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadStore<B> {
+    backend: B,
+}
+
+impl PayloadStore<MemoryPayloadBackend> {
+    /// Create a new, empty, in-memory [`PayloadStore`].
+    pub fn memory() -> Self {
+        Self::new(MemoryPayloadBackend::new())
+    }
+}
+
+impl PayloadStore<FilePayloadBackend> {
+    /// Create a new [`PayloadStore`] within a directory, creating it if missing.
+    pub fn file<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        FilePayloadBackend::new(directory).map(Self::new)
+    }
+}
+
+impl<B> PayloadStore<B> {
+    /// Create a new [`PayloadStore`] from a [`PayloadBackend`].
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    /// Get a reference to the [`PayloadBackend`].
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+impl<B: PayloadBackend> PayloadStore<B> {
+    /// Put a payload, returning its payload digest under the digest algorithm.
+    pub fn put(
+        &self,
+        digest_algorithm: DigestAlgorithm,
+        payload: &[u8],
+    ) -> Result<[u8; 32], PayloadStoreError<B::Error>> {
+        let payload_digest = digest_algorithm
+            .digest(payload)
+            .map_err(PayloadStoreError::UnsupportedAlgorithm)?;
+        self.backend
+            .put_payload(&payload_digest, payload)
+            .map_err(PayloadStoreError::Backend)?;
+        Ok(payload_digest)
+    }
+
+    /// Get the payload with the payload digest, verifying it under the digest algorithm.
+    pub fn get(
+        &self,
+        digest_algorithm: DigestAlgorithm,
+        payload_digest: &[u8; 32],
+    ) -> Result<Option<Vec<u8>>, PayloadStoreError<B::Error>> {
+        let payload = match self
+            .backend
+            .get_payload(payload_digest)
+            .map_err(PayloadStoreError::Backend)?
+        {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+
+        // Verify digest
+        let digest = digest_algorithm
+            .digest(&payload)
+            .map_err(PayloadStoreError::UnsupportedAlgorithm)?;
+        if &digest != payload_digest {
+            return Err(PayloadStoreError::DigestMismatch);
+        }
+        Ok(Some(payload))
+    }
+
+    /// Check whether the payload with the payload digest is present.
+    ///
+    /// The payload is not verified.
+    pub fn contains(&self, payload_digest: &[u8; 32]) -> Result<bool, PayloadStoreError<B::Error>> {
+        self.backend
+            .contains_payload(payload_digest)
+            .map_err(PayloadStoreError::Backend)
+    }
+
+    /// Remove the payload with the payload digest, returning whether it was present.
+    pub fn remove(&self, payload_digest: &[u8; 32]) -> Result<bool, PayloadStoreError<B::Error>> {
+        self.backend
+            .remove_payload(payload_digest)
+            .map_err(PayloadStoreError::Backend)
+    }
+
+    /// Put the payload of a [`Message`], returning its payload digest.
+    ///
+    /// The payload digest is recalculated, a message whose digest does not match its payload is
+    /// rejected.
+    pub fn put_message(&self, message: &Message) -> Result<[u8; 32], PayloadStoreError<B::Error>> {
+        if message.payload.is_empty() {
+            return Err(PayloadStoreError::PayloadMissing);
+        }
+        let digest_algorithm = DigestAlgorithm::from_i32(message.digest_algorithm).ok_or(
+            PayloadStoreError::UnsupportedAlgorithm(UnsupportedDigestAlgorithm),
+        )?;
+        let payload_digest = digest_algorithm
+            .digest(&message.payload)
+            .map_err(PayloadStoreError::UnsupportedAlgorithm)?;
+        if !message.payload_digest.is_empty() && message.payload_digest[..] != payload_digest[..] {
+            return Err(PayloadStoreError::DigestMismatch);
+        }
+        self.backend
+            .put_payload(&payload_digest, &message.payload)
+            .map_err(PayloadStoreError::Backend)?;
+        Ok(payload_digest)
+    }
+
+    /// Put the payload of a [`ParsedMessage`], returning its payload digest.
+    pub fn put_parsed(
+        &self,
+        message: &ParsedMessage,
+    ) -> Result<[u8; 32], PayloadStoreError<B::Error>> {
+        if message.payload.is_empty() {
+            return Err(PayloadStoreError::PayloadMissing);
+        }
+        self.put(message.digest_algorithm, &message.payload)
+    }
+
+    /// Populate the payload of a [`ParsedMessage`] from the store, returning whether it was
+    /// present.
+    pub fn fill_parsed(
+        &self,
+        message: &mut ParsedMessage,
+    ) -> Result<bool, PayloadStoreError<B::Error>> {
+        match self.get(message.digest_algorithm, &message.payload_digest)? {
+            Some(payload) => {
+                message.payload = payload;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_verifies_digest() {
+        let store = PayloadStore::memory();
+        let payload_digest = store.put(DigestAlgorithm::Sha256, b"payload").unwrap();
+        assert_eq!(
+            store.get(DigestAlgorithm::Sha256, &payload_digest).unwrap(),
+            Some(b"payload".to_vec())
+        );
+        assert_eq!(store.get(DigestAlgorithm::Sha256, &[0; 32]).unwrap(), None);
+
+        // A tampered backend is detected
+        store
+            .backend()
+            .put_payload(&payload_digest, b"tampered")
+            .unwrap();
+        assert!(matches!(
+            store.get(DigestAlgorithm::Sha256, &payload_digest),
+            Err(PayloadStoreError::DigestMismatch)
+        ));
+
+        // Messages with fraudulent digests are rejected
+        let message = Message {
+            payload: b"payload".to_vec(),
+            payload_digest: vec![1; 32],
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.put_message(&message),
+            Err(PayloadStoreError::DigestMismatch)
+        ));
+    }
+
+    #[test]
+    fn file() {
+        let directory =
+            std::env::temp_dir().join(format!("cashweb-payload-store-{}", std::process::id()));
+        let store = PayloadStore::file(&directory).unwrap();
+        let payload_digest = store.put(DigestAlgorithm::Sha256, b"payload").unwrap();
+        assert!(store.contains(&payload_digest).unwrap());
+        assert_eq!(
+            store.get(DigestAlgorithm::Sha256, &payload_digest).unwrap(),
+            Some(b"payload".to_vec())
+        );
+        assert!(store.remove(&payload_digest).unwrap());
+        assert!(!store.remove(&payload_digest).unwrap());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
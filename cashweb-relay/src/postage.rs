@@ -0,0 +1,171 @@
+//! This module contains the [`PostagePolicy`] describing the stamp a relay server requires to
+//! accept a message, and [`estimate_stamp`] which prices a stamp satisfying it.
+//!
+//! The estimate allows wallets to display the cost of sending a message before building the stamp
+//! transaction. Transaction sizes assume a single pay-to-pubkey-hash input and a change output.
+//!
+//! Relay servers advertise their policy as [`PostageRates`], which convert to and from a
+//! [`PostagePolicy`].
+
+use crate::PostageRates;
+
+/// The length of the transaction version, input and output counts and lock time.
+pub(crate) const TX_OVERHEAD_LEN: u64 = 10;
+
+/// The length of a signed pay-to-pubkey-hash input.
+pub(crate) const P2PKH_INPUT_LEN: u64 = 148;
+
+/// The length of a pay-to-pubkey-hash output.
+pub(crate) const P2PKH_OUTPUT_LEN: u64 = 34;
+
+/// The default minimum value, in satoshis, of a stamp output.
+pub const DEFAULT_DUST_LIMIT: u64 = 546;
+
+/// The stamp a relay server requires to accept a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PostagePolicy {
+    /// The fixed postage, in satoshis, of each message.
+    pub base: u64,
+    /// The postage, in satoshis, per byte of payload.
+    pub per_byte: u64,
+    /// The minimum value, in satoshis, of each stamp output.
+    pub dust_limit: u64,
+    /// The maximum value, in satoshis, of each stamp output, if any.
+    pub max_output_value: Option<u64>,
+}
+
+impl Default for PostagePolicy {
+    fn default() -> Self {
+        Self {
+            base: 0,
+            per_byte: 0,
+            dust_limit: DEFAULT_DUST_LIMIT,
+            max_output_value: None,
+        }
+    }
+}
+
+impl PostagePolicy {
+    /// The postage, in satoshis, required for a payload of the given length.
+    pub fn required_postage(&self, payload_len: usize) -> u64 {
+        self.base
+            .saturating_add(self.per_byte.saturating_mul(payload_len as u64))
+    }
+}
+
+impl From<PostageRates> for PostagePolicy {
+    fn from(rates: PostageRates) -> Self {
+        Self {
+            base: rates.base,
+            per_byte: rates.per_byte,
+            dust_limit: rates.dust_limit,
+            max_output_value: Some(rates.max_output_value).filter(|value| *value != 0),
+        }
+    }
+}
+
+impl From<PostagePolicy> for PostageRates {
+    fn from(policy: PostagePolicy) -> Self {
+        Self {
+            base: policy.base,
+            per_byte: policy.per_byte,
+            dust_limit: policy.dust_limit,
+            max_output_value: policy.max_output_value.unwrap_or(0),
+        }
+    }
+}
+
+/// The estimated cost of a stamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampEstimate {
+    /// The number of stamp outputs.
+    pub outputs: u32,
+    /// The value, in satoshis, of each stamp output.
+    pub output_value: u64,
+    /// The estimated transaction fee, in satoshis.
+    pub fee: u64,
+    /// The total cost, in satoshis, of the stamp outputs and fee.
+    pub total: u64,
+}
+
+/// Estimate the stamp required to send a payload of `payload_len` bytes under the policy, paying a
+/// `fee_rate` given in satoshis per byte.
+///
+/// The postage is split evenly between as few outputs as the `max_output_value` allows, each
+/// raised to at least the `dust_limit`.
+pub fn estimate_stamp(payload_len: usize, fee_rate: u64, policy: &PostagePolicy) -> StampEstimate {
+    let postage = policy.required_postage(payload_len);
+
+    // Split postage into outputs
+    let outputs = match policy.max_output_value {
+        Some(max_output_value) if max_output_value != 0 => {
+            ((postage + max_output_value - 1) / max_output_value).max(1)
+        }
+        _ => 1,
+    };
+    let output_value = ((postage + outputs - 1) / outputs).max(policy.dust_limit);
+
+    // Estimate fee
+    let tx_len = TX_OVERHEAD_LEN + P2PKH_INPUT_LEN + (outputs + 1) * P2PKH_OUTPUT_LEN;
+    let fee = tx_len.saturating_mul(fee_rate);
+
+    StampEstimate {
+        outputs: outputs as u32,
+        output_value,
+        fee,
+        total: (outputs * output_value).saturating_add(fee),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_output() {
+        let policy = PostagePolicy {
+            base: 1_000,
+            per_byte: 2,
+            ..Default::default()
+        };
+        let estimate = estimate_stamp(500, 1, &policy);
+        assert_eq!(
+            estimate,
+            StampEstimate {
+                outputs: 1,
+                output_value: 2_000,
+                fee: 226,
+                total: 2_226,
+            }
+        );
+
+        // Raised to dust limit
+        let estimate = estimate_stamp(0, 1, &PostagePolicy::default());
+        assert_eq!(estimate.output_value, DEFAULT_DUST_LIMIT);
+    }
+
+    #[test]
+    fn split_outputs() {
+        let policy = PostagePolicy {
+            base: 10_001,
+            max_output_value: Some(5_000),
+            ..Default::default()
+        };
+        let estimate = estimate_stamp(0, 2, &policy);
+        assert_eq!(estimate.outputs, 3);
+        assert_eq!(estimate.output_value, 3_334);
+        assert_eq!(estimate.fee, 2 * (10 + 148 + 4 * 34));
+    }
+
+    #[test]
+    fn rates() {
+        let policy = PostagePolicy {
+            base: 1_000,
+            per_byte: 2,
+            ..Default::default()
+        };
+        let rates = PostageRates::from(policy.clone());
+        assert_eq!(rates.max_output_value, 0);
+        assert_eq!(PostagePolicy::from(rates), policy);
+    }
+}
@@ -0,0 +1,286 @@
+//! This module contains the [`ReceivedTimePolicy`] which checks the `received_time` of a message.
+//!
+//! The `received_time` is supplied by the relay server, in unix milliseconds, and is not covered by
+//! the HMAC. A malicious relay server may therefore rewrite it, for example to reorder a
+//! conversation. The policy checks it against bounds which the relay server cannot influence:
+//! * The local time, read from a [`Clock`], bounds it from above and, optionally, from below.
+//! * The lock time of each stamp transaction, when given as a unix time and enforced, bounds it
+//!   from below, as the transaction could not have been confirmed, and hence broadcast alongside a
+//!   valid stamp, before it.
+//!
+//! Violations may be flagged, leaving the caller to decide how to present the message, or rejected.
+
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bitcoin::{transaction::Transaction, Decodable};
+use clock::{system_clock, Clock, SharedClock};
+use error_code::ErrorCode;
+use thiserror::Error;
+
+use crate::ParsedMessage;
+
+/// Lock times below this threshold are block heights, otherwise unix times in seconds.
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// The sequence number disabling the lock time of an input.
+const FINAL_SEQUENCE: u32 = 0xffff_ffff;
+
+/// The default maximum amount by which the `received_time` may lead the local time.
+pub const DEFAULT_MAX_FUTURE_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// A `received_time` which violates the [`ReceivedTimePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ReceivedTimeViolation {
+    /// The `received_time` is ahead of the local time by more than the permitted skew.
+    #[error("received time in the future: {received_time} > {now}")]
+    InFuture {
+        /// The `received_time`, in unix milliseconds.
+        received_time: i64,
+        /// The local time, in unix milliseconds.
+        now: i64,
+    },
+    /// The `received_time` is older than the maximum age.
+    #[error("received time too old: {received_time} < {oldest}")]
+    TooOld {
+        /// The `received_time`, in unix milliseconds.
+        received_time: i64,
+        /// The oldest permitted time, in unix milliseconds.
+        oldest: i64,
+    },
+    /// The `received_time` precedes the lock time of a stamp transaction.
+    #[error("received time precedes lock time of stamp transaction {tx_num}: {received_time} < {lock_time}")]
+    BeforeLockTime {
+        /// The `received_time`, in unix milliseconds.
+        received_time: i64,
+        /// The position of the transaction within the stamp.
+        tx_num: usize,
+        /// The lock time, in unix milliseconds.
+        lock_time: i64,
+    },
+}
+
+impl ErrorCode for ReceivedTimeViolation {
+    fn code(&self) -> u16 {
+        match self {
+            Self::InFuture { .. } => 1701,
+            Self::TooOld { .. } => 1702,
+            Self::BeforeLockTime { .. } => 1703,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::InFuture { .. } => "received_time.in_future",
+            Self::TooOld { .. } => "received_time.too_old",
+            Self::BeforeLockTime { .. } => "received_time.before_lock_time",
+        }
+    }
+}
+
+/// The action taken on a `received_time` which violates the [`ReceivedTimePolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReceivedTimeAction {
+    /// Return the violations alongside the message.
+    Flag,
+    /// Reject the message.
+    Reject,
+}
+
+/// Checks the `received_time` of messages against the local time and the stamp lock times.
+///
+/// By default violations are flagged, the `received_time` may lead the local time by at most
+/// [`DEFAULT_MAX_FUTURE_SKEW`], there is no maximum age, and the lock times are given no tolerance.
+#[derive(Clone, Debug)]
+pub struct ReceivedTimePolicy {
+    action: ReceivedTimeAction,
+    max_future_skew: Duration,
+    max_age: Option<Duration>,
+    lock_time_tolerance: Duration,
+    clock: SharedClock,
+}
+
+impl Default for ReceivedTimePolicy {
+    fn default() -> Self {
+        Self {
+            action: ReceivedTimeAction::Flag,
+            max_future_skew: DEFAULT_MAX_FUTURE_SKEW,
+            max_age: None,
+            lock_time_tolerance: Duration::from_secs(0),
+            clock: system_clock(),
+        }
+    }
+}
+
+impl ReceivedTimePolicy {
+    /// Create a new [`ReceivedTimePolicy`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the [`ReceivedTimeAction`] taken on violations.
+    pub fn with_action(mut self, action: ReceivedTimeAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Set the maximum amount by which the `received_time` may lead the local time.
+    pub fn with_max_future_skew(mut self, max_future_skew: Duration) -> Self {
+        self.max_future_skew = max_future_skew;
+        self
+    }
+
+    /// Set the maximum amount by which the `received_time` may trail the local time.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set the amount by which the `received_time` may precede the lock time of a stamp
+    /// transaction, allowing for the skew of the relay server clock.
+    pub fn with_lock_time_tolerance(mut self, lock_time_tolerance: Duration) -> Self {
+        self.lock_time_tolerance = lock_time_tolerance;
+        self
+    }
+
+    /// Set the [`Clock`] giving the local time, the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Check the `received_time` of a message.
+    ///
+    /// If flagging, the violations are returned, otherwise the first violation is returned as an
+    /// error. Stamp transactions which fail to decode are skipped, these are rejected by stamp
+    /// verification.
+    pub fn check(
+        &self,
+        message: &ParsedMessage,
+    ) -> Result<Vec<ReceivedTimeViolation>, ReceivedTimeViolation> {
+        let received_time = message.received_time;
+        let now = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or(0);
+        let mut violations = Vec::new();
+
+        // Check against local time
+        if received_time > now.saturating_add(self.max_future_skew.as_millis() as i64) {
+            violations.push(ReceivedTimeViolation::InFuture { received_time, now });
+        }
+        if let Some(max_age) = self.max_age {
+            let oldest = now.saturating_sub(max_age.as_millis() as i64);
+            if received_time < oldest {
+                violations.push(ReceivedTimeViolation::TooOld {
+                    received_time,
+                    oldest,
+                });
+            }
+        }
+
+        // Check against stamp lock times
+        let tolerance = self.lock_time_tolerance.as_millis() as i64;
+        for (tx_num, outpoints) in message.stamp.stamp_outpoints.iter().enumerate() {
+            let lock_time = match Transaction::decode(&mut outpoints.stamp_tx.as_slice()) {
+                Ok(transaction) => match unix_lock_time(&transaction) {
+                    Some(lock_time) => lock_time,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            if received_time.saturating_add(tolerance) < lock_time {
+                violations.push(ReceivedTimeViolation::BeforeLockTime {
+                    received_time,
+                    tx_num,
+                    lock_time,
+                });
+            }
+        }
+
+        match (self.action, violations.first()) {
+            (ReceivedTimeAction::Reject, Some(violation)) => Err(violation.clone()),
+            _ => Ok(violations),
+        }
+    }
+}
+
+/// The lock time of a transaction, in unix milliseconds, if given as a unix time and enforced by a
+/// non-final input.
+fn unix_lock_time(transaction: &Transaction) -> Option<i64> {
+    let enforced = transaction
+        .inputs
+        .iter()
+        .any(|input| input.sequence != FINAL_SEQUENCE);
+    if enforced && transaction.lock_time >= LOCK_TIME_THRESHOLD {
+        Some(i64::from(transaction.lock_time) * 1_000)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{transaction::Input, Encodable};
+    use clock::ManualClock;
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use super::*;
+    use crate::{seal::MessageBuilder, stamp::StampOutpoints, Payload};
+
+    fn stamped_message(received_time: i64, lock_time: u32) -> ParsedMessage {
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let mut message = MessageBuilder::new(Payload::default())
+            .seal(&private_key, &public_key, &[2; 32])
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![Input::default()],
+            outputs: vec![],
+            lock_time,
+        };
+        message.received_time = received_time;
+        message.stamp.as_mut().unwrap().stamp_outpoints = vec![StampOutpoints {
+            stamp_tx: transaction.encode_to_vec(),
+            vouts: vec![],
+        }];
+        message.parse().unwrap()
+    }
+
+    #[test]
+    fn violations() {
+        let now = 1_600_000_000;
+        let policy = ReceivedTimePolicy::new()
+            .with_max_age(Duration::from_secs(60))
+            .with_clock(ManualClock::from_unix(now));
+
+        // Within bounds
+        let message = stamped_message(now as i64 * 1_000, now as u32 - 10);
+        assert_eq!(policy.check(&message), Ok(vec![]));
+
+        // Rewritten into the past, before the stamp lock time
+        let message = stamped_message(0, now as u32 - 10);
+        let violations = policy.check(&message).unwrap();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[1].label(), "received_time.before_lock_time");
+
+        // Rewritten into the future
+        let message = stamped_message((now as i64 + 3_600) * 1_000, 0);
+        let policy = policy.with_action(ReceivedTimeAction::Reject);
+        assert_eq!(
+            policy.check(&message),
+            Err(ReceivedTimeViolation::InFuture {
+                received_time: (now as i64 + 3_600) * 1_000,
+                now: now as i64 * 1_000,
+            })
+        );
+    }
+}
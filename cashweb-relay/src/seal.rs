@@ -0,0 +1,213 @@
+//! This module contains the [`MessageBuilder`] which seals a [`Payload`] into a [`Message`].
+//!
+//! Sealing derives the [`PayloadKeys`] from the merged key, `sdG`, and the salt according to the
+//! [`EncryptionScheme`], encrypts the serialized payload, then calculates the payload digest and
+//! HMAC under the [`DigestAlgorithm`]. This is the inverse of [`ParsedMessage::open`].
+//!
+//! The stamp commits to the payload digest, hence it can only be constructed after sealing. The
+//! sealed message carries an empty [`Stamp`], whose outpoints should be populated before the
+//! message is sent, see [`create_stamp_outputs`](crate::stamp::create_stamp_outputs).
+//!
+//! [`ParsedMessage::open`]: crate::ParsedMessage::open
+
+use error_code::ErrorCode;
+use prost::Message as _;
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Secp256k1,
+};
+use thiserror::Error;
+
+use crate::{
+    create_merged_key,
+    key_schedule::PayloadKeys,
+    padding::PaddingPolicy,
+    stamp::{Stamp, StampType},
+    DigestAlgorithm, EncryptionScheme, Message, Payload, VERSION,
+};
+
+/// Error associated with sealing a [`Payload`].
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SealError {
+    /// Failed to construct the merged key.
+    #[error("shared key: {0}")]
+    SharedKey(SecpError),
+    /// The encryption scheme does not support pairwise encryption.
+    #[error("unsupported encryption scheme")]
+    UnsupportedScheme,
+    /// The digest algorithm is unsupported.
+    #[error("unsupported digest algorithm")]
+    UnsupportedDigestAlgorithm,
+}
+
+impl ErrorCode for SealError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::SharedKey(_) => 1501,
+            Self::UnsupportedScheme => 1502,
+            Self::UnsupportedDigestAlgorithm => 1503,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::SharedKey(_) => "seal.shared_key",
+            Self::UnsupportedScheme => "seal.unsupported_scheme",
+            Self::UnsupportedDigestAlgorithm => "seal.unsupported_digest_algorithm",
+        }
+    }
+}
+
+/// Constructs a [`Message`] from a [`Payload`].
+///
+/// By default the payload is sealed under [`EncryptionScheme::EphemeralDh`] and
+/// [`DigestAlgorithm::Sha256`], without padding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageBuilder {
+    payload: Payload,
+    scheme: EncryptionScheme,
+    digest_algorithm: DigestAlgorithm,
+    padding: Option<PaddingPolicy>,
+}
+
+impl MessageBuilder {
+    /// Create a new [`MessageBuilder`] from a [`Payload`].
+    pub fn new(payload: Payload) -> Self {
+        Self {
+            payload,
+            scheme: EncryptionScheme::EphemeralDh,
+            digest_algorithm: DigestAlgorithm::Sha256,
+            padding: None,
+        }
+    }
+
+    /// Set the [`EncryptionScheme`].
+    ///
+    /// Only the pairwise schemes are supported, group messages are sealed using a
+    /// [`GroupSession`](crate::group::GroupSession).
+    pub fn with_scheme(mut self, scheme: EncryptionScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Set the [`DigestAlgorithm`] used to calculate the payload digest and HMAC.
+    pub fn with_digest_algorithm(mut self, digest_algorithm: DigestAlgorithm) -> Self {
+        self.digest_algorithm = digest_algorithm;
+        self
+    }
+
+    /// Set the [`PaddingPolicy`] applied to the payload before encryption.
+    pub fn with_padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy);
+        self
+    }
+
+    /// Derive the keys, encrypt the payload, and calculate the digest and HMAC, returning the
+    /// [`Message`] from the source private key to the destination public key.
+    ///
+    /// The `received_time` is unset and the [`Stamp`] has no outpoints.
+    pub fn seal(
+        self,
+        private_key: &SecretKey,
+        destination_public_key: &PublicKey,
+        salt: &[u8],
+    ) -> Result<Message, SealError> {
+        let Self {
+            mut payload,
+            scheme,
+            digest_algorithm,
+            padding,
+        } = self;
+        if !digest_algorithm.is_supported() {
+            return Err(SealError::UnsupportedDigestAlgorithm);
+        }
+
+        // Pad payload
+        if let Some(policy) = &padding {
+            policy.pad(&mut payload);
+        }
+
+        // Create payload keys
+        let merged_key = create_merged_key(*destination_public_key, &private_key[..])
+            .map_err(SealError::SharedKey)?;
+        let keys = PayloadKeys::derive(scheme, &merged_key, salt)
+            .map_err(|_| SealError::UnsupportedScheme)?
+            .with_digest_algorithm(digest_algorithm);
+
+        // Encrypt payload
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap(); // This is safe
+        let ciphertext = keys.encrypt(&raw_payload);
+
+        // Calculate digest and HMAC, the digest algorithm was checked above
+        let payload_digest = digest_algorithm.digest(&ciphertext).unwrap(); // This is safe
+        let payload_hmac = keys.payload_hmac(&payload_digest).unwrap(); // This is safe
+
+        let source_public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), private_key);
+        Ok(Message {
+            source_public_key: source_public_key.serialize().to_vec(),
+            destination_public_key: destination_public_key.serialize().to_vec(),
+            received_time: 0,
+            payload_digest: payload_digest.to_vec(),
+            stamp: Some(Stamp {
+                stamp_type: StampType::MessageCommitment.into(),
+                stamp_outpoints: vec![],
+            }),
+            scheme: scheme.into(),
+            salt: salt.to_vec(),
+            payload_hmac: payload_hmac.to_vec(),
+            payload_size: ciphertext.len() as u64,
+            version: VERSION,
+            digest_algorithm: digest_algorithm.into(),
+            payload: ciphertext,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PayloadEntry;
+
+    #[test]
+    fn seal_and_open() {
+        let secp = Secp256k1::signing_only();
+        let source_private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let destination_private_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let destination_public_key = PublicKey::from_secret_key(&secp, &destination_private_key);
+        let payload = Payload {
+            entries: vec![PayloadEntry::text("hello".to_string())],
+            ..Default::default()
+        };
+
+        for scheme in [
+            EncryptionScheme::EphemeralDh,
+            EncryptionScheme::EphemeralDhHkdf,
+        ]
+        .iter()
+        {
+            let message = MessageBuilder::new(payload.clone())
+                .with_scheme(*scheme)
+                .seal(&source_private_key, &destination_public_key, &[3; 32])
+                .unwrap();
+            assert_eq!(message.digest().unwrap()[..], message.payload_digest[..]);
+
+            // The destination derives the same keys
+            let parsed = message.parse().unwrap();
+            let keys = parsed
+                .create_payload_keys(&destination_private_key[..])
+                .unwrap();
+            keys.authenticate(&parsed.payload_digest, &parsed.payload_hmac)
+                .unwrap();
+            let plaintext = keys.decrypt(&parsed.payload).unwrap();
+            assert_eq!(Payload::decode(&plaintext[..]).unwrap(), payload);
+        }
+
+        assert_eq!(
+            MessageBuilder::new(payload)
+                .with_scheme(EncryptionScheme::GroupKey)
+                .seal(&source_private_key, &destination_public_key, &[3; 32]),
+            Err(SealError::UnsupportedScheme)
+        );
+    }
+}
@@ -1,18 +1,33 @@
 //! This module contains the [`Stamp`] message and methods for verifying and constructing them.
+//!
+//! Stamp outputs pay to public keys derived from the destination public key and the payload digest.
+//! Verification accepts pay-to-pubkey-hash outputs, pay-to-witness-pubkey-hash outputs, and
+//! pay-to-script-hash outputs wrapping either, while constructed outputs are pay-to-pubkey-hash.
+
+use std::collections::HashMap;
 
 use bitcoin::{
     bip32::*,
-    transaction::{DecodeError as TransactionDecodeError, Transaction},
-    Decodable,
+    hash160,
+    signing::{p2pkh_script, public_key_hash, sign_p2pkh_input},
+    transaction::{
+        outpoint::Outpoint,
+        script::{opcodes, Script},
+        DecodeError as TransactionDecodeError, Input, Output, SignatureHashType, Transaction,
+    },
+    Decodable, Encodable,
 };
-use ring::digest::{digest, SHA256};
-use ripemd160::{Digest, Ripemd160};
+use error_code::ErrorCode;
+use once_cell::sync::OnceCell;
 use secp256k1::{
     key::{PublicKey, SecretKey as PrivateKey},
-    Error as SecpError, Secp256k1,
+    All, Error as SecpError, Secp256k1, Signing, Verification,
 };
 use thiserror::Error;
 
+use crate::postage::{
+    StampEstimate, DEFAULT_DUST_LIMIT, P2PKH_INPUT_LEN, P2PKH_OUTPUT_LEN, TX_OVERHEAD_LEN,
+};
 pub use crate::{
     create_shared_key,
     models::{stamp::StampType, Stamp, StampOutpoints},
@@ -27,9 +42,10 @@ pub enum StampError {
     /// A specified stamp output doesn't exist.
     #[error("missing output")]
     MissingOutput,
-    /// A specified stamp output was not a pay-to-pubkey-hash.
-    #[error("output is non-p2pkh")]
-    NotP2PKH,
+    /// A specified stamp output was not pay-to-pubkey-hash, pay-to-witness-pubkey-hash or
+    /// pay-to-script-hash.
+    #[error("unsupported output script")]
+    UnsupportedScript,
     /// A specified stamp output contained an unexpected address.
     #[error("unexpected address: {0:?} != {1:?}")]
     UnexpectedAddress(Vec<u8>, Vec<u8>),
@@ -47,6 +63,34 @@ pub enum StampError {
     NoneType,
 }
 
+impl ErrorCode for StampError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Decode(_) => 1101,
+            Self::MissingOutput => 1102,
+            Self::UnsupportedScript => 1103,
+            Self::UnexpectedAddress(..) => 1104,
+            Self::DegenerateCombination => 1105,
+            Self::ChildNumberOverflow => 1106,
+            Self::UnsupportedStampType => 1107,
+            Self::NoneType => 1108,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Decode(_) => "stamp.decode",
+            Self::MissingOutput => "stamp.missing_output",
+            Self::UnsupportedScript => "stamp.unsupported_script",
+            Self::UnexpectedAddress(..) => "stamp.unexpected_address",
+            Self::DegenerateCombination => "stamp.degenerate_combination",
+            Self::ChildNumberOverflow => "stamp.child_number_overflow",
+            Self::UnsupportedStampType => "stamp.unsupported_stamp_type",
+            Self::NoneType => "stamp.none_type",
+        }
+    }
+}
+
 impl Stamp {
     /// Verify that the stamp covers the payload_digest.
     #[inline]
@@ -62,24 +106,57 @@ impl Stamp {
             StampType::from_i32(self.stamp_type).ok_or(StampError::UnsupportedStampType)?, // This is safe
         )
     }
+
+    /// Verify that the stamp covers the payload_digest, returning the value and script of each
+    /// stamp output alongside the decoded transactions.
+    #[inline]
+    pub fn verify_stamp_detailed(
+        &self,
+        payload_digest: &[u8; 32],
+        destination_public_key: &PublicKey,
+    ) -> Result<VerifiedStamp, StampError> {
+        verify_stamp_detailed_with_context(
+            shared_context(),
+            &self.stamp_outpoints,
+            payload_digest,
+            destination_public_key,
+            StampType::from_i32(self.stamp_type).ok_or(StampError::UnsupportedStampType)?,
+        )
+    }
+
+    /// Verify that the stamp covers the payload_digest using an existing context.
+    #[inline]
+    pub fn verify_stamp_with_context<C: Signing + Verification>(
+        &self,
+        context: &Secp256k1<C>,
+        payload_digest: &[u8; 32],
+        destination_public_key: &PublicKey,
+    ) -> Result<Vec<Transaction>, StampError> {
+        verify_stamp_with_context(
+            context,
+            &self.stamp_outpoints,
+            payload_digest,
+            destination_public_key,
+            StampType::from_i32(self.stamp_type).ok_or(StampError::UnsupportedStampType)?,
+        )
+    }
 }
 
-/// Verify that the stamp covers the payload_digest.
-#[inline]
-pub fn verify_stamp(
-    stamp_outpoints: &[StampOutpoints],
+/// The context used by the methods not given one, constructing a context is expensive.
+pub(crate) fn shared_context() -> &'static Secp256k1<All> {
+    static CONTEXT: OnceCell<Secp256k1<All>> = OnceCell::new();
+    CONTEXT.get_or_init(Secp256k1::new)
+}
+
+/// Calculate the intermediate public key, at `m/44/145`, from which the stamp keys are derived.
+fn intermediate_public_key<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
     payload_digest: &[u8; 32],
     destination_public_key: &PublicKey,
-    stamp_type: StampType,
-) -> Result<Vec<Transaction>, StampError> {
-    if stamp_type == StampType::None {
-        return Err(StampError::NoneType);
-    }
-
+) -> Result<ExtendedPublicKey, StampError> {
     // Calculate master pubkey
     let payload_secret_key = PrivateKey::from_slice(&payload_digest.as_ref()).unwrap(); // This is safe
-    let payload_public_key =
-        PublicKey::from_secret_key(&Secp256k1::signing_only(), &payload_secret_key);
+    let payload_public_key = PublicKey::from_secret_key(secp, &payload_secret_key);
     let combined_key = destination_public_key
         .combine(&payload_public_key)
         .map_err(|_| StampError::DegenerateCombination)?;
@@ -88,15 +165,116 @@ pub fn verify_stamp(
     // Calculate intermediate child
     let intermediate_child = master_pk
         .derive_public_path(
-            &Secp256k1::verification_only(),
+            secp,
             &[
                 ChildNumber::from_normal_index(44).unwrap(),
                 ChildNumber::from_normal_index(145).unwrap(),
             ],
         )
         .unwrap(); // This is safe
+    Ok(intermediate_child)
+}
+
+/// Construct a pay-to-witness-pubkey-hash script.
+fn p2wpkh_script(pubkey_hash: &[u8]) -> Script {
+    let mut raw_script = Vec::with_capacity(22);
+    raw_script.extend_from_slice(&[opcodes::OP_0, opcodes::OP_PUSHBYTES_20]);
+    raw_script.extend_from_slice(pubkey_hash);
+    Script::from(raw_script)
+}
+
+/// The script type of a stamp output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StampOutputType {
+    /// Pay-to-pubkey-hash.
+    P2pkh,
+    /// Pay-to-witness-pubkey-hash.
+    P2wpkh,
+    /// Pay-to-script-hash, wrapping a pay-to-witness-pubkey-hash or pay-to-pubkey-hash script.
+    P2sh,
+}
+
+impl StampOutputType {
+    /// Classify a script, returning the hash it commits to.
+    fn classify(script: &Script) -> Option<(Self, &[u8])> {
+        let raw_script = script.as_bytes();
+        if script.is_p2pkh() {
+            Some((Self::P2pkh, &raw_script[3..23]))
+        } else if script.is_p2wpkh() {
+            Some((Self::P2wpkh, &raw_script[2..22]))
+        } else if script.is_p2sh() {
+            Some((Self::P2sh, &raw_script[2..22]))
+        } else {
+            None
+        }
+    }
+}
+
+/// Check that a stamp output script pays to the child public key, returning its type.
+fn check_stamp_script(
+    script: &Script,
+    child_key: &PublicKey,
+) -> Result<StampOutputType, StampError> {
+    let (output_type, hash) =
+        StampOutputType::classify(script).ok_or(StampError::UnsupportedScript)?;
+    let pubkey_hash = public_key_hash(child_key);
+    let expected = match output_type {
+        StampOutputType::P2pkh | StampOutputType::P2wpkh => pubkey_hash,
+        StampOutputType::P2sh => {
+            let p2sh_p2wpkh = hash160(p2wpkh_script(&pubkey_hash).as_bytes());
+            let p2sh_p2pkh = hash160(Script::p2pkh(&pubkey_hash).as_bytes());
+            if hash == &p2sh_p2pkh[..] {
+                p2sh_p2pkh
+            } else {
+                p2sh_p2wpkh
+            }
+        }
+    };
+
+    // Check equivalence
+    if &expected[..] != hash {
+        return Err(StampError::UnexpectedAddress(
+            expected.to_vec(),
+            hash.to_vec(),
+        ));
+    }
+    Ok(output_type)
+}
+
+/// Verify that the stamp covers the payload_digest.
+#[inline]
+pub fn verify_stamp(
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_type: StampType,
+) -> Result<Vec<Transaction>, StampError> {
+    verify_stamp_with_context(
+        shared_context(),
+        stamp_outpoints,
+        payload_digest,
+        destination_public_key,
+        stamp_type,
+    )
+}
+
+/// Verify that the stamp covers the payload_digest using an existing context.
+///
+/// Constructing a context is expensive, this allows it to be reused across many verifications.
+pub fn verify_stamp_with_context<C: Signing + Verification>(
+    context: &Secp256k1<C>,
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_type: StampType,
+) -> Result<Vec<Transaction>, StampError> {
+    if stamp_type == StampType::None {
+        return Err(StampError::NoneType);
+    }
+
+    let intermediate_child =
+        intermediate_public_key(context, payload_digest, destination_public_key)?;
 
-    let context = Secp256k1::verification_only();
     let mut txs = Vec::with_capacity(stamp_outpoints.len());
     for (tx_num, outpoint) in stamp_outpoints.iter().enumerate() {
         let tx =
@@ -106,7 +284,7 @@ pub fn verify_stamp(
         let child_number = ChildNumber::from_normal_index(tx_num as u32)
             .map_err(|_| StampError::ChildNumberOverflow)?;
         let tx_child = intermediate_child
-            .derive_public_child(&context, child_number)
+            .derive_public_child(context, child_number)
             .unwrap(); // TODO: Double check this is safe
 
         for (index, vout) in outpoint.vouts.iter().enumerate() {
@@ -114,29 +292,12 @@ pub fn verify_stamp(
                 .outputs
                 .get(*vout as usize)
                 .ok_or(StampError::MissingOutput)?;
-            let script = &output.script;
-            if !script.is_p2pkh() {
-                return Err(StampError::NotP2PKH);
-            }
-            let pubkey_hash = &script.as_bytes()[3..23]; // This is safe as we've checked it's a p2pkh
 
             // Derive child key
             let child_number = ChildNumber::from_normal_index(index as u32)
                 .map_err(|_| StampError::ChildNumberOverflow)?;
-            let child_key = tx_child
-                .derive_public_child(&context, child_number)
-                .unwrap(); // TODO: Double check this is safe
-            let raw_child_key = child_key.get_public_key().serialize();
-            let sha256_digest = digest(&SHA256, &raw_child_key);
-            let hash160_digest = Ripemd160::digest(sha256_digest.as_ref());
-
-            // Check equivalence
-            if &hash160_digest[..] != pubkey_hash {
-                return Err(StampError::UnexpectedAddress(
-                    hash160_digest.to_vec(),
-                    pubkey_hash.to_vec(),
-                ));
-            }
+            let child_key = tx_child.derive_public_child(context, child_number).unwrap(); // TODO: Double check this is safe
+            check_stamp_script(&output.script, child_key.get_public_key())?;
         }
 
         txs.push(tx);
@@ -145,6 +306,277 @@ pub fn verify_stamp(
     Ok(txs)
 }
 
+/// A stamp output which has been verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampOutputDetail {
+    /// The position of the transaction within the stamp.
+    pub tx_num: u32,
+    /// The index of the output within the transaction.
+    pub vout: u32,
+    /// The value of the output, in satoshis.
+    pub value: u64,
+    /// The script type of the output.
+    pub output_type: StampOutputType,
+    /// The script of the output.
+    pub script: Script,
+}
+
+/// The result of verifying a stamp, including the details of each stamp output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedStamp {
+    /// The decoded stamp transactions.
+    pub transactions: Vec<Transaction>,
+    /// The stamp outputs, in the order they were specified.
+    pub outputs: Vec<StampOutputDetail>,
+    /// The summed value of the stamp outputs, in satoshis.
+    pub total_value: u64,
+}
+
+/// Verify that the stamp covers the payload_digest, returning the value and script of each stamp
+/// output alongside the decoded transactions.
+#[inline]
+pub fn verify_stamp_detailed(
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_type: StampType,
+) -> Result<VerifiedStamp, StampError> {
+    verify_stamp_detailed_with_context(
+        shared_context(),
+        stamp_outpoints,
+        payload_digest,
+        destination_public_key,
+        stamp_type,
+    )
+}
+
+/// Verify that the stamp covers the payload_digest using an existing context, returning the value
+/// and script of each stamp output alongside the decoded transactions.
+pub fn verify_stamp_detailed_with_context<C: Signing + Verification>(
+    context: &Secp256k1<C>,
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+    stamp_type: StampType,
+) -> Result<VerifiedStamp, StampError> {
+    let transactions = verify_stamp_with_context(
+        context,
+        stamp_outpoints,
+        payload_digest,
+        destination_public_key,
+        stamp_type,
+    )?;
+
+    // Collect outputs, these have been checked to exist
+    let mut outputs = Vec::new();
+    let mut total_value: u64 = 0;
+    for (tx_num, (outpoint, transaction)) in stamp_outpoints.iter().zip(&transactions).enumerate() {
+        for vout in &outpoint.vouts {
+            let output = &transaction.outputs[*vout as usize];
+            total_value = total_value.saturating_add(output.value);
+            let (output_type, _) = StampOutputType::classify(&output.script).unwrap(); // This is safe
+            outputs.push(StampOutputDetail {
+                tx_num: tx_num as u32,
+                vout: *vout,
+                value: output.value,
+                output_type,
+                script: output.script.clone(),
+            });
+        }
+    }
+
+    Ok(VerifiedStamp {
+        transactions,
+        outputs,
+        total_value,
+    })
+}
+
+/// Construct the pay-to-pubkey-hash outputs of a stamp transaction, one for each of the `amounts`.
+///
+/// The outputs pay to the public keys derived from the destination public key and the
+/// `payload_digest`. The `tx_num` is the position of the transaction within the stamp and the
+/// outputs are expected to be referenced in the order given.
+pub fn create_stamp_outputs(
+    destination_public_key: &PublicKey,
+    payload_digest: &[u8; 32],
+    tx_num: u32,
+    amounts: &[u64],
+) -> Result<Vec<Output>, StampError> {
+    let context = shared_context();
+    let intermediate_child =
+        intermediate_public_key(context, payload_digest, destination_public_key)?;
+
+    // Calculate transaction child
+    let child_number =
+        ChildNumber::from_normal_index(tx_num).map_err(|_| StampError::ChildNumberOverflow)?;
+    let tx_child = intermediate_child
+        .derive_public_child(context, child_number)
+        .unwrap(); // This is safe
+
+    amounts
+        .iter()
+        .enumerate()
+        .map(|(index, amount)| {
+            // Derive child key
+            let child_number = ChildNumber::from_normal_index(index as u32)
+                .map_err(|_| StampError::ChildNumberOverflow)?;
+            let child_key = tx_child.derive_public_child(context, child_number).unwrap(); // This is safe
+            Ok(Output {
+                value: *amount,
+                script: p2pkh_script(child_key.get_public_key()),
+            })
+        })
+        .collect()
+}
+
+/// The characters permitted within an output descriptor, ordered as required by the checksum.
+const DESCRIPTOR_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+/// The characters of an output descriptor checksum.
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn descriptor_poly_mod(c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    if c0 & 1 != 0 {
+        c ^= 0xf5_dee5_1989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9_fdca_3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1b_ab10_e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x37_06b1_677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x64_4d62_6ffd;
+    }
+    c
+}
+
+/// Calculate the checksum of an output descriptor, `None` if it contains invalid characters.
+fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = DESCRIPTOR_INPUT_CHARSET.find(ch)? as u64;
+        c = descriptor_poly_mod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = descriptor_poly_mod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = descriptor_poly_mod(c, class);
+    }
+    for _ in 0..8 {
+        c = descriptor_poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    let checksum = (0..8)
+        .map(|j| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    Some(checksum)
+}
+
+/// Encode bytes as lowercase hexadecimal.
+fn to_hex(raw: &[u8]) -> String {
+    raw.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A stamp output to be watched by a wallet prior to sweeping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampWatchEntry {
+    /// The position of the transaction within the stamp.
+    pub tx_num: u32,
+    /// The index of the output within the transaction.
+    pub vout: u32,
+    /// The derived public key the output pays to.
+    pub public_key: PublicKey,
+    /// The pay-to-pubkey-hash script of the output.
+    pub script: Script,
+}
+
+impl StampWatchEntry {
+    /// The hex encoded script, suitable for `importaddress`.
+    pub fn script_hex(&self) -> String {
+        to_hex(self.script.as_bytes())
+    }
+
+    /// The `pkh` output descriptor, including its checksum, suitable for descriptor imports.
+    pub fn descriptor(&self) -> String {
+        let descriptor = format!("pkh({})", to_hex(&self.public_key.serialize()));
+        let checksum = descriptor_checksum(&descriptor).unwrap(); // This is safe
+        format!("{}#{}", descriptor, checksum)
+    }
+}
+
+/// Derive the public keys and scripts of the stamp outputs, allowing them to be imported into a
+/// watch-only wallet.
+///
+/// The stamp is expected to have been verified, the transactions are not decoded.
+pub fn stamp_watch_entries(
+    stamp_outpoints: &[StampOutpoints],
+    payload_digest: &[u8; 32],
+    destination_public_key: &PublicKey,
+) -> Result<Vec<StampWatchEntry>, StampError> {
+    let context = shared_context();
+    let intermediate_child =
+        intermediate_public_key(context, payload_digest, destination_public_key)?;
+
+    let mut entries = Vec::new();
+    for (tx_num, outpoint) in stamp_outpoints.iter().enumerate() {
+        // Calculate transaction child
+        let child_number = ChildNumber::from_normal_index(tx_num as u32)
+            .map_err(|_| StampError::ChildNumberOverflow)?;
+        let tx_child = intermediate_child
+            .derive_public_child(context, child_number)
+            .unwrap(); // This is safe
+
+        for (index, vout) in outpoint.vouts.iter().enumerate() {
+            // Derive child key
+            let child_number = ChildNumber::from_normal_index(index as u32)
+                .map_err(|_| StampError::ChildNumberOverflow)?;
+            let child_key = tx_child.derive_public_child(context, child_number).unwrap(); // This is safe
+            let public_key = *child_key.get_public_key();
+
+            entries.push(StampWatchEntry {
+                tx_num: tx_num as u32,
+                vout: *vout,
+                script: p2pkh_script(&public_key),
+                public_key,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+impl Stamp {
+    /// Derive the public keys and scripts of the stamp outputs, allowing them to be imported into
+    /// a watch-only wallet.
+    #[inline]
+    pub fn watch_entries(
+        &self,
+        payload_digest: &[u8; 32],
+        destination_public_key: &PublicKey,
+    ) -> Result<Vec<StampWatchEntry>, StampError> {
+        stamp_watch_entries(
+            &self.stamp_outpoints,
+            payload_digest,
+            destination_public_key,
+        )
+    }
+}
+
 /// Error associated with creating stamp private keys.
 #[derive(Debug, Error)]
 pub enum StampKeyError {
@@ -167,7 +599,7 @@ pub fn create_stamp_private_keys<O>(
 where
     for<'a> &'a O: IntoIterator<Item = &'a u32>,
 {
-    let context = Secp256k1::signing_only();
+    let context = shared_context();
     private_key
         .add_assign(payload_digest.as_ref())
         .map_err(StampKeyError::Addition)?;
@@ -179,7 +611,7 @@ where
         ChildNumber::from_normal_index(145).unwrap(),
     ];
     let intermediate_child =
-        master_private_key.derive_private_path::<_, [ChildNumber; 2]>(&context, &path_prefix);
+        master_private_key.derive_private_path::<_, [ChildNumber; 2]>(context, &path_prefix);
     output_profile
         .into_iter()
         .enumerate()
@@ -187,12 +619,12 @@ where
             // Create intermediate child
             let child_number = ChildNumber::from_normal_index(tx_num as u32)
                 .map_err(|_| StampKeyError::ChildNumberOverflow)?;
-            let tx_child = intermediate_child.derive_private_child(&context, child_number);
+            let tx_child = intermediate_child.derive_private_child(context, child_number);
             let private_keys_inner: Result<Vec<_>, _> = (0..*n_index)
                 .map(|index| {
                     let child_number = ChildNumber::from_normal_index(index)
                         .map_err(|_| StampKeyError::ChildNumberOverflow)?;
-                    let tx_child = tx_child.derive_private_child(&context, child_number);
+                    let tx_child = tx_child.derive_private_child(context, child_number);
                     Ok(tx_child.into_private_key())
                 })
                 .collect();
@@ -200,3 +632,546 @@ where
         })
         .collect()
 }
+
+/// Error associated with building stamp transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StampBuildError {
+    /// Failed to construct the stamp outputs.
+    #[error("failed to create stamp outputs: {0}")]
+    Outputs(StampError),
+    /// The unspent outputs could not cover the stamp outputs and fee.
+    #[error("insufficient funds: {available} < {required}")]
+    InsufficientFunds {
+        /// The value, in satoshis, required.
+        required: u64,
+        /// The value, in satoshis, of the remaining unspent outputs.
+        available: u64,
+    },
+    /// No private key was given for the public key of a spent output.
+    #[error("missing private key for input {input_index} of transaction {tx_num}")]
+    MissingPrivateKey {
+        /// The position of the transaction within the stamp.
+        tx_num: usize,
+        /// The index of the input within the transaction.
+        input_index: usize,
+    },
+}
+
+impl ErrorCode for StampBuildError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Outputs(_) => 1121,
+            Self::InsufficientFunds { .. } => 1122,
+            Self::MissingPrivateKey { .. } => 1123,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Outputs(_) => "stamp_build.outputs",
+            Self::InsufficientFunds { .. } => "stamp_build.insufficient_funds",
+            Self::MissingPrivateKey { .. } => "stamp_build.missing_private_key",
+        }
+    }
+}
+
+/// An unspent pay-to-pubkey-hash output available to fund stamp transactions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampUtxo {
+    /// The outpoint of the unspent output.
+    pub outpoint: Outpoint,
+    /// The value, in satoshis.
+    pub value: u64,
+    /// The public key the output pays to.
+    pub public_key: PublicKey,
+}
+
+/// Constructs the stamp transactions covering a payload digest.
+///
+/// Each stamp transaction is funded by the unspent outputs in the order given, until they cover the
+/// stamp outputs and the fee. Change, if above the dust limit, is returned to the change public key,
+/// defaulting to the public key of the first output spent by the transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampBuilder {
+    destination_public_key: PublicKey,
+    payload_digest: [u8; 32],
+    utxos: Vec<StampUtxo>,
+    fee_rate: u64,
+    amounts: Vec<Vec<u64>>,
+    change_public_key: Option<PublicKey>,
+    dust_limit: u64,
+}
+
+impl StampBuilder {
+    /// Create a new [`StampBuilder`] funded by the unspent outputs, paying a `fee_rate` given in
+    /// satoshis per byte.
+    pub fn new(
+        destination_public_key: PublicKey,
+        payload_digest: [u8; 32],
+        utxos: Vec<StampUtxo>,
+        fee_rate: u64,
+    ) -> Self {
+        Self {
+            destination_public_key,
+            payload_digest,
+            utxos,
+            fee_rate,
+            amounts: vec![],
+            change_public_key: None,
+            dust_limit: DEFAULT_DUST_LIMIT,
+        }
+    }
+
+    /// Append a stamp transaction with an output for each of the `amounts`.
+    pub fn with_transaction(mut self, amounts: Vec<u64>) -> Self {
+        self.amounts.push(amounts);
+        self
+    }
+
+    /// Append a stamp transaction with the outputs of a [`StampEstimate`].
+    pub fn with_estimate(self, estimate: &StampEstimate) -> Self {
+        self.with_transaction(vec![estimate.output_value; estimate.outputs as usize])
+    }
+
+    /// Set the public key the change is paid to.
+    pub fn with_change_public_key(mut self, change_public_key: PublicKey) -> Self {
+        self.change_public_key = Some(change_public_key);
+        self
+    }
+
+    /// Set the minimum value, in satoshis, of the change output.
+    pub fn with_dust_limit(mut self, dust_limit: u64) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    /// Calculate the fee of a transaction with the given number of inputs and outputs.
+    fn fee(&self, n_inputs: usize, n_outputs: usize) -> u64 {
+        let tx_len = TX_OVERHEAD_LEN
+            + n_inputs as u64 * P2PKH_INPUT_LEN
+            + n_outputs as u64 * P2PKH_OUTPUT_LEN;
+        tx_len.saturating_mul(self.fee_rate)
+    }
+
+    /// Construct the unsigned stamp transactions.
+    pub fn build(self) -> Result<StampTransactions, StampBuildError> {
+        let mut utxos = self.utxos.iter();
+        let mut transactions = Vec::with_capacity(self.amounts.len());
+        let mut spent = Vec::with_capacity(self.amounts.len());
+        for (tx_num, amounts) in self.amounts.iter().enumerate() {
+            let mut outputs = create_stamp_outputs(
+                &self.destination_public_key,
+                &self.payload_digest,
+                tx_num as u32,
+                amounts,
+            )
+            .map_err(StampBuildError::Outputs)?;
+            let stamp_value: u64 = amounts.iter().sum();
+
+            // Select unspent outputs
+            let mut selected: Vec<StampUtxo> = Vec::new();
+            let mut total = 0;
+            while total < stamp_value + self.fee(selected.len(), outputs.len()) {
+                match utxos.next() {
+                    Some(utxo) => {
+                        total += utxo.value;
+                        selected.push(utxo.clone());
+                    }
+                    None => {
+                        return Err(StampBuildError::InsufficientFunds {
+                            required: stamp_value + self.fee(selected.len(), outputs.len()),
+                            available: total,
+                        })
+                    }
+                }
+            }
+
+            // Add change output
+            let change_fee = self.fee(selected.len(), outputs.len() + 1);
+            let change = total.saturating_sub(stamp_value + change_fee);
+            if change >= self.dust_limit && change != 0 {
+                let change_public_key = self
+                    .change_public_key
+                    .as_ref()
+                    .unwrap_or(&selected[0].public_key); // This is safe
+                outputs.push(Output {
+                    value: change,
+                    script: p2pkh_script(change_public_key),
+                });
+            }
+
+            let inputs = selected
+                .iter()
+                .map(|utxo| Input {
+                    outpoint: utxo.outpoint.clone(),
+                    script: Script::default(),
+                    sequence: 0xffff_ffff,
+                })
+                .collect();
+            transactions.push(Transaction {
+                version: 2,
+                inputs,
+                outputs,
+                lock_time: 0,
+            });
+            spent.push(selected);
+        }
+
+        Ok(StampTransactions::new(
+            transactions,
+            spent,
+            self.amounts.iter().map(Vec::len).collect(),
+        ))
+    }
+
+    /// Construct the stamp transactions, signing each input using the private key of its public
+    /// key.
+    pub fn build_signed(
+        self,
+        private_keys: &[PrivateKey],
+    ) -> Result<StampTransactions, StampBuildError> {
+        self.build()?.sign(private_keys)
+    }
+}
+
+/// The stamp transactions constructed by a [`StampBuilder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StampTransactions {
+    /// The stamp transactions.
+    pub transactions: Vec<Transaction>,
+    /// The unspent outputs spent by each stamp transaction.
+    pub spent: Vec<Vec<StampUtxo>>,
+    /// The stamp outpoints referencing the stamp transactions.
+    ///
+    /// The stamp outputs precede the change output, hence they are referenced by the first vouts.
+    pub stamp_outpoints: Vec<StampOutpoints>,
+}
+
+impl StampTransactions {
+    fn new(
+        transactions: Vec<Transaction>,
+        spent: Vec<Vec<StampUtxo>>,
+        n_outputs: Vec<usize>,
+    ) -> Self {
+        let stamp_outpoints = transactions
+            .iter()
+            .zip(n_outputs)
+            .map(|(transaction, n_outputs)| StampOutpoints {
+                stamp_tx: transaction.encode_to_vec(),
+                vouts: (0..n_outputs as u32).collect(),
+            })
+            .collect();
+        Self {
+            transactions,
+            spent,
+            stamp_outpoints,
+        }
+    }
+
+    /// Sign each input using the private key of its public key, with `SIGHASH_ALL | SIGHASH_FORKID`.
+    ///
+    /// Signing changes the transaction IDs, hence the stamp outpoints are reconstructed.
+    pub fn sign(self, private_keys: &[PrivateKey]) -> Result<Self, StampBuildError> {
+        let context = shared_context();
+        let private_keys: HashMap<_, _> = private_keys
+            .iter()
+            .map(|private_key| {
+                let public_key = PublicKey::from_secret_key(context, private_key);
+                (public_key.serialize(), private_key)
+            })
+            .collect();
+
+        let n_outputs = self
+            .stamp_outpoints
+            .iter()
+            .map(|stamp_outpoints| stamp_outpoints.vouts.len())
+            .collect();
+        let mut transactions = self.transactions;
+        for (tx_num, (transaction, spent)) in transactions.iter_mut().zip(&self.spent).enumerate() {
+            for (input_index, utxo) in spent.iter().enumerate() {
+                let private_key = private_keys.get(&utxo.public_key.serialize()).ok_or(
+                    StampBuildError::MissingPrivateKey {
+                        tx_num,
+                        input_index,
+                    },
+                )?;
+                sign_p2pkh_input(
+                    context,
+                    transaction,
+                    input_index,
+                    utxo.value,
+                    private_key,
+                    SignatureHashType::All,
+                )
+                .unwrap(); // This is safe
+            }
+        }
+
+        Ok(Self::new(transactions, self.spent, n_outputs))
+    }
+
+    /// The [`Stamp`] referencing the stamp transactions.
+    pub fn stamp(&self) -> Stamp {
+        Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: self.stamp_outpoints.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::Message;
+
+    use super::*;
+
+    #[test]
+    fn stamp_outputs_verify() {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let payload_digest = [2; 32];
+
+        let outputs = create_stamp_outputs(&public_key, &payload_digest, 0, &[1000, 2000]).unwrap();
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs,
+            lock_time: 0,
+        };
+        let stamp_outpoints = StampOutpoints {
+            stamp_tx: transaction.encode_to_vec(),
+            vouts: vec![0, 1],
+        };
+
+        verify_stamp(
+            &[stamp_outpoints],
+            &payload_digest,
+            &public_key,
+            StampType::MessageCommitment,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn stamp_verify_detailed() {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let payload_digest = [2; 32];
+
+        let outputs = create_stamp_outputs(&public_key, &payload_digest, 0, &[1000, 2000]).unwrap();
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: outputs.clone(),
+            lock_time: 0,
+        };
+        let stamp_outpoints = StampOutpoints {
+            stamp_tx: transaction.encode_to_vec(),
+            vouts: vec![0, 1],
+        };
+
+        let verified = verify_stamp_detailed(
+            &[stamp_outpoints],
+            &payload_digest,
+            &public_key,
+            StampType::MessageCommitment,
+        )
+        .unwrap();
+        assert_eq!(verified.total_value, 3000);
+        assert_eq!(verified.outputs[1].vout, 1);
+        assert_eq!(verified.outputs[1].script, outputs[1].script);
+        assert_eq!(verified.transactions, vec![transaction]);
+    }
+
+    #[test]
+    fn watch_entries() {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let payload_digest = [2; 32];
+
+        let outputs = create_stamp_outputs(&public_key, &payload_digest, 0, &[1000, 2000]).unwrap();
+        let stamp_outpoints = StampOutpoints {
+            stamp_tx: vec![],
+            vouts: vec![3, 5],
+        };
+        let entries =
+            stamp_watch_entries(&[stamp_outpoints], &payload_digest, &public_key).unwrap();
+        assert_eq!(entries[0].script, outputs[0].script);
+        assert_eq!(entries[1].script, outputs[1].script);
+        assert_eq!(entries[1].vout, 5);
+        assert!(entries[0].descriptor().starts_with("pkh(0"));
+    }
+
+    #[test]
+    fn descriptor_checksums() {
+        assert_eq!(
+            descriptor_checksum("raw(deadbeef)"),
+            Some("89f8spxm".to_string())
+        );
+        assert_eq!(descriptor_checksum("raw(\u{e9})"), None);
+    }
+
+    #[test]
+    fn stamp_outputs_match_private_keys() {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let payload_digest = [2; 32];
+
+        let outputs = create_stamp_outputs(&public_key, &payload_digest, 0, &[1000]).unwrap();
+        let private_keys =
+            create_stamp_private_keys(private_key, &payload_digest, vec![1u32]).unwrap();
+        let child_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_keys[0][0]);
+
+        assert_eq!(
+            &outputs[0].script.as_bytes()[3..23],
+            &public_key_hash(&child_public_key)[..]
+        );
+    }
+
+    #[test]
+    fn build_and_sign() {
+        let context = Secp256k1::new();
+        let destination_public_key =
+            PublicKey::from_secret_key(&context, &PrivateKey::from_slice(&[1; 32]).unwrap());
+        let funding_private_key = PrivateKey::from_slice(&[3; 32]).unwrap();
+        let funding_public_key = PublicKey::from_secret_key(&context, &funding_private_key);
+        let payload_digest = [2; 32];
+        let utxos: Vec<_> = (0..3)
+            .map(|vout| StampUtxo {
+                outpoint: Outpoint {
+                    tx_id: [4; 32],
+                    vout,
+                },
+                value: 2000,
+                public_key: funding_public_key,
+            })
+            .collect();
+
+        // Requires two inputs, with change
+        let stamp = StampBuilder::new(destination_public_key, payload_digest, utxos.clone(), 1)
+            .with_transaction(vec![1000, 2000])
+            .build_signed(&[funding_private_key])
+            .unwrap();
+        let transaction = &stamp.transactions[0];
+        assert_eq!(transaction.inputs.len(), 2);
+        assert_eq!(transaction.outputs.len(), 3);
+        assert_eq!(
+            transaction.outputs[2].value,
+            4000 - 3000 - (10 + 2 * 148 + 3 * 34)
+        );
+        stamp
+            .stamp()
+            .verify_stamp(&payload_digest, &destination_public_key)
+            .unwrap();
+
+        // Signatures commit to the spent values
+        let script_code = p2pkh_script(&funding_public_key);
+        let sig_hash = transaction
+            .signature_hash_forkid(1, &script_code, 2000, SignatureHashType::All)
+            .unwrap();
+        let raw_script = transaction.inputs[1].script.as_bytes();
+        let raw_signature = &raw_script[1..raw_script[0] as usize];
+        let signature = secp256k1::Signature::from_der(raw_signature).unwrap();
+        context
+            .verify(
+                &Message::from_slice(&sig_hash).unwrap(),
+                &signature,
+                &funding_public_key,
+            )
+            .unwrap();
+
+        // A second transaction exhausts the unspent outputs
+        let error = StampBuilder::new(destination_public_key, payload_digest, utxos, 1)
+            .with_transaction(vec![3000])
+            .with_transaction(vec![3000])
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            StampBuildError::InsufficientFunds {
+                required: 3000 + 10 + 148 + 34,
+                available: 2000,
+            }
+        );
+    }
+
+    #[test]
+    fn stamp_output_types() {
+        let private_key = PrivateKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let payload_digest = [2; 32];
+
+        // Rewrite the pay-to-pubkey-hash outputs
+        let mut outputs =
+            create_stamp_outputs(&public_key, &payload_digest, 0, &[1000, 2000, 3000]).unwrap();
+        let pubkey_hashes: Vec<_> = outputs
+            .iter()
+            .map(|output| output.script.as_bytes()[3..23].to_vec())
+            .collect();
+        outputs[1].script = p2wpkh_script(&pubkey_hashes[1]);
+        let redeem_script = p2wpkh_script(&pubkey_hashes[2]);
+        outputs[2].script = Script::p2sh(&hash160(redeem_script.as_bytes()));
+
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs,
+            lock_time: 0,
+        };
+        let mut stamp_outpoints = StampOutpoints {
+            stamp_tx: transaction.encode_to_vec(),
+            vouts: vec![0, 1, 2],
+        };
+        let verified = verify_stamp_detailed(
+            &[stamp_outpoints.clone()],
+            &payload_digest,
+            &public_key,
+            StampType::MessageCommitment,
+        )
+        .unwrap();
+        let output_types: Vec<_> = verified
+            .outputs
+            .iter()
+            .map(|output| output.output_type)
+            .collect();
+        assert_eq!(
+            output_types,
+            vec![
+                StampOutputType::P2pkh,
+                StampOutputType::P2wpkh,
+                StampOutputType::P2sh
+            ]
+        );
+
+        // Outputs are derived by position
+        stamp_outpoints.vouts = vec![1];
+        assert!(matches!(
+            verify_stamp(
+                &[stamp_outpoints],
+                &payload_digest,
+                &public_key,
+                StampType::MessageCommitment,
+            ),
+            Err(StampError::UnexpectedAddress(..))
+        ));
+    }
+
+    #[test]
+    fn error_codes_unique() {
+        let errors = [
+            StampError::MissingOutput,
+            StampError::UnsupportedScript,
+            StampError::UnexpectedAddress(vec![], vec![]),
+            StampError::DegenerateCombination,
+            StampError::ChildNumberOverflow,
+            StampError::UnsupportedStampType,
+            StampError::NoneType,
+        ];
+        let codes: std::collections::HashSet<_> = errors.iter().map(StampError::code).collect();
+        let labels: std::collections::HashSet<_> = errors.iter().map(StampError::label).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(labels.len(), errors.len());
+        assert_eq!(StampError::MissingOutput.code(), 1102);
+    }
+}
@@ -0,0 +1,64 @@
+//! This module contains the [`AuthContext`] describing a successfully validated POP token.
+
+use crate::scope::Scope;
+
+/// The identity established by a successfully validated POP token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthContext {
+    /// The POP token.
+    pub token: String,
+    /// The name of the scheme which validated the token.
+    pub scheme: &'static str,
+    /// The public key hash the token is bound to, if known.
+    pub pub_key_hash: Option<Vec<u8>>,
+    /// The scopes the token is bound to, if known.
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthContext {
+    /// Create a new [`AuthContext`] without public key hash or scopes.
+    pub fn new(token: String, scheme: &'static str) -> Self {
+        Self {
+            token,
+            scheme,
+            pub_key_hash: None,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Set the public key hash the token is bound to.
+    pub fn with_pub_key_hash(mut self, pub_key_hash: Vec<u8>) -> Self {
+        self.pub_key_hash = Some(pub_key_hash);
+        self
+    }
+
+    /// Add a scope the token is bound to.
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scopes.push(scope);
+        self
+    }
+
+    /// Whether the token is bound to the given scope.
+    pub fn has_scope(&self, scope: &Scope) -> bool {
+        self.scopes.contains(scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::*;
+
+    #[test]
+    fn scopes() {
+        let messages = Scope::new(Method::GET, "/messages".to_string());
+        let profiles = Scope::new(Method::PUT, "/profiles".to_string());
+        let context = AuthContext::new("abc".to_string(), "hmac")
+            .with_pub_key_hash(vec![0; 20])
+            .with_scope(messages.clone());
+        assert_eq!(context.pub_key_hash, Some(vec![0; 20]));
+        assert!(context.has_scope(&messages));
+        assert!(!context.has_scope(&profiles));
+    }
+}
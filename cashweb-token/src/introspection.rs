@@ -0,0 +1,300 @@
+//! This module contains the [`IntrospectionClient`] which queries the token introspection endpoint
+//! of a server, allowing services to validate POP tokens issued by a sibling service.
+//!
+//! The endpoint is sent the token in the `Authorization` header of a `POST` request and responds
+//! with a JSON [`Introspection`]:
+//!
+//! ```json
+//! {
+//!     "active": true,
+//!     "scheme": "hmac",
+//!     "scopes": ["GET /messages"],
+//!     "exp": 1600000000,
+//!     "pubKeyHash": "..."
+//! }
+//! ```
+//!
+//! The public key hash is URL-safe base64 encoded and the expiry is given in unix seconds.
+
+use std::{convert::TryFrom, fmt};
+
+use body_limit::{read_body_limited, BodyError, StatusError, DEFAULT_EXCERPT_SIZE};
+use http::{header::AUTHORIZATION, Method};
+use hyper::{
+    client::HttpConnector, Body, Client as HyperClient, Error as HyperError, Request, Response,
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::{context::AuthContext, scope::Scope, DEFAULT_SCHEME};
+
+/// The result of introspecting a token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Introspection {
+    /// Whether the token is currently valid.
+    pub active: bool,
+    /// The name of the scheme which validated the token.
+    pub scheme: Option<String>,
+    /// The scopes the token is bound to.
+    pub scopes: Vec<Scope>,
+    /// The time after which the token is invalid. Given in unix seconds.
+    pub expires_at: Option<u64>,
+    /// The public key hash the token is bound to.
+    pub pub_key_hash: Option<Vec<u8>>,
+}
+
+impl Introspection {
+    /// The response for an invalid token.
+    pub fn inactive() -> Self {
+        Self {
+            active: false,
+            scheme: None,
+            scopes: Vec::new(),
+            expires_at: None,
+            pub_key_hash: None,
+        }
+    }
+
+    /// Check whether the token is active and unexpired at the given time.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.active
+            && self
+                .expires_at
+                .map(|expires| now <= expires)
+                .unwrap_or(true)
+    }
+
+    /// Whether the token is bound to the given scope.
+    pub fn has_scope(&self, scope: &Scope) -> bool {
+        self.scopes.contains(scope)
+    }
+
+    /// Serialize to the JSON response of the introspection endpoint.
+    pub fn to_json(&self) -> Vec<u8> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw = RawIntrospection {
+            active: self.active,
+            scheme: self.scheme.clone(),
+            scopes: self
+                .scopes
+                .iter()
+                .map(|scope| format!("{} {}", scope.method, scope.path))
+                .collect(),
+            exp: self.expires_at,
+            pub_key_hash: self
+                .pub_key_hash
+                .as_ref()
+                .map(|pub_key_hash| base64::encode_config(pub_key_hash, url_safe_config)),
+        };
+        serde_json::to_vec(&raw).unwrap() // This is safe
+    }
+}
+
+impl From<&AuthContext> for Introspection {
+    fn from(context: &AuthContext) -> Self {
+        Self {
+            active: true,
+            scheme: Some(context.scheme.to_string()),
+            scopes: context.scopes.clone(),
+            expires_at: None,
+            pub_key_hash: context.pub_key_hash.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RawIntrospection {
+    active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub_key_hash: Option<String>,
+}
+
+/// Error associated with decoding an [`Introspection`].
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    /// Failed to parse the JSON.
+    #[error("json decoding failure: {0}")]
+    Json(serde_json::Error),
+    /// A scope was not of the form `METHOD path`.
+    #[error("invalid scope")]
+    InvalidScope,
+    /// The public key hash was not URL-safe base64.
+    #[error("invalid public key hash: {0}")]
+    PubKeyHash(base64::DecodeError),
+}
+
+impl TryFrom<RawIntrospection> for Introspection {
+    type Error = DecodeError;
+
+    fn try_from(raw: RawIntrospection) -> Result<Self, Self::Error> {
+        let scopes = raw
+            .scopes
+            .iter()
+            .map(|scope| {
+                let mut split = scope.splitn(2, ' ');
+                let method = split
+                    .next()
+                    .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+                    .ok_or(DecodeError::InvalidScope)?;
+                let path = split.next().ok_or(DecodeError::InvalidScope)?;
+                Ok(Scope::new(method, path.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let pub_key_hash = raw
+            .pub_key_hash
+            .map(|pub_key_hash| base64::decode_config(pub_key_hash, url_safe_config))
+            .transpose()
+            .map_err(DecodeError::PubKeyHash)?;
+        Ok(Self {
+            active: raw.active,
+            scheme: raw.scheme,
+            scopes,
+            expires_at: raw.exp,
+            pub_key_hash,
+        })
+    }
+}
+
+impl Introspection {
+    /// Deserialize from the JSON response of the introspection endpoint.
+    pub fn from_json(raw: &[u8]) -> Result<Self, DecodeError> {
+        let raw: RawIntrospection = serde_json::from_slice(raw).map_err(DecodeError::Json)?;
+        Self::try_from(raw)
+    }
+}
+
+/// Error associated with introspecting a token.
+#[derive(Debug, Error)]
+pub enum IntrospectionError<E: fmt::Debug + fmt::Display> {
+    /// The endpoint was an invalid URI.
+    #[error("invalid uri")]
+    Uri,
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(BodyError<HyperError>),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status: {0}")]
+    UnexpectedStatus(StatusError),
+    /// Error while decoding the body.
+    #[error(transparent)]
+    Decode(DecodeError),
+}
+
+/// The default maximum size, in bytes, of an introspection response body.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// IntrospectionClient queries the token introspection endpoints of servers.
+#[derive(Clone, Debug)]
+pub struct IntrospectionClient<S> {
+    inner_client: S,
+    max_body_size: usize,
+}
+
+impl<S> IntrospectionClient<S> {
+    /// Create a new client from a service.
+    pub fn from_service(service: S) -> Self {
+        Self {
+            inner_client: service,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Set the maximum size, in bytes, of response bodies read.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+impl Default for IntrospectionClient<HyperClient<HttpConnector>> {
+    fn default() -> Self {
+        Self {
+            inner_client: HyperClient::new(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl IntrospectionClient<HyperClient<HttpConnector>> {
+    /// Create a new HTTP client.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<S> IntrospectionClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    /// Introspect a POP token using the endpoint given.
+    ///
+    /// An invalid token yields an inactive [`Introspection`] rather than an error.
+    pub async fn introspect(
+        &self,
+        endpoint: &str,
+        token: &str,
+    ) -> Result<Introspection, IntrospectionError<S::Error>> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(endpoint)
+            .header(AUTHORIZATION, format!("{} {}", DEFAULT_SCHEME, token))
+            .body(Body::empty())
+            .map_err(|_| IntrospectionError::Uri)?;
+
+        let response = self
+            .inner_client
+            .clone()
+            .call(request)
+            .await
+            .map_err(IntrospectionError::Service)?;
+        if response.status() != StatusCode::OK {
+            let error = StatusError::from_response(response, DEFAULT_EXCERPT_SIZE).await;
+            return Err(IntrospectionError::UnexpectedStatus(error));
+        }
+        let body = read_body_limited(response.into_body(), self.max_body_size)
+            .await
+            .map_err(IntrospectionError::Body)?;
+        Introspection::from_json(&body).map_err(IntrospectionError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let introspection = Introspection {
+            active: true,
+            scheme: Some("hmac".to_string()),
+            scopes: vec![Scope::new(Method::GET, "/messages".to_string())],
+            expires_at: Some(100),
+            pub_key_hash: Some(vec![1; 20]),
+        };
+        let raw = introspection.to_json();
+        assert_eq!(Introspection::from_json(&raw).unwrap(), introspection);
+        assert!(introspection.is_valid_at(100));
+        assert!(!introspection.is_valid_at(101));
+
+        let inactive = Introspection::from_json(br#"{"active":false}"#).unwrap();
+        assert_eq!(inactive, Introspection::inactive());
+        assert!(matches!(
+            Introspection::from_json(br#"{"active":true,"scopes":["GET"]}"#),
+            Err(DecodeError::InvalidScope)
+        ));
+    }
+}
@@ -7,11 +7,31 @@
 
 //! `cashweb-token` is a library providing utility methods for the [`POP Token Protocol`].
 //!
+//! Tokens issued by a sibling service can be validated by querying its introspection endpoint
+//! using the [`IntrospectionClient`](introspection::IntrospectionClient).
+//!
 //! [`POP Token Protocol`]: https://github.com/cashweb/specifications/blob/master/proof-of-payment-token/specification.mediawiki
 
+pub mod context;
+pub mod introspection;
 pub mod schemes;
+pub mod scope;
+
+pub use context::AuthContext;
+pub use error_code::ErrorCode;
+
+use std::future::Future;
 
-use http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use http::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE},
+    Uri,
+};
+
+/// The default name of the query parameter containing the POP token.
+pub const DEFAULT_QUERY_NAME: &str = "code";
+
+/// The default authorization scheme preceding the POP token.
+pub const DEFAULT_SCHEME: &str = "POP";
 
 /// Extract a POP token from `Authorization` header.
 pub fn extract_pop_header(value: &HeaderValue) -> Option<&str> {
@@ -20,18 +40,233 @@ pub fn extract_pop_header(value: &HeaderValue) -> Option<&str> {
 
 /// Split the POP token, removing the prefix "POP".
 pub fn split_pop_token(full_token: &str) -> Option<&str> {
-    if full_token.len() > 4 && &full_token[..4] == "POP " {
-        return Some(&full_token[4..]);
+    split_token(full_token, DEFAULT_SCHEME)
+}
+
+/// Split the token, removing the scheme prefix and the following space.
+pub fn split_token<'a>(full_token: &'a str, scheme: &str) -> Option<&'a str> {
+    let scheme_len = scheme.len();
+    if full_token.get(..scheme_len)? != scheme {
+        return None;
     }
-    None
+    full_token[scheme_len..]
+        .strip_prefix(' ')
+        .filter(|token| !token.is_empty())
 }
 
 /// Extract the first POP token from [`HeaderMap`].
 pub fn extract_pop(headers: &HeaderMap) -> Option<&str> {
+    extract_header_token(headers, &AUTHORIZATION, DEFAULT_SCHEME)
+}
+
+/// Extract the first token with the given scheme from the headers with the given name.
+pub fn extract_header_token<'a>(
+    headers: &'a HeaderMap,
+    name: &HeaderName,
+    scheme: &str,
+) -> Option<&'a str> {
     headers
-        .get_all(AUTHORIZATION)
+        .get_all(name)
         .iter()
-        .find_map(extract_pop_header)
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|value| split_token(value, scheme))
+}
+
+/// Extract a POP token from a query string using the parameter name given.
+pub fn extract_pop_query<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        let mut split = pair.splitn(2, '=');
+        if split.next()? == name {
+            split.next().filter(|value| !value.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+/// Extract a POP token from the `Cookie` headers using the cookie name given.
+pub fn extract_pop_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let mut split = pair.trim().splitn(2, '=');
+            if split.next()? == name {
+                split.next().filter(|value| !value.is_empty())
+            } else {
+                None
+            }
+        })
+}
+
+/// Provides a common asynchronous interface for validating POP tokens.
+pub trait TokenValidator {
+    /// The data the token is expected to cover.
+    type Data;
+    /// The result of a successful validation.
+    type Output;
+    /// Error associated with token validation.
+    type Error;
+    /// The future returned by [`TokenValidator::validate`].
+    type Future: Future<Output = Result<Self::Output, Self::Error>>;
+
+    /// Validate a token covering the given data.
+    fn validate(&self, data: Self::Data, token: String) -> Self::Future;
+
+    /// Construct the [`AuthContext`] describing a successful validation.
+    ///
+    /// By default this contains only the token.
+    fn auth_context(&self, token: String, _output: &Self::Output) -> AuthContext {
+        AuthContext::new(token, "custom")
+    }
+}
+
+/// Provides a common interface for extracting POP tokens from requests.
+pub trait TokenExtractor {
+    /// Extract a POP token from the request headers and URI.
+    fn extract<'a>(&self, headers: &'a HeaderMap, uri: &'a Uri) -> Option<&'a str>;
+}
+
+/// A location from which a POP token can be extracted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// The configured header, prefixed by the configured scheme.
+    Header,
+    /// The configured query parameter.
+    Query,
+    /// The configured cookie.
+    Cookie,
+}
+
+/// Configuration describing where, and in what order, POP tokens are extracted from.
+///
+/// By default the token is extracted from the `Authorization` header, with the `POP` scheme,
+/// falling back to the `code` query parameter. Cookies are ignored unless a cookie name is set.
+#[derive(Clone, Debug)]
+pub struct ExtractionConfig {
+    header_name: HeaderName,
+    scheme: String,
+    query_name: String,
+    cookie_name: Option<String>,
+    order: Vec<TokenSource>,
+}
+
+impl Default for ExtractionConfig {
+    fn default() -> Self {
+        Self {
+            header_name: AUTHORIZATION,
+            scheme: DEFAULT_SCHEME.to_string(),
+            query_name: DEFAULT_QUERY_NAME.to_string(),
+            cookie_name: None,
+            order: vec![TokenSource::Header, TokenSource::Query, TokenSource::Cookie],
+        }
+    }
+}
+
+impl ExtractionConfig {
+    /// Create the default [`ExtractionConfig`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the name of the header containing the token.
+    pub fn header_name(mut self, header_name: HeaderName) -> Self {
+        self.header_name = header_name;
+        self
+    }
+
+    /// Set the scheme prefixing the token in the header.
+    pub fn scheme(mut self, scheme: String) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Set the name of the query parameter containing the token.
+    pub fn query_name(mut self, query_name: String) -> Self {
+        self.query_name = query_name;
+        self
+    }
+
+    /// Set the name of the cookie containing the token.
+    pub fn cookie_name(mut self, cookie_name: String) -> Self {
+        self.cookie_name = Some(cookie_name);
+        self
+    }
+
+    /// Set the sources to extract from, in order of priority.
+    ///
+    /// Sources not included are ignored.
+    pub fn order(mut self, order: Vec<TokenSource>) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Extract a POP token from a single source.
+    pub fn extract_from<'a>(
+        &self,
+        source: TokenSource,
+        headers: &'a HeaderMap,
+        uri: &'a Uri,
+    ) -> Option<&'a str> {
+        match source {
+            TokenSource::Header => extract_header_token(headers, &self.header_name, &self.scheme),
+            TokenSource::Query => uri
+                .query()
+                .and_then(|query| extract_pop_query(query, &self.query_name)),
+            TokenSource::Cookie => self
+                .cookie_name
+                .as_ref()
+                .and_then(|name| extract_pop_cookie(headers, name)),
+        }
+    }
+}
+
+impl TokenExtractor for ExtractionConfig {
+    fn extract<'a>(&self, headers: &'a HeaderMap, uri: &'a Uri) -> Option<&'a str> {
+        self.order
+            .iter()
+            .find_map(|source| self.extract_from(*source, headers, uri))
+    }
+}
+
+/// Extracts a POP token according to an [`ExtractionConfig`].
+///
+/// By default this extracts from the `Authorization` header, falling back to the query string and
+/// then, if configured, a cookie.
+#[derive(Clone, Debug, Default)]
+pub struct PopTokenExtractor {
+    config: ExtractionConfig,
+}
+
+impl PopTokenExtractor {
+    /// Create a new [`PopTokenExtractor`] which ignores cookies.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a new [`PopTokenExtractor`] from an [`ExtractionConfig`].
+    pub fn from_config(config: ExtractionConfig) -> Self {
+        Self { config }
+    }
+
+    /// Also extract the POP token from the cookie with the given name.
+    pub fn with_cookie(mut self, cookie_name: String) -> Self {
+        self.config = self.config.cookie_name(cookie_name);
+        self
+    }
+
+    /// Get the [`ExtractionConfig`].
+    pub fn config(&self) -> &ExtractionConfig {
+        &self.config
+    }
+}
+
+impl TokenExtractor for PopTokenExtractor {
+    fn extract<'a>(&self, headers: &'a HeaderMap, uri: &'a Uri) -> Option<&'a str> {
+        self.config.extract(headers, uri)
+    }
 }
 
 #[cfg(test)]
@@ -52,4 +287,61 @@ mod tests {
     fn test_split_err() {
         assert_eq!(split_pop_token("ABC d"), None);
     }
+
+    #[test]
+    fn test_split_multibyte() {
+        assert_eq!(split_pop_token("PO\u{e9}abc"), None);
+        assert_eq!(split_pop_token("\u{1f600}"), None);
+        assert_eq!(split_token("Bearer abc", "Bearer"), Some("abc"));
+    }
+
+    #[test]
+    fn test_extract_query() {
+        assert_eq!(extract_pop_query("a=b&code=abc", "code"), Some("abc"));
+        assert_eq!(extract_pop_query("code=", "code"), None);
+        assert_eq!(extract_pop_query("codes=abc", "code"), None);
+    }
+
+    #[test]
+    fn test_extract_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("a=b; pop=abc"));
+        assert_eq!(extract_pop_cookie(&headers, "pop"), Some("abc"));
+        assert_eq!(extract_pop_cookie(&headers, "c"), None);
+    }
+
+    #[test]
+    fn test_extractor_order() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("pop=cookie"));
+        let uri: Uri = "/messages?code=query".parse().unwrap();
+        let extractor = PopTokenExtractor::new().with_cookie("pop".to_string());
+        assert_eq!(extractor.extract(&headers, &uri), Some("query"));
+
+        let uri: Uri = "/messages".parse().unwrap();
+        assert_eq!(extractor.extract(&headers, &uri), Some("cookie"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("POP header"));
+        assert_eq!(extractor.extract(&headers, &uri), Some("header"));
+    }
+
+    #[test]
+    fn test_extraction_config() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("POP header"));
+        headers.insert("x-token", HeaderValue::from_static("Token custom"));
+        let uri: Uri = "/messages?t=query".parse().unwrap();
+
+        let config = ExtractionConfig::new()
+            .header_name(HeaderName::from_static("x-token"))
+            .scheme("Token".to_string())
+            .query_name("t".to_string());
+        assert_eq!(config.extract(&headers, &uri), Some("custom"));
+
+        let config = config.order(vec![TokenSource::Query, TokenSource::Header]);
+        assert_eq!(config.extract(&headers, &uri), Some("query"));
+
+        let config = config.order(vec![TokenSource::Cookie]);
+        assert_eq!(config.extract(&headers, &uri), None);
+    }
 }
@@ -3,18 +3,22 @@
 //!
 //! [`Keyserver Protocol`]: https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki
 
-use std::{convert::TryInto, fmt};
+use std::{convert::TryInto, fmt, future::Future, pin::Pin};
 
 use bitcoin::{
-    prelude::{Transaction, TransactionDecodeError},
-    Decodable,
+    prelude::{Output, Script, Transaction, TransactionDecodeError},
+    transaction::script::opcodes,
+    Decodable, Encodable,
 };
 use bitcoin_client::{BitcoinClient, HttpClient, HttpsClient, NodeError};
+use error_code::ErrorCode;
 use hyper::{Body, Request as HttpRequest, Response as HttpResponse};
 use ring::digest::{Context, SHA256};
 use thiserror::Error;
 use tower_service::Service;
 
+use crate::{AuthContext, TokenValidator};
+
 /// Error associated with token validation.
 #[derive(Debug, Error)]
 pub enum ValidationError<E: fmt::Debug + fmt::Display + 'static> {
@@ -42,12 +46,75 @@ pub enum ValidationError<E: fmt::Debug + fmt::Display + 'static> {
     /// Token was unexpected length.
     #[error("unexpected token length")]
     TokenLength,
+    /// The commitment transaction has insufficient confirmations.
+    #[error("insufficient confirmations: {confirmations} < {required}")]
+    Unconfirmed {
+        /// The current number of confirmations.
+        confirmations: u64,
+        /// The number of confirmations required by the [`ConfirmationPolicy`].
+        required: u32,
+    },
+}
+
+impl<E: fmt::Debug + fmt::Display + 'static> ValidationError<E> {
+    /// Whether the token may become valid at a later time.
+    ///
+    /// Servers should respond with "come back later" rather than "invalid" in this case.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Unconfirmed { .. })
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display + 'static> ErrorCode for ValidationError<E> {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Base64(_) => 2101,
+            Self::IncorrectLength => 2102,
+            Self::Invalid => 2103,
+            Self::Node(err) => err.code(),
+            Self::NotOpReturn => 2104,
+            Self::OutputNotFound => 2105,
+            Self::Transaction(_) => 2106,
+            Self::TokenLength => 2107,
+            Self::Unconfirmed { .. } => 2108,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Base64(_) => "chain_commitment.base64",
+            Self::IncorrectLength => "chain_commitment.incorrect_length",
+            Self::Invalid => "chain_commitment.invalid",
+            Self::Node(err) => err.label(),
+            Self::NotOpReturn => "chain_commitment.not_op_return",
+            Self::OutputNotFound => "chain_commitment.output_not_found",
+            Self::Transaction(_) => "chain_commitment.transaction",
+            Self::TokenLength => "chain_commitment.token_length",
+            Self::Unconfirmed { .. } => "chain_commitment.unconfirmed",
+        }
+    }
+}
+
+/// The confirmation requirements placed on the commitment transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmationPolicy {
+    /// Accept commitments which are still in the mempool.
+    AllowMempool,
+    /// Require the commitment transaction to have a minimum number of confirmations.
+    Confirmations(u32),
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        Self::AllowMempool
+    }
 }
 
 /// Chain commitment scheme used in the keyserver protocol.
 #[derive(Clone, Debug)]
 pub struct ChainCommitmentScheme<S> {
     client: BitcoinClient<S>,
+    policy: ConfirmationPolicy,
 }
 
 const COMMITMENT_LEN: usize = 32;
@@ -60,6 +127,24 @@ pub fn construct_commitment(pub_key_hash: &[u8], address_metadata_hash: &[u8]) -
     sha256_context.finish().as_ref().to_vec()
 }
 
+/// Construct the `OP_RETURN` script containing the commitment.
+pub fn construct_commitment_script(commitment: &[u8]) -> Script {
+    let mut raw_script = Vec::with_capacity(2 + commitment.len());
+    raw_script.push(opcodes::OP_RETURN);
+    raw_script.push(opcodes::OP_PUSHBYTES_32);
+    raw_script.extend_from_slice(commitment);
+    raw_script.into()
+}
+
+/// Construct the zero-valued `OP_RETURN` output containing the commitment.
+pub fn construct_commitment_output(pub_key_hash: &[u8], address_metadata_hash: &[u8]) -> Output {
+    let commitment = construct_commitment(pub_key_hash, address_metadata_hash);
+    Output {
+        value: 0,
+        script: construct_commitment_script(&commitment),
+    }
+}
+
 /// Construct the raw token.
 pub fn construct_token_raw(tx_id: &[u8], vout: u32) -> Vec<u8> {
     [tx_id, &vout.to_le_bytes()[..]].concat()
@@ -75,7 +160,21 @@ pub fn construct_token(tx_id: &[u8], vout: u32) -> String {
 impl<S> ChainCommitmentScheme<S> {
     /// Create a [`ChainCommitmentScheme`] from a [`BitcoinClient`].
     pub fn from_client(client: BitcoinClient<S>) -> Self {
-        ChainCommitmentScheme { client }
+        ChainCommitmentScheme {
+            client,
+            policy: ConfirmationPolicy::default(),
+        }
+    }
+
+    /// Set the [`ConfirmationPolicy`] applied during validation.
+    pub fn with_confirmation_policy(mut self, policy: ConfirmationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Get the [`ConfirmationPolicy`] applied during validation.
+    pub fn confirmation_policy(&self) -> ConfirmationPolicy {
+        self.policy
     }
 }
 
@@ -84,6 +183,7 @@ impl ChainCommitmentScheme<HttpClient> {
     pub fn new(endpoint: String, username: String, password: String) -> Self {
         Self {
             client: BitcoinClient::new(endpoint, username, password),
+            policy: ConfirmationPolicy::default(),
         }
     }
 }
@@ -93,10 +193,66 @@ impl ChainCommitmentScheme<HttpsClient> {
     pub fn new_tls(endpoint: String, username: String, password: String) -> Self {
         Self {
             client: BitcoinClient::new_tls(endpoint, username, password),
+            policy: ConfirmationPolicy::default(),
         }
     }
 }
 
+/// Validate that the output at `vout` of the raw transaction commits to the public key hash and
+/// address metadata hash.
+fn validate_commitment<E: fmt::Debug + fmt::Display + 'static>(
+    raw_transaction: &[u8],
+    vout: u32,
+    pub_key_hash: &[u8],
+    address_metadata_hash: &[u8],
+) -> Result<(), ValidationError<E>> {
+    let transaction =
+        Transaction::decode(&mut &raw_transaction[..]).map_err(ValidationError::Transaction)?;
+
+    // Parse script
+    let output = transaction
+        .outputs
+        .get(vout as usize)
+        .ok_or(ValidationError::OutputNotFound)?;
+
+    if !output.script.is_op_return() {
+        return Err(ValidationError::NotOpReturn);
+    }
+
+    let raw_script = output.script.as_bytes();
+
+    // Check length
+    if raw_script.len() != 2 + COMMITMENT_LEN || raw_script[1] != COMMITMENT_LEN as u8 {
+        return Err(ValidationError::IncorrectLength);
+    }
+
+    // Check commitment
+    let commitment = &raw_script[2..34];
+    let expected_commitment = construct_commitment(pub_key_hash, address_metadata_hash);
+    if expected_commitment != commitment {
+        return Err(ValidationError::Invalid);
+    }
+    Ok(())
+}
+
+/// Check the confirmation depth of the commitment transaction against the [`ConfirmationPolicy`].
+fn check_confirmations<E: fmt::Debug + fmt::Display + 'static>(
+    policy: ConfirmationPolicy,
+    confirmations: Option<u64>,
+) -> Result<(), ValidationError<E>> {
+    match (policy, confirmations) {
+        (ConfirmationPolicy::Confirmations(required), Some(confirmations))
+            if confirmations < required as u64 =>
+        {
+            Err(ValidationError::Unconfirmed {
+                confirmations,
+                required,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 impl<S> ChainCommitmentScheme<S>
 where
     S: Service<HttpRequest<Body>, Response = HttpResponse<Body>> + Clone,
@@ -123,42 +279,232 @@ where
         // Parse ID
         let tx_id = &outpoint_raw[..32];
 
-        // Get transaction
-        let raw_transaction = self
-            .client
-            .get_raw_transaction(tx_id)
-            .await
-            .map_err(ValidationError::Node)?;
-        let transaction = Transaction::decode(&mut raw_transaction.as_slice())
-            .map_err(ValidationError::Transaction)?;
-
         // Get vout
         let vout_raw: [u8; 4] = outpoint_raw[32..36].try_into().unwrap(); // This is safe
         let vout = u32::from_le_bytes(vout_raw);
 
-        // Parse script
-        let output = transaction
-            .outputs
-            .get(vout as usize)
-            .ok_or(ValidationError::OutputNotFound)?;
+        // Get transaction
+        let (raw_transaction, confirmations) = match self.policy {
+            ConfirmationPolicy::AllowMempool => {
+                let raw_transaction = self
+                    .client
+                    .get_raw_transaction(tx_id)
+                    .await
+                    .map_err(ValidationError::Node)?;
+                (raw_transaction, None)
+            }
+            ConfirmationPolicy::Confirmations(_) => {
+                let verbose_transaction = self
+                    .client
+                    .get_raw_transaction_verbose(tx_id)
+                    .await
+                    .map_err(ValidationError::Node)?;
+                let raw_transaction = verbose_transaction
+                    .raw_transaction()
+                    .map_err(|err| ValidationError::Node(err.into()))?;
+                (raw_transaction, Some(verbose_transaction.confirmations))
+            }
+        };
 
-        if !output.script.is_op_return() {
-            return Err(ValidationError::NotOpReturn);
-        }
+        // Check commitment before confirmation depth, so that invalid tokens are never reported
+        // as pending
+        validate_commitment(&raw_transaction, vout, pub_key_hash, address_metadata_hash)?;
+        check_confirmations(self.policy, confirmations)?;
+
+        Ok(outpoint_raw)
+    }
+}
+
+/// The result of a successful chain commitment token validation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitmentOutput {
+    /// The raw outpoint of the commitment.
+    pub outpoint: Vec<u8>,
+    /// The public key hash committed to.
+    pub pub_key_hash: Vec<u8>,
+}
 
-        let raw_script = output.script.as_bytes();
+/// The data covered by a chain commitment token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommitmentData {
+    /// The public key hash of the address.
+    pub pub_key_hash: Vec<u8>,
+    /// The digest of the address metadata.
+    pub address_metadata_hash: Vec<u8>,
+}
 
-        // Check length
-        if raw_script.len() != 2 + COMMITMENT_LEN || raw_script[1] != COMMITMENT_LEN as u8 {
-            return Err(ValidationError::IncorrectLength);
-        }
+impl<S> TokenValidator for ChainCommitmentScheme<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<Body>> + Clone,
+    S: Send + Sync + 'static,
+    S::Error: fmt::Debug + fmt::Display + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Data = CommitmentData;
+    type Output = CommitmentOutput;
+    type Error = ValidationError<S::Error>;
+    #[allow(clippy::type_complexity)]
+    type Future =
+        Pin<Box<dyn Future<Output = Result<CommitmentOutput, Self::Error>> + Send + 'static>>;
+
+    fn validate(&self, data: CommitmentData, token: String) -> Self::Future {
+        let scheme = self.clone();
+        Box::pin(async move {
+            let outpoint = scheme
+                .validate_token(&data.pub_key_hash, &data.address_metadata_hash, &token)
+                .await?;
+            Ok(CommitmentOutput {
+                outpoint,
+                pub_key_hash: data.pub_key_hash,
+            })
+        })
+    }
+
+    fn auth_context(&self, token: String, output: &CommitmentOutput) -> AuthContext {
+        AuthContext::new(token, "chain-commitment").with_pub_key_hash(output.pub_key_hash.clone())
+    }
+}
+
+/// Error associated with issuing a commitment token.
+#[derive(Debug, Error)]
+pub enum IssueError<E: fmt::Debug + fmt::Display + 'static> {
+    /// Error occured when communicating with bitcoind.
+    #[error(transparent)]
+    Node(NodeError<E>),
+    /// Error decoding the funded or signed transaction.
+    #[error("failed to decode transaction: {0}")]
+    Transaction(TransactionDecodeError),
+    /// The wallet was unable to sign all inputs.
+    #[error("incomplete signatures")]
+    IncompleteSignatures,
+    /// The commitment output was missing from the signed transaction.
+    #[error("commitment output missing")]
+    CommitmentMissing,
+}
 
-        // Check commitment
-        let commitment = &raw_script[2..34];
-        let expected_commitment = construct_commitment(pub_key_hash, address_metadata_hash);
-        if expected_commitment != commitment {
-            return Err(ValidationError::Invalid);
+/// Issue a commitment transaction using the bitcoind wallet and return the token.
+///
+/// The commitment output is funded via `fundrawtransaction`, signed via `signrawtransactionwithwallet`
+/// and then broadcast.
+pub async fn issue_token<S>(
+    client: &BitcoinClient<S>,
+    pub_key_hash: &[u8],
+    address_metadata_hash: &[u8],
+) -> Result<String, IssueError<S::Error>>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<Body>> + Clone,
+    S::Error: fmt::Debug + fmt::Display + 'static,
+    S::Future: Send + 'static,
+{
+    // Construct unfunded transaction
+    let commitment_output = construct_commitment_output(pub_key_hash, address_metadata_hash);
+    let transaction = Transaction {
+        version: 2,
+        inputs: vec![],
+        outputs: vec![commitment_output.clone()],
+        lock_time: 0,
+    };
+    let raw_transaction = transaction.encode_to_bytes();
+
+    // Fund transaction
+    let funded_transaction = client
+        .fund_raw_transaction(&raw_transaction)
+        .await
+        .map_err(IssueError::Node)?;
+    let raw_funded = funded_transaction
+        .raw_transaction()
+        .map_err(|err| IssueError::Node(err.into()))?;
+
+    // Sign transaction
+    let signed_transaction = client
+        .sign_raw_transaction_with_wallet(&raw_funded)
+        .await
+        .map_err(IssueError::Node)?;
+    if !signed_transaction.complete {
+        return Err(IssueError::IncompleteSignatures);
+    }
+    let raw_signed = signed_transaction
+        .raw_transaction()
+        .map_err(|err| IssueError::Node(err.into()))?;
+    let transaction =
+        Transaction::decode(&mut raw_signed.as_slice()).map_err(IssueError::Transaction)?;
+
+    // Find commitment output, the change output may have been inserted before it
+    let vout = transaction
+        .outputs
+        .iter()
+        .position(|output| *output == commitment_output)
+        .ok_or(IssueError::CommitmentMissing)? as u32;
+
+    // Broadcast transaction
+    client
+        .send_tx(&raw_signed)
+        .await
+        .map_err(IssueError::Node)?;
+
+    let tx_id = transaction.transaction_id();
+    Ok(construct_token(&tx_id, vout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commitment_script() {
+        let output = construct_commitment_output(&[0; 20], &[1; 32]);
+        let script = output.script;
+        assert!(script.is_op_return());
+        assert_eq!(script.len(), 2 + COMMITMENT_LEN);
+        assert_eq!(script.as_bytes()[1], COMMITMENT_LEN as u8);
+        assert_eq!(
+            &script.as_bytes()[2..],
+            &construct_commitment(&[0; 20], &[1; 32])[..]
+        );
+    }
+
+    type TestError = ValidationError<std::convert::Infallible>;
+
+    fn commitment_transaction(pub_key_hash: &[u8], address_metadata_hash: &[u8]) -> Vec<u8> {
+        Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: vec![
+                Output {
+                    value: 1000,
+                    script: vec![opcodes::OP_RETURN].into(),
+                },
+                construct_commitment_output(pub_key_hash, address_metadata_hash),
+            ],
+            lock_time: 0,
         }
-        Ok(outpoint_raw)
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn validate_commitment_output() {
+        let raw_transaction = commitment_transaction(&[0; 20], &[1; 32]);
+        validate_commitment::<std::convert::Infallible>(&raw_transaction, 1, &[0; 20], &[1; 32])
+            .unwrap();
+
+        let err: TestError =
+            validate_commitment(&raw_transaction, 1, &[0; 20], &[2; 32]).unwrap_err();
+        assert!(matches!(err, ValidationError::Invalid));
+        let err: TestError =
+            validate_commitment(&raw_transaction, 0, &[0; 20], &[1; 32]).unwrap_err();
+        assert!(matches!(err, ValidationError::IncorrectLength));
+        let err: TestError =
+            validate_commitment(&raw_transaction, 2, &[0; 20], &[1; 32]).unwrap_err();
+        assert!(matches!(err, ValidationError::OutputNotFound));
+    }
+
+    #[test]
+    fn confirmation_depth() {
+        let policy = ConfirmationPolicy::Confirmations(3);
+        let err: TestError = check_confirmations(policy, Some(2)).unwrap_err();
+        assert!(err.is_pending());
+        check_confirmations::<std::convert::Infallible>(policy, Some(3)).unwrap();
+        check_confirmations::<std::convert::Infallible>(ConfirmationPolicy::AllowMempool, None)
+            .unwrap();
     }
 }
@@ -1,8 +1,22 @@
 //! This module contains [`HmacScheme`] which provides a rudimentary HMAC validation scheme.
+//!
+//! The [`HmacScheme`] supports key rotation. When the signing key has a key ID it is prepended to
+//! the tag, allowing tokens signed by older, but still accepted, keys to be validated.
 
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+};
+
+use error_code::ErrorCode;
 use ring::hmac;
 use thiserror::Error;
 
+use crate::{AuthContext, TokenValidator};
+
+/// Length of the HMAC-SHA256 tag.
+const TAG_LEN: usize = 32;
+
 /// Error associated with basic HMAC token validation.
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum ValidationError {
@@ -12,32 +26,184 @@ pub enum ValidationError {
     /// Token was invalid.
     #[error("invalid token")]
     Invalid,
+    /// Token was signed by a key which is not accepted.
+    #[error("unknown key id: {0:?}")]
+    UnknownKey(Option<u8>),
+}
+
+impl ErrorCode for ValidationError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Base64(_) => 2001,
+            Self::Invalid => 2002,
+            Self::UnknownKey(_) => 2003,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Base64(_) => "hmac.base64",
+            Self::Invalid => "hmac.invalid",
+            Self::UnknownKey(_) => "hmac.unknown_key",
+        }
+    }
 }
 
 /// Basic HMAC token scheme.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HmacScheme {
+    key_id: Option<u8>,
     key: hmac::Key,
+    verification_keys: HashMap<Option<u8>, hmac::Key>,
 }
 
 impl HmacScheme {
     /// Create a new HMAC scheme using a speficied secret key.
+    ///
+    /// Tokens constructed using this scheme do not contain a key ID.
     pub fn new(key: &[u8]) -> Self {
+        Self::from_key(None, key)
+    }
+
+    /// Create a new HMAC scheme using a speficied secret key and key ID.
+    ///
+    /// The key ID is embedded in each token constructed.
+    pub fn with_key_id(key_id: u8, key: &[u8]) -> Self {
+        Self::from_key(Some(key_id), key)
+    }
+
+    fn from_key(key_id: Option<u8>, key: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        let mut verification_keys = HashMap::new();
+        verification_keys.insert(key_id, key.clone());
+        Self {
+            key_id,
+            key,
+            verification_keys,
+        }
+    }
+
+    /// The key ID of the signing key.
+    pub fn key_id(&self) -> Option<u8> {
+        self.key_id
+    }
+
+    /// Accept tokens signed by an additional key.
+    ///
+    /// Pass `None` as the key ID to accept tokens without an embedded key ID.
+    pub fn add_verification_key(&mut self, key_id: Option<u8>, key: &[u8]) {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        self.verification_keys.insert(key_id, key);
+    }
+
+    /// Stop accepting tokens signed by a key.
+    ///
+    /// The signing key cannot be removed, in this case `false` is returned.
+    pub fn remove_verification_key(&mut self, key_id: Option<u8>) -> bool {
+        if key_id == self.key_id {
+            return false;
+        }
+        self.verification_keys.remove(&key_id).is_some()
+    }
+
+    /// Replace the signing key.
+    ///
+    /// The previous signing key continues to be accepted until removed using
+    /// [`remove_verification_key`](HmacScheme::remove_verification_key).
+    pub fn rotate(&mut self, key_id: u8, key: &[u8]) {
         let key = hmac::Key::new(hmac::HMAC_SHA256, key);
-        Self { key }
+        self.verification_keys.insert(Some(key_id), key.clone());
+        self.key_id = Some(key_id);
+        self.key = key;
     }
 
     /// Construct a token.
     pub fn construct_token(&self, data: &[u8]) -> String {
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
         let tag = hmac::sign(&self.key, data);
-        base64::encode_config(tag.as_ref(), url_safe_config)
+        match self.key_id {
+            Some(key_id) => {
+                let raw_token = [&[key_id][..], tag.as_ref()].concat();
+                base64::encode_config(raw_token, url_safe_config)
+            }
+            None => base64::encode_config(tag.as_ref(), url_safe_config),
+        }
     }
 
     /// Validate a token.
     pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let tag = base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
-        hmac::verify(&self.key, data, &tag).map_err(|_| ValidationError::Invalid)
+        let raw_token =
+            base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
+
+        // Split key ID from tag
+        let (key_id, tag) = match raw_token.len() {
+            TAG_LEN => (None, &raw_token[..]),
+            len if len == TAG_LEN + 1 => (Some(raw_token[0]), &raw_token[1..]),
+            _ => return Err(ValidationError::Invalid),
+        };
+
+        let key = self
+            .verification_keys
+            .get(&key_id)
+            .ok_or(ValidationError::UnknownKey(key_id))?;
+        hmac::verify(key, data, tag).map_err(|_| ValidationError::Invalid)
+    }
+}
+
+impl TokenValidator for HmacScheme {
+    type Data = Vec<u8>;
+    type Output = ();
+    type Error = ValidationError;
+    type Future = Ready<Result<(), ValidationError>>;
+
+    fn validate(&self, data: Vec<u8>, token: String) -> Self::Future {
+        ready(self.validate_token(&data, &token))
+    }
+
+    fn auth_context(&self, token: String, _output: &()) -> AuthContext {
+        AuthContext::new(token, "hmac")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        let mut scheme = HmacScheme::new(b"legacy");
+        let legacy_token = scheme.construct_token(b"data");
+        scheme.validate_token(b"data", &legacy_token).unwrap();
+
+        scheme.rotate(1, b"first");
+        let first_token = scheme.construct_token(b"data");
+        scheme.validate_token(b"data", &legacy_token).unwrap();
+        scheme.validate_token(b"data", &first_token).unwrap();
+
+        assert!(scheme.remove_verification_key(None));
+        assert!(!scheme.remove_verification_key(Some(1)));
+        assert_eq!(
+            scheme.validate_token(b"data", &legacy_token),
+            Err(ValidationError::UnknownKey(None))
+        );
+        assert_eq!(
+            scheme.validate_token(b"other", &first_token),
+            Err(ValidationError::Invalid)
+        );
+    }
+
+    #[test]
+    fn token_validator() {
+        let scheme = HmacScheme::new(b"key");
+        let token = scheme.construct_token(b"data");
+        TokenValidator::validate(&scheme, b"data".to_vec(), token.clone())
+            .into_inner()
+            .unwrap();
+        assert_eq!(
+            TokenValidator::validate(&scheme, b"other".to_vec(), token.clone()).into_inner(),
+            Err(ValidationError::Invalid)
+        );
+        assert_eq!(scheme.auth_context(token, &()).scheme, "hmac");
     }
 }
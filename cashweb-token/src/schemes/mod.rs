@@ -2,3 +2,6 @@
 
 pub mod chain_commitment;
 pub mod hmac_bearer;
+pub mod pow;
+pub mod pubkey_bound;
+pub mod time_bucket;
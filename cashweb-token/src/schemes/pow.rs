@@ -0,0 +1,139 @@
+//! This module contains [`PowScheme`] which provides a hashcash-style proof-of-work token scheme.
+//!
+//! Tokens are nonces such that `SHA-256(data || nonce)` has at least `difficulty` leading zero bits.
+//! This acts as a free-tier alternative to proof-of-payment for low-value operations. The data
+//! should be bound to the request, for example using [`Scope::scoped_data`].
+//!
+//! [`Scope::scoped_data`]: crate::scope::Scope::scoped_data
+
+use std::{
+    convert::TryInto,
+    future::{ready, Ready},
+};
+
+use ring::digest::{Context, SHA256};
+use thiserror::Error;
+
+use crate::{AuthContext, TokenValidator};
+
+const NONCE_LEN: usize = 8;
+
+/// Error associated with proof-of-work token validation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Failed to decode token.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Token was unexpected length.
+    #[error("unexpected token length")]
+    TokenLength,
+    /// The digest did not meet the difficulty target.
+    #[error("insufficient work: {0} < {1}")]
+    InsufficientWork(u32, u32),
+}
+
+/// Proof-of-work token scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PowScheme {
+    difficulty: u32,
+}
+
+/// Count the leading zero bits of a digest.
+fn leading_zeros(digest: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in digest {
+        if *byte == 0 {
+            count += 8;
+        } else {
+            count += byte.leading_zeros();
+            break;
+        }
+    }
+    count
+}
+
+/// Calculate the work of a nonce over data, this is the number of leading zero bits of `SHA-256(data || nonce)`.
+pub fn calculate_work(data: &[u8], nonce: u64) -> u32 {
+    let mut sha256_context = Context::new(&SHA256);
+    sha256_context.update(data);
+    sha256_context.update(&nonce.to_le_bytes());
+    leading_zeros(sha256_context.finish().as_ref())
+}
+
+impl PowScheme {
+    /// Create a new proof-of-work scheme requiring `difficulty` leading zero bits.
+    pub fn new(difficulty: u32) -> Self {
+        Self { difficulty }
+    }
+
+    /// The number of leading zero bits required.
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    /// Construct a token, searching at most `max_iterations` nonces.
+    pub fn construct_token_bounded(&self, data: &[u8], max_iterations: u64) -> Option<String> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        (0..max_iterations)
+            .find(|nonce| calculate_work(data, *nonce) >= self.difficulty)
+            .map(|nonce| base64::encode_config(nonce.to_le_bytes(), url_safe_config))
+    }
+
+    /// Construct a token.
+    ///
+    /// The expected number of iterations is `2^difficulty`.
+    pub fn construct_token(&self, data: &[u8]) -> String {
+        self.construct_token_bounded(data, u64::MAX).unwrap() // This is safe for reasonable difficulties
+    }
+
+    /// Validate a token.
+    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_nonce =
+            base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
+        let raw_nonce: [u8; NONCE_LEN] = raw_nonce[..]
+            .try_into()
+            .map_err(|_| ValidationError::TokenLength)?;
+        let nonce = u64::from_le_bytes(raw_nonce);
+
+        let work = calculate_work(data, nonce);
+        if work < self.difficulty {
+            return Err(ValidationError::InsufficientWork(work, self.difficulty));
+        }
+        Ok(())
+    }
+}
+
+impl TokenValidator for PowScheme {
+    type Data = Vec<u8>;
+    type Output = ();
+    type Error = ValidationError;
+    type Future = Ready<Result<(), ValidationError>>;
+
+    fn validate(&self, data: Vec<u8>, token: String) -> Self::Future {
+        ready(self.validate_token(&data, &token))
+    }
+
+    fn auth_context(&self, token: String, _output: &()) -> AuthContext {
+        AuthContext::new(token, "pow")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn construct_validate() {
+        let scheme = PowScheme::new(8);
+        let token = scheme.construct_token(b"GET /messages");
+        scheme.validate_token(b"GET /messages", &token).unwrap();
+    }
+
+    #[test]
+    fn leading_zero_bits() {
+        assert_eq!(leading_zeros(&[0, 0x80]), 8);
+        assert_eq!(leading_zeros(&[0, 0x0f]), 12);
+        assert_eq!(leading_zeros(&[0x01]), 7);
+    }
+}
@@ -0,0 +1,454 @@
+//! This module contains [`PubkeyBoundScheme`] which wraps the [`HmacScheme`] to bind tokens to a
+//! public key held by the client.
+//!
+//! A bearer token alone is sufficient to access the resource it was issued for, so a stolen token
+//! grants access to, for example, a paid relay inbox. Tokens issued by this scheme instead cover
+//! `data || public_key`, where the compressed public key is supplied by the client when paying.
+//! Each request must then carry a [`RequestSignature`] by that key over the timestamp, method, path,
+//! body digest and token of the request, see [`RequestSignature::digest`], with the timestamp lying
+//! within the permitted skew of the current time.
+//!
+//! Tokens take the form `base64(public_key) "." inner_token`.
+
+use std::{
+    convert::TryInto,
+    future::{ready, Ready},
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::Duration,
+};
+
+use clock::{system_clock, Clock, SharedClock};
+use error_code::ErrorCode;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use ring::digest::{Context, SHA256};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Message, Secp256k1, Signature, VerifyOnly,
+};
+use thiserror::Error;
+
+use super::hmac_bearer::{HmacScheme, ValidationError as HmacError};
+use crate::{AuthContext, TokenValidator};
+
+/// The default maximum difference, in either direction, between the request timestamp and the
+/// current time.
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(60);
+
+/// The name of the header containing the request timestamp, in unix seconds.
+pub const TIMESTAMP_HEADER: &str = "x-pop-timestamp";
+
+/// The name of the header containing the request signature.
+pub const SIGNATURE_HEADER: &str = "x-pop-signature";
+
+/// Error associated with public key bound token validation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Failed to decode token.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Token was missing the public key.
+    #[error("malformed token")]
+    Malformed,
+    /// The public key within the token was invalid.
+    #[error(transparent)]
+    PublicKey(SecpError),
+    /// The inner token was invalid.
+    #[error(transparent)]
+    Token(HmacError),
+    /// The request was missing its signature.
+    #[error("missing request signature")]
+    MissingSignature,
+    /// The request timestamp was outside of the permitted skew.
+    #[error("request timestamp outside of permitted skew: {0}")]
+    Skew(u64),
+    /// The request signature failed verification.
+    #[error(transparent)]
+    InvalidSignature(SecpError),
+}
+
+impl ErrorCode for ValidationError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Base64(_) => 2201,
+            Self::Malformed => 2202,
+            Self::PublicKey(_) => 2203,
+            Self::Token(_) => 2204,
+            Self::MissingSignature => 2205,
+            Self::Skew(_) => 2206,
+            Self::InvalidSignature(_) => 2207,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Base64(_) => "pubkey_bound.base64",
+            Self::Malformed => "pubkey_bound.malformed",
+            Self::PublicKey(_) => "pubkey_bound.public_key",
+            Self::Token(_) => "pubkey_bound.token",
+            Self::MissingSignature => "pubkey_bound.missing_signature",
+            Self::Skew(_) => "pubkey_bound.skew",
+            Self::InvalidSignature(_) => "pubkey_bound.invalid_signature",
+        }
+    }
+}
+
+/// Calculate the digest of a request body, this is `SHA256(body)`.
+pub fn body_digest(body: &[u8]) -> [u8; 32] {
+    let mut context = Context::new(&SHA256);
+    context.update(body);
+    context.finish().as_ref().try_into().unwrap() // This is safe
+}
+
+/// A signature, by the public key a token is bound to, over the request timestamp, method, path,
+/// body digest and token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestSignature {
+    /// The unix time, in seconds, the request was made.
+    pub timestamp: u64,
+    /// The signature over the digest of the request.
+    pub signature: Signature,
+}
+
+impl Hash for RequestSignature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp.hash(state);
+        self.signature.serialize_compact().hash(state);
+    }
+}
+
+impl RequestSignature {
+    /// Calculate the digest covered by the signature, this is
+    /// `SHA256(timestamp || method || 0 || path || 0 || body_digest || token)` where the timestamp is
+    /// a big-endian 64-bit integer and the body digest is given by [`body_digest`].
+    pub fn digest(
+        timestamp: u64,
+        method: &str,
+        path: &str,
+        body_digest: &[u8; 32],
+        token: &str,
+    ) -> [u8; 32] {
+        let mut context = Context::new(&SHA256);
+        context.update(&timestamp.to_be_bytes());
+        context.update(method.as_bytes());
+        context.update(&[0]);
+        context.update(path.as_bytes());
+        context.update(&[0]);
+        context.update(body_digest);
+        context.update(token.as_bytes());
+        context.finish().as_ref().try_into().unwrap() // This is safe
+    }
+
+    /// Sign a request using the private key the token is bound to.
+    pub fn sign(
+        private_key: &SecretKey,
+        timestamp: u64,
+        method: &str,
+        path: &str,
+        body_digest: &[u8; 32],
+        token: &str,
+    ) -> Self {
+        let digest = Self::digest(timestamp, method, path, body_digest, token);
+        let msg = Message::from_slice(&digest).unwrap(); // This is safe
+        let signature = Secp256k1::signing_only().sign(&msg, private_key);
+        Self {
+            timestamp,
+            signature,
+        }
+    }
+
+    /// Extract the [`RequestSignature`] from the [`TIMESTAMP_HEADER`] and [`SIGNATURE_HEADER`].
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let timestamp = headers.get(TIMESTAMP_HEADER)?.to_str().ok()?.parse().ok()?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_signature =
+            base64::decode_config(headers.get(SIGNATURE_HEADER)?.as_bytes(), url_safe_config)
+                .ok()?;
+        let signature = Signature::from_compact(&raw_signature).ok()?;
+        Some(Self {
+            timestamp,
+            signature,
+        })
+    }
+
+    /// Insert the [`TIMESTAMP_HEADER`] and [`SIGNATURE_HEADER`] into a [`HeaderMap`].
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let signature =
+            base64::encode_config(&self.signature.serialize_compact()[..], url_safe_config);
+        // These are safe
+        headers.insert(
+            HeaderName::from_static(TIMESTAMP_HEADER),
+            HeaderValue::from(self.timestamp),
+        );
+        headers.insert(
+            HeaderName::from_static(SIGNATURE_HEADER),
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+    }
+}
+
+/// A request made using a public key bound token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BoundRequest {
+    /// The data the token is expected to cover.
+    pub data: Vec<u8>,
+    /// The method of the request.
+    pub method: String,
+    /// The path of the request.
+    pub path: String,
+    /// The digest of the request body, see [`body_digest`].
+    pub body_digest: [u8; 32],
+    /// The signature attached to the request.
+    pub signature: Option<RequestSignature>,
+}
+
+/// HMAC token scheme binding tokens to a client public key.
+#[derive(Clone, Debug)]
+pub struct PubkeyBoundScheme {
+    inner: HmacScheme,
+    max_skew: u64,
+    secp: Secp256k1<VerifyOnly>,
+    clock: SharedClock,
+}
+
+impl PubkeyBoundScheme {
+    /// Create a new [`PubkeyBoundScheme`] from a [`HmacScheme`], using the [`DEFAULT_MAX_SKEW`].
+    pub fn new(inner: HmacScheme) -> Self {
+        Self {
+            inner,
+            max_skew: DEFAULT_MAX_SKEW.as_secs(),
+            secp: Secp256k1::verification_only(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Set the maximum difference between the request timestamp and the current time.
+    ///
+    /// The skew is truncated to whole seconds.
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew.as_secs();
+        self
+    }
+
+    /// Set the [`Clock`] the request timestamp is compared against, the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Get a reference to the inner [`HmacScheme`].
+    pub fn inner(&self) -> &HmacScheme {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner [`HmacScheme`], allowing key rotation.
+    pub fn inner_mut(&mut self) -> &mut HmacScheme {
+        &mut self.inner
+    }
+
+    /// Construct the data to be covered by the inner token, this is `data || public_key` where the
+    /// public key is compressed.
+    pub fn bound_data(data: &[u8], public_key: &PublicKey) -> Vec<u8> {
+        [data, &public_key.serialize()[..]].concat()
+    }
+
+    /// Construct a token bound to a public key.
+    pub fn construct_token(&self, data: &[u8], public_key: &PublicKey) -> String {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let inner_token = self
+            .inner
+            .construct_token(&Self::bound_data(data, public_key));
+        format!(
+            "{}.{}",
+            base64::encode_config(&public_key.serialize()[..], url_safe_config),
+            inner_token
+        )
+    }
+
+    /// Validate a token, returning the public key it is bound to.
+    ///
+    /// This does not check the request signature, see
+    /// [`validate_request`](PubkeyBoundScheme::validate_request).
+    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<PublicKey, ValidationError> {
+        let mut split = token.splitn(2, '.');
+        let raw_public_key = split.next().ok_or(ValidationError::Malformed)?;
+        let inner_token = split.next().ok_or(ValidationError::Malformed)?;
+
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_public_key = base64::decode_config(raw_public_key, url_safe_config)
+            .map_err(ValidationError::Base64)?;
+        let public_key =
+            PublicKey::from_slice(&raw_public_key).map_err(ValidationError::PublicKey)?;
+
+        self.inner
+            .validate_token(&Self::bound_data(data, &public_key), inner_token)
+            .map_err(ValidationError::Token)?;
+        Ok(public_key)
+    }
+
+    /// Validate a token and the request signature, returning the public key it is bound to.
+    pub fn validate_request(
+        &self,
+        request: &BoundRequest,
+        token: &str,
+    ) -> Result<PublicKey, ValidationError> {
+        self.validate_request_at(request, token, self.clock.unix_now())
+    }
+
+    /// Validate a token and the request signature against a unix time, given in seconds.
+    pub fn validate_request_at(
+        &self,
+        request: &BoundRequest,
+        token: &str,
+        now: u64,
+    ) -> Result<PublicKey, ValidationError> {
+        let public_key = self.validate_token(&request.data, token)?;
+
+        // Check timestamp
+        let signature = request
+            .signature
+            .as_ref()
+            .ok_or(ValidationError::MissingSignature)?;
+        let skew = if signature.timestamp > now {
+            signature.timestamp - now
+        } else {
+            now - signature.timestamp
+        };
+        if skew > self.max_skew {
+            return Err(ValidationError::Skew(signature.timestamp));
+        }
+
+        // Verify signature
+        let digest = RequestSignature::digest(
+            signature.timestamp,
+            &request.method,
+            &request.path,
+            &request.body_digest,
+            token,
+        );
+        let msg = Message::from_slice(&digest).unwrap(); // This is safe
+        self.secp
+            .verify(&msg, &signature.signature, &public_key)
+            .map_err(ValidationError::InvalidSignature)?;
+        Ok(public_key)
+    }
+}
+
+impl TokenValidator for PubkeyBoundScheme {
+    type Data = BoundRequest;
+    type Output = PublicKey;
+    type Error = ValidationError;
+    type Future = Ready<Result<PublicKey, ValidationError>>;
+
+    fn validate(&self, request: BoundRequest, token: String) -> Self::Future {
+        ready(self.validate_request(&request, &token))
+    }
+
+    fn auth_context(&self, token: String, _output: &PublicKey) -> AuthContext {
+        AuthContext::new(token, "pubkey-bound")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bound_request() {
+        let scheme = PubkeyBoundScheme::new(HmacScheme::new(b"key"));
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let token = scheme.construct_token(b"inbox", &public_key);
+
+        let body = body_digest(b"body");
+        let mut headers = HeaderMap::new();
+        RequestSignature::sign(&private_key, 1_000, "PUT", "/messages", &body, &token)
+            .insert_headers(&mut headers);
+        let mut request = BoundRequest {
+            data: b"inbox".to_vec(),
+            method: "PUT".to_string(),
+            path: "/messages".to_string(),
+            body_digest: body,
+            signature: RequestSignature::from_headers(&headers),
+        };
+        assert_eq!(
+            scheme.validate_request_at(&request, &token, 1_030).unwrap(),
+            public_key
+        );
+        assert_eq!(
+            scheme.validate_request_at(&request, &token, 1_100),
+            Err(ValidationError::Skew(1_000))
+        );
+
+        // A signature over another method, path or body is rejected
+        let assert_invalid = |request: &BoundRequest| {
+            assert!(matches!(
+                scheme.validate_request_at(request, &token, 1_000),
+                Err(ValidationError::InvalidSignature(_))
+            ));
+        };
+        assert_invalid(&BoundRequest {
+            method: "DELETE".to_string(),
+            ..request.clone()
+        });
+        assert_invalid(&BoundRequest {
+            path: "/payloads".to_string(),
+            ..request.clone()
+        });
+        assert_invalid(&BoundRequest {
+            body_digest: body_digest(b"other body"),
+            ..request.clone()
+        });
+
+        // A stolen token is insufficient without the private key
+        request.signature = None;
+        assert_eq!(
+            scheme.validate_request_at(&request, &token, 1_000),
+            Err(ValidationError::MissingSignature)
+        );
+        let other_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        request.signature = Some(RequestSignature::sign(
+            &other_key,
+            1_000,
+            "PUT",
+            "/messages",
+            &body,
+            &token,
+        ));
+        assert!(matches!(
+            scheme.validate_request_at(&request, &token, 1_000),
+            Err(ValidationError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn signature_covers_token() {
+        let scheme = PubkeyBoundScheme::new(HmacScheme::new(b"key"));
+        let private_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        let token = scheme.construct_token(b"inbox", &public_key);
+        let other_token = scheme.construct_token(b"outbox", &public_key);
+
+        // A signature made for one token is not accepted alongside another
+        let body = body_digest(&[]);
+        let request = BoundRequest {
+            data: b"outbox".to_vec(),
+            method: "GET".to_string(),
+            path: "/messages".to_string(),
+            body_digest: body,
+            signature: Some(RequestSignature::sign(
+                &private_key,
+                1_000,
+                "GET",
+                "/messages",
+                &body,
+                &token,
+            )),
+        };
+        assert!(matches!(
+            scheme.validate_request_at(&request, &other_token, 1_000),
+            Err(ValidationError::InvalidSignature(_))
+        ));
+    }
+}
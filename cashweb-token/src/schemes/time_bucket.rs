@@ -0,0 +1,169 @@
+//! This module contains [`TimeBucketScheme`] which wraps the [`HmacScheme`] to bound the lifetime
+//! of tokens without server-side state.
+//!
+//! The data covered by the HMAC is suffixed by a coarse time bucket, the number of whole bucket
+//! widths elapsed since the unix epoch. Validators accept tokens from the current and previous
+//! buckets only, so a stolen token is usable for at most two bucket widths.
+
+use std::{
+    future::{ready, Ready},
+    sync::Arc,
+    time::Duration,
+};
+
+use clock::{system_clock, Clock, SharedClock};
+
+use super::hmac_bearer::{HmacScheme, ValidationError};
+use crate::{AuthContext, TokenValidator};
+
+/// The default width of a time bucket.
+pub const DEFAULT_BUCKET_WIDTH: Duration = Duration::from_secs(60 * 60);
+
+/// HMAC token scheme covering a coarse time bucket.
+#[derive(Clone, Debug)]
+pub struct TimeBucketScheme {
+    inner: HmacScheme,
+    bucket_width: u64,
+    clock: SharedClock,
+}
+
+impl TimeBucketScheme {
+    /// Create a new [`TimeBucketScheme`] from a [`HmacScheme`] and the width of each bucket.
+    ///
+    /// Widths are truncated to whole seconds, with a minimum of one second.
+    pub fn new(inner: HmacScheme, bucket_width: Duration) -> Self {
+        Self {
+            inner,
+            bucket_width: bucket_width.as_secs().max(1),
+            clock: system_clock(),
+        }
+    }
+
+    /// Set the [`Clock`] giving the current bucket, the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Get a reference to the inner [`HmacScheme`].
+    pub fn inner(&self) -> &HmacScheme {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner [`HmacScheme`], allowing key rotation.
+    pub fn inner_mut(&mut self) -> &mut HmacScheme {
+        &mut self.inner
+    }
+
+    /// The width of each bucket.
+    pub fn bucket_width(&self) -> Duration {
+        Duration::from_secs(self.bucket_width)
+    }
+
+    /// The bucket containing a unix time, given in seconds.
+    pub fn bucket(&self, time: u64) -> u64 {
+        time / self.bucket_width
+    }
+
+    /// Construct the data to be covered by the token, this is `data || bucket` where the bucket is
+    /// a big-endian 64-bit integer.
+    pub fn bucketed_data(data: &[u8], bucket: u64) -> Vec<u8> {
+        let mut bucketed_data = Vec::with_capacity(data.len() + 8);
+        bucketed_data.extend_from_slice(data);
+        bucketed_data.extend_from_slice(&bucket.to_be_bytes());
+        bucketed_data
+    }
+
+    /// Construct a token covering the current bucket.
+    pub fn construct_token(&self, data: &[u8]) -> String {
+        self.construct_token_at(data, self.clock.unix_now())
+    }
+
+    /// Construct a token covering the bucket containing a unix time, given in seconds.
+    pub fn construct_token_at(&self, data: &[u8], now: u64) -> String {
+        self.inner
+            .construct_token(&Self::bucketed_data(data, self.bucket(now)))
+    }
+
+    /// Validate a token against the current and previous buckets.
+    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
+        self.validate_token_at(data, token, self.clock.unix_now())
+    }
+
+    /// Validate a token against the bucket containing a unix time, given in seconds, and the
+    /// bucket preceding it.
+    pub fn validate_token_at(
+        &self,
+        data: &[u8],
+        token: &str,
+        now: u64,
+    ) -> Result<(), ValidationError> {
+        let bucket = self.bucket(now);
+        match self
+            .inner
+            .validate_token(&Self::bucketed_data(data, bucket), token)
+        {
+            Err(ValidationError::Invalid) if bucket != 0 => self
+                .inner
+                .validate_token(&Self::bucketed_data(data, bucket - 1), token),
+            result => result,
+        }
+    }
+}
+
+impl TokenValidator for TimeBucketScheme {
+    type Data = Vec<u8>;
+    type Output = ();
+    type Error = ValidationError;
+    type Future = Ready<Result<(), ValidationError>>;
+
+    fn validate(&self, data: Vec<u8>, token: String) -> Self::Future {
+        ready(self.validate_token(&data, &token))
+    }
+
+    fn auth_context(&self, token: String, _output: &()) -> AuthContext {
+        AuthContext::new(token, "hmac-time-bucket")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let scheme = TimeBucketScheme::new(HmacScheme::new(b"key"), Duration::from_secs(100));
+        let token = scheme.construct_token_at(b"data", 250);
+
+        scheme.validate_token_at(b"data", &token, 250).unwrap();
+        scheme.validate_token_at(b"data", &token, 299).unwrap();
+        scheme.validate_token_at(b"data", &token, 350).unwrap();
+        assert_eq!(
+            scheme.validate_token_at(b"data", &token, 400),
+            Err(ValidationError::Invalid)
+        );
+        assert_eq!(
+            scheme.validate_token_at(b"data", &token, 150),
+            Err(ValidationError::Invalid)
+        );
+        assert_eq!(
+            scheme.validate_token_at(b"other", &token, 250),
+            Err(ValidationError::Invalid)
+        );
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = clock::ManualClock::from_unix(250);
+        let scheme = TimeBucketScheme::new(HmacScheme::new(b"key"), Duration::from_secs(100))
+            .with_clock(clock.clone());
+        let token = scheme.construct_token(b"data");
+        scheme.validate_token(b"data", &token).unwrap();
+
+        clock.advance(Duration::from_secs(150));
+        assert_eq!(
+            scheme.validate_token(b"data", &token),
+            Err(ValidationError::Invalid)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! This module contains the [`Scope`] struct which allows tokens to be bound to a specific HTTP
+//! method and resource path.
+//!
+//! A token issued for `GET /messages` will then fail validation for `PUT /profiles`.
+
+use http::{Method, Uri};
+
+use crate::schemes::hmac_bearer::{HmacScheme, ValidationError};
+
+/// The HTTP method and resource path a token is bound to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scope {
+    /// The HTTP method.
+    pub method: Method,
+    /// The resource path.
+    pub path: String,
+}
+
+impl Scope {
+    /// Create a new [`Scope`].
+    pub fn new(method: Method, path: String) -> Self {
+        Self { method, path }
+    }
+
+    /// Create a [`Scope`] from a request method and [`Uri`], the query string is ignored.
+    pub fn from_request(method: &Method, uri: &Uri) -> Self {
+        Self {
+            method: method.clone(),
+            path: uri.path().to_string(),
+        }
+    }
+
+    /// Construct the data to be covered by the token.
+    ///
+    /// This is `method || 0x00 || path || 0x00 || data`, neither the method nor the path may contain
+    /// a null byte so the encoding is unambiguous.
+    pub fn scoped_data(&self, data: &[u8]) -> Vec<u8> {
+        let method = self.method.as_str().as_bytes();
+        let path = self.path.as_bytes();
+        let mut scoped_data = Vec::with_capacity(method.len() + path.len() + data.len() + 2);
+        scoped_data.extend_from_slice(method);
+        scoped_data.push(0);
+        scoped_data.extend_from_slice(path);
+        scoped_data.push(0);
+        scoped_data.extend_from_slice(data);
+        scoped_data
+    }
+}
+
+impl HmacScheme {
+    /// Construct a token bound to a [`Scope`].
+    pub fn construct_scoped_token(&self, scope: &Scope, data: &[u8]) -> String {
+        self.construct_token(&scope.scoped_data(data))
+    }
+
+    /// Validate a token bound to a [`Scope`].
+    pub fn validate_scoped_token(
+        &self,
+        scope: &Scope,
+        data: &[u8],
+        token: &str,
+    ) -> Result<(), ValidationError> {
+        self.validate_token(&scope.scoped_data(data), token)
+    }
+
+    /// Validate that a token was issued for the method and path of an incoming request.
+    pub fn validate_request(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        data: &[u8],
+        token: &str,
+    ) -> Result<(), ValidationError> {
+        self.validate_scoped_token(&Scope::from_request(method, uri), data, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_mismatch() {
+        let scheme = HmacScheme::new(b"secret");
+        let scope = Scope::new(Method::GET, "/messages".to_string());
+        let token = scheme.construct_scoped_token(&scope, b"data");
+
+        let uri: Uri = "/messages?start_time=0".parse().unwrap();
+        scheme
+            .validate_request(&Method::GET, &uri, b"data", &token)
+            .unwrap();
+
+        let uri: Uri = "/profiles".parse().unwrap();
+        assert_eq!(
+            scheme.validate_request(&Method::PUT, &uri, b"data", &token),
+            Err(ValidationError::Invalid)
+        );
+    }
+}
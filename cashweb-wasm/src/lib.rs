@@ -0,0 +1,395 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-wasm` is a library exposing message sealing, opening and authorization wrapper
+//! verification to JavaScript, allowing browser extensions and web wallets to share the
+//! implementation used by native clients.
+//!
+//! Hashing uses the pure Rust `sha2-backend` of the protocol crates and randomness is sourced from
+//! the `crypto.getRandomValues` of the host.
+//!
+//! Structures are passed as their protobuf serialization, in a `Uint8Array`. Failures are thrown as
+//! an `Error` carrying the stable `code` and `label` of the underlying error, see [`ErrorCode`].
+
+use auth_wrapper::AuthWrapper;
+use bitcoin::{transaction::Transaction, Encodable};
+use js_sys::{Error, Reflect, Uint8Array};
+use prost::Message as _;
+use relay::{
+    create_merged_key,
+    key_schedule::PayloadKeys,
+    secp::{PrivateKey, PublicKey, Secp256k1},
+    stamp::{create_stamp_outputs, Stamp, StampOutpoints, StampType},
+    DigestAlgorithm, EncryptionScheme, ErrorCode, Message, OpenError, ParseError,
+};
+use wasm_bindgen::prelude::*;
+
+/// The code thrown when the input failed to decode from its protobuf serialization.
+pub const DECODE_CODE: u16 = 9002;
+
+/// The code thrown when the private key provided was invalid.
+pub const PRIVATE_KEY_CODE: u16 = 9003;
+
+/// The code thrown when randomness failed to generate.
+pub const RANDOM_CODE: u16 = 9004;
+
+/// The length of a salt generated during sealing.
+const SALT_LEN: usize = 32;
+
+/// Construct a JavaScript `Error` carrying a code and label.
+fn js_error(code: u16, label: &str, message: &str) -> JsValue {
+    let error = Error::new(message);
+    Reflect::set(&error, &"code".into(), &code.into()).unwrap(); // This is safe
+    Reflect::set(&error, &"label".into(), &label.into()).unwrap(); // This is safe
+    error.into()
+}
+
+/// Construct a JavaScript `Error` from an error implementing [`ErrorCode`].
+macro_rules! coded_error {
+    ($err:expr) => {{
+        let err = $err;
+        js_error(err.code(), err.label(), &err.to_string())
+    }};
+}
+
+fn decode_error(err: prost::DecodeError) -> JsValue {
+    js_error(DECODE_CODE, "decode", &err.to_string())
+}
+
+fn to_array(bytes: &[u8]) -> JsValue {
+    Uint8Array::from(bytes).into()
+}
+
+/// A sealed [`Message`] awaiting its stamp.
+///
+/// The stamp commits to the payload digest, which is only known once the payload has been
+/// encrypted. Stamp transactions are attached using [`add_stamp_transaction`] before the message
+/// is serialized using [`encode`].
+///
+/// [`add_stamp_transaction`]: SealedMessage::add_stamp_transaction
+/// [`encode`]: SealedMessage::encode
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct SealedMessage {
+    inner: Message,
+}
+
+#[wasm_bindgen]
+impl SealedMessage {
+    /// The payload digest the stamp must commit to.
+    #[wasm_bindgen(getter, js_name = payloadDigest)]
+    pub fn payload_digest(&self) -> Vec<u8> {
+        self.inner.payload_digest.clone()
+    }
+
+    /// Attach a signed stamp transaction, and the indexes of its stamp outputs.
+    #[wasm_bindgen(js_name = addStampTransaction)]
+    pub fn add_stamp_transaction(&mut self, raw_transaction: Vec<u8>, vouts: Vec<u32>) {
+        let stamp = self.inner.stamp.get_or_insert_with(Default::default);
+        stamp.stamp_outpoints.push(StampOutpoints {
+            stamp_tx: raw_transaction,
+            vouts,
+        });
+    }
+
+    /// Serialize the [`Message`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut raw_message = Vec::with_capacity(self.inner.encoded_len());
+        self.inner.encode(&mut raw_message).unwrap(); // This is safe
+        raw_message
+    }
+}
+
+/// Encrypt a serialized [`Payload`] to a destination public key, resolving to a
+/// [`SealedMessage`].
+///
+/// The `scheme` is the [`EncryptionScheme`] as given in the protobuf specification.
+///
+/// [`Payload`]: relay::Payload
+#[wasm_bindgen(js_name = sealMessage)]
+pub async fn seal_message(
+    private_key: Vec<u8>,
+    destination_public_key: Vec<u8>,
+    scheme: i32,
+    payload: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    seal(&private_key, &destination_public_key, scheme, &payload).map(Into::into)
+}
+
+/// Encrypt a serialized [`Payload`] to a destination public key.
+///
+/// [`Payload`]: relay::Payload
+fn seal(
+    private_key: &[u8],
+    destination_public_key: &[u8],
+    scheme: i32,
+    payload: &[u8],
+) -> Result<SealedMessage, JsValue> {
+    // Parse keys
+    let private_key = PrivateKey::from_slice(private_key)
+        .map_err(|err| js_error(PRIVATE_KEY_CODE, "private_key", &err.to_string()))?;
+    let source_public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+    let destination_public_key = PublicKey::from_slice(destination_public_key)
+        .map_err(|err| coded_error!(ParseError::DestinationPublicKey(err)))?;
+    let scheme = EncryptionScheme::from_i32(scheme)
+        .ok_or_else(|| coded_error!(OpenError::UnsupportedScheme))?;
+
+    // Generate salt
+    let mut salt = [0; SALT_LEN];
+    getrandom::getrandom(&mut salt)
+        .map_err(|err| js_error(RANDOM_CODE, "random", &err.to_string()))?;
+
+    // Create payload keys
+    let merged_key = create_merged_key(destination_public_key, &private_key[..])
+        .map_err(|err| coded_error!(OpenError::SharedKey(err)))?;
+    let keys = PayloadKeys::derive(scheme, &merged_key, &salt)
+        .map_err(|_| coded_error!(OpenError::UnsupportedScheme))?;
+
+    // Encrypt payload
+    let ciphertext = keys.encrypt(payload);
+
+    // Calculate digest and HMAC, SHA256 is always supported
+    let payload_digest = DigestAlgorithm::Sha256.digest(&ciphertext).unwrap(); // This is safe
+    let payload_hmac = keys.payload_hmac(&payload_digest).unwrap(); // This is safe
+
+    let message = Message {
+        source_public_key: source_public_key.serialize().to_vec(),
+        destination_public_key: destination_public_key.serialize().to_vec(),
+        received_time: 0,
+        payload_digest: payload_digest.to_vec(),
+        stamp: Some(Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![],
+        }),
+        scheme: scheme.into(),
+        salt: salt.to_vec(),
+        payload_hmac: payload_hmac.to_vec(),
+        payload_size: ciphertext.len() as u64,
+        version: relay::VERSION,
+        digest_algorithm: DigestAlgorithm::Sha256.into(),
+        payload: ciphertext,
+    };
+    Ok(SealedMessage { inner: message })
+}
+
+/// Parse a serialized [`Message`], verify its stamp, then authenticate and decrypt it, resolving to
+/// the serialized [`Payload`].
+///
+/// [`Payload`]: relay::Payload
+#[wasm_bindgen(js_name = openMessage)]
+pub async fn open_message(raw_message: Vec<u8>, private_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let message = Message::decode(raw_message.as_slice()).map_err(decode_error)?;
+    let parsed_message = message.parse().map_err(|err| coded_error!(err))?;
+    let opened = parsed_message
+        .open(&private_key)
+        .map_err(|err| coded_error!(err))?;
+
+    let mut raw_payload = Vec::with_capacity(opened.payload.encoded_len());
+    opened.payload.encode(&mut raw_payload).unwrap(); // This is safe
+    Ok(to_array(&raw_payload))
+}
+
+/// Construct an unfunded stamp transaction, paying each of the `amounts` to the stamp outputs
+/// derived from the destination public key and payload digest.
+///
+/// The `tx_num` is the position of the transaction within the stamp.
+#[wasm_bindgen(js_name = stampTransaction)]
+pub fn stamp_transaction(
+    destination_public_key: Vec<u8>,
+    payload_digest: Vec<u8>,
+    tx_num: u32,
+    amounts: Vec<u64>,
+) -> Result<Vec<u8>, JsValue> {
+    let destination_public_key = PublicKey::from_slice(&destination_public_key)
+        .map_err(|err| coded_error!(ParseError::DestinationPublicKey(err)))?;
+    let mut digest = [0; 32];
+    if payload_digest.len() != digest.len() {
+        return Err(coded_error!(relay::DigestError::UnexpectedLengthDigest));
+    }
+    digest.copy_from_slice(&payload_digest);
+
+    let outputs = create_stamp_outputs(&destination_public_key, &digest, tx_num, &amounts)
+        .map_err(|err| coded_error!(err))?;
+    let transaction = Transaction {
+        version: 2,
+        inputs: vec![],
+        outputs,
+        lock_time: 0,
+    };
+    Ok(transaction.encode_to_vec())
+}
+
+/// A verified [`AuthWrapper`].
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct VerifiedAuthWrapper {
+    public_key: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl VerifiedAuthWrapper {
+    /// The serialized public key of the signer.
+    #[wasm_bindgen(getter, js_name = publicKey)]
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+
+    /// The payload covered by the signature.
+    #[wasm_bindgen(getter)]
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+/// Decode, parse and verify the signature of a serialized [`AuthWrapper`], resolving to a
+/// [`VerifiedAuthWrapper`].
+#[wasm_bindgen(js_name = verifyAuthWrapper)]
+pub async fn verify_auth_wrapper(raw_auth_wrapper: Vec<u8>) -> Result<JsValue, JsValue> {
+    let auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.as_slice()).map_err(decode_error)?;
+    let parsed = auth_wrapper.parse().map_err(|err| coded_error!(err))?;
+    parsed.verify().map_err(|err| coded_error!(err))?;
+
+    Ok(VerifiedAuthWrapper {
+        public_key: parsed.public_key.serialize().to_vec(),
+        payload: parsed.payload,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Decodable;
+    use relay::Payload;
+
+    use super::*;
+
+    fn keys(byte: u8) -> (Vec<u8>, PublicKey) {
+        let private_key = PrivateKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        (private_key[..].to_vec(), public_key)
+    }
+
+    fn payload() -> Vec<u8> {
+        let payload = Payload {
+            timestamp: 1_600_000_000_000,
+            ..Default::default()
+        };
+        let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut raw_payload).unwrap();
+        raw_payload
+    }
+
+    fn sealed_message(
+        source_private_key: &[u8],
+        destination_public_key: &PublicKey,
+    ) -> SealedMessage {
+        let mut sealed = seal(
+            source_private_key,
+            &destination_public_key.serialize(),
+            EncryptionScheme::EphemeralDhHkdf as i32,
+            &payload(),
+        )
+        .unwrap();
+        let raw_transaction = stamp_transaction(
+            destination_public_key.serialize().to_vec(),
+            sealed.payload_digest(),
+            0,
+            vec![1_000, 2_000],
+        )
+        .unwrap();
+        sealed.add_stamp_transaction(raw_transaction, vec![0, 1]);
+        sealed
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let (source_private_key, _) = keys(1);
+        let (destination_private_key, destination_public_key) = keys(2);
+        let sealed = sealed_message(&source_private_key, &destination_public_key);
+
+        let message = Message::decode(sealed.encode().as_slice()).unwrap();
+        let parsed_message = message.parse().unwrap();
+        let opened = parsed_message.open(&destination_private_key).unwrap();
+        let mut raw_payload = Vec::new();
+        opened.payload.encode(&mut raw_payload).unwrap();
+        assert_eq!(raw_payload, payload());
+    }
+
+    #[test]
+    fn stamp_transaction_outputs() {
+        let (_, destination_public_key) = keys(2);
+        let digest = [3; 32];
+        let raw_transaction = stamp_transaction(
+            destination_public_key.serialize().to_vec(),
+            digest.to_vec(),
+            1,
+            vec![1_000, 2_000],
+        )
+        .unwrap();
+        let transaction = Transaction::decode(&mut raw_transaction.as_slice()).unwrap();
+        assert!(transaction.inputs.is_empty());
+        assert_eq!(
+            transaction.outputs,
+            create_stamp_outputs(&destination_public_key, &digest, 1, &[1_000, 2_000]).unwrap()
+        );
+    }
+
+    /// Errors are thrown as JavaScript values, which are only available within a JavaScript host.
+    #[cfg(target_arch = "wasm32")]
+    mod wasm {
+        use wasm_bindgen_test::*;
+
+        use super::*;
+
+        fn code(err: &JsValue) -> JsValue {
+            Reflect::get(err, &"code".into()).unwrap()
+        }
+
+        #[wasm_bindgen_test]
+        async fn open_sealed_message() {
+            let (source_private_key, _) = keys(1);
+            let (destination_private_key, destination_public_key) = keys(2);
+            let sealed = sealed_message(&source_private_key, &destination_public_key);
+
+            let raw_payload = open_message(sealed.encode(), destination_private_key)
+                .await
+                .unwrap();
+            assert_eq!(Uint8Array::new(&raw_payload).to_vec(), payload());
+
+            let err = open_message(vec![0xff], vec![]).await.unwrap_err();
+            assert_eq!(code(&err), JsValue::from(DECODE_CODE));
+        }
+
+        #[wasm_bindgen_test]
+        async fn invalid_private_key() {
+            let (_, destination_public_key) = keys(2);
+            let err = seal_message(
+                vec![0; 32],
+                destination_public_key.serialize().to_vec(),
+                EncryptionScheme::EphemeralDhHkdf as i32,
+                payload(),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(code(&err), JsValue::from(PRIVATE_KEY_CODE));
+        }
+
+        #[wasm_bindgen_test]
+        async fn verify_signed_auth_wrapper() {
+            let (private_key, _) = keys(1);
+            let private_key = PrivateKey::from_slice(&private_key).unwrap();
+            let auth_wrapper = AuthWrapper::sign(&private_key, b"payload".to_vec());
+            let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+            auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+            assert!(verify_auth_wrapper(raw_auth_wrapper).await.is_ok());
+
+            let err = verify_auth_wrapper(vec![0xff]).await.unwrap_err();
+            assert_eq!(code(&err), JsValue::from(DECODE_CODE));
+        }
+    }
+}
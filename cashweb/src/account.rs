@@ -0,0 +1,268 @@
+//! This module contains the [`Account`] which derives every key used for messaging from a single
+//! [`BIP32`] root.
+//!
+//! Keys are derived along the following hardened paths, where `PURPOSE` is [`PURPOSE`]:
+//! * `m / PURPOSE' / account' / 0'` is the identity key, published via the keyserver and used to
+//!   receive messages from senders who have not been assigned a contact key.
+//! * `m / PURPOSE' / account' / 1' / index'` is the message key dedicated to a contact, where the
+//!   `index` is given by [`contact_index`].
+//!
+//! Messages sent to a contact are sealed using their dedicated key, so contacts reply to that key.
+//! The stamp keys of a received message are derived from the private key it was addressed to, see
+//! [`create_stamp_private_keys`].
+//!
+//! This module is enabled by the `messenger` feature.
+//!
+//! [`BIP32`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+
+use std::{collections::HashMap, convert::TryInto, fmt};
+
+use bitcoin::bip32::{ChildNumber, ExtendedPrivateKey};
+use relay::{
+    secp::{PrivateKey, PublicKey, Secp256k1, SecpError},
+    stamp::{create_stamp_private_keys, StampKeyError},
+    OpenError, Opened, ParsedMessage,
+};
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+};
+use thiserror::Error;
+
+/// The purpose, `cw` in ASCII, forming the first level of the derivation paths.
+pub const PURPOSE: u32 = 0x6377;
+
+/// The child number of the identity key beneath the account key.
+pub const IDENTITY_BRANCH: u32 = 0;
+
+/// The child number of the contact keys beneath the account key.
+pub const CONTACT_BRANCH: u32 = 1;
+
+/// The hardened index of the message key dedicated to an address, this is the first four bytes of
+/// `SHA256(address)`, as a big-endian integer, with the most significant bit cleared.
+pub fn contact_index(address: &str) -> u32 {
+    let address_digest = digest(&SHA256, address.as_bytes());
+    let prefix: [u8; 4] = address_digest.as_ref()[..4].try_into().unwrap(); // This is safe
+    u32::from_be_bytes(prefix) & !(1 << 31)
+}
+
+/// Error associated with using an [`Account`].
+#[derive(Debug, Error)]
+pub enum AccountError {
+    /// The message was addressed to a key not known to the account.
+    #[error("unknown destination key")]
+    UnknownKey,
+    /// Failed to open the message.
+    #[error("failed to open message: {0}")]
+    Open(OpenError),
+    /// Failed to derive the stamp keys.
+    #[error("failed to derive stamp keys: {0}")]
+    StampKey(StampKeyError),
+}
+
+/// A key pair derived by an [`Account`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KeyPair {
+    /// The private key.
+    pub private_key: PrivateKey,
+    /// The public key.
+    pub public_key: PublicKey,
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+impl KeyPair {
+    fn from_extended(extended_key: ExtendedPrivateKey) -> Self {
+        let private_key = extended_key.into_private_key();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        Self {
+            private_key,
+            public_key,
+        }
+    }
+}
+
+/// Derives the identity and per-contact message keys from a single root, and selects the private
+/// key required to seal or open each message.
+///
+/// Contacts must be added, using [`add_contact`](Account::add_contact), before messages addressed
+/// to their dedicated key can be opened.
+#[derive(Clone)]
+pub struct Account {
+    account_key: ExtendedPrivateKey,
+    identity: KeyPair,
+    contacts: HashMap<PublicKey, (String, KeyPair)>,
+}
+
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("identity", &self.identity)
+            .field("contacts", &self.contacts.len())
+            .finish()
+    }
+}
+
+impl Account {
+    /// Create an [`Account`] from a [`BIP32`] master key and an account number.
+    ///
+    /// The account number must be less than 2^31.
+    ///
+    /// [`BIP32`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+    pub fn from_master(master_key: &ExtendedPrivateKey, account: u32) -> Self {
+        let secp = Secp256k1::signing_only();
+        let path = [
+            ChildNumber::Hardened(PURPOSE),
+            ChildNumber::Hardened(account & !(1 << 31)),
+        ];
+        let account_key = master_key.derive_private_path(&secp, &path);
+        let identity = KeyPair::from_extended(
+            account_key.derive_private_child(&secp, ChildNumber::Hardened(IDENTITY_BRANCH)),
+        );
+        Self {
+            account_key,
+            identity,
+            contacts: HashMap::new(),
+        }
+    }
+
+    /// Create an [`Account`] from a seed, deriving the master key as specified by [`BIP32`].
+    ///
+    /// [`BIP32`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+    pub fn from_seed(seed: &[u8], account: u32) -> Result<Self, SecpError> {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, b"Bitcoin seed");
+        let tag = hmac::sign(&key, seed);
+        let private_key = PrivateKey::from_slice(&tag.as_ref()[..32])?;
+        let chain_code = tag.as_ref()[32..].try_into().unwrap(); // This is safe
+        let master_key = ExtendedPrivateKey::new_master(private_key, chain_code);
+        Ok(Self::from_master(&master_key, account))
+    }
+
+    /// The identity key, whose public key is published via the keyserver.
+    pub fn identity(&self) -> &KeyPair {
+        &self.identity
+    }
+
+    /// Derive the message key dedicated to an address, without adding the contact.
+    pub fn derive_contact_key(&self, address: &str) -> KeyPair {
+        let secp = Secp256k1::signing_only();
+        let path = [
+            ChildNumber::Hardened(CONTACT_BRANCH),
+            ChildNumber::Hardened(contact_index(address)),
+        ];
+        KeyPair::from_extended(self.account_key.derive_private_path(&secp, &path))
+    }
+
+    /// Add a contact, returning their dedicated message key.
+    pub fn add_contact(&mut self, address: &str) -> KeyPair {
+        let key_pair = self.derive_contact_key(address);
+        self.contacts
+            .insert(key_pair.public_key, (address.to_string(), key_pair));
+        key_pair
+    }
+
+    /// Remove a contact, returning whether they were present.
+    ///
+    /// Messages addressed to their dedicated key can no longer be opened.
+    pub fn remove_contact(&mut self, address: &str) -> bool {
+        let public_key = self.derive_contact_key(address).public_key;
+        self.contacts.remove(&public_key).is_some()
+    }
+
+    /// The address of the contact a public key is dedicated to.
+    pub fn contact_address(&self, public_key: &PublicKey) -> Option<&str> {
+        self.contacts
+            .get(public_key)
+            .map(|(address, _)| address.as_str())
+    }
+
+    /// The public keys for which messages can be opened, the identity key followed by the
+    /// contact keys.
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        std::iter::once(self.identity.public_key)
+            .chain(self.contacts.keys().copied())
+            .collect()
+    }
+
+    /// The key used to seal messages sent to an address, this is the dedicated contact key.
+    pub fn sealing_key(&self, address: &str) -> KeyPair {
+        self.derive_contact_key(address)
+    }
+
+    /// The private key corresponding to a public key, if it is known to the account.
+    pub fn private_key(&self, public_key: &PublicKey) -> Option<&PrivateKey> {
+        if *public_key == self.identity.public_key {
+            return Some(&self.identity.private_key);
+        }
+        self.contacts
+            .get(public_key)
+            .map(|(_, key_pair)| &key_pair.private_key)
+    }
+
+    /// Open a message using the private key it was addressed to.
+    pub fn open(&self, message: &ParsedMessage) -> Result<Opened, AccountError> {
+        let private_key = self
+            .private_key(&message.destination_public_key)
+            .ok_or(AccountError::UnknownKey)?;
+        message.open(&private_key[..]).map_err(AccountError::Open)
+    }
+
+    /// Derive the private keys of the stamp outputs attached to a message, allowing them to be
+    /// swept.
+    pub fn stamp_private_keys(
+        &self,
+        message: &ParsedMessage,
+    ) -> Result<Vec<Vec<PrivateKey>>, AccountError> {
+        let private_key = self
+            .private_key(&message.destination_public_key)
+            .ok_or(AccountError::UnknownKey)?;
+        let output_profile: Vec<u32> = message
+            .stamp
+            .stamp_outpoints
+            .iter()
+            .map(|outpoints| outpoints.vouts.len() as u32)
+            .collect();
+        create_stamp_private_keys(*private_key, &message.payload_digest, output_profile)
+            .map_err(AccountError::StampKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation() {
+        let mut account = Account::from_seed(&[7; 32], 0).unwrap();
+        let other_account = Account::from_seed(&[7; 32], 1).unwrap();
+        assert_ne!(
+            account.identity().public_key,
+            other_account.identity().public_key
+        );
+
+        // Contact keys are deterministic and distinct
+        let alice = account.derive_contact_key("alice");
+        assert_eq!(account.sealing_key("alice"), alice);
+        assert_ne!(account.derive_contact_key("bob"), alice);
+        assert_ne!(alice.public_key, account.identity().public_key);
+
+        // Only added contacts are known
+        assert!(account.private_key(&alice.public_key).is_none());
+        assert_eq!(account.add_contact("alice"), alice);
+        assert_eq!(
+            account.private_key(&alice.public_key),
+            Some(&alice.private_key)
+        );
+        assert_eq!(account.contact_address(&alice.public_key), Some("alice"));
+        assert_eq!(account.public_keys().len(), 2);
+
+        assert!(account.remove_contact("alice"));
+        assert!(account.private_key(&alice.public_key).is_none());
+    }
+}
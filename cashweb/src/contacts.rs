@@ -0,0 +1,281 @@
+//! This module contains the [`ContactBook`] which resolves addresses to public keys and pins the
+//! first key seen for each address, trust on first use.
+//!
+//! Subsequent resolutions are compared against the pinned key. A differing key is reported as
+//! [`TrustStatus::Rotated`] and is not pinned until explicitly accepted using
+//! [`ContactBook::accept`].
+//!
+//! The pins are persisted via a [`ContactStore`], a [`MemoryContactStore`] is provided.
+//!
+//! This module is enabled by the `messenger` feature.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error, fmt,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use hyper::{Body, Request, Response};
+use keyserver_client::KeyserverManager;
+use relay::secp::PublicKey;
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::messenger::{Contact, ResolveError, DEFAULT_SAMPLE_SIZE};
+
+/// A public key pinned to an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinnedKey {
+    /// The pinned public key.
+    pub public_key: PublicKey,
+    /// The time at which the key was pinned.
+    pub pinned_at: SystemTime,
+}
+
+/// A store persisting the [`PinnedKey`] associated with each address.
+pub trait ContactStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get the key pinned to an address.
+    fn get_pin(&self, address: &str) -> Result<Option<PinnedKey>, Self::Error>;
+
+    /// Pin a key to an address, replacing any existing pin.
+    fn put_pin(&self, address: &str, pin: PinnedKey) -> Result<(), Self::Error>;
+
+    /// Remove the pin associated with an address.
+    fn remove_pin(&self, address: &str) -> Result<(), Self::Error>;
+}
+
+/// An in-memory [`ContactStore`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryContactStore {
+    pins: Arc<Mutex<HashMap<String, PinnedKey>>>,
+}
+
+impl MemoryContactStore {
+    /// Create a new, empty, [`MemoryContactStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl ContactStore for MemoryContactStore {
+    type Error = Infallible;
+
+    fn get_pin(&self, address: &str) -> Result<Option<PinnedKey>, Self::Error> {
+        Ok(self.pins.lock().unwrap().get(address).cloned())
+    }
+
+    fn put_pin(&self, address: &str, pin: PinnedKey) -> Result<(), Self::Error> {
+        self.pins.lock().unwrap().insert(address.to_string(), pin);
+        Ok(())
+    }
+
+    fn remove_pin(&self, address: &str) -> Result<(), Self::Error> {
+        self.pins.lock().unwrap().remove(address);
+        Ok(())
+    }
+}
+
+/// The trust status of a resolved public key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrustStatus {
+    /// No key was pinned to the address, the key has now been pinned.
+    FirstSeen,
+    /// The key matches the pinned key.
+    Trusted,
+    /// The key differs from the pinned key. The pin is left unchanged.
+    Rotated {
+        /// The key currently pinned to the address.
+        pinned: PublicKey,
+    },
+}
+
+/// A [`Contact`] paired with the [`TrustStatus`] of its public key.
+#[derive(Clone, Debug)]
+pub struct Resolution {
+    /// The resolved contact.
+    pub contact: Contact,
+    /// The trust status of the contact's public key.
+    pub status: TrustStatus,
+}
+
+/// Error associated with resolving a contact via the [`ContactBook`].
+#[derive(Debug, Error)]
+pub enum ContactError<E, T>
+where
+    E: fmt::Debug + fmt::Display,
+    T: fmt::Debug + fmt::Display,
+{
+    /// Failed to resolve the contact from the keyservers.
+    #[error(transparent)]
+    Resolve(ResolveError<E>),
+    /// Failed to access the [`ContactStore`].
+    #[error("contact store failure: {0}")]
+    Store(T),
+}
+
+/// ContactBook resolves addresses to public keys, via keyserver sampling, and pins the first key
+/// seen for each address.
+#[derive(Clone, Debug)]
+pub struct ContactBook<S, T> {
+    keyserver_manager: KeyserverManager<S>,
+    store: T,
+    sample_size: usize,
+}
+
+impl<S, T> ContactBook<S, T> {
+    /// Create a new [`ContactBook`] from a [`KeyserverManager`] and a [`ContactStore`].
+    pub fn new(keyserver_manager: KeyserverManager<S>, store: T) -> Self {
+        Self {
+            keyserver_manager,
+            store,
+            sample_size: DEFAULT_SAMPLE_SIZE,
+        }
+    }
+
+    /// Set the number of keyservers sampled when resolving a contact.
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size;
+        self
+    }
+
+    /// Get a reference to the [`ContactStore`].
+    pub fn store(&self) -> &T {
+        &self.store
+    }
+}
+
+impl<S, T> ContactBook<S, T>
+where
+    T: ContactStore,
+{
+    /// Get the key pinned to an address.
+    pub fn pinned(&self, address: &str) -> Result<Option<PinnedKey>, T::Error> {
+        self.store.get_pin(address)
+    }
+
+    /// Compare a public key against the key pinned to the address, pinning it if none exists.
+    pub fn check(&self, address: &str, public_key: &PublicKey) -> Result<TrustStatus, T::Error> {
+        match self.store.get_pin(address)? {
+            None => {
+                self.accept(address, *public_key)?;
+                Ok(TrustStatus::FirstSeen)
+            }
+            Some(pin) if pin.public_key == *public_key => Ok(TrustStatus::Trusted),
+            Some(pin) => Ok(TrustStatus::Rotated {
+                pinned: pin.public_key,
+            }),
+        }
+    }
+
+    /// Pin a public key to an address, replacing the existing pin.
+    ///
+    /// This is used to accept a key after a [`TrustStatus::Rotated`].
+    pub fn accept(&self, address: &str, public_key: PublicKey) -> Result<(), T::Error> {
+        let pin = PinnedKey {
+            public_key,
+            pinned_at: SystemTime::now(),
+        };
+        self.store.put_pin(address, pin)
+    }
+
+    /// Remove the pin associated with an address.
+    pub fn forget(&self, address: &str) -> Result<(), T::Error> {
+        self.store.remove_pin(address)
+    }
+}
+
+impl<S, T> ContactBook<S, T>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + error::Error + Send + 'static,
+    T: ContactStore,
+{
+    /// Resolve an address to a [`Contact`] by sampling the keyservers and check its public key
+    /// against the pinned key.
+    pub async fn resolve(
+        &self,
+        address: &str,
+    ) -> Result<Resolution, ContactError<S::Error, T::Error>> {
+        let sample_response = self
+            .keyserver_manager
+            .uniform_sample_metadata(address, self.sample_size)
+            .await
+            .map_err(|err| ContactError::Resolve(ResolveError::Sample(err)))?;
+        let (_, package) = sample_response
+            .response
+            .ok_or(ContactError::Resolve(ResolveError::NotFound))?;
+
+        let status = self
+            .check(address, &package.public_key)
+            .map_err(ContactError::Store)?;
+        let contact = Contact {
+            address: address.to_string(),
+            public_key: package.public_key,
+            metadata: package.metadata,
+        };
+
+        Ok(Resolution { contact, status })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use relay::secp::{PrivateKey, Secp256k1};
+
+    use super::*;
+
+    fn public_key(byte: u8) -> PublicKey {
+        let private_key = PrivateKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key)
+    }
+
+    fn contact_book() -> ContactBook<hyper::Client<hyper::client::HttpConnector>, MemoryContactStore>
+    {
+        let keyserver_manager = KeyserverManager::new(vec![]).unwrap();
+        ContactBook::new(keyserver_manager, MemoryContactStore::new())
+    }
+
+    #[test]
+    fn trust_on_first_use() {
+        let contact_book = contact_book();
+        let first = public_key(1);
+        assert_eq!(
+            contact_book.check("alice", &first).unwrap(),
+            TrustStatus::FirstSeen
+        );
+        assert_eq!(
+            contact_book.check("alice", &first).unwrap(),
+            TrustStatus::Trusted
+        );
+    }
+
+    #[test]
+    fn rotation() {
+        let contact_book = contact_book();
+        let first = public_key(1);
+        let second = public_key(2);
+        contact_book.check("alice", &first).unwrap();
+        assert_eq!(
+            contact_book.check("alice", &second).unwrap(),
+            TrustStatus::Rotated { pinned: first }
+        );
+
+        // Pin is unchanged until accepted
+        assert_eq!(
+            contact_book.pinned("alice").unwrap().unwrap().public_key,
+            first
+        );
+        contact_book.accept("alice", second).unwrap();
+        assert_eq!(
+            contact_book.check("alice", &second).unwrap(),
+            TrustStatus::Trusted
+        );
+    }
+}
@@ -10,24 +10,104 @@
 //! * [Authorization Wrapper Protocol](https://github.com/cashweb/specifications/blob/master/authorization-wrapper-protocol/specification.mediawiki)
 //! * [Keyserver Protocol](https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki)
 //! * [Relay Server Protocol](https://github.com/cashweb/specifications/blob/master/relay-server-protocol/specification.mediawiki)
+//!
+//! Each component is re-exported behind a cargo feature of the same name, all of which are enabled
+//! by default. Disable the default features to select only the components required, for example
+//! the serialization and cryptography layers without the `hyper` based clients:
+//!
+//! ```toml
+//! cashweb = { version = "0.1.0-alpha.9", default-features = false, features = ["auth-wrapper", "relay"] }
+//! ```
+//!
+//! The `messenger` feature provides the [`Messenger`](messenger::Messenger), a high-level facade
+//! for sending and receiving messages, the [`ContactBook`](contacts::ContactBook) which pins
+//! the public keys of contacts, and the [`Outbox`](outbox::Outbox) which retries delivery of
+//! messages until they are accepted. The [`Account`](account::Account) derives the identity and
+//! per-contact message keys from a single BIP32 root, selecting the private key to open each
+//! message with. The [`InboxSync`](sync::InboxSync) retrieves only the messages received since
+//! the last synchronization, persisting a cursor per relay server.
+//!
+//! The `test-vectors` feature, not enabled by default, provides the [`test_vectors`] module which
+//! generates and verifies deterministic fixtures for message sealing, stamp derivation and token
+//! construction, for cross-validating alternative implementations.
 
+#[cfg(feature = "messenger")]
+pub mod account;
+#[cfg(feature = "messenger")]
+pub mod contacts;
+#[cfg(feature = "messenger")]
+pub mod messenger;
+#[cfg(feature = "messenger")]
+pub mod outbox;
+pub mod prelude;
+#[cfg(feature = "messenger")]
+pub mod sync;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+
+#[cfg(feature = "auth-wrapper")]
 #[doc(inline)]
 pub use auth_wrapper;
+#[cfg(feature = "bitcoin")]
 #[doc(inline)]
 pub use bitcoin;
+#[cfg(feature = "bitcoin-client")]
 #[doc(inline)]
 pub use bitcoin_client;
+#[cfg(feature = "clock")]
+#[doc(inline)]
+pub use clock;
+#[cfg(feature = "keyserver")]
 #[doc(inline)]
 pub use keyserver;
+#[cfg(feature = "keyserver-client")]
 #[doc(inline)]
 pub use keyserver_client;
+#[cfg(feature = "payments")]
 #[doc(inline)]
 pub use payments;
+#[cfg(feature = "protection")]
+#[doc(inline)]
+pub use protection;
+#[cfg(feature = "relay")]
 #[doc(inline)]
 pub use relay;
+#[cfg(feature = "relay-client")]
 #[doc(inline)]
 pub use relay_client;
+#[cfg(feature = "secp256k1")]
 #[doc(inline)]
 pub use secp256k1;
+#[cfg(feature = "token")]
 #[doc(inline)]
 pub use token;
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(all(feature = "protection", feature = "token"))]
+    fn assert_validator<V: protection::TokenValidator>(_: &V) {}
+
+    #[cfg(all(feature = "protection", feature = "token"))]
+    #[test]
+    fn protection_reexport() {
+        // The schemes implement the trait re-exported by the protection layer
+        let scheme = token::schemes::hmac_bearer::HmacScheme::new(b"key");
+        assert_validator(&scheme);
+        let _layer = protection::ProtectionLayer::new(scheme, protection::path_data::<()>);
+    }
+
+    #[cfg(all(feature = "auth-wrapper", feature = "secp256k1"))]
+    #[test]
+    fn secp256k1_reexport() {
+        // The keys re-exported are those used by the components
+        let private_key = secp256k1::key::SecretKey::from_slice(&[1; 32]).unwrap();
+        auth_wrapper::AuthWrapper::sign(&private_key, b"payload".to_vec())
+            .parse()
+            .unwrap()
+            .verify()
+            .unwrap();
+    }
+}
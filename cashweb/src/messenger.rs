@@ -0,0 +1,623 @@
+//! This module contains the [`Messenger`], a high-level facade combining a [`KeyserverManager`],
+//! a [`RelayClient`] and a bitcoind wallet, accessed via [`BitcoinClient`], to send and receive
+//! messages.
+//!
+//! Contacts are resolved by sampling keyservers for their [`AddressMetadata`]. Messages are
+//! encrypted to the contact's public key, stamped using outputs funded by the wallet, and then
+//! put to the relay server of the contact.
+//!
+//! The inbox may be mirrored across several relay servers, in which case messages are deduplicated
+//! by payload digest so that each is surfaced once.
+//!
+//! The identity of the messenger, its [`AddressMetadata`] and [`Profile`], is published to the
+//! keyservers and relay server in a single call to
+//! [`publish_identity`](Messenger::publish_identity).
+//!
+//! This module is enabled by the `messenger` feature.
+
+use std::{
+    convert::TryInto,
+    error, fmt,
+    sync::{Arc, Mutex},
+};
+
+use bitcoin::{
+    transaction::{DecodeError as TransactionDecodeError, Transaction},
+    Decodable, Encodable,
+};
+use bitcoin_client::{BitcoinClient, NodeError};
+use hyper::{Body, Request, Response};
+use keyserver_client::{
+    models::{AddressMetadata, AuthWrapper},
+    services::{GetMetadataError, PutMetadataError, SampleError},
+    AggregateResponse, KeyserverManager,
+};
+use prost::Message as _;
+use relay::{
+    dedup::MessageDeduplicator,
+    padding::PaddingPolicy,
+    seal::{MessageBuilder, SealError},
+    secp::{PrivateKey, PublicKey, Secp256k1, SecpError},
+    stamp::{create_stamp_outputs, Stamp, StampError, StampOutpoints, StampType, StampWatchEntry},
+    DigestAlgorithm, EncryptionScheme, Message, MessageSet, OpenError, Opened, ParseError,
+    ParsedMessage, Payload, Profile,
+};
+use relay_client::{
+    services::{GetMessageError, PutMessagesError, PutProfileError},
+    RelayClient, RelayError,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+use tower_service::Service;
+
+/// The default amount, in satoshis, paid to the stamp output of each message.
+pub const DEFAULT_STAMP_AMOUNT: u64 = 5_000;
+
+/// The default number of keyservers sampled when resolving a contact.
+pub const DEFAULT_SAMPLE_SIZE: usize = 3;
+
+/// A contact resolved from the keyservers.
+#[derive(Clone, Debug)]
+pub struct Contact {
+    /// The address of the contact.
+    pub address: String,
+    /// The public key of the contact, messages are encrypted to this key.
+    pub public_key: PublicKey,
+    /// The address metadata of the contact.
+    pub metadata: AddressMetadata,
+}
+
+/// A message received from the relay server, post-decryption.
+#[derive(Clone, Debug)]
+pub struct ReceivedMessage {
+    /// The parsed message, the `payload` remains encrypted.
+    pub message: ParsedMessage,
+    /// The decoded stamp transactions and decrypted payload.
+    pub opened: Opened,
+}
+
+/// Error associated with parsing or opening a message in the inbox.
+#[derive(Debug, Error)]
+pub enum InboxMessageError {
+    /// Failed to parse the message.
+    #[error("failed to parse message: {0}")]
+    Parse(ParseError),
+    /// Failed to open the message.
+    #[error("failed to open message: {0}")]
+    Open(OpenError),
+}
+
+/// The messages retrieved from the inbox.
+#[derive(Debug)]
+pub struct Inbox {
+    /// Successfully opened messages.
+    pub messages: Vec<ReceivedMessage>,
+    /// Errors paired with the index of the message, within the concatenated pages of the relay
+    /// servers, they originated at.
+    pub errors: Vec<(usize, InboxMessageError)>,
+}
+
+/// Error associated with resolving a contact.
+#[derive(Debug, Error)]
+pub enum ResolveError<E: fmt::Debug + fmt::Display> {
+    /// Failed to sample the keyservers.
+    #[error("failed to sample keyservers: {0}")]
+    Sample(SampleError<GetMetadataError<E>>),
+    /// None of the sampled keyservers held metadata for the address.
+    #[error("contact not found")]
+    NotFound,
+}
+
+/// Error associated with sending a message.
+#[derive(Debug, Error)]
+pub enum SendError<E, B>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+    B: fmt::Debug + fmt::Display + 'static,
+{
+    /// Failed to resolve the contact.
+    #[error(transparent)]
+    Resolve(ResolveError<E>),
+    /// Failed to generate the salt.
+    #[error("failed to generate salt")]
+    Random,
+    /// Failed to construct shared key.
+    #[error("shared key: {0}")]
+    SharedKey(SecpError),
+    /// The encryption scheme does not support encryption.
+    #[error("unsupported encryption scheme")]
+    UnsupportedScheme,
+    /// The digest algorithm is unsupported.
+    #[error("unsupported digest algorithm")]
+    UnsupportedDigestAlgorithm,
+    /// Failed to construct the stamp outputs.
+    #[error("stamp error: {0}")]
+    Stamp(StampError),
+    /// Error occured when communicating with bitcoind.
+    #[error(transparent)]
+    Node(NodeError<B>),
+    /// Error decoding the signed stamp transaction.
+    #[error("failed to decode transaction: {0}")]
+    Transaction(TransactionDecodeError),
+    /// The wallet was unable to sign all inputs.
+    #[error("incomplete signatures")]
+    IncompleteSignatures,
+    /// A stamp output was missing from the signed transaction.
+    #[error("stamp output missing")]
+    StampOutputMissing,
+    /// Failed to put the message to the relay server.
+    #[error("failed to put message: {0}")]
+    Relay(RelayError<PutMessagesError<E>>),
+}
+
+/// Error associated with retrieving the inbox.
+#[derive(Debug, Error)]
+pub enum InboxError<E>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+{
+    /// No POP token has been provided for the relay server.
+    #[error("missing token")]
+    MissingToken,
+    /// Failed to get messages from the relay server.
+    #[error("failed to get messages: {0}")]
+    Relay(RelayError<GetMessageError<E>>),
+}
+
+/// Error associated with publishing an identity.
+#[derive(Debug, Error)]
+pub enum PublishError<E>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+{
+    /// No POP token has been provided for the relay server.
+    #[error("missing token")]
+    MissingToken,
+    /// Failed to broadcast the metadata to the keyservers.
+    #[error("failed to broadcast metadata: {0}")]
+    Keyserver(SampleError<PutMetadataError<E>>),
+    /// Failed to put the profile to the relay server.
+    #[error("failed to put profile: {0}")]
+    Relay(RelayError<PutProfileError<E>>),
+}
+
+/// Error associated with importing stamp outputs into the wallet.
+#[derive(Debug, Error)]
+pub enum WatchStampError<B: fmt::Debug + fmt::Display + 'static> {
+    /// Failed to derive the stamp outputs.
+    #[error("stamp error: {0}")]
+    Stamp(StampError),
+    /// Error occured when communicating with bitcoind.
+    #[error(transparent)]
+    Node(NodeError<B>),
+}
+
+/// Messenger combines a [`KeyserverManager`], a [`RelayClient`], key material and a stamp-funding
+/// wallet to send and receive messages.
+#[derive(Clone, Debug)]
+pub struct Messenger<S, B> {
+    keyserver_manager: KeyserverManager<S>,
+    relay_client: RelayClient<S>,
+    bitcoin_client: BitcoinClient<B>,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+    address: String,
+    relay_url: String,
+    mirror_relay_urls: Vec<String>,
+    deduplicator: Option<Arc<Mutex<MessageDeduplicator>>>,
+    token: Option<String>,
+    stamp_amount: u64,
+    sample_size: usize,
+    padding: Option<PaddingPolicy>,
+    scheme: EncryptionScheme,
+    digest_algorithm: DigestAlgorithm,
+}
+
+impl<S, B> Messenger<S, B> {
+    /// Create a new [`Messenger`].
+    ///
+    /// The `address` and `relay_url` locate the inbox associated with the `private_key`.
+    pub fn new(
+        keyserver_manager: KeyserverManager<S>,
+        relay_client: RelayClient<S>,
+        bitcoin_client: BitcoinClient<B>,
+        private_key: PrivateKey,
+        address: String,
+        relay_url: String,
+    ) -> Self {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &private_key);
+        Self {
+            keyserver_manager,
+            relay_client,
+            bitcoin_client,
+            private_key,
+            public_key,
+            address,
+            relay_url,
+            mirror_relay_urls: Vec::new(),
+            deduplicator: None,
+            token: None,
+            stamp_amount: DEFAULT_STAMP_AMOUNT,
+            sample_size: DEFAULT_SAMPLE_SIZE,
+            padding: None,
+            scheme: EncryptionScheme::EphemeralDh,
+            digest_algorithm: DigestAlgorithm::Sha256,
+        }
+    }
+
+    /// Set the POP token used to access the inbox.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Set additional relay servers mirroring the inbox, these are fetched from along with the
+    /// primary relay server.
+    pub fn with_mirror_relays(mut self, relay_urls: Vec<String>) -> Self {
+        self.mirror_relay_urls = relay_urls;
+        self
+    }
+
+    /// Remember the payload digests of up to `capacity` messages across calls to
+    /// [`inbox`](Messenger::inbox), so that each message is only returned once.
+    ///
+    /// Without this, messages are only deduplicated within a single call.
+    pub fn with_deduplication(mut self, capacity: usize) -> Self {
+        self.deduplicator = Some(Arc::new(Mutex::new(MessageDeduplicator::new(capacity))));
+        self
+    }
+
+    /// Set the amount, in satoshis, paid to the stamp output of each message.
+    pub fn with_stamp_amount(mut self, stamp_amount: u64) -> Self {
+        self.stamp_amount = stamp_amount;
+        self
+    }
+
+    /// Set the number of keyservers sampled when resolving a contact.
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size;
+        self
+    }
+
+    /// Pad payloads to the buckets of a [`PaddingPolicy`] before encryption.
+    pub fn with_padding(mut self, policy: PaddingPolicy) -> Self {
+        self.padding = Some(policy);
+        self
+    }
+
+    /// Set the [`EncryptionScheme`] used to encrypt payloads.
+    ///
+    /// This defaults to [`EncryptionScheme::EphemeralDh`], which all recipients support.
+    pub fn with_scheme(mut self, scheme: EncryptionScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Set the [`DigestAlgorithm`] used for the payload digest and HMAC.
+    ///
+    /// This defaults to [`DigestAlgorithm::Sha256`], which all recipients support.
+    pub fn with_digest_algorithm(mut self, digest_algorithm: DigestAlgorithm) -> Self {
+        self.digest_algorithm = digest_algorithm;
+        self
+    }
+
+    /// Replace the POP token used to access the inbox.
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    /// Get the public key associated with the messenger.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Get the address associated with the messenger.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get a reference to the [`KeyserverManager`].
+    pub fn keyserver_manager(&self) -> &KeyserverManager<S> {
+        &self.keyserver_manager
+    }
+
+    /// Get a reference to the [`RelayClient`].
+    pub fn relay_client(&self) -> &RelayClient<S> {
+        &self.relay_client
+    }
+
+    /// Get a reference to the [`BitcoinClient`].
+    pub fn bitcoin_client(&self) -> &BitcoinClient<B> {
+        &self.bitcoin_client
+    }
+}
+
+impl<S, B> Messenger<S, B>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Sync + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + error::Error + Send + 'static,
+    B: Service<Request<Body>, Response = Response<Body>> + Clone,
+    B::Error: fmt::Debug + fmt::Display + 'static,
+    B::Future: Send + 'static,
+{
+    /// Resolve the public key and metadata of an address by sampling the keyservers.
+    pub async fn resolve_contact(&self, address: &str) -> Result<Contact, ResolveError<S::Error>> {
+        let sample_response = self
+            .keyserver_manager
+            .uniform_sample_metadata(address, self.sample_size)
+            .await
+            .map_err(ResolveError::Sample)?;
+        let (_, package) = sample_response.response.ok_or(ResolveError::NotFound)?;
+
+        Ok(Contact {
+            address: address.to_string(),
+            public_key: package.public_key,
+            metadata: package.metadata,
+        })
+    }
+
+    /// Encrypt, stamp and send a [`Payload`] to an address, returning the [`Message`] sent.
+    ///
+    /// The stamp transaction is funded and signed by the wallet, then broadcast before the message
+    /// is put to the inbox of the address on the recipient's relay server, at `relay_url`.
+    pub async fn send(
+        &self,
+        relay_url: &str,
+        address: &str,
+        payload: Payload,
+    ) -> Result<Message, SendError<S::Error, B::Error>> {
+        let message = self.prepare(address, payload).await?;
+
+        // Put message
+        let message_set = MessageSet {
+            messages: vec![message.clone()],
+        };
+        self.relay_client
+            .put_messages(relay_url, address, message_set)
+            .await
+            .map_err(SendError::Relay)?;
+
+        Ok(message)
+    }
+
+    /// Encrypt and stamp a [`Payload`] to an address, without putting it to the relay server.
+    ///
+    /// The stamp transaction is broadcast, hence the message should be delivered, for example via
+    /// an [`Outbox`](crate::outbox::Outbox).
+    pub async fn prepare(
+        &self,
+        address: &str,
+        payload: Payload,
+    ) -> Result<Message, SendError<S::Error, B::Error>> {
+        let contact = self
+            .resolve_contact(address)
+            .await
+            .map_err(SendError::Resolve)?;
+
+        // Generate salt
+        let mut salt = [0; 32];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| SendError::Random)?;
+
+        // Seal payload
+        let mut builder = MessageBuilder::new(payload)
+            .with_scheme(self.scheme)
+            .with_digest_algorithm(self.digest_algorithm);
+        if let Some(policy) = &self.padding {
+            builder = builder.with_padding(policy.clone());
+        }
+        let mut message = builder
+            .seal(&self.private_key, &contact.public_key, &salt)
+            .map_err(|err| match err {
+                SealError::SharedKey(err) => SendError::SharedKey(err),
+                SealError::UnsupportedScheme => SendError::UnsupportedScheme,
+                SealError::UnsupportedDigestAlgorithm => SendError::UnsupportedDigestAlgorithm,
+            })?;
+        let payload_digest: [u8; 32] = message.payload_digest[..].try_into().unwrap(); // This is safe
+
+        // Construct stamp
+        let stamp_outpoints = self
+            .issue_stamp(&contact.public_key, &payload_digest)
+            .await?;
+        message.stamp = Some(Stamp {
+            stamp_type: StampType::MessageCommitment.into(),
+            stamp_outpoints: vec![stamp_outpoints],
+        });
+
+        Ok(message)
+    }
+
+    /// Fund, sign and broadcast a stamp transaction covering the payload digest.
+    async fn issue_stamp(
+        &self,
+        destination_public_key: &PublicKey,
+        payload_digest: &[u8; 32],
+    ) -> Result<StampOutpoints, SendError<S::Error, B::Error>> {
+        // Construct unfunded transaction
+        let stamp_outputs = create_stamp_outputs(
+            destination_public_key,
+            payload_digest,
+            0,
+            &[self.stamp_amount],
+        )
+        .map_err(SendError::Stamp)?;
+        let transaction = Transaction {
+            version: 2,
+            inputs: vec![],
+            outputs: stamp_outputs.clone(),
+            lock_time: 0,
+        };
+        let raw_transaction = transaction.encode_to_bytes();
+
+        // Fund transaction
+        let funded_transaction = self
+            .bitcoin_client
+            .fund_raw_transaction(&raw_transaction)
+            .await
+            .map_err(SendError::Node)?;
+        let raw_funded = funded_transaction
+            .raw_transaction()
+            .map_err(|err| SendError::Node(err.into()))?;
+
+        // Sign transaction
+        let signed_transaction = self
+            .bitcoin_client
+            .sign_raw_transaction_with_wallet(&raw_funded)
+            .await
+            .map_err(SendError::Node)?;
+        if !signed_transaction.complete {
+            return Err(SendError::IncompleteSignatures);
+        }
+        let raw_signed = signed_transaction
+            .raw_transaction()
+            .map_err(|err| SendError::Node(err.into()))?;
+        let transaction =
+            Transaction::decode(&mut raw_signed.as_slice()).map_err(SendError::Transaction)?;
+
+        // Find stamp outputs, the change output may have been inserted between them
+        let vouts = stamp_outputs
+            .iter()
+            .map(|stamp_output| {
+                transaction
+                    .outputs
+                    .iter()
+                    .position(|output| output == stamp_output)
+                    .map(|vout| vout as u32)
+                    .ok_or(SendError::StampOutputMissing)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Broadcast transaction
+        self.bitcoin_client
+            .send_tx(&raw_signed)
+            .await
+            .map_err(SendError::Node)?;
+
+        Ok(StampOutpoints {
+            stamp_tx: raw_signed,
+            vouts,
+        })
+    }
+
+    /// Import the stamp outputs of a received message into the wallet as watch-only scripts,
+    /// allowing them to be tracked before they are swept.
+    ///
+    /// If `rescan` is set, the wallet rescans the chain after the final import.
+    pub async fn watch_stamp(
+        &self,
+        message: &ParsedMessage,
+        rescan: bool,
+    ) -> Result<Vec<StampWatchEntry>, WatchStampError<B::Error>> {
+        let entries = message
+            .stamp
+            .watch_entries(&message.payload_digest, &message.destination_public_key)
+            .map_err(WatchStampError::Stamp)?;
+        for (index, entry) in entries.iter().enumerate() {
+            let rescan = rescan && index + 1 == entries.len();
+            self.bitcoin_client
+                .import_address(&entry.script_hex(), rescan)
+                .await
+                .map_err(WatchStampError::Node)?;
+        }
+        Ok(entries)
+    }
+
+    /// Publish the identity of the messenger, signing the [`AddressMetadata`] and broadcasting it to
+    /// the keyservers, then putting the [`Profile`] to the relay server.
+    ///
+    /// The keyservers are authorized using the `keyserver_token` and the relay server using the
+    /// POP token of the messenger. The profile is only put once the metadata has been accepted by
+    /// enough keyservers, see [`ReplicationThreshold`](keyserver_client::ReplicationThreshold).
+    /// The errors of keyservers which rejected the metadata are returned.
+    pub async fn publish_identity(
+        &self,
+        metadata: AddressMetadata,
+        profile: Profile,
+        keyserver_token: String,
+    ) -> Result<AggregateResponse<(), PutMetadataError<S::Error>>, PublishError<S::Error>> {
+        let relay_token = self.token.clone().ok_or(PublishError::MissingToken)?;
+
+        // Sign metadata
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap(); // This is safe
+        let auth_wrapper = AuthWrapper::sign(&self.private_key, raw_metadata);
+
+        // Broadcast metadata
+        let aggregate_response = self
+            .keyserver_manager
+            .uniform_broadcast_metadata(
+                &self.address,
+                auth_wrapper,
+                keyserver_token,
+                self.sample_size,
+            )
+            .await
+            .map_err(PublishError::Keyserver)?;
+
+        // Put profile
+        self.relay_client
+            .put_profile(&self.relay_url, &self.address, profile, relay_token)
+            .await
+            .map_err(PublishError::Relay)?;
+
+        Ok(aggregate_response)
+    }
+
+    /// Retrieve and open the messages in the inbox.
+    ///
+    /// The primary relay server and each mirror relay server are fetched from in turn. Messages
+    /// already retrieved, identified by their payload digest, are skipped.
+    ///
+    /// Messages which fail to parse, or fail stamp verification, authentication or decryption
+    /// are returned in [`Inbox::errors`].
+    pub async fn inbox(&self) -> Result<Inbox, InboxError<S::Error>> {
+        let token = self.token.clone().ok_or(InboxError::MissingToken)?;
+
+        // Fetch pages
+        let mut raw_messages = Vec::new();
+        for relay_url in std::iter::once(&self.relay_url).chain(&self.mirror_relay_urls) {
+            let message_page = self
+                .relay_client
+                .get_messages(relay_url, &self.address, token.clone())
+                .await
+                .map_err(InboxError::Relay)?;
+            raw_messages.extend(message_page.messages);
+        }
+
+        let mut local_deduplicator = MessageDeduplicator::new(raw_messages.len());
+        let mut shared_deduplicator = self
+            .deduplicator
+            .as_ref()
+            .map(|deduplicator| deduplicator.lock().unwrap());
+        let deduplicator = shared_deduplicator
+            .as_deref_mut()
+            .unwrap_or(&mut local_deduplicator);
+
+        let mut messages = Vec::with_capacity(raw_messages.len());
+        let mut errors = Vec::new();
+        for (index, message) in raw_messages.into_iter().enumerate() {
+            let message = match message.parse() {
+                Ok(ok) => ok,
+                Err(err) => {
+                    errors.push((index, InboxMessageError::Parse(err)));
+                    continue;
+                }
+            };
+
+            // Skip duplicates
+            if !deduplicator.insert(message.payload_digest) {
+                continue;
+            }
+
+            match message.open(&self.private_key[..]) {
+                Ok(opened) => messages.push(ReceivedMessage { message, opened }),
+                Err(err) => errors.push((index, InboxMessageError::Open(err))),
+            }
+        }
+
+        Ok(Inbox { messages, errors })
+    }
+}
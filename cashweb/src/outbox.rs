@@ -0,0 +1,442 @@
+//! This module contains the [`Outbox`] which durably queues sealed messages and retries putting
+//! them to relay servers, with exponential backoff, until they are delivered.
+//!
+//! Entries, along with their [`DeliveryState`] and the time of their next attempt, are persisted via
+//! an [`OutboxStore`], hence delivery resumes across process restarts. A [`MemoryOutboxStore`] is
+//! provided. Applications drive delivery by calling [`Outbox::flush`], for example on a timer or
+//! when connectivity is restored, see [`Outbox::next_attempt`].
+//!
+//! This module is enabled by the `messenger` feature.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error, fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use hyper::{Body, Request, Response};
+use prost::Message as _;
+use relay::{Message, MessageSet};
+use relay_client::RelayClient;
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+use tower_service::Service;
+
+/// The identifier of an [`OutboxEntry`], the SHA-256 digest of the serialized [`Message`].
+///
+/// The payload digest is not used as messages sealed to a group share their payload.
+pub type OutboxId = [u8; 32];
+
+/// The delivery state of an [`OutboxEntry`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// The message has yet to be delivered, and will be retried.
+    Pending {
+        /// The number of failed attempts.
+        attempts: u32,
+        /// The time after which the next attempt should be made.
+        next_attempt: SystemTime,
+        /// The error of the last failed attempt.
+        last_error: Option<String>,
+    },
+    /// The message was accepted by the relay server.
+    Delivered {
+        /// The time of delivery.
+        delivered_at: SystemTime,
+    },
+    /// The maximum number of attempts was reached, the message will not be retried.
+    Failed {
+        /// The number of failed attempts.
+        attempts: u32,
+        /// The error of the last failed attempt.
+        last_error: String,
+    },
+}
+
+impl DeliveryState {
+    /// Check whether the message is pending and due an attempt at the given time.
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        match self {
+            Self::Pending { next_attempt, .. } => *next_attempt <= now,
+            _ => false,
+        }
+    }
+}
+
+/// Describes the delay between attempts.
+///
+/// The delay after the `n`th failed attempt is `initial * 2^(n - 1)`, capped at `max_delay`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// The delay after the first failed attempt.
+    pub initial: Duration,
+    /// The maximum delay between attempts.
+    pub max_delay: Duration,
+    /// The number of failed attempts after which the message is marked as failed.
+    pub max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60 * 60),
+            max_attempts: 20,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay following a number of failed attempts.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        self.initial
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+/// A sealed message queued for delivery to the inbox of an address on a relay server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxEntry {
+    /// The identifier of the entry.
+    pub id: OutboxId,
+    /// The URL of the relay server.
+    pub relay_url: String,
+    /// The address of the recipient.
+    pub address: String,
+    /// The sealed and stamped message.
+    pub message: Message,
+    /// The delivery state.
+    pub state: DeliveryState,
+}
+
+impl OutboxEntry {
+    /// Create a new entry, due immediately.
+    pub fn new(relay_url: String, address: String, message: Message) -> Self {
+        let mut raw_message = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut raw_message).unwrap(); // This is safe
+        let mut id = [0; 32];
+        id.copy_from_slice(digest(&SHA256, &raw_message).as_ref());
+
+        Self {
+            id,
+            relay_url,
+            address,
+            message,
+            state: DeliveryState::Pending {
+                attempts: 0,
+                next_attempt: SystemTime::UNIX_EPOCH,
+                last_error: None,
+            },
+        }
+    }
+
+    /// Record a successful attempt.
+    pub fn record_success(&mut self, now: SystemTime) {
+        self.state = DeliveryState::Delivered { delivered_at: now };
+    }
+
+    /// Record a failed attempt, scheduling the next attempt according to the [`Backoff`].
+    pub fn record_failure(&mut self, error: String, now: SystemTime, backoff: &Backoff) {
+        let attempts = match &self.state {
+            DeliveryState::Pending { attempts, .. } => attempts + 1,
+            _ => 1,
+        };
+        self.state = if attempts >= backoff.max_attempts {
+            DeliveryState::Failed {
+                attempts,
+                last_error: error,
+            }
+        } else {
+            DeliveryState::Pending {
+                attempts,
+                next_attempt: now + backoff.delay(attempts),
+                last_error: Some(error),
+            }
+        };
+    }
+}
+
+/// A store persisting [`OutboxEntry`]s.
+pub trait OutboxStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get an entry.
+    fn get_entry(&self, id: &OutboxId) -> Result<Option<OutboxEntry>, Self::Error>;
+
+    /// Get all entries.
+    fn entries(&self) -> Result<Vec<OutboxEntry>, Self::Error>;
+
+    /// Insert an entry, replacing any existing entry with the same identifier.
+    fn put_entry(&self, entry: OutboxEntry) -> Result<(), Self::Error>;
+
+    /// Remove an entry.
+    fn remove_entry(&self, id: &OutboxId) -> Result<(), Self::Error>;
+}
+
+/// An in-memory [`OutboxStore`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryOutboxStore {
+    entries: Arc<Mutex<HashMap<OutboxId, OutboxEntry>>>,
+}
+
+impl MemoryOutboxStore {
+    /// Create a new, empty, [`MemoryOutboxStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl OutboxStore for MemoryOutboxStore {
+    type Error = Infallible;
+
+    fn get_entry(&self, id: &OutboxId) -> Result<Option<OutboxEntry>, Self::Error> {
+        Ok(self.entries.lock().unwrap().get(id).cloned())
+    }
+
+    fn entries(&self) -> Result<Vec<OutboxEntry>, Self::Error> {
+        Ok(self.entries.lock().unwrap().values().cloned().collect())
+    }
+
+    fn put_entry(&self, entry: OutboxEntry) -> Result<(), Self::Error> {
+        self.entries.lock().unwrap().insert(entry.id, entry);
+        Ok(())
+    }
+
+    fn remove_entry(&self, id: &OutboxId) -> Result<(), Self::Error> {
+        self.entries.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// Error associated with flushing the [`Outbox`].
+#[derive(Debug, Error)]
+pub enum OutboxError<T: fmt::Debug + fmt::Display> {
+    /// Failed to access the [`OutboxStore`].
+    #[error("outbox store failure: {0}")]
+    Store(T),
+}
+
+/// Outbox queues sealed messages in an [`OutboxStore`] and puts them to relay servers, retrying
+/// failures according to a [`Backoff`].
+#[derive(Clone, Debug)]
+pub struct Outbox<S, T> {
+    relay_client: RelayClient<S>,
+    store: T,
+    backoff: Backoff,
+}
+
+impl<S, T> Outbox<S, T> {
+    /// Create a new [`Outbox`] from a [`RelayClient`] and an [`OutboxStore`].
+    pub fn new(relay_client: RelayClient<S>, store: T) -> Self {
+        Self {
+            relay_client,
+            store,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Set the [`Backoff`] between attempts.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Get a reference to the [`OutboxStore`].
+    pub fn store(&self) -> &T {
+        &self.store
+    }
+}
+
+impl<S, T> Outbox<S, T>
+where
+    T: OutboxStore,
+{
+    /// Queue a sealed and stamped [`Message`] for delivery to the inbox of an address, returning
+    /// the identifier of the entry.
+    ///
+    /// The message is delivered on the next [`flush`](Outbox::flush).
+    pub fn enqueue(
+        &self,
+        relay_url: String,
+        address: String,
+        message: Message,
+    ) -> Result<OutboxId, T::Error> {
+        let entry = OutboxEntry::new(relay_url, address, message);
+        let id = entry.id;
+        self.store.put_entry(entry)?;
+        Ok(id)
+    }
+
+    /// Get the [`DeliveryState`] of an entry.
+    pub fn state(&self, id: &OutboxId) -> Result<Option<DeliveryState>, T::Error> {
+        Ok(self.store.get_entry(id)?.map(|entry| entry.state))
+    }
+
+    /// The earliest time at which a pending entry is due, if any.
+    pub fn next_attempt(&self) -> Result<Option<SystemTime>, T::Error> {
+        let next_attempt = self
+            .store
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| match entry.state {
+                DeliveryState::Pending { next_attempt, .. } => Some(next_attempt),
+                _ => None,
+            })
+            .min();
+        Ok(next_attempt)
+    }
+
+    /// Requeue a failed entry, resetting its attempts.
+    pub fn retry(&self, id: &OutboxId) -> Result<(), T::Error> {
+        if let Some(mut entry) = self.store.get_entry(id)? {
+            if let DeliveryState::Failed { last_error, .. } = entry.state {
+                entry.state = DeliveryState::Pending {
+                    attempts: 0,
+                    next_attempt: SystemTime::UNIX_EPOCH,
+                    last_error: Some(last_error),
+                };
+                self.store.put_entry(entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove delivered entries from the store.
+    pub fn prune_delivered(&self) -> Result<(), T::Error> {
+        for entry in self.store.entries()? {
+            if let DeliveryState::Delivered { .. } = entry.state {
+                self.store.remove_entry(&entry.id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S, T> Outbox<S, T>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Sync + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + error::Error + Send + 'static,
+    T: OutboxStore,
+{
+    /// Attempt to put each due entry to its relay server, returning the updated entries.
+    ///
+    /// Entries are attempted one at a time, and persisted after each attempt.
+    pub async fn flush(&self) -> Result<Vec<OutboxEntry>, OutboxError<T::Error>> {
+        let now = SystemTime::now();
+        let due = self
+            .store
+            .entries()
+            .map_err(OutboxError::Store)?
+            .into_iter()
+            .filter(|entry| entry.state.is_due(now));
+
+        let mut updated = Vec::new();
+        for mut entry in due {
+            let message_set = MessageSet {
+                messages: vec![entry.message.clone()],
+            };
+            let result = self
+                .relay_client
+                .put_messages(&entry.relay_url, &entry.address, message_set)
+                .await;
+            match result {
+                Ok(()) => entry.record_success(SystemTime::now()),
+                Err(err) => entry.record_failure(err.to_string(), SystemTime::now(), &self.backoff),
+            }
+            self.store
+                .put_entry(entry.clone())
+                .map_err(OutboxError::Store)?;
+            updated.push(entry);
+        }
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: 3,
+        };
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(4), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5), Duration::from_secs(10));
+        assert_eq!(backoff.delay(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn delivery_state() {
+        let backoff = Backoff {
+            max_attempts: 2,
+            ..Default::default()
+        };
+        let now = SystemTime::now();
+        let mut entry = OutboxEntry::new(
+            "http://relay".to_string(),
+            "alice".to_string(),
+            Message::default(),
+        );
+        assert!(entry.state.is_due(now));
+
+        entry.record_failure("offline".to_string(), now, &backoff);
+        assert!(!entry.state.is_due(now));
+        assert!(entry.state.is_due(now + backoff.initial));
+
+        entry.record_failure("offline".to_string(), now, &backoff);
+        assert_eq!(
+            entry.state,
+            DeliveryState::Failed {
+                attempts: 2,
+                last_error: "offline".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn store_and_retry() {
+        let outbox = Outbox::new(RelayClient::new(), MemoryOutboxStore::new());
+        let id = outbox
+            .enqueue(
+                "http://relay".to_string(),
+                "alice".to_string(),
+                Message::default(),
+            )
+            .unwrap();
+        assert_eq!(outbox.next_attempt().unwrap(), Some(SystemTime::UNIX_EPOCH));
+
+        let mut entry = outbox.store().get_entry(&id).unwrap().unwrap();
+        entry.state = DeliveryState::Failed {
+            attempts: 20,
+            last_error: "offline".to_string(),
+        };
+        outbox.store().put_entry(entry).unwrap();
+        assert_eq!(outbox.next_attempt().unwrap(), None);
+
+        outbox.retry(&id).unwrap();
+        assert!(outbox
+            .state(&id)
+            .unwrap()
+            .unwrap()
+            .is_due(SystemTime::now()));
+
+        let mut entry = outbox.store().get_entry(&id).unwrap().unwrap();
+        entry.record_success(SystemTime::now());
+        outbox.store().put_entry(entry).unwrap();
+        outbox.prune_delivered().unwrap();
+        assert_eq!(outbox.state(&id).unwrap(), None);
+    }
+}
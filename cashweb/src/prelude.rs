@@ -0,0 +1,56 @@
+//! A "prelude" for crates using the `cashweb` crate.
+//!
+//! This prelude is similar to the standard library's prelude in that you'll
+//! almost always want to import its entire contents, but unlike the
+//! standard library's prelude you'll have to do so manually:
+//!
+//! ```
+//! # #[allow(unused_imports)]
+//! use cashweb::prelude::*;
+//! ```
+//!
+//! Only items from the enabled components are included.
+//!
+//! The prelude may grow over time as additional items see ubiquitous use.
+
+#[cfg(feature = "auth-wrapper")]
+#[doc(inline)]
+pub use crate::auth_wrapper::{AuthWrapper, ParsedAuthWrapper};
+#[cfg(feature = "bitcoin")]
+#[doc(inline)]
+pub use crate::bitcoin::{Decodable, Encodable, Network};
+#[cfg(feature = "bitcoin-client")]
+#[doc(inline)]
+pub use crate::bitcoin_client::BitcoinClient;
+#[cfg(feature = "keyserver-client")]
+#[doc(inline)]
+pub use crate::keyserver_client::{KeyserverClient, KeyserverManager};
+#[cfg(feature = "messenger")]
+#[doc(inline)]
+pub use crate::messenger::Messenger;
+#[cfg(feature = "relay")]
+#[doc(inline)]
+pub use crate::relay::{Message, ParsedMessage, Payload};
+#[cfg(feature = "relay-client")]
+#[doc(inline)]
+pub use crate::relay_client::RelayClient;
+
+#[cfg(all(test, feature = "bitcoin", feature = "relay"))]
+mod tests {
+    use super::*;
+    use crate::bitcoin::transaction::Output;
+
+    #[test]
+    fn prelude_items() {
+        let output = Output {
+            value: 1000,
+            script: vec![0x6a].into(),
+        };
+        let mut raw_output = output.encode_to_bytes();
+        assert_eq!(Output::decode(&mut raw_output).unwrap(), output);
+
+        // The relay models do not collide with the encoding traits
+        let parsed: Result<ParsedMessage, _> = Message::default().parse();
+        assert!(parsed.is_err());
+    }
+}
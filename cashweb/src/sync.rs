@@ -0,0 +1,566 @@
+//! This module contains the [`InboxSync`] which incrementally retrieves the messages in an inbox,
+//! surfacing each message once.
+//!
+//! A [`SyncCursor`] records, per relay server, the latest `received_time` synchronized and the
+//! payload digests of the messages received at that time. Each synchronization is driven by a
+//! [`SyncState`], which resumes from the payload digest of the last message seen and pages forward
+//! until no new messages are returned. Should the relay server have since removed that message, for
+//! example after a long period offline, the state falls back to rescanning from the
+//! `received_time` and reports a gap.
+//!
+//! Cursors are persisted via a [`CursorStore`], a [`MemoryCursorStore`] is provided. The cursor of
+//! a [`SyncBatch`] is only persisted once the batch is [committed](InboxSync::commit), hence
+//! messages are redelivered if the application fails before handling them.
+//!
+//! Payloads may be persisted in a [`PayloadStore`], see [`SyncBatch::store_payloads`]. Messages
+//! retrieved without their payload have it fetched lazily, from the payloads endpoint of the relay
+//! server, by [`fetch_payload`](InboxSync::fetch_payload).
+//!
+//! This module is enabled by the `messenger` feature.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    error, fmt,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{Body, Request, Response};
+use relay::{
+    payload_store::{PayloadBackend, PayloadStore, PayloadStoreError},
+    DigestAlgorithm, DigestError, Message, MessagePage,
+};
+use relay_client::{
+    services::{GetMessageError, GetPayloadsError},
+    MessageRange, RelayClient, RelayError,
+};
+use thiserror::Error;
+use tower_service::Service;
+
+/// The default maximum number of pages requested per synchronization.
+pub const DEFAULT_MAX_PAGES: usize = 64;
+
+/// The position up to which an inbox has been synchronized.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncCursor {
+    /// The latest `received_time` synchronized, in unix milliseconds.
+    pub time: i64,
+    /// The payload digests of the messages received at `time`, in inbox order.
+    pub digests: Vec<[u8; 32]>,
+}
+
+impl SyncCursor {
+    /// Check whether a message, given its `received_time` and payload digest, has been seen.
+    pub fn is_seen(&self, time: i64, payload_digest: &[u8; 32]) -> bool {
+        time < self.time || (time == self.time && self.digests.contains(payload_digest))
+    }
+
+    /// Advance the cursor past a message, returning whether it was unseen.
+    pub fn advance(&mut self, time: i64, payload_digest: [u8; 32]) -> bool {
+        if self.is_seen(time, &payload_digest) {
+            return false;
+        }
+        if time > self.time {
+            self.time = time;
+            self.digests.clear();
+        }
+        self.digests.push(payload_digest);
+        true
+    }
+
+    /// The request to resume synchronization from.
+    pub fn resume_request(&self) -> SyncRequest {
+        match self.digests.last() {
+            Some(digest) => SyncRequest::Resume(*digest),
+            None => SyncRequest::Rescan(self.time),
+        }
+    }
+}
+
+/// A request issued while synchronizing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncRequest {
+    /// Request the messages following, and including, the message with the payload digest.
+    Resume([u8; 32]),
+    /// Request the messages received at, or after, the time.
+    Rescan(i64),
+}
+
+impl SyncRequest {
+    /// The [`MessageRange`] requested.
+    pub fn range(&self) -> MessageRange {
+        match self {
+            Self::Resume(digest) => MessageRange {
+                start_digest: Some(*digest),
+                ..Default::default()
+            },
+            Self::Rescan(time) => MessageRange {
+                start_time: Some(*time),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The next step of a [`SyncState`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncStep {
+    /// Fetch a [`MessagePage`] and [apply](SyncState::apply) it.
+    Fetch(SyncRequest),
+    /// Synchronization is complete.
+    Done,
+}
+
+/// The state of a single synchronization against a relay server.
+#[derive(Clone, Debug)]
+pub struct SyncState {
+    cursor: SyncCursor,
+    step: SyncStep,
+    messages: Vec<Message>,
+    invalid: Vec<DigestError>,
+    gap: bool,
+}
+
+impl SyncState {
+    /// Begin synchronizing from a [`SyncCursor`].
+    pub fn new(cursor: SyncCursor) -> Self {
+        let step = SyncStep::Fetch(cursor.resume_request());
+        Self {
+            cursor,
+            step,
+            messages: Vec::new(),
+            invalid: Vec::new(),
+            gap: false,
+        }
+    }
+
+    /// The next step.
+    pub fn step(&self) -> SyncStep {
+        self.step
+    }
+
+    /// The cursor, advanced past the messages applied so far.
+    pub fn cursor(&self) -> &SyncCursor {
+        &self.cursor
+    }
+
+    /// Apply the [`MessagePage`] fetched for the current step, advancing to the next step.
+    ///
+    /// Messages already seen are discarded, as are messages whose payload digest cannot be
+    /// calculated.
+    pub fn apply(&mut self, page: MessagePage) {
+        let request = match self.step {
+            SyncStep::Fetch(request) => request,
+            SyncStep::Done => return,
+        };
+
+        // The message resumed from is always returned, unless it has been removed
+        if page.messages.is_empty() {
+            self.step = match request {
+                SyncRequest::Resume(_) => {
+                    self.gap = true;
+                    SyncStep::Fetch(SyncRequest::Rescan(self.cursor.time))
+                }
+                SyncRequest::Rescan(_) => SyncStep::Done,
+            };
+            return;
+        }
+
+        let mut unseen = 0;
+        for message in page.messages {
+            let payload_digest = match message.digest() {
+                Ok(ok) => ok,
+                Err(err) => {
+                    self.invalid.push(err);
+                    continue;
+                }
+            };
+            if self.cursor.advance(message.received_time, payload_digest) {
+                self.messages.push(message);
+                unseen += 1;
+            }
+        }
+
+        // Page forward until no unseen messages are returned
+        self.step = if unseen == 0 {
+            SyncStep::Done
+        } else {
+            SyncStep::Fetch(self.cursor.resume_request())
+        };
+    }
+
+    /// Finish synchronizing, producing a [`SyncBatch`].
+    pub fn finish(self, relay_url: String) -> SyncBatch {
+        SyncBatch {
+            relay_url,
+            complete: self.step == SyncStep::Done,
+            cursor: self.cursor,
+            messages: self.messages,
+            invalid: self.invalid,
+            gap: self.gap,
+        }
+    }
+}
+
+/// The result of synchronizing against a relay server.
+#[derive(Clone, Debug)]
+pub struct SyncBatch {
+    /// The URL of the relay server.
+    pub relay_url: String,
+    /// The unseen messages, in inbox order.
+    pub messages: Vec<Message>,
+    /// The cursor following the messages, persisted on [commit](InboxSync::commit).
+    pub cursor: SyncCursor,
+    /// Errors calculating the payload digest of messages which were discarded.
+    pub invalid: Vec<DigestError>,
+    /// Whether the message resumed from had been removed, in which case messages may have been
+    /// missed.
+    pub gap: bool,
+    /// Whether synchronization completed, otherwise the page limit was reached and further
+    /// messages may remain.
+    pub complete: bool,
+}
+
+impl SyncBatch {
+    /// Put the payloads carried by the messages into a [`PayloadStore`], returning the number
+    /// stored.
+    ///
+    /// Messages without a payload are skipped, their payload may be fetched lazily using
+    /// [`fetch_payload`](InboxSync::fetch_payload).
+    pub fn store_payloads<B: PayloadBackend>(
+        &self,
+        store: &PayloadStore<B>,
+    ) -> Result<usize, PayloadStoreError<B::Error>> {
+        let mut stored = 0;
+        for message in self
+            .messages
+            .iter()
+            .filter(|message| !message.payload.is_empty())
+        {
+            store.put_message(message)?;
+            stored += 1;
+        }
+        Ok(stored)
+    }
+}
+
+/// A store persisting a [`SyncCursor`] per relay server and address.
+pub trait CursorStore {
+    /// Error associated with accessing the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get the cursor of an inbox.
+    fn get_cursor(&self, relay_url: &str, address: &str)
+        -> Result<Option<SyncCursor>, Self::Error>;
+
+    /// Set the cursor of an inbox.
+    fn put_cursor(
+        &self,
+        relay_url: &str,
+        address: &str,
+        cursor: SyncCursor,
+    ) -> Result<(), Self::Error>;
+}
+
+/// An in-memory [`CursorStore`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryCursorStore {
+    cursors: Arc<Mutex<HashMap<(String, String), SyncCursor>>>,
+}
+
+impl MemoryCursorStore {
+    /// Create a new, empty, [`MemoryCursorStore`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl CursorStore for MemoryCursorStore {
+    type Error = Infallible;
+
+    fn get_cursor(
+        &self,
+        relay_url: &str,
+        address: &str,
+    ) -> Result<Option<SyncCursor>, Self::Error> {
+        let key = (relay_url.to_string(), address.to_string());
+        Ok(self.cursors.lock().unwrap().get(&key).cloned())
+    }
+
+    fn put_cursor(
+        &self,
+        relay_url: &str,
+        address: &str,
+        cursor: SyncCursor,
+    ) -> Result<(), Self::Error> {
+        let key = (relay_url.to_string(), address.to_string());
+        self.cursors.lock().unwrap().insert(key, cursor);
+        Ok(())
+    }
+}
+
+/// Error associated with synchronizing an inbox.
+#[derive(Debug, Error)]
+pub enum SyncError<E, T>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+    T: fmt::Debug + fmt::Display,
+{
+    /// Failed to get messages from the relay server.
+    #[error("failed to get messages: {0}")]
+    Relay(RelayError<GetMessageError<E>>),
+    /// Failed to access the [`CursorStore`].
+    #[error("cursor store failure: {0}")]
+    Store(T),
+}
+
+/// Error associated with fetching a payload.
+#[derive(Debug, Error)]
+pub enum FetchPayloadError<E, B>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+    B: fmt::Debug + fmt::Display,
+{
+    /// Failed to calculate the payload digest of the message.
+    #[error("invalid digest: {0}")]
+    Digest(DigestError),
+    /// Failed to get the payload from the relay server.
+    #[error("failed to get payload: {0}")]
+    Relay(RelayError<GetPayloadsError<E>>),
+    /// The relay server does not hold the payload.
+    #[error("payload not found")]
+    NotFound,
+    /// Failed to access the [`PayloadStore`], or the payload does not match its digest.
+    #[error("payload store failure: {0}")]
+    Store(PayloadStoreError<B>),
+}
+
+/// InboxSync retrieves the unseen messages in the inbox of an address, tracking a [`SyncCursor`]
+/// per relay server in a [`CursorStore`].
+#[derive(Clone, Debug)]
+pub struct InboxSync<S, T> {
+    relay_client: RelayClient<S>,
+    store: T,
+    address: String,
+    max_pages: usize,
+}
+
+impl<S, T> InboxSync<S, T> {
+    /// Create a new [`InboxSync`] for the inbox of an address.
+    pub fn new(relay_client: RelayClient<S>, store: T, address: String) -> Self {
+        Self {
+            relay_client,
+            store,
+            address,
+            max_pages: DEFAULT_MAX_PAGES,
+        }
+    }
+
+    /// Set the maximum number of pages requested per synchronization.
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Get a reference to the [`CursorStore`].
+    pub fn store(&self) -> &T {
+        &self.store
+    }
+}
+
+impl<S, T> InboxSync<S, T>
+where
+    T: CursorStore,
+{
+    /// Persist the cursor of a [`SyncBatch`], once its messages have been handled.
+    pub fn commit(&self, batch: &SyncBatch) -> Result<(), T::Error> {
+        self.store
+            .put_cursor(&batch.relay_url, &self.address, batch.cursor.clone())
+    }
+
+    /// Reset the cursor of a relay server, causing the entire inbox to be retrieved.
+    pub fn reset(&self, relay_url: &str) -> Result<(), T::Error> {
+        self.store
+            .put_cursor(relay_url, &self.address, SyncCursor::default())
+    }
+}
+
+impl<S, T> InboxSync<S, T>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Sync + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display + error::Error + Send + 'static,
+    T: CursorStore,
+{
+    /// Retrieve the messages received by a relay server since the last committed cursor.
+    ///
+    /// The cursor is not persisted, call [`commit`](InboxSync::commit) once the batch has been
+    /// handled.
+    pub async fn sync(
+        &self,
+        relay_url: &str,
+        token: String,
+    ) -> Result<SyncBatch, SyncError<S::Error, T::Error>> {
+        let cursor = self
+            .store
+            .get_cursor(relay_url, &self.address)
+            .map_err(SyncError::Store)?
+            .unwrap_or_default();
+
+        let mut state = SyncState::new(cursor);
+        for _ in 0..self.max_pages {
+            let request = match state.step() {
+                SyncStep::Fetch(request) => request,
+                SyncStep::Done => break,
+            };
+            let page = self
+                .relay_client
+                .get_messages_range(relay_url, &self.address, token.clone(), &request.range())
+                .await
+                .map_err(SyncError::Relay)?;
+            state.apply(page);
+        }
+
+        Ok(state.finish(relay_url.to_string()))
+    }
+
+    /// Get the payload of a message, from the [`PayloadStore`] if present, otherwise from the
+    /// payloads endpoint of a relay server.
+    ///
+    /// Fetched payloads are verified against the payload digest of the message before being
+    /// stored.
+    pub async fn fetch_payload<B: PayloadBackend>(
+        &self,
+        relay_url: &str,
+        token: String,
+        message: &Message,
+        store: &PayloadStore<B>,
+    ) -> Result<Vec<u8>, FetchPayloadError<S::Error, B::Error>> {
+        let payload_digest = message.digest().map_err(FetchPayloadError::Digest)?;
+        let digest_algorithm = DigestAlgorithm::from_i32(message.digest_algorithm).unwrap(); // This is safe
+        if let Some(payload) = store
+            .get(digest_algorithm, &payload_digest)
+            .map_err(FetchPayloadError::Store)?
+        {
+            return Ok(payload);
+        }
+        if !message.payload.is_empty() {
+            store
+                .put_message(message)
+                .map_err(FetchPayloadError::Store)?;
+            return Ok(message.payload.clone());
+        }
+
+        // Fetch payload
+        let payload = self
+            .relay_client
+            .get_payload(relay_url, &self.address, token, &payload_digest)
+            .await
+            .map_err(FetchPayloadError::Relay)?
+            .ok_or(FetchPayloadError::NotFound)?;
+
+        // Verify and store payload
+        let fetched = Message {
+            payload,
+            ..message.clone()
+        };
+        store
+            .put_message(&fetched)
+            .map_err(FetchPayloadError::Store)?;
+        Ok(fetched.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(received_time: i64, digest_byte: u8) -> Message {
+        Message {
+            received_time,
+            payload_digest: vec![digest_byte; 32],
+            digest_algorithm: DigestAlgorithm::Sha256 as i32,
+            ..Default::default()
+        }
+    }
+
+    fn page(messages: Vec<Message>) -> MessagePage {
+        MessagePage {
+            messages,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let mut state = SyncState::new(SyncCursor::default());
+        assert_eq!(state.step(), SyncStep::Fetch(SyncRequest::Rescan(0)));
+
+        state.apply(page(vec![message(10, 1), message(20, 2), message(20, 3)]));
+        assert_eq!(state.step(), SyncStep::Fetch(SyncRequest::Resume([3; 32])));
+
+        // Only the message resumed from is returned
+        state.apply(page(vec![message(20, 3)]));
+        assert_eq!(state.step(), SyncStep::Done);
+
+        let batch = state.finish("http://relay".to_string());
+        assert!(batch.complete);
+        assert!(!batch.gap);
+        assert_eq!(batch.messages.len(), 3);
+        assert_eq!(
+            batch.cursor,
+            SyncCursor {
+                time: 20,
+                digests: vec![[2; 32], [3; 32]],
+            }
+        );
+    }
+
+    #[test]
+    fn gap() {
+        let cursor = SyncCursor {
+            time: 20,
+            digests: vec![[2; 32], [3; 32]],
+        };
+        let mut state = SyncState::new(cursor);
+
+        // The message resumed from has been removed
+        state.apply(page(vec![]));
+        assert_eq!(state.step(), SyncStep::Fetch(SyncRequest::Rescan(20)));
+
+        // Messages already seen at the cursor time are discarded
+        state.apply(page(vec![message(20, 2), message(30, 4)]));
+        state.apply(page(vec![message(30, 4)]));
+
+        let batch = state.finish("http://relay".to_string());
+        assert!(batch.gap);
+        assert_eq!(batch.messages, vec![message(30, 4)]);
+        assert_eq!(batch.cursor.digests, vec![[4; 32]]);
+    }
+
+    #[test]
+    fn store_payloads() {
+        let payload = b"payload".to_vec();
+        let payload_digest = DigestAlgorithm::Sha256.digest(&payload).unwrap();
+        let mut state = SyncState::new(SyncCursor::default());
+        state.apply(page(vec![
+            Message {
+                payload: payload.clone(),
+                payload_digest: payload_digest.to_vec(),
+                ..message(10, 0)
+            },
+            message(20, 1),
+        ]));
+        let batch = state.finish("http://relay".to_string());
+
+        // Only messages carrying their payload are stored
+        let store = PayloadStore::memory();
+        assert_eq!(batch.store_payloads(&store).unwrap(), 1);
+        assert_eq!(
+            store.get(DigestAlgorithm::Sha256, &payload_digest).unwrap(),
+            Some(payload)
+        );
+        assert!(!store.contains(&[1; 32]).unwrap());
+    }
+}
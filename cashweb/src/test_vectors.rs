@@ -0,0 +1,370 @@
+//! This module generates and verifies canonical test vectors for message sealing, stamp derivation
+//! and token construction, allowing alternative implementations of the specifications to
+//! cross-validate against this crate.
+//!
+//! Each vector is generated deterministically from a 32-byte seed. The inputs, such as private
+//! keys and salts, are derived from the seed and a label as `SHA256(seed || label || counter)`,
+//! incrementing the counter until a valid private key is found. Hence generating from the same seed
+//! always yields the same vector.
+//!
+//! Every vector carries both its inputs and its expected outputs. Verifying a vector recomputes the
+//! outputs from the inputs, so that vectors produced by another implementation can be checked
+//! against this crate, and vice versa.
+
+use bitcoin::{
+    transaction::{Output, Transaction},
+    Encodable,
+};
+use prost::Message as _;
+use relay::{
+    seal::{MessageBuilder, SealError},
+    secp::{PrivateKey, PublicKey, Secp256k1, SecpError},
+    stamp::{create_stamp_outputs, create_stamp_private_keys, StampError, StampKeyError},
+    EncryptionScheme, Message, Payload, PayloadEntry,
+};
+use ring::digest::{Context, SHA256};
+use thiserror::Error;
+use token::schemes::{
+    chain_commitment::{construct_commitment, construct_commitment_script, construct_token},
+    hmac_bearer::HmacScheme,
+};
+
+/// The text of the payload sealed within a [`SealVector`].
+pub const SEAL_VECTOR_TEXT: &str = "cash:web test vector";
+
+/// The amounts of the outputs of each stamp transaction within a [`StampVector`].
+pub const STAMP_VECTOR_AMOUNTS: &[&[u64]] = &[&[1000, 2000], &[3000]];
+
+/// Error associated with verifying a test vector.
+#[derive(Debug, Error)]
+pub enum VectorError {
+    /// A private key was invalid.
+    #[error("invalid private key: {0}")]
+    PrivateKey(SecpError),
+    /// A field could not be decoded.
+    #[error("failed to decode {0}")]
+    Decode(&'static str),
+    /// Failed to seal the payload.
+    #[error("failed to seal payload: {0}")]
+    Seal(SealError),
+    /// Failed to construct the stamp outputs.
+    #[error("failed to create stamp outputs: {0}")]
+    Stamp(StampError),
+    /// Failed to derive the stamp private keys.
+    #[error("failed to create stamp private keys: {0}")]
+    StampKey(StampKeyError),
+    /// A field differs from the recomputed value.
+    #[error("mismatched {0}")]
+    Mismatch(&'static str),
+}
+
+/// Derive 32 bytes from the seed and a label.
+fn derive_bytes(seed: &[u8; 32], label: &[u8], counter: u32) -> [u8; 32] {
+    let mut sha256_context = Context::new(&SHA256);
+    sha256_context.update(seed);
+    sha256_context.update(label);
+    sha256_context.update(&counter.to_be_bytes());
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(sha256_context.finish().as_ref());
+    bytes
+}
+
+/// Derive a valid private key from the seed and a label.
+fn derive_private_key(seed: &[u8; 32], label: &[u8]) -> [u8; 32] {
+    (0..)
+        .map(|counter| derive_bytes(seed, label, counter))
+        .find(|bytes| PrivateKey::from_slice(bytes).is_ok())
+        .unwrap() // This is safe
+}
+
+fn parse_private_key(raw_private_key: &[u8; 32]) -> Result<PrivateKey, VectorError> {
+    PrivateKey::from_slice(raw_private_key).map_err(VectorError::PrivateKey)
+}
+
+fn encode<M: prost::Message>(message: &M) -> Vec<u8> {
+    let mut raw_message = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut raw_message).unwrap(); // This is safe
+    raw_message
+}
+
+fn check(field: &'static str, equal: bool) -> Result<(), VectorError> {
+    if equal {
+        Ok(())
+    } else {
+        Err(VectorError::Mismatch(field))
+    }
+}
+
+/// A test vector for sealing a [`Payload`] into a [`Message`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SealVector {
+    /// The source private key.
+    pub source_private_key: [u8; 32],
+    /// The destination private key.
+    pub destination_private_key: [u8; 32],
+    /// The salt.
+    pub salt: [u8; 32],
+    /// The encryption scheme.
+    pub scheme: EncryptionScheme,
+    /// The serialized [`Payload`].
+    pub payload: Vec<u8>,
+    /// The serialized [`Message`], sealed without stamp outpoints.
+    pub message: Vec<u8>,
+}
+
+impl SealVector {
+    /// Generate the vector from a seed under an encryption scheme.
+    pub fn generate(seed: &[u8; 32], scheme: EncryptionScheme) -> Result<Self, VectorError> {
+        let payload = Payload {
+            entries: vec![PayloadEntry::text(SEAL_VECTOR_TEXT.to_string())],
+            ..Default::default()
+        };
+        let mut vector = Self {
+            source_private_key: derive_private_key(seed, b"source"),
+            destination_private_key: derive_private_key(seed, b"destination"),
+            salt: derive_bytes(seed, b"salt", 0),
+            scheme,
+            payload: encode(&payload),
+            message: vec![],
+        };
+        vector.message = encode(&vector.seal()?);
+        Ok(vector)
+    }
+
+    fn seal(&self) -> Result<Message, VectorError> {
+        let source_private_key = parse_private_key(&self.source_private_key)?;
+        let destination_private_key = parse_private_key(&self.destination_private_key)?;
+        let destination_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &destination_private_key);
+        let payload =
+            Payload::decode(self.payload.as_slice()).map_err(|_| VectorError::Decode("payload"))?;
+        MessageBuilder::new(payload)
+            .with_scheme(self.scheme)
+            .seal(&source_private_key, &destination_public_key, &self.salt)
+            .map_err(VectorError::Seal)
+    }
+
+    /// Verify the vector, resealing the payload and opening the message using the destination
+    /// private key.
+    pub fn verify(&self) -> Result<(), VectorError> {
+        check("message", encode(&self.seal()?) == self.message)?;
+
+        let message = Message::decode(self.message.as_slice())
+            .map_err(|_| VectorError::Decode("message"))?
+            .parse()
+            .map_err(|_| VectorError::Decode("message"))?;
+        let keys = message
+            .create_payload_keys(&self.destination_private_key)
+            .map_err(|_| VectorError::Mismatch("payload keys"))?;
+        keys.authenticate(&message.payload_digest, &message.payload_hmac)
+            .map_err(|_| VectorError::Mismatch("payload hmac"))?;
+        let plaintext = keys
+            .decrypt(&message.payload)
+            .map_err(|_| VectorError::Mismatch("payload"))?;
+        check("payload", plaintext == self.payload)
+    }
+}
+
+/// A test vector for deriving the outputs and private keys of a stamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StampVector {
+    /// The destination private key.
+    pub destination_private_key: [u8; 32],
+    /// The payload digest.
+    pub payload_digest: [u8; 32],
+    /// The amounts of the outputs of each stamp transaction.
+    pub amounts: Vec<Vec<u64>>,
+    /// The serialized stamp transactions, without inputs, containing the stamp outputs.
+    pub stamp_txs: Vec<Vec<u8>>,
+    /// The private key of each stamp output, grouped by transaction.
+    pub stamp_private_keys: Vec<Vec<[u8; 32]>>,
+}
+
+impl StampVector {
+    /// Generate the vector from a seed, using the [`STAMP_VECTOR_AMOUNTS`].
+    pub fn generate(seed: &[u8; 32]) -> Result<Self, VectorError> {
+        let mut vector = Self {
+            destination_private_key: derive_private_key(seed, b"destination"),
+            payload_digest: derive_bytes(seed, b"payload digest", 0),
+            amounts: STAMP_VECTOR_AMOUNTS
+                .iter()
+                .map(|amounts| amounts.to_vec())
+                .collect(),
+            stamp_txs: vec![],
+            stamp_private_keys: vec![],
+        };
+        let (stamp_txs, stamp_private_keys) = vector.derive()?;
+        vector.stamp_txs = stamp_txs;
+        vector.stamp_private_keys = stamp_private_keys;
+        Ok(vector)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn derive(&self) -> Result<(Vec<Vec<u8>>, Vec<Vec<[u8; 32]>>), VectorError> {
+        let destination_private_key = parse_private_key(&self.destination_private_key)?;
+        let destination_public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &destination_private_key);
+
+        let stamp_txs = self
+            .amounts
+            .iter()
+            .enumerate()
+            .map(|(tx_num, amounts)| {
+                let outputs: Vec<Output> = create_stamp_outputs(
+                    &destination_public_key,
+                    &self.payload_digest,
+                    tx_num as u32,
+                    amounts,
+                )
+                .map_err(VectorError::Stamp)?;
+                let transaction = Transaction {
+                    version: 2,
+                    inputs: vec![],
+                    outputs,
+                    lock_time: 0,
+                };
+                Ok(transaction.encode_to_vec())
+            })
+            .collect::<Result<_, _>>()?;
+
+        let output_profile: Vec<u32> = self
+            .amounts
+            .iter()
+            .map(|amounts| amounts.len() as u32)
+            .collect();
+        let stamp_private_keys = create_stamp_private_keys(
+            destination_private_key,
+            &self.payload_digest,
+            output_profile,
+        )
+        .map_err(VectorError::StampKey)?
+        .into_iter()
+        .map(|private_keys| {
+            private_keys
+                .iter()
+                .map(|private_key| {
+                    let mut raw_private_key = [0; 32];
+                    raw_private_key.copy_from_slice(&private_key[..]);
+                    raw_private_key
+                })
+                .collect()
+        })
+        .collect();
+
+        Ok((stamp_txs, stamp_private_keys))
+    }
+
+    /// Verify the vector, rederiving the stamp transactions and private keys.
+    pub fn verify(&self) -> Result<(), VectorError> {
+        let (stamp_txs, stamp_private_keys) = self.derive()?;
+        check("stamp transactions", stamp_txs == self.stamp_txs)?;
+        check(
+            "stamp private keys",
+            stamp_private_keys == self.stamp_private_keys,
+        )
+    }
+}
+
+/// A test vector for constructing chain commitment and HMAC bearer tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenVector {
+    /// The public key hash of the address.
+    pub pub_key_hash: [u8; 20],
+    /// The hash of the address metadata.
+    pub address_metadata_hash: [u8; 32],
+    /// The commitment to the address metadata.
+    pub commitment: Vec<u8>,
+    /// The `OP_RETURN` script containing the commitment.
+    pub commitment_script: Vec<u8>,
+    /// The ID of the commitment transaction.
+    pub tx_id: [u8; 32],
+    /// The index of the commitment output.
+    pub vout: u32,
+    /// The chain commitment token.
+    pub commitment_token: String,
+    /// The HMAC key.
+    pub hmac_key: [u8; 32],
+    /// The HMAC bearer token over the public key hash.
+    pub hmac_token: String,
+}
+
+impl TokenVector {
+    /// Generate the vector from a seed.
+    pub fn generate(seed: &[u8; 32]) -> Self {
+        let mut pub_key_hash = [0; 20];
+        pub_key_hash.copy_from_slice(&derive_bytes(seed, b"pub key hash", 0)[..20]);
+        let mut vector = Self {
+            pub_key_hash,
+            address_metadata_hash: derive_bytes(seed, b"address metadata hash", 0),
+            commitment: vec![],
+            commitment_script: vec![],
+            tx_id: derive_bytes(seed, b"tx id", 0),
+            vout: 1,
+            commitment_token: String::new(),
+            hmac_key: derive_bytes(seed, b"hmac key", 0),
+            hmac_token: String::new(),
+        };
+        vector.commitment = construct_commitment(&pub_key_hash, &vector.address_metadata_hash);
+        vector.commitment_script = construct_commitment_script(&vector.commitment).into();
+        vector.commitment_token = construct_token(&vector.tx_id, vector.vout);
+        vector.hmac_token = HmacScheme::new(&vector.hmac_key).construct_token(&pub_key_hash);
+        vector
+    }
+
+    /// Verify the vector, reconstructing the commitment and tokens.
+    pub fn verify(&self) -> Result<(), VectorError> {
+        let commitment = construct_commitment(&self.pub_key_hash, &self.address_metadata_hash);
+        check("commitment", commitment == self.commitment)?;
+        let commitment_script: Vec<u8> = construct_commitment_script(&commitment).into();
+        check(
+            "commitment script",
+            commitment_script == self.commitment_script,
+        )?;
+        check(
+            "commitment token",
+            construct_token(&self.tx_id, self.vout) == self.commitment_token,
+        )?;
+        check(
+            "hmac token",
+            HmacScheme::new(&self.hmac_key).construct_token(&self.pub_key_hash) == self.hmac_token,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_and_verify() {
+        let seed = [7; 32];
+        for scheme in [
+            EncryptionScheme::EphemeralDh,
+            EncryptionScheme::EphemeralDhHkdf,
+            EncryptionScheme::EphemeralDhAes256Gcm,
+            EncryptionScheme::EphemeralDhChacha20Poly1305,
+        ]
+        .iter()
+        {
+            let vector = SealVector::generate(&seed, *scheme).unwrap();
+            assert_eq!(SealVector::generate(&seed, *scheme).unwrap(), vector);
+            vector.verify().unwrap();
+        }
+
+        let mut vector = StampVector::generate(&seed).unwrap();
+        vector.verify().unwrap();
+        vector.stamp_private_keys[1][0][0] ^= 1;
+        assert!(matches!(
+            vector.verify(),
+            Err(VectorError::Mismatch("stamp private keys"))
+        ));
+
+        let mut vector = TokenVector::generate(&seed);
+        vector.verify().unwrap();
+        vector.vout = 0;
+        assert!(matches!(
+            vector.verify(),
+            Err(VectorError::Mismatch("commitment token"))
+        ));
+    }
+}